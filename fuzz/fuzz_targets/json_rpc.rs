@@ -0,0 +1,52 @@
+// Feeds arbitrary bytes through the same path the stdio loop uses:
+// framing, request parsing, request validation and parameter
+// deserialization. None of it may panic, whatever the input.
+//
+//     cargo +nightly fuzz run json_rpc
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mcp_server_microsoft_paint::jsonrpc::{extract_method_and_params, parse_json_rpc_request};
+use mcp_server_microsoft_paint::protocol::*;
+use mcp_server_microsoft_paint::transport::{read_message, set_framing, Framing};
+use serde_json::Value;
+
+// Deserializes params the way the handler for `method` would
+fn deserialize_params(method: &str, params: Value) {
+    fn try_as<T: serde::de::DeserializeOwned>(params: Value) {
+        let _ = serde_json::from_value::<T>(params);
+    }
+    match method {
+        "initialize" => try_as::<InitializeParams>(params),
+        "connect" => try_as::<ConnectParams>(params),
+        "draw_pixel" => try_as::<DrawPixelParams>(params),
+        "draw_line" => try_as::<DrawLineParams>(params),
+        "draw_shape" => try_as::<DrawShapeParams>(params),
+        "draw_polyline" => try_as::<DrawPolylineParams>(params),
+        "add_text" => try_as::<AddTextParams>(params),
+        "create_canvas" => try_as::<CreateCanvasParams>(params),
+        "get_canvas_region" => try_as::<GetCanvasRegionParams>(params),
+        "tools/call" => try_as::<ToolsCallParams>(params),
+        "replay_input" => try_as::<ReplayInputParams>(params),
+        _ => {}
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // Framing is detected per input, as it would be per connection
+    set_framing(Framing::Auto);
+    let mut reader = data;
+    loop {
+        let message = match read_message(&mut reader) {
+            Ok(Some(message)) => message,
+            // Rejected messages are consumed, so the loop always advances
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => continue,
+            Ok(None) | Err(_) => break,
+        };
+        let Ok(request) = parse_json_rpc_request(&message) else { continue };
+        if let Ok((method, Some(params), _)) = extract_method_and_params(&request) {
+            deserialize_params(&method, params);
+        }
+    }
+});
@@ -0,0 +1,167 @@
+// Append-only audit trail of requests.
+//
+// With `audit_log` set in the config, every request is appended to a JSON
+// Lines file once it finishes: when it ran, which client sent it, the
+// method, a hash of its params (not the params themselves, which can hold
+// file paths or text), whether it succeeded and how long it took. The file
+// is only ever appended to, and `get_audit_log` reads it back so users can
+// review what an agent did to their machine.
+
+use crate::error::{MspMcpError, Result};
+use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Entries get_audit_log returns when the request doesn't say
+pub const DEFAULT_AUDIT_LIMIT: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp_ms: u64,          // Unix time the request finished, in milliseconds
+    pub client_id: String,
+    pub method: String,
+    pub params_hash: String,        // FNV-1a of the params JSON, or "" without params
+    pub result: String,             // "ok" or "error"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<i32>,    // Our error code when result is "error"
+    pub duration_ms: u64,
+}
+
+/// Which entries to return from the log.
+#[derive(Debug, Default)]
+pub struct AuditFilter<'a> {
+    pub client_id: Option<&'a str>,
+    pub method: Option<&'a str>,
+    pub since_ms: Option<u64>,
+    pub errors_only: bool,
+    pub limit: usize,
+}
+
+impl AuditFilter<'_> {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.client_id.is_none_or(|c| entry.client_id == c)
+            && self.method.is_none_or(|m| entry.method == m)
+            && self.since_ms.is_none_or(|t| entry.timestamp_ms >= t)
+            && (!self.errors_only || entry.result == "error")
+    }
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Opens (creating if needed) the log for appending.
+    pub fn open(path: &Path) -> Result<AuditLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        info!("Writing audit log to {}", path.display());
+        Ok(AuditLog { path: path.to_path_buf(), file: Mutex::new(file) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends one entry. Failures are logged, never passed to the request.
+    pub fn append(&self, entry: &AuditEntry) {
+        let written = serde_json::to_string(entry).map_err(std::io::Error::from).and_then(|line| {
+            let mut file = self.file.lock().map_err(|_| std::io::Error::other("audit log lock poisoned"))?;
+            writeln!(file, "{}", line)?;
+            file.flush()
+        });
+        if let Err(e) = written {
+            warn!("Failed to write to audit log {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Reads the entries matching `filter`, oldest first, keeping the last
+    /// `filter.limit` of them. Lines that don't parse are skipped.
+    pub fn read(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        let file = File::open(&self.path)?;
+        let mut entries: Vec<AuditEntry> = BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .filter(|entry| filter.matches(entry))
+            .collect();
+        let skip = entries.len().saturating_sub(filter.limit);
+        entries.drain(..skip);
+        Ok(entries)
+    }
+}
+
+/// Hex FNV-1a hash of a request's params, stable across runs and builds.
+pub fn hash_params(params: Option<&Value>) -> String {
+    let Some(params) = params else { return String::new() };
+    let hash = params.to_string().bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn unix_time_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Builds the entry for a finished request.
+pub fn entry_for(client_id: &str, method: &str, params_hash: String, result: &std::result::Result<Value, MspMcpError>, duration_ms: u64) -> AuditEntry {
+    AuditEntry {
+        timestamp_ms: unix_time_ms(),
+        client_id: client_id.to_string(),
+        method: method.to_string(),
+        params_hash,
+        result: if result.is_ok() { "ok" } else { "error" }.to_string(),
+        error_code: result.as_ref().err().map(|e| e.code()),
+        duration_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_params_hash_is_stable() {
+        assert_eq!(hash_params(None), "");
+        assert_eq!(hash_params(Some(&json!({"x": 1}))), hash_params(Some(&json!({"x": 1}))));
+        assert_ne!(hash_params(Some(&json!({"x": 1}))), hash_params(Some(&json!({"x": 2}))));
+        assert_eq!(hash_params(Some(&json!({"x": 1}))).len(), 16);
+    }
+
+    #[test]
+    fn test_append_and_filter() {
+        let path = std::env::temp_dir().join(format!("mspmcp-audit-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = AuditLog::open(&path).unwrap();
+        let ok = Ok(json!({}));
+        let failed = Err(MspMcpError::WindowNotFound);
+        log.append(&entry_for("agent", "draw_line", hash_params(None), &ok, 5));
+        log.append(&entry_for("agent", "draw_pixel", hash_params(None), &failed, 1));
+        log.append(&entry_for("other", "draw_line", hash_params(None), &ok, 2));
+
+        let all = log.read(&AuditFilter { limit: 10, ..AuditFilter::default() }).unwrap();
+        assert_eq!(all.iter().map(|e| e.method.as_str()).collect::<Vec<_>>(), ["draw_line", "draw_pixel", "draw_line"]);
+        let agent = log.read(&AuditFilter { client_id: Some("agent"), limit: 10, ..AuditFilter::default() }).unwrap();
+        assert_eq!(agent.len(), 2);
+        let errors = log.read(&AuditFilter { errors_only: true, limit: 10, ..AuditFilter::default() }).unwrap();
+        assert_eq!((errors.len(), errors[0].error_code), (1, Some(1001)));
+        let last = log.read(&AuditFilter { limit: 1, ..AuditFilter::default() }).unwrap();
+        assert_eq!(last[0].client_id, "other");
+
+        // Reopening appends instead of truncating
+        drop(log);
+        let log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.read(&AuditFilter { limit: 10, ..AuditFilter::default() }).unwrap().len(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,636 @@
+// Window-automation backend.
+//
+// Handlers in core.rs drive Paint only through the PaintBackend trait. The
+// real implementation forwards to the Win32/UIA code in windows.rs and
+// capture.rs; tests substitute a mock that records calls, so handler logic
+// (parameter handling, ordering of tool/color/thickness changes, response
+// shapes) can be checked without a desktop session.
+
+use crate::capture::CapturedImage;
+use crate::error::Result;
+use crate::input_record::RecordedInput;
+use crate::protocol::{AddTextParams, CanvasDimensionsResponse, MonitorInfo, MouseButton, PaintCapabilities, SaveCanvasParams, SetImagePropertiesParams, SetToolStateParams, UnsavedChanges, WindowInfoResponse};
+use std::time::Duration;
+
+#[cfg(feature = "windows-automation")]
+pub use windows_sys::Win32::Foundation::HWND;
+/// A window handle; only ever compared and passed back to the backend
+/// without the windows-automation feature.
+#[cfg(not(feature = "windows-automation"))]
+pub type HWND = isize;
+
+pub trait PaintBackend: Send + Sync {
+    // --- Window discovery and tracking ---
+
+    /// Finds a running Paint window or launches Paint.
+    fn find_or_launch_paint(&self) -> Result<HWND>;
+    /// Starts another Paint window, leaving the ones already open alone.
+    fn launch_paint_window(&self) -> Result<HWND>;
+    /// Finds the main window of a Paint process.
+    fn find_window_for_pid(&self, pid: u32) -> Option<HWND>;
+    /// True if `hwnd` still exists and belongs to `pid`.
+    fn is_window_owned_by(&self, hwnd: HWND, pid: u32) -> bool;
+    fn window_pid(&self, hwnd: HWND) -> u32;
+    fn window_title(&self, hwnd: HWND) -> String;
+    /// File version of the Paint executable, e.g. "11.2410.28.0", if it can be read.
+    fn paint_version(&self, hwnd: HWND) -> Option<String>;
+    /// What this Paint build offers: which tools, brushes and shapes it has
+    /// and whether it has layers and background removal.
+    fn capabilities(&self, hwnd: HWND) -> Result<PaintCapabilities>;
+    fn window_info(&self, hwnd: HWND) -> Result<WindowInfoResponse>;
+    fn ensure_window_on_monitor(&self, hwnd: HWND, monitor: Option<u32>) -> Result<MonitorInfo>;
+
+    // --- Input primitives ---
+
+    /// Brings Paint to the foreground, ready for input.
+    fn activate(&self, hwnd: HWND) -> Result<()>;
+    /// Clicks at a canvas position.
+    fn click(&self, hwnd: HWND, x: i32, y: i32) -> Result<()>;
+    /// Drags between two canvas positions with the left button held.
+    fn drag(&self, hwnd: HWND, start: (i32, i32), end: (i32, i32)) -> Result<()>;
+    /// Types text into the focused control.
+    fn type_text(&self, text: &str) -> Result<()>;
+    /// Sends Ctrl+S.
+    fn save(&self, hwnd: HWND) -> Result<()>;
+    /// Sends Ctrl+Z, undoing the last canvas change.
+    fn undo(&self, hwnd: HWND) -> Result<()>;
+    /// Releases any mouse buttons/modifier keys still held down.
+    fn release_held_input(&self) -> Result<()>;
+    /// Presses Escape in whatever window has focus, cancelling a
+    /// half-finished drag, selection or menu.
+    fn cancel_gesture(&self) -> Result<()>;
+    /// Ends the Paint process without saving.
+    fn terminate(&self, pid: u32) -> Result<()>;
+    /// Closes Paint like its close button, answering the save prompt with
+    /// `unsaved_changes`, and waits up to `timeout` for the process to exit.
+    fn quit_paint(&self, hwnd: HWND, unsaved_changes: UnsavedChanges, timeout: Duration) -> Result<()>;
+    /// Re-injects a recorded input sequence (see input_record.rs).
+    fn replay_input(&self, events: &[RecordedInput], speed: f64) -> Result<()>;
+    /// Input events synthesized since startup, for rate limits.
+    fn input_events_sent(&self) -> u64;
+
+    // --- Tool settings ---
+
+    fn select_tool(&self, hwnd: HWND, tool: &str) -> Result<()>;
+    /// Picks a brush variant (one of protocol::BRUSH_TYPES).
+    fn select_brush_type(&self, hwnd: HWND, brush_type: &str) -> Result<()>;
+    fn set_color(&self, hwnd: HWND, color: &str) -> Result<()>;
+    /// Sets Color 2, the color right-button strokes draw with.
+    fn set_secondary_color(&self, hwnd: HWND, color: &str) -> Result<()>;
+    /// Adds a color to Paint's custom colors through the Edit colors
+    /// dialog, which also sets the slot `button` draws with to it.
+    fn add_custom_color(&self, hwnd: HWND, color: &str, button: MouseButton) -> Result<()>;
+    /// Sets the slot `button` draws with to a custom color swatch, counted
+    /// from the newest (0).
+    fn select_custom_color(&self, hwnd: HWND, index: usize, button: MouseButton) -> Result<()>;
+    fn set_thickness(&self, hwnd: HWND, level: u32) -> Result<()>;
+    /// Sets the stroke width in pixels with Paint's size slider.
+    fn set_thickness_px(&self, hwnd: HWND, px: u32) -> Result<()>;
+    fn set_brush_size(&self, hwnd: HWND, size: u32, tool: Option<&str>) -> Result<()>;
+    fn set_fill(&self, hwnd: HWND, fill_type: &str) -> Result<()>;
+    /// Applies the settings given in `params` in one pass, activating Paint once.
+    fn set_tool_state(&self, hwnd: HWND, params: &SetToolStateParams) -> Result<()>;
+
+    // --- Canvas operations (canvas coordinates) ---
+
+    /// Document size in image pixels, whatever the zoom level.
+    fn canvas_dimensions(&self, hwnd: HWND) -> Result<(u32, u32)>;
+    /// Document size with the zoom, viewport and scroll position.
+    fn canvas_view(&self, hwnd: HWND) -> Result<CanvasDimensionsResponse>;
+    /// Canvas size estimate used at connect, before Paint is activated.
+    fn initial_canvas_dimensions(&self, hwnd: HWND) -> Result<(u32, u32)>;
+    // Strokes take the mouse button to draw with; see MouseButton
+    fn draw_pixel(&self, hwnd: HWND, x: i32, y: i32, button: MouseButton) -> Result<()>;
+    fn draw_line(&self, hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()>;
+    #[allow(clippy::too_many_arguments)]
+    fn draw_shape(&self, hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()>;
+    fn draw_polyline(&self, hwnd: HWND, points: &[(i32, i32)], button: MouseButton) -> Result<()>;
+    fn clear_canvas(&self, hwnd: HWND) -> Result<()>;
+    fn select_region(&self, hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32) -> Result<()>;
+    fn copy_selection(&self, hwnd: HWND) -> Result<()>;
+    /// Copies the selection and reads the copied bitmap back from the clipboard.
+    fn copy_selection_image(&self, hwnd: HWND) -> Result<CapturedImage>;
+    /// Copies the whole canvas, scrolled-out parts included, and reads it
+    /// back from the clipboard, leaving nothing selected.
+    fn copy_canvas_image(&self, hwnd: HWND) -> Result<CapturedImage>;
+    fn paste_at(&self, hwnd: HWND, x: i32, y: i32) -> Result<()>;
+    /// Puts a bitmap on the clipboard, replacing what was there.
+    fn set_clipboard_image(&self, image: &CapturedImage) -> Result<()>;
+    /// Pastes the clipboard bitmap (`width` x `height`) with its top-left
+    /// corner at (x, y) and commits it, leaving nothing selected.
+    fn stamp_clipboard_image(&self, hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<()>;
+    /// Rotates the selection clockwise by 90, 180 or 270 degrees.
+    fn rotate_selection(&self, hwnd: HWND, degrees: u32) -> Result<()>;
+    /// Mirrors the selection: "horizontal" or "vertical".
+    fn flip_selection(&self, hwnd: HWND, direction: &str) -> Result<()>;
+    /// Inverts the colors of the selection, or of the whole canvas.
+    fn invert_colors(&self, hwnd: HWND, selection_only: bool) -> Result<()>;
+    fn add_text(&self, hwnd: HWND, params: &AddTextParams) -> Result<()>;
+    /// Opens a new document at Paint's default size. `unsaved_changes`
+    /// answers Paint's save prompt if the current document has unsaved changes.
+    fn new_document(&self, hwnd: HWND, unsaved_changes: UnsavedChanges) -> Result<()>;
+    /// Starts a new image. `unsaved_changes` answers Paint's save prompt if
+    /// the current document has unsaved changes.
+    fn create_canvas(&self, hwnd: HWND, width: u32, height: u32, background_color: Option<&str>, unsaved_changes: UnsavedChanges) -> Result<()>;
+    /// Saves the image to a file with Save As, answering the overwrite and
+    /// format prompts as `params` says.
+    fn save_canvas(&self, hwnd: HWND, params: &SaveCanvasParams) -> Result<()>;
+    /// Like save_canvas, but fails rather than falling back on the file
+    /// extension if the format can't be picked in "Save as type".
+    fn save_as(&self, hwnd: HWND, params: &SaveCanvasParams) -> Result<()>;
+    /// Resizes the image and/or changes its color mode through the Image
+    /// Properties dialog. Shrinking crops from the right and bottom.
+    fn set_image_properties(&self, hwnd: HWND, params: &SetImagePropertiesParams) -> Result<()>;
+    /// Fills the whole canvas with `color`, which also becomes Color 2.
+    fn set_background(&self, hwnd: HWND, color: &str) -> Result<()>;
+
+    // --- Layers (index 0 = top of Paint's Layers panel) ---
+
+    /// Fails with OperationNotSupported on Paint builds without layers.
+    fn add_layer(&self, hwnd: HWND) -> Result<()>;
+    fn select_layer(&self, hwnd: HWND, index: u32) -> Result<()>;
+    fn set_layer_visibility(&self, hwnd: HWND, index: u32, visible: bool) -> Result<()>;
+    /// Merges the layer into the one below it.
+    fn merge_layer_down(&self, hwnd: HWND, index: u32) -> Result<()>;
+    fn delete_layer(&self, hwnd: HWND, index: u32) -> Result<()>;
+
+    // --- Capture ---
+
+    fn capture_window(&self, hwnd: HWND) -> Result<CapturedImage>;
+    fn capture_canvas(&self, hwnd: HWND) -> Result<CapturedImage>;
+    fn capture_canvas_region(&self, hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<CapturedImage>;
+}
+
+/// Drives the real Windows 11 Paint through Win32 input and UI Automation.
+#[cfg(feature = "windows-automation")]
+pub struct WindowsBackend;
+
+#[cfg(feature = "windows-automation")]
+impl PaintBackend for WindowsBackend {
+    fn find_or_launch_paint(&self) -> Result<HWND> {
+        crate::windows::get_paint_hwnd()
+    }
+
+    fn launch_paint_window(&self) -> Result<HWND> {
+        crate::windows::launch_paint_window()
+    }
+
+    fn find_window_for_pid(&self, pid: u32) -> Option<HWND> {
+        crate::windows::find_window_for_pid(pid)
+    }
+
+    fn is_window_owned_by(&self, hwnd: HWND, pid: u32) -> bool {
+        crate::windows::is_window_owned_by(hwnd, pid)
+    }
+
+    fn window_pid(&self, hwnd: HWND) -> u32 {
+        crate::windows::get_window_pid(hwnd)
+    }
+
+    fn window_title(&self, hwnd: HWND) -> String {
+        crate::windows::get_window_title(hwnd)
+    }
+
+    fn paint_version(&self, hwnd: HWND) -> Option<String> {
+        crate::windows::get_paint_version(hwnd)
+    }
+
+    fn capabilities(&self, hwnd: HWND) -> Result<PaintCapabilities> {
+        crate::windows::read_capabilities(hwnd)
+    }
+
+    fn window_info(&self, hwnd: HWND) -> Result<WindowInfoResponse> {
+        crate::windows::get_window_info(hwnd)
+    }
+
+    fn ensure_window_on_monitor(&self, hwnd: HWND, monitor: Option<u32>) -> Result<MonitorInfo> {
+        crate::windows::ensure_window_on_monitor(hwnd, monitor)
+    }
+
+    fn activate(&self, hwnd: HWND) -> Result<()> {
+        crate::windows::activate_paint_window(hwnd)
+    }
+
+    fn click(&self, hwnd: HWND, x: i32, y: i32) -> Result<()> {
+        let (offset_x, offset_y) = crate::windows::get_drawing_area_offset(hwnd)?;
+        crate::windows::click_at_client_position(hwnd, x + offset_x, y + offset_y)
+    }
+
+    fn drag(&self, hwnd: HWND, start: (i32, i32), end: (i32, i32)) -> Result<()> {
+        let (offset_x, offset_y) = crate::windows::get_drawing_area_offset(hwnd)?;
+        let (start_x, start_y) = crate::windows::client_to_screen(hwnd, start.0 + offset_x, start.1 + offset_y)?;
+        let (end_x, end_y) = crate::windows::client_to_screen(hwnd, end.0 + offset_x, end.1 + offset_y)?;
+        crate::windows::drag_mouse(start_x, start_y, end_x, end_y)
+    }
+
+    fn type_text(&self, text: &str) -> Result<()> {
+        crate::windows::type_text(text)
+    }
+
+    fn save(&self, hwnd: HWND) -> Result<()> {
+        crate::windows::activate_paint_window(hwnd)?;
+        crate::windows::press_ctrl_s()
+    }
+
+    fn undo(&self, hwnd: HWND) -> Result<()> {
+        crate::windows::activate_paint_window(hwnd)?;
+        crate::windows::press_ctrl_z()
+    }
+
+    fn release_held_input(&self) -> Result<()> {
+        crate::windows::release_held_input()
+    }
+
+    fn cancel_gesture(&self) -> Result<()> {
+        crate::windows::press_escape()
+    }
+
+    fn terminate(&self, pid: u32) -> Result<()> {
+        crate::windows::terminate_process(pid)
+    }
+
+    fn quit_paint(&self, hwnd: HWND, unsaved_changes: UnsavedChanges, timeout: Duration) -> Result<()> {
+        crate::windows::quit_paint(hwnd, unsaved_changes, timeout)
+    }
+
+    fn replay_input(&self, events: &[RecordedInput], speed: f64) -> Result<()> {
+        crate::input_record::replay(events, speed)
+    }
+
+    fn input_events_sent(&self) -> u64 {
+        crate::input_record::events_sent()
+    }
+
+    fn select_tool(&self, hwnd: HWND, tool: &str) -> Result<()> {
+        crate::windows::select_tool(hwnd, tool)
+    }
+
+    fn select_brush_type(&self, hwnd: HWND, brush_type: &str) -> Result<()> {
+        crate::windows::select_brush_type(hwnd, brush_type)
+    }
+
+    fn set_color(&self, hwnd: HWND, color: &str) -> Result<()> {
+        crate::windows::set_color(hwnd, color)
+    }
+
+    fn set_secondary_color(&self, hwnd: HWND, color: &str) -> Result<()> {
+        crate::windows::set_secondary_color(hwnd, color)
+    }
+
+    fn add_custom_color(&self, hwnd: HWND, color: &str, button: MouseButton) -> Result<()> {
+        crate::windows::add_custom_color(hwnd, color, button)
+    }
+
+    fn select_custom_color(&self, hwnd: HWND, index: usize, button: MouseButton) -> Result<()> {
+        crate::windows::select_custom_color(hwnd, index, button)
+    }
+
+    fn set_thickness(&self, hwnd: HWND, level: u32) -> Result<()> {
+        crate::windows::set_thickness(hwnd, level)
+    }
+
+    fn set_thickness_px(&self, hwnd: HWND, px: u32) -> Result<()> {
+        crate::windows::set_thickness_px(hwnd, px)
+    }
+
+    fn set_brush_size(&self, hwnd: HWND, size: u32, tool: Option<&str>) -> Result<()> {
+        crate::windows::set_brush_size(hwnd, size, tool)
+    }
+
+    fn set_fill(&self, hwnd: HWND, fill_type: &str) -> Result<()> {
+        crate::windows::set_fill(hwnd, fill_type)
+    }
+
+    fn set_tool_state(&self, hwnd: HWND, params: &SetToolStateParams) -> Result<()> {
+        crate::windows::set_tool_state(hwnd, params)
+    }
+
+    fn canvas_dimensions(&self, hwnd: HWND) -> Result<(u32, u32)> {
+        crate::windows::get_canvas_dimensions(hwnd)
+    }
+
+    fn canvas_view(&self, hwnd: HWND) -> Result<CanvasDimensionsResponse> {
+        crate::windows::get_canvas_view(hwnd)
+    }
+
+    fn initial_canvas_dimensions(&self, hwnd: HWND) -> Result<(u32, u32)> {
+        crate::windows::get_initial_canvas_dimensions(hwnd)
+    }
+
+    fn draw_pixel(&self, hwnd: HWND, x: i32, y: i32, button: MouseButton) -> Result<()> {
+        crate::windows::draw_pixel_at(hwnd, x, y, button)
+    }
+
+    fn draw_line(&self, hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+        crate::windows::draw_line_at(hwnd, start_x, start_y, end_x, end_y, button)
+    }
+
+    fn draw_shape(&self, hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+        crate::windows::draw_shape(hwnd, shape_type, start_x, start_y, end_x, end_y, button)
+    }
+
+    fn draw_polyline(&self, hwnd: HWND, points: &[(i32, i32)], button: MouseButton) -> Result<()> {
+        crate::windows::draw_polyline(hwnd, points, button)
+    }
+
+    fn clear_canvas(&self, hwnd: HWND) -> Result<()> {
+        crate::windows::clear_canvas(hwnd)
+    }
+
+    fn select_region(&self, hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32) -> Result<()> {
+        crate::windows::select_region(hwnd, start_x, start_y, end_x, end_y)
+    }
+
+    fn copy_selection(&self, hwnd: HWND) -> Result<()> {
+        crate::windows::copy_selection(hwnd)
+    }
+
+    fn copy_selection_image(&self, hwnd: HWND) -> Result<CapturedImage> {
+        crate::capture::copy_selection_image(hwnd)
+    }
+
+    fn copy_canvas_image(&self, hwnd: HWND) -> Result<CapturedImage> {
+        crate::capture::copy_canvas_image(hwnd)
+    }
+
+    fn paste_at(&self, hwnd: HWND, x: i32, y: i32) -> Result<()> {
+        crate::windows::paste_at(hwnd, x, y)
+    }
+
+    fn set_clipboard_image(&self, image: &CapturedImage) -> Result<()> {
+        crate::capture::write_clipboard_image(image)
+    }
+
+    fn stamp_clipboard_image(&self, hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<()> {
+        crate::windows::stamp_clipboard_image(hwnd, x, y, width, height)
+    }
+
+    fn rotate_selection(&self, hwnd: HWND, degrees: u32) -> Result<()> {
+        crate::windows::rotate_selection(hwnd, degrees)
+    }
+
+    fn flip_selection(&self, hwnd: HWND, direction: &str) -> Result<()> {
+        crate::windows::flip_selection(hwnd, direction)
+    }
+
+    fn invert_colors(&self, hwnd: HWND, selection_only: bool) -> Result<()> {
+        crate::windows::invert_colors(hwnd, selection_only)
+    }
+
+    fn add_text(&self, hwnd: HWND, params: &AddTextParams) -> Result<()> {
+        crate::windows::add_text(
+            hwnd,
+            params.x,
+            params.y,
+            &params.text,
+            params.color.as_deref(),
+            params.font_name.as_deref(),
+            params.font_size,
+            params.font_style.as_deref(),
+        )
+    }
+
+    fn new_document(&self, hwnd: HWND, unsaved_changes: UnsavedChanges) -> Result<()> {
+        crate::windows::new_document(hwnd, unsaved_changes)
+    }
+
+    fn create_canvas(&self, hwnd: HWND, width: u32, height: u32, background_color: Option<&str>, unsaved_changes: UnsavedChanges) -> Result<()> {
+        crate::windows::create_canvas(hwnd, width, height, background_color, unsaved_changes)
+    }
+
+    fn save_canvas(&self, hwnd: HWND, params: &SaveCanvasParams) -> Result<()> {
+        crate::windows::save_canvas(hwnd, &params.file_path, &params.format, params.overwrite, params.jpeg_quality)
+    }
+
+    fn save_as(&self, hwnd: HWND, params: &SaveCanvasParams) -> Result<()> {
+        crate::windows::save_as(hwnd, &params.file_path, &params.format, params.overwrite, params.jpeg_quality)
+    }
+
+    fn set_image_properties(&self, hwnd: HWND, params: &SetImagePropertiesParams) -> Result<()> {
+        crate::windows::set_image_properties(hwnd, params)
+    }
+
+    fn set_background(&self, hwnd: HWND, color: &str) -> Result<()> {
+        crate::windows::set_background(hwnd, color)
+    }
+
+    fn add_layer(&self, hwnd: HWND) -> Result<()> {
+        crate::windows::add_layer(hwnd)
+    }
+
+    fn select_layer(&self, hwnd: HWND, index: u32) -> Result<()> {
+        crate::windows::select_layer(hwnd, index)
+    }
+
+    fn set_layer_visibility(&self, hwnd: HWND, index: u32, visible: bool) -> Result<()> {
+        crate::windows::set_layer_visibility(hwnd, index, visible)
+    }
+
+    fn merge_layer_down(&self, hwnd: HWND, index: u32) -> Result<()> {
+        crate::windows::merge_layer_down(hwnd, index)
+    }
+
+    fn delete_layer(&self, hwnd: HWND, index: u32) -> Result<()> {
+        crate::windows::delete_layer(hwnd, index)
+    }
+
+    fn capture_window(&self, hwnd: HWND) -> Result<CapturedImage> {
+        crate::capture::capture_window(hwnd)
+    }
+
+    fn capture_canvas(&self, hwnd: HWND) -> Result<CapturedImage> {
+        crate::capture::capture_canvas(hwnd)
+    }
+
+    fn capture_canvas_region(&self, hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<CapturedImage> {
+        crate::capture::capture_canvas_region(hwnd, x, y, width, height)
+    }
+}
+
+/// Backend for handler tests: records every call and pretends it worked.
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use crate::protocol::RectInfo;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+
+    pub const MOCK_HWND: HWND = 0x1234;
+    pub const MOCK_PID: u32 = 4242;
+
+    #[derive(Default)]
+    pub struct MockBackend {
+        pub calls: Mutex<Vec<String>>,
+        pub title: Mutex<String>,
+        pub panic_on: Mutex<Option<String>>, // Calls starting with this panic instead
+        pub input_events: AtomicU64,          // Every call counts as one input event
+    }
+
+    impl MockBackend {
+        fn record(&self, call: String) {
+            if self.panic_on.lock().unwrap().as_deref().is_some_and(|prefix| call.starts_with(prefix)) {
+                panic!("mock backend panicked in {}", call);
+            }
+            self.calls.lock().unwrap().push(call);
+            self.input_events.fetch_add(1, Ordering::Relaxed);
+        }
+
+        /// Returns the calls made so far and clears the log.
+        pub fn take_calls(&self) -> Vec<String> {
+            std::mem::take(&mut *self.calls.lock().unwrap())
+        }
+
+        fn image(width: u32, height: u32) -> CapturedImage {
+            CapturedImage { width, height, rgba: vec![0xFF; (width * height * 4) as usize] }
+        }
+    }
+
+    impl PaintBackend for MockBackend {
+        fn find_or_launch_paint(&self) -> Result<HWND> { self.record("find_or_launch_paint".into()); Ok(MOCK_HWND) }
+        fn launch_paint_window(&self) -> Result<HWND> { self.record("launch_paint_window".into()); Ok(MOCK_HWND + 1) }
+        fn find_window_for_pid(&self, _pid: u32) -> Option<HWND> { Some(MOCK_HWND) }
+        fn is_window_owned_by(&self, hwnd: HWND, pid: u32) -> bool { hwnd == MOCK_HWND && pid == MOCK_PID }
+        fn window_pid(&self, _hwnd: HWND) -> u32 { MOCK_PID }
+        fn window_title(&self, _hwnd: HWND) -> String { self.title.lock().unwrap().clone() }
+        fn paint_version(&self, _hwnd: HWND) -> Option<String> { Some("11.2410.28.0".into()) }
+        fn capabilities(&self, _hwnd: HWND) -> Result<PaintCapabilities> {
+            self.record("capabilities".into());
+            Ok(PaintCapabilities {
+                tools: vec!["brush".into(), "pencil".into()],
+                brush_types: vec!["brush".into(), "marker".into()],
+                shapes: vec!["ellipse".into(), "rectangle".into()],
+                layers: true,
+                background_removal: false,
+            })
+        }
+        fn window_info(&self, hwnd: HWND) -> Result<WindowInfoResponse> {
+            self.record("window_info".into());
+            let rect = RectInfo::new(0, 0, 800, 600);
+            Ok(WindowInfoResponse {
+                hwnd, pid: MOCK_PID, class_name: "MSPaintApp".into(), title: self.window_title(hwnd),
+                window_rect: rect, client_rect: rect, client_origin_x: 0, client_origin_y: 0,
+                dpi: 96, scale_factor: 1.0, monitor: None, is_foreground: true, is_minimized: false,
+                is_maximized: false, z_order: 0, canvas_rect: rect,
+            })
+        }
+        fn ensure_window_on_monitor(&self, _hwnd: HWND, monitor: Option<u32>) -> Result<MonitorInfo> {
+            self.record(format!("ensure_window_on_monitor {:?}", monitor));
+            let rect = RectInfo::new(0, 0, 1920, 1080);
+            Ok(MonitorInfo { index: monitor.unwrap_or(0), handle: 1, monitor_rect: rect, work_rect: rect, is_primary: true })
+        }
+        fn activate(&self, _hwnd: HWND) -> Result<()> { self.record("activate".into()); Ok(()) }
+        fn click(&self, _hwnd: HWND, x: i32, y: i32) -> Result<()> { self.record(format!("click {} {}", x, y)); Ok(()) }
+        fn drag(&self, _hwnd: HWND, start: (i32, i32), end: (i32, i32)) -> Result<()> {
+            self.record(format!("drag {:?} {:?}", start, end)); Ok(())
+        }
+        fn type_text(&self, text: &str) -> Result<()> { self.record(format!("type_text {}", text)); Ok(()) }
+        fn save(&self, _hwnd: HWND) -> Result<()> { self.record("save".into()); Ok(()) }
+        fn undo(&self, _hwnd: HWND) -> Result<()> { self.record("undo".into()); Ok(()) }
+        fn release_held_input(&self) -> Result<()> { self.record("release_held_input".into()); Ok(()) }
+        fn cancel_gesture(&self) -> Result<()> { self.record("cancel_gesture".into()); Ok(()) }
+        fn terminate(&self, pid: u32) -> Result<()> { self.record(format!("terminate {}", pid)); Ok(()) }
+        fn quit_paint(&self, _hwnd: HWND, unsaved_changes: UnsavedChanges, timeout: Duration) -> Result<()> {
+            self.record(format!("quit_paint {:?} {:?}", unsaved_changes, timeout)); Ok(())
+        }
+        fn replay_input(&self, events: &[RecordedInput], speed: f64) -> Result<()> {
+            self.record(format!("replay_input {} {}", events.len(), speed));
+            // Keep the real replay's pacing so tests see its timing on the clock
+            crate::input_record::replay_delays(events, speed).into_iter().for_each(crate::clock::sleep);
+            Ok(())
+        }
+        fn input_events_sent(&self) -> u64 { self.input_events.load(Ordering::Relaxed) }
+        fn select_tool(&self, _hwnd: HWND, tool: &str) -> Result<()> { self.record(format!("select_tool {}", tool)); Ok(()) }
+        fn select_brush_type(&self, _hwnd: HWND, brush_type: &str) -> Result<()> {
+            self.record(format!("select_brush_type {}", brush_type)); Ok(())
+        }
+        fn set_color(&self, _hwnd: HWND, color: &str) -> Result<()> { self.record(format!("set_color {}", color)); Ok(()) }
+        fn set_secondary_color(&self, _hwnd: HWND, color: &str) -> Result<()> {
+            self.record(format!("set_secondary_color {}", color)); Ok(())
+        }
+        fn add_custom_color(&self, _hwnd: HWND, color: &str, button: MouseButton) -> Result<()> {
+            self.record(format!("add_custom_color {} {:?}", color, button)); Ok(())
+        }
+        fn select_custom_color(&self, _hwnd: HWND, index: usize, button: MouseButton) -> Result<()> {
+            self.record(format!("select_custom_color {} {:?}", index, button)); Ok(())
+        }
+        fn set_thickness(&self, _hwnd: HWND, level: u32) -> Result<()> { self.record(format!("set_thickness {}", level)); Ok(()) }
+        fn set_thickness_px(&self, _hwnd: HWND, px: u32) -> Result<()> { self.record(format!("set_thickness_px {}", px)); Ok(()) }
+        fn set_brush_size(&self, _hwnd: HWND, size: u32, tool: Option<&str>) -> Result<()> {
+            self.record(format!("set_brush_size {} {:?}", size, tool)); Ok(())
+        }
+        fn set_fill(&self, _hwnd: HWND, fill_type: &str) -> Result<()> { self.record(format!("set_fill {}", fill_type)); Ok(()) }
+        fn set_tool_state(&self, _hwnd: HWND, params: &SetToolStateParams) -> Result<()> {
+            self.record(format!("set_tool_state {:?} {:?} {:?} {:?} {:?} {:?}", params.tool, params.brush_type,
+                params.color, params.secondary_color, params.thickness_px, params.fill_type));
+            Ok(())
+        }
+        fn canvas_dimensions(&self, _hwnd: HWND) -> Result<(u32, u32)> { Ok((800, 600)) }
+        fn canvas_view(&self, _hwnd: HWND) -> Result<CanvasDimensionsResponse> {
+            Ok(CanvasDimensionsResponse {
+                width: 800, height: 600, zoom: 1.0, viewport: RectInfo::new(0, 120, 530, 475),
+                scroll_x: 0, scroll_y: 0, estimated: false,
+            })
+        }
+        fn initial_canvas_dimensions(&self, _hwnd: HWND) -> Result<(u32, u32)> { Ok((800, 600)) }
+        fn draw_pixel(&self, _hwnd: HWND, x: i32, y: i32, button: MouseButton) -> Result<()> {
+            self.record(format!("draw_pixel {} {} {:?}", x, y, button)); Ok(())
+        }
+        fn draw_line(&self, _hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+            self.record(format!("draw_line {} {} {} {} {:?}", start_x, start_y, end_x, end_y, button)); Ok(())
+        }
+        fn draw_shape(&self, _hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+            self.record(format!("draw_shape {} {} {} {} {} {:?}", shape_type, start_x, start_y, end_x, end_y, button)); Ok(())
+        }
+        fn draw_polyline(&self, _hwnd: HWND, points: &[(i32, i32)], button: MouseButton) -> Result<()> {
+            self.record(format!("draw_polyline {:?} {:?}", points, button)); Ok(())
+        }
+        fn clear_canvas(&self, _hwnd: HWND) -> Result<()> { self.record("clear_canvas".into()); Ok(()) }
+        fn select_region(&self, _hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32) -> Result<()> {
+            self.record(format!("select_region {} {} {} {}", start_x, start_y, end_x, end_y)); Ok(())
+        }
+        fn copy_selection(&self, _hwnd: HWND) -> Result<()> { self.record("copy_selection".into()); Ok(()) }
+        fn copy_selection_image(&self, _hwnd: HWND) -> Result<CapturedImage> { self.record("copy_selection_image".into()); Ok(Self::image(4, 2)) }
+        fn copy_canvas_image(&self, hwnd: HWND) -> Result<CapturedImage> { self.record(format!("copy_canvas_image {}", hwnd)); Ok(Self::image(800, 600)) }
+        fn paste_at(&self, _hwnd: HWND, x: i32, y: i32) -> Result<()> { self.record(format!("paste_at {} {}", x, y)); Ok(()) }
+        fn set_clipboard_image(&self, image: &CapturedImage) -> Result<()> {
+            self.record(format!("set_clipboard_image {}x{}", image.width, image.height)); Ok(())
+        }
+        fn stamp_clipboard_image(&self, _hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<()> {
+            self.record(format!("stamp_clipboard_image {} {} {}x{}", x, y, width, height)); Ok(())
+        }
+        fn rotate_selection(&self, _hwnd: HWND, degrees: u32) -> Result<()> { self.record(format!("rotate_selection {}", degrees)); Ok(()) }
+        fn flip_selection(&self, _hwnd: HWND, direction: &str) -> Result<()> { self.record(format!("flip_selection {}", direction)); Ok(()) }
+        fn invert_colors(&self, _hwnd: HWND, selection_only: bool) -> Result<()> { self.record(format!("invert_colors {}", selection_only)); Ok(()) }
+        fn add_text(&self, _hwnd: HWND, params: &AddTextParams) -> Result<()> {
+            self.record(format!("add_text {} {} {}", params.x, params.y, params.text)); Ok(())
+        }
+        fn new_document(&self, _hwnd: HWND, unsaved_changes: UnsavedChanges) -> Result<()> {
+            self.record(format!("new_document {:?}", unsaved_changes)); Ok(())
+        }
+        fn create_canvas(&self, _hwnd: HWND, width: u32, height: u32, background_color: Option<&str>, unsaved_changes: UnsavedChanges) -> Result<()> {
+            self.record(format!("create_canvas {} {} {:?} {:?}", width, height, background_color, unsaved_changes)); Ok(())
+        }
+        fn save_canvas(&self, _hwnd: HWND, params: &SaveCanvasParams) -> Result<()> {
+            self.record(format!("save_canvas {} {} {}", params.file_path, params.format, params.overwrite)); Ok(())
+        }
+        fn save_as(&self, _hwnd: HWND, params: &SaveCanvasParams) -> Result<()> {
+            self.record(format!("save_as {} {} {:?}", params.file_path, params.format, params.jpeg_quality)); Ok(())
+        }
+        fn set_image_properties(&self, _hwnd: HWND, params: &SetImagePropertiesParams) -> Result<()> {
+            self.record(format!("set_image_properties {:?} {:?} {:?} {:?}", params.width, params.height, params.units, params.color_mode)); Ok(())
+        }
+        fn set_background(&self, _hwnd: HWND, color: &str) -> Result<()> { self.record(format!("set_background {}", color)); Ok(()) }
+        fn add_layer(&self, _hwnd: HWND) -> Result<()> { self.record("add_layer".into()); Ok(()) }
+        fn select_layer(&self, _hwnd: HWND, index: u32) -> Result<()> { self.record(format!("select_layer {}", index)); Ok(()) }
+        fn set_layer_visibility(&self, _hwnd: HWND, index: u32, visible: bool) -> Result<()> {
+            self.record(format!("set_layer_visibility {} {}", index, visible)); Ok(())
+        }
+        fn merge_layer_down(&self, _hwnd: HWND, index: u32) -> Result<()> { self.record(format!("merge_layer_down {}", index)); Ok(()) }
+        fn delete_layer(&self, _hwnd: HWND, index: u32) -> Result<()> { self.record(format!("delete_layer {}", index)); Ok(()) }
+        fn capture_window(&self, _hwnd: HWND) -> Result<CapturedImage> { Ok(Self::image(8, 6)) }
+        fn capture_canvas(&self, _hwnd: HWND) -> Result<CapturedImage> { Ok(Self::image(8, 6)) }
+        fn capture_canvas_region(&self, _hwnd: HWND, _x: i32, _y: i32, width: u32, height: u32) -> Result<CapturedImage> {
+            Ok(Self::image(width, height))
+        }
+    }
+}
@@ -0,0 +1,174 @@
+// Finding the tool buttons by trying them.
+//
+// The layout tables in layout.rs are a best guess at where Paint puts its
+// tool buttons. `calibrate` checks the guess on the connected window: for
+// each tool it clicks the expected button position, drags a short stroke in
+// a probe color near the canvas corner and captures the spot. A stroke in
+// the probe color means the click selected a drawing tool; anything else
+// means it missed, and positions to either side are tried in turn. Each
+// probe stroke is undone. The positions found are kept for the window's size
+// and DPI in the calibration file and reapplied on later connects.
+
+use crate::backend::{PaintBackend, HWND};
+use crate::capture::CapturedImage;
+use crate::error::{MspMcpError, Result};
+use crate::layout::{self, UiLayout};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::{info, warn};
+
+// Tools whose selection a probe stroke can confirm
+pub const CALIBRATABLE_TOOLS: &[&str] = &["pencil", "brush"];
+
+// Strokes are drawn in this color, which drawings rarely use
+pub const PROBE_COLOR: &str = "#FF00FF";
+const PROBE_RGB: [u8; 3] = [0xFF, 0x00, 0xFF];
+
+// How far each candidate is from the expected position, as fractions of the
+// window width, in the order they are tried
+const CANDIDATE_OFFSETS: &[f64] = &[0.0, -0.01, 0.01, -0.02, 0.02, -0.03, 0.03, -0.04, 0.04, -0.05, 0.05];
+
+// Canvas region the probe strokes are drawn and checked in
+const PROBE_REGION: (i32, i32, u32, u32) = (8, 8, 48, 16);
+
+/// Calibrated positions in a calibration file: layout name -> window key
+/// (layout::window_key) -> tool -> x as a fraction of the window width.
+pub type Calibrations = BTreeMap<String, BTreeMap<String, BTreeMap<String, f64>>>;
+
+/// What calibration found for one tool.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ToolCalibration {
+    pub tool: String,
+    pub x: f64,          // Position that worked
+    pub previous_x: f64, // Position the layout expected
+    pub attempts: usize, // Candidates tried, the working one included
+}
+
+/// Finds the position of each of `tools` in the window and makes the
+/// active layout use them. Returns the window key they were found for and
+/// a report per tool. Fails on the first tool no candidate works for,
+/// leaving the active layout as it was.
+pub fn calibrate(backend: &dyn PaintBackend, hwnd: HWND, tools: &[String]) -> Result<(String, Vec<ToolCalibration>)> {
+    if let Some(tool) = tools.iter().find(|tool| !CALIBRATABLE_TOOLS.contains(&tool.as_str())) {
+        return Err(MspMcpError::InvalidParameters(
+            format!("Can't calibrate '{}'; calibratable tools are {}", tool, CALIBRATABLE_TOOLS.join(", "))));
+    }
+    let (width, height) = backend.canvas_dimensions(hwnd)?;
+    let (probe_x, probe_y, probe_width, probe_height) = PROBE_REGION;
+    if width < (probe_x as u32 + probe_width) || height < (probe_y as u32 + probe_height) {
+        return Err(MspMcpError::InvalidParameters(format!("The {}x{} canvas is too small to draw probe strokes on", width, height)));
+    }
+
+    let info = backend.window_info(hwnd)?;
+    let window_key = layout::window_key(info.window_rect.width, info.window_rect.height, info.dpi);
+    let original = layout::active();
+    backend.set_color(hwnd, PROBE_COLOR)?;
+
+    let mut calibrated = original.clone();
+    let mut report = Vec::new();
+    for tool in tools {
+        let expected = calibrated.for_window(&window_key).tool_positions.get(tool).copied()
+            .ok_or_else(|| MspMcpError::InvalidParameters(format!("The '{}' layout has no position for '{}'", calibrated.name, tool)))?;
+        let mut found = None;
+        for (attempt, offset) in CANDIDATE_OFFSETS.iter().enumerate() {
+            let x = (expected + offset).clamp(0.0, 1.0);
+            let mut trial = calibrated.clone();
+            trial.calibrated.entry(window_key.clone()).or_default().insert(tool.clone(), x);
+            layout::set_active(trial.clone());
+            match probe(backend, hwnd, tool) {
+                Ok(true) => {
+                    found = Some((x, attempt + 1, trial));
+                    break;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    layout::set_active(original);
+                    return Err(e);
+                }
+            }
+        }
+        let Some((x, attempts, trial)) = found else {
+            layout::set_active(original);
+            return Err(MspMcpError::ElementNotFound(format!(
+                "The '{}' button; no position within {} of the window width from {:.3} worked",
+                tool, CANDIDATE_OFFSETS.iter().fold(0.0_f64, |max, offset| max.max(offset.abs())), expected)));
+        };
+        info!("Calibrated '{}' at x = {:.3} of the window width (expected {:.3}, {} attempts)", tool, x, expected, attempts);
+        calibrated = trial;
+        report.push(ToolCalibration { tool: tool.clone(), x, previous_x: expected, attempts });
+    }
+    layout::set_active(calibrated);
+    Ok((window_key, report))
+}
+
+// Selects `tool` through the active layout and draws a probe stroke. True
+// if the stroke came out in the probe color. A stroke that changed the
+// canvas is undone either way
+fn probe(backend: &dyn PaintBackend, hwnd: HWND, tool: &str) -> Result<bool> {
+    let (x, y, width, height) = PROBE_REGION;
+    backend.select_tool(hwnd, tool)?;
+    let before = backend.capture_canvas_region(hwnd, x, y, width, height)?;
+    let middle = y + height as i32 / 2;
+    backend.drag(hwnd, (x + 4, middle), (x + width as i32 - 4, middle))?;
+    let after = backend.capture_canvas_region(hwnd, x, y, width, height)?;
+    if after.rgba != before.rgba {
+        backend.undo(hwnd)?;
+    }
+    Ok(!shows_probe_color(&before) && shows_probe_color(&after))
+}
+
+fn shows_probe_color(image: &CapturedImage) -> bool {
+    image.rgba.chunks_exact(4)
+        .any(|px| (0..3).all(|i| (px[i] as i32 - PROBE_RGB[i] as i32).abs() <= 24))
+}
+
+/// Reads a calibration file; a missing file has no calibrations.
+pub fn load(path: &Path) -> Result<Calibrations> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(serde_json::from_str(&text)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Calibrations::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Adds the calibrated positions of `layout` to the calibration file.
+pub fn save(path: &Path, layout: &UiLayout) -> Result<()> {
+    let mut calibrations = load(path).unwrap_or_else(|e| {
+        warn!("Replacing unreadable calibration file {}: {}", path.display(), e);
+        Calibrations::new()
+    });
+    calibrations.insert(layout.name.clone(), layout.calibrated.clone());
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&calibrations)?)?;
+    info!("Saved calibration to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulate::SimulatedBackend;
+
+    #[test]
+    fn test_calibration_is_undone_and_saved() {
+        let backend = SimulatedBackend::new();
+        let hwnd = backend.find_or_launch_paint().unwrap();
+        let blank = backend.capture_canvas(hwnd).unwrap();
+        let (window_key, report) = calibrate(&backend, hwnd, &["pencil".to_string()]).unwrap();
+        assert_eq!(report[0].attempts, 1);
+        assert_eq!(report[0].x, report[0].previous_x);
+        assert_eq!(backend.capture_canvas(hwnd).unwrap().rgba, blank.rgba);
+        assert!(calibrate(&backend, hwnd, &["text".to_string()]).is_err());
+
+        let path = std::env::temp_dir().join(format!("mspmcp-calibration-{}.json", std::process::id()));
+        let mut layout = UiLayout::built_in("windows11").unwrap();
+        layout.calibrated.insert(window_key.clone(), BTreeMap::from([("pencil".to_string(), report[0].x)]));
+        save(&path, &layout).unwrap();
+        assert_eq!(load(&path).unwrap()["windows11"][&window_key]["pencil"], report[0].x);
+        std::fs::remove_file(&path).ok();
+        assert!(load(&path).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,470 @@
+// Screen capture of the Paint window and canvas.
+//
+// Pixels are copied from the screen with GDI, so Paint has to be visible
+// (it's activated first). The result is encoded as PNG for MCP image
+// content blocks. A selection can also be read from the clipboard after
+// Paint copies it, which gets its exact pixels whatever the zoom or scroll,
+// and an image put on the clipboard for Paint to paste.
+// Only the image type and the PNG/DIB codecs build without windows-automation.
+
+use crate::error::{MspMcpError, Result};
+#[cfg(feature = "windows-automation")]
+use crate::geometry::rect_size;
+#[cfg(feature = "windows-automation")]
+use crate::windows::{activate_paint_window, client_to_screen, get_canvas_view, get_drawing_area_offset};
+#[cfg(feature = "windows-automation")]
+use std::time::Duration;
+#[cfg(feature = "windows-automation")]
+use tracing::debug;
+#[cfg(feature = "windows-automation")]
+use windows_sys::Win32::Foundation::{GlobalFree, FALSE, HWND, RECT};
+#[cfg(feature = "windows-automation")]
+use windows_sys::Win32::System::DataExchange::{
+    CloseClipboard, EmptyClipboard, GetClipboardData, GetClipboardSequenceNumber, OpenClipboard, SetClipboardData,
+};
+#[cfg(feature = "windows-automation")]
+use windows_sys::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE};
+#[cfg(feature = "windows-automation")]
+use windows_sys::Win32::System::Ole::CF_DIB;
+#[cfg(feature = "windows-automation")]
+use windows_sys::Win32::Graphics::Gdi::{
+    BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, DeleteObject, GetDC, GetDIBits,
+    ReleaseDC, SelectObject, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, CAPTUREBLT, DIB_RGB_COLORS, SRCCOPY,
+};
+#[cfg(feature = "windows-automation")]
+use windows_sys::Win32::UI::WindowsAndMessaging::GetWindowRect;
+
+pub const PNG_MIME_TYPE: &str = "image/png";
+
+// How long to wait for Paint to put a copied selection on the clipboard
+#[cfg(feature = "windows-automation")]
+const CLIPBOARD_TIMEOUT: Duration = Duration::from_secs(2);
+
+// biCompression values of the DIB headers Paint puts on the clipboard
+const BI_RGB_COMPRESSION: u32 = 0;
+const BI_BITFIELDS_COMPRESSION: u32 = 3;
+
+// A captured image as tightly packed 8-bit RGBA rows, top row first
+pub struct CapturedImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl CapturedImage {
+    /// The color of one pixel as `#RRGGBB`, or None outside the image.
+    pub fn pixel_hex(&self, x: u32, y: u32) -> Option<String> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let offset = ((y * self.width + x) * 4) as usize;
+        let px = self.rgba.get(offset..offset + 3)?;
+        Some(format!("#{:02X}{:02X}{:02X}", px[0], px[1], px[2]))
+    }
+
+    /// True if some pixels of this image are the color-inverse of the same
+    /// pixels in `before`. Mid-grays invert to themselves, so only pixels
+    /// that visibly changed count.
+    pub fn shows_inversion_of(&self, before: &CapturedImage) -> bool {
+        if (self.width, self.height) != (before.width, before.height) {
+            return false;
+        }
+        self.rgba.chunks_exact(4).zip(before.rgba.chunks_exact(4)).any(|(after, before)| {
+            (0..3).all(|i| (after[i] as i32 + before[i] as i32 - 255).abs() <= 8)
+                && (0..3).any(|i| (after[i] as i32 - before[i] as i32).abs() > 32)
+        })
+    }
+
+    /// This image resized by `factor` with nearest-neighbour sampling, so
+    /// pixel art stays crisp. Each side is at least 1 pixel.
+    pub fn scaled(&self, factor: f64) -> CapturedImage {
+        let side = |size: u32| ((size as f64 * factor).round() as u32).max(1);
+        let (width, height) = (side(self.width), side(self.height));
+        let source = |i: u32, size: u32, scaled: u32| (i as u64 * size as u64 / scaled as u64) as u32;
+        let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            let row = source(y, self.height, height);
+            for x in 0..width {
+                let offset = ((row * self.width + source(x, self.width, width)) * 4) as usize;
+                rgba.extend_from_slice(&self.rgba[offset..offset + 4]);
+            }
+        }
+        CapturedImage { width, height, rgba }
+    }
+
+    /// This image with transparent pixels blended onto white and alpha
+    /// forced to opaque, the way Paint shows a pasted image.
+    pub fn flattened(&self) -> CapturedImage {
+        let rgba = self.rgba.chunks_exact(4)
+            .flat_map(|px| {
+                let blend = |c: u8| ((c as u32 * px[3] as u32 + 255 * (255 - px[3] as u32) + 127) / 255) as u8;
+                [blend(px[0]), blend(px[1]), blend(px[2]), 0xFF]
+            })
+            .collect();
+        CapturedImage { width: self.width, height: self.height, rgba }
+    }
+}
+
+/// Converts GDI's BGRX pixels to RGBA in place, forcing alpha to opaque
+/// (screen DCs leave the fourth byte undefined).
+pub fn bgra_to_rgba(pixels: &mut [u8]) {
+    for px in pixels.chunks_exact_mut(4) {
+        px.swap(0, 2);
+        px[3] = 0xFF;
+    }
+}
+
+/// Copies a rectangle of the screen (virtual-screen coordinates).
+#[cfg(feature = "windows-automation")]
+pub fn capture_screen_rect(left: i32, top: i32, width: u32, height: u32) -> Result<CapturedImage> {
+    if width == 0 || height == 0 {
+        return Err(MspMcpError::InvalidParameters(format!("Cannot capture an empty {}x{} area", width, height)));
+    }
+    debug!("Capturing screen area {}x{} at ({}, {})", width, height, left, top);
+
+    let mut pixels = vec![0u8; width as usize * height as usize * 4];
+    unsafe {
+        let screen_dc = GetDC(0);
+        if screen_dc == 0 {
+            return Err(MspMcpError::WindowsApiError("GetDC failed".to_string()));
+        }
+        let mem_dc = CreateCompatibleDC(screen_dc);
+        let bitmap = CreateCompatibleBitmap(screen_dc, width as i32, height as i32);
+        if mem_dc == 0 || bitmap == 0 {
+            if bitmap != 0 { DeleteObject(bitmap); }
+            if mem_dc != 0 { DeleteDC(mem_dc); }
+            ReleaseDC(0, screen_dc);
+            return Err(MspMcpError::WindowsApiError("Failed to create capture bitmap".to_string()));
+        }
+        let previous = SelectObject(mem_dc, bitmap);
+
+        let copied = BitBlt(mem_dc, 0, 0, width as i32, height as i32, screen_dc, left, top, SRCCOPY | CAPTUREBLT);
+
+        // Negative height requests top-down rows
+        let mut info: BITMAPINFO = std::mem::zeroed();
+        info.bmiHeader = BITMAPINFOHEADER {
+            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: width as i32,
+            biHeight: -(height as i32),
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB,
+            biSizeImage: 0,
+            biXPelsPerMeter: 0,
+            biYPelsPerMeter: 0,
+            biClrUsed: 0,
+            biClrImportant: 0,
+        };
+        SelectObject(mem_dc, previous);
+        let lines = GetDIBits(mem_dc, bitmap, 0, height, pixels.as_mut_ptr() as *mut _, &mut info, DIB_RGB_COLORS);
+
+        DeleteObject(bitmap);
+        DeleteDC(mem_dc);
+        ReleaseDC(0, screen_dc);
+
+        if copied == FALSE {
+            return Err(MspMcpError::WindowsApiError("BitBlt from the screen failed".to_string()));
+        }
+        if lines != height as i32 {
+            return Err(MspMcpError::WindowsApiError(format!("GetDIBits returned {} of {} rows", lines, height)));
+        }
+    }
+
+    bgra_to_rgba(&mut pixels);
+    Ok(CapturedImage { width, height, rgba: pixels })
+}
+
+/// Captures the whole Paint window, including its frame.
+#[cfg(feature = "windows-automation")]
+pub fn capture_window(hwnd: HWND) -> Result<CapturedImage> {
+    activate_paint_window(hwnd)?;
+
+    let mut rect: RECT = unsafe { std::mem::zeroed() };
+    if unsafe { GetWindowRect(hwnd, &mut rect) } == FALSE {
+        return Err(MspMcpError::WindowsApiError("GetWindowRect failed".to_string()));
+    }
+    capture_screen_rect(rect.left, rect.top, (rect.right - rect.left).max(0) as u32, (rect.bottom - rect.top).max(0) as u32)
+}
+
+/// Captures part of the canvas, in canvas coordinates. The region is
+/// clipped to the part of the canvas that fits in the viewport.
+#[cfg(feature = "windows-automation")]
+pub fn capture_canvas_region(hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<CapturedImage> {
+    let (canvas_width, canvas_height) = visible_canvas_size(hwnd)?; // Also activates Paint
+
+    let left = x.max(0);
+    let top = y.max(0);
+    let right = (x as i64 + width as i64).min(canvas_width as i64) as i32;
+    let bottom = (y as i64 + height as i64).min(canvas_height as i64) as i32;
+    if right <= left || bottom <= top {
+        return Err(MspMcpError::InvalidParameters(format!(
+            "Region {}x{} at ({}, {}) is outside the {}x{} canvas", width, height, x, y, canvas_width, canvas_height)));
+    }
+
+    let (offset_x, offset_y) = get_drawing_area_offset(hwnd)?;
+    let (screen_x, screen_y) = client_to_screen(hwnd, left + offset_x, top + offset_y)?;
+    capture_screen_rect(screen_x, screen_y, (right - left) as u32, (bottom - top) as u32)
+}
+
+/// Captures the whole canvas, or as much of it as is on screen.
+#[cfg(feature = "windows-automation")]
+pub fn capture_canvas(hwnd: HWND) -> Result<CapturedImage> {
+    let (canvas_width, canvas_height) = visible_canvas_size(hwnd)?;
+    capture_canvas_region(hwnd, 0, 0, canvas_width, canvas_height)
+}
+
+// The document size, cut down to the viewport so a document larger than the
+// window doesn't capture whatever is next to Paint on screen
+#[cfg(feature = "windows-automation")]
+fn visible_canvas_size(hwnd: HWND) -> Result<(u32, u32)> {
+    let view = get_canvas_view(hwnd)?;
+    let (viewport_width, viewport_height) = rect_size(view.viewport.left, view.viewport.top, view.viewport.right, view.viewport.bottom);
+    Ok((view.width.min(viewport_width), view.height.min(viewport_height)))
+}
+
+/// Encodes a captured image as PNG.
+pub fn encode_png(image: &CapturedImage) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut out, image.width, image.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()
+            .map_err(|e| MspMcpError::General(format!("PNG encoding failed: {}", e)))?;
+        writer.write_image_data(&image.rgba)
+            .map_err(|e| MspMcpError::General(format!("PNG encoding failed: {}", e)))?;
+    }
+    Ok(out)
+}
+
+/// Decodes an 8-bit RGB or RGBA PNG (e.g. a golden image) to RGBA.
+pub fn decode_png(data: &[u8]) -> Result<CapturedImage> {
+    let mut reader = png::Decoder::new(data).read_info()
+        .map_err(|e| MspMcpError::InvalidImageFormat(format!("PNG decoding failed: {}", e)))?;
+    let mut buffer = vec![0u8; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buffer)
+        .map_err(|e| MspMcpError::InvalidImageFormat(format!("PNG decoding failed: {}", e)))?;
+    buffer.truncate(frame.buffer_size());
+
+    if frame.bit_depth != png::BitDepth::Eight {
+        return Err(MspMcpError::InvalidImageFormat(format!("Unsupported PNG bit depth {:?}", frame.bit_depth)));
+    }
+    let rgba = match frame.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer.chunks_exact(3).flat_map(|px| [px[0], px[1], px[2], 0xFF]).collect(),
+        other => return Err(MspMcpError::InvalidImageFormat(format!("Unsupported PNG color type {:?}", other))),
+    };
+    Ok(CapturedImage { width: frame.width, height: frame.height, rgba })
+}
+
+/// Decodes a packed DIB (the clipboard's CF_DIB: a BITMAPINFOHEADER or
+/// later header followed by the pixels) with 24 or 32 bits per pixel.
+/// Alpha is forced to opaque, as for screen captures.
+pub fn decode_dib(data: &[u8]) -> Result<CapturedImage> {
+    let invalid = |what: &str| MspMcpError::InvalidImageFormat(format!("Clipboard bitmap {}", what));
+    let u32_at = |offset: usize| data.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let header_size = u32_at(0).filter(|size| *size >= 40).ok_or_else(|| invalid("has no BITMAPINFOHEADER"))? as usize;
+    let width = u32_at(4).ok_or_else(|| invalid("is truncated"))? as i32;
+    let height = u32_at(8).ok_or_else(|| invalid("is truncated"))? as i32;
+    let bit_count = data.get(14..16).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(|| invalid("is truncated"))?;
+    let compression = u32_at(16).ok_or_else(|| invalid("is truncated"))?;
+    if width <= 0 || height == 0 {
+        return Err(invalid(&format!("has an empty {}x{} size", width, height)));
+    }
+    if !matches!(bit_count, 24 | 32) || !matches!(compression, BI_RGB_COMPRESSION | BI_BITFIELDS_COMPRESSION) {
+        return Err(invalid(&format!("has an unsupported format ({} bits, compression {})", bit_count, compression)));
+    }
+
+    // Bit masks follow a plain BITMAPINFOHEADER; later headers include them
+    let masks = if compression == BI_BITFIELDS_COMPRESSION && header_size == 40 { 12 } else { 0 };
+    let bytes_per_pixel = bit_count as usize / 8;
+    let (width, rows) = (width as usize, height.unsigned_abs() as usize);
+    let stride = (width * bytes_per_pixel).div_ceil(4) * 4;
+    let pixels = data.get(header_size + masks..).filter(|p| p.len() >= stride * rows).ok_or_else(|| invalid("is truncated"))?;
+
+    // Positive heights store the bottom row first
+    let mut rgba = Vec::with_capacity(width * rows * 4);
+    for row in 0..rows {
+        let source = if height > 0 { rows - 1 - row } else { row };
+        let line = &pixels[source * stride..source * stride + width * bytes_per_pixel];
+        rgba.extend(line.chunks_exact(bytes_per_pixel).flat_map(|px| [px[2], px[1], px[0], 0xFF]));
+    }
+    Ok(CapturedImage { width: width as u32, height: rows as u32, rgba })
+}
+
+/// Encodes an image as a packed DIB for the clipboard's CF_DIB: a
+/// BITMAPINFOHEADER followed by 24-bit rows, bottom row first. Alpha is
+/// dropped; see CapturedImage::flattened.
+pub fn encode_dib(image: &CapturedImage) -> Vec<u8> {
+    let stride = (image.width as usize * 3).div_ceil(4) * 4;
+    let mut data = Vec::with_capacity(40 + stride * image.height as usize);
+    for value in [40u32, image.width, image.height] {
+        data.extend(value.to_le_bytes());
+    }
+    data.extend(1u16.to_le_bytes()); // Planes
+    data.extend(24u16.to_le_bytes()); // Bits per pixel
+    data.extend(BI_RGB_COMPRESSION.to_le_bytes());
+    data.extend(((stride * image.height as usize) as u32).to_le_bytes());
+    data.extend([0u8; 16]); // Resolution and palette, unused
+    for row in image.rgba.chunks_exact(image.width as usize * 4).rev() {
+        let start = data.len();
+        data.extend(row.chunks_exact(4).flat_map(|px| [px[2], px[1], px[0]]));
+        data.resize(start + stride, 0);
+    }
+    data
+}
+
+/// Puts an image on the clipboard as a bitmap, replacing what was there.
+#[cfg(feature = "windows-automation")]
+pub fn write_clipboard_image(image: &CapturedImage) -> Result<()> {
+    let dib = encode_dib(image);
+    unsafe {
+        if OpenClipboard(0) == FALSE {
+            return Err(MspMcpError::WindowsApiError("OpenClipboard failed".to_string()));
+        }
+        let result = if EmptyClipboard() == FALSE {
+            Err(MspMcpError::WindowsApiError("EmptyClipboard failed".to_string()))
+        } else {
+            let handle = GlobalAlloc(GMEM_MOVEABLE, dib.len());
+            let data = if handle.is_null() { std::ptr::null_mut() } else { GlobalLock(handle) as *mut u8 };
+            if data.is_null() {
+                if !handle.is_null() { GlobalFree(handle); }
+                Err(MspMcpError::WindowsApiError("Failed to allocate the clipboard bitmap".to_string()))
+            } else {
+                std::ptr::copy_nonoverlapping(dib.as_ptr(), data, dib.len());
+                GlobalUnlock(handle);
+                // The clipboard owns the memory once it takes it
+                if SetClipboardData(CF_DIB as u32, handle as _) == 0 {
+                    GlobalFree(handle);
+                    Err(MspMcpError::WindowsApiError("SetClipboardData failed".to_string()))
+                } else {
+                    debug!("Put a {}x{} bitmap on the clipboard", image.width, image.height);
+                    Ok(())
+                }
+            }
+        };
+        CloseClipboard();
+        result
+    }
+}
+
+/// Reads the bitmap on the clipboard. Fails if the clipboard is held by
+/// another program or has no bitmap.
+#[cfg(feature = "windows-automation")]
+pub fn read_clipboard_image() -> Result<CapturedImage> {
+    unsafe {
+        if OpenClipboard(0) == FALSE {
+            return Err(MspMcpError::WindowsApiError("OpenClipboard failed".to_string()));
+        }
+        let handle = GetClipboardData(CF_DIB as u32);
+        let result = if handle == 0 {
+            Err(MspMcpError::General("The clipboard has no bitmap".to_string()))
+        } else {
+            let data = GlobalLock(handle as _) as *const u8;
+            if data.is_null() {
+                Err(MspMcpError::WindowsApiError("GlobalLock on the clipboard bitmap failed".to_string()))
+            } else {
+                let decoded = decode_dib(std::slice::from_raw_parts(data, GlobalSize(handle as _)));
+                GlobalUnlock(handle as _);
+                decoded
+            }
+        };
+        CloseClipboard();
+        result
+    }
+}
+
+/// Copies the current selection (Ctrl+C) and reads it back from the
+/// clipboard, once Paint has replaced what was there before.
+#[cfg(feature = "windows-automation")]
+pub fn copy_selection_image(hwnd: HWND) -> Result<CapturedImage> {
+    let before = unsafe { GetClipboardSequenceNumber() };
+    crate::windows::copy_selection(hwnd)?;
+
+    let started = crate::clock::now();
+    loop {
+        crate::clock::sleep(Duration::from_millis(100));
+        // Paint may still have the clipboard open right after it changes
+        let copied = unsafe { GetClipboardSequenceNumber() } != before;
+        match copied.then(read_clipboard_image) {
+            Some(Ok(image)) => {
+                debug!("Read a {}x{} selection from the clipboard", image.width, image.height);
+                return Ok(image);
+            }
+            Some(Err(e)) if crate::clock::now() - started > CLIPBOARD_TIMEOUT => return Err(e),
+            None if crate::clock::now() - started > CLIPBOARD_TIMEOUT => {
+                return Err(MspMcpError::OperationTimeout("Paint did not copy the selection to the clipboard".to_string()));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Selects the whole canvas (Ctrl+A), copies it and reads it back from
+/// the clipboard, then drops the selection (Escape). Unlike
+/// capture_canvas, this gets the parts of the image scrolled out of view.
+#[cfg(feature = "windows-automation")]
+pub fn copy_canvas_image(hwnd: HWND) -> Result<CapturedImage> {
+    activate_paint_window(hwnd)?;
+    crate::windows::press_ctrl_a()?;
+    crate::clock::sleep(Duration::from_millis(200));
+    let copied = copy_selection_image(hwnd);
+    crate::windows::press_escape()?;
+    copied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A packed DIB with a 40-byte header and 24-bit rows, padded to 4 bytes
+    fn dib(width: i32, height: i32, rows: &[&[[u8; 3]]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for value in [40u32, width as u32, height as u32] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(1u16.to_le_bytes());
+        data.extend(24u16.to_le_bytes());
+        data.extend([0u8; 24]);
+        for row in rows {
+            let start = data.len();
+            data.extend(row.iter().flat_map(|&[r, g, b]| [b, g, r]));
+            data.resize(start + (row.len() * 3).div_ceil(4) * 4, 0);
+        }
+        data
+    }
+
+    #[test]
+    fn test_decode_dib_rows_and_padding() {
+        let (red, blue) = ([0xFF, 0, 0], [0, 0, 0xFF]);
+        // Bottom-up: the blue row is stored first but is the bottom row
+        let image = decode_dib(&dib(3, 2, &[&[blue; 3], &[red; 3]])).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!((image.pixel_hex(2, 0).as_deref(), image.pixel_hex(0, 1).as_deref()), (Some("#FF0000"), Some("#0000FF")));
+        assert_eq!(image.rgba[3], 0xFF);
+
+        let top_down = decode_dib(&dib(3, -2, &[&[blue; 3], &[red; 3]])).unwrap();
+        assert_eq!(top_down.pixel_hex(0, 0).as_deref(), Some("#0000FF"));
+
+        let truncated = dib(3, 2, &[&[red; 3]]);
+        assert!(matches!(decode_dib(&truncated), Err(MspMcpError::InvalidImageFormat(_))));
+        assert!(decode_dib(&[0u8; 8]).is_err());
+    }
+
+    #[test]
+    fn test_stamp_images_scale_flatten_and_round_trip() {
+        // A 2x1 image: opaque red, half-transparent black
+        let image = CapturedImage { width: 2, height: 1, rgba: vec![0xFF, 0, 0, 0xFF, 0, 0, 0, 0x80] };
+        let flat = image.flattened();
+        assert_eq!((flat.pixel_hex(0, 0).as_deref(), flat.pixel_hex(1, 0).as_deref()), (Some("#FF0000"), Some("#7F7F7F")));
+
+        let big = flat.scaled(3.0);
+        assert_eq!((big.width, big.height), (6, 3));
+        assert_eq!((big.pixel_hex(2, 2).as_deref(), big.pixel_hex(3, 0).as_deref()), (Some("#FF0000"), Some("#7F7F7F")));
+        assert_eq!((flat.scaled(0.1).width, flat.scaled(0.1).height), (1, 1));
+
+        let decoded = decode_dib(&encode_dib(&big)).unwrap();
+        assert_eq!((decoded.width, decoded.height), (6, 3));
+        assert_eq!(decoded.rgba, big.rgba);
+    }
+}
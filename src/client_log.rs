@@ -0,0 +1,121 @@
+// Forwarding of server log events to the client (MCP logging capability).
+//
+// Warnings and errors from windows.rs/uia.rs otherwise only end up in the
+// temp-file log on the Windows box. This layer sits alongside the file and
+// terminal layers and sends events at or above the client-selected level
+// as `notifications/message`.
+
+use std::cell::Cell;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+// Only records from this crate are forwarded (not the SDK, tokio, etc.)
+const CRATE_TARGET: &str = "mcp_server_microsoft_paint";
+
+// MCP log levels (RFC 5424 severities), lowest to highest
+pub const MCP_LOG_LEVELS: [&str; 8] = [
+    "debug", "info", "notice", "warning", "error", "critical", "alert", "emergency",
+];
+
+// Index into MCP_LOG_LEVELS; records below it are not forwarded
+static CLIENT_LOG_LEVEL: AtomicU8 = AtomicU8::new(3); // "warning"
+
+thread_local! {
+    // Prevents recursion when sending a notification itself logs
+    static FORWARDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns the index of an MCP level name, or None if it isn't one.
+pub fn parse_mcp_level(name: &str) -> Option<u8> {
+    MCP_LOG_LEVELS.iter().position(|l| l.eq_ignore_ascii_case(name)).map(|i| i as u8)
+}
+
+/// Maps a `tracing` level onto the closest MCP level index.
+pub fn mcp_level_for(level: Level) -> u8 {
+    if level == Level::ERROR {
+        4
+    } else if level == Level::WARN {
+        3
+    } else if level == Level::INFO {
+        1
+    } else {
+        0 // DEBUG and TRACE
+    }
+}
+
+/// Sets the minimum level forwarded to the client.
+pub fn set_client_log_level(level: u8) {
+    CLIENT_LOG_LEVEL.store(level, Ordering::SeqCst);
+}
+
+/// Returns the name of the minimum level forwarded to the client.
+pub fn client_log_level() -> &'static str {
+    MCP_LOG_LEVELS[CLIENT_LOG_LEVEL.load(Ordering::SeqCst) as usize]
+}
+
+// Collects an event's message, followed by any other fields as name=value
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+pub struct ClientLogLayer;
+
+impl<S: Subscriber> Layer<S> for ClientLogLayer {
+    // Filtering happens here rather than in `enabled`, which would hide the
+    // events from the file and terminal layers too
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = mcp_level_for(*metadata.level());
+        if !metadata.target().starts_with(CRATE_TARGET) || level < CLIENT_LOG_LEVEL.load(Ordering::SeqCst) {
+            return;
+        }
+        if FORWARDING.with(|f| f.replace(true)) {
+            return; // Already forwarding on this thread
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        crate::transport::send_notification("notifications/message", serde_json::json!({
+            "level": MCP_LOG_LEVELS[level as usize],
+            "logger": metadata.target(),
+            "data": format!("{}{}", visitor.message, visitor.fields)
+        }));
+
+        FORWARDING.with(|f| f.set(false));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mcp_level() {
+        assert_eq!(parse_mcp_level("debug"), Some(0));
+        assert_eq!(parse_mcp_level("WARNING"), Some(3));
+        assert_eq!(parse_mcp_level("emergency"), Some(7));
+        assert_eq!(parse_mcp_level("verbose"), None);
+    }
+
+    #[test]
+    fn test_log_levels_map_onto_mcp_levels() {
+        assert_eq!(MCP_LOG_LEVELS[mcp_level_for(Level::ERROR) as usize], "error");
+        assert_eq!(MCP_LOG_LEVELS[mcp_level_for(Level::WARN) as usize], "warning");
+        assert_eq!(MCP_LOG_LEVELS[mcp_level_for(Level::TRACE) as usize], "debug");
+    }
+}
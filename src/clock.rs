@@ -0,0 +1,143 @@
+// Time source for waits and timestamps.
+//
+// Automating Paint is full of short pauses (waiting for a click to land, a
+// dialog to open). They all go through `clock::sleep`, which uses the clock
+// installed on the current thread: the real system clock by default, or a
+// VirtualClock in tests, where sleeping just advances virtual time so a
+// handler that would pause for seconds finishes instantly.
+//
+// PaintServerState carries the clock for its requests and installs it for
+// the duration of each one. Handlers run to completion without yielding to
+// the runtime, so the installed clock stays on the thread doing the work.
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+/// Real time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Time that only moves when someone sleeps (or calls `advance`).
+pub struct VirtualClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock::new()
+    }
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        VirtualClock { start: Instant::now(), elapsed: Mutex::new(Duration::ZERO) }
+    }
+
+    /// Total virtual time passed so far.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner()) += duration;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+thread_local! {
+    static THREAD_CLOCK: RefCell<Option<Arc<dyn Clock>>> = const { RefCell::new(None) };
+}
+
+/// Restores the previously installed clock when dropped.
+pub struct ClockGuard {
+    previous: Option<Arc<dyn Clock>>,
+}
+
+impl Drop for ClockGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        THREAD_CLOCK.with(|clock| *clock.borrow_mut() = previous);
+    }
+}
+
+/// Makes `clock` the current thread's clock until the guard is dropped.
+pub fn install(clock: Arc<dyn Clock>) -> ClockGuard {
+    let previous = THREAD_CLOCK.with(|current| current.borrow_mut().replace(clock));
+    ClockGuard { previous }
+}
+
+/// Current time on this thread's clock.
+pub fn now() -> Instant {
+    THREAD_CLOCK.with(|clock| match clock.borrow().as_ref() {
+        Some(clock) => clock.now(),
+        None => Instant::now(),
+    })
+}
+
+/// Waits on this thread's clock.
+pub fn sleep(duration: Duration) {
+    crate::timing::record(crate::timing::Phase::Sleep, duration);
+    // Clone out of the cell so a clock that sleeps for real doesn't hold the borrow
+    match THREAD_CLOCK.with(|clock| clock.borrow().clone()) {
+        Some(clock) => clock.sleep(duration),
+        None => std::thread::sleep(duration),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_sleep_is_instant() {
+        let clock = Arc::new(VirtualClock::new());
+        let started = Instant::now();
+        {
+            let _guard = install(clock.clone());
+            let virtual_start = now();
+            sleep(Duration::from_secs(3600));
+            assert_eq!(now() - virtual_start, Duration::from_secs(3600));
+        }
+        assert_eq!(clock.elapsed(), Duration::from_secs(3600));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_guard_restores_previous_clock() {
+        let outer = Arc::new(VirtualClock::new());
+        let inner = Arc::new(VirtualClock::new());
+        let _outer_guard = install(outer.clone());
+        {
+            let _inner_guard = install(inner.clone());
+            sleep(Duration::from_millis(5));
+        }
+        sleep(Duration::from_millis(7));
+        assert_eq!(inner.elapsed(), Duration::from_millis(5));
+        assert_eq!(outer.elapsed(), Duration::from_millis(7));
+    }
+}
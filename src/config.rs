@@ -0,0 +1,533 @@
+// Server-side configuration.
+//
+// Loaded at startup from a TOML file: the path given with `--config`, else
+// the MSPAINT_MCP_CONFIG environment variable, else `mcp_paint.toml` in the
+// working directory if it exists. Every setting has a default, so the file
+// (and any key in it) is optional. While the server runs, config_watch.rs
+// reloads the file when it changes; the settings in RELOADABLE_SETTINGS take
+// effect right away and the others on the next start.
+
+use crate::error::{MspMcpError, Result};
+use crate::presets::StrokePreset;
+use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Environment variable naming the config file
+pub const CONFIG_ENV_VAR: &str = "MSPAINT_MCP_CONFIG";
+
+// Config file looked for in the working directory
+pub const DEFAULT_CONFIG_FILE: &str = "mcp_paint.toml";
+
+// Default for max_fetch_bytes
+pub const DEFAULT_MAX_FETCH_BYTES: u64 = 10 * 1024 * 1024;
+
+// Default for max_capture_megapixels
+pub const DEFAULT_MAX_CAPTURE_MEGAPIXELS: f64 = 25.0;
+
+// Default for slow_operations.warn_after_ms
+pub const DEFAULT_SLOW_OPERATION_MS: u64 = 5000;
+
+// Default for input_desktop_wait_ms
+pub const DEFAULT_INPUT_DESKTOP_WAIT_MS: u64 = 30_000;
+
+// Default for watchdog.limit_ms
+pub const DEFAULT_WATCHDOG_LIMIT_MS: u64 = 300_000;
+
+// Defaults for screenshot_trail.max_sessions and max_screenshots
+pub const DEFAULT_TRAIL_SESSIONS: usize = 5;
+pub const DEFAULT_TRAIL_SCREENSHOTS: usize = 1000;
+
+// Calibration file kept in the temp directory unless calibration_file is set
+pub const DEFAULT_CALIBRATION_FILE: &str = "mcp_paint_calibration.json";
+
+// Default for log_max_field_len
+pub const DEFAULT_LOG_MAX_FIELD_LEN: usize = 200;
+
+// Settings a changed config file applies without a restart. Each is read
+// where it is used, or pushed to its owner by PaintServerState::reload_config
+pub const RELOADABLE_SETTINGS: &[&str] = &[
+    "confirm_destructive", "shutdown_document", "allowed_dirs", "allow_unc_paths", "max_fetch_bytes",
+    "max_capture_megapixels", "rate_limits", "slow_operations", "log_max_field_len", "log_level", "ui_layout",
+    "calibration_file", "stroke_presets", "watchdog", "input_desktop_wait_ms",
+];
+
+// What to do with the open document when the client shuts the server down
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShutdownDocumentAction {
+    Keep,    // Leave Paint open as it is
+    Save,    // Save the document if it has a name and unsaved changes
+    Discard, // Close Paint, dropping unsaved changes
+}
+
+// How mouse and keyboard input reaches Paint (see session.rs)
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InputPath {
+    #[default]
+    Auto,      // SendInput, or window messages while the session is disconnected
+    SendInput, // Always SendInput
+    Messages,  // Always window messages posted to Paint
+}
+
+// Deliberate failures for exercising retry and recovery paths. Each value
+// is the probability (0.0-1.0) that one backend call of that kind fails
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct FaultConfig {
+    pub send_input: f64,   // Mouse/keyboard input (clicks, drags, strokes, typing)
+    pub uia_lookup: f64,   // UI Automation lookups (tool, color, size and fill settings)
+    pub activation: f64,   // Bringing Paint to the foreground
+    pub seed: Option<u64>, // Fixed seed to make a run reproducible
+}
+
+impl FaultConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.send_input > 0.0 || self.uia_lookup > 0.0 || self.activation > 0.0
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (name, p) in [("send_input", self.send_input), ("uia_lookup", self.uia_lookup), ("activation", self.activation)] {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(MspMcpError::ConfigError(format!("faults.{} must be between 0.0 and 1.0, got {}", name, p)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Budgets for the session over a sliding minute. 0 means unlimited
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub ops_per_minute: u32,          // Requests of any kind except the session lifecycle
+    pub input_events_per_minute: u32, // Mouse and keyboard events sent to Paint
+}
+
+// When a request counts as slow. Slow requests are logged as warnings with
+// a breakdown of where the time went
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SlowOperationConfig {
+    pub warn_after_ms: u64,             // 0 turns the warnings off
+    pub methods: BTreeMap<String, u64>, // Per-method thresholds overriding warn_after_ms
+    pub notify_client: bool,            // Also send notifications/paint/slow_operation
+}
+
+impl Default for SlowOperationConfig {
+    fn default() -> Self {
+        SlowOperationConfig { warn_after_ms: DEFAULT_SLOW_OPERATION_MS, methods: BTreeMap::new(), notify_client: false }
+    }
+}
+
+impl SlowOperationConfig {
+    /// Duration after which `method` counts as slow, or None if it never does.
+    pub fn threshold_for(&self, method: &str) -> Option<Duration> {
+        let ms = self.methods.get(method).copied().unwrap_or(self.warn_after_ms);
+        if ms == 0 { None } else { Some(Duration::from_millis(ms)) }
+    }
+}
+
+// Hard limits on requests that drive Paint. A request still running at its
+// limit has its held input released and its gesture cancelled (see watchdog.rs)
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub limit_ms: u64,                  // 0 turns the watchdog off
+    pub methods: BTreeMap<String, u64>, // Per-method limits overriding limit_ms
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig { limit_ms: DEFAULT_WATCHDOG_LIMIT_MS, methods: BTreeMap::new() }
+    }
+}
+
+impl WatchdogConfig {
+    /// Longest `method` may run, or None if it has no limit.
+    pub fn limit_for(&self, method: &str) -> Option<Duration> {
+        let ms = self.methods.get(method).copied().unwrap_or(self.limit_ms);
+        if ms == 0 { None } else { Some(Duration::from_millis(ms)) }
+    }
+}
+
+// Debug mode saving a screenshot after every automation step (see
+// screenshot_trail.rs). Limits of 0 mean unlimited
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ScreenshotTrailConfig {
+    pub enabled: bool,
+    pub dir: Option<PathBuf>,   // Parent of the session folders; mcp_paint_screenshots in the temp directory by default
+    pub max_sessions: usize,    // Session folders kept, oldest deleted first
+    pub max_screenshots: usize, // Screenshots kept per session, oldest deleted first
+}
+
+impl Default for ScreenshotTrailConfig {
+    fn default() -> Self {
+        ScreenshotTrailConfig {
+            enabled: false,
+            dir: None,
+            max_sessions: DEFAULT_TRAIL_SESSIONS,
+            max_screenshots: DEFAULT_TRAIL_SCREENSHOTS,
+        }
+    }
+}
+
+// Which of Paint's UI layouts (see layout.rs) to click by, and changes to
+// it. Unset values come from the layout picked for Paint's version
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct UiLayoutConfig {
+    pub name: Option<String>,                     // A built-in layout, instead of picking one by version
+    pub toolbar_height: Option<i32>,              // Client pixels above the drawing area
+    pub left_margin: Option<i32>,                 // Client pixels left of the drawing area
+    pub tool_row_y: Option<i32>,                  // Client y of the tool buttons
+    pub tool_positions: BTreeMap<String, f64>,    // Tool -> client x as a fraction of the window width
+    pub automation_ids: BTreeMap<String, String>, // Tool -> AutomationId of its button
+}
+
+impl UiLayoutConfig {
+    fn validate(&self) -> Result<()> {
+        let layouts = crate::layout::built_in_names();
+        if let Some(name) = self.name.as_deref().filter(|name| !layouts.contains(name)) {
+            return Err(MspMcpError::ConfigError(format!("ui_layout.name must be one of {}, got \"{}\"", layouts.join(", "), name)));
+        }
+        if let Some((tool, x)) = self.tool_positions.iter().find(|(_, x)| !(0.0..=1.0).contains(*x)) {
+            return Err(MspMcpError::ConfigError(format!("ui_layout.tool_positions.{} must be between 0.0 and 1.0, got {}", tool, x)));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ServerConfig {
+    // Refuse destructive methods (clear_canvas, create_canvas, ...) unless
+    // the request carries `"confirm": true`
+    pub confirm_destructive: bool,
+    // Applied to the Paint document on `shutdown`
+    pub shutdown_document: ShutdownDocumentAction,
+    // Fault injection, off unless a probability is set
+    pub faults: FaultConfig,
+    // Directories that methods reading or writing files may touch. Empty
+    // means any path is allowed
+    pub allowed_dirs: Vec<PathBuf>,
+    // Let file paths name network shares (\\server\share\...)
+    pub allow_unc_paths: bool,
+    // Largest file fetch_image returns as is, in bytes
+    pub max_fetch_bytes: u64,
+    // Largest image capture methods return, in megapixels
+    pub max_capture_megapixels: f64,
+    // Rate limits, off unless set
+    pub rate_limits: RateLimitConfig,
+    // JSON Lines file every request is appended to; no audit trail if unset
+    pub audit_log: Option<PathBuf>,
+    // Warnings for requests that take too long
+    pub slow_operations: SlowOperationConfig,
+    // Hard limits on requests that send input
+    pub watchdog: WatchdogConfig,
+    // How input is delivered; read at startup
+    pub input_path: InputPath,
+    // Milliseconds input waits for the user's desktop to return while the
+    // workstation is locked or a UAC prompt is shown; 0 fails right away
+    pub input_desktop_wait_ms: u64,
+    // Screenshots after every automation step, off unless enabled
+    pub screenshot_trail: ScreenshotTrailConfig,
+    // Characters of each string in a request that are logged; longer ones
+    // (base64 images, mostly) are cut short. 0 logs them in full
+    pub log_max_field_len: usize,
+    // Milliseconds between notifications/paint/heartbeat messages; 0 (the
+    // default) sends none
+    pub heartbeat_interval_ms: u64,
+    // Level of every server logger, as set_server_log_level takes it; the
+    // loggers keep their own defaults if unset
+    pub log_level: Option<String>,
+    // Paint UI layout; picked from Paint's version unless named here
+    pub ui_layout: UiLayoutConfig,
+    // File calibrate saves measured tool positions to, read back on every
+    // connect; mcp_paint_calibration.json in the temp directory by default
+    pub calibration_file: Option<PathBuf>,
+    // Stroke presets by name (see presets.rs), added to the built-in ones
+    // and replacing any of the same name
+    pub stroke_presets: BTreeMap<String, StrokePreset>,
+}
+
+// The settings that differ between two versions of a config file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigChanges {
+    pub reloaded: Vec<String>,      // Applied by a reload
+    pub needs_restart: Vec<String>, // Only read at startup
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        self.reloaded.is_empty() && self.needs_restart.is_empty()
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            confirm_destructive: false,
+            shutdown_document: ShutdownDocumentAction::Keep,
+            faults: FaultConfig::default(),
+            allowed_dirs: Vec::new(),
+            allow_unc_paths: false,
+            max_fetch_bytes: DEFAULT_MAX_FETCH_BYTES,
+            max_capture_megapixels: DEFAULT_MAX_CAPTURE_MEGAPIXELS,
+            rate_limits: RateLimitConfig::default(),
+            audit_log: None,
+            slow_operations: SlowOperationConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            input_path: InputPath::default(),
+            input_desktop_wait_ms: DEFAULT_INPUT_DESKTOP_WAIT_MS,
+            screenshot_trail: ScreenshotTrailConfig::default(),
+            log_max_field_len: DEFAULT_LOG_MAX_FIELD_LEN,
+            heartbeat_interval_ms: 0,
+            log_level: None,
+            ui_layout: UiLayoutConfig::default(),
+            calibration_file: None,
+            stroke_presets: BTreeMap::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Parses a config from TOML text.
+    pub fn from_toml(text: &str) -> Result<ServerConfig> {
+        let config: ServerConfig = toml::from_str(text).map_err(|e| MspMcpError::ConfigError(e.to_string()))?;
+        config.faults.validate()?;
+        config.ui_layout.validate()?;
+        if config.max_capture_megapixels.is_nan() || config.max_capture_megapixels <= 0.0 {
+            return Err(MspMcpError::ConfigError(format!("max_capture_megapixels must be positive, got {}", config.max_capture_megapixels)));
+        }
+        if let Some(dir) = config.allowed_dirs.iter().find(|dir| !dir.is_absolute()) {
+            return Err(MspMcpError::ConfigError(format!("allowed_dirs entries must be absolute, got {}", dir.display())));
+        }
+        if let Some(level) = config.log_level.as_deref().filter(|level| crate::log_level::parse_level(level).is_none()) {
+            return Err(MspMcpError::ConfigError(format!("log_level must be a level such as \"debug\" or \"warn\", got \"{}\"", level)));
+        }
+        for (name, preset) in &config.stroke_presets {
+            preset.validate().map_err(|e| MspMcpError::ConfigError(format!("stroke_presets.{}: {}", name, e)))?;
+        }
+        Ok(config)
+    }
+
+    /// The top-level settings that differ in `new`, split by whether a
+    /// reload applies them.
+    pub fn changes(&self, new: &ServerConfig) -> ConfigChanges {
+        let (old, new) = (settings(self), settings(new));
+        let mut changes = ConfigChanges::default();
+        for (name, value) in &new {
+            if old.get(name) != Some(value) {
+                let list = if RELOADABLE_SETTINGS.contains(&name.as_str()) { &mut changes.reloaded } else { &mut changes.needs_restart };
+                list.push(name.clone());
+            }
+        }
+        changes.reloaded.sort();
+        changes.needs_restart.sort();
+        changes
+    }
+
+    /// This config with the named settings taken from `new`.
+    pub fn with_settings_from(&self, new: &ServerConfig, names: &[String]) -> Result<ServerConfig> {
+        let mut merged = settings(self);
+        let new = settings(new);
+        for name in names {
+            if let Some(value) = new.get(name) {
+                merged.insert(name.clone(), value.clone());
+            }
+        }
+        serde_json::from_value(serde_json::Value::Object(merged)).map_err(|e| MspMcpError::ConfigError(e.to_string()))
+    }
+
+    /// The file calibrations are kept in.
+    pub fn calibration_path(&self) -> PathBuf {
+        self.calibration_file.clone().unwrap_or_else(|| std::env::temp_dir().join(DEFAULT_CALIBRATION_FILE))
+    }
+
+    /// max_capture_megapixels as a pixel count.
+    pub fn max_capture_pixels(&self) -> u64 {
+        (self.max_capture_megapixels * 1_000_000.0) as u64
+    }
+
+    /// Loads a config file, failing if it can't be read or parsed.
+    pub fn load(path: &Path) -> Result<ServerConfig> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| MspMcpError::ConfigError(format!("Failed to read {}: {}", path.display(), e)))?;
+        let config = ServerConfig::from_toml(&text)
+            .map_err(|e| MspMcpError::ConfigError(format!("{}: {}", path.display(), e)))?;
+        info!("Loaded configuration from {}", path.display());
+        Ok(config)
+    }
+
+    /// Returns the config file to use when none was given explicitly.
+    pub fn default_path() -> Option<PathBuf> {
+        if let Ok(path) = std::env::var(CONFIG_ENV_VAR) {
+            return Some(PathBuf::from(path));
+        }
+        let path = PathBuf::from(DEFAULT_CONFIG_FILE);
+        if path.exists() { Some(path) } else { None }
+    }
+
+    /// Loads the default config file if there is one. Errors are logged and
+    /// fall back to the built-in defaults so the server still starts.
+    pub fn load_default() -> ServerConfig {
+        match ServerConfig::default_path() {
+            Some(path) => ServerConfig::load(&path).unwrap_or_else(|e| {
+                warn!("Using default configuration: {}", e);
+                ServerConfig::default()
+            }),
+            None => ServerConfig::default(),
+        }
+    }
+}
+
+// Each top-level setting by name
+fn settings(config: &ServerConfig) -> serde_json::Map<String, serde_json::Value> {
+    match serde_json::to_value(config) {
+        Ok(serde_json::Value::Object(settings)) => settings,
+        _ => serde_json::Map::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_keys_use_defaults() {
+        assert_eq!(ServerConfig::from_toml("").unwrap(), ServerConfig::default());
+    }
+
+    #[test]
+    fn test_parse_confirm_destructive() {
+        let config = ServerConfig::from_toml("confirm_destructive = true").unwrap();
+        assert!(config.confirm_destructive);
+        assert!(ServerConfig::from_toml("confirm_destructive = \"yes\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_shutdown_document() {
+        let config = ServerConfig::from_toml("shutdown_document = \"discard\"").unwrap();
+        assert_eq!(config.shutdown_document, ShutdownDocumentAction::Discard);
+        assert!(ServerConfig::from_toml("shutdown_document = \"burn\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_faults() {
+        assert!(!ServerConfig::default().faults.is_enabled());
+        let config = ServerConfig::from_toml("[faults]\nsend_input = 0.25\nseed = 7").unwrap();
+        assert_eq!(config.faults, FaultConfig { send_input: 0.25, uia_lookup: 0.0, activation: 0.0, seed: Some(7) });
+        assert!(config.faults.is_enabled());
+        assert!(ServerConfig::from_toml("[faults]\nactivation = 1.5").is_err());
+    }
+
+    #[test]
+    fn test_allowed_dirs_must_be_absolute() {
+        assert!(ServerConfig::from_toml("allowed_dirs = [\"relative/dir\"]").is_err());
+    }
+
+    #[test]
+    fn test_parse_rate_limits() {
+        let config = ServerConfig::from_toml("[rate_limits]\nops_per_minute = 120").unwrap();
+        assert_eq!(config.rate_limits, RateLimitConfig { ops_per_minute: 120, input_events_per_minute: 0 });
+    }
+
+    #[test]
+    fn test_parse_screenshot_trail() {
+        assert!(!ServerConfig::default().screenshot_trail.enabled);
+        let config = ServerConfig::from_toml("[screenshot_trail]\nenabled = true\nmax_sessions = 2").unwrap();
+        assert!(config.screenshot_trail.enabled);
+        assert_eq!((config.screenshot_trail.max_sessions, config.screenshot_trail.max_screenshots), (2, DEFAULT_TRAIL_SCREENSHOTS));
+    }
+
+    #[test]
+    fn test_parse_slow_operations() {
+        let config = ServerConfig::from_toml("[slow_operations]
+warn_after_ms = 2000
+[slow_operations.methods]
+save_canvas = 15000
+get_version = 0").unwrap();
+        assert_eq!(config.slow_operations.threshold_for("draw_line"), Some(Duration::from_secs(2)));
+        assert_eq!(config.slow_operations.threshold_for("save_canvas"), Some(Duration::from_secs(15)));
+        assert_eq!(config.slow_operations.threshold_for("get_version"), None);
+        assert!(!config.slow_operations.notify_client);
+    }
+
+    #[test]
+    fn test_parse_watchdog() {
+        assert_eq!(ServerConfig::default().watchdog.limit_for("draw_line"), Some(Duration::from_millis(DEFAULT_WATCHDOG_LIMIT_MS)));
+        let config = ServerConfig::from_toml("[watchdog]
+limit_ms = 60000
+[watchdog.methods]
+run_macro = 0").unwrap();
+        assert_eq!(config.watchdog.limit_for("draw_line"), Some(Duration::from_secs(60)));
+        assert_eq!(config.watchdog.limit_for("run_macro"), None);
+    }
+
+    #[test]
+    fn test_parse_input_path() {
+        assert_eq!(ServerConfig::default().input_path, InputPath::Auto);
+        assert_eq!(ServerConfig::from_toml("input_path = \"send_input\"").unwrap().input_path, InputPath::SendInput);
+        assert_eq!(ServerConfig::from_toml("input_path = \"messages\"").unwrap().input_path, InputPath::Messages);
+        assert!(ServerConfig::from_toml("input_path = \"postmessage\"").is_err());
+        assert_eq!(ServerConfig::default().input_desktop_wait_ms, DEFAULT_INPUT_DESKTOP_WAIT_MS);
+    }
+
+    #[test]
+    fn test_parse_log_max_field_len() {
+        assert_eq!(ServerConfig::default().log_max_field_len, DEFAULT_LOG_MAX_FIELD_LEN);
+        assert_eq!(ServerConfig::from_toml("log_max_field_len = 0").unwrap().log_max_field_len, 0);
+    }
+
+    #[test]
+    fn test_parse_heartbeat_interval() {
+        assert_eq!(ServerConfig::default().heartbeat_interval_ms, 0);
+        assert_eq!(ServerConfig::from_toml("heartbeat_interval_ms = 30000").unwrap().heartbeat_interval_ms, 30000);
+    }
+
+    #[test]
+    fn test_parse_max_capture_megapixels() {
+        let config = ServerConfig::from_toml("max_capture_megapixels = 2.5").unwrap();
+        assert_eq!(config.max_capture_pixels(), 2_500_000);
+        assert!(ServerConfig::from_toml("max_capture_megapixels = 0.0").is_err());
+    }
+
+    #[test]
+    fn test_parse_ui_layout() {
+        let config = ServerConfig::from_toml("[ui_layout]\nname = \"windows10\"\n[ui_layout.tool_positions]\npencil = 0.2").unwrap();
+        assert_eq!(config.ui_layout.name.as_deref(), Some("windows10"));
+        assert_eq!(config.ui_layout.tool_positions["pencil"], 0.2);
+        assert!(ServerConfig::from_toml("[ui_layout]\nname = \"windows95\"").is_err());
+        assert!(ServerConfig::from_toml("[ui_layout.tool_positions]\npencil = 20").is_err());
+    }
+
+    #[test]
+    fn test_parse_stroke_presets() {
+        let config = ServerConfig::from_toml("[stroke_presets.chalk]\ntool = \"brush\"\nbrush_type = \"crayon\"\nthickness_px = 12").unwrap();
+        assert_eq!(config.stroke_presets["chalk"].brush_type.as_deref(), Some("crayon"));
+        assert_eq!(config.stroke_presets["chalk"].color, None);
+        assert!(ServerConfig::from_toml("[stroke_presets.chalk]\ntool = \"spray can\"").is_err());
+        assert!(ServerConfig::from_toml("[stroke_presets.chalk]\nsize = 3").is_err());
+    }
+
+    #[test]
+    fn test_changes_split_by_reloadability() {
+        let old = ServerConfig::from_toml("[rate_limits]\nops_per_minute = 60\n[faults]\nsend_input = 0.1").unwrap();
+        let new = ServerConfig::from_toml("log_level = \"warn\"\nheartbeat_interval_ms = 1000\n[rate_limits]\nops_per_minute = 30").unwrap();
+        let changes = old.changes(&new);
+        assert_eq!(changes.reloaded, vec!["log_level", "rate_limits"]);
+        assert_eq!(changes.needs_restart, vec!["faults", "heartbeat_interval_ms"]);
+        assert!(old.changes(&old.clone()).is_empty());
+
+        let merged = old.with_settings_from(&new, &changes.reloaded).unwrap();
+        assert_eq!(merged.rate_limits.ops_per_minute, 30);
+        assert_eq!(merged.log_level.as_deref(), Some("warn"));
+        assert_eq!((merged.faults.send_input, merged.heartbeat_interval_ms), (0.1, 0));
+        assert!(ServerConfig::from_toml("log_level = \"loud\"").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+// Reloading the config file while the server runs.
+//
+// Restarting the server to loosen a rate limit or allow another directory
+// loses the Paint session. A background thread checks the config file's
+// modification time, and when it changes reloads the file and applies the
+// settings that can change at runtime (config::RELOADABLE_SETTINGS). The
+// client gets `notifications/paint/config_reloaded` listing what was
+// applied and what only takes effect after a restart. A file that no longer
+// parses is reported and the running settings are kept.
+
+use crate::config::ServerConfig;
+use crate::PaintServerState;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+// How often the config file's modification time is checked
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Watches `path` for as long as the process runs. `loaded` is the config
+/// as read from the file at startup, before command-line overrides, so
+/// only edits to the file count as changes.
+pub fn spawn(state: PaintServerState, path: PathBuf, loaded: ServerConfig) {
+    info!("Watching {} for configuration changes", path.display());
+    std::thread::spawn(move || {
+        let mut modified = modified_time(&path);
+        let mut loaded = loaded;
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let current = modified_time(&path);
+            if current == modified {
+                continue;
+            }
+            modified = current;
+            if let Some(notification) = reload(&state, &path, &mut loaded) {
+                crate::transport::send_notification("notifications/paint/config_reloaded", notification);
+            }
+        }
+    });
+}
+
+/// Reloads `path` and applies what changed since `loaded`. Returns the
+/// notification to send, or None if no setting changed.
+pub fn reload(state: &PaintServerState, path: &Path, loaded: &mut ServerConfig) -> Option<Value> {
+    let new = match ServerConfig::load(path) {
+        Ok(new) => new,
+        Err(e) => {
+            warn!("Keeping the running configuration: {}", e);
+            return Some(json!({ "path": path.display().to_string(), "error": e.to_string() }));
+        }
+    };
+    let changes = loaded.changes(&new);
+    if changes.is_empty() {
+        return None;
+    }
+    if let Err(e) = state.reload_config(&new, &changes.reloaded) {
+        warn!("Failed to apply the reloaded configuration: {}", e);
+        return Some(json!({ "path": path.display().to_string(), "error": e.to_string() }));
+    }
+    if !changes.needs_restart.is_empty() {
+        warn!("Changed settings that need a restart: {}", changes.needs_restart.join(", "));
+    }
+    *loaded = new;
+    Some(json!({
+        "path": path.display().to_string(),
+        "reloaded": changes.reloaded,
+        "requires_restart": changes.needs_restart,
+    }))
+}
+
+// None if the file is missing; a deleted file counts as a change
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_reload_applies_safe_settings() {
+        let path = std::env::temp_dir().join(format!("mspmcp-reload-{}.toml", std::process::id()));
+        let mut loaded = ServerConfig::default();
+        let state = PaintServerState::with_backend(loaded.clone(), Arc::new(MockBackend::default()));
+
+        std::fs::write(&path, "max_fetch_bytes = 1000\nheartbeat_interval_ms = 5000\n[rate_limits]\nops_per_minute = 1").unwrap();
+        let notification = reload(&state, &path, &mut loaded).unwrap();
+        assert_eq!(notification["reloaded"], json!(["max_fetch_bytes", "rate_limits"]));
+        assert_eq!(notification["requires_restart"], json!(["heartbeat_interval_ms"]));
+        let config = state.config().unwrap();
+        assert_eq!((config.max_fetch_bytes, config.heartbeat_interval_ms), (1000, 0));
+        state.rate_limiter.check("draw_line", true).unwrap();
+        assert!(state.rate_limiter.check("draw_line", true).is_err());
+
+        // Unchanged and unparseable files leave the running settings alone
+        assert_eq!(reload(&state, &path, &mut loaded), None);
+        std::fs::write(&path, "max_fetch_bytes = \"lots\"").unwrap();
+        assert!(reload(&state, &path, &mut loaded).unwrap()["error"].is_string());
+        assert_eq!(state.config().unwrap().max_fetch_bytes, 1000);
+        std::fs::remove_file(&path).ok();
+
+        // A level the loggers don't know is reported, not skipped
+        let new = ServerConfig { log_level: Some("loud".to_string()), max_fetch_bytes: 2000, ..ServerConfig::default() };
+        let settings = ["log_level".to_string(), "max_fetch_bytes".to_string()];
+        assert!(matches!(state.reload_config(&new, &settings), Err(crate::error::MspMcpError::ConfigError(_))));
+        assert_eq!(state.config().unwrap().max_fetch_bytes, 1000);
+    }
+}
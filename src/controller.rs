@@ -0,0 +1,179 @@
+// Paint automation as a library, without the JSON-RPC server.
+//
+// PaintController drives one Paint window through a PaintBackend, the same
+// backend the server's handlers use, so another Rust program can embed the
+// automation directly:
+//
+//     let paint = PaintController::connect()?;
+//     paint.set_color("#FF0000")?;
+//     paint.draw_line((10, 10), (200, 120))?;
+//     paint.save("C:\\drawings\\line.png")?;
+//
+// Positions are canvas pixels. The server's session features (coordinate
+// spaces, transforms, snapping, rate limits, the audit log) stay in the
+// server; PaintServerState::controller hands out a controller for the window
+// a session is connected to.
+
+use crate::backend::{PaintBackend, HWND};
+use crate::capture::CapturedImage;
+use crate::error::{MspMcpError, Result};
+use crate::protocol::{AddTextParams, MouseButton, SaveCanvasParams};
+use std::path::Path;
+use std::sync::Arc;
+
+pub struct PaintController {
+    backend: Arc<dyn PaintBackend>,
+    hwnd: HWND,
+    button: MouseButton, // Strokes draw with this button: Color 1 (left) or Color 2 (right)
+}
+
+impl PaintController {
+    /// Connects to a running Paint window, launching Paint if there is none.
+    #[cfg(feature = "windows-automation")]
+    pub fn connect() -> Result<Self> {
+        Self::connect_with(Arc::new(crate::backend::WindowsBackend))
+    }
+
+    /// Connects through `backend`, e.g. a simulate::SimulatedBackend.
+    pub fn connect_with(backend: Arc<dyn PaintBackend>) -> Result<Self> {
+        let hwnd = backend.find_or_launch_paint()?;
+        backend.activate(hwnd)?;
+        Ok(Self::attach(backend, hwnd))
+    }
+
+    /// A controller for a Paint window that is already known.
+    pub fn attach(backend: Arc<dyn PaintBackend>, hwnd: HWND) -> Self {
+        PaintController { backend, hwnd, button: MouseButton::Left }
+    }
+
+    pub fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
+    /// Draws later strokes with `button`; Paint draws right-button strokes in Color 2.
+    pub fn use_button(&mut self, button: MouseButton) {
+        self.button = button;
+    }
+
+    /// The document size in image pixels.
+    pub fn canvas_size(&self) -> Result<(u32, u32)> {
+        self.backend.canvas_dimensions(self.hwnd)
+    }
+
+    // --- Tool settings ---
+
+    pub fn select_tool(&self, tool: &str) -> Result<()> {
+        self.backend.select_tool(self.hwnd, tool)
+    }
+
+    /// Sets the color strokes draw in (Color 1, or Color 2 after `use_button(Right)`).
+    pub fn set_color(&self, color: &str) -> Result<()> {
+        let color = crate::palette::normalize_color(color)?;
+        match self.button {
+            MouseButton::Left => self.backend.set_color(self.hwnd, &color),
+            MouseButton::Right => self.backend.set_secondary_color(self.hwnd, &color),
+        }
+    }
+
+    /// Sets the stroke width in pixels.
+    pub fn set_thickness_px(&self, px: u32) -> Result<()> {
+        self.backend.set_thickness_px(self.hwnd, px)
+    }
+
+    /// Sets the shape fill: "none", "solid" or "outline".
+    pub fn set_fill(&self, fill_type: &str) -> Result<()> {
+        self.backend.set_fill(self.hwnd, fill_type)
+    }
+
+    // --- Drawing ---
+
+    pub fn draw_pixel(&self, x: i32, y: i32) -> Result<()> {
+        self.backend.draw_pixel(self.hwnd, x, y, self.button)
+    }
+
+    pub fn draw_line(&self, start: (i32, i32), end: (i32, i32)) -> Result<()> {
+        self.backend.draw_line(self.hwnd, start.0, start.1, end.0, end.1, self.button)
+    }
+
+    /// Draws a shape between two corners with Paint's shape tool: "rectangle",
+    /// "ellipse", "line", "arrow", "triangle", "pentagon" or "hexagon".
+    pub fn draw_shape(&self, shape_type: &str, start: (i32, i32), end: (i32, i32)) -> Result<()> {
+        self.backend.draw_shape(self.hwnd, shape_type, start.0, start.1, end.0, end.1, self.button)
+    }
+
+    /// Draws connected segments through `points` with the current tool.
+    pub fn draw_polyline(&self, points: &[(i32, i32)]) -> Result<()> {
+        if points.len() < 2 {
+            return Err(MspMcpError::InvalidParameters("A polyline needs at least 2 points".to_string()));
+        }
+        self.backend.draw_polyline(self.hwnd, points, self.button)
+    }
+
+    pub fn add_text(&self, x: i32, y: i32, text: &str) -> Result<()> {
+        let params = AddTextParams { x, y, text: text.to_string(), color: None, font_name: None, font_size: None, font_style: None };
+        self.backend.add_text(self.hwnd, &params)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.backend.clear_canvas(self.hwnd)
+    }
+
+    pub fn undo(&self) -> Result<()> {
+        self.backend.undo(self.hwnd)
+    }
+
+    // --- Capture and saving ---
+
+    /// The whole canvas at image resolution.
+    pub fn capture(&self) -> Result<CapturedImage> {
+        self.backend.capture_canvas(self.hwnd)
+    }
+
+    pub fn capture_region(&self, x: i32, y: i32, width: u32, height: u32) -> Result<CapturedImage> {
+        self.backend.capture_canvas_region(self.hwnd, x, y, width, height)
+    }
+
+    /// Saves the image to `path`, replacing an existing file, in the format
+    /// its extension names (png, jpeg/jpg or bmp).
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let format = match path.extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+            Some("png") => "png",
+            Some("jpg" | "jpeg") => "jpeg",
+            Some("bmp") => "bmp",
+            _ => return Err(MspMcpError::InvalidParameters(
+                format!("Can't tell the format of {}; use a .png, .jpg or .bmp file", path.display()))),
+        };
+        let params = SaveCanvasParams {
+            file_path: path.to_string_lossy().into_owned(), format: format.to_string(), overwrite: true, jpeg_quality: None, create_dirs: false,
+        };
+        self.backend.save_canvas(self.hwnd, &params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{MockBackend, MOCK_HWND};
+
+    #[test]
+    fn test_controller_drives_the_backend() {
+        let backend = Arc::new(MockBackend::default());
+        let mut paint = PaintController::connect_with(backend.clone()).unwrap();
+        assert_eq!(paint.hwnd(), MOCK_HWND);
+        paint.set_color("#ff0000").unwrap();
+        paint.draw_line((10, 10), (200, 120)).unwrap();
+        paint.use_button(MouseButton::Right);
+        paint.set_color("#00ff00").unwrap();
+        paint.draw_shape("ellipse", (5, 5), (50, 40)).unwrap();
+        assert_eq!(backend.take_calls(), vec![
+            "find_or_launch_paint", "activate", "set_color #FF0000", "draw_line 10 10 200 120 Left",
+            "set_secondary_color #00FF00", "draw_shape ellipse 5 5 50 40 Right",
+        ]);
+
+        assert!(paint.set_color("red").is_err());
+        assert!(paint.draw_polyline(&[(1, 1)]).is_err());
+        assert!(paint.save("drawing.tiff").is_err());
+        assert!(backend.take_calls().is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+// Positions and sizes in other coordinate spaces than canvas pixels.
+//
+// Agents lay things out far better in proportions ("a third of the way
+// across") than in pixels on a canvas whose size they don't know, and some
+// clients compute their own window or screen positions. The methods in
+// COORDINATE_METHODS take a `coordinate_space`: "canvas" (the default),
+// "normalized" (fractions 0.0 to 1.0 of the canvas width and height),
+// "client" (the Paint window's client area) or "screen". PaintServerState
+// resolves the coordinates to canvas pixels before the handler parses its
+// params, so handlers only ever see canvas pixels. The conversion itself is
+// geometry::CanvasFrame::to_canvas.
+//
+// After that, with `set_snap` on, the positions of SNAP_METHODS are rounded
+// to the nearest grid point, so sloppy coordinates still line up.
+
+use crate::error::{MspMcpError, Result};
+use crate::geometry::{Axis, CanvasFrame, Measure};
+use crate::protocol::CoordinateSpace;
+use serde_json::{json, Map, Value};
+
+// Methods whose params hold canvas positions or sizes
+pub const COORDINATE_METHODS: &[&str] = &[
+    "draw_pixel", "draw_line", "draw_shape", "draw_polyline", "draw_ellipse", "draw_gradient", "fill_pattern", "draw_glyph_text", "add_text",
+    "select_region", "paste", "stamp_image", "pick_color_at", "get_canvas_region", "export_region", "pen_move_to", "pen_line_to",
+];
+
+// Drawing primitives whose positions set_snap rounds to the grid
+pub const SNAP_METHODS: &[&str] = &["draw_pixel", "draw_line", "draw_shape", "draw_polyline", "pen_move_to", "pen_line_to"];
+
+const FIELDS: &[(&str, Axis, Measure)] = &[
+    ("x", Axis::Horizontal, Measure::Position),
+    ("start_x", Axis::Horizontal, Measure::Position),
+    ("end_x", Axis::Horizontal, Measure::Position),
+    ("width", Axis::Horizontal, Measure::Size),
+    ("y", Axis::Vertical, Measure::Position),
+    ("start_y", Axis::Vertical, Measure::Position),
+    ("end_y", Axis::Vertical, Measure::Position),
+    ("height", Axis::Vertical, Measure::Size),
+    ("radius_x", Axis::Horizontal, Measure::Size),
+    ("radius_y", Axis::Vertical, Measure::Size),
+];
+
+/// True if `name` is a position or size field that can be normalized.
+pub fn is_coordinate_field(name: &str) -> bool {
+    FIELDS.iter().any(|(field, ..)| *field == name)
+}
+
+/// The coordinate space a request asks for; canvas pixels unless it says otherwise.
+pub fn requested(params: Option<&Value>) -> Result<CoordinateSpace> {
+    match params.and_then(|p| p.get("coordinate_space")) {
+        None | Some(Value::Null) => Ok(CoordinateSpace::Canvas),
+        Some(space) => serde_json::from_value(space.clone()).map_err(|_| MspMcpError::InvalidParameters(format!(
+            "coordinate_space must be \"canvas\", \"normalized\", \"client\" or \"screen\", got {}", space))),
+    }
+}
+
+/// Replaces the coordinates in `params` (and in its `points` or
+/// `positions`), given in `space`, with canvas pixels.
+pub fn resolve(params: &mut Value, space: CoordinateSpace, frame: &CanvasFrame) -> Result<()> {
+    let Some(fields) = params.as_object_mut() else { return Ok(()) };
+    fields.remove("coordinate_space");
+    resolve_fields(fields, space, frame)?;
+    for list in ["points", "positions"] {
+        if let Some(Value::Array(points)) = fields.get_mut(list) {
+            for point in points.iter_mut().filter_map(Value::as_object_mut) {
+                resolve_fields(point, space, frame)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn resolve_fields(fields: &mut Map<String, Value>, space: CoordinateSpace, frame: &CanvasFrame) -> Result<()> {
+    for &(name, axis, measure) in FIELDS {
+        let Some(value) = fields.get_mut(name) else { continue };
+        let number = value.as_f64().ok_or_else(|| MspMcpError::InvalidParameters(format!("{} must be a number, got {}", name, value)))?;
+        if space == CoordinateSpace::Normalized && !(0.0..=1.0).contains(&number) {
+            return Err(MspMcpError::InvalidParameters(
+                format!("{} must be between 0 and 1 with normalized coordinates, got {}", name, value)));
+        }
+        *value = json!(frame.to_canvas(number, space, axis, measure));
+    }
+    Ok(())
+}
+
+/// Rounds the canvas positions in `params` (and in its `points`) to the
+/// nearest multiple of `grid` pixels. Sizes are left alone.
+pub fn snap(params: &mut Value, grid: u32) {
+    let Some(fields) = params.as_object_mut() else { return };
+    snap_fields(fields, grid);
+    if let Some(Value::Array(points)) = fields.get_mut("points") {
+        for point in points.iter_mut().filter_map(Value::as_object_mut) {
+            snap_fields(point, grid);
+        }
+    }
+}
+
+fn snap_fields(fields: &mut Map<String, Value>, grid: u32) {
+    for &(name, _, measure) in FIELDS {
+        // Anything but a whole number is left for the handler to reject
+        let Some(value) = fields.get_mut(name).filter(|_| measure == Measure::Position) else { continue };
+        if let Some(position) = value.as_i64() {
+            *value = json!(crate::geometry::snap_to_grid(position.clamp(i32::MIN as i64, i32::MAX as i64) as i32, grid));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requested_space() {
+        assert_eq!(requested(None).unwrap(), CoordinateSpace::Canvas);
+        assert_eq!(requested(Some(&json!({"x": 4}))).unwrap(), CoordinateSpace::Canvas);
+        assert_eq!(requested(Some(&json!({"coordinate_space": "pixels"}))).unwrap(), CoordinateSpace::Canvas);
+        assert_eq!(requested(Some(&json!({"coordinate_space": "normalized"}))).unwrap(), CoordinateSpace::Normalized);
+        assert_eq!(requested(Some(&json!({"coordinate_space": "screen"}))).unwrap(), CoordinateSpace::Screen);
+        assert!(requested(Some(&json!({"coordinate_space": "percent"}))).is_err());
+    }
+
+    #[test]
+    fn test_fractions_become_pixels() {
+        let mut line = json!({"start_x": 0.0, "start_y": 0.5, "end_x": 1.0, "end_y": 1, "color": "#000000", "coordinate_space": "normalized"});
+        resolve(&mut line, CoordinateSpace::Normalized, &CanvasFrame::of_size(801, 601)).unwrap();
+        assert_eq!(line, json!({"start_x": 0, "start_y": 300, "end_x": 800, "end_y": 600, "color": "#000000"}));
+
+        let mut region = json!({"x": 0.25, "y": 0.0, "width": 0.5, "height": 1.0});
+        resolve(&mut region, CoordinateSpace::Normalized, &CanvasFrame::of_size(800, 600)).unwrap();
+        assert_eq!(region, json!({"x": 200, "y": 0, "width": 400, "height": 600}));
+
+        let mut polyline = json!({"points": [{"x": 0.5, "y": 0.5}, {"x": 1.0, "y": 0.0}]});
+        resolve(&mut polyline, CoordinateSpace::Normalized, &CanvasFrame::of_size(101, 51)).unwrap();
+        assert_eq!(polyline["points"], json!([{"x": 50, "y": 25}, {"x": 100, "y": 0}]));
+
+        for bad in [json!({"x": 1.5, "y": 0}), json!({"x": -0.1, "y": 0}), json!({"x": "left", "y": 0})] {
+            let mut params = bad;
+            assert!(resolve(&mut params, CoordinateSpace::Normalized, &CanvasFrame::of_size(800, 600)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_snapping_to_the_grid() {
+        let mut polyline = json!({"points": [{"x": 12, "y": 37}, {"x": 98, "y": -3}], "color": "#000000"});
+        snap(&mut polyline, 10);
+        assert_eq!(polyline, json!({"points": [{"x": 10, "y": 40}, {"x": 100, "y": 0}], "color": "#000000"}));
+        let mut region = json!({"x": 12, "y": 3, "width": 17, "height": "tall"});
+        snap(&mut region, 5);
+        assert_eq!(region, json!({"x": 10, "y": 5, "width": 17, "height": "tall"}));
+    }
+
+    #[test]
+    fn test_screen_positions_become_pixels() {
+        let frame = CanvasFrame {
+            width: 800, height: 600, zoom: 1.0, viewport_origin: (10, 130), viewport_size: (530, 355), scroll: (0, 0), client_origin: (5, 10),
+        };
+        let mut pixel = json!({"x": 60, "y": 170, "coordinate_space": "screen"});
+        resolve(&mut pixel, CoordinateSpace::Screen, &frame).unwrap();
+        assert_eq!(pixel, json!({"x": 50, "y": 40}));
+        let mut pixel = json!({"x": 55, "y": 160});
+        resolve(&mut pixel, CoordinateSpace::Client, &frame).unwrap();
+        assert_eq!(pixel, json!({"x": 50, "y": 40}));
+    }
+}
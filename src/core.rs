@@ -1,13 +1,12 @@
 // Placeholder for core server logic (command handlers) 
 
 use crate::error::{Result, MspMcpError};
-use crate::protocol::{ConnectParams, ConnectResponse, success_response, DrawPixelParams, DrawLineParams, DrawShapeParams, DrawPolylineParams, SelectToolParams, SetColorParams, SetThicknessParams, SetBrushSizeParams, SetFillParams, AddTextParams, CreateCanvasParams};
-use crate::windows;
-use crate::windows::{get_paint_hwnd, get_initial_canvas_dimensions, activate_paint_window, get_canvas_dimensions, draw_pixel_at, draw_line_at, draw_shape, draw_polyline, clear_canvas, select_region, copy_selection, paste_at, add_text, create_canvas};
+use crate::protocol::{ConnectParams, InitializeParams, ConnectResponse, EnsureWindowOnMonitorParams, SetDedicatedDesktopParams, SetLogLevelParams, SetServerLogLevelParams, ToolsCallParams, GetCanvasRegionParams, StartInputRecordingParams, ReplayInputParams, RunBenchmarkParams, CalibrateParams, RunMacroParams, RegisterMacroParams, CheckpointParams, DrawAnimationParams, FrameReset, MAX_ANIMATION_LOOPS, MAX_FRAME_DELAY_MS, GetAuditLogParams, ResourceUriParams, success_response, DrawPixelParams, DrawLineParams, DrawShapeParams, DrawPolylineParams, DrawGradientParams, FillPatternParams, SelectToolParams, SetColorParams, SetSecondaryColorParams, PickColorAtParams, DefinePaletteParams, UsePaletteColorParams, AddCustomColorParams, UseCustomColorParams, SetSnapParams, PushTransformParams, PenPointParams, PenStrokeParams, MouseButton, SetThicknessParams, SetBrushSizeParams, SetFillParams, SetToolStateParams, AddTextParams, NewDocumentParams, CloseParams, CreateCanvasParams, DuplicateCanvasParams, SetImagePropertiesParams, ImageUnits, MAX_IMAGE_SIDE_PX, SetBackgroundParams, SaveCanvasParams, FetchImageParams, StampImageParams, DrawGlyphTextParams, DrawEllipseParams, MAX_STAMP_SIDE_PX, MAX_STAMP_POSITIONS, RotateSelectionParams, FlipSelectionParams, InvertColorsParams, LayerIndexParams, SetLayerVisibilityParams, Clipped, Smoothing};
+use crate::presets::StrokePreset;
 use crate::PaintServerState; // Import the state struct from lib.rs
-use log::{info, warn, error, debug};
+use crate::backend::HWND;
+use tracing::{info, warn, error, debug};
 use serde_json::{json, Value};
-use std::time;
 use tokio;
 
 // Handler for the 'connect' method
@@ -23,6 +22,19 @@ pub async fn handle_connect(
         .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
 
     info!("Client connected: id={}, name={}", connect_params.client_id, connect_params.client_name);
+    state.set_client_id(&connect_params.client_id)?;
+
+    // Agree on a protocol version before anything else; newer options need 1.1.
+    // Without a version, keep whatever initialize negotiated
+    let protocol_version = match connect_params.protocol_version.as_deref() {
+        Some(requested) => state.negotiate_protocol_version(Some(requested))?.to_string(),
+        None => state.protocol_version()?,
+    };
+    let v1_1 = crate::protocol::protocol_at_least(&protocol_version, "1.1");
+    if !v1_1 && (connect_params.monitor.is_some() || connect_params.dedicated_desktop.is_some()) {
+        return Err(MspMcpError::InvalidParameters(format!(
+            "'monitor' and 'dedicated_desktop' require protocol 1.1 (negotiated {})", protocol_version)));
+    }
 
     // Get HWND from state (should have been set during initialize)
     let hwnd = {
@@ -35,18 +47,41 @@ pub async fn handle_connect(
         }
     };
 
+    // Move Paint onto the requested monitor before any drawing happens
+    if let Some(monitor) = connect_params.monitor {
+        state.backend.ensure_window_on_monitor(hwnd, Some(monitor))?;
+    }
+
+    // Optionally move Paint to its own virtual desktop
+    if connect_params.dedicated_desktop.unwrap_or(false) {
+        move_to_automation_desktop(hwnd)?;
+        let mut dedicated = state.dedicated_desktop.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock dedicated desktop state".to_string()))?;
+        *dedicated = true;
+    }
+
     // Get initial canvas dimensions (still needed for connect response)
-    let (width, height) = get_initial_canvas_dimensions(hwnd)?;
+    let (width, height) = state.backend.initial_canvas_dimensions(hwnd)?;
 
     // Create and return the response
+    let mut result = json!({
+        "paint_version": "windows11", // Assuming Win11 for now
+        "canvas_width": width,
+        "canvas_height": height
+    });
+    if v1_1 {
+        let document = state.document.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock document state".to_string()))?.clone();
+        result["protocol_version"] = json!(protocol_version);
+        result["hwnd"] = json!(hwnd);
+        result["pid"] = json!(state.backend.window_pid(hwnd));
+        result["document"] = json!(document);
+    }
+
     Ok(json!({
         "jsonrpc": "2.0",
         "id": 1, // Should be extracted from the request
-        "result": {
-            "paint_version": "windows11", // Assuming Win11 for now
-            "canvas_width": width,
-            "canvas_height": height
-        }
+        "result": result
     }))
 }
 
@@ -74,7 +109,7 @@ pub async fn handle_activate_window(
     };
 
     // Call the windows module to activate the window
-    activate_paint_window(hwnd)?;
+    state.backend.activate(hwnd)?;
 
     // Return success response
     Ok(success_response())
@@ -103,17 +138,14 @@ pub async fn handle_get_canvas_dimensions(
         }
     };
 
-    // Call the windows module to get canvas dimensions
-    let (width, height) = get_canvas_dimensions(hwnd)?;
+    // Document size in image pixels, plus how it is shown
+    let view = state.backend.canvas_view(hwnd)?;
 
     // Return dimensions in response
     Ok(json!({
         "jsonrpc": "2.0",
         "id": 1, // Should be extracted from the request
-        "result": {
-            "width": width,
-            "height": height
-        }
+        "result": view
     }))
 }
 
@@ -124,13 +156,9 @@ pub async fn handle_disconnect(
 ) -> Result<Value> {
     info!("Handling disconnect request...");
 
-    // Optionally clear the HWND state to indicate we're no longer connected
-    {
-        let mut hwnd_state = state.paint_hwnd.lock().map_err(|_| 
-            MspMcpError::General("Failed to lock HWND state".to_string()))?;
-        *hwnd_state = None;
-        info!("Cleared Paint HWND state on disconnect");
-    }
+    // Clear the tracked window state to indicate we're no longer connected
+    state.clear_paint_window()?;
+    info!("Cleared Paint HWND state on disconnect");
 
     // Note: we don't actually close Paint, just clear our reference to it
     // If we wanted to close Paint, we could use WM_CLOSE or TerminateProcess
@@ -139,36 +167,89 @@ pub async fn handle_disconnect(
     Ok(success_response())
 }
 
+// Handler for the 'shutdown' method. Stops accepting operations, releases
+// any input we might still be holding and applies the configured document
+// action. The process itself ends on 'exit' (or end of input).
+pub async fn handle_shutdown(
+    state: PaintServerState,
+    _params: Option<Value>, // No parameters needed for this command
+) -> Result<Value> {
+    info!("Handling shutdown request...");
+
+    {
+        let mut shutdown = state.shutdown_requested.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock shutdown state".to_string()))?;
+        *shutdown = true;
+    }
+
+    // Never leave a mouse button or modifier stuck down
+    if let Err(e) = state.backend.release_held_input() {
+        warn!("Failed to release held input during shutdown: {}", e);
+    }
+
+    let hwnd = *state.paint_hwnd.lock().map_err(|_| 
+        MspMcpError::General("Failed to lock HWND state".to_string()))?;
+    let pid = *state.paint_pid.lock().map_err(|_| 
+        MspMcpError::General("Failed to lock PID state".to_string()))?;
+    let document = state.document.lock().map_err(|_| 
+        MspMcpError::General("Failed to lock document state".to_string()))?.clone();
+
+    let action = state.config()?.shutdown_document;
+    match (action, hwnd, pid) {
+        (crate::config::ShutdownDocumentAction::Save, Some(hwnd), _) => {
+            // Untitled documents would open the Save As dialog, so leave those alone
+            if document.has_unsaved_changes && !document.is_untitled() {
+                info!("Saving {:?} before shutdown", document.document_name);
+                state.backend.save(hwnd)?;
+            } else {
+                info!("Nothing to save on shutdown (name={:?}, unsaved={})",
+                    document.document_name, document.has_unsaved_changes);
+            }
+        }
+        (crate::config::ShutdownDocumentAction::Discard, _, Some(pid)) => {
+            info!("Closing Paint (PID={}) and discarding changes on shutdown", pid);
+            state.backend.terminate(pid)?;
+            state.clear_paint_window()?;
+        }
+        _ => {}
+    }
+
+    Ok(success_response())
+}
+
 // Handler for the 'get_version' method
 pub async fn handle_get_version(
-    _state: PaintServerState, // No state needed for this command
+    state: PaintServerState,
     _params: Option<Value>,   // No parameters needed for this command
 ) -> Result<Value> {
     info!("Handling get_version request...");
 
+    // The Paint executable's own version, once connected
+    let hwnd = *state.paint_hwnd.lock().map_err(|_|
+        MspMcpError::General("Failed to lock HWND state".to_string()))?;
+    let paint_app_version = hwnd.and_then(|hwnd| state.backend.paint_version(hwnd));
+
     // Return version information
     Ok(json!({
         "jsonrpc": "2.0",
         "id": 1, // Should be extracted from the request
         "result": {
-            "protocol_version": "1.1",
+            "protocol_version": state.protocol_version()?,
+            "supported_protocol_versions": crate::protocol::SUPPORTED_PROTOCOL_VERSIONS,
             "server_version": env!("CARGO_PKG_VERSION"),
-            "paint_version": "windows11"
+            "paint_version": "windows11",
+            "paint_app_version": paint_app_version,
+            "ui_layout": crate::layout::active().name
         }
     }))
 }
 
-// Handler for the 'draw_pixel' method
-pub async fn handle_draw_pixel(
+// Handler for the 'get_window_info' method
+pub async fn handle_get_window_info(
     state: PaintServerState,
-    params: Option<Value>,
+    _params: Option<Value>, // No parameters needed for this command
 ) -> Result<Value> {
-    info!("Handling draw_pixel request...");
-
-    // Deserialize parameters
-    let draw_params: DrawPixelParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_pixel".to_string()))
-        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    info!("Handling get_window_info request...");
 
     // Get the Paint window handle from state
     let hwnd = {
@@ -181,29 +262,25 @@ pub async fn handle_draw_pixel(
         }
     };
 
-    // If a color is specified, set it first
-    if let Some(color) = &draw_params.color {
-        windows::set_color(hwnd, color)?;
-    }
-
-    // Draw the pixel at the specified coordinates
-    draw_pixel_at(hwnd, draw_params.x, draw_params.y)?;
+    // Collect window details without activating the window
+    let window_info = state.backend.window_info(hwnd)?;
 
-    // Return success response
-    Ok(success_response())
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": window_info
+    }))
 }
 
-// Handler for the 'draw_line' method
-pub async fn handle_draw_line(
+// Methods that need Paint's Layers panel
+const LAYER_METHODS: &[&str] = &["add_layer", "select_layer", "set_layer_visibility", "merge_layers", "delete_layer"];
+
+// Handler for the 'get_supported_tools' method
+pub async fn handle_get_supported_tools(
     state: PaintServerState,
-    params: Option<Value>,
+    _params: Option<Value>, // No parameters needed for this command
 ) -> Result<Value> {
-    info!("Handling draw_line request...");
-
-    // Deserialize parameters
-    let draw_params: DrawLineParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_line".to_string()))
-        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    info!("Handling get_supported_tools request...");
 
     // Get the Paint window handle from state
     let hwnd = {
@@ -216,38 +293,44 @@ pub async fn handle_draw_line(
         }
     };
 
-    // If a color is specified, set it first
-    if let Some(color) = &draw_params.color {
-        windows::set_color(hwnd, color)?;
-    }
+    let capabilities = state.backend.capabilities(hwnd)?;
 
-    // If thickness is specified, set it
-    if let Some(thickness) = draw_params.thickness {
-        windows::set_thickness(hwnd, thickness)?;
+    // Methods that would fail on this build, so clients can leave them out
+    let mut unavailable: Vec<&str> = Vec::new();
+    if !capabilities.layers {
+        unavailable.extend(LAYER_METHODS);
+    }
+    if capabilities.shapes.is_empty() {
+        unavailable.push("draw_shape");
     }
 
-    // Draw the line at the specified coordinates
-    draw_line_at(
-        hwnd, 
-        draw_params.start_x, draw_params.start_y,
-        draw_params.end_x, draw_params.end_y
-    )?;
-
-    // Return success response
-    Ok(success_response())
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "paint_app_version": state.backend.paint_version(hwnd),
+            "tools": capabilities.tools,
+            "brush_types": capabilities.brush_types,
+            "shapes": capabilities.shapes,
+            "layers": capabilities.layers,
+            "background_removal": capabilities.background_removal,
+            "unavailable_methods": unavailable
+        }
+    }))
 }
 
-// Handler for the 'select_tool' method
-pub async fn handle_select_tool(
+// Handler for the 'ensure_window_on_monitor' method
+pub async fn handle_ensure_window_on_monitor(
     state: PaintServerState,
     params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling select_tool request...");
+    info!("Handling ensure_window_on_monitor request...");
 
-    // Deserialize parameters
-    let tool_params: SelectToolParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for select_tool".to_string()))
-        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    // Parameters are optional; without them the window stays on its current monitor
+    let monitor_params: EnsureWindowOnMonitorParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => EnsureWindowOnMonitorParams { monitor: None },
+    };
 
     // Get the Paint window handle from state
     let hwnd = {
@@ -260,90 +343,197 @@ pub async fn handle_select_tool(
         }
     };
 
-    // Select the tool
-    windows::select_tool(hwnd, &tool_params.tool)?;
+    let monitor = state.backend.ensure_window_on_monitor(hwnd, monitor_params.monitor)?;
+    let window_info = state.backend.window_info(hwnd)?;
 
-    // If a shape type is specified, handle that as well
-    if let Some(shape_type) = tool_params.shape_type {
-        // TODO: Implement shape type selection
-        info!("Would select shape type: {}", shape_type);
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "monitor": monitor,
+            "window_rect": window_info.window_rect
+        }
+    }))
+}
+
+// Handler for the MCP 'logging/setLevel' method
+pub async fn handle_set_log_level(
+    _state: PaintServerState, // Level is process-wide, not per connection
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling logging/setLevel request...");
+
+    // Deserialize parameters
+    let level_params: SetLogLevelParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for logging/setLevel".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    let level = crate::client_log::parse_mcp_level(&level_params.level).ok_or_else(|| {
+        MspMcpError::InvalidParameters(format!("Unknown log level '{}'. Expected one of: {}",
+            level_params.level, crate::client_log::MCP_LOG_LEVELS.join(", ")))
+    })?;
+
+    crate::client_log::set_client_log_level(level);
+    info!("Client log level set to '{}'", crate::client_log::client_log_level());
+
+    // The terminal and file logs follow the client's choice too
+    if let Some(filter) = crate::log_level::parse_level(&level_params.level) {
+        crate::log_level::set_level(None, filter)?;
     }
 
-    // Return success response
     Ok(success_response())
 }
 
-// Handler for the 'set_color' method
-pub async fn handle_set_color(
-    state: PaintServerState,
+// Handler for the 'set_log_level' method
+pub async fn handle_set_server_log_level(
+    _state: PaintServerState, // Levels are process-wide, not per connection
     params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling set_color request...");
+    info!("Handling set_log_level request...");
 
     // Deserialize parameters
-    let color_params: SetColorParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_color".to_string()))
+    let level_params: SetServerLogLevelParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_log_level".to_string()))
         .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
 
-    // Get the Paint window handle from state
-    let hwnd = {
-        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
-            MspMcpError::General("Failed to lock HWND state".to_string()))?;
-        
-        match *hwnd_state {
-            Some(hwnd) => hwnd,
-            None => return Err(MspMcpError::WindowNotFound),
+    let level = crate::log_level::parse_level(&level_params.level).ok_or_else(|| {
+        MspMcpError::InvalidParameters(format!("Unknown log level '{}'. Expected one of: off, trace, debug, info, warn, error",
+            level_params.level))
+    })?;
+
+    crate::log_level::set_level(level_params.logger.as_deref(), level)?;
+    info!("Server log level set to '{}' for {}", level_params.level, level_params.logger.as_deref().unwrap_or("all loggers"));
+
+    let loggers: serde_json::Map<String, Value> = crate::log_level::levels()
+        .into_iter()
+        .map(|(name, level)| (name.to_string(), json!(level)))
+        .collect();
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "loggers": loggers
         }
-    };
+    }))
+}
 
-    // Set the color
-    windows::set_color(hwnd, &color_params.color)?;
+// Handler for the MCP 'ping' method
+pub async fn handle_ping(
+    state: PaintServerState,
+    _params: Option<Value>,   // No parameters needed for this command
+) -> Result<Value> {
+    // Uptime and queue depth let clients tell a busy server from a wedged one
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": state.activity.status()
+    }))
+}
 
-    // Return success response
-    Ok(success_response())
+// Handler for the MCP 'tools/list' method
+pub async fn handle_tools_list(
+    _state: PaintServerState, // No state needed for this command
+    _params: Option<Value>,   // Pagination cursor is ignored; the list is short
+) -> Result<Value> {
+    info!("Handling tools/list request...");
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "tools": crate::tools::tool_definitions()
+        }
+    }))
 }
 
-// Handler for the 'set_thickness' method
-pub async fn handle_set_thickness(
+// Handler for the 'get_api_schema' method: the OpenRPC description of the Paint methods
+pub async fn handle_get_api_schema(
+    _state: PaintServerState, // No state needed for this command
+    _params: Option<Value>,   // No parameters needed
+) -> Result<Value> {
+    info!("Handling get_api_schema request...");
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": crate::tools::api_schema()
+    }))
+}
+
+// Handler for the MCP 'tools/call' method
+pub async fn handle_tools_call(
     state: PaintServerState,
     params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling set_thickness request...");
+    info!("Handling tools/call request...");
 
     // Deserialize parameters
-    let thickness_params: SetThicknessParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_thickness".to_string()))
+    let call_params: ToolsCallParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for tools/call".to_string()))
         .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
 
-    // Get the Paint window handle from state
-    let hwnd = {
-        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
-            MspMcpError::General("Failed to lock HWND state".to_string()))?;
-        
-        match *hwnd_state {
-            Some(hwnd) => hwnd,
-            None => return Err(MspMcpError::WindowNotFound),
+    if !crate::protocol::PAINT_METHODS.contains(&call_params.name.as_str()) && crate::plugins::get(&call_params.name).is_none() {
+        return Err(MspMcpError::InvalidParameters(format!("Unknown tool '{}'", call_params.name)));
+    }
+
+    // Run the method through the normal request path (boxed, since that path
+    // is what routed us here)
+    let call: futures::future::BoxFuture<'_, Result<Value>> =
+        Box::pin(state.handle_request(&call_params.name, call_params.arguments));
+
+    // Tool failures are reported in the result so the model can see them
+    let (payload, is_error) = match call.await {
+        Ok(response) => (response.get("result").cloned().unwrap_or(response), false),
+        Err(e) => {
+            warn!("Tool '{}' failed: {}", call_params.name, e);
+            (json!({ "code": e.code(), "message": e.to_string() }), true)
         }
     };
 
-    // Set the thickness
-    windows::set_thickness(hwnd, thickness_params.level)?;
+    // Methods that already return MCP content (images) pass it through as is
+    let content = match payload.get("content") {
+        Some(content) if content.is_array() => content.clone(),
+        _ => json!([crate::protocol::text_content(&payload.to_string())]),
+    };
 
-    // Return success response
-    Ok(success_response())
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "content": content,
+            "isError": is_error
+        }
+    }))
 }
 
-// Handler for the 'set_brush_size' method
-pub async fn handle_set_brush_size(
+// Handler for the MCP 'resources/list' method
+pub async fn handle_resources_list(
+    _state: PaintServerState, // No state needed for this command
+    _params: Option<Value>,   // Pagination cursor is ignored; the list is short
+) -> Result<Value> {
+    info!("Handling resources/list request...");
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "resources": crate::resources::resource_list()
+        }
+    }))
+}
+
+// Handler for the MCP 'resources/read' method
+pub async fn handle_resources_read(
     state: PaintServerState,
     params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling set_brush_size request...");
+    info!("Handling resources/read request...");
 
     // Deserialize parameters
-    let brush_params: SetBrushSizeParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_brush_size".to_string()))
+    let read_params: ResourceUriParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for resources/read".to_string()))
         .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    crate::resources::check_known_uri(&read_params.uri)?;
 
     // Get the Paint window handle from state
     let hwnd = {
@@ -356,104 +546,101 @@ pub async fn handle_set_brush_size(
         }
     };
 
-    // Set the brush size
-    windows::set_brush_size(hwnd, brush_params.size, brush_params.tool.as_deref())?;
+    // paint://canvas is the only resource so far
+    let image = state.backend.capture_canvas(hwnd)?;
+    check_capture_size(&state, &image)?;
+    let png = crate::capture::encode_png(&image)?;
+    let blob = crate::protocol::image_content(&png, crate::capture::PNG_MIME_TYPE)["data"].clone();
 
-    // Return success response
-    Ok(success_response())
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "contents": [{
+                "uri": read_params.uri,
+                "mimeType": crate::capture::PNG_MIME_TYPE,
+                "blob": blob
+            }]
+        }
+    }))
 }
 
-// Handler for the 'set_fill' method
-pub async fn handle_set_fill(
+// Handler for the MCP 'resources/subscribe' method
+pub async fn handle_resources_subscribe(
     state: PaintServerState,
     params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling set_fill request...");
+    info!("Handling resources/subscribe request...");
 
     // Deserialize parameters
-    let fill_params: SetFillParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_fill".to_string()))
+    let subscribe_params: ResourceUriParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for resources/subscribe".to_string()))
         .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    crate::resources::check_known_uri(&subscribe_params.uri)?;
 
-    // Get the Paint window handle from state
-    let hwnd = {
-        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
-            MspMcpError::General("Failed to lock HWND state".to_string()))?;
-        
-        match *hwnd_state {
-            Some(hwnd) => hwnd,
-            None => return Err(MspMcpError::WindowNotFound),
-        }
-    };
-
-    // Set the fill type
-    windows::set_fill(hwnd, &fill_params.fill_type)?;
+    state.subscriptions.lock().map_err(|_| 
+        MspMcpError::General("Failed to lock subscription state".to_string()))?
+        .insert(subscribe_params.uri.clone());
+    info!("Client subscribed to {}", subscribe_params.uri);
 
-    // Return success response
     Ok(success_response())
 }
 
-// Handler for the 'draw_shape' method
-pub async fn handle_draw_shape(
+// Handler for the MCP 'resources/unsubscribe' method
+pub async fn handle_resources_unsubscribe(
     state: PaintServerState,
     params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling draw_shape request...");
+    info!("Handling resources/unsubscribe request...");
 
     // Deserialize parameters
-    let shape_params: DrawShapeParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_shape".to_string()))
+    let unsubscribe_params: ResourceUriParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for resources/unsubscribe".to_string()))
         .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
 
-    // Get the Paint window handle from state
-    let hwnd = {
-        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
-            MspMcpError::General("Failed to lock HWND state".to_string()))?;
-        
-        match *hwnd_state {
-            Some(hwnd) => hwnd,
-            None => return Err(MspMcpError::WindowNotFound),
-        }
-    };
+    state.subscriptions.lock().map_err(|_| 
+        MspMcpError::General("Failed to lock subscription state".to_string()))?
+        .remove(&unsubscribe_params.uri);
+    info!("Client unsubscribed from {}", unsubscribe_params.uri);
 
-    // If a color is specified, set it first
-    if let Some(color) = &shape_params.color {
-        windows::set_color(hwnd, color)?;
-    }
-
-    // If a thickness is specified, set it
-    if let Some(thickness) = shape_params.thickness {
-        windows::set_thickness(hwnd, thickness)?;
-    }
+    Ok(success_response())
+}
 
-    // If a fill type is specified, set it
-    if let Some(fill_type) = &shape_params.fill_type {
-        windows::set_fill(hwnd, fill_type)?;
+// Refuses captures over max_capture_megapixels so clients ask for a region instead
+fn check_capture_size(state: &PaintServerState, image: &crate::capture::CapturedImage) -> Result<()> {
+    let limit = state.config()?.max_capture_pixels();
+    let pixels = image.width as u64 * image.height as u64;
+    if pixels > limit {
+        return Err(MspMcpError::PayloadTooLarge {
+            message: format!("{}x{} capture has {} pixels", image.width, image.height, pixels),
+            limit,
+            unit: "pixels",
+        });
     }
+    Ok(())
+}
 
-    // Draw the shape
-    draw_shape(
-        hwnd,
-        &shape_params.shape_type,
-        shape_params.start_x, shape_params.start_y,
-        shape_params.end_x, shape_params.end_y
-    )?;
-
-    // Return success response
-    Ok(success_response())
+// Builds a result holding a captured image as MCP content
+fn image_result(image: &crate::capture::CapturedImage, description: &str) -> Result<Value> {
+    let png = crate::capture::encode_png(image)?;
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "content": [
+                crate::protocol::image_content(&png, crate::capture::PNG_MIME_TYPE),
+                crate::protocol::text_content(&format!("{} ({}x{} PNG)", description, image.width, image.height))
+            ]
+        }
+    }))
 }
 
-// Handler for the 'draw_polyline' method
-pub async fn handle_draw_polyline(
+// Handler for the 'export_canvas' method
+pub async fn handle_export_canvas(
     state: PaintServerState,
-    params: Option<Value>,
+    _params: Option<Value>, // No parameters needed
 ) -> Result<Value> {
-    info!("Handling draw_polyline request...");
-
-    // Deserialize parameters
-    let polyline_params: DrawPolylineParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_polyline".to_string()))
-        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    info!("Handling export_canvas request...");
 
     // Get the Paint window handle from state
     let hwnd = {
@@ -466,43 +653,17 @@ pub async fn handle_draw_polyline(
         }
     };
 
-    // If a tool is specified, select it first (pencil or brush)
-    if let Some(tool) = &polyline_params.tool {
-        windows::select_tool(hwnd, tool)?;
-    } else {
-        // Default to pencil if no tool specified
-        windows::select_tool(hwnd, "pencil")?;
-    }
-
-    // If a color is specified, set it
-    if let Some(color) = &polyline_params.color {
-        windows::set_color(hwnd, color)?;
-    }
-
-    // If a thickness is specified, set it
-    if let Some(thickness) = polyline_params.thickness {
-        windows::set_thickness(hwnd, thickness)?;
-    }
-
-    // Convert Point structs to (i32, i32) tuples for the Windows API
-    let point_tuples: Vec<(i32, i32)> = polyline_params.points
-        .iter()
-        .map(|point| (point.x, point.y))
-        .collect();
-
-    // Draw the polyline
-    draw_polyline(hwnd, &point_tuples)?;
-
-    // Return success response
-    Ok(success_response())
+    let image = state.backend.capture_canvas(hwnd)?;
+    check_capture_size(&state, &image)?;
+    image_result(&image, "Paint canvas")
 }
 
-// Handler for the 'clear_canvas' method
-pub async fn handle_clear_canvas(
+// Handler for the 'capture_window' method
+pub async fn handle_capture_window(
     state: PaintServerState,
     _params: Option<Value>, // No parameters needed
 ) -> Result<Value> {
-    info!("Handling clear_canvas request...");
+    info!("Handling capture_window request...");
 
     // Get the Paint window handle from state
     let hwnd = {
@@ -515,23 +676,21 @@ pub async fn handle_clear_canvas(
         }
     };
 
-    // Clear the canvas
-    clear_canvas(hwnd)?;
-
-    // Return success response
-    Ok(success_response())
+    let image = state.backend.capture_window(hwnd)?;
+    check_capture_size(&state, &image)?;
+    image_result(&image, "Paint window")
 }
 
-// Handler for the 'select_region' method
-pub async fn handle_select_region(
+// Handler for the 'get_canvas_region' method
+pub async fn handle_get_canvas_region(
     state: PaintServerState,
     params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling select_region request...");
+    info!("Handling get_canvas_region request...");
 
-    // Deserialize parameters - reusing DrawLineParams since it has the same structure
-    let select_params: DrawLineParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for select_region".to_string()))
+    // Deserialize parameters
+    let region_params: GetCanvasRegionParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for get_canvas_region".to_string()))
         .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
 
     // Get the Paint window handle from state
@@ -545,23 +704,28 @@ pub async fn handle_select_region(
         }
     };
 
-    // Select the region
-    select_region(
-        hwnd,
-        select_params.start_x, select_params.start_y,
-        select_params.end_x, select_params.end_y
-    )?;
-
-    // Return success response
-    Ok(success_response())
+    let image = state.backend.capture_canvas_region(
+        hwnd, region_params.x, region_params.y, region_params.width, region_params.height)?;
+    check_capture_size(&state, &image)?;
+    image_result(&image, &format!("Canvas region at ({}, {})", region_params.x, region_params.y))
 }
 
-// Handler for the 'copy_selection' method
-pub async fn handle_copy_selection(
+// Handler for the 'export_region' method: selects the region, copies it and
+// reads the copy back from the clipboard, so the pixels are exact at any zoom
+pub async fn handle_export_region(
     state: PaintServerState,
-    _params: Option<Value>, // No parameters needed
+    params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling copy_selection request...");
+    info!("Handling export_region request...");
+
+    // Deserialize parameters (the same rectangle as get_canvas_region)
+    let region_params: GetCanvasRegionParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for export_region".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    if region_params.width == 0 || region_params.height == 0 {
+        return Err(MspMcpError::InvalidParameters(format!(
+            "Cannot export an empty {}x{} region", region_params.width, region_params.height)));
+    }
 
     // Get the Paint window handle from state
     let hwnd = {
@@ -574,159 +738,4363 @@ pub async fn handle_copy_selection(
         }
     };
 
-    // Copy the selection
-    copy_selection(hwnd)?;
+    let end_x = region_params.x.saturating_add(region_params.width.min(i32::MAX as u32) as i32);
+    let end_y = region_params.y.saturating_add(region_params.height.min(i32::MAX as u32) as i32);
+    state.backend.select_region(hwnd, region_params.x, region_params.y, end_x, end_y)?;
+    let image = state.backend.copy_selection_image(hwnd)?;
+    check_capture_size(&state, &image)?;
+    image_result(&image, &format!("Canvas region at ({}, {}) copied from Paint", region_params.x, region_params.y))
+}
+
+// Handler for the 'start_input_recording' method
+pub async fn handle_start_input_recording(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling start_input_recording request...");
+
+    // Deserialize parameters
+    let record_params: StartInputRecordingParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for start_input_recording".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    let path = crate::fs_guard::check_path(&record_params.path, &state.config()?)?;
+    crate::input_record::start_recording(&path)?;
 
-    // Return success response
     Ok(success_response())
 }
 
-// Handler for the 'paste' method
-pub async fn handle_paste(
+// Handler for the 'stop_input_recording' method
+pub async fn handle_stop_input_recording(
+    _state: PaintServerState,
+    _params: Option<Value>, // No parameters needed
+) -> Result<Value> {
+    info!("Handling stop_input_recording request...");
+
+    let (path, events) = crate::input_record::stop_recording()?;
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "path": path.display().to_string(),
+            "events": events
+        }
+    }))
+}
+
+// Handler for the 'replay_input' method
+pub async fn handle_replay_input(
     state: PaintServerState,
     params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling paste request...");
+    info!("Handling replay_input request...");
 
-    // Deserialize parameters - we just need x, y coordinates
-    let paste_params: DrawPixelParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for paste".to_string()))
+    // Deserialize parameters
+    let replay_params: ReplayInputParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for replay_input".to_string()))
         .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
 
-    // Get the Paint window handle from state
-    let hwnd = {
-        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
-            MspMcpError::General("Failed to lock HWND state".to_string()))?;
-        
-        match *hwnd_state {
-            Some(hwnd) => hwnd,
-            None => return Err(MspMcpError::WindowNotFound),
+    let path = crate::fs_guard::check_path(&replay_params.path, &state.config()?)?;
+    let events = crate::input_record::load_recording(&path)?;
+    let speed = replay_params.speed.unwrap_or(1.0);
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err(MspMcpError::InvalidParameters(format!("Replay speed must be positive, got {}", speed)));
+    }
+
+    let started = state.clock.now();
+    state.backend.replay_input(&events, speed)?;
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "events": events.len(),
+            "duration_ms": (state.clock.now() - started).as_millis() as u64
         }
+    }))
+}
+
+// Benchmark strokes stay inside this square at the top-left of the canvas
+const BENCHMARK_AREA: i32 = 200;
+
+// Summarizes one benchmark measurement
+fn benchmark_result(count: usize, elapsed: std::time::Duration) -> Value {
+    let secs = elapsed.as_secs_f64();
+    json!({
+        "count": count,
+        "elapsed_ms": elapsed.as_millis() as u64,
+        // Rounded to 0.1; null when too fast to measure
+        "per_sec": if secs > 0.0 { Some((count as f64 / secs * 10.0).round() / 10.0) } else { None }
+    })
+}
+
+// Handler for the 'get_audit_log' method
+pub async fn handle_get_audit_log(
+    state: PaintServerState,
+    params: Option<Value>, // All filters optional
+) -> Result<Value> {
+    info!("Handling get_audit_log request...");
+
+    // Deserialize parameters (all optional)
+    let log_params: GetAuditLogParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => GetAuditLogParams::default(),
     };
 
-    // Paste at the specified position
-    paste_at(hwnd, paste_params.x, paste_params.y)?;
+    let audit = state.audit.as_ref().ok_or_else(||
+        MspMcpError::OperationNotSupported("Audit logging is off; set audit_log in the config file".to_string()))?;
+    let entries = audit.read(&crate::audit::AuditFilter {
+        client_id: log_params.client_id.as_deref(),
+        method: log_params.method.as_deref(),
+        since_ms: log_params.since_ms,
+        errors_only: log_params.errors_only,
+        limit: log_params.limit.map_or(crate::audit::DEFAULT_AUDIT_LIMIT, |l| l as usize),
+    })?;
 
-    // Return success response
-    Ok(success_response())
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "path": audit.path().display().to_string(),
+            "entries": entries
+        }
+    }))
 }
 
-// Handler for the 'add_text' method
-pub async fn handle_add_text(
+// Handler for the 'run_benchmark' method
+pub async fn handle_run_benchmark(
     state: PaintServerState,
     params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling add_text request...");
+    info!("Handling run_benchmark request...");
 
-    // Deserialize parameters
-    let text_params: AddTextParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for add_text".to_string()))
-        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    // Parameters are optional
+    let bench_params: RunBenchmarkParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => RunBenchmarkParams::default(),
+    };
+    let iterations = bench_params.iterations.unwrap_or(20);
+    if !(1..=500).contains(&iterations) {
+        return Err(MspMcpError::InvalidParameters(format!("iterations must be between 1 and 500, got {}", iterations)));
+    }
+    let iterations = iterations as i32;
 
     // Get the Paint window handle from state
     let hwnd = {
-        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_|
             MspMcpError::General("Failed to lock HWND state".to_string()))?;
-        
+
         match *hwnd_state {
             Some(hwnd) => hwnd,
             None => return Err(MspMcpError::WindowNotFound),
         }
     };
 
-    // Add text to the canvas
-    add_text(
-        hwnd,
-        text_params.x,
-        text_params.y,
-        &text_params.text,
-        text_params.color.as_deref(),
-        text_params.font_name.as_deref(),
-        text_params.font_size,
-        text_params.font_style.as_deref()
-    )?;
+    // Keep every stroke inside the canvas, even a tiny one
+    let (width, height) = state.backend.canvas_dimensions(hwnd)?;
+    let area = BENCHMARK_AREA.min(width as i32).min(height as i32);
+    if area < 10 {
+        return Err(MspMcpError::InvalidParameters(format!("Canvas {}x{} is too small to benchmark", width, height)));
+    }
 
-    // Return success response
-    Ok(success_response())
+    // Settings are applied once so only drawing is timed
+    state.backend.activate(hwnd)?;
+    state.backend.select_tool(hwnd, "pencil")?;
+    state.backend.set_color(hwnd, "#000000")?;
+    state.backend.set_thickness(hwnd, 1)?;
+
+    // Single pixels on a 4px grid
+    let columns = (area / 4).max(1);
+    let started = state.clock.now();
+    for i in 0..iterations {
+        state.backend.draw_pixel(hwnd, (i % columns) * 4 + 2, (i / columns % columns) * 4 + 2, MouseButton::Left)?;
+    }
+    let pixels = benchmark_result(iterations as usize, state.clock.now() - started);
+
+    // Short diagonal lines, one call each
+    let started = state.clock.now();
+    for i in 0..iterations {
+        let x = (i * 7) % (area - 10);
+        let y = (i * 13) % (area - 10);
+        state.backend.draw_line(hwnd, x, y, x + 9, y + 9, MouseButton::Left)?;
+    }
+    let lines = benchmark_result(iterations as usize, state.clock.now() - started);
+
+    // A zigzag drawn as one batch
+    let points: Vec<(i32, i32)> = (0..=iterations)
+        .map(|i| ((i * 5) % (area - 1), if i % 2 == 0 { 0 } else { area - 1 }))
+        .collect();
+    let started = state.clock.now();
+    state.backend.draw_polyline(hwnd, &points, MouseButton::Left)?;
+    let batch = benchmark_result(points.len() - 1, state.clock.now() - started);
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "iterations": iterations,
+            "pixels": pixels,
+            "lines": lines,
+            "batch": batch,
+            "pixels_per_sec": pixels["per_sec"],
+            "lines_per_sec": lines["per_sec"],
+            "segments_per_sec": batch["per_sec"]
+        }
+    }))
 }
 
-// Handler for the 'create_canvas' method
-pub async fn handle_create_canvas(
+// Handler for the 'run_macro' method. Runs the script to get its batch of
+// requests, then runs them in order and stops at the first that fails
+pub async fn handle_run_macro(
     state: PaintServerState,
     params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling create_canvas request...");
+    info!("Handling run_macro request...");
 
-    // Deserialize parameters
-    let canvas_params: CreateCanvasParams = params
-        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for create_canvas".to_string()))
+    let macro_params: RunMacroParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for run_macro".to_string()))
         .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    let source = match (macro_params.source, &macro_params.name) {
+        (Some(source), None) => source,
+        (None, Some(name)) => state.macros.lock()
+            .map_err(|_| MspMcpError::General("Failed to lock macro state".to_string()))?
+            .get(name)
+            .map(str::to_string)
+            .ok_or_else(|| MspMcpError::InvalidParameters(format!("No macro named '{}'; register it with register_macro", name)))?,
+        _ => return Err(MspMcpError::InvalidParameters("Give either source or the name of a registered macro".to_string())),
+    };
 
     // Get the Paint window handle from state
     let hwnd = {
-        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_|
             MspMcpError::General("Failed to lock HWND state".to_string()))?;
-        
+
         match *hwnd_state {
             Some(hwnd) => hwnd,
             None => return Err(MspMcpError::WindowNotFound),
         }
     };
 
-    // Create a new canvas
-    create_canvas(
-        hwnd,
-        canvas_params.width,
-        canvas_params.height,
-        canvas_params.background_color.as_deref()
-    )?;
+    let (canvas_width, canvas_height) = state.backend.canvas_dimensions(hwnd)?;
+    let input = crate::macros::MacroInput { canvas_width, canvas_height, args: macro_params.args.unwrap_or_else(|| json!({})) };
+    let requests = crate::macros::plan(&source, &input)?;
+    info!("Macro planned {} requests", requests.len());
 
-    // Get the updated canvas dimensions
-    let (width, height) = get_canvas_dimensions(hwnd)?;
+    if macro_params.dry_run.unwrap_or(false) {
+        let planned: Vec<Value> = requests.into_iter().map(|(method, params)| json!({ "method": method, "params": params })).collect();
+        return Ok(json!({
+            "jsonrpc": "2.0",
+            "id": 1, // Should be extracted from the request
+            "result": { "dry_run": true, "steps": planned.len(), "requests": planned }
+        }));
+    }
+
+    let steps = requests.len();
+    for (step, (method, params)) in requests.into_iter().enumerate() {
+        // Boxed, since the request path is what routed us here
+        let request: futures::future::BoxFuture<'_, Result<Value>> = Box::pin(state.handle_request(&method, Some(params)));
+        if let Err(e) = request.await {
+            warn!("Macro stopped at step {} of {} ({}): {}", step + 1, steps, method, e);
+            return Err(e);
+        }
+    }
 
-    // Return success response with the new dimensions
     Ok(json!({
-        "status": "success",
-        "canvas_width": width,
-        "canvas_height": height
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": { "steps": steps }
     }))
 }
 
-// Handler for the 'initialize' method
-pub async fn handle_initialize(
+// Handler for the 'draw_animation' method. Plays the frames like a flip
+// book: each frame first clears or undoes the one before if it asks to,
+// then runs its steps through the normal request path, as run_macro does,
+// and is held until `frame_delay_ms` after it started.
+pub async fn handle_draw_animation(
     state: PaintServerState,
-    _params: Option<Value>, // No parameters needed for initialization
+    params: Option<Value>,
 ) -> Result<Value> {
-    info!("Handling initialize request...");
-    
-    // Find or launch Paint
-    let hwnd = match windows::get_paint_hwnd() {
-        Ok(h) => {
-            info!("Found or launched Paint window: HWND={}", h);
-            h
-        },
-        Err(e) => {
-            error!("Failed to find or launch Paint: {}", e);
-            return Err(e);
+    info!("Handling draw_animation request...");
+
+    // Clearing between frames goes through confirm_destructive like any
+    // clear_canvas; the animation's own confirm stands for those clears
+    let clear_params = params.as_ref()
+        .and_then(|p| p.get("confirm"))
+        .filter(|confirm| confirm.as_bool() == Some(true))
+        .map(|_| json!({ "confirm": true }));
+    let animation: DrawAnimationParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_animation".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    let loops = animation.loop_count.unwrap_or(1);
+    if animation.frames.is_empty() {
+        return Err(MspMcpError::InvalidParameters("draw_animation needs at least one frame".to_string()));
+    }
+    if !(1..=MAX_ANIMATION_LOOPS).contains(&loops) {
+        return Err(MspMcpError::InvalidParameters(format!("loop_count must be between 1 and {}, got {}", MAX_ANIMATION_LOOPS, loops)));
+    }
+    if animation.frame_delay_ms > MAX_FRAME_DELAY_MS {
+        return Err(MspMcpError::InvalidParameters(
+            format!("frame_delay_ms must be at most {}, got {}", MAX_FRAME_DELAY_MS, animation.frame_delay_ms)));
+    }
+    let steps: usize = animation.frames.iter().map(|frame| frame.steps.len()).sum::<usize>() * loops as usize;
+    if steps > crate::macros::MAX_MACRO_REQUESTS {
+        return Err(MspMcpError::InvalidParameters(
+            format!("The animation makes {} requests; at most {} are allowed", steps, crate::macros::MAX_MACRO_REQUESTS)));
+    }
+    if let Some(step) = animation.frames.iter().flat_map(|frame| &frame.steps)
+        .find(|step| crate::macros::FORBIDDEN_METHODS.contains(&step.method.as_str())) {
+        return Err(MspMcpError::InvalidParameters(format!("Animation frames can't call '{}'", step.method)));
+    }
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_|
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
         }
     };
-    
-    // Store HWND in state
-    {
-        let mut hwnd_state = state.paint_hwnd.lock().map_err(|_| 
-            MspMcpError::General("Failed to lock HWND state".to_string()))?;
-        *hwnd_state = Some(hwnd);
-        info!("Stored Paint HWND in server state");
+
+    let delay = std::time::Duration::from_millis(animation.frame_delay_ms);
+    let started = crate::clock::now();
+    let mut shown = 0;
+    let mut changes = 0; // Canvas changes the frame on screen made, to undo
+    for round in 0..loops {
+        for (index, frame) in animation.frames.iter().enumerate() {
+            let frame_started = crate::clock::now();
+            match frame.before {
+                Some(FrameReset::Clear) => {
+                    let request: futures::future::BoxFuture<'_, Result<Value>> = Box::pin(state.handle_request("clear_canvas", clear_params.clone()));
+                    request.await?;
+                }
+                Some(FrameReset::Undo) => {
+                    for _ in 0..changes {
+                        state.backend.undo(hwnd)?;
+                    }
+                    if let Ok(mut history) = state.history.lock() {
+                        history.undone(changes);
+                    }
+                }
+                None => {}
+            }
+            changes = 0;
+            for step in &frame.steps {
+                let params = (!step.params.is_null()).then(|| step.params.clone());
+                // Boxed, since the request path is what routed us here
+                let request: futures::future::BoxFuture<'_, Result<Value>> = Box::pin(state.handle_request(&step.method, params));
+                if let Err(e) = request.await {
+                    warn!("Animation stopped at frame {} of loop {} ({}): {}", index + 1, round + 1, step.method, e);
+                    return Err(e);
+                }
+                if crate::tools::mutates_canvas(&step.method) {
+                    changes += 1;
+                }
+            }
+            shown += 1;
+            // Hold the frame for what is left of its delay; the last one stays up
+            let last = round + 1 == loops && index + 1 == animation.frames.len();
+            if !last {
+                crate::clock::sleep(delay.saturating_sub(crate::clock::now() - frame_started));
+            }
+        }
     }
-    
-    // Return success
+
     Ok(json!({
         "jsonrpc": "2.0",
-        "id": 1, // Should be overridden with actual request ID later
+        "id": 1, // Should be extracted from the request
         "result": {
-            "initialized": true
+            "frames": shown,
+            "loops": loops,
+            "duration_ms": (crate::clock::now() - started).as_millis() as u64
         }
     }))
 }
 
-// TODO: Add tests for handlers (might require mocking windows module) 
\ No newline at end of file
+// Handler for the 'checkpoint' method
+pub async fn handle_checkpoint(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling checkpoint request...");
+
+    let checkpoint_params: CheckpointParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for checkpoint".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_|
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let fingerprint = crate::history::fingerprint(&state.backend.capture_canvas(hwnd)?);
+    let mut history = state.history.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock history state".to_string()))?;
+    let replaced = history.checkpoint(&checkpoint_params.label, fingerprint)?;
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "label": checkpoint_params.label,
+            "replaced": replaced,
+            "checkpoints": history.labels().collect::<Vec<_>>()
+        }
+    }))
+}
+
+// Handler for the 'revert_to_checkpoint' method. Undoes once per operation
+// since the checkpoint, then until the canvas matches it (see history.rs).
+pub async fn handle_revert_to_checkpoint(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling revert_to_checkpoint request...");
+
+    let checkpoint_params: CheckpointParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for revert_to_checkpoint".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    let label = checkpoint_params.label;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_|
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let checkpoint = state.history.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock history state".to_string()))?
+        .get(&label)?
+        .clone();
+
+    let at_checkpoint = |state: &PaintServerState| -> Result<bool> {
+        // Give Paint a moment to redraw after the last Ctrl+Z
+        crate::clock::sleep(std::time::Duration::from_millis(150));
+        Ok(crate::history::fingerprint(&state.backend.capture_canvas(hwnd)?) == checkpoint.fingerprint)
+    };
+    let mut undo_steps = 0;
+    for _ in 0..checkpoint.operations {
+        state.backend.undo(hwnd)?;
+        undo_steps += 1;
+    }
+    let mut matched = at_checkpoint(&state)?;
+    while !matched && undo_steps < checkpoint.operations + crate::history::MAX_EXTRA_UNDOS {
+        state.backend.undo(hwnd)?;
+        undo_steps += 1;
+        matched = at_checkpoint(&state)?;
+    }
+
+    // Either way the operations since the checkpoint are gone
+    state.history.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock history state".to_string()))?
+        .reverted(&label);
+    if !matched {
+        return Err(MspMcpError::General(format!(
+            "Undid {} steps but the canvas doesn't match checkpoint '{}'; Paint's undo history may not reach back that far",
+            undo_steps, label)));
+    }
+    info!("Reverted to checkpoint '{}' with {} undo steps for {} operations", label, undo_steps, checkpoint.operations);
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "label": label,
+            "operations": checkpoint.operations,
+            "undo_steps": undo_steps
+        }
+    }))
+}
+
+// Handler for the 'register_macro' method
+pub async fn handle_register_macro(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling register_macro request...");
+
+    let register_params: RegisterMacroParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for register_macro".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    let replaced = state.macros.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock macro state".to_string()))?
+        .register(&register_params.name, &register_params.source)?;
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "name": register_params.name,
+            "replaced": replaced
+        }
+    }))
+}
+
+// Handler for the 'self_test' method
+pub async fn handle_self_test(
+    state: PaintServerState,
+    _params: Option<Value>, // No parameters needed
+) -> Result<Value> {
+    info!("Handling self_test request...");
+
+    let steps = crate::self_test::run_smoke_test(&state);
+    let passed = steps.iter().all(|s| s.status == crate::self_test::StepStatus::Pass);
+    if !passed {
+        warn!("Smoke test failed");
+    }
+
+    // A failed step is reported in the result, not as an error
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "passed": passed,
+            "steps": steps
+        }
+    }))
+}
+
+// Handler for the 'calibrate' method. Finds where the tool buttons are by
+// trying positions around the layout's guess (see calibrate.rs)
+pub async fn handle_calibrate(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling calibrate request...");
+
+    let calibrate_params: CalibrateParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => CalibrateParams::default(),
+    };
+    let tools = calibrate_params.tools
+        .unwrap_or_else(|| crate::calibrate::CALIBRATABLE_TOOLS.iter().map(|tool| tool.to_string()).collect());
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_|
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    state.backend.activate(hwnd)?;
+    let calibration = crate::calibrate::calibrate(state.backend.as_ref(), hwnd, &tools);
+    // The probe color replaced Color 1 either way
+    state.forget_color_slots();
+    let (window_key, report) = calibration?;
+
+    let layout = crate::layout::active();
+    let path = state.config()?.calibration_path();
+    let persisted = calibrate_params.persist.unwrap_or(true);
+    if persisted {
+        crate::calibrate::save(&path, &layout)?;
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "layout": layout.name,
+            "window": window_key,
+            "tools": report,
+            "saved_to": if persisted { Some(path.display().to_string()) } else { None }
+        }
+    }))
+}
+
+// Handler for the 'set_dedicated_desktop' method
+pub async fn handle_set_dedicated_desktop(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_dedicated_desktop request...");
+
+    // Deserialize parameters
+    let desktop_params: SetDedicatedDesktopParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_dedicated_desktop".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Paint stays on the automation desktop when the mode is turned off; the
+    // user can drag it back from Task View if desired
+    let desktop_index = if desktop_params.enabled {
+        Some(move_to_automation_desktop(hwnd)?)
+    } else {
+        None
+    };
+
+    {
+        let mut dedicated = state.dedicated_desktop.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock dedicated desktop state".to_string()))?;
+        *dedicated = desktop_params.enabled;
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "enabled": desktop_params.enabled,
+            "desktop_index": desktop_index
+        }
+    }))
+}
+
+#[cfg(feature = "windows-automation")]
+fn move_to_automation_desktop(hwnd: HWND) -> Result<u32> {
+    crate::virtual_desktop::move_to_automation_desktop(hwnd)
+}
+
+// Virtual desktops are a Windows feature; other builds can't move anything
+#[cfg(not(feature = "windows-automation"))]
+fn move_to_automation_desktop(_hwnd: HWND) -> Result<u32> {
+    Err(MspMcpError::OperationNotSupported(
+        "Dedicated desktop mode needs a build with the windows-automation feature".to_string()))
+}
+
+// Sets the color a stroke will draw in: Paint draws left-button strokes in
+// Color 1 and right-button strokes in Color 2
+fn set_stroke_color(state: &PaintServerState, hwnd: HWND, color: &str, button: MouseButton) -> Result<()> {
+    // A color we added to the custom colors is a swatch click away
+    let custom_slot = state.palettes.lock().ok().and_then(|palettes| palettes.custom_slot(color));
+    let result = match (custom_slot, button) {
+        (Some(slot), _) => state.backend.select_custom_color(hwnd, slot, button),
+        (None, MouseButton::Left) => state.backend.set_color(hwnd, color),
+        (None, MouseButton::Right) => state.backend.set_secondary_color(hwnd, color),
+    };
+    track_color_slot(state, button, color, result)
+}
+
+// Keeps the record of what Color 1 (left) and Color 2 (right) hold in line
+// with a color setter's outcome; after a failure the slot is unknown
+fn track_color_slot(state: &PaintServerState, button: MouseButton, color: &str, result: Result<()>) -> Result<()> {
+    if let Ok(mut palettes) = state.palettes.lock() {
+        match result {
+            Ok(()) => palettes.record_slot(button, color),
+            Err(_) => palettes.forget_slot(button),
+        }
+    }
+    result
+}
+
+// Sets the stroke width from a drawing request. `thickness_px` goes
+// straight to Paint's size slider; the old 1-5 `thickness` levels are still
+// accepted and mapped to pixels
+fn set_stroke_thickness(state: &PaintServerState, hwnd: HWND, level: Option<u32>, px: Option<u32>) -> Result<()> {
+    match (level, px) {
+        (Some(_), Some(_)) => Err(MspMcpError::InvalidParameters("Pass either thickness or thickness_px, not both".to_string())),
+        (None, Some(px)) => state.backend.set_thickness_px(hwnd, px),
+        (Some(level), None) => {
+            debug!("thickness levels are deprecated, use thickness_px");
+            state.backend.set_thickness(hwnd, level)
+        }
+        (None, None) => Ok(()),
+    }
+}
+
+// The stroke preset a drawing request names, from the config or built in
+fn stroke_preset(state: &PaintServerState, name: Option<&str>) -> Result<Option<StrokePreset>> {
+    let Some(name) = name else { return Ok(None) };
+    crate::presets::resolve(name, &state.config()?.stroke_presets).map(Some)
+}
+
+// Fills in the color and width a request left out from its preset. A
+// thickness level in the request counts as setting the width
+fn apply_stroke_preset(preset: &StrokePreset, color: &mut Option<String>, thickness: Option<u32>, thickness_px: &mut Option<u32>) {
+    if color.is_none() {
+        color.clone_from(&preset.color);
+    }
+    if thickness.is_none() && thickness_px.is_none() {
+        *thickness_px = preset.thickness_px;
+    }
+}
+
+// Selects the tool a stroke is drawn with, and the preset's brush variant
+// when that tool is the brush
+fn select_stroke_tool(state: &PaintServerState, hwnd: HWND, tool: &str, preset: Option<&StrokePreset>) -> Result<()> {
+    state.backend.select_tool(hwnd, tool)?;
+    match preset.and_then(|preset| preset.brush_type.as_deref()) {
+        Some(brush_type) if tool.eq_ignore_ascii_case("brush") => state.backend.select_brush_type(hwnd, brush_type),
+        _ => Ok(()),
+    }
+}
+
+// Handler for the 'draw_pixel' method
+pub async fn handle_draw_pixel(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling draw_pixel request...");
+
+    // Deserialize parameters
+    let mut draw_params: DrawPixelParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_pixel".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let button = draw_params.button.unwrap_or_default();
+    if let Some(preset) = stroke_preset(&state, draw_params.preset.as_deref())? {
+        draw_params.color = draw_params.color.or(preset.color);
+    }
+
+    if draw_params.dry_run == Some(true) {
+        check_stroke(draw_params.color.as_deref(), None, None)?;
+        return dry_run_response(&state, hwnd, &[vec![(draw_params.x, draw_params.y)]], None);
+    }
+
+    // If a color is specified, set it first
+    if let Some(color) = &draw_params.color {
+        set_stroke_color(&state, hwnd, color, button)?;
+    }
+
+    // Draw the pixel at the specified coordinates
+    state.backend.draw_pixel(hwnd, draw_params.x, draw_params.y, button)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'draw_line' method
+pub async fn handle_draw_line(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling draw_line request...");
+
+    // Deserialize parameters
+    let mut draw_params: DrawLineParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_line".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let button = draw_params.button.unwrap_or_default();
+    let preset = stroke_preset(&state, draw_params.preset.as_deref())?;
+    if let Some(preset) = &preset {
+        apply_stroke_preset(preset, &mut draw_params.color, draw_params.thickness, &mut draw_params.thickness_px);
+    }
+
+    let (start, end) = ((draw_params.start_x, draw_params.start_y), (draw_params.end_x, draw_params.end_y));
+    let (segment, clipped) = match clip_size(&state, hwnd, draw_params.clip)? {
+        None => (Some((start, end)), None),
+        // Only the part of the line on the canvas
+        Some(size) => match crate::geometry::clip_segment(start, end, size) {
+            Some((from, to)) => (Some((from, to)), Some(if (from, to) == (start, end) { Clipped::None } else { Clipped::Partial })),
+            None => (None, Some(Clipped::All)),
+        },
+    };
+
+    if draw_params.dry_run == Some(true) {
+        check_stroke(draw_params.color.as_deref(), draw_params.thickness, draw_params.thickness_px)?;
+        let strokes: Vec<_> = segment.map(|(from, to)| vec![from, to]).into_iter().collect();
+        return dry_run_response(&state, hwnd, &strokes, clipped);
+    }
+
+    // Lines are pencil strokes unless the preset picks the brush, which
+    // draw_line can't use; those are drawn as a two-point polyline
+    let brush = preset.as_ref().is_some_and(|preset| preset.tool.as_deref().is_some_and(|tool| tool.eq_ignore_ascii_case("brush")));
+    if brush && segment.is_some() {
+        select_stroke_tool(&state, hwnd, "brush", preset.as_ref())?;
+    }
+
+    // If a color is specified, set it first
+    if let Some(color) = &draw_params.color {
+        set_stroke_color(&state, hwnd, color, button)?;
+    }
+
+    // If thickness is specified, set it
+    set_stroke_thickness(&state, hwnd, draw_params.thickness, draw_params.thickness_px)?;
+
+    // Draw the line at the specified coordinates
+    match (segment, brush) {
+        (Some((from, to)), true) => state.backend.draw_polyline(hwnd, &[from, to], button)?,
+        (Some((from, to)), false) => state.backend.draw_line(hwnd, from.0, from.1, to.0, to.1, button)?,
+        (None, _) => {}
+    }
+    Ok(clipped.map_or_else(success_response, clipped_response))
+}
+
+// The canvas size to clip a figure to, if the request asked for clipping
+fn clip_size(state: &PaintServerState, hwnd: HWND, clip: Option<bool>) -> Result<Option<(u32, u32)>> {
+    if clip != Some(true) {
+        return Ok(None);
+    }
+    state.backend.canvas_dimensions(hwnd).map(Some)
+}
+
+// Success response for a figure drawn with `clip`, saying how much of it was cut off
+fn clipped_response(clipped: Clipped) -> Value {
+    if clipped != Clipped::None {
+        debug!("Clipped the figure to the canvas: {:?}", clipped);
+    }
+    json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": { "clipped": clipped }
+    })
+}
+
+// The checks Paint's color and stroke width setters would make, for a dry
+// run that doesn't call them
+fn check_stroke(color: Option<&str>, level: Option<u32>, px: Option<u32>) -> Result<()> {
+    if let Some(color) = color {
+        crate::palette::normalize_color(color)?;
+    }
+    match (level, px) {
+        (Some(_), Some(_)) => Err(MspMcpError::InvalidParameters("Pass either thickness or thickness_px, not both".to_string())),
+        (None, Some(px)) if !(1..=crate::protocol::MAX_THICKNESS_PX).contains(&px) => Err(MspMcpError::InvalidParameters(
+            format!("Thickness must be between 1 and {} pixels", crate::protocol::MAX_THICKNESS_PX))),
+        (Some(level), None) if !(1..=crate::protocol::THICKNESS_LEVEL_PIXELS.len() as u32).contains(&level) => Err(
+            MspMcpError::InvalidParameters(format!("Thickness level must be between 1 and {}", crate::protocol::THICKNESS_LEVEL_PIXELS.len()))),
+        _ => Ok(()),
+    }
+}
+
+// Response for a figure planned with `dry_run` instead of drawn: its
+// strokes in canvas pixels and on screen as the canvas is shown now, with
+// their bounding boxes. Nothing is sent to Paint.
+fn dry_run_response(state: &PaintServerState, hwnd: HWND, strokes: &[Vec<(i32, i32)>], clipped: Option<Clipped>) -> Result<Value> {
+    let view = state.backend.canvas_view(hwnd)?;
+    // Screen positions only depend on the viewport, not on the client area
+    let frame = crate::geometry::CanvasFrame::from_view(&view, (0, 0));
+    let screen: Vec<Vec<(i32, i32)>> = strokes.iter()
+        .map(|stroke| stroke.iter().map(|&(x, y)| frame.to_screen(x, y)).collect())
+        .collect();
+    let as_json = |strokes: &[Vec<(i32, i32)>]| strokes.iter()
+        .map(|stroke| stroke.iter().map(|&(x, y)| json!({"x": x, "y": y})).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let bounds = |strokes: &[Vec<(i32, i32)>]| crate::geometry::bounding_box(&strokes.concat())
+        .map(|(min, max)| crate::protocol::RectInfo::new(min.0, min.1, max.0.saturating_add(1), max.1.saturating_add(1)));
+    // Strokes over pixels out of view scroll the canvas when drawn for real
+    let in_view = strokes.iter().flatten()
+        .filter(|&&point| crate::geometry::on_canvas(point, (view.width, view.height)))
+        .all(|&(x, y)| frame.is_visible(x, y));
+
+    let mut result = json!({
+        "dry_run": true,
+        "canvas_strokes": as_json(strokes),
+        "screen_strokes": as_json(&screen),
+        "canvas_bounding_box": bounds(strokes),
+        "bounding_box": bounds(&screen),
+        "in_view": in_view,
+        "estimated": view.estimated
+    });
+    if let Some(clipped) = clipped {
+        result["clipped"] = json!(clipped);
+    }
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": result
+    }))
+}
+
+// A brush variant only makes sense for the brush tool
+fn check_brush_type(tool: Option<&str>, brush_type: &str) -> Result<()> {
+    if !tool.is_some_and(|t| t.eq_ignore_ascii_case("brush")) {
+        return Err(MspMcpError::InvalidParameters(format!(
+            "brush_type requires tool \"brush\", got \"{}\"", tool.unwrap_or_default())));
+    }
+    if !crate::protocol::BRUSH_TYPES.contains(&brush_type) {
+        return Err(MspMcpError::InvalidParameters(format!(
+            "Unsupported brush type: {}. Must be one of: {}", brush_type, crate::protocol::BRUSH_TYPES.join(", "))));
+    }
+    Ok(())
+}
+
+// Handler for the 'select_tool' method
+pub async fn handle_select_tool(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling select_tool request...");
+
+    // Deserialize parameters
+    let tool_params: SelectToolParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for select_tool".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    if let Some(brush_type) = &tool_params.brush_type {
+        check_brush_type(Some(&tool_params.tool), brush_type)?;
+    }
+
+    // Select the tool
+    state.backend.select_tool(hwnd, &tool_params.tool)?;
+
+    // Then the brush variant
+    if let Some(brush_type) = &tool_params.brush_type {
+        state.backend.select_brush_type(hwnd, brush_type)?;
+    }
+
+    // If a shape type is specified, handle that as well
+    if let Some(shape_type) = tool_params.shape_type {
+        // TODO: Implement shape type selection
+        info!("Would select shape type: {}", shape_type);
+    }
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'set_color' method
+pub async fn handle_set_color(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_color request...");
+
+    // Deserialize parameters
+    let color_params: SetColorParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_color".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Set the color
+    set_stroke_color(&state, hwnd, &color_params.color, MouseButton::Left)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'set_secondary_color' method
+pub async fn handle_set_secondary_color(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_secondary_color request...");
+
+    // Deserialize parameters
+    let color_params: SetSecondaryColorParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_secondary_color".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Set Color 2
+    set_stroke_color(&state, hwnd, &color_params.color, MouseButton::Right)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'pick_color_at' method
+pub async fn handle_pick_color_at(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling pick_color_at request...");
+
+    // Deserialize parameters
+    let pick_params: PickColorAtParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for pick_color_at".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Sample the pixel from a capture rather than with Paint's color picker
+    // tool, which would stay selected and swallow the next stroke
+    let pixel = state.backend.capture_canvas_region(hwnd, pick_params.x, pick_params.y, 1, 1)?;
+    let color = pixel.pixel_hex(0, 0).ok_or_else(|| MspMcpError::InvalidParameters(
+        format!("({}, {}) is outside the canvas", pick_params.x, pick_params.y)))?;
+
+    let button = pick_params.button.unwrap_or_default();
+    set_stroke_color(&state, hwnd, &color, button)?;
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "color": color,
+            "button": button
+        }
+    }))
+}
+
+// Handler for the 'define_palette' method. Only the server is involved;
+// Paint sees the colors when they are used
+pub async fn handle_define_palette(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling define_palette request...");
+
+    let palette_params: DefinePaletteParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for define_palette".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    let colors = state.palettes.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock palette state".to_string()))?
+        .define(&palette_params.name, &palette_params.colors)?;
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "name": palette_params.name,
+            "colors": colors
+        }
+    }))
+}
+
+// Handler for the 'use_palette_color' method. A slot that already holds the
+// color is left alone
+pub async fn handle_use_palette_color(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling use_palette_color request...");
+
+    let use_params: UsePaletteColorParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for use_palette_color".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    let button = use_params.button.unwrap_or_default();
+
+    let (color, already_set) = {
+        let palettes = state.palettes.lock()
+            .map_err(|_| MspMcpError::General("Failed to lock palette state".to_string()))?;
+        let color = palettes.color(&use_params.name, use_params.index)?;
+        let already_set = palettes.slot_holds(button, &color);
+        (color, already_set)
+    };
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    if !already_set {
+        set_stroke_color(&state, hwnd, &color, button)?;
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "color": color,
+            "button": button,
+            "changed": !already_set
+        }
+    }))
+}
+
+// Handler for the 'add_custom_color' method. A color we already added is
+// selected from its swatch instead of being added a second time
+pub async fn handle_add_custom_color(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling add_custom_color request...");
+
+    let add_params: AddCustomColorParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for add_custom_color".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    let button = add_params.button.unwrap_or_default();
+    let color = crate::palette::normalize_color(&add_params.color)?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let known = state.palettes.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock palette state".to_string()))?
+        .custom_slot(&color)
+        .is_some();
+    if known {
+        set_stroke_color(&state, hwnd, &color, button)?;
+    } else {
+        let result = state.backend.add_custom_color(hwnd, &color, button);
+        if result.is_ok() {
+            if let Ok(mut palettes) = state.palettes.lock() {
+                palettes.record_custom(&color);
+            }
+        }
+        track_color_slot(&state, button, &color, result)?;
+    }
+
+    let palettes = state.palettes.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock palette state".to_string()))?;
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "color": color,
+            "slot": palettes.custom_slot(&color),
+            "added": !known,
+            "custom_colors": palettes.custom_colors()
+        }
+    }))
+}
+
+// Handler for the 'use_custom_color' method. Picks a color added with
+// add_custom_color by color or by slot; a slot that already holds it is
+// left alone
+pub async fn handle_use_custom_color(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling use_custom_color request...");
+
+    let use_params: UseCustomColorParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for use_custom_color".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    let button = use_params.button.unwrap_or_default();
+
+    let (color, slot, already_set) = {
+        let palettes = state.palettes.lock()
+            .map_err(|_| MspMcpError::General("Failed to lock palette state".to_string()))?;
+        let (color, slot) = match (use_params.color, use_params.slot) {
+            (Some(color), None) => {
+                let slot = palettes.custom_slot(&color).ok_or_else(|| MspMcpError::InvalidParameters(
+                    format!("{} isn't a custom color; add it with add_custom_color", color)))?;
+                (palettes.custom_colors()[slot].clone(), slot)
+            }
+            (None, Some(slot)) => {
+                let color = palettes.custom_colors().get(slot).cloned().ok_or_else(|| MspMcpError::InvalidParameters(
+                    format!("{} custom colors were added, slot {} is out of range", palettes.custom_colors().len(), slot)))?;
+                (color, slot)
+            }
+            _ => return Err(MspMcpError::InvalidParameters("Give exactly one of color and slot".to_string())),
+        };
+        let already_set = palettes.slot_holds(button, &color);
+        (color, slot, already_set)
+    };
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    if !already_set {
+        let result = state.backend.select_custom_color(hwnd, slot, button);
+        track_color_slot(&state, button, &color, result)?;
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "color": color,
+            "slot": slot,
+            "button": button,
+            "changed": !already_set
+        }
+    }))
+}
+
+// Handler for the 'set_snap' method. Only the server is involved; the grid
+// applies to the drawing requests that follow
+pub async fn handle_set_snap(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_snap request...");
+
+    let snap_params: SetSnapParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => SetSnapParams { grid_size: None },
+    };
+    let grid_size = snap_params.grid_size.filter(|&size| size > 0);
+
+    *state.snap_grid.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock snap state".to_string()))? = grid_size;
+    match grid_size {
+        Some(size) => info!("Snapping drawing positions to a {}px grid", size),
+        None => info!("Snapping turned off"),
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "grid_size": grid_size
+        }
+    }))
+}
+
+// Handler for the 'push_transform' method. Only the server is involved; the
+// transform applies to the drawing requests that follow
+pub async fn handle_push_transform(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling push_transform request...");
+
+    let transform_params: PushTransformParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for push_transform".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+    let transform = crate::transform::Affine::new(
+        (transform_params.translate_x.unwrap_or(0.0), transform_params.translate_y.unwrap_or(0.0)),
+        transform_params.rotate_degrees.unwrap_or(0.0),
+        (transform_params.scale_x.unwrap_or(1.0), transform_params.scale_y.unwrap_or(1.0)),
+    )?;
+
+    let depth = state.transforms.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock transform state".to_string()))?
+        .push(transform)?;
+    debug!("Pushed transform {:?}; {} now applied", transform, depth);
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "depth": depth
+        }
+    }))
+}
+
+// Handler for the 'pop_transform' method
+pub async fn handle_pop_transform(
+    state: PaintServerState,
+    _params: Option<Value>, // No parameters needed
+) -> Result<Value> {
+    info!("Handling pop_transform request...");
+
+    let depth = state.transforms.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock transform state".to_string()))?
+        .pop()?;
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "depth": depth
+        }
+    }))
+}
+
+// Handler for the 'set_thickness' method
+pub async fn handle_set_thickness(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_thickness request...");
+
+    // Deserialize parameters
+    let thickness_params: SetThicknessParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_thickness".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Set the thickness
+    state.backend.set_thickness(hwnd, thickness_params.level)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'set_brush_size' method
+pub async fn handle_set_brush_size(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_brush_size request...");
+
+    // Deserialize parameters
+    let brush_params: SetBrushSizeParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_brush_size".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Set the brush size
+    state.backend.set_brush_size(hwnd, brush_params.size, brush_params.tool.as_deref())?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'set_fill' method
+pub async fn handle_set_fill(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_fill request...");
+
+    // Deserialize parameters
+    let fill_params: SetFillParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_fill".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Set the fill type
+    state.backend.set_fill(hwnd, &fill_params.fill_type)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'set_tool_state' method: select_tool, set_color,
+// set_secondary_color, the size slider and set_fill in one call, with one
+// activation of the Paint window instead of one per setting
+pub async fn handle_set_tool_state(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_tool_state request...");
+
+    let tool_state: SetToolStateParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_tool_state".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    if tool_state == SetToolStateParams::default() {
+        return Err(MspMcpError::InvalidParameters(
+            "Pass at least one of tool, color, secondary_color, thickness_px or fill_type".to_string()));
+    }
+    if let Some(brush_type) = &tool_state.brush_type {
+        check_brush_type(tool_state.tool.as_deref(), brush_type)?;
+    }
+
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let result = state.backend.set_tool_state(hwnd, &tool_state);
+    if let Ok(mut palettes) = state.palettes.lock() {
+        for (button, color) in [(MouseButton::Left, &tool_state.color), (MouseButton::Right, &tool_state.secondary_color)] {
+            // On failure the colors may be half applied
+            match (color, &result) {
+                (Some(color), Ok(())) => palettes.record_slot(button, color),
+                (Some(_), Err(_)) => palettes.forget_slot(button),
+                (None, _) => {}
+            }
+        }
+    }
+    result?;
+
+    Ok(success_response())
+}
+
+// Handler for the 'draw_shape' method
+pub async fn handle_draw_shape(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling draw_shape request...");
+
+    // Deserialize parameters
+    let mut shape_params: DrawShapeParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_shape".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let button = shape_params.button.unwrap_or_default();
+    if let Some(preset) = stroke_preset(&state, shape_params.preset.as_deref())? {
+        apply_stroke_preset(&preset, &mut shape_params.color, shape_params.thickness, &mut shape_params.thickness_px);
+    }
+
+    let size = clip_size(&state, hwnd, shape_params.clip)?;
+    let (plan, clipped) = plan_shape(&shape_params, size, button)?;
+
+    if shape_params.dry_run == Some(true) {
+        check_stroke(shape_params.color.as_deref(), shape_params.thickness, shape_params.thickness_px)?;
+        if let Some(fill_type) = &shape_params.fill_type {
+            if !matches!(fill_type.to_lowercase().as_str(), "none" | "solid" | "outline") {
+                return Err(MspMcpError::InvalidParameters(
+                    format!("Fill type must be 'none', 'solid', or 'outline', got '{}'", fill_type)));
+            }
+        }
+        return dry_run_response(&state, hwnd, &plan.strokes(&shape_params.shape_type)?, clipped);
+    }
+
+    // If a color is specified, set it first
+    if let Some(color) = &shape_params.color {
+        set_stroke_color(&state, hwnd, color, button)?;
+    }
+
+    // If a thickness is specified, set it
+    set_stroke_thickness(&state, hwnd, shape_params.thickness, shape_params.thickness_px)?;
+
+    // If a fill type is specified, set it
+    if let Some(fill_type) = &shape_params.fill_type {
+        state.backend.set_fill(hwnd, fill_type)?;
+    }
+
+    // Draw the shape
+    draw_planned_shape(&state, hwnd, &shape_params.shape_type, plan, button)?;
+
+    // Return success response
+    Ok(clipped.map_or_else(success_response, clipped_response))
+}
+
+// How a shape gets onto the canvas
+enum ShapePlan {
+    Whole((i32, i32), (i32, i32)), // With the shape tool, between these corners
+    Outline(Vec<Vec<(i32, i32)>>), // The visible runs of its outline, with the pencil
+    Filled(Vec<(i32, i32)>),       // The visible polygon, outlined and then filled
+    Nothing,                       // It is all off the canvas
+}
+
+impl ShapePlan {
+    // The strokes the plan draws, in canvas pixels
+    fn strokes(&self, shape_type: &str) -> Result<Vec<Vec<(i32, i32)>>> {
+        let closed = |points: &[(i32, i32)]| points.iter().chain(points.first()).copied().collect::<Vec<_>>();
+        Ok(match self {
+            ShapePlan::Whole(start, end) if matches!(shape_type.to_lowercase().as_str(), "line" | "arrow") => vec![vec![*start, *end]],
+            ShapePlan::Whole(start, end) => vec![closed(&crate::simulate::shape_outline(shape_type, *start, *end)?)],
+            ShapePlan::Outline(runs) => runs.clone(),
+            ShapePlan::Filled(visible) => vec![closed(visible)],
+            ShapePlan::Nothing => Vec::new(),
+        })
+    }
+}
+
+// Plans a shape, clipped to a canvas of `size` if given. Paint's shape
+// tool only draws whole shapes, so a cut-off line or arrow is drawn
+// shortened, and any other cut-off shape as the visible part of its outline
+// with the pencil. A solid one is clipped as a polygon instead, outlined
+// along the canvas edge too and filled with the fill tool.
+fn plan_shape(params: &DrawShapeParams, size: Option<(u32, u32)>, button: MouseButton) -> Result<(ShapePlan, Option<Clipped>)> {
+    let (start, end) = ((params.start_x, params.start_y), (params.end_x, params.end_y));
+    let Some(size) = size else { return Ok((ShapePlan::Whole(start, end), None)) };
+    let shape_type = params.shape_type.to_lowercase();
+    if matches!(shape_type.as_str(), "line" | "arrow") {
+        return Ok(match crate::geometry::clip_segment(start, end, size) {
+            Some((from, to)) if (from, to) == (start, end) => (ShapePlan::Whole(from, to), Some(Clipped::None)),
+            Some((from, to)) => (ShapePlan::Whole(from, to), Some(Clipped::Partial)),
+            None => (ShapePlan::Nothing, Some(Clipped::All)),
+        });
+    }
+    if crate::geometry::on_canvas(start, size) && crate::geometry::on_canvas(end, size) {
+        return Ok((ShapePlan::Whole(start, end), Some(Clipped::None)));
+    }
+
+    let outline = crate::simulate::shape_outline(&shape_type, start, end)?;
+    if params.fill_type.as_deref() != Some("solid") {
+        let runs = crate::geometry::clip_path(&outline, size, true);
+        if runs.is_empty() {
+            return Ok((ShapePlan::Nothing, Some(Clipped::All)));
+        }
+        return Ok((ShapePlan::Outline(runs), Some(Clipped::Partial)));
+    }
+
+    let visible = crate::geometry::clip_polygon(&outline, size);
+    if visible.is_empty() {
+        return Ok((ShapePlan::Nothing, Some(Clipped::All)));
+    }
+    // The fill tool is clicked, and a click only fills with Color 1
+    if button == MouseButton::Right {
+        return Err(MspMcpError::InvalidParameters(
+            "A cut-off shape with a solid fill can only be drawn with the left button".to_string()));
+    }
+    Ok((ShapePlan::Filled(visible), Some(Clipped::Partial)))
+}
+
+// Draws a planned shape with the current color, thickness and fill
+fn draw_planned_shape(state: &PaintServerState, hwnd: HWND, shape_type: &str, plan: ShapePlan, button: MouseButton) -> Result<()> {
+    match plan {
+        ShapePlan::Whole(from, to) => state.backend.draw_shape(hwnd, shape_type, from.0, from.1, to.0, to.1, button),
+        ShapePlan::Outline(runs) => runs.iter().try_for_each(|run| state.backend.draw_polyline(hwnd, run, button)),
+        ShapePlan::Filled(visible) => {
+            let mut closed = visible.clone();
+            closed.push(visible[0]);
+            state.backend.draw_polyline(hwnd, &closed, button)?;
+            // Shapes are convex, so the average of the corners is inside
+            let count = visible.len() as i64;
+            let (sum_x, sum_y) = visible.iter().fold((0i64, 0i64), |(x, y), p| (x + p.0 as i64, y + p.1 as i64));
+            state.backend.select_tool(hwnd, "fill")?;
+            state.backend.click(hwnd, (sum_x / count) as i32, (sum_y / count) as i32)
+        }
+        ShapePlan::Nothing => Ok(()),
+    }
+}
+
+// Handler for the 'draw_polyline' method
+pub async fn handle_draw_polyline(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling draw_polyline request...");
+
+    // Deserialize parameters
+    let mut polyline_params: DrawPolylineParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_polyline".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let preset = stroke_preset(&state, polyline_params.preset.as_deref())?;
+    if let Some(preset) = &preset {
+        apply_stroke_preset(preset, &mut polyline_params.color, polyline_params.thickness, &mut polyline_params.thickness_px);
+        polyline_params.tool = polyline_params.tool.or_else(|| preset.tool.clone());
+    }
+
+    // Convert Point structs to (i32, i32) tuples for the Windows API
+    let mut point_tuples: Vec<(i32, i32)> = polyline_params.points
+        .iter()
+        .map(|point| (point.x, point.y))
+        .collect();
+
+    // Curve through the points, then drop the ones the path hardly needs
+    if let Some(smoothing) = polyline_params.smoothing {
+        point_tuples = match smoothing {
+            Smoothing::CatmullRom => crate::geometry::catmull_rom(&point_tuples),
+        };
+    }
+    if let Some(tolerance) = polyline_params.simplify_tolerance {
+        if !tolerance.is_finite() || tolerance < 0.0 {
+            return Err(MspMcpError::InvalidParameters(
+                format!("simplify_tolerance must be 0 or more pixels, got {}", tolerance)));
+        }
+        point_tuples = crate::geometry::simplify_rdp(&point_tuples, tolerance);
+    }
+
+    // The stroke, or with a width profile its pieces, and the width of each
+    let pieces = match &polyline_params.width_profile {
+        None => vec![(None, point_tuples)],
+        Some(profile) => {
+            let points: Vec<(i32, i32)> = polyline_params.points.iter().map(|point| (point.x, point.y)).collect();
+            crate::stroke_width::pieces(&point_tuples, &points, profile, polyline_params.thickness_px)?
+                .into_iter()
+                .map(|(px, piece)| (Some(px), piece))
+                .collect()
+        }
+    };
+
+    let clip = clip_size(&state, hwnd, polyline_params.clip)?
+        .filter(|_| pieces.iter().map(|(_, piece)| piece.len()).sum::<usize>() > 1);
+    let (mut strokes, mut widths, mut cut) = (Vec::new(), Vec::new(), false);
+    for (width, piece) in pieces {
+        match clip {
+            None => {
+                strokes.push(piece);
+                widths.push(width);
+            }
+            Some(size) => {
+                // Only the parts of the polyline on the canvas, each as its own stroke
+                let runs = crate::geometry::clip_path(&piece, size, false);
+                cut |= !matches!(runs.as_slice(), [run] if *run == piece);
+                widths.extend(std::iter::repeat_n(width, runs.len()));
+                strokes.extend(runs);
+            }
+        }
+    }
+    let clipped = clip.map(|_| match (strokes.is_empty(), cut) {
+        (true, _) => Clipped::All,
+        (false, false) => Clipped::None,
+        (false, true) => Clipped::Partial,
+    });
+
+    let button = polyline_params.button.unwrap_or_default();
+
+    if polyline_params.dry_run == Some(true) {
+        check_stroke(polyline_params.color.as_deref(), polyline_params.thickness, polyline_params.thickness_px)?;
+        return dry_run_response(&state, hwnd, &strokes, clipped);
+    }
+
+    // If a tool is specified, select it first (pencil or brush)
+    if let Some(tool) = &polyline_params.tool {
+        select_stroke_tool(&state, hwnd, tool, preset.as_ref())?;
+    } else {
+        // Default to pencil if no tool specified
+        state.backend.select_tool(hwnd, "pencil")?;
+    }
+
+    // If a color is specified, set it
+    if let Some(color) = &polyline_params.color {
+        set_stroke_color(&state, hwnd, color, button)?;
+    }
+
+    // If a thickness is specified, set it; a width profile sets its own
+    if polyline_params.width_profile.is_none() {
+        set_stroke_thickness(&state, hwnd, polyline_params.thickness, polyline_params.thickness_px)?;
+    }
+
+    // Draw the polyline, changing the size only where the width does
+    let mut current_px = None;
+    for (stroke, width) in strokes.iter().zip(widths) {
+        if width.is_some() && width != current_px {
+            set_stroke_thickness(&state, hwnd, None, width)?;
+            current_px = width;
+        }
+        state.backend.draw_polyline(hwnd, stroke, button)?;
+    }
+    Ok(clipped.map_or_else(success_response, clipped_response))
+}
+
+// Handler for the 'draw_ellipse' method. Paint's ellipse tool only draws
+// upright ellipses, so the outline is tessellated on the server and drawn
+// as a polyline
+pub async fn handle_draw_ellipse(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling draw_ellipse request...");
+
+    // Deserialize parameters
+    let mut ellipse_params: DrawEllipseParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_ellipse".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    if ellipse_params.radius_x == 0 || ellipse_params.radius_y == 0 {
+        return Err(MspMcpError::InvalidParameters(format!(
+            "radius_x and radius_y must be at least 1, got {} and {}", ellipse_params.radius_x, ellipse_params.radius_y)));
+    }
+    let rotation = ellipse_params.rotation_deg.unwrap_or(0.0);
+    let arc = match (ellipse_params.start_angle, ellipse_params.end_angle) {
+        (None, None) => None,
+        (Some(start), Some(end)) => Some((start, end)),
+        _ => return Err(MspMcpError::InvalidParameters("Pass both start_angle and end_angle for an arc, or neither".to_string())),
+    };
+    let angles = [rotation, arc.map_or(0.0, |arc| arc.0), arc.map_or(0.0, |arc| arc.1)];
+    if angles.iter().any(|angle| !angle.is_finite()) {
+        return Err(MspMcpError::InvalidParameters("Angles must be finite numbers of degrees".to_string()));
+    }
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let preset = stroke_preset(&state, ellipse_params.preset.as_deref())?;
+    if let Some(preset) = &preset {
+        apply_stroke_preset(preset, &mut ellipse_params.color, ellipse_params.thickness, &mut ellipse_params.thickness_px);
+        ellipse_params.tool = ellipse_params.tool.or_else(|| preset.tool.clone());
+    }
+
+    let outline = crate::geometry::ellipse(
+        (ellipse_params.x, ellipse_params.y), (ellipse_params.radius_x, ellipse_params.radius_y), rotation, arc);
+
+    let (strokes, clipped) = match clip_size(&state, hwnd, ellipse_params.clip)? {
+        None => (vec![outline], None),
+        Some(size) => {
+            // Only the parts of the outline on the canvas, each as its own stroke
+            let runs = crate::geometry::clip_path(&outline, size, false);
+            let clipped = match runs.as_slice() {
+                [] => Clipped::All,
+                [run] if *run == outline => Clipped::None,
+                _ => Clipped::Partial,
+            };
+            (runs, Some(clipped))
+        }
+    };
+
+    let button = ellipse_params.button.unwrap_or_default();
+
+    if ellipse_params.dry_run == Some(true) {
+        check_stroke(ellipse_params.color.as_deref(), ellipse_params.thickness, ellipse_params.thickness_px)?;
+        return dry_run_response(&state, hwnd, &strokes, clipped);
+    }
+
+    // Pencil unless told otherwise, as for draw_polyline
+    select_stroke_tool(&state, hwnd, ellipse_params.tool.as_deref().unwrap_or("pencil"), preset.as_ref())?;
+    if let Some(color) = &ellipse_params.color {
+        set_stroke_color(&state, hwnd, color, button)?;
+    }
+    set_stroke_thickness(&state, hwnd, ellipse_params.thickness, ellipse_params.thickness_px)?;
+
+    for stroke in &strokes {
+        state.backend.draw_polyline(hwnd, stroke, button)?;
+    }
+    Ok(clipped.map_or_else(success_response, clipped_response))
+}
+
+// Handler for the 'draw_gradient' method. The bands are planned on the
+// server (see gradient.rs) and drawn with the pencil, one stroke each
+pub async fn handle_draw_gradient(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling draw_gradient request...");
+
+    // Deserialize parameters
+    let gradient_params: DrawGradientParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_gradient".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let rect = (gradient_params.x, gradient_params.y, gradient_params.width, gradient_params.height);
+    let gradient = crate::gradient::plan(
+        rect,
+        &gradient_params.start_color,
+        &gradient_params.end_color,
+        gradient_params.direction.unwrap_or_default(),
+        gradient_params.steps.unwrap_or(crate::gradient::DEFAULT_GRADIENT_STEPS),
+    )?;
+
+    if gradient_params.dry_run == Some(true) {
+        let strokes: Vec<_> = gradient.bands.into_iter().map(|band| band.points).collect();
+        return dry_run_response(&state, hwnd, &strokes, None);
+    }
+
+    let button = gradient_params.button.unwrap_or_default();
+    state.backend.select_tool(hwnd, "pencil")?;
+    set_stroke_thickness(&state, hwnd, None, Some(gradient.thickness_px))?;
+    for band in &gradient.bands {
+        set_stroke_color(&state, hwnd, &band.color, button)?;
+        state.backend.draw_polyline(hwnd, &band.points, button)?;
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "bands": gradient.bands.len(),
+            "thickness_px": gradient.thickness_px
+        }
+    }))
+}
+
+// Handler for the 'fill_pattern' method. The pattern is planned and clipped
+// to the region on the server (see pattern.rs) and drawn with the pencil
+pub async fn handle_fill_pattern(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling fill_pattern request...");
+
+    // Deserialize parameters
+    let pattern_params: FillPatternParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for fill_pattern".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let p = &pattern_params;
+    let region = match (p.x, p.y, p.width, p.height, &p.points) {
+        (Some(x), Some(y), Some(width), Some(height), None) => crate::pattern::Region::Rect(x, y, width, height),
+        (None, None, None, None, Some(points)) => crate::pattern::Region::Polygon(points.iter().map(|point| (point.x, point.y)).collect()),
+        _ => return Err(MspMcpError::InvalidParameters(
+            "Give the region as x, y, width and height, or as points, not both".to_string())),
+    };
+    let strokes = crate::pattern::plan(
+        &region, pattern_params.pattern, pattern_params.spacing.unwrap_or(crate::pattern::DEFAULT_PATTERN_SPACING))?;
+
+    if pattern_params.dry_run == Some(true) {
+        check_stroke(pattern_params.color.as_deref(), None, None)?;
+        let strokes: Vec<_> = strokes.into_iter().map(|stroke| stroke.points).collect();
+        return dry_run_response(&state, hwnd, &strokes, None);
+    }
+
+    let button = pattern_params.button.unwrap_or_default();
+    state.backend.select_tool(hwnd, "pencil")?;
+    if let Some(color) = &pattern_params.color {
+        set_stroke_color(&state, hwnd, color, button)?;
+    }
+    let mut thickness = None;
+    for stroke in &strokes {
+        if thickness != Some(stroke.thickness_px) {
+            set_stroke_thickness(&state, hwnd, None, Some(stroke.thickness_px))?;
+            thickness = Some(stroke.thickness_px);
+        }
+        state.backend.draw_polyline(hwnd, &stroke.points, button)?;
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "strokes": strokes.len()
+        }
+    }))
+}
+
+// Handler for the 'draw_glyph_text' method. The text is drawn from the
+// font's outlines with the pencil, not with Paint's text tool
+pub async fn handle_draw_glyph_text(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling draw_glyph_text request...");
+
+    // Deserialize parameters
+    let text_params: DrawGlyphTextParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for draw_glyph_text".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    if text_params.text.trim().is_empty() {
+        return Err(MspMcpError::InvalidParameters("text is empty".to_string()));
+    }
+    let fill = text_params.fill.unwrap_or(true);
+    if fill && text_params.thickness_px.is_some() {
+        return Err(MspMcpError::InvalidParameters("thickness_px only applies with \"fill\": false".to_string()));
+    }
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let glyphs = crate::glyphs::outline_text(
+        &text_params.font, &text_params.text, text_params.size_px, (text_params.x, text_params.y), &state.config()?)?;
+    let (strokes, thickness_px) = if fill {
+        (crate::glyphs::fill_strokes(&glyphs.contours)?, Some(1))
+    } else {
+        (crate::glyphs::outline_strokes(&glyphs.contours)?, text_params.thickness_px)
+    };
+
+    if text_params.dry_run == Some(true) {
+        check_stroke(text_params.color.as_deref(), None, thickness_px)?;
+        return dry_run_response(&state, hwnd, &strokes, None);
+    }
+
+    let button = text_params.button.unwrap_or_default();
+    state.backend.select_tool(hwnd, "pencil")?;
+    if let Some(color) = &text_params.color {
+        set_stroke_color(&state, hwnd, color, button)?;
+    }
+    set_stroke_thickness(&state, hwnd, None, thickness_px)?;
+    for stroke in &strokes {
+        state.backend.draw_polyline(hwnd, stroke, button)?;
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "strokes": strokes.len(),
+            "width": glyphs.width,
+            "height": glyphs.height
+        }
+    }))
+}
+
+// Handler for the 'pen_move_to' method. Only the server is involved until pen_stroke
+pub async fn handle_pen_move_to(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling pen_move_to request...");
+
+    let point_params: PenPointParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for pen_move_to".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    let mut pen = state.pen.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock pen state".to_string()))?;
+    pen.move_to((point_params.x, point_params.y))?;
+    Ok(pen_response(&pen))
+}
+
+// Handler for the 'pen_line_to' method
+pub async fn handle_pen_line_to(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling pen_line_to request...");
+
+    let point_params: PenPointParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for pen_line_to".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    let mut pen = state.pen.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock pen state".to_string()))?;
+    pen.line_to((point_params.x, point_params.y))?;
+    Ok(pen_response(&pen))
+}
+
+// Handler for the 'pen_close' method
+pub async fn handle_pen_close(
+    state: PaintServerState,
+    _params: Option<Value>, // No parameters needed
+) -> Result<Value> {
+    info!("Handling pen_close request...");
+
+    let mut pen = state.pen.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock pen state".to_string()))?;
+    pen.close()?;
+    Ok(pen_response(&pen))
+}
+
+// Success response for the pen methods, with the pen's current point
+fn pen_response(pen: &crate::pen::PenState) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "current": pen.current().map(|(x, y)| json!({"x": x, "y": y}))
+        }
+    })
+}
+
+// Handler for the 'pen_stroke' method. Draws the path gathered since the
+// last stroke, one drag per connected subpath, and empties it
+pub async fn handle_pen_stroke(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling pen_stroke request...");
+
+    let mut stroke_params: PenStrokeParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => PenStrokeParams { color: None, thickness: None, thickness_px: None, tool: None, button: None, preset: None },
+    };
+    let preset = stroke_preset(&state, stroke_params.preset.as_deref())?;
+    if let Some(preset) = &preset {
+        apply_stroke_preset(preset, &mut stroke_params.color, stroke_params.thickness, &mut stroke_params.thickness_px);
+        stroke_params.tool = stroke_params.tool.or_else(|| preset.tool.clone());
+    }
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let strokes = state.pen.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock pen state".to_string()))?
+        .take_strokes();
+    if strokes.is_empty() {
+        return Err(MspMcpError::InvalidParameters("The path is empty; build it with pen_move_to and pen_line_to".to_string()));
+    }
+
+    // Pencil unless told otherwise, as for draw_polyline
+    select_stroke_tool(&state, hwnd, stroke_params.tool.as_deref().unwrap_or("pencil"), preset.as_ref())?;
+    let button = stroke_params.button.unwrap_or_default();
+    if let Some(color) = &stroke_params.color {
+        set_stroke_color(&state, hwnd, color, button)?;
+    }
+    set_stroke_thickness(&state, hwnd, stroke_params.thickness, stroke_params.thickness_px)?;
+
+    for stroke in &strokes {
+        match stroke.as_slice() {
+            [(x, y)] => state.backend.draw_pixel(hwnd, *x, *y, button)?,
+            points => state.backend.draw_polyline(hwnd, points, button)?,
+        }
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "strokes": strokes.len(),
+            "points": strokes.iter().map(Vec::len).sum::<usize>()
+        }
+    }))
+}
+
+// Handler for the 'clear_canvas' method
+pub async fn handle_clear_canvas(
+    state: PaintServerState,
+    _params: Option<Value>, // No parameters needed
+) -> Result<Value> {
+    info!("Handling clear_canvas request...");
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Clear the canvas
+    state.backend.clear_canvas(hwnd)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'select_region' method
+pub async fn handle_select_region(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling select_region request...");
+
+    // Deserialize parameters - reusing DrawLineParams since it has the same structure
+    let select_params: DrawLineParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for select_region".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Select the region
+    state.backend.select_region(
+        hwnd,
+        select_params.start_x, select_params.start_y,
+        select_params.end_x, select_params.end_y
+    )?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'copy_selection' method
+pub async fn handle_copy_selection(
+    state: PaintServerState,
+    _params: Option<Value>, // No parameters needed
+) -> Result<Value> {
+    info!("Handling copy_selection request...");
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Copy the selection
+    state.backend.copy_selection(hwnd)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'paste' method
+pub async fn handle_paste(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling paste request...");
+
+    // Deserialize parameters - we just need x, y coordinates
+    let paste_params: DrawPixelParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for paste".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Paste at the specified position
+    state.backend.paste_at(hwnd, paste_params.x, paste_params.y)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'stamp_image' method. The image is put on the clipboard
+// once and pasted and committed at each position
+pub async fn handle_stamp_image(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling stamp_image request...");
+
+    // Deserialize parameters
+    let stamp_params: StampImageParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for stamp_image".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    if stamp_params.positions.is_empty() || stamp_params.positions.len() > MAX_STAMP_POSITIONS {
+        return Err(MspMcpError::InvalidParameters(
+            format!("positions must hold 1 to {} positions, got {}", MAX_STAMP_POSITIONS, stamp_params.positions.len())));
+    }
+    let scale = stamp_params.scale.unwrap_or(1.0);
+    if !scale.is_finite() || scale <= 0.0 {
+        return Err(MspMcpError::InvalidParameters(format!("scale must be more than 0, got {}", scale)));
+    }
+
+    // The same limits as fetch_image, checked before anything is decoded
+    let config = state.config()?;
+    let encoded = stamp_params.image.trim();
+    let size_bytes = encoded.len() as u64 / 4 * 3;
+    if size_bytes > config.max_fetch_bytes {
+        return Err(MspMcpError::PayloadTooLarge {
+            message: format!("The image is about {} bytes", size_bytes),
+            limit: config.max_fetch_bytes,
+            unit: "bytes",
+        });
+    }
+    use base64::Engine;
+    let bytes = base64::engine::general_purpose::STANDARD.decode(encoded)
+        .map_err(|e| MspMcpError::InvalidImageFormat(format!("The image is not valid base64: {}", e)))?;
+    let decode_error = |e: image::ImageError| MspMcpError::InvalidImageFormat(format!("Failed to read the image: {}", e));
+    let (width, height) = image::io::Reader::new(std::io::Cursor::new(&bytes))
+        .with_guessed_format()?
+        .into_dimensions()
+        .map_err(decode_error)?;
+    let pixels = width as u64 * height as u64;
+    if pixels > config.max_capture_pixels() {
+        return Err(MspMcpError::PayloadTooLarge {
+            message: format!("The {}x{} image has {} pixels", width, height, pixels),
+            limit: config.max_capture_pixels(),
+            unit: "pixels",
+        });
+    }
+    // Sized the way CapturedImage::scaled rounds, before it allocates anything
+    let side = |size: u32| (size as f64 * scale).round().max(1.0);
+    if side(width) > MAX_STAMP_SIDE_PX as f64 || side(height) > MAX_STAMP_SIDE_PX as f64 {
+        return Err(MspMcpError::InvalidParameters(format!(
+            "The stamp would be {}x{}; stamps can be at most {} pixels across", side(width), side(height), MAX_STAMP_SIDE_PX)));
+    }
+
+    let decoded = image::load_from_memory(&bytes).map_err(decode_error)?.to_rgba8();
+    let original = crate::capture::CapturedImage { width: decoded.width(), height: decoded.height(), rgba: decoded.into_raw() };
+    // Paint pastes opaque pixels, so transparency is settled on the server
+    let stamp = original.scaled(scale).flattened();
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    state.backend.set_clipboard_image(&stamp)?;
+    for position in &stamp_params.positions {
+        state.backend.stamp_clipboard_image(hwnd, position.x, position.y, stamp.width, stamp.height)?;
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "stamps": stamp_params.positions.len(),
+            "width": stamp.width,
+            "height": stamp.height
+        }
+    }))
+}
+
+// Handler for the 'rotate_selection' method
+pub async fn handle_rotate_selection(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling rotate_selection request...");
+
+    // Deserialize parameters
+    let rotate_params: RotateSelectionParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for rotate_selection".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    if ![90, 180, 270].contains(&rotate_params.angle) {
+        return Err(MspMcpError::InvalidParameters(format!("Rotation must be 90, 180 or 270 degrees, got {}", rotate_params.angle)));
+    }
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Rotate only what is selected
+    state.backend.rotate_selection(hwnd, rotate_params.angle)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'flip_selection' method
+pub async fn handle_flip_selection(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling flip_selection request...");
+
+    // Deserialize parameters
+    let flip_params: FlipSelectionParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for flip_selection".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    if !matches!(flip_params.direction.as_str(), "horizontal" | "vertical") {
+        return Err(MspMcpError::InvalidParameters(format!("Flip direction must be 'horizontal' or 'vertical', got '{}'", flip_params.direction)));
+    }
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Mirror only what is selected
+    state.backend.flip_selection(hwnd, &flip_params.direction)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'invert_colors' method
+pub async fn handle_invert_colors(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling invert_colors request...");
+
+    // Deserialize parameters; the whole canvas is inverted by default
+    let invert_params: InvertColorsParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => InvertColorsParams::default(),
+    };
+    let selection_only = match invert_params.scope.as_deref() {
+        None | Some("canvas") => false,
+        Some("selection") => true,
+        Some(other) => return Err(MspMcpError::InvalidParameters(format!("Scope must be 'canvas' or 'selection', got '{}'", other))),
+    };
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Invert and confirm it took effect
+    state.backend.invert_colors(hwnd, selection_only)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'add_text' method
+pub async fn handle_add_text(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling add_text request...");
+
+    // Deserialize parameters
+    let text_params: AddTextParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for add_text".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Add text to the canvas
+    state.backend.add_text(hwnd, &text_params)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'new_document' method
+pub async fn handle_new_document(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling new_document request...");
+
+    // Deserialize parameters; Paint's save prompt fails the call by default
+    let new_params: NewDocumentParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => NewDocumentParams::default(),
+    };
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let document = replace_document(&state, hwnd, new_params.unsaved_changes.unwrap_or_default())?;
+    let (width, height) = state.backend.canvas_dimensions(hwnd)?;
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "canvas_width": width,
+            "canvas_height": height,
+            "document": document
+        }
+    }))
+}
+
+// Opens a new document in place of the current one for new_document and
+// close_document, and starts tracking it afresh
+fn replace_document(state: &PaintServerState, hwnd: HWND, unsaved_changes: crate::protocol::UnsavedChanges) -> Result<crate::document::DocumentState> {
+    state.backend.new_document(hwnd, unsaved_changes)?;
+
+    // Nothing known about the old document (its path, its last save) carries over
+    let mut document = state.document.lock().map_err(|_| 
+        MspMcpError::General("Failed to lock document state".to_string()))?;
+    *document = crate::document::DocumentState::default();
+    document.update_from_title(&state.backend.window_title(hwnd));
+    Ok(document.clone())
+}
+
+// Handler for the 'close_document' method. Paint always has a document
+// open, so closing one leaves a new untitled document in its place.
+pub async fn handle_close_document(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling close_document request...");
+
+    // Deserialize parameters; Paint's save prompt fails the call by default
+    let close_params: CloseParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => CloseParams::default(),
+    };
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let closed = state.document.lock().map_err(|_| 
+        MspMcpError::General("Failed to lock document state".to_string()))?.document_name.clone();
+    let document = replace_document(&state, hwnd, close_params.unsaved_changes.unwrap_or_default())?;
+    info!("Closed document {:?}", closed);
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "closed": closed,
+            "document": document
+        }
+    }))
+}
+
+// Handler for the 'quit_paint' method. Closes Paint, waits for the process
+// to exit and forgets the window, document and anything else tracked for it.
+pub async fn handle_quit_paint(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling quit_paint request...");
+
+    // Deserialize parameters; Paint's save prompt fails the call by default
+    let quit_params: CloseParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => CloseParams::default(),
+    };
+    let unsaved_changes = quit_params.unsaved_changes.unwrap_or_default();
+    let timeout = std::time::Duration::from_millis(quit_params.timeout_ms.unwrap_or(crate::protocol::DEFAULT_QUIT_TIMEOUT_MS));
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+    let pid = *state.paint_pid.lock().map_err(|_| 
+        MspMcpError::General("Failed to lock PID state".to_string()))?;
+
+    match (state.backend.quit_paint(hwnd, unsaved_changes, timeout), pid) {
+        (Ok(()), _) => {}
+        // The changes were to be dropped anyway, so don't leave Paint hanging
+        (Err(MspMcpError::OperationTimeout(message)), Some(pid)) if unsaved_changes == crate::protocol::UnsavedChanges::Discard => {
+            warn!("{}; terminating it", message);
+            state.backend.terminate(pid)?;
+        }
+        (Err(e), _) => return Err(e),
+    }
+
+    state.clear_paint_window()?;
+    info!("Paint has exited; cleared the session's window state");
+    Ok(success_response())
+}
+
+// Handler for the 'create_canvas' method
+pub async fn handle_create_canvas(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling create_canvas request...");
+
+    // Deserialize parameters
+    let canvas_params: CreateCanvasParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for create_canvas".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Create a new canvas
+    state.backend.create_canvas(
+        hwnd,
+        canvas_params.width,
+        canvas_params.height,
+        canvas_params.background_color.as_deref(),
+        canvas_params.unsaved_changes.unwrap_or_default()
+    )?;
+
+    // Get the updated canvas dimensions
+    let (width, height) = state.backend.canvas_dimensions(hwnd)?;
+
+    // Return success response with the new dimensions
+    Ok(json!({
+        "status": "success",
+        "canvas_width": width,
+        "canvas_height": height
+    }))
+}
+
+// Handler for the 'duplicate_canvas' method. The whole image goes through
+// the clipboard into a canvas of the same size in a second Paint window, so
+// an agent can keep a checkpoint or try something out on a copy
+pub async fn handle_duplicate_canvas(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling duplicate_canvas request...");
+
+    let duplicate_params: DuplicateCanvasParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => DuplicateCanvasParams { target_hwnd: None, unsaved_changes: None, track_copy: None },
+    };
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    if let Some(target) = duplicate_params.target_hwnd {
+        if target == hwnd {
+            return Err(MspMcpError::InvalidParameters("target_hwnd is the window being copied".to_string()));
+        }
+        if !state.backend.is_window_owned_by(target, state.backend.window_pid(target)) {
+            return Err(MspMcpError::WindowNotFound);
+        }
+    }
+
+    let image = state.backend.copy_canvas_image(hwnd)?;
+    let target = match duplicate_params.target_hwnd {
+        Some(target) => target,
+        None => state.backend.launch_paint_window()?,
+    };
+    state.backend.create_canvas(target, image.width, image.height, None, duplicate_params.unsaved_changes.unwrap_or_default())?;
+    state.backend.stamp_clipboard_image(target, 0, 0, image.width, image.height)?;
+
+    let tracked = if duplicate_params.track_copy == Some(true) {
+        state.set_paint_window(target)?;
+        target
+    } else {
+        state.backend.activate(hwnd)?;
+        hwnd
+    };
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "hwnd": target,
+            "pid": state.backend.window_pid(target),
+            "width": image.width,
+            "height": image.height,
+            "tracked_hwnd": tracked
+        }
+    }))
+}
+
+// Handler for the 'set_image_properties' method
+pub async fn handle_set_image_properties(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_image_properties request...");
+
+    // Deserialize parameters
+    let props: SetImagePropertiesParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_image_properties".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    if props.width.is_none() && props.height.is_none() && props.color_mode.is_none() {
+        return Err(MspMcpError::InvalidParameters("Nothing to change; pass width, height or color_mode".to_string()));
+    }
+    let units = props.units.unwrap_or_default();
+    for (name, value) in [("width", props.width), ("height", props.height)] {
+        let Some(value) = value else { continue };
+        if units == ImageUnits::Pixels && value.fract() != 0.0 {
+            return Err(MspMcpError::InvalidParameters(format!("{} must be a whole number of pixels, got {}", name, value)));
+        }
+        let pixels = units.to_pixels(value);
+        if !(1.0..=MAX_IMAGE_SIDE_PX as f64).contains(&pixels) {
+            return Err(MspMcpError::InvalidParameters(format!(
+                "{} must come to between 1 and {} pixels, got {} {:?}", name, MAX_IMAGE_SIDE_PX, value, units)));
+        }
+    }
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    state.backend.set_image_properties(hwnd, &props)?;
+
+    // Report the size Paint ended up with
+    let (width, height) = state.backend.canvas_dimensions(hwnd)?;
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "canvas_width": width,
+            "canvas_height": height
+        }
+    }))
+}
+
+// Handler for the 'set_background' method
+pub async fn handle_set_background(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_background request...");
+
+    // Deserialize parameters
+    let background_params: SetBackgroundParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_background".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Fill the whole canvas, which leaves the color in Color 2
+    let result = state.backend.set_background(hwnd, &background_params.color);
+    track_color_slot(&state, MouseButton::Right, &background_params.color, result)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+/// Checks a save path before any dialog is opened (see
+/// `fs_guard::check_path`) and makes sure the parent directory exists, creating it when
+/// `create_dirs` is set.
+fn resolve_save_path(file_path: &str, create_dirs: bool, config: &crate::config::ServerConfig) -> Result<std::path::PathBuf> {
+    let path = crate::fs_guard::check_path(file_path, config)?;
+    let parent = path.parent().unwrap_or(&path);
+    if !parent.is_dir() {
+        if !create_dirs {
+            return Err(MspMcpError::FileNotFound(format!(
+                "Directory {} does not exist; pass create_dirs: true to create it", parent.display())));
+        }
+        info!("Creating directory {}", parent.display());
+        std::fs::create_dir_all(parent).map_err(|e| match e.kind() {
+            std::io::ErrorKind::PermissionDenied => MspMcpError::FilePermissionDenied(parent.display().to_string()),
+            _ => MspMcpError::IoError(e),
+        })?;
+    }
+    Ok(path)
+}
+
+/// How long to wait for a saved file to show up and stop growing.
+const SAVED_FILE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Polls until the saved file exists, is non-empty and keeps the same size
+/// across two checks, then returns its path, size and modified time.
+/// Paint appends an extension when the name has none, so those are tried too.
+fn wait_for_saved_file(file_path: &str, format: &str) -> Result<(std::path::PathBuf, u64, std::time::SystemTime)> {
+    let path = std::path::PathBuf::from(file_path);
+    let mut candidates = vec![path.clone()];
+    if path.extension().is_none() {
+        let extension = if format == "jpeg" { "jpg" } else { format };
+        candidates.push(path.with_extension(extension));
+    }
+
+    let started = crate::clock::now();
+    let mut last_size = None;
+    loop {
+        let found = candidates.iter()
+            .find_map(|candidate| std::fs::metadata(candidate).ok().map(|meta| (candidate, meta)));
+        if let Some((candidate, meta)) = found {
+            let size = meta.len();
+            if size > 0 && last_size == Some(size) {
+                let modified = meta.modified().map_err(MspMcpError::IoError)?;
+                return Ok((candidate.clone(), size, modified));
+            }
+            last_size = Some(size);
+        }
+        if crate::clock::now() - started > SAVED_FILE_TIMEOUT {
+            return Err(MspMcpError::OperationTimeout(format!("{} was not written to disk", file_path)));
+        }
+        crate::clock::sleep(std::time::Duration::from_millis(250));
+    }
+}
+
+// Handler for the 'save_canvas' method
+pub async fn handle_save_canvas(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling save_canvas request...");
+    save_document(state, params, "save_canvas").await
+}
+
+// Handler for the 'save_as' method: like save_canvas, but the file type is
+// always picked in the dialog and GIF is allowed
+pub async fn handle_save_as(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling save_as request...");
+    save_document(state, params, "save_as").await
+}
+
+// Saves through the Save As dialog for save_canvas and save_as
+async fn save_document(
+    state: PaintServerState,
+    params: Option<Value>,
+    method: &str,
+) -> Result<Value> {
+    // Deserialize parameters
+    let mut save_params: SaveCanvasParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters(format!("Missing params for {}", method)))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    let formats = if method == "save_as" { crate::protocol::SAVE_AS_FORMATS } else { crate::protocol::SAVE_FORMATS };
+    if !formats.contains(&save_params.format.as_str()) {
+        return Err(MspMcpError::InvalidImageFormat(format!("Format must be one of {}, got '{}'",
+            formats.join(", "), save_params.format)));
+    }
+    if let Some(quality) = save_params.jpeg_quality {
+        if save_params.format != "jpeg" || !(1..=100).contains(&quality) {
+            return Err(MspMcpError::InvalidParameters("jpeg_quality must be 1-100 and only applies to jpeg".to_string()));
+        }
+    }
+    save_params.file_path = resolve_save_path(&save_params.file_path, save_params.create_dirs, &state.config()?)?
+        .display().to_string();
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Save through Paint's Save As dialog
+    if method == "save_as" {
+        state.backend.save_as(hwnd, &save_params)?;
+    } else {
+        state.backend.save_canvas(hwnd, &save_params)?;
+    }
+
+    // Don't report success until the file is really on disk
+    let (saved_path, size_bytes, modified) = wait_for_saved_file(&save_params.file_path, &save_params.format)?;
+    let modified_unix_ms = modified.duration_since(std::time::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    info!("Saved {} ({} bytes)", saved_path.display(), size_bytes);
+
+    // The title only shows the file name, so remember the whole path
+    {
+        let mut document = state.document.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock document state".to_string()))?;
+        document.record_save(&saved_path.display().to_string(), modified_unix_ms);
+        document.update_from_title(&state.backend.window_title(hwnd));
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "file_path": saved_path.display().to_string(),
+            "size_bytes": size_bytes,
+            "modified_unix_ms": modified_unix_ms
+        }
+    }))
+}
+
+// Handler for the 'get_document_info' method
+pub async fn handle_get_document_info(
+    state: PaintServerState,
+    _params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling get_document_info request...");
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    let document = {
+        let mut document = state.document.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock document state".to_string()))?;
+        document.update_from_title(&state.backend.window_title(hwnd));
+        document.clone()
+    };
+    let (width, height) = state.backend.canvas_dimensions(hwnd)?;
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": {
+            "title": document.title,
+            "document_name": document.document_name,
+            "file_path": document.file_path,
+            "has_unsaved_changes": document.has_unsaved_changes,
+            "last_saved_unix_ms": document.last_saved_unix_ms,
+            "width": width,
+            "height": height
+        }
+    }))
+}
+
+// Handler for the 'fetch_image' method
+pub async fn handle_fetch_image(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling fetch_image request...");
+
+    // Deserialize parameters
+    let fetch_params: FetchImageParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for fetch_image".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    let config = state.config()?;
+    let path = crate::fs_guard::check_path(&fetch_params.file_path, &config)?;
+    if !path.is_file() {
+        return Err(MspMcpError::FileNotFound(path.display().to_string()));
+    }
+    let size_bytes = std::fs::metadata(&path)?.len();
+    let within_limit = size_bytes <= config.max_fetch_bytes;
+    if !within_limit && fetch_params.preview_max_px.is_none() {
+        return Err(MspMcpError::PayloadTooLarge {
+            message: format!("{} is {} bytes; pass preview_max_px for a downscaled copy", path.display(), size_bytes),
+            limit: config.max_fetch_bytes,
+            unit: "bytes",
+        });
+    }
+    if fetch_params.preview_max_px == Some(0) {
+        return Err(MspMcpError::InvalidParameters("preview_max_px must be at least 1".to_string()));
+    }
+    let bytes = std::fs::read(&path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::PermissionDenied => MspMcpError::FilePermissionDenied(path.display().to_string()),
+        _ => MspMcpError::IoError(e),
+    })?;
+
+    let format = match image::guess_format(&bytes) {
+        Ok(image::ImageFormat::Png) => "png",
+        Ok(image::ImageFormat::Jpeg) => "jpeg",
+        Ok(image::ImageFormat::Bmp) => "bmp",
+        Ok(image::ImageFormat::Gif) => "gif",
+        _ => return Err(MspMcpError::InvalidImageFormat(format!("{} is not a PNG, JPEG, BMP or GIF image", path.display()))),
+    };
+    let decode_error = |e: image::ImageError| MspMcpError::InvalidImageFormat(format!("Failed to read {}: {}", path.display(), e));
+    let (width, height) = image::io::Reader::new(std::io::Cursor::new(&bytes))
+        .with_guessed_format()?
+        .into_dimensions()
+        .map_err(decode_error)?;
+
+    let mut result = json!({
+        "file_path": path.display().to_string(),
+        "format": format,
+        "width": width,
+        "height": height,
+        "size_bytes": bytes.len()
+    });
+
+    use base64::Engine;
+    if within_limit {
+        result["data"] = json!(base64::engine::general_purpose::STANDARD.encode(&bytes));
+    } else if let Some(max_px) = fetch_params.preview_max_px {
+        // Too big to send as is: send a smaller PNG instead
+        let preview = image::load_from_memory(&bytes).map_err(decode_error)?
+            .thumbnail(max_px, max_px)
+            .to_rgba8();
+        let preview = crate::capture::CapturedImage { width: preview.width(), height: preview.height(), rgba: preview.into_raw() };
+        let png = crate::capture::encode_png(&preview)?;
+        info!("{} is {} bytes; sending a {}x{} preview", path.display(), bytes.len(), preview.width, preview.height);
+        result["data"] = json!(base64::engine::general_purpose::STANDARD.encode(&png));
+        result["preview"] = json!({ "format": "png", "width": preview.width, "height": preview.height });
+    }
+
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": result
+    }))
+}
+
+// Handler for the 'add_layer' method
+pub async fn handle_add_layer(
+    state: PaintServerState,
+    _params: Option<Value>, // No parameters needed
+) -> Result<Value> {
+    info!("Handling add_layer request...");
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Add a layer above the selected one
+    state.backend.add_layer(hwnd)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'select_layer' method
+pub async fn handle_select_layer(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling select_layer request...");
+
+    // Deserialize parameters
+    let layer_params: LayerIndexParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for select_layer".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Make it the active layer
+    state.backend.select_layer(hwnd, layer_params.index)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'set_layer_visibility' method
+pub async fn handle_set_layer_visibility(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling set_layer_visibility request...");
+
+    // Deserialize parameters
+    let visibility_params: SetLayerVisibilityParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for set_layer_visibility".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Show or hide the layer
+    state.backend.set_layer_visibility(hwnd, visibility_params.index, visibility_params.visible)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'merge_layers' method
+pub async fn handle_merge_layers(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling merge_layers request...");
+
+    // Deserialize parameters
+    let layer_params: LayerIndexParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for merge_layers".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Merge the layer into the one below it
+    state.backend.merge_layer_down(hwnd, layer_params.index)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'delete_layer' method
+pub async fn handle_delete_layer(
+    state: PaintServerState,
+    params: Option<Value>,
+) -> Result<Value> {
+    info!("Handling delete_layer request...");
+
+    // Deserialize parameters
+    let layer_params: LayerIndexParams = params
+        .ok_or_else(|| MspMcpError::InvalidParameters("Missing params for delete_layer".to_string()))
+        .and_then(|p| serde_json::from_value(p).map_err(MspMcpError::JsonError))?;
+
+    // Get the Paint window handle from state
+    let hwnd = {
+        let hwnd_state = state.paint_hwnd.lock().map_err(|_| 
+            MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        
+        match *hwnd_state {
+            Some(hwnd) => hwnd,
+            None => return Err(MspMcpError::WindowNotFound),
+        }
+    };
+
+    // Delete the layer
+    state.backend.delete_layer(hwnd, layer_params.index)?;
+
+    // Return success response
+    Ok(success_response())
+}
+
+// Handler for the 'initialize' method
+pub async fn handle_initialize(
+    state: PaintServerState,
+    params: Option<Value>, // Optional; hosts send protocolVersion/capabilities/clientInfo
+) -> Result<Value> {
+    info!("Handling initialize request...");
+
+    // Deserialize parameters (all optional)
+    let init_params: InitializeParams = match params {
+        Some(p) => serde_json::from_value(p).map_err(MspMcpError::JsonError)?,
+        None => InitializeParams::default(),
+    };
+
+    if let Some(client_info) = &init_params.client_info {
+        info!("Client: {} {}", client_info.name, client_info.version.as_deref().unwrap_or(""));
+        state.set_client_id(&client_info.name)?;
+    }
+
+    // Agree on versions before touching Paint
+    let protocol_version = state.negotiate_protocol_version(init_params.protocol_version.as_deref())?;
+    let mcp_protocol_version = crate::protocol::negotiate_mcp_protocol_version(init_params.mcp_protocol_version.as_deref());
+    
+    // Find or launch Paint
+    let hwnd = match state.backend.find_or_launch_paint() {
+        Ok(h) => {
+            info!("Found or launched Paint window: HWND={}", h);
+            h
+        },
+        Err(e) => {
+            error!("Failed to find or launch Paint: {}", e);
+            return Err(e);
+        }
+    };
+    
+    // Store HWND (and its owning PID) in state
+    state.set_paint_window(hwnd)?;
+    info!("Stored Paint HWND in server state");
+    
+    // Return success
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be overridden with actual request ID later
+        "result": {
+            "initialized": true,
+            "protocolVersion": mcp_protocol_version,
+            "protocol_version": protocol_version,
+            "capabilities": crate::protocol::server_capabilities(),
+            "serverInfo": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION")
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{MockBackend, MOCK_HWND};
+    use crate::config::ServerConfig;
+    use std::sync::Arc;
+
+    fn mock_state() -> (PaintServerState, Arc<MockBackend>) {
+        let backend = Arc::new(MockBackend::default());
+        let state = PaintServerState::with_backend(ServerConfig::default(), backend.clone());
+        (state, backend)
+    }
+
+    #[tokio::test]
+    async fn test_initialize_tracks_window() {
+        let (state, backend) = mock_state();
+        handle_initialize(state.clone(), None).await.unwrap();
+        assert_eq!(*state.paint_hwnd.lock().unwrap(), Some(MOCK_HWND));
+        assert_eq!(backend.take_calls(), vec!["find_or_launch_paint"]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_keeps_the_initialized_protocol_version() {
+        let (state, _backend) = mock_state();
+        handle_initialize(state.clone(), Some(json!({"protocol_version": "1.0"}))).await.unwrap();
+
+        let params = json!({"client_id": "agent", "client_name": "Agent"});
+        handle_connect(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(state.protocol_version().unwrap(), "1.0");
+
+        let params = json!({"client_id": "agent", "client_name": "Agent", "protocol_version": "1.1"});
+        let response = handle_connect(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(state.protocol_version().unwrap(), "1.1");
+        assert_eq!(response["result"]["protocol_version"], "1.1");
+    }
+
+    #[tokio::test]
+    async fn test_draw_line_sets_color_and_thickness_first() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let params = json!({"start_x": 1, "start_y": 2, "end_x": 30, "end_y": 40, "color": "#FF0000", "thickness": 3});
+        handle_draw_line(state, Some(params)).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["set_color #FF0000", "set_thickness 3", "draw_line 1 2 30 40 Left"]);
+    }
+
+    #[tokio::test]
+    async fn test_thickness_px_sets_the_size_slider() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let params = json!({"points": [{"x": 0, "y": 0}, {"x": 9, "y": 9}], "thickness_px": 24});
+        handle_draw_polyline(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["select_tool pencil", "set_thickness_px 24", "draw_polyline [(0, 0), (9, 9)] Left"]);
+
+        let params = json!({"start_x": 1, "start_y": 2, "end_x": 30, "end_y": 40, "thickness": 2, "thickness_px": 4});
+        assert!(handle_draw_line(state, Some(params)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gradient_draws_one_stroke_per_band() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let params = json!({"x": 10, "y": 20, "width": 8, "height": 3, "start_color": "#000000", "end_color": "#0000FF", "steps": 2});
+        let response = handle_draw_gradient(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(response["result"], json!({"bands": 2, "thickness_px": 3}));
+        assert_eq!(backend.take_calls(), vec![
+            "select_tool pencil", "set_thickness_px 3",
+            "set_color #000000", "draw_polyline [(11, 21), (12, 21)] Left",
+            "set_color #0000FF", "draw_polyline [(15, 21), (16, 21)] Left",
+        ]);
+
+        let params = json!({"x": 0, "y": 0, "width": 8, "height": 3, "start_color": "#000000", "end_color": "#0000FF", "dry_run": true});
+        handle_draw_gradient(state.clone(), Some(params)).await.unwrap();
+        assert!(backend.take_calls().is_empty());
+        let params = json!({"x": 0, "y": 0, "width": 8, "height": 3, "start_color": "#000000", "end_color": "navy"});
+        assert!(handle_draw_gradient(state, Some(params)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stroke_presets_fill_in_what_the_request_leaves_out() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let params = json!({"points": [{"x": 0, "y": 0}, {"x": 5, "y": 5}], "preset": "marker", "color": "#FF0000"});
+        handle_draw_polyline(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(backend.take_calls(), vec![
+            "select_tool brush", "select_brush_type marker", "set_color #FF0000", "set_thickness_px 8",
+            "draw_polyline [(0, 0), (5, 5)] Left",
+        ]);
+
+        // A line drawn with a brush preset is a brush stroke
+        let params = json!({"start_x": 0, "start_y": 0, "end_x": 9, "end_y": 0, "preset": "sketch"});
+        handle_draw_line(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(backend.take_calls(), vec![
+            "select_tool brush", "select_brush_type natural_pencil", "set_color #595959", "set_thickness_px 2",
+            "draw_polyline [(0, 0), (9, 0)] Left",
+        ]);
+
+        let hairline = StrokePreset { thickness_px: Some(1), color: Some("#000000".to_string()), ..StrokePreset::default() };
+        state.config.lock().unwrap().stroke_presets.insert("hairline".to_string(), hairline);
+        let params = json!({"start_x": 0, "start_y": 0, "end_x": 9, "end_y": 0, "preset": "hairline", "thickness": 3});
+        handle_draw_line(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["set_color #000000", "set_thickness 3", "draw_line 0 0 9 0 Left"]);
+
+        let params = json!({"start_x": 0, "start_y": 0, "end_x": 9, "end_y": 0, "preset": "crayon"});
+        assert!(handle_draw_line(state, Some(params)).await.is_err());
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fill_pattern_sets_each_stroke_width_once() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let params = json!({"pattern": "checker", "x": 0, "y": 0, "width": 10, "height": 4, "spacing": 4, "color": "#336699"});
+        let response = handle_fill_pattern(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(response["result"]["strokes"], 2);
+        assert_eq!(backend.take_calls(), vec![
+            "select_tool pencil", "set_color #336699",
+            "set_thickness_px 2", "draw_polyline [(9, 1), (9, 3)] Left",
+            "set_thickness_px 4", "draw_polyline [(2, 2), (2, 2)] Left",
+        ]);
+
+        let params = json!({"pattern": "hatch", "points": [{"x": 0, "y": 0}, {"x": 9, "y": 0}, {"x": 0, "y": 9}], "x": 0, "y": 0, "width": 9, "height": 9});
+        assert!(handle_fill_pattern(state.clone(), Some(params)).await.is_err());
+        let params = json!({"pattern": "stripes", "x": 0, "y": 0, "width": 9, "height": 9});
+        assert!(handle_fill_pattern(state, Some(params)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stamp_image_imports_once_and_pastes_at_each_position() {
+        use base64::Engine;
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let sprite = crate::capture::CapturedImage { width: 4, height: 2, rgba: vec![255; 4 * 2 * 4] };
+        let image = base64::engine::general_purpose::STANDARD.encode(crate::capture::encode_png(&sprite).unwrap());
+        let params = json!({"image": image, "positions": [{"x": 10, "y": 20}, {"x": 30, "y": 5}], "scale": 2.0});
+        let response = handle_stamp_image(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(response["result"]["stamps"], 2);
+        assert_eq!(backend.take_calls(), vec![
+            "set_clipboard_image 8x4",
+            "stamp_clipboard_image 10 20 8x4",
+            "stamp_clipboard_image 30 5 8x4",
+        ]);
+
+        let params = json!({"image": image, "positions": []});
+        assert!(handle_stamp_image(state.clone(), Some(params)).await.is_err());
+        let params = json!({"image": image, "positions": [{"x": 0, "y": 0}], "scale": 200.0});
+        assert!(handle_stamp_image(state.clone(), Some(params)).await.is_err());
+        // Refused from the size alone, without allocating the scaled image
+        let params = json!({"image": image, "positions": [{"x": 0, "y": 0}], "scale": 100000.0});
+        assert!(matches!(handle_stamp_image(state.clone(), Some(params)).await, Err(MspMcpError::InvalidParameters(_))));
+        state.config.lock().unwrap().max_capture_megapixels = 0.000004;
+        let params = json!({"image": image, "positions": [{"x": 0, "y": 0}]});
+        let result = handle_stamp_image(state.clone(), Some(params)).await;
+        assert!(matches!(result, Err(MspMcpError::PayloadTooLarge { unit: "pixels", .. })));
+        state.config.lock().unwrap().max_fetch_bytes = 10;
+        let params = json!({"image": image, "positions": [{"x": 0, "y": 0}]});
+        let result = handle_stamp_image(state.clone(), Some(params)).await;
+        assert!(matches!(result, Err(MspMcpError::PayloadTooLarge { limit: 10, unit: "bytes", .. })));
+        let params = json!({"image": "not an image", "positions": [{"x": 0, "y": 0}]});
+        assert!(matches!(handle_stamp_image(state, Some(params)).await, Err(MspMcpError::InvalidImageFormat(_))));
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_right_button_strokes_use_the_secondary_color() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let params = json!({"x": 5, "y": 6, "color": "#FFFFFF", "button": "right"});
+        handle_draw_pixel(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["set_secondary_color #FFFFFF", "draw_pixel 5 6 Right"]);
+
+        let params = json!({"x": 5, "y": 6, "button": "middle"});
+        assert!(matches!(handle_draw_pixel(state, Some(params)).await, Err(MspMcpError::JsonError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_custom_colors_are_added_once_and_picked_from_swatches() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let added = handle_add_custom_color(state.clone(), Some(json!({"color": "#ff8800"}))).await.unwrap();
+        assert_eq!(added["result"], json!({"color": "#FF8800", "slot": 0, "added": true, "custom_colors": ["#FF8800"]}));
+        handle_add_custom_color(state.clone(), Some(json!({"color": "#003366", "button": "right"}))).await.unwrap();
+        let again = handle_add_custom_color(state.clone(), Some(json!({"color": "#FF8800"}))).await.unwrap();
+        assert_eq!((again["result"]["slot"].clone(), again["result"]["added"].clone()), (json!(1), json!(false)));
+        assert_eq!(backend.take_calls(), vec!["add_custom_color #FF8800 Left", "add_custom_color #003366 Right", "select_custom_color 1 Left"]);
+
+        // Color 1 already holds it; set_color goes through the swatch too
+        let used = handle_use_custom_color(state.clone(), Some(json!({"slot": 1}))).await.unwrap();
+        assert_eq!(used["result"]["changed"], false);
+        handle_use_custom_color(state.clone(), Some(json!({"color": "#003366"}))).await.unwrap();
+        handle_set_color(state.clone(), Some(json!({"color": "#ff8800"}))).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["select_custom_color 0 Left", "select_custom_color 1 Left"]);
+
+        for params in [json!({"slot": 2}), json!({"color": "#123456"}), json!({"color": "#003366", "slot": 0}), json!({})] {
+            let err = handle_use_custom_color(state.clone(), Some(params)).await.unwrap_err();
+            assert!(matches!(err, MspMcpError::InvalidParameters(_)));
+        }
+
+        // Another window has its own custom colors
+        state.set_paint_window(MOCK_HWND).unwrap();
+        assert!(handle_use_custom_color(state, Some(json!({"slot": 0}))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pick_color_at_sets_the_sampled_color() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let response = handle_pick_color_at(state, Some(json!({"x": 3, "y": 4, "button": "right"}))).await.unwrap();
+        assert_eq!(response["result"], json!({"color": "#FFFFFF", "button": "right"}));
+        assert_eq!(backend.take_calls(), vec!["set_secondary_color #FFFFFF"]);
+    }
+
+    #[tokio::test]
+    async fn test_select_tool_picks_brush_type() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        handle_select_tool(state.clone(), Some(json!({"tool": "brush", "brush_type": "watercolor"}))).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["select_tool brush", "select_brush_type watercolor"]);
+
+        for params in [json!({"tool": "pencil", "brush_type": "marker"}), json!({"tool": "brush", "brush_type": "glitter"})] {
+            let err = handle_select_tool(state.clone(), Some(params)).await.unwrap_err();
+            assert!(matches!(err, MspMcpError::InvalidParameters(_)));
+        }
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_set_tool_state_makes_one_backend_call() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let params = json!({"tool": "brush", "brush_type": "marker", "color": "#112233", "thickness_px": 6, "fill_type": "solid"});
+        handle_set_tool_state(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(backend.take_calls(), vec![
+            r##"set_tool_state Some("brush") Some("marker") Some("#112233") None Some(6) Some("solid")"##,
+        ]);
+
+        for params in [json!({}), json!({"brush_type": "marker"}), json!({"tool": "brush", "brush_type": "glitter"})] {
+            let err = handle_set_tool_state(state.clone(), Some(params)).await.unwrap_err();
+            assert!(matches!(err, MspMcpError::InvalidParameters(_)));
+        }
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_palette_colors_skip_slots_that_already_hold_them() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let defined = handle_define_palette(state.clone(), Some(json!({"name": "sea", "colors": ["#003366", "#66ccff"]}))).await.unwrap();
+        assert_eq!(defined["result"]["colors"], json!(["#003366", "#66CCFF"]));
+
+        let used = handle_use_palette_color(state.clone(), Some(json!({"name": "sea", "index": 1}))).await.unwrap();
+        assert_eq!((used["result"]["color"].as_str(), used["result"]["changed"].as_bool()), (Some("#66CCFF"), Some(true)));
+        let again = handle_use_palette_color(state.clone(), Some(json!({"name": "sea", "index": 1}))).await.unwrap();
+        assert_eq!(again["result"]["changed"], false);
+        handle_use_palette_color(state.clone(), Some(json!({"name": "sea", "index": 1, "button": "right"}))).await.unwrap();
+
+        // set_color changes Color 1 behind the palette's back
+        handle_set_color(state.clone(), Some(json!({"color": "#000000"}))).await.unwrap();
+        handle_use_palette_color(state.clone(), Some(json!({"name": "sea", "index": 1}))).await.unwrap();
+        assert_eq!(backend.take_calls(), vec![
+            "set_color #66CCFF", "set_secondary_color #66CCFF", "set_color #000000", "set_color #66CCFF",
+        ]);
+
+        for params in [json!({"name": "sea", "index": 2}), json!({"name": "lava", "index": 0})] {
+            let err = handle_use_palette_color(state.clone(), Some(params)).await.unwrap_err();
+            assert!(matches!(err, MspMcpError::InvalidParameters(_)));
+        }
+        assert!(handle_define_palette(state, Some(json!({"name": "sea", "colors": ["blue"]}))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_coordinate_spaces_resolve_against_the_canvas() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let params = json!({"start_x": 0.5, "start_y": 0.0, "end_x": 1.0, "end_y": 0.5, "coordinate_space": "normalized"});
+        state.handle_request("draw_line", Some(params)).await.unwrap();
+        let params = json!({"points": [{"x": 0, "y": 0}, {"x": 0.25, "y": 1}], "coordinate_space": "normalized"});
+        state.handle_request("draw_polyline", Some(params)).await.unwrap();
+        state.handle_request("draw_pixel", Some(json!({"x": 7, "y": 9, "coordinate_space": "pixels"}))).await.unwrap();
+        // The mock's viewport starts 120 pixels down the screen
+        state.handle_request("draw_pixel", Some(json!({"x": 30, "y": 150, "coordinate_space": "screen"}))).await.unwrap();
+        assert_eq!(backend.take_calls(), vec![
+            "draw_line 400 0 799 300 Left", "select_tool pencil", "draw_polyline [(0, 0), (200, 599)] Left", "draw_pixel 7 9 Left",
+            "window_info", "draw_pixel 30 30 Left",
+        ]);
+
+        let off_canvas = json!({"x": 1.2, "y": 0.5, "coordinate_space": "normalized"});
+        assert!(matches!(state.handle_request("draw_pixel", Some(off_canvas)).await, Err(MspMcpError::InvalidParameters(_))));
+        let no_coordinates = json!({"color": "#000000", "coordinate_space": "normalized"});
+        assert!(matches!(state.handle_request("set_color", Some(no_coordinates)).await, Err(MspMcpError::InvalidParameters(_))));
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_activation_applies_only_to_input_methods() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        state.handle_request("draw_pixel", Some(json!({"x": 7, "y": 9, "activation": "never"}))).await.unwrap();
+        assert_eq!(crate::session::activation(), crate::protocol::Activation::Always);
+        assert_eq!(backend.take_calls(), vec!["draw_pixel 7 9 Left"]);
+
+        let unknown = json!({"x": 7, "y": 9, "activation": "sometimes"});
+        assert!(matches!(state.handle_request("draw_pixel", Some(unknown)).await, Err(MspMcpError::InvalidParameters(_))));
+        let read_only = json!({"activation": "never"});
+        assert!(matches!(state.handle_request("get_window_info", Some(read_only)).await, Err(MspMcpError::InvalidParameters(_))));
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_snap_rounds_drawing_positions() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let snapped = state.handle_request("set_snap", Some(json!({"grid_size": 10}))).await.unwrap();
+        assert_eq!(snapped["result"]["grid_size"], 10);
+        state.handle_request("draw_line", Some(json!({"start_x": 13, "start_y": 27, "end_x": 95, "end_y": 4}))).await.unwrap();
+        state.handle_request("draw_polyline", Some(json!({"points": [{"x": 0, "y": 0.004}, {"x": 0.52, "y": 0.5}], "coordinate_space": "normalized"}))).await.unwrap();
+        // Regions aren't drawing primitives
+        state.handle_request("select_region", Some(json!({"start_x": 13, "start_y": 27, "end_x": 24, "end_y": 38}))).await.unwrap();
+
+        let off = state.handle_request("set_snap", Some(json!({"grid_size": 0}))).await.unwrap();
+        assert!(off["result"]["grid_size"].is_null());
+        state.handle_request("draw_pixel", Some(json!({"x": 13, "y": 27}))).await.unwrap();
+        assert_eq!(backend.take_calls(), vec![
+            "draw_line 10 30 100 0 Left", "select_tool pencil", "draw_polyline [(0, 0), (420, 300)] Left",
+            "select_region 13 27 24 38", "draw_pixel 13 27 Left",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_transforms_stamp_a_figure() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let figure = json!({"start_x": 0, "start_y": 0, "end_x": 10, "end_y": 0});
+        for (x, y) in [(100, 100), (300, 50)] {
+            let pushed = state.handle_request("push_transform", Some(json!({"translate_x": x, "translate_y": y}))).await.unwrap();
+            assert_eq!(pushed["result"]["depth"], 1);
+            state.handle_request("push_transform", Some(json!({"rotate_degrees": 90, "scale_x": 2, "scale_y": 2}))).await.unwrap();
+            state.handle_request("draw_line", Some(figure.clone())).await.unwrap();
+            state.handle_request("pop_transform", None).await.unwrap();
+            let popped = state.handle_request("pop_transform", None).await.unwrap();
+            assert_eq!(popped["result"]["depth"], 0);
+        }
+        state.handle_request("draw_line", Some(figure)).await.unwrap();
+        assert_eq!(backend.take_calls(), vec![
+            "draw_line 100 100 100 120 Left", "draw_line 300 50 300 70 Left", "draw_line 0 0 10 0 Left",
+        ]);
+
+        assert!(state.handle_request("pop_transform", None).await.is_err());
+        assert!(state.handle_request("push_transform", Some(json!({"scale_x": 0}))).await.is_err());
+        state.handle_request("push_transform", Some(json!({"rotate_degrees": 30}))).await.unwrap();
+        let ellipse = json!({"shape_type": "ellipse", "start_x": 0, "start_y": 0, "end_x": 10, "end_y": 10});
+        assert!(matches!(state.handle_request("draw_shape", Some(ellipse)).await, Err(MspMcpError::InvalidParameters(_))));
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pen_strokes_the_gathered_path() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        assert!(state.handle_request("pen_line_to", Some(json!({"x": 5, "y": 5}))).await.is_err());
+        assert!(state.handle_request("pen_stroke", None).await.is_err());
+
+        state.handle_request("pen_move_to", Some(json!({"x": 10, "y": 10}))).await.unwrap();
+        state.handle_request("pen_line_to", Some(json!({"x": 20, "y": 10}))).await.unwrap();
+        state.handle_request("pen_line_to", Some(json!({"x": 30, "y": 10}))).await.unwrap();
+        // Pen positions take coordinate spaces like any other position
+        state.handle_request("pen_line_to", Some(json!({"x": 0.5, "y": 0.5, "coordinate_space": "normalized"}))).await.unwrap();
+        let closed = state.handle_request("pen_close", None).await.unwrap();
+        assert_eq!(closed["result"]["current"], json!({"x": 10, "y": 10}));
+        state.handle_request("pen_move_to", Some(json!({"x": 100, "y": 100}))).await.unwrap();
+        state.handle_request("pen_line_to", Some(json!({"x": 100, "y": 100}))).await.unwrap();
+        assert!(backend.take_calls().is_empty());
+
+        let stroked = state.handle_request("pen_stroke", Some(json!({"color": "#FF0000"}))).await.unwrap();
+        assert_eq!((stroked["result"]["strokes"].as_u64(), stroked["result"]["points"].as_u64()), (Some(2), Some(5)));
+        assert_eq!(backend.take_calls(), vec![
+            "select_tool pencil", "set_color #FF0000",
+            "draw_polyline [(10, 10), (30, 10), (400, 300), (10, 10)] Left", "draw_pixel 100 100 Left",
+        ]);
+        assert!(state.handle_request("pen_stroke", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_clip_draws_the_part_on_the_canvas() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let line = json!({"start_x": -100, "start_y": 300, "end_x": 400, "end_y": 300, "clip": true});
+        let result = handle_draw_line(state.clone(), Some(line)).await.unwrap();
+        assert_eq!(result["result"]["clipped"], "partial");
+        let polyline = json!({"points": [{"x": 900, "y": 0}, {"x": 1000, "y": 50}], "clip": true});
+        let result = handle_draw_polyline(state.clone(), Some(polyline)).await.unwrap();
+        assert_eq!(result["result"]["clipped"], "all");
+        let inside = json!({"shape_type": "rectangle", "start_x": 10, "start_y": 10, "end_x": 50, "end_y": 40, "clip": true});
+        assert_eq!(handle_draw_shape(state.clone(), Some(inside)).await.unwrap()["result"]["clipped"], "none");
+        let solid = json!({"shape_type": "rectangle", "start_x": 700, "start_y": 100, "end_x": 900, "end_y": 200, "fill_type": "solid", "clip": true});
+        assert_eq!(handle_draw_shape(state.clone(), Some(solid)).await.unwrap()["result"]["clipped"], "partial");
+        assert_eq!(backend.take_calls(), vec![
+            "draw_line 0 300 400 300 Left", "select_tool pencil", "draw_shape rectangle 10 10 50 40 Left",
+            "set_fill solid", "draw_polyline [(700, 100), (799, 100), (799, 200), (700, 200), (700, 100)] Left",
+            "select_tool fill", "click 749 150",
+        ]);
+
+        // Without clip the figure is drawn as given
+        let line = json!({"start_x": -100, "start_y": 300, "end_x": 400, "end_y": 300});
+        assert_eq!(handle_draw_line(state, Some(line)).await.unwrap(), success_response());
+        assert_eq!(backend.take_calls(), vec!["draw_line -100 300 400 300 Left"]);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_plans_without_input() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        // The mock shows the canvas unzoomed at (0, 120), 530 pixels wide
+        let line = json!({"start_x": 10, "start_y": 10, "end_x": 50, "end_y": 10, "color": "#ff0000", "thickness_px": 3, "dry_run": true});
+        let result = handle_draw_line(state.clone(), Some(line)).await.unwrap()["result"].clone();
+        assert_eq!(result["screen_strokes"], json!([[{"x": 10, "y": 130}, {"x": 50, "y": 130}]]));
+        assert_eq!(result["bounding_box"], json!({"left": 10, "top": 130, "right": 51, "bottom": 131, "width": 41, "height": 1}));
+        assert_eq!(result["in_view"], true);
+
+        let solid = json!({"shape_type": "rectangle", "start_x": 700, "start_y": 100, "end_x": 900, "end_y": 200, "fill_type": "solid", "clip": true, "dry_run": true});
+        let result = handle_draw_shape(state.clone(), Some(solid)).await.unwrap()["result"].clone();
+        assert_eq!(result["clipped"], "partial");
+        assert_eq!(result["canvas_strokes"][0].as_array().unwrap().len(), 5);
+        assert_eq!(result["canvas_bounding_box"]["right"], 800);
+        assert_eq!(result["in_view"], false);
+
+        // Bad values are still rejected
+        assert!(handle_draw_pixel(state.clone(), Some(json!({"x": 1, "y": 1, "color": "red", "dry_run": true}))).await.is_err());
+        let polyline = json!({"points": [{"x": 0, "y": 0}, {"x": 5, "y": 5}], "thickness": 2, "thickness_px": 4, "dry_run": true});
+        assert!(handle_draw_polyline(state, Some(polyline)).await.is_err());
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_polyline_smoothing_and_simplifying() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let wobbly = json!({"points": [{"x": 0, "y": 0}, {"x": 10, "y": 1}, {"x": 20, "y": 0}, {"x": 30, "y": 10}], "simplify_tolerance": 2.0});
+        handle_draw_polyline(state.clone(), Some(wobbly)).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["select_tool pencil", "draw_polyline [(0, 0), (20, 0), (30, 10)] Left"]);
+
+        let corner = json!({"points": [{"x": 0, "y": 0}, {"x": 40, "y": 0}, {"x": 40, "y": 40}], "smoothing": "catmull_rom", "dry_run": true});
+        let result = handle_draw_polyline(state.clone(), Some(corner)).await.unwrap();
+        let curve = result["result"]["canvas_strokes"][0].as_array().unwrap().clone();
+        assert!(curve.len() > 10);
+        assert_eq!(curve.last(), Some(&json!({"x": 40, "y": 40})));
+
+        let bad = json!({"points": [{"x": 0, "y": 0}, {"x": 5, "y": 5}], "simplify_tolerance": -1.0});
+        assert!(handle_draw_polyline(state.clone(), Some(bad)).await.is_err());
+        let bad = json!({"points": [{"x": 0, "y": 0}, {"x": 5, "y": 5}], "smoothing": "bezier"});
+        assert!(handle_draw_polyline(state, Some(bad)).await.is_err());
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_polyline_width_profile_draws_pieces_at_their_widths() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let tapered = json!({"points": [{"x": 0, "y": 0}, {"x": 60, "y": 0}], "thickness_px": 5, "width_profile": {"taper_out": 0.5}});
+        handle_draw_polyline(state.clone(), Some(tapered)).await.unwrap();
+        let calls = backend.take_calls();
+        assert_eq!(calls[..2], ["select_tool pencil", "set_thickness_px 5"]);
+        // Every size change is followed by the piece drawn at it
+        let sizes: Vec<&String> = calls.iter().filter(|c| c.starts_with("set_thickness_px")).collect();
+        let pieces: Vec<&String> = calls.iter().filter(|c| c.starts_with("draw_polyline")).collect();
+        assert!(sizes.len() > 2 && sizes.len() == pieces.len());
+        assert_eq!(sizes.last().unwrap().as_str(), "set_thickness_px 1");
+        assert!(pieces.last().unwrap().contains("(60, 0)] Left"));
+
+        let miscounted = json!({"points": [{"x": 0, "y": 0}, {"x": 60, "y": 0}], "width_profile": {"widths": [1, 2, 3]}});
+        assert!(handle_draw_polyline(state, Some(miscounted)).await.is_err());
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_canvas_copies_into_a_new_window() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let response = handle_duplicate_canvas(state.clone(), None).await.unwrap();
+        assert_eq!(response["result"]["hwnd"], MOCK_HWND + 1);
+        assert_eq!(response["result"]["tracked_hwnd"], MOCK_HWND);
+        assert_eq!(backend.take_calls(), vec![
+            format!("copy_canvas_image {}", MOCK_HWND),
+            "launch_paint_window".to_string(),
+            "create_canvas 800 600 None Fail".to_string(),
+            "stamp_clipboard_image 0 0 800x600".to_string(),
+            "activate".to_string(),
+        ]);
+
+        handle_duplicate_canvas(state.clone(), Some(json!({"track_copy": true}))).await.unwrap();
+        assert_eq!(*state.paint_hwnd.lock().unwrap(), Some(MOCK_HWND + 1));
+
+        // The window being copied can't be the target
+        let params = json!({"target_hwnd": MOCK_HWND + 1});
+        assert!(handle_duplicate_canvas(state, Some(params)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ellipses_are_drawn_as_polylines() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let tilted = json!({"x": 50, "y": 40, "radius_x": 30, "radius_y": 10, "rotation_deg": 90, "thickness_px": 3});
+        handle_draw_ellipse(state.clone(), Some(tilted)).await.unwrap();
+        let calls = backend.take_calls();
+        assert_eq!(calls[..2], ["select_tool pencil", "set_thickness_px 3"]);
+        assert!(calls[2].starts_with("draw_polyline [(50, 70), ") && calls[2].ends_with(", (50, 70)] Left"), "{}", calls[2]);
+
+        let arc = json!({"x": 0, "y": 0, "radius_x": 20, "radius_y": 20, "start_angle": 0, "end_angle": 90, "dry_run": true});
+        let result = handle_draw_ellipse(state.clone(), Some(arc)).await.unwrap();
+        let arc = result["result"]["canvas_strokes"][0].as_array().unwrap().clone();
+        assert_eq!((arc.first(), arc.last()), (Some(&json!({"x": 20, "y": 0})), Some(&json!({"x": 0, "y": 20}))));
+
+        let bad = json!({"x": 0, "y": 0, "radius_x": 20, "radius_y": 0});
+        assert!(handle_draw_ellipse(state.clone(), Some(bad)).await.is_err());
+        let bad = json!({"x": 0, "y": 0, "radius_x": 20, "radius_y": 20, "start_angle": 45});
+        assert!(handle_draw_ellipse(state, Some(bad)).await.is_err());
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_selection_transforms_validate_params() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        handle_rotate_selection(state.clone(), Some(json!({"angle": 270}))).await.unwrap();
+        handle_flip_selection(state.clone(), Some(json!({"direction": "vertical"}))).await.unwrap();
+        assert!(handle_rotate_selection(state.clone(), Some(json!({"angle": 45}))).await.is_err());
+        assert!(handle_flip_selection(state, Some(json!({"direction": "diagonal"}))).await.is_err());
+        assert_eq!(backend.take_calls(), vec!["rotate_selection 270", "flip_selection vertical"]);
+    }
+
+    #[tokio::test]
+    async fn test_create_canvas_passes_the_unsaved_changes_policy() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        handle_create_canvas(state.clone(), Some(json!({"width": 640, "height": 480}))).await.unwrap();
+        handle_create_canvas(state.clone(), Some(json!({"width": 640, "height": 480, "unsaved_changes": "discard"}))).await.unwrap();
+        let calls = backend.take_calls();
+        assert_eq!(calls[0], "create_canvas 640 480 None Fail");
+        assert!(calls.contains(&"create_canvas 640 480 None Discard".to_string()));
+        let params = json!({"width": 640, "height": 480, "unsaved_changes": "ask"});
+        assert!(handle_create_canvas(state, Some(params)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_image_properties_validates_sizes() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let response = handle_set_image_properties(state.clone(), Some(json!({"width": 1024, "height": 768}))).await.unwrap();
+        assert!(response["result"]["canvas_width"].is_u64());
+        let params = json!({"width": 2.5, "units": "inches", "color_mode": "black_and_white"});
+        handle_set_image_properties(state.clone(), Some(params)).await.unwrap();
+        let calls = backend.take_calls();
+        assert_eq!(calls[0], "set_image_properties Some(1024.0) Some(768.0) None None");
+        assert!(calls.contains(&"set_image_properties Some(2.5) None Some(Inches) Some(BlackAndWhite)".to_string()));
+
+        for params in [json!({}), json!({"width": 10.5}), json!({"height": 0}), json!({"width": 1000, "units": "inches"}),
+            json!({"color_mode": "grayscale"})] {
+            assert!(handle_set_image_properties(state.clone(), Some(params)).await.is_err());
+        }
+        assert!(backend.take_calls().iter().all(|call| !call.starts_with("set_image_properties")));
+    }
+
+    #[tokio::test]
+    async fn test_new_document_forgets_the_saved_path() {
+        let (state, backend) = mock_state();
+        *backend.title.lock().unwrap() = "flower.png - Paint".to_string();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        state.document.lock().unwrap().record_save("C:/out/flower.png", 1);
+        *backend.title.lock().unwrap() = "Untitled - Paint".to_string();
+
+        let response = handle_new_document(state.clone(), Some(json!({"unsaved_changes": "discard"}))).await.unwrap();
+        assert_eq!((response["result"]["canvas_width"].as_u64(), response["result"]["document"]["document_name"].as_str()), (Some(800), Some("Untitled")));
+        assert!(response["result"]["document"]["file_path"].is_null());
+        assert_eq!(backend.take_calls(), vec!["new_document Discard".to_string()]);
+
+        handle_new_document(state.clone(), None).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["new_document Fail".to_string()]);
+        assert!(handle_new_document(state, Some(json!({"unsaved_changes": "ask"}))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quit_paint_forgets_the_window() {
+        let (state, backend) = mock_state();
+        *backend.title.lock().unwrap() = "*Untitled - Paint".to_string();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let response = handle_close_document(state.clone(), Some(json!({"unsaved_changes": "discard"}))).await.unwrap();
+        assert_eq!(response["result"]["closed"], "Untitled");
+        assert_eq!(backend.take_calls(), vec!["new_document Discard".to_string()]);
+
+        handle_quit_paint(state.clone(), Some(json!({"timeout_ms": 2000}))).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["quit_paint Fail 2s".to_string()]);
+        assert!(state.paint_hwnd.lock().unwrap().is_none() && state.paint_pid.lock().unwrap().is_none());
+        assert!(matches!(handle_quit_paint(state, None).await, Err(MspMcpError::WindowNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_save_canvas_validates_format_and_quality() {
+        let (mut state, backend) = mock_state();
+        state.clock = Arc::new(crate::clock::VirtualClock::new());
+        state.set_paint_window(MOCK_HWND).unwrap();
+
+        // The mock doesn't write anything, so put the "saved" file in place first
+        let path = std::env::temp_dir().join(format!("mspmcp-save-{}.jpg", std::process::id()));
+        std::fs::write(&path, b"jpeg bytes").unwrap();
+        let file_path = path.display().to_string();
+        let params = json!({"file_path": file_path, "format": "jpeg", "jpeg_quality": 80, "overwrite": true});
+        let response = state.handle_request("save_canvas", Some(params)).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(response["result"]["file_path"], file_path.as_str());
+        assert_eq!(response["result"]["size_bytes"], 10);
+        assert_eq!(backend.take_calls(), vec![format!("save_canvas {} jpeg true", file_path)]);
+
+        // Nothing appears on disk: time out rather than claim success
+        let params = json!({"file_path": file_path, "format": "jpeg"});
+        assert!(matches!(state.handle_request("save_canvas", Some(params)).await, Err(MspMcpError::OperationTimeout(_))));
+
+        let params = json!({"file_path": "C:\\out\\a.gif", "format": "gif"});
+        assert!(matches!(handle_save_canvas(state.clone(), Some(params)).await, Err(MspMcpError::InvalidImageFormat(_))));
+        let params = json!({"file_path": "C:\\out\\a.png", "format": "png", "jpeg_quality": 80});
+        assert!(handle_save_canvas(state, Some(params)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_overwriting_saves_need_confirmation() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        state.config.lock().unwrap().confirm_destructive = true;
+
+        let params = json!({"file_path": "C:\\out\\a.png", "format": "png", "overwrite": true});
+        let result = state.handle_request("save_canvas", Some(params)).await;
+        assert!(matches!(&result, Err(MspMcpError::ConfirmationRequired(message)) if message.contains("may overwrite an existing file")));
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_document_info_remembers_the_saved_path() {
+        let (mut state, backend) = mock_state();
+        state.clock = Arc::new(crate::clock::VirtualClock::new());
+        *backend.title.lock().unwrap() = "*Untitled - Paint".to_string();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let info = handle_get_document_info(state.clone(), None).await.unwrap();
+        assert_eq!((info["result"]["document_name"].as_str(), info["result"]["has_unsaved_changes"].as_bool()), (Some("Untitled"), Some(true)));
+        assert!(info["result"]["file_path"].is_null());
+
+        let path = std::env::temp_dir().join(format!("mspmcp-doc-{}.png", std::process::id()));
+        std::fs::write(&path, b"png bytes").unwrap();
+        let file_path = path.display().to_string();
+        let params = json!({"file_path": file_path, "format": "png", "overwrite": true});
+        state.handle_request("save_canvas", Some(params)).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        *backend.title.lock().unwrap() = format!("mspmcp-doc-{}.png - Paint", std::process::id());
+
+        let info = handle_get_document_info(state.clone(), None).await.unwrap();
+        assert_eq!(info["result"]["file_path"], file_path.as_str());
+        assert_eq!((info["result"]["has_unsaved_changes"].as_bool(), info["result"]["width"].as_u64()), (Some(false), Some(800)));
+        assert!(info["result"]["last_saved_unix_ms"].as_u64().unwrap() > 0);
+
+        // A new document forgets the path
+        *backend.title.lock().unwrap() = "Untitled - Paint".to_string();
+        let info = handle_get_document_info(state, None).await.unwrap();
+        assert!(info["result"]["file_path"].is_null() && info["result"]["last_saved_unix_ms"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_save_as_accepts_gif() {
+        let (mut state, backend) = mock_state();
+        state.clock = Arc::new(crate::clock::VirtualClock::new());
+        state.set_paint_window(MOCK_HWND).unwrap();
+
+        let path = std::env::temp_dir().join(format!("mspmcp-save-as-{}.gif", std::process::id()));
+        std::fs::write(&path, b"GIF89a").unwrap();
+        let file_path = path.display().to_string();
+        let response = state.handle_request("save_as", Some(json!({"file_path": file_path, "format": "gif", "overwrite": true}))).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(response["result"]["size_bytes"], 6);
+        assert_eq!(backend.take_calls(), vec![format!("save_as {} gif None", file_path)]);
+
+        // save_canvas still leaves GIF out
+        let params = json!({"file_path": file_path, "format": "gif"});
+        assert!(matches!(handle_save_canvas(state.clone(), Some(params)).await, Err(MspMcpError::InvalidImageFormat(_))));
+        let params = json!({"file_path": file_path, "format": "gif", "jpeg_quality": 50});
+        assert!(handle_save_as(state, Some(params)).await.is_err());
+    }
+
+    #[test]
+    fn test_save_paths_are_validated_before_saving() {
+        let dir = std::env::temp_dir().join(format!("mspmcp-paths-{}", std::process::id()));
+        std::env::set_var("MSPMCP_TEST_DIR", &dir);
+        let config = ServerConfig::default();
+
+        assert!(matches!(resolve_save_path("out.png", true, &config), Err(MspMcpError::InvalidParameters(_))));
+        assert!(resolve_save_path("%MSPMCP_UNSET_VAR%/out.png", true, &config).is_err());
+        assert!(resolve_save_path("%MSPMCP_TEST_DIR%/a<b.png", true, &config).is_err());
+        assert!(resolve_save_path("%MSPMCP_TEST_DIR%/../out.png", true, &config).is_err());
+
+        let nested = "%MSPMCP_TEST_DIR%/nested/out.png";
+        assert!(matches!(resolve_save_path(nested, false, &config), Err(MspMcpError::FileNotFound(_))));
+        let resolved = resolve_save_path(nested, true, &config).unwrap();
+        assert_eq!(resolved, dir.join("nested").join("out.png"));
+        assert!(dir.join("nested").is_dir());
+
+        // Nothing is created outside the allowed directories
+        let config = ServerConfig { allowed_dirs: vec![dir.join("nested")], ..ServerConfig::default() };
+        assert!(resolve_save_path("%MSPMCP_TEST_DIR%/nested/out.png", false, &config).is_ok());
+        let other = "%MSPMCP_TEST_DIR%/other/out.png";
+        assert!(matches!(resolve_save_path(other, true, &config), Err(MspMcpError::PathNotAllowed(_))));
+        assert!(!dir.join("other").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_image_sends_a_preview_over_the_size_limit() {
+        let (state, _backend) = mock_state();
+        let path = std::env::temp_dir().join(format!("mspmcp-fetch-{}.png", std::process::id()));
+        let image = crate::capture::CapturedImage { width: 40, height: 20, rgba: vec![0x80; 40 * 20 * 4] };
+        let png = crate::capture::encode_png(&image).unwrap();
+        std::fs::write(&path, &png).unwrap();
+        let file_path = path.display().to_string();
+
+        let response = handle_fetch_image(state.clone(), Some(json!({"file_path": file_path}))).await.unwrap();
+        assert_eq!(response["result"]["format"], "png");
+        assert_eq!((response["result"]["width"].clone(), response["result"]["height"].clone()), (json!(40), json!(20)));
+        use base64::Engine;
+        let data = base64::engine::general_purpose::STANDARD.decode(response["result"]["data"].as_str().unwrap()).unwrap();
+        assert_eq!(data, png);
+
+        // Over the limit: refused unless a preview is asked for
+        state.config.lock().unwrap().max_fetch_bytes = 10;
+        let result = handle_fetch_image(state.clone(), Some(json!({"file_path": file_path}))).await;
+        assert!(matches!(result, Err(MspMcpError::PayloadTooLarge { limit: 10, unit: "bytes", .. })));
+        let params = json!({"file_path": file_path, "preview_max_px": 8});
+        let response = handle_fetch_image(state.clone(), Some(params)).await.unwrap();
+        assert_eq!(response["result"]["preview"], json!({"format": "png", "width": 8, "height": 4}));
+        assert_eq!(response["result"]["width"], 40);
+
+        std::fs::write(&path, b"not an image").unwrap();
+        let params = json!({"file_path": file_path, "preview_max_px": 8});
+        assert!(matches!(handle_fetch_image(state, Some(params)).await, Err(MspMcpError::InvalidImageFormat(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_background_fills_the_canvas() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        handle_set_background(state.clone(), Some(json!({"color": "#336699"}))).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["set_background #336699"]);
+        assert!(handle_set_background(state, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_invert_colors_defaults_to_the_whole_canvas() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        handle_invert_colors(state.clone(), None).await.unwrap();
+        handle_invert_colors(state.clone(), Some(json!({"scope": "selection"}))).await.unwrap();
+        assert!(handle_invert_colors(state, Some(json!({"scope": "layer"}))).await.is_err());
+        assert_eq!(backend.take_calls(), vec!["invert_colors false", "invert_colors true"]);
+    }
+
+    #[tokio::test]
+    async fn test_layer_methods_address_layers_by_index() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        handle_add_layer(state.clone(), None).await.unwrap();
+        handle_set_layer_visibility(state.clone(), Some(json!({"index": 1, "visible": false}))).await.unwrap();
+        handle_merge_layers(state.clone(), Some(json!({"index": 0}))).await.unwrap();
+        assert_eq!(backend.take_calls(), vec!["add_layer", "set_layer_visibility 1 false", "merge_layer_down 0"]);
+        assert!(handle_delete_layer(state, Some(json!({"index": -1}))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_supported_tools_reports_the_build_capabilities() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let response = handle_get_supported_tools(state, None).await.unwrap();
+        let result = &response["result"];
+        assert_eq!(result["paint_app_version"], "11.2410.28.0");
+        assert_eq!(result["brush_types"], json!(["brush", "marker"]));
+        assert_eq!(result["shapes"], json!(["ellipse", "rectangle"]));
+        assert_eq!(result["layers"], true);
+        assert_eq!(result["background_removal"], false);
+        assert_eq!(result["unavailable_methods"], json!([]));
+        assert_eq!(backend.take_calls(), vec!["capabilities"]);
+    }
+
+    #[tokio::test]
+    async fn test_handlers_require_a_window() {
+        let (state, backend) = mock_state();
+        let err = handle_clear_canvas(state, None).await.unwrap_err();
+        assert!(matches!(err, MspMcpError::WindowNotFound));
+        assert!(backend.take_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_canvas_region_returns_image() {
+        let (state, _backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let params = json!({"x": 0, "y": 0, "width": 4, "height": 2});
+        let response = handle_get_canvas_region(state, Some(params)).await.unwrap();
+        let content = &response["result"]["content"];
+        assert_eq!(content[0]["type"], "image");
+        assert_eq!(content[0]["mimeType"], "image/png");
+        assert!(content[1]["text"].as_str().unwrap().contains("4x2"));
+    }
+
+    #[tokio::test]
+    async fn test_export_region_copies_the_selection() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let response = handle_export_region(state.clone(), Some(json!({"x": 10, "y": 20, "width": 4, "height": 2}))).await.unwrap();
+        assert_eq!(response["result"]["content"][0]["mimeType"], "image/png");
+        assert_eq!(backend.take_calls(), vec!["select_region 10 20 14 22".to_string(), "copy_selection_image".to_string()]);
+        assert!(handle_export_region(state, Some(json!({"x": 0, "y": 0, "width": 0, "height": 2}))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_captures_report_the_limit() {
+        let (state, _backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        state.config.lock().unwrap().max_capture_megapixels = 0.000004;
+        let params = json!({"x": 0, "y": 0, "width": 4, "height": 2});
+        let error = handle_get_canvas_region(state.clone(), Some(params)).await.unwrap_err();
+        let data = crate::protocol::error_data("get_canvas_region", &error);
+        assert_eq!((data["code"].clone(), data["limit"].clone(), data["unit"].clone()), (json!(1025), json!(4), json!("pixels")));
+        let params = json!({"x": 0, "y": 0, "width": 2, "height": 2});
+        assert!(handle_get_canvas_region(state, Some(params)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_cover_the_whole_session() {
+        let backend = Arc::new(MockBackend::default());
+        let mut config = ServerConfig::default();
+        config.rate_limits.ops_per_minute = 1;
+        let mut state = PaintServerState::with_backend(config, backend);
+        state.clock = Arc::new(crate::clock::VirtualClock::new());
+        state.set_paint_window(MOCK_HWND).unwrap();
+
+        state.handle_request("get_canvas_dimensions", None).await.unwrap();
+        let error = state.handle_request("get_canvas_dimensions", None).await.unwrap_err();
+        assert!(matches!(error, MspMcpError::RateLimited { retry_after_ms: 1..=60_000, .. }));
+        assert!(crate::protocol::error_data("get_canvas_dimensions", &error)["retry_after_ms"].is_u64());
+
+        // Connecting again under another id doesn't buy a fresh budget
+        let params = json!({"client_id": "second", "client_name": "Second"});
+        state.handle_request("connect", Some(params)).await.unwrap();
+        assert!(matches!(state.handle_request("get_canvas_dimensions", None).await, Err(MspMcpError::RateLimited { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_requests_cost_no_budget() {
+        let (state, _backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        state.config.lock().unwrap().confirm_destructive = true;
+        state.rate_limiter.set_limits(crate::config::RateLimitConfig { ops_per_minute: 1, input_events_per_minute: 0 });
+
+        let result = state.handle_request("clear_canvas", None).await;
+        assert!(matches!(result, Err(MspMcpError::ConfirmationRequired(_))));
+        state.handle_request("clear_canvas", Some(json!({"confirm": true}))).await.unwrap();
+    }
+
+    #[test]
+    fn test_macros_and_animations_are_watched_step_by_step() {
+        let (state, _backend) = mock_state();
+        assert!(state.arm_watchdog("draw_line").is_some());
+        assert!(state.arm_watchdog("run_macro").is_none());
+        assert!(state.arm_watchdog("draw_animation").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_macro_input_events_count_once() {
+        use crate::backend::PaintBackend;
+        let (mut state, backend) = mock_state();
+        let clock = Arc::new(crate::clock::VirtualClock::new());
+        state.clock = clock.clone();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let source = "line(0, 0, 10, 0); line(0, 10, 10, 10);";
+        state.handle_request("register_macro", Some(json!({"name": "rows", "source": source}))).await.unwrap();
+
+        // Measure the macro's events, then let them leave the window
+        let before = backend.input_events_sent();
+        state.handle_request("run_macro", Some(json!({"name": "rows"}))).await.unwrap();
+        let events = backend.input_events_sent() - before;
+        clock.advance(crate::rate_limit::WINDOW);
+
+        // Counted once, the macro leaves room for one more stroke
+        let limits = crate::config::RateLimitConfig { ops_per_minute: 0, input_events_per_minute: events as u32 + 1 };
+        state.rate_limiter.set_limits(limits);
+        state.handle_request("run_macro", Some(json!({"name": "rows"}))).await.unwrap();
+        state.handle_request("draw_pixel", Some(json!({"x": 1, "y": 1}))).await.unwrap();
+        let result = state.handle_request("draw_pixel", Some(json!({"x": 2, "y": 1}))).await;
+        assert!(matches!(result, Err(MspMcpError::RateLimited { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_records_every_request() {
+        let path = std::env::temp_dir().join(format!("mspmcp-audit-state-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = ServerConfig { audit_log: Some(path.clone()), ..ServerConfig::default() };
+        let state = PaintServerState::with_backend(config, Arc::new(MockBackend::default()));
+
+        assert!(state.handle_request("get_canvas_dimensions", None).await.is_err()); // No window yet
+        state.set_paint_window(MOCK_HWND).unwrap();
+        state.handle_request("draw_pixel", Some(json!({"x": 1, "y": 2}))).await.unwrap();
+
+        let response = state.handle_request("get_audit_log", Some(json!({"errors_only": true}))).await.unwrap();
+        let entries = response["result"]["entries"].as_array().unwrap().clone();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0]["method"].clone(), entries[0]["error_code"].clone()), (json!("get_canvas_dimensions"), json!(1008)));
+
+        let response = state.handle_request("get_audit_log", Some(json!({"method": "draw_pixel"}))).await.unwrap();
+        let entry = &response["result"]["entries"][0];
+        assert_eq!((entry["client_id"].clone(), entry["result"].clone()), (json!(crate::DEFAULT_CLIENT_ID), json!("ok")));
+        assert_eq!(entry["params_hash"], crate::audit::hash_params(Some(&json!({"x": 1, "y": 2}))));
+        std::fs::remove_file(&path).unwrap();
+
+        let (state, _backend) = mock_state();
+        assert!(matches!(handle_get_audit_log(state, None).await, Err(MspMcpError::OperationNotSupported(_))));
+    }
+
+    #[tokio::test]
+    async fn test_responses_carry_a_request_id() {
+        let (state, _backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let first = state.handle_request("draw_pixel", Some(json!({"x": 1, "y": 2}))).await.unwrap();
+        let second = state.handle_request("get_version", None).await.unwrap();
+        let first_id = first["result"]["_request_id"].as_str().unwrap();
+        assert!(first_id.starts_with(&format!("{}-", std::process::id())));
+        assert_ne!(Some(first_id), second["result"]["_request_id"].as_str());
+        assert_eq!(second["result"]["paint_app_version"], "11.2410.28.0");
+
+        let mut data = crate::protocol::error_data("draw_pixel", &MspMcpError::WindowNotFound);
+        crate::protocol::add_request_id(&mut data, first_id);
+        assert_eq!(data["_request_id"], first_id);
+    }
+
+    #[tokio::test]
+    async fn test_set_log_level_validates_params() {
+        let (state, _backend) = mock_state();
+        let result = state.handle_request("set_log_level", Some(json!({"level": "verbose"}))).await;
+        assert!(matches!(result, Err(MspMcpError::InvalidParameters(_))));
+        let result = state.handle_request("set_log_level", Some(json!({"level": "debug", "logger": "syslog"}))).await;
+        assert!(matches!(result, Err(MspMcpError::InvalidParameters(_))));
+        assert!(state.handle_request("set_log_level", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_canvas_dimensions_include_the_view() {
+        let (state, _backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let response = state.handle_request("get_canvas_dimensions", None).await.unwrap();
+        let result = &response["result"];
+        assert_eq!((result["width"].clone(), result["height"].clone(), result["zoom"].clone()), (json!(800), json!(600), json!(1.0)));
+        assert_eq!((result["viewport"]["width"].clone(), result["scroll_x"].clone()), (json!(530), json!(0)));
+        assert_eq!(result["estimated"], false);
+    }
+
+    #[tokio::test]
+    async fn test_ping_reports_activity() {
+        let (state, _backend) = mock_state();
+        let _busy = state.activity.begin("draw_polyline");
+        let response = state.handle_request("ping", None).await.unwrap();
+        assert_eq!(response["result"]["queue_depth"], 1);
+        assert_eq!(response["result"]["busy_method"], "draw_polyline");
+        assert!(response["result"]["uptime_ms"].is_u64());
+
+        // Still answered after shutdown
+        state.handle_request("shutdown", None).await.unwrap();
+        assert!(state.handle_request("ping", None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_saves_only_documents_with_a_file() {
+        for (title, saved) in [("*Untitled - Paint", false), ("*flower.png - Paint", true)] {
+            let (state, backend) = mock_state();
+            state.set_paint_window(MOCK_HWND).unwrap();
+            state.config.lock().unwrap().shutdown_document = crate::config::ShutdownDocumentAction::Save;
+            state.document.lock().unwrap().update_from_title(title);
+            handle_shutdown(state, None).await.unwrap();
+            assert_eq!(backend.take_calls().contains(&"save".to_string()), saved, "{}", title);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handler_becomes_an_error() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        *backend.panic_on.lock().unwrap() = Some("draw_pixel".to_string());
+        let result = state.handle_request("draw_pixel", Some(json!({"x": 1, "y": 2}))).await;
+        assert!(matches!(result, Err(MspMcpError::Panicked(ref message)) if message.contains("draw_pixel 1 2")));
+        assert!(backend.take_calls().contains(&"release_held_input".to_string()));
+
+        // The server keeps working
+        *backend.panic_on.lock().unwrap() = None;
+        state.handle_request("draw_pixel", Some(json!({"x": 1, "y": 2}))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_waits_on_the_state_clock() {
+        let (mut state, backend) = mock_state();
+        let clock = Arc::new(crate::clock::VirtualClock::new());
+        state.clock = clock.clone();
+        state.set_paint_window(MOCK_HWND).unwrap();
+
+        // Ten seconds of recorded input replays instantly on virtual time
+        let path = std::env::temp_dir().join(format!("mspmcp-replay-{}.jsonl", std::process::id()));
+        std::fs::write(&path, concat!(
+            "{\"time_ms\":0,\"type\":\"keyboard\",\"vk\":17,\"scan\":0,\"flags\":0}\n",
+            "{\"time_ms\":10000,\"type\":\"keyboard\",\"vk\":17,\"scan\":0,\"flags\":2}\n",
+        )).unwrap();
+        let response = state.handle_request("replay_input", Some(json!({"path": path.display().to_string()}))).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(response.unwrap()["result"]["duration_ms"], 10000);
+        assert_eq!(clock.elapsed(), std::time::Duration::from_secs(10));
+        assert!(backend.take_calls().contains(&"replay_input 2 1".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_run_benchmark_reports_each_measurement() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let response = handle_run_benchmark(state, Some(json!({"iterations": 5}))).await.unwrap();
+        let result = &response["result"];
+        assert_eq!(result["pixels"]["count"], 5);
+        assert_eq!(result["lines"]["count"], 5);
+        assert_eq!(result["batch"]["count"], 5);
+
+        let calls = backend.take_calls();
+        assert_eq!(&calls[..4], ["activate", "select_tool pencil", "set_color #000000", "set_thickness 1"]);
+        assert_eq!(calls.iter().filter(|c| c.starts_with("draw_pixel")).count(), 5);
+        assert_eq!(calls.iter().filter(|c| c.starts_with("draw_line")).count(), 5);
+        assert_eq!(calls.last().unwrap(), "draw_polyline [(0, 0), (5, 199), (10, 0), (15, 199), (20, 0), (25, 199)] Left");
+    }
+
+    #[tokio::test]
+    async fn test_registered_macro_runs_its_batch() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let source = "for i in 0..args.rows { line(0, i * 10, canvas_width / 2, i * 10); }";
+        let response = handle_register_macro(state.clone(), Some(json!({"name": "rows", "source": source}))).await.unwrap();
+        assert_eq!(response["result"]["replaced"], false);
+
+        let planned = handle_run_macro(state.clone(), Some(json!({"name": "rows", "args": {"rows": 2}, "dry_run": true}))).await.unwrap();
+        assert_eq!(planned["result"]["steps"], 2);
+        assert_eq!(planned["result"]["requests"][1]["params"]["start_y"], 10);
+        assert!(backend.take_calls().iter().all(|c| !c.starts_with("draw_line")));
+
+        let response = handle_run_macro(state.clone(), Some(json!({"name": "rows", "args": {"rows": 2}}))).await.unwrap();
+        assert_eq!(response["result"]["steps"], 2);
+        let lines: Vec<String> = backend.take_calls().into_iter().filter(|c| c.starts_with("draw_line")).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(handle_run_macro(state, Some(json!({"name": "missing"}))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_draw_animation_flips_through_its_frames() {
+        let (mut state, backend) = mock_state();
+        state.clock = Arc::new(crate::clock::VirtualClock::new());
+        state.set_paint_window(MOCK_HWND).unwrap();
+        let frames = json!([
+            {"before": "clear", "steps": [{"method": "draw_pixel", "params": {"x": 1, "y": 1}}]},
+            {"before": "undo", "steps": [
+                {"method": "set_color", "params": {"color": "#FF0000"}},
+                {"method": "draw_pixel", "params": {"x": 2, "y": 1}},
+                {"method": "draw_pixel", "params": {"x": 3, "y": 1}}
+            ]},
+            {"before": "undo", "steps": []}
+        ]);
+        let response = state.handle_request("draw_animation", Some(json!({"frames": frames, "frame_delay_ms": 100}))).await.unwrap();
+        assert_eq!(response["result"]["frames"], 3);
+        // Two frames held for their delay; the last one stays up
+        assert_eq!(response["result"]["duration_ms"], 200);
+        let calls: Vec<String> = backend.take_calls().into_iter()
+            .filter(|c| ["clear_canvas", "draw_pixel", "undo"].iter().any(|name| c.starts_with(name)))
+            .collect();
+        assert_eq!(calls, vec![
+            "clear_canvas", "draw_pixel 1 1 Left", "undo", "draw_pixel 2 1 Left", "draw_pixel 3 1 Left", "undo", "undo",
+        ]);
+
+        let nested = json!({"frames": [{"steps": [{"method": "run_macro", "params": {"source": ""}}]}], "frame_delay_ms": 0});
+        assert!(state.handle_request("draw_animation", Some(nested)).await.is_err());
+        let forever = json!({"frames": [{"steps": []}], "frame_delay_ms": 0, "loop_count": 0});
+        assert!(state.handle_request("draw_animation", Some(forever)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_revert_to_checkpoint_undoes_the_operations_since() {
+        let (mut state, backend) = mock_state();
+        state.clock = Arc::new(crate::clock::VirtualClock::new());
+        state.set_paint_window(MOCK_HWND).unwrap();
+        assert!(state.handle_request("revert_to_checkpoint", Some(json!({"label": "start"}))).await.is_err());
+
+        state.handle_request("checkpoint", Some(json!({"label": "start"}))).await.unwrap();
+        state.handle_request("draw_pixel", Some(json!({"x": 1, "y": 1}))).await.unwrap();
+        state.handle_request("set_color", Some(json!({"color": "#FF0000"}))).await.unwrap();
+        state.handle_request("draw_line", Some(json!({"start_x": 0, "start_y": 0, "end_x": 5, "end_y": 5}))).await.unwrap();
+        backend.take_calls();
+        let response = state.handle_request("revert_to_checkpoint", Some(json!({"label": "start"}))).await.unwrap();
+        assert_eq!(response["result"]["operations"], 2);
+        // The mock's canvas never changes, so it matches right away
+        assert_eq!(response["result"]["undo_steps"], 2);
+        assert_eq!(backend.take_calls(), vec!["undo", "undo"]);
+
+        // Back at the checkpoint, which stays for another revert
+        let response = state.handle_request("revert_to_checkpoint", Some(json!({"label": "start"}))).await.unwrap();
+        assert_eq!(response["result"]["undo_steps"], 0);
+
+        // A new document has an undo stack of its own
+        state.handle_request("new_document", Some(json!({"unsaved_changes": "discard"}))).await.unwrap();
+        assert!(state.handle_request("revert_to_checkpoint", Some(json!({"label": "start"}))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_calibration_keeps_the_layout() {
+        let (state, backend) = mock_state();
+        state.set_paint_window(MOCK_HWND).unwrap();
+        backend.take_calls();
+        // The mock's captures never change, so no probe stroke shows up
+        let result = handle_calibrate(state, Some(json!({"tools": ["pencil"], "persist": false}))).await;
+        assert!(matches!(result, Err(MspMcpError::ElementNotFound(_))));
+        let calls = backend.take_calls();
+        assert_eq!(&calls[..3], ["activate", "window_info", "set_color #FF00FF"]);
+        assert_eq!(calls.iter().filter(|c| *c == "select_tool pencil").count(), 11);
+        assert!(!calls.contains(&"undo".to_string()));
+    }
+}
@@ -0,0 +1,163 @@
+// Tracking of the open Paint document, derived from the window title.
+//
+// Paint's title changes as the document is saved or modified, e.g.
+// "Untitled - Paint", "flower.png - Paint" or "*flower.png - Paint".
+// We keep tracking the window by HWND/PID and only use the title to
+// update this metadata, never to find the window. The title only has the
+// file name, so the full path and save time are remembered from our own
+// saves, for as long as the title still names that file.
+
+use serde::Serialize;
+use tracing::info;
+
+// Markers Paint builds use to flag unsaved changes in the title
+const DIRTY_MARKERS: [char; 3] = ['*', '●', '•'];
+
+// Separator between the document name and the application name
+const TITLE_SEPARATOR: &str = " - ";
+
+// Name Paint gives a document that was never saved
+const UNTITLED_NAME: &str = "Untitled";
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct DocumentState {
+    pub title: String,                 // Raw window title
+    pub document_name: Option<String>, // e.g. "flower.png" or "Untitled"; None if the title has no document part
+    pub has_unsaved_changes: bool,
+    pub file_path: Option<String>,        // Where save_canvas last wrote this document
+    pub last_saved_unix_ms: Option<u64>,  // When it did
+}
+
+// True if the title's document name is the file at `path`. Paint leaves
+// the extension out when Explorer hides extensions.
+fn names_file(document_name: &str, path: &str) -> bool {
+    let path = std::path::Path::new(path);
+    let matches = |part: Option<&std::ffi::OsStr>| part.and_then(|p| p.to_str()).is_some_and(|p| p.eq_ignore_ascii_case(document_name));
+    matches(path.file_name()) || matches(path.file_stem())
+}
+
+/// Splits a Paint window title into the document name and its dirty flag.
+/// Returns None for the name when the title has no document part.
+pub fn parse_paint_title(title: &str) -> (Option<String>, bool) {
+    let title = title.trim();
+
+    // Everything before the last separator is the document part
+    let document_part = match title.rfind(TITLE_SEPARATOR) {
+        Some(pos) => &title[..pos],
+        None => return (None, false),
+    };
+
+    let mut dirty = false;
+    let mut name = document_part.trim();
+    if let Some(stripped) = name.strip_prefix(&DIRTY_MARKERS[..]) {
+        dirty = true;
+        name = stripped.trim_start();
+    }
+    if let Some(stripped) = name.strip_suffix(&DIRTY_MARKERS[..]) {
+        dirty = true;
+        name = stripped.trim_end();
+    }
+
+    if name.is_empty() {
+        (None, dirty)
+    } else {
+        (Some(name.to_string()), dirty)
+    }
+}
+
+impl DocumentState {
+    /// Updates the metadata from a (possibly changed) window title.
+    /// Returns true if anything changed.
+    pub fn update_from_title(&mut self, title: &str) -> bool {
+        if self.title == title {
+            return false;
+        }
+
+        let (document_name, has_unsaved_changes) = parse_paint_title(title);
+        if document_name != self.document_name {
+            info!("Paint document changed from {:?} to {:?}", self.document_name, document_name);
+        }
+
+        // Another document (or a new one) is open; its path is unknown
+        if !self.file_path.as_deref().zip(document_name.as_deref()).is_some_and(|(path, name)| names_file(name, path)) {
+            self.file_path = None;
+            self.last_saved_unix_ms = None;
+        }
+
+        self.title = title.to_string();
+        self.document_name = document_name;
+        self.has_unsaved_changes = has_unsaved_changes;
+        true
+    }
+
+    /// Remembers that the document was just saved to `file_path`.
+    pub fn record_save(&mut self, file_path: &str, saved_unix_ms: u64) {
+        self.file_path = Some(file_path.to_string());
+        self.last_saved_unix_ms = Some(saved_unix_ms);
+    }
+
+    /// True if the document has no file of its own yet, so saving it
+    /// would open the Save As dialog. A saved file named "Untitled" (shown
+    /// without its extension) counts as saved once we know its path.
+    pub fn is_untitled(&self) -> bool {
+        match self.document_name.as_deref() {
+            None => true,
+            Some(name) => name == UNTITLED_NAME && self.file_path.is_none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_untitled_title() {
+        assert_eq!(parse_paint_title("Untitled - Paint"), (Some("Untitled".to_string()), false));
+        let mut doc = DocumentState::default();
+        assert!(doc.is_untitled());
+        doc.update_from_title("*Untitled - Paint");
+        assert!(doc.is_untitled());
+        doc.record_save("C:/out/Untitled.png", 1_700_000_000_000);
+        assert!(!doc.is_untitled());
+        doc.update_from_title("flower.png - Paint");
+        assert!(!doc.is_untitled());
+    }
+
+    #[test]
+    fn test_parse_saved_and_dirty_titles() {
+        assert_eq!(parse_paint_title("flower.png - Paint"), (Some("flower.png".to_string()), false));
+        assert_eq!(parse_paint_title("*flower.png - Paint"), (Some("flower.png".to_string()), true));
+        assert_eq!(parse_paint_title("flower.png* - Paint"), (Some("flower.png".to_string()), true));
+        assert_eq!(parse_paint_title("● my - file.png - Paint"), (Some("my - file.png".to_string()), true));
+    }
+
+    #[test]
+    fn test_parse_title_without_document() {
+        assert_eq!(parse_paint_title("Paint"), (None, false));
+    }
+
+    #[test]
+    fn test_update_from_title_reports_changes() {
+        let mut doc = DocumentState::default();
+        assert!(doc.update_from_title("Untitled - Paint"));
+        assert!(!doc.update_from_title("Untitled - Paint"));
+        assert!(doc.update_from_title("*flower.png - Paint"));
+        assert_eq!(doc.document_name.as_deref(), Some("flower.png"));
+        assert!(doc.has_unsaved_changes);
+    }
+
+    #[test]
+    fn test_saved_path_lasts_while_the_title_names_it() {
+        let mut doc = DocumentState::default();
+        doc.update_from_title("*Untitled - Paint");
+        doc.record_save("C:/out/flower.png", 1_700_000_000_000);
+        doc.update_from_title("flower.png - Paint");
+        assert_eq!((doc.file_path.as_deref(), doc.last_saved_unix_ms), (Some("C:/out/flower.png"), Some(1_700_000_000_000)));
+        doc.update_from_title("*Flower - Paint");
+        assert!(doc.file_path.is_some() && doc.has_unsaved_changes);
+
+        doc.update_from_title("Untitled - Paint");
+        assert_eq!((doc.file_path, doc.last_saved_unix_ms), (None, None));
+    }
+}
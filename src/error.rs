@@ -53,6 +53,42 @@ pub enum MspMcpError {
     #[error("Element not found: {0}")]
     ElementNotFound(String), // 1016
 
+    #[error("Input blocked by privilege mismatch: {0}")]
+    ElevationMismatch(String), // 1017
+
+    #[error("Input desktop unavailable: {0}")]
+    InputDesktopUnavailable(String), // 1018
+
+    #[error("Method not found: {0}")]
+    MethodNotFound(String), // 1019
+
+    #[error("Unsupported protocol version: {0}")]
+    UnsupportedProtocolVersion(String), // 1020
+
+    #[error("Confirmation required: {0}")]
+    ConfirmationRequired(String), // 1021
+
+    #[error("Resource not found: {0}")]
+    ResourceNotFound(String), // 1022
+
+    #[error("Server is shutting down; only 'exit' is accepted")]
+    ShuttingDown, // 1023
+
+    #[error("Path not allowed: {0}")]
+    PathNotAllowed(String), // 1024
+
+    #[error("Payload too large: {message} (limit {limit} {unit})")]
+    PayloadTooLarge { message: String, limit: u64, unit: &'static str }, // 1025
+
+    #[error("Rate limited: {message}; retry in {retry_after_ms} ms")]
+    RateLimited { message: String, retry_after_ms: u64 }, // 1026
+
+    #[error("Internal error: the server panicked: {0}")]
+    Panicked(String), // 1027
+
+    #[error("Configuration error: {0}")]
+    ConfigError(String),
+
     #[error("Windows API error: {0}")]
     WindowsApiError(String),
 
@@ -92,7 +128,19 @@ impl MspMcpError {
             MspMcpError::ImageTransformationFailed(_) => 1014,
             MspMcpError::CanvasCreationFailed(_) => 1015,
             MspMcpError::ElementNotFound(_) => 1016,
+            MspMcpError::ElevationMismatch(_) => 1017,
+            MspMcpError::InputDesktopUnavailable(_) => 1018,
+            MspMcpError::MethodNotFound(_) => 1019,
+            MspMcpError::UnsupportedProtocolVersion(_) => 1020,
+            MspMcpError::ConfirmationRequired(_) => 1021,
+            MspMcpError::ResourceNotFound(_) => 1022,
+            MspMcpError::ShuttingDown => 1023,
+            MspMcpError::PathNotAllowed(_) => 1024,
+            MspMcpError::PayloadTooLarge { .. } => 1025,
+            MspMcpError::RateLimited { .. } => 1026,
+            MspMcpError::Panicked(_) => 1027,
             // Internal errors might map to a general code or have specific ones if needed
+            MspMcpError::ConfigError(_) => 1000,
             MspMcpError::WindowsApiError(_) => 1000,
             MspMcpError::UiAutomationError(_) => 1000,
             MspMcpError::IoError(_) => 1000,
@@ -102,7 +150,31 @@ impl MspMcpError {
     }
 }
 
+impl MspMcpError {
+    /// Standard JSON-RPC error code for this error. Our own code from
+    /// `code()` is sent alongside it in the error's `data`.
+    pub fn json_rpc_code(&self) -> i32 {
+        match self {
+            MspMcpError::MethodNotFound(_) => -32601,
+            MspMcpError::ShuttingDown => -32600, // Requests after shutdown are invalid
+            MspMcpError::ResourceNotFound(_) => -32002, // MCP's resource-not-found code
+            MspMcpError::InvalidParameters(_)
+            | MspMcpError::InvalidColorFormat(_)
+            | MspMcpError::InvalidTool(_)
+            | MspMcpError::InvalidShape(_)
+            | MspMcpError::UnsupportedProtocolVersion(_)
+            | MspMcpError::ConfirmationRequired(_) // Missing "confirm": true
+            | MspMcpError::PathNotAllowed(_) // Outside the configured allowed_dirs
+            | MspMcpError::PayloadTooLarge { .. } // Ask for less and retry
+            | MspMcpError::JsonError(_) // Params that don't match the method's struct
+            | MspMcpError::Base64DecodeError(_) => -32602,
+            _ => -32603,
+        }
+    }
+}
+
 // Implement From for UIAutomation errors
+#[cfg(feature = "windows-automation")]
 impl From<uiautomation::Error> for MspMcpError {
     fn from(err: uiautomation::Error) -> Self {
         MspMcpError::UiAutomationError(format!("{}", err))
@@ -0,0 +1,423 @@
+// Fault injection for the backend layer.
+//
+// FaultInjectingBackend wraps another backend and makes calls fail at random
+// with the probabilities from the `[faults]` config table, the way they fail
+// on a busy or flaky desktop: input that never arrives, ribbon controls UI
+// Automation can't find, a window that won't come to the foreground. It is
+// for exercising retry and recovery paths and for reproducing such reports
+// on demand; with a fixed seed the same calls fail on every run.
+
+use crate::backend::PaintBackend;
+use crate::capture::CapturedImage;
+use crate::config::FaultConfig;
+use crate::error::{MspMcpError, Result};
+use crate::input_record::RecordedInput;
+use crate::protocol::{AddTextParams, CanvasDimensionsResponse, MonitorInfo, MouseButton, PaintCapabilities, SaveCanvasParams, SetImagePropertiesParams, SetToolStateParams, UnsavedChanges, WindowInfoResponse};
+use tracing::{info, warn};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::backend::HWND;
+
+// Kinds of operation a fault can hit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultKind {
+    SendInput,
+    UiaLookup,
+    Activation,
+}
+
+pub struct FaultInjectingBackend {
+    inner: Arc<dyn PaintBackend>,
+    config: FaultConfig,
+    rng: Mutex<u64>, // xorshift64* state
+}
+
+impl FaultInjectingBackend {
+    pub fn new(inner: Arc<dyn PaintBackend>, config: FaultConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0)
+        });
+        info!("Fault injection enabled (seed {}): {:?}", seed, config);
+        // xorshift never leaves the all-zero state
+        FaultInjectingBackend { inner, config, rng: Mutex::new(seed.max(1)) }
+    }
+
+    // Uniform sample in [0, 1)
+    fn next_f64(&self) -> f64 {
+        let mut state = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        let value = state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Fails with the configured probability for `kind`
+    fn maybe_fail(&self, kind: FaultKind, operation: &str) -> Result<()> {
+        let probability = match kind {
+            FaultKind::SendInput => self.config.send_input,
+            FaultKind::UiaLookup => self.config.uia_lookup,
+            FaultKind::Activation => self.config.activation,
+        };
+        if probability <= 0.0 || self.next_f64() >= probability {
+            return Ok(());
+        }
+        warn!("Injecting {:?} fault into {}", kind, operation);
+        let message = format!("Injected fault in {}", operation);
+        Err(match kind {
+            FaultKind::SendInput => MspMcpError::WindowsApiError(message),
+            FaultKind::UiaLookup => MspMcpError::ElementNotFound(message),
+            FaultKind::Activation => MspMcpError::WindowActivationFailed(message),
+        })
+    }
+}
+
+impl PaintBackend for FaultInjectingBackend {
+    fn find_or_launch_paint(&self) -> Result<HWND> {
+        self.inner.find_or_launch_paint()
+    }
+
+    fn launch_paint_window(&self) -> Result<HWND> {
+        self.inner.launch_paint_window()
+    }
+
+    fn find_window_for_pid(&self, pid: u32) -> Option<HWND> {
+        self.inner.find_window_for_pid(pid)
+    }
+
+    fn is_window_owned_by(&self, hwnd: HWND, pid: u32) -> bool {
+        self.inner.is_window_owned_by(hwnd, pid)
+    }
+
+    fn window_pid(&self, hwnd: HWND) -> u32 {
+        self.inner.window_pid(hwnd)
+    }
+
+    fn window_title(&self, hwnd: HWND) -> String {
+        self.inner.window_title(hwnd)
+    }
+
+    fn paint_version(&self, hwnd: HWND) -> Option<String> {
+        self.inner.paint_version(hwnd)
+    }
+
+    fn capabilities(&self, hwnd: HWND) -> Result<PaintCapabilities> {
+        self.maybe_fail(FaultKind::UiaLookup, "capabilities")?;
+        self.inner.capabilities(hwnd)
+    }
+
+    fn window_info(&self, hwnd: HWND) -> Result<WindowInfoResponse> {
+        self.inner.window_info(hwnd)
+    }
+
+    fn ensure_window_on_monitor(&self, hwnd: HWND, monitor: Option<u32>) -> Result<MonitorInfo> {
+        self.inner.ensure_window_on_monitor(hwnd, monitor)
+    }
+
+    fn activate(&self, hwnd: HWND) -> Result<()> {
+        self.maybe_fail(FaultKind::Activation, "activate")?;
+        self.inner.activate(hwnd)
+    }
+
+    fn click(&self, hwnd: HWND, x: i32, y: i32) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "click")?;
+        self.inner.click(hwnd, x, y)
+    }
+
+    fn drag(&self, hwnd: HWND, start: (i32, i32), end: (i32, i32)) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "drag")?;
+        self.inner.drag(hwnd, start, end)
+    }
+
+    fn type_text(&self, text: &str) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "type_text")?;
+        self.inner.type_text(text)
+    }
+
+    fn save(&self, hwnd: HWND) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "save")?;
+        self.inner.save(hwnd)
+    }
+
+    fn undo(&self, hwnd: HWND) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "undo")?;
+        self.inner.undo(hwnd)
+    }
+
+    // Recovery itself is never sabotaged
+    fn release_held_input(&self) -> Result<()> {
+        self.inner.release_held_input()
+    }
+
+    fn cancel_gesture(&self) -> Result<()> {
+        self.inner.cancel_gesture()
+    }
+
+    fn terminate(&self, pid: u32) -> Result<()> {
+        self.inner.terminate(pid)
+    }
+
+    fn quit_paint(&self, hwnd: HWND, unsaved_changes: UnsavedChanges, timeout: Duration) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "quit_paint")?;
+        self.inner.quit_paint(hwnd, unsaved_changes, timeout)
+    }
+
+    fn replay_input(&self, events: &[RecordedInput], speed: f64) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "replay_input")?;
+        self.inner.replay_input(events, speed)
+    }
+
+    fn input_events_sent(&self) -> u64 {
+        self.inner.input_events_sent()
+    }
+
+    fn select_tool(&self, hwnd: HWND, tool: &str) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "select_tool")?;
+        self.inner.select_tool(hwnd, tool)
+    }
+
+    fn select_brush_type(&self, hwnd: HWND, brush_type: &str) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "select_brush_type")?;
+        self.inner.select_brush_type(hwnd, brush_type)
+    }
+
+    fn set_color(&self, hwnd: HWND, color: &str) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "set_color")?;
+        self.inner.set_color(hwnd, color)
+    }
+
+    fn set_secondary_color(&self, hwnd: HWND, color: &str) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "set_secondary_color")?;
+        self.inner.set_secondary_color(hwnd, color)
+    }
+
+    fn add_custom_color(&self, hwnd: HWND, color: &str, button: MouseButton) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "add_custom_color")?;
+        self.inner.add_custom_color(hwnd, color, button)
+    }
+
+    fn select_custom_color(&self, hwnd: HWND, index: usize, button: MouseButton) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "select_custom_color")?;
+        self.inner.select_custom_color(hwnd, index, button)
+    }
+
+    fn set_thickness(&self, hwnd: HWND, level: u32) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "set_thickness")?;
+        self.inner.set_thickness(hwnd, level)
+    }
+
+    fn set_thickness_px(&self, hwnd: HWND, px: u32) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "set_thickness_px")?;
+        self.inner.set_thickness_px(hwnd, px)
+    }
+
+    fn set_brush_size(&self, hwnd: HWND, size: u32, tool: Option<&str>) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "set_brush_size")?;
+        self.inner.set_brush_size(hwnd, size, tool)
+    }
+
+    fn set_fill(&self, hwnd: HWND, fill_type: &str) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "set_fill")?;
+        self.inner.set_fill(hwnd, fill_type)
+    }
+
+    fn set_tool_state(&self, hwnd: HWND, params: &SetToolStateParams) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "set_tool_state")?;
+        self.inner.set_tool_state(hwnd, params)
+    }
+
+    fn canvas_dimensions(&self, hwnd: HWND) -> Result<(u32, u32)> {
+        self.inner.canvas_dimensions(hwnd)
+    }
+
+    fn canvas_view(&self, hwnd: HWND) -> Result<CanvasDimensionsResponse> {
+        self.inner.canvas_view(hwnd)
+    }
+
+    fn initial_canvas_dimensions(&self, hwnd: HWND) -> Result<(u32, u32)> {
+        self.inner.initial_canvas_dimensions(hwnd)
+    }
+
+    fn draw_pixel(&self, hwnd: HWND, x: i32, y: i32, button: MouseButton) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "draw_pixel")?;
+        self.inner.draw_pixel(hwnd, x, y, button)
+    }
+
+    fn draw_line(&self, hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "draw_line")?;
+        self.inner.draw_line(hwnd, start_x, start_y, end_x, end_y, button)
+    }
+
+    fn draw_shape(&self, hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "draw_shape")?;
+        self.inner.draw_shape(hwnd, shape_type, start_x, start_y, end_x, end_y, button)
+    }
+
+    fn draw_polyline(&self, hwnd: HWND, points: &[(i32, i32)], button: MouseButton) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "draw_polyline")?;
+        self.inner.draw_polyline(hwnd, points, button)
+    }
+
+    fn clear_canvas(&self, hwnd: HWND) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "clear_canvas")?;
+        self.inner.clear_canvas(hwnd)
+    }
+
+    fn select_region(&self, hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "select_region")?;
+        self.inner.select_region(hwnd, start_x, start_y, end_x, end_y)
+    }
+
+    fn copy_selection(&self, hwnd: HWND) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "copy_selection")?;
+        self.inner.copy_selection(hwnd)
+    }
+
+    fn copy_selection_image(&self, hwnd: HWND) -> Result<CapturedImage> {
+        self.maybe_fail(FaultKind::SendInput, "copy_selection_image")?;
+        self.inner.copy_selection_image(hwnd)
+    }
+
+    fn copy_canvas_image(&self, hwnd: HWND) -> Result<CapturedImage> {
+        self.maybe_fail(FaultKind::SendInput, "copy_canvas_image")?;
+        self.inner.copy_canvas_image(hwnd)
+    }
+
+    fn paste_at(&self, hwnd: HWND, x: i32, y: i32) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "paste_at")?;
+        self.inner.paste_at(hwnd, x, y)
+    }
+
+    fn set_clipboard_image(&self, image: &CapturedImage) -> Result<()> {
+        self.inner.set_clipboard_image(image)
+    }
+
+    fn stamp_clipboard_image(&self, hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "stamp_clipboard_image")?;
+        self.inner.stamp_clipboard_image(hwnd, x, y, width, height)
+    }
+
+    fn rotate_selection(&self, hwnd: HWND, degrees: u32) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "rotate_selection")?;
+        self.inner.rotate_selection(hwnd, degrees)
+    }
+
+    fn flip_selection(&self, hwnd: HWND, direction: &str) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "flip_selection")?;
+        self.inner.flip_selection(hwnd, direction)
+    }
+
+    fn invert_colors(&self, hwnd: HWND, selection_only: bool) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "invert_colors")?;
+        self.inner.invert_colors(hwnd, selection_only)
+    }
+
+    fn add_text(&self, hwnd: HWND, params: &AddTextParams) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "add_text")?;
+        self.inner.add_text(hwnd, params)
+    }
+
+    fn new_document(&self, hwnd: HWND, unsaved_changes: UnsavedChanges) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "new_document")?;
+        self.inner.new_document(hwnd, unsaved_changes)
+    }
+
+    fn create_canvas(&self, hwnd: HWND, width: u32, height: u32, background_color: Option<&str>, unsaved_changes: UnsavedChanges) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "create_canvas")?;
+        self.inner.create_canvas(hwnd, width, height, background_color, unsaved_changes)
+    }
+
+    fn save_canvas(&self, hwnd: HWND, params: &SaveCanvasParams) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "save_canvas")?;
+        self.inner.save_canvas(hwnd, params)
+    }
+
+    fn save_as(&self, hwnd: HWND, params: &SaveCanvasParams) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "save_as")?;
+        self.inner.save_as(hwnd, params)
+    }
+
+    fn set_image_properties(&self, hwnd: HWND, params: &SetImagePropertiesParams) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "set_image_properties")?;
+        self.inner.set_image_properties(hwnd, params)
+    }
+
+    fn set_background(&self, hwnd: HWND, color: &str) -> Result<()> {
+        self.maybe_fail(FaultKind::SendInput, "set_background")?;
+        self.inner.set_background(hwnd, color)
+    }
+
+    fn add_layer(&self, hwnd: HWND) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "add_layer")?;
+        self.inner.add_layer(hwnd)
+    }
+
+    fn select_layer(&self, hwnd: HWND, index: u32) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "select_layer")?;
+        self.inner.select_layer(hwnd, index)
+    }
+
+    fn set_layer_visibility(&self, hwnd: HWND, index: u32, visible: bool) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "set_layer_visibility")?;
+        self.inner.set_layer_visibility(hwnd, index, visible)
+    }
+
+    fn merge_layer_down(&self, hwnd: HWND, index: u32) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "merge_layer_down")?;
+        self.inner.merge_layer_down(hwnd, index)
+    }
+
+    fn delete_layer(&self, hwnd: HWND, index: u32) -> Result<()> {
+        self.maybe_fail(FaultKind::UiaLookup, "delete_layer")?;
+        self.inner.delete_layer(hwnd, index)
+    }
+
+    fn capture_window(&self, hwnd: HWND) -> Result<CapturedImage> {
+        self.inner.capture_window(hwnd)
+    }
+
+    fn capture_canvas(&self, hwnd: HWND) -> Result<CapturedImage> {
+        self.inner.capture_canvas(hwnd)
+    }
+
+    fn capture_canvas_region(&self, hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<CapturedImage> {
+        self.inner.capture_canvas_region(hwnd, x, y, width, height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{MockBackend, MOCK_HWND};
+
+    fn faulty(send_input: f64, seed: u64) -> (FaultInjectingBackend, Arc<MockBackend>) {
+        let mock = Arc::new(MockBackend::default());
+        let config = FaultConfig { send_input, seed: Some(seed), ..FaultConfig::default() };
+        (FaultInjectingBackend::new(mock.clone(), config), mock)
+    }
+
+    #[test]
+    fn test_certain_fault_skips_the_inner_call() {
+        let (backend, mock) = faulty(1.0, 1);
+        let err = backend.click(MOCK_HWND, 1, 2).unwrap_err();
+        assert!(matches!(err, MspMcpError::WindowsApiError(_)));
+        // Other kinds of call are untouched
+        backend.set_color(MOCK_HWND, "#000000").unwrap();
+        assert_eq!(mock.take_calls(), vec!["set_color #000000"]);
+    }
+
+    #[test]
+    fn test_faults_follow_probability_and_seed() {
+        let outcomes = |seed| {
+            let (backend, _) = faulty(0.3, seed);
+            (0..1000).map(|i| backend.draw_pixel(MOCK_HWND, i, 0, MouseButton::Left).is_err()).collect::<Vec<_>>()
+        };
+        let first = outcomes(42);
+        assert_eq!(first, outcomes(42));
+        let failures = first.iter().filter(|failed| **failed).count();
+        assert!((200..400).contains(&failures), "{} failures", failures);
+    }
+}
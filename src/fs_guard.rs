@@ -0,0 +1,216 @@
+// Checks on file paths supplied by clients.
+//
+// Requests come from an LLM, so every path a method reads or writes
+// (save_canvas, fetch_image, input recordings) goes through `check_path`
+// before anything touches the disk. It expands `%VARIABLES%`, rejects
+// relative paths, `..`, device paths, UNC shares (unless allowed), names
+// Windows can't store, and paths running through symlinks or junctions,
+// then enforces the configured `allowed_dirs`.
+
+use crate::config::ServerConfig;
+use crate::error::{MspMcpError, Result};
+use std::path::{Component, Path, PathBuf};
+
+/// Expands `%NAME%` environment variables in a path, failing on unset ones.
+pub fn expand_env_vars(path: &str) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = path;
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('%') else {
+            return Err(MspMcpError::InvalidParameters(format!("Unterminated %VARIABLE% in '{}'", path)));
+        };
+        let name = &after[..end];
+        let value = std::env::var(name).map_err(|_|
+            MspMcpError::InvalidParameters(format!("Environment variable %{}% in '{}' is not set", name, path)))?;
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Validates a client-supplied path and returns it with variables expanded.
+pub fn check_path(file_path: &str, config: &ServerConfig) -> Result<PathBuf> {
+    let expanded = expand_env_vars(file_path)?;
+    let path = PathBuf::from(&expanded);
+
+    let unified = expanded.replace('/', "\\");
+    if unified.starts_with("\\\\?\\") || unified.starts_with("\\\\.\\") {
+        return Err(MspMcpError::PathNotAllowed(format!("Device path '{}' is not allowed", expanded)));
+    }
+    if unified.starts_with("\\\\") && !config.allow_unc_paths {
+        return Err(MspMcpError::PathNotAllowed(format!("Network path '{}' is not allowed; set allow_unc_paths to permit it", expanded)));
+    }
+    if !path.is_absolute() {
+        return Err(MspMcpError::InvalidParameters(format!("file_path must be absolute, got '{}'", expanded)));
+    }
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                let name = name.to_string_lossy();
+                if let Some(c) = name.chars().find(|c| c.is_control() || "<>:\"|?*".contains(*c)) {
+                    return Err(MspMcpError::InvalidParameters(format!("'{}' contains the invalid character {:?}", name, c)));
+                }
+                if name.ends_with(' ') || name.ends_with('.') {
+                    return Err(MspMcpError::InvalidParameters(format!("'{}' may not end with a space or a dot", name)));
+                }
+            }
+            Component::ParentDir => {
+                return Err(MspMcpError::PathNotAllowed(format!("file_path may not contain '..', got '{}'", expanded)));
+            }
+            _ => {}
+        }
+    }
+    if path.file_name().is_none() {
+        return Err(MspMcpError::InvalidParameters(format!("file_path '{}' has no file name", expanded)));
+    }
+
+    // A link anywhere along the way could point outside the allowed directories
+    for ancestor in path.ancestors() {
+        if let Ok(meta) = std::fs::symlink_metadata(ancestor) {
+            if is_reparse_point(&meta) {
+                return Err(MspMcpError::PathNotAllowed(format!(
+                    "{} is a symbolic link or junction", ancestor.display())));
+            }
+        }
+    }
+
+    check_allowed(&path, &config.allowed_dirs)?;
+    Ok(path)
+}
+
+/// Fails with PathNotAllowed unless `path` is inside one of `allowed_dirs`.
+/// Empty means everything is allowed. Both sides are resolved through the
+/// file system as far as they exist, so differently-cased or otherwise
+/// equivalent spellings compare equal.
+pub fn check_allowed(path: &Path, allowed_dirs: &[PathBuf]) -> Result<()> {
+    if allowed_dirs.is_empty() {
+        return Ok(());
+    }
+    let resolved = resolve_existing_prefix(path);
+    if allowed_dirs.iter().any(|dir| resolved.starts_with(resolve_existing_prefix(dir))) {
+        return Ok(());
+    }
+    Err(MspMcpError::PathNotAllowed(format!("{} is outside the allowed directories", path.display())))
+}
+
+/// Canonicalizes the longest existing ancestor of `path` and appends the
+/// rest, so paths that don't exist yet can still be compared.
+fn resolve_existing_prefix(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return rest.iter().rev().fold(canonical, |acc, name| acc.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn is_reparse_point(meta: &std::fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+    meta.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0
+}
+
+#[cfg(not(windows))]
+fn is_reparse_point(meta: &std::fs::Metadata) -> bool {
+    meta.file_type().is_symlink()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mspmcp-guard-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_expand_env_vars() {
+        std::env::set_var("MSPMCP_GUARD_VAR", "expanded");
+        assert_eq!(expand_env_vars("%MSPMCP_GUARD_VAR%\\a.png").unwrap(), "expanded\\a.png");
+        assert_eq!(expand_env_vars("no variables").unwrap(), "no variables");
+        assert!(expand_env_vars("%MSPMCP_GUARD_UNSET%\\a.png").is_err());
+        assert!(expand_env_vars("50%.png").is_err());
+    }
+
+    #[test]
+    fn test_rejects_traversal_and_bad_names() {
+        let dir = scratch_dir("names");
+        let config = ServerConfig::default();
+        let path = |name: &str| dir.join(name).display().to_string();
+
+        assert!(check_path(&path("ok.png"), &config).is_ok());
+        assert!(matches!(check_path("relative.png", &config), Err(MspMcpError::InvalidParameters(_))));
+        let traversal = format!("{}/../escape.png", dir.display());
+        assert!(matches!(check_path(&traversal, &config), Err(MspMcpError::PathNotAllowed(_))));
+        assert!(check_path(&path("a<b.png"), &config).is_err());
+        assert!(check_path(&path("trailing."), &config).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unc_and_device_paths() {
+        let config = ServerConfig::default();
+        assert!(matches!(check_path("\\\\server\\share\\a.png", &config), Err(MspMcpError::PathNotAllowed(_))));
+        assert!(matches!(check_path("//server/share/a.png", &config), Err(MspMcpError::PathNotAllowed(_))));
+        let config = ServerConfig { allow_unc_paths: true, ..ServerConfig::default() };
+        assert!(matches!(check_path("\\\\?\\C:\\a.png", &config), Err(MspMcpError::PathNotAllowed(_))));
+        assert!(matches!(check_path("\\\\.\\PhysicalDrive0", &config), Err(MspMcpError::PathNotAllowed(_))));
+    }
+
+    #[test]
+    fn test_allowed_dirs_limit_file_access() {
+        assert!(check_allowed(Path::new("/anywhere/at/all.png"), &[]).is_ok());
+
+        let root = scratch_dir("allowed");
+        let allowed = root.join("out");
+        std::fs::create_dir_all(&allowed).unwrap();
+        let allowed_dirs = vec![allowed.clone()];
+
+        assert!(check_allowed(&allowed.join("new").join("a.png"), &allowed_dirs).is_ok());
+        assert!(matches!(check_allowed(&root.join("a.png"), &allowed_dirs), Err(MspMcpError::PathNotAllowed(_))));
+        assert!(check_allowed(&root.join("outside").join("a.png"), &allowed_dirs).is_err()); // Not a string prefix match
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rejects_paths_through_links() {
+        let root = scratch_dir("links");
+        std::fs::create_dir_all(root.join("real")).unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("link")).unwrap();
+        let config = ServerConfig::default();
+
+        assert!(check_path(&root.join("real").join("a.png").display().to_string(), &config).is_ok());
+        let linked = root.join("link").join("a.png").display().to_string();
+        assert!(matches!(check_path(&linked, &config), Err(MspMcpError::PathNotAllowed(_))));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_rejects_paths_through_links() {
+        let root = scratch_dir("links");
+        std::fs::create_dir_all(root.join("real")).unwrap();
+        // Creating symlinks needs developer mode or elevation; skip without it
+        if std::os::windows::fs::symlink_dir(root.join("real"), root.join("link")).is_ok() {
+            let linked = root.join("link").join("a.png").display().to_string();
+            assert!(matches!(check_path(&linked, &ServerConfig::default()), Err(MspMcpError::PathNotAllowed(_))));
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
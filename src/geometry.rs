@@ -0,0 +1,891 @@
+// Coordinate and rectangle math for the Win32 automation code.
+//
+// Kept free of Win32 calls so it can be property-tested. Intermediate
+// values are computed in i64 and results are clamped rather than cast, so
+// extreme window positions or sizes saturate instead of wrapping around.
+
+use crate::protocol::{CanvasDimensionsResponse, CoordinateSpace, RectInfo};
+
+// Full scale of SendInput's absolute (normalized) coordinates
+pub const NORMALIZED_MAX: i32 = 65535;
+
+// DPI at which Windows applies no scaling
+pub const DEFAULT_DPI: u32 = 96;
+
+/// Origin and size of the virtual desktop: (x, y, width, height).
+pub type ScreenRect = (i32, i32, i32, i32);
+
+/// Width and height of a rectangle given by its edges. Inverted
+/// rectangles are empty rather than enormous.
+pub fn rect_size(left: i32, top: i32, right: i32, bottom: i32) -> (u32, u32) {
+    let extent = |from: i32, to: i32| (to as i64 - from as i64).max(0) as u32;
+    (extent(left, right), extent(top, bottom))
+}
+
+/// Converts a screen position to SendInput's normalized 0-65535 range over
+/// the virtual desktop. Positions off the desktop are clamped to its edge.
+pub fn screen_to_normalized(x: i32, y: i32, screen: ScreenRect) -> (i32, i32) {
+    let (origin_x, origin_y, width, height) = screen;
+    let axis = |value: i32, origin: i32, extent: i32| {
+        let scaled = (value as i64 - origin as i64) * NORMALIZED_MAX as i64 / extent.max(1) as i64;
+        scaled.clamp(0, NORMALIZED_MAX as i64) as i32
+    };
+    (axis(x, origin_x, width), axis(y, origin_y, height))
+}
+
+/// Converts a normalized (0-65535) position back to screen coordinates,
+/// rounding to the nearest pixel.
+pub fn normalized_to_screen(normalized_x: i32, normalized_y: i32, screen: ScreenRect) -> (i32, i32) {
+    let (origin_x, origin_y, width, height) = screen;
+    let axis = |value: i32, origin: i32, extent: i32| {
+        let value = value.clamp(0, NORMALIZED_MAX) as i64;
+        let offset = (value * extent.max(0) as i64 + NORMALIZED_MAX as i64 / 2) / NORMALIZED_MAX as i64;
+        (origin as i64 + offset).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+    };
+    (axis(normalized_x, origin_x, width), axis(normalized_y, origin_y, height))
+}
+
+/// The DPI to compute with: GetDpiForWindow reports 0 for invalid windows.
+pub fn effective_dpi(dpi: u32) -> u32 {
+    if dpi == 0 { DEFAULT_DPI } else { dpi }
+}
+
+/// Display scaling for a DPI (1.0 at 96 DPI, 1.5 at 144).
+pub fn scale_factor(dpi: u32) -> f64 {
+    effective_dpi(dpi) as f64 / DEFAULT_DPI as f64
+}
+
+/// Computes where a window should go so it lies fully inside a work area.
+/// Windows larger than the work area are shrunk to fit.
+pub fn fit_rect_within(window: RectInfo, work: RectInfo) -> RectInfo {
+    let width = window.width.clamp(0, work.width.max(0));
+    let height = window.height.clamp(0, work.height.max(0));
+    let left = window.left.clamp(work.left, work.left + work.width.max(0) - width);
+    let top = window.top.clamp(work.top, work.top + work.height.max(0) - height);
+    RectInfo::new(left, top, left + width, top + height)
+}
+
+/// Approximates the canvas dimensions from the outer window size by
+/// subtracting the typical sizes of Paint's UI elements.
+pub fn compute_canvas_dimensions(window_width: u32, window_height: u32) -> (u32, u32) {
+    // These values are estimates and may need adjustment based on actual Win11 Paint UI
+    const TITLE_BAR_HEIGHT: u32 = 32;
+    const MENU_BAR_HEIGHT: u32 = 30;
+    const TOOLBAR_HEIGHT: u32 = 80;  // Combined height of ribbon/toolbar
+    const STATUS_BAR_HEIGHT: u32 = 25;
+    const LEFT_PANEL_WIDTH: u32 = 0;  // No left panel in modern Paint
+    const RIGHT_PANEL_WIDTH: u32 = 270; // Right tools/properties panel
+
+    // Calculate canvas dimensions by subtracting UI elements
+    // Ensure we don't underflow if window is very small
+    let canvas_width = window_width.saturating_sub(LEFT_PANEL_WIDTH + RIGHT_PANEL_WIDTH);
+    let canvas_height = window_height.saturating_sub(
+        TITLE_BAR_HEIGHT + MENU_BAR_HEIGHT + TOOLBAR_HEIGHT + STATUS_BAR_HEIGHT
+    );
+
+    (canvas_width, canvas_height)
+}
+
+/// Parses the image size in Paint's status bar, e.g. "1152 × 648px".
+/// Digit grouping ("1,920 × 1,080px") is accepted; the cursor position
+/// ("120, 45px") is not a size.
+pub fn parse_image_size(text: &str) -> Option<(u32, u32)> {
+    let text = text.trim().trim_end_matches("px").trim_end();
+    let (width, height) = text.split_once(['×', 'x', 'X'])?;
+    let number = |part: &str| -> Option<u32> {
+        let digits: String = part.chars().filter(|c| !matches!(c, ',' | '.' | ' ' | '\u{a0}' | '\u{202f}')).collect();
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+    Some((number(width)?, number(height)?))
+}
+
+/// Parses Paint's zoom readout, e.g. "100%" or "12,5 %", as a factor (1.0 = 100%).
+pub fn parse_zoom(text: &str) -> Option<f64> {
+    let percent: f64 = text.trim().strip_suffix('%')?.trim().replace(',', ".").parse().ok()?;
+    (percent.is_finite() && percent > 0.0).then_some(percent / 100.0)
+}
+
+/// Image pixels scrolled out of view along one axis, given the UI Automation
+/// scroll percentage (negative when the axis can't scroll), the document
+/// length in image pixels, the zoom and the viewport length in screen pixels.
+pub fn scroll_offset(percent: f64, document: u32, zoom: f64, viewport: u32) -> u32 {
+    if percent.is_nan() || percent <= 0.0 || zoom.is_nan() || zoom <= 0.0 {
+        return 0;
+    }
+    let hidden = (document as f64 * zoom - viewport as f64).max(0.0);
+    (hidden * percent.min(100.0) / 100.0 / zoom).round() as u32
+}
+
+/// The inverse of scroll_offset: the UI Automation scroll percentage that
+/// scrolls `offset` image pixels out of view, or -1 (leave the axis alone)
+/// when the whole document fits in the viewport.
+pub fn scroll_percent(offset: u32, document: u32, zoom: f64, viewport: u32) -> f64 {
+    if zoom.is_nan() || zoom <= 0.0 {
+        return -1.0;
+    }
+    let hidden = document as f64 * zoom - viewport as f64;
+    if hidden <= 0.0 {
+        return -1.0;
+    }
+    (offset as f64 * zoom * 100.0 / hidden).clamp(0.0, 100.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Measure {
+    Position, // A point on the axis
+    Size,     // A length along it
+}
+
+/// Where the canvas is shown: what converting a request's coordinates to
+/// canvas pixels needs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CanvasFrame {
+    pub width: u32,                  // Document size in image pixels
+    pub height: u32,
+    pub zoom: f64,                   // 1.0 is 100%
+    pub viewport_origin: (i32, i32), // Screen position of the canvas viewport's top-left corner
+    pub viewport_size: (u32, u32),   // and its size in screen pixels
+    pub scroll: (u32, u32),          // Image pixels scrolled out of view on the left and at the top
+    pub client_origin: (i32, i32),   // Screen position of the window's client area
+}
+
+impl CanvasFrame {
+    /// A frame for a canvas of `width` x `height` shown unzoomed at the screen origin.
+    pub fn of_size(width: u32, height: u32) -> Self {
+        CanvasFrame {
+            width, height, zoom: 1.0, viewport_origin: (0, 0), viewport_size: (width, height), scroll: (0, 0), client_origin: (0, 0),
+        }
+    }
+
+    /// The frame of a canvas view, in a window whose client area starts at `client_origin`.
+    pub fn from_view(view: &CanvasDimensionsResponse, client_origin: (i32, i32)) -> Self {
+        CanvasFrame {
+            width: view.width,
+            height: view.height,
+            zoom: view.zoom,
+            viewport_origin: (view.viewport.left, view.viewport.top),
+            viewport_size: (view.viewport.width.max(0) as u32, view.viewport.height.max(0) as u32),
+            scroll: (view.scroll_x, view.scroll_y),
+            client_origin,
+        }
+    }
+
+    /// Converts one coordinate given in `space` to canvas pixels. Every
+    /// coordinate space a request can use is turned into canvas pixels here
+    /// and nowhere else. Normalized values are rounded; a screen or client
+    /// position lands in the image pixel under it, and points off the
+    /// canvas convert to positions off the canvas.
+    pub fn to_canvas(&self, value: f64, space: CoordinateSpace, axis: Axis, measure: Measure) -> i32 {
+        let (extent, viewport, scroll, client) = match axis {
+            Axis::Horizontal => (self.width, self.viewport_origin.0, self.scroll.0, self.client_origin.0),
+            Axis::Vertical => (self.height, self.viewport_origin.1, self.scroll.1, self.client_origin.1),
+        };
+        let zoom = self.zoom();
+        let canvas = match (space, measure) {
+            (CoordinateSpace::Canvas, _) => value,
+            // The last pixel, not one past it, so 1.0 stays on the canvas
+            (CoordinateSpace::Normalized, Measure::Position) => (value * extent.saturating_sub(1) as f64).round(),
+            (CoordinateSpace::Normalized, Measure::Size) => (value * extent as f64).round(),
+            (CoordinateSpace::Client, Measure::Position) => {
+                scroll as f64 + ((value + client as f64 - viewport as f64) / zoom).floor()
+            }
+            (CoordinateSpace::Screen, Measure::Position) => scroll as f64 + ((value - viewport as f64) / zoom).floor(),
+            (CoordinateSpace::Client | CoordinateSpace::Screen, Measure::Size) => (value / zoom).round(),
+        };
+        canvas.clamp(i32::MIN as f64, i32::MAX as f64) as i32
+    }
+
+    /// Screen position of the middle of canvas pixel (x, y).
+    pub fn to_screen(&self, x: i32, y: i32) -> (i32, i32) {
+        let zoom = self.zoom();
+        let axis = |value: i32, scroll: u32, origin: i32| {
+            let screen = origin as f64 + (value as f64 - scroll as f64 + 0.5) * zoom;
+            screen.floor().clamp(i32::MIN as f64, i32::MAX as f64) as i32
+        };
+        (axis(x, self.scroll.0, self.viewport_origin.0), axis(y, self.scroll.1, self.viewport_origin.1))
+    }
+
+    /// How many whole canvas pixels the viewport shows across and down.
+    pub fn visible_size(&self) -> (u32, u32) {
+        let zoom = self.zoom();
+        let axis = |viewport: u32, extent: u32| ((viewport as f64 / zoom).floor() as u32).min(extent);
+        (axis(self.viewport_size.0, self.width), axis(self.viewport_size.1, self.height))
+    }
+
+    /// True if canvas pixel (x, y) is in view.
+    pub fn is_visible(&self, x: i32, y: i32) -> bool {
+        let (visible_width, visible_height) = self.visible_size();
+        let axis = |value: i32, scroll: u32, visible: u32| (scroll as i64..scroll as i64 + visible as i64).contains(&(value as i64));
+        axis(x, self.scroll.0, visible_width) && axis(y, self.scroll.1, visible_height)
+    }
+
+    /// The scroll position that shows canvas pixels `min` to `max`
+    /// (inclusive corners). Axes already in view keep their position; the
+    /// others center the pixels, as far as the document allows.
+    pub fn scroll_to_show(&self, min: (i32, i32), max: (i32, i32)) -> (u32, u32) {
+        let (visible_width, visible_height) = self.visible_size();
+        let axis = |low: i32, high: i32, scroll: u32, visible: u32, extent: u32| {
+            let (low, high, scroll) = (low as i64, high as i64, scroll as i64);
+            if low >= scroll && high < scroll + visible as i64 {
+                return scroll as u32;
+            }
+            let centered = (low + high + 1) / 2 - visible as i64 / 2;
+            centered.clamp(0, extent.saturating_sub(visible) as i64) as u32
+        };
+        (axis(min.0, max.0, self.scroll.0, visible_width, self.width),
+            axis(min.1, max.1, self.scroll.1, visible_height, self.height))
+    }
+
+    fn zoom(&self) -> f64 {
+        if self.zoom.is_finite() && self.zoom > 0.0 { self.zoom } else { 1.0 }
+    }
+}
+
+/// `value` rounded to the nearest multiple of `grid` (halfway rounds up).
+pub fn snap_to_grid(value: i32, grid: u32) -> i32 {
+    let grid = grid.max(1) as i64;
+    ((value as i64 + grid / 2).div_euclid(grid) * grid).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+/// True if pixel (x, y) is on a canvas of `size`.
+pub fn on_canvas(point: (i32, i32), size: (u32, u32)) -> bool {
+    point.0 >= 0 && point.1 >= 0 && (point.0 as u32) < size.0 && (point.1 as u32) < size.1
+}
+
+/// The top-left and bottom-right corners (inclusive) of the smallest
+/// rectangle holding all of `points`, or None if there are none.
+pub fn bounding_box(points: &[(i32, i32)]) -> Option<((i32, i32), (i32, i32))> {
+    let &first = points.first()?;
+    Some(points.iter().fold((first, first), |(min, max), &(x, y)| ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))))
+}
+
+/// Clips the segment from `a` to `b` to a canvas of `size` (Cohen-Sutherland).
+/// None if none of it is on the canvas.
+pub fn clip_segment(a: (i32, i32), b: (i32, i32), size: (u32, u32)) -> Option<((i32, i32), (i32, i32))> {
+    const LEFT: u8 = 1;
+    const RIGHT: u8 = 2;
+    const TOP: u8 = 4;
+    const BOTTOM: u8 = 8;
+    if size.0 == 0 || size.1 == 0 {
+        return None;
+    }
+    let (max_x, max_y) = ((size.0 - 1) as f64, (size.1 - 1) as f64);
+    let outcode = |(x, y): (f64, f64)| {
+        let horizontal = if x < 0.0 { LEFT } else if x > max_x { RIGHT } else { 0 };
+        let vertical = if y < 0.0 { TOP } else if y > max_y { BOTTOM } else { 0 };
+        horizontal | vertical
+    };
+    let (mut p, mut q) = ((a.0 as f64, a.1 as f64), (b.0 as f64, b.1 as f64));
+    let (mut code_p, mut code_q) = (outcode(p), outcode(q));
+    while code_p | code_q != 0 {
+        if code_p & code_q != 0 {
+            return None;
+        }
+        // Move an outside end onto the edge it is beyond
+        let out = if code_p != 0 { code_p } else { code_q };
+        let (dx, dy) = (q.0 - p.0, q.1 - p.1);
+        let moved = if out & TOP != 0 {
+            (p.0 + dx * -p.1 / dy, 0.0)
+        } else if out & BOTTOM != 0 {
+            (p.0 + dx * (max_y - p.1) / dy, max_y)
+        } else if out & LEFT != 0 {
+            (0.0, p.1 + dy * -p.0 / dx)
+        } else {
+            (max_x, p.1 + dy * (max_x - p.0) / dx)
+        };
+        if code_p != 0 {
+            p = moved;
+            code_p = outcode(p);
+        } else {
+            q = moved;
+            code_q = outcode(q);
+        }
+    }
+    let round = |(x, y): (f64, f64)| (x.round() as i32, y.round() as i32);
+    Some((round(p), round(q)))
+}
+
+/// The parts of the path through `points` that are on a canvas of `size`,
+/// each as its own run of points. A `closed` path also runs from its last
+/// point back to the first.
+pub fn clip_path(points: &[(i32, i32)], size: (u32, u32), closed: bool) -> Vec<Vec<(i32, i32)>> {
+    let closing = match (points.first(), points.last()) {
+        (Some(&first), Some(&last)) if closed && points.len() > 2 => Some((last, first)),
+        _ => None,
+    };
+    let mut runs: Vec<Vec<(i32, i32)>> = Vec::new();
+    for (a, b) in points.windows(2).map(|pair| (pair[0], pair[1])).chain(closing) {
+        let Some((from, to)) = clip_segment(a, b, size) else { continue };
+        match runs.last_mut() {
+            Some(run) if run.last() == Some(&from) => run.push(to),
+            _ => runs.push(vec![from, to]),
+        }
+    }
+    // A closed path that leaves the canvas and comes back ends where it began
+    if closing.is_some() && runs.len() > 1 && runs.last().and_then(|run| run.last()) == runs[0].first() {
+        let mut last = runs.pop().unwrap_or_default();
+        last.extend_from_slice(&runs[0][1..]);
+        runs[0] = last;
+    }
+    runs
+}
+
+/// Clips the polygon with corners `points` to a canvas of `size`
+/// (Sutherland-Hodgman). Empty if none of it is on the canvas.
+pub fn clip_polygon(points: &[(i32, i32)], size: (u32, u32)) -> Vec<(i32, i32)> {
+    if size.0 == 0 || size.1 == 0 {
+        return Vec::new();
+    }
+    let (max_x, max_y) = ((size.0 - 1) as f64, (size.1 - 1) as f64);
+    let mut polygon: Vec<(f64, f64)> = points.iter().map(|&(x, y)| (x as f64, y as f64)).collect();
+    // Each canvas edge: the axis it is across, where, and which side is kept
+    for (vertical, bound, keep_below) in [(false, 0.0, false), (false, max_x, true), (true, 0.0, false), (true, max_y, true)] {
+        let coordinate = |p: (f64, f64)| if vertical { p.1 } else { p.0 };
+        let inside = |p: (f64, f64)| if keep_below { coordinate(p) <= bound } else { coordinate(p) >= bound };
+        let crossing = |a: (f64, f64), b: (f64, f64)| {
+            let t = (bound - coordinate(a)) / (coordinate(b) - coordinate(a));
+            let along = (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+            if vertical { (along.0, bound) } else { (bound, along.1) }
+        };
+        let corners = std::mem::take(&mut polygon);
+        for (i, &current) in corners.iter().enumerate() {
+            let previous = corners[(i + corners.len() - 1) % corners.len()];
+            match (inside(previous), inside(current)) {
+                (true, true) => polygon.push(current),
+                (true, false) => polygon.push(crossing(previous, current)),
+                (false, true) => polygon.extend([crossing(previous, current), current]),
+                (false, false) => {}
+            }
+        }
+    }
+    let mut clipped: Vec<(i32, i32)> = polygon.iter().map(|&(x, y)| (x.round() as i32, y.round() as i32)).collect();
+    clipped.dedup();
+    if clipped.len() > 1 && clipped.first() == clipped.last() {
+        clipped.pop();
+    }
+    clipped
+}
+
+/// The same path through fewer points: repeated points, and points in the
+/// middle of a straight run, are dropped. Turns and reversals are kept.
+pub fn simplify_straight_runs(points: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    let mut kept: Vec<(i32, i32)> = Vec::with_capacity(points.len());
+    for &point in points {
+        if kept.last() == Some(&point) {
+            continue;
+        }
+        if let [.., a, b] = kept[..] {
+            let (ab, bc) = ((b.0 as i64 - a.0 as i64, b.1 as i64 - a.1 as i64), (point.0 as i64 - b.0 as i64, point.1 as i64 - b.1 as i64));
+            let straight_on = ab.0 * bc.1 == ab.1 * bc.0 && ab.0 * bc.0 + ab.1 * bc.1 > 0;
+            if straight_on {
+                kept.pop();
+            }
+        }
+        kept.push(point);
+    }
+    kept
+}
+
+// Pixels between the points a smoothed curve is resampled at
+const SMOOTHING_STEP_PX: f64 = 4.0;
+// Most points one segment of a smoothed curve is resampled into
+const MAX_SMOOTHING_STEPS: f64 = 250.0;
+
+/// A Catmull-Rom spline through `points`, resampled about every
+/// SMOOTHING_STEP_PX pixels, so a few points become a smooth curve through
+/// all of them. A path that ends where it starts is smoothed all the way
+/// round; otherwise the ends are held by repeating the first and last point.
+pub fn catmull_rom(points: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let given: Vec<(f64, f64)> = points.iter().map(|&(x, y)| (x as f64, y as f64)).collect();
+    let last = given.len() - 1;
+    let closed = points[0] == points[last] && points.len() > 3;
+    let at = |i: isize| if closed { given[i.rem_euclid(last as isize) as usize] } else { given[i.clamp(0, last as isize) as usize] };
+
+    let mut curve = vec![points[0]];
+    for i in 0..last as isize {
+        let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+        let steps = ((p2.0 - p1.0).hypot(p2.1 - p1.1) / SMOOTHING_STEP_PX).ceil().clamp(1.0, MAX_SMOOTHING_STEPS) as usize;
+        for step in 1..=steps {
+            let t = step as f64 / steps as f64;
+            let along = |a: f64, b: f64, c: f64, d: f64| {
+                0.5 * (2.0 * b + (c - a) * t + (2.0 * a - 5.0 * b + 4.0 * c - d) * t * t + (3.0 * b - a - 3.0 * c + d) * t * t * t)
+            };
+            let round = |v: f64| v.round().clamp(i32::MIN as f64, i32::MAX as f64) as i32;
+            let point = (round(along(p0.0, p1.0, p2.0, p3.0)), round(along(p0.1, p1.1, p2.1, p3.1)));
+            if curve.last() != Some(&point) {
+                curve.push(point);
+            }
+        }
+    }
+    curve
+}
+
+// Most points an ellipse is tessellated into
+const MAX_ELLIPSE_POINTS: f64 = 1000.0;
+
+/// The outline of an ellipse centered at `center` with radii `radius_x`
+/// and `radius_y`, its x radius turned `rotation_degrees` clockwise (y
+/// points down), as a path with points about every SMOOTHING_STEP_PX
+/// pixels. With `arc` (start and end angle in degrees), only the part from
+/// the start angle clockwise to the end angle; angles are directions from
+/// the center, measured clockwise from the x radius. A whole ellipse ends
+/// where it starts.
+pub fn ellipse(center: (i32, i32), (radius_x, radius_y): (u32, u32), rotation_degrees: f64, arc: Option<(f64, f64)>) -> Vec<(i32, i32)> {
+    let (rx, ry) = (radius_x as f64, radius_y as f64);
+    // Directions become the ellipse's own parameter, which the points are
+    // spaced evenly along
+    let parameter = |degrees: f64| {
+        let direction = degrees.to_radians();
+        (rx * direction.sin()).atan2(ry * direction.cos())
+    };
+    let (start, sweep) = match arc {
+        Some((start, end)) => {
+            let start = parameter(start);
+            (start, (parameter(end) - start).rem_euclid(std::f64::consts::TAU))
+        }
+        None => (0.0, std::f64::consts::TAU),
+    };
+    let sweep = if sweep == 0.0 { std::f64::consts::TAU } else { sweep };
+    // Ramanujan's approximation of the perimeter
+    let perimeter = std::f64::consts::PI * (3.0 * (rx + ry) - ((3.0 * rx + ry) * (rx + 3.0 * ry)).sqrt());
+    let steps = (perimeter * sweep / std::f64::consts::TAU / SMOOTHING_STEP_PX).ceil().clamp(8.0, MAX_ELLIPSE_POINTS) as usize;
+    // A whole ellipse then has a point at each end of both axes
+    let steps = steps.next_multiple_of(4);
+
+    let (sin, cos) = rotation_degrees.to_radians().sin_cos();
+    let round = |v: f64| v.round().clamp(i32::MIN as f64, i32::MAX as f64) as i32;
+    let mut path: Vec<(i32, i32)> = Vec::with_capacity(steps + 1);
+    for step in 0..=steps {
+        let t = start + sweep * step as f64 / steps as f64;
+        let (x, y) = (rx * t.cos(), ry * t.sin());
+        let point = (round(center.0 as f64 + x * cos - y * sin), round(center.1 as f64 + x * sin + y * cos));
+        if path.last() != Some(&point) {
+            path.push(point);
+        }
+    }
+    path
+}
+
+/// The points of a path that matter at `tolerance` pixels
+/// (Ramer-Douglas-Peucker): the path through them never strays more than
+/// `tolerance` from the original. The first and last points are kept.
+pub fn simplify_rdp(points: &[(i32, i32)], tolerance: f64) -> Vec<(i32, i32)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    let mut pending = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = pending.pop() {
+        let (far, distance) = (start + 1..end)
+            .map(|i| (i, distance_to_segment(points[i], points[start], points[end])))
+            .fold((start, 0.0), |furthest, candidate| if candidate.1 > furthest.1 { candidate } else { furthest });
+        if distance > tolerance {
+            keep[far] = true;
+            pending.push((start, far));
+            pending.push((far, end));
+        }
+    }
+    points.iter().zip(keep).filter_map(|(&point, kept)| kept.then_some(point)).collect()
+}
+
+// Distance from `p` to the nearest point of the segment from `a` to `b`
+fn distance_to_segment(p: (i32, i32), a: (i32, i32), b: (i32, i32)) -> f64 {
+    let (px, py, ax, ay, bx, by) = (p.0 as f64, p.1 as f64, a.0 as f64, a.1 as f64, b.0 as f64, b.1 as f64);
+    let (dx, dy) = (bx - ax, by - ay);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared == 0.0 { 0.0 } else { (((px - ax) * dx + (py - ay) * dy) / length_squared).clamp(0.0, 1.0) };
+    (px - (ax + t * dx)).hypot(py - (ay + t * dy))
+}
+
+/// Splits a stroke through `points` into pieces no bigger than `max`
+/// (width, height) pixels, so each piece can be scrolled into view and
+/// drawn on its own. Each piece starts where the previous one ended.
+pub fn split_stroke(points: &[(i32, i32)], max: (u32, u32)) -> Vec<Vec<(i32, i32)>> {
+    let Some(&first) = points.first() else { return Vec::new() };
+    // Steps of at most half the piece size always fit two to a piece
+    let (step_x, step_y) = ((max.0.saturating_sub(1) / 2).max(1) as u64, (max.1.saturating_sub(1) / 2).max(1) as u64);
+    let mut steps = vec![first];
+    for pair in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = ((pair[0].0 as i64, pair[0].1 as i64), (pair[1].0 as i64, pair[1].1 as i64));
+        let count = (x1 - x0).unsigned_abs().div_ceil(step_x).max((y1 - y0).unsigned_abs().div_ceil(step_y)).max(1);
+        for i in 1..=count {
+            let along = |from: i64, to: i64| (from as f64 + (to - from) as f64 * i as f64 / count as f64).round() as i32;
+            steps.push((along(x0, x1), along(y0, y1)));
+        }
+    }
+
+    let fits = |piece: &[(i32, i32)], next: (i32, i32)| {
+        let span = |axis: fn(&(i32, i32)) -> i32| {
+            let values = piece.iter().map(axis).chain(std::iter::once(axis(&next)));
+            let (low, high) = values.fold((i64::MAX, i64::MIN), |(low, high), v| (low.min(v as i64), high.max(v as i64)));
+            high - low
+        };
+        span(|p| p.0) < max.0 as i64 && span(|p| p.1) < max.1 as i64
+    };
+    let mut pieces: Vec<Vec<(i32, i32)>> = Vec::new();
+    let mut piece = vec![first];
+    for &point in &steps[1..] {
+        if !fits(&piece, point) {
+            let end = *piece.last().unwrap_or(&first);
+            pieces.push(std::mem::replace(&mut piece, vec![end]));
+        }
+        piece.push(point);
+    }
+    pieces.push(piece);
+    pieces
+}
+
+/// A path along which a square pen `thickness` pixels wide covers exactly
+/// the rectangle at (x, y): passes along its longer side, joined at
+/// alternate ends, the last overlapping the one before. The pen must fit
+/// the rectangle (1 <= thickness <= width, height).
+pub fn cover_rect(x: i32, y: i32, width: u32, height: u32, thickness: u32) -> Vec<(i32, i32)> {
+    // Fewer passes along the longer side
+    let vertical = height >= width;
+    let (along, across) = if vertical { (height, width) } else { (width, height) };
+    // A pen centered on c covers c - thickness / 2 and the thickness - 1 pixels after it
+    let center = |first: u32| (first + thickness / 2) as i32;
+    let (near, far) = (center(0), center(along - thickness));
+    let mut passes = Vec::new();
+    let mut first = 0;
+    while first + thickness < across {
+        passes.push(center(first));
+        first += thickness;
+    }
+    passes.push(center(across - thickness));
+
+    passes.into_iter().enumerate()
+        .flat_map(|(i, pass)| {
+            let ends = if i % 2 == 0 { [near, far] } else { [far, near] };
+            ends.map(|end| if vertical { (x + pass, y + end) } else { (x + end, y + pass) })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Virtual desktops from one small monitor up to a wall of 8K panels
+    fn screen_rect() -> impl Strategy<Value = ScreenRect> {
+        (-20_000..20_000i32, -20_000..20_000i32, 1..60_000i32, 1..20_000i32)
+    }
+
+    fn rect() -> impl Strategy<Value = RectInfo> {
+        (-10_000..10_000i32, -10_000..10_000i32, 0..10_000i32, 0..10_000i32)
+            .prop_map(|(left, top, width, height)| RectInfo::new(left, top, left + width, top + height))
+    }
+
+    proptest! {
+        #[test]
+        fn cover_rect_covers_exactly_the_rectangle((width, height) in (1..40u32, 1..40u32), pen in 1..40u32) {
+            let thickness = pen.min(width).min(height);
+            let white = image::Rgba([255, 255, 255, 255]);
+            let mut canvas = image::RgbaImage::from_pixel(48, 48, white);
+            let path = cover_rect(4, 3, width, height, thickness);
+            for pair in path.windows(2) {
+                crate::simulate::stroke_line(&mut canvas, pair[0], pair[1], thickness, image::Rgba([0, 0, 0, 255]));
+            }
+            for py in 0..48 {
+                for px in 0..48 {
+                    let inside = (4..4 + width).contains(&px) && (3..3 + height).contains(&py);
+                    prop_assert_eq!(*canvas.get_pixel(px, py) != white, inside, "pixel ({}, {})", px, py);
+                }
+            }
+        }
+
+        #[test]
+        fn normalized_round_trip_stays_within_a_pixel(screen in screen_rect(), fx in 0.0..1.0f64, fy in 0.0..1.0f64) {
+            let (origin_x, origin_y, width, height) = screen;
+            let x = origin_x + (fx * width as f64) as i32;
+            let y = origin_y + (fy * height as f64) as i32;
+            let (nx, ny) = screen_to_normalized(x, y, screen);
+            prop_assert!((0..=NORMALIZED_MAX).contains(&nx) && (0..=NORMALIZED_MAX).contains(&ny));
+
+            // One normalized step covers extent/65535 pixels
+            let (back_x, back_y) = normalized_to_screen(nx, ny, screen);
+            prop_assert!((back_x - x).abs() <= 1 + width / NORMALIZED_MAX, "{} -> {} -> {}", x, nx, back_x);
+            prop_assert!((back_y - y).abs() <= 1 + height / NORMALIZED_MAX, "{} -> {} -> {}", y, ny, back_y);
+        }
+
+        #[test]
+        fn any_screen_position_normalizes_into_range(x in any::<i32>(), y in any::<i32>(), screen in screen_rect()) {
+            let (nx, ny) = screen_to_normalized(x, y, screen);
+            prop_assert!((0..=NORMALIZED_MAX).contains(&nx) && (0..=NORMALIZED_MAX).contains(&ny));
+        }
+
+        #[test]
+        fn any_normalized_position_lands_on_the_desktop(nx in any::<i32>(), ny in any::<i32>(), screen in screen_rect()) {
+            let (origin_x, origin_y, width, height) = screen;
+            let (x, y) = normalized_to_screen(nx, ny, screen);
+            prop_assert!((origin_x..=origin_x + width).contains(&x));
+            prop_assert!((origin_y..=origin_y + height).contains(&y));
+        }
+
+        #[test]
+        fn rect_size_never_wraps(left in any::<i32>(), top in any::<i32>(), right in any::<i32>(), bottom in any::<i32>()) {
+            let (width, height) = rect_size(left, top, right, bottom);
+            prop_assert_eq!(width as i64, (right as i64 - left as i64).max(0));
+            prop_assert_eq!(height as i64, (bottom as i64 - top as i64).max(0));
+        }
+
+        #[test]
+        fn fitted_window_lies_inside_the_work_area(window in rect(), work in rect()) {
+            let fitted = fit_rect_within(window, work);
+            prop_assert!(fitted.left >= work.left && fitted.right <= work.right);
+            prop_assert!(fitted.top >= work.top && fitted.bottom <= work.bottom);
+            // Only shrunk when it didn't fit
+            prop_assert_eq!(fitted.width, window.width.min(work.width));
+            prop_assert_eq!(fitted.height, window.height.min(work.height));
+        }
+
+        #[test]
+        fn canvas_fits_inside_the_window(width in any::<u32>(), height in any::<u32>()) {
+            let (canvas_width, canvas_height) = compute_canvas_dimensions(width, height);
+            prop_assert!(canvas_width <= width && canvas_height <= height);
+        }
+
+        #[test]
+        fn scroll_offset_stays_inside_the_document(percent in -1.0..=100.0f64, document in 0..100_000u32, zoom in 0.01..64.0f64, viewport in 0..10_000u32) {
+            prop_assert!(scroll_offset(percent, document, zoom, viewport) <= document);
+        }
+
+        #[test]
+        fn client_and_screen_positions_agree(
+            x in -5_000..5_000i32, client in (-3_000..3_000i32, -3_000..3_000i32), viewport in (-3_000..3_000i32, -3_000..3_000i32),
+            scroll in (0..10_000u32, 0..10_000u32), zoom in 0.05..16.0f64,
+        ) {
+            let frame = CanvasFrame {
+                width: 20_000, height: 20_000, zoom, viewport_origin: viewport, viewport_size: (1_000, 800), scroll, client_origin: client,
+            };
+            let from_screen = frame.to_canvas(x as f64, CoordinateSpace::Screen, Axis::Horizontal, Measure::Position);
+            let from_client = frame.to_canvas((x - client.0) as f64, CoordinateSpace::Client, Axis::Horizontal, Measure::Position);
+            prop_assert_eq!(from_screen, from_client);
+            // The viewport's corner shows the first pixel not scrolled away
+            prop_assert_eq!(frame.to_canvas(viewport.1 as f64, CoordinateSpace::Screen, Axis::Vertical, Measure::Position), scroll.1 as i32);
+            // and, zoomed in, a canvas pixel's screen position is inside it
+            let (screen_x, _) = frame.to_screen(x, 0);
+            let start = viewport.0 as f64 + (x as f64 - scroll.0 as f64) * zoom;
+            if zoom > 1.0 {
+                prop_assert!(start <= screen_x as f64 + 0.5 && (screen_x as f64 + 0.5) < start + zoom);
+            }
+        }
+
+        #[test]
+        fn scroll_percent_inverts_scroll_offset(offset in 0..20_000u32, document in 1..20_000u32, zoom in 0.05..16.0f64, viewport in 1..4_000u32) {
+            let percent = scroll_percent(offset, document, zoom, viewport);
+            if percent >= 0.0 {
+                let hidden = ((document as f64 * zoom - viewport as f64) / zoom).round() as u32;
+                let back = scroll_offset(percent, document, zoom, viewport) as i64;
+                prop_assert!((back - offset.min(hidden) as i64).abs() <= 1);
+            }
+        }
+
+        #[test]
+        fn clipped_segments_stay_on_the_canvas(a in (-2_000..2_000i32, -2_000..2_000i32), b in (-2_000..2_000i32, -2_000..2_000i32), size in (1..1_000u32, 1..1_000u32)) {
+            match clip_segment(a, b, size) {
+                Some((from, to)) => prop_assert!(on_canvas(from, size) && on_canvas(to, size)),
+                // Nothing left means no point along the segment is on the canvas
+                None => {
+                    let on = |t: f64| {
+                        let (x, y) = (a.0 as f64 + (b.0 - a.0) as f64 * t, a.1 as f64 + (b.1 - a.1) as f64 * t);
+                        (0.0..=(size.0 - 1) as f64).contains(&x) && (0.0..=(size.1 - 1) as f64).contains(&y)
+                    };
+                    prop_assert!(!(0..=256).any(|i| on(i as f64 / 256.0)));
+                }
+            }
+            if on_canvas(a, size) && on_canvas(b, size) {
+                prop_assert_eq!(clip_segment(a, b, size), Some((a, b)));
+            }
+        }
+
+        #[test]
+        fn split_strokes_fit_the_view(
+            points in proptest::collection::vec((-5_000..5_000i32, -5_000..5_000i32), 1..6), max in (2..500u32, 2..500u32),
+        ) {
+            let pieces = split_stroke(&points, max);
+            prop_assert_eq!(pieces.first().and_then(|p| p.first()), points.first());
+            prop_assert_eq!(pieces.last().and_then(|p| p.last()), points.last());
+            for (piece, next) in pieces.iter().zip(pieces.iter().skip(1)) {
+                prop_assert_eq!(piece.last(), next.first());
+            }
+            for piece in &pieces {
+                let span = |axis: fn(&(i32, i32)) -> i32| piece.iter().map(axis).max().unwrap() - piece.iter().map(axis).min().unwrap();
+                prop_assert!(span(|p| p.0) < max.0 as i32 && span(|p| p.1) < max.1 as i32);
+            }
+        }
+
+        #[test]
+        fn scale_factor_matches_dpi(dpi in 0..1_000u32) {
+            let factor = scale_factor(dpi);
+            prop_assert!(factor > 0.0);
+            prop_assert_eq!((factor * DEFAULT_DPI as f64).round() as u32, effective_dpi(dpi));
+        }
+    }
+
+    #[test]
+    fn test_normalized_corners() {
+        let screen = (-1920, 0, 3840, 1080);
+        assert_eq!(screen_to_normalized(-1920, 0, screen), (0, 0));
+        assert_eq!(normalized_to_screen(NORMALIZED_MAX, NORMALIZED_MAX, screen), (1920, 1080));
+        assert_eq!(scale_factor(0), 1.0);
+    }
+
+    #[test]
+    fn test_coordinate_spaces_to_canvas() {
+        let frame = CanvasFrame {
+            width: 801, height: 601, zoom: 2.0, viewport_origin: (100, 220), viewport_size: (400, 300), scroll: (40, 0),
+            client_origin: (90, 100),
+        };
+        let convert = |value: f64, space, axis, measure| frame.to_canvas(value, space, axis, measure);
+        assert_eq!(convert(12.0, CoordinateSpace::Canvas, Axis::Horizontal, Measure::Position), 12);
+        assert_eq!(convert(1.0, CoordinateSpace::Normalized, Axis::Horizontal, Measure::Position), 800);
+        assert_eq!(convert(0.5, CoordinateSpace::Normalized, Axis::Vertical, Measure::Size), 301);
+        // Screen x 111 is 11 screen pixels into the viewport: pixel 5, plus 40 scrolled away
+        assert_eq!(convert(111.0, CoordinateSpace::Screen, Axis::Horizontal, Measure::Position), 45);
+        assert_eq!(convert(21.0, CoordinateSpace::Client, Axis::Horizontal, Measure::Position), 45);
+        assert_eq!(convert(219.0, CoordinateSpace::Screen, Axis::Vertical, Measure::Position), -1);
+        assert_eq!(convert(30.0, CoordinateSpace::Screen, Axis::Vertical, Measure::Size), 15);
+    }
+
+    #[test]
+    fn test_scrolling_into_view() {
+        // 200 x 150 canvas pixels in view, scrolled 40 across
+        let frame = CanvasFrame {
+            width: 801, height: 601, zoom: 2.0, viewport_origin: (100, 220), viewport_size: (400, 300), scroll: (40, 0),
+            client_origin: (90, 100),
+        };
+        assert_eq!(frame.visible_size(), (200, 150));
+        assert!(frame.is_visible(40, 149) && frame.is_visible(239, 0));
+        assert!(!frame.is_visible(39, 0) && !frame.is_visible(240, 0) && !frame.is_visible(40, 150));
+        assert_eq!(frame.to_screen(45, 0), (111, 221));
+        assert_eq!(frame.scroll_to_show((50, 10), (60, 20)), (40, 0));
+        assert_eq!(frame.scroll_to_show((500, 400), (519, 409)), (410, 330));
+        assert_eq!(frame.scroll_to_show((790, 590), (800, 600)), (601, 451));
+        assert_eq!(scroll_percent(300, 2000, 2.0, 1000), 20.0);
+        assert_eq!(scroll_percent(0, 400, 2.0, 1000), -1.0);
+
+        let pieces = split_stroke(&[(0, 0), (500, 0)], (200, 150));
+        assert!(pieces.len() >= 3);
+        assert!(pieces.iter().all(|piece| piece.iter().all(|p| p.1 == 0)));
+        assert_eq!(split_stroke(&[(5, 5)], (200, 150)), vec![vec![(5, 5)]]);
+        assert!(split_stroke(&[], (200, 150)).is_empty());
+        assert_eq!(bounding_box(&[(5, 30), (-2, 40), (9, 35)]), Some(((-2, 30), (9, 40))));
+        assert_eq!(bounding_box(&[]), None);
+    }
+
+    #[test]
+    fn test_simplify_straight_runs() {
+        assert_eq!(simplify_straight_runs(&[(0, 0), (5, 0), (5, 0), (10, 0), (10, 10), (10, 20)]), vec![(0, 0), (10, 0), (10, 20)]);
+        // Going back over a line is a turn, not a straight run
+        assert_eq!(simplify_straight_runs(&[(0, 0), (10, 0), (3, 0)]), vec![(0, 0), (10, 0), (3, 0)]);
+        assert_eq!(simplify_straight_runs(&[(0, 0), (2, 1), (4, 2), (5, 3)]), vec![(0, 0), (4, 2), (5, 3)]);
+        assert_eq!(simplify_straight_runs(&[(7, 7), (7, 7)]), vec![(7, 7)]);
+    }
+
+    #[test]
+    fn test_smoothing_and_simplifying() {
+        let corner = [(0, 0), (40, 0), (40, 40)];
+        let curve = catmull_rom(&corner);
+        assert_eq!((curve.first(), curve.last()), (Some(&(0, 0)), Some(&(40, 40))));
+        assert!(curve.contains(&(40, 0)) && curve.len() > 10);
+        // The curve rounds the corner outside the original lines
+        assert!(curve.iter().any(|&(x, _)| x > 40));
+        assert_eq!(catmull_rom(&[(0, 0), (9, 9)]), vec![(0, 0), (9, 9)]);
+        let loop_curve = catmull_rom(&[(0, 0), (40, 0), (40, 40), (0, 40), (0, 0)]);
+        assert_eq!(loop_curve.last(), Some(&(0, 0)));
+
+        assert_eq!(simplify_rdp(&[(0, 0), (10, 1), (20, 0), (30, 10)], 2.0), vec![(0, 0), (20, 0), (30, 10)]);
+        assert_eq!(simplify_rdp(&[(0, 0), (10, 1), (20, 0)], 0.5), vec![(0, 0), (10, 1), (20, 0)]);
+        assert_eq!(simplify_rdp(&curve, 1000.0), vec![(0, 0), (40, 40)]);
+        // A closed path keeps its far side
+        assert_eq!(simplify_rdp(&[(0, 0), (5, 0), (10, 0), (5, 1), (0, 0)], 0.5), vec![(0, 0), (10, 0), (5, 1), (0, 0)]);
+    }
+
+    #[test]
+    fn test_ellipses_and_arcs() {
+        let outline = ellipse((50, 40), (30, 10), 0.0, None);
+        assert_eq!((outline[0], *outline.last().unwrap()), ((80, 40), (80, 40)));
+        assert_eq!(bounding_box(&outline), Some(((20, 30), (80, 50))));
+
+        // Turned a quarter clockwise, the long axis runs down the canvas
+        let tilted = ellipse((50, 40), (30, 10), 90.0, None);
+        assert_eq!(bounding_box(&tilted), Some(((40, 10), (60, 70))));
+        assert_eq!(tilted[0], (50, 70));
+
+        // A quarter arc from the x radius clockwise to the bottom; the arc
+        // from 270 to 90 wraps past 0
+        let arc = ellipse((0, 0), (20, 20), 0.0, Some((0.0, 90.0)));
+        assert_eq!((arc[0], *arc.last().unwrap()), ((20, 0), (0, 20)));
+        assert!(arc.iter().all(|&(x, y)| x >= 0 && y >= 0));
+        let arc = ellipse((0, 0), (20, 20), 0.0, Some((270.0, 90.0)));
+        assert_eq!((arc[0], *arc.last().unwrap()), ((0, -20), (0, 20)));
+        assert!(arc.iter().all(|&(x, _)| x >= 0));
+        // Angles are directions, not the ellipse's parameter
+        let arc = ellipse((0, 0), (40, 10), 0.0, Some((0.0, 45.0)));
+        let end = *arc.last().unwrap();
+        assert!((end.0 - end.1).abs() <= 1, "{:?}", end);
+    }
+
+    #[test]
+    fn test_snap_to_grid() {
+        assert_eq!(snap_to_grid(13, 10), 10);
+        assert_eq!(snap_to_grid(15, 10), 20);
+        assert_eq!(snap_to_grid(-4, 10), 0);
+        assert_eq!(snap_to_grid(-6, 10), -10);
+        assert_eq!(snap_to_grid(7, 1), 7);
+        assert_eq!(snap_to_grid(7, 0), 7);
+        assert_eq!(snap_to_grid(i32::MAX, 1000), i32::MAX);
+    }
+
+    #[test]
+    fn test_clipping_to_the_canvas() {
+        let size = (800, 600);
+        assert_eq!(clip_segment((10, 10), (20, 30), size), Some(((10, 10), (20, 30))));
+        assert_eq!(clip_segment((-100, 300), (400, 300), size), Some(((0, 300), (400, 300))));
+        assert_eq!(clip_segment((-10, -10), (810, 610), size), Some(((3, 0), (795, 599))));
+        assert_eq!(clip_segment((-10, 5), (-1, 500), size), None);
+        assert_eq!(clip_segment((900, -50), (1000, 650), size), None);
+
+        // In at the left, out at the top, back in and out at the bottom
+        let runs = clip_path(&[(-50, 100), (50, 100), (50, -100), (100, -100), (100, 700)], size, false);
+        assert_eq!(runs, vec![vec![(0, 100), (50, 100), (50, 0)], vec![(100, 0), (100, 599)]]);
+        // A closed outline poking out to the right is one run around the gap
+        let runs = clip_path(&[(700, 100), (900, 100), (900, 200), (700, 200)], size, true);
+        assert_eq!(runs, vec![vec![(799, 200), (700, 200), (700, 100), (799, 100)]]);
+
+        assert_eq!(clip_polygon(&[(700, 100), (900, 100), (900, 200), (700, 200)], size),
+            vec![(700, 100), (799, 100), (799, 200), (700, 200)]);
+        assert_eq!(clip_polygon(&[(-5, -5), (900, -5), (900, 700), (-5, 700)], size), vec![(0, 599), (0, 0), (799, 0), (799, 599)]);
+        assert!(clip_polygon(&[(900, 0), (950, 0), (950, 50)], size).is_empty());
+    }
+
+    #[test]
+    fn test_parse_status_bar() {
+        assert_eq!(parse_image_size("1152 × 648px"), Some((1152, 648)));
+        assert_eq!(parse_image_size("1,920 x 1,080 px"), Some((1920, 1080)));
+        assert_eq!(parse_image_size("120, 45px"), None);
+        assert_eq!(parse_image_size("× 648px"), None);
+        assert_eq!(parse_zoom("100%"), Some(1.0));
+        assert_eq!(parse_zoom("12,5 %"), Some(0.125));
+        assert_eq!(parse_zoom("Zoom"), None);
+        // 2000px wide at 200% in a 1000px viewport: 3000 screen pixels hidden
+        assert_eq!(scroll_offset(50.0, 2000, 2.0, 1000), 750);
+        assert_eq!(scroll_offset(-1.0, 2000, 2.0, 1000), 0);
+    }
+}
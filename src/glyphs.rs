@@ -0,0 +1,354 @@
+// Text drawn from a font's glyph outlines.
+//
+// Paint's text tool caps the size, picks fonts through a dropdown that
+// automation struggles with, and leaves an editable text box behind.
+// `draw_glyph_text` sidesteps it: the server reads a TrueType or OpenType
+// font (with the glyph-outlines feature, through ttf-parser), lays the
+// string out with the font's advances (no kerning), flattens each glyph's
+// quadratic and cubic curves into line segments, and draws the result with
+// the pencil. Filled text is drawn one 1 pixel stroke per span: a row's
+// spans are the runs of pixels whose centers lie inside the outlines by the
+// nonzero winding rule TrueType uses. Outlined text draws each contour as a
+// closed polyline instead.
+
+use crate::config::ServerConfig;
+use crate::error::{MspMcpError, Result};
+
+// Smallest and largest em size text may be drawn at
+pub const MIN_GLYPH_SIZE_PX: u32 = 4;
+pub const MAX_GLYPH_SIZE_PX: u32 = 1000;
+
+// Most strokes one request may take
+pub const MAX_GLYPH_STROKES: usize = 20_000;
+
+// Pixels between the points a curve is flattened into
+#[cfg(feature = "glyph-outlines")]
+const CURVE_STEP_PX: f64 = 2.0;
+
+// Most segments one curve is flattened into
+#[cfg(feature = "glyph-outlines")]
+const MAX_CURVE_SEGMENTS: usize = 32;
+
+/// A string laid out in canvas pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphText {
+    pub contours: Vec<Vec<(f64, f64)>>, // Closed outlines, last point not repeated
+    pub width: u32,                     // Widest line's advance
+    pub height: u32,                    // Line height times the number of lines
+}
+
+/// Lays out `text` in `font` (a font file path, a file in the Windows font
+/// folders, or a family or full font name) at `size_px` pixels to the em,
+/// with the top of the first line at (x, y). Lines break at '\n'. A font
+/// path goes through the same checks and size limit as fetch_image.
+#[cfg(feature = "glyph-outlines")]
+pub fn outline_text(font: &str, text: &str, size_px: u32, (x, y): (i32, i32), config: &ServerConfig) -> Result<GlyphText> {
+    check_size(size_px)?;
+    let (data, index) = font_files::load(font, config)?;
+    let face = ttf_parser::Face::parse(&data, index)
+        .map_err(|e| MspMcpError::InvalidParameters(format!("Can't read the font '{}': {}", font, e)))?;
+    let scale = size_px as f64 / face.units_per_em() as f64;
+    let ascender = face.ascender() as f64 * scale;
+    let line_height = (face.ascender() as f64 - face.descender() as f64 + face.line_gap() as f64) * scale;
+
+    let mut contours = Vec::new();
+    let mut widest = 0.0_f64;
+    let lines: Vec<&str> = text.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        let baseline = y as f64 + ascender + i as f64 * line_height;
+        let mut pen_x = x as f64;
+        for c in line.chars() {
+            let glyph = face.glyph_index(c).unwrap_or(ttf_parser::GlyphId(0));
+            let mut builder = Flattener { origin: (pen_x, baseline), scale, contours: Vec::new(), current: Vec::new() };
+            face.outline_glyph(glyph, &mut builder);
+            ttf_parser::OutlineBuilder::close(&mut builder);
+            contours.extend(builder.contours);
+            pen_x += face.glyph_hor_advance(glyph).unwrap_or(0) as f64 * scale;
+        }
+        widest = widest.max(pen_x - x as f64);
+    }
+    Ok(GlyphText { contours, width: widest.ceil() as u32, height: (line_height * lines.len() as f64).ceil() as u32 })
+}
+
+/// Without the glyph-outlines feature there is no font reader.
+#[cfg(not(feature = "glyph-outlines"))]
+pub fn outline_text(_font: &str, _text: &str, size_px: u32, _origin: (i32, i32), _config: &ServerConfig) -> Result<GlyphText> {
+    check_size(size_px)?;
+    Err(MspMcpError::OperationNotSupported(
+        "draw_glyph_text needs the server built with the glyph-outlines feature".to_string()))
+}
+
+fn check_size(size_px: u32) -> Result<()> {
+    if !(MIN_GLYPH_SIZE_PX..=MAX_GLYPH_SIZE_PX).contains(&size_px) {
+        return Err(MspMcpError::InvalidParameters(
+            format!("size_px must be between {} and {}, got {}", MIN_GLYPH_SIZE_PX, MAX_GLYPH_SIZE_PX, size_px)));
+    }
+    Ok(())
+}
+
+/// Strokes that fill the outlines: one per span of each pixel row, as
+/// 2-point lines for a 1 pixel pencil.
+pub fn fill_strokes(contours: &[Vec<(f64, f64)>]) -> Result<Vec<Vec<(i32, i32)>>> {
+    let edges: Vec<((f64, f64), (f64, f64))> = contours.iter()
+        .filter(|contour| contour.len() >= 2)
+        .flat_map(|contour| contour.iter().copied().zip(contour.iter().copied().cycle().skip(1)))
+        .filter(|(a, b)| a.1 != b.1)
+        .collect();
+    if edges.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (top, bottom) = edges.iter()
+        .flat_map(|(a, b)| [a.1, b.1])
+        .fold((f64::MAX, f64::MIN), |(low, high), y| (low.min(y), high.max(y)));
+
+    let mut strokes = Vec::new();
+    for row in top.floor() as i32..bottom.ceil() as i32 {
+        let center = row as f64 + 0.5;
+        // Where the row's center line crosses an edge, and which way the edge runs
+        let mut crossings: Vec<(f64, i32)> = edges.iter()
+            .filter(|(a, b)| (a.1 > center) != (b.1 > center))
+            .map(|(a, b)| (a.0 + (center - a.1) * (b.0 - a.0) / (b.1 - a.1), if b.1 > a.1 { 1 } else { -1 }))
+            .collect();
+        crossings.sort_by(|p, q| p.0.total_cmp(&q.0));
+        let mut winding = 0;
+        let mut enter = 0.0;
+        for (x, direction) in crossings {
+            let was_inside = winding != 0;
+            winding += direction;
+            match (was_inside, winding != 0) {
+                (false, true) => enter = x,
+                (true, false) => {
+                    // Pixels whose centers lie in [enter, x)
+                    let (first, last) = ((enter - 0.5).ceil() as i32, (x - 0.5).ceil() as i32 - 1);
+                    if first <= last {
+                        strokes.push(vec![(first, row), (last, row)]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if strokes.len() > MAX_GLYPH_STROKES {
+            return Err(too_many_strokes());
+        }
+    }
+    Ok(strokes)
+}
+
+/// Strokes that trace the outlines: each contour as a closed polyline.
+pub fn outline_strokes(contours: &[Vec<(f64, f64)>]) -> Result<Vec<Vec<(i32, i32)>>> {
+    if contours.len() > MAX_GLYPH_STROKES {
+        return Err(too_many_strokes());
+    }
+    Ok(contours.iter()
+        .filter(|contour| !contour.is_empty())
+        .map(|contour| {
+            let mut points: Vec<(i32, i32)> = contour.iter().map(|&(x, y)| (x.round() as i32, y.round() as i32)).collect();
+            points.dedup();
+            points.push(points[0]);
+            points
+        })
+        .collect())
+}
+
+fn too_many_strokes() -> MspMcpError {
+    MspMcpError::InvalidParameters(format!(
+        "The text takes more than the {} strokes allowed; use a smaller size, fewer characters or an outline", MAX_GLYPH_STROKES))
+}
+
+// Collects a glyph's outline as contours in canvas pixels. Font units run
+// up from the baseline; canvas pixels run down
+#[cfg(feature = "glyph-outlines")]
+struct Flattener {
+    origin: (f64, f64), // Pen position on the baseline
+    scale: f64,         // Pixels per font unit
+    contours: Vec<Vec<(f64, f64)>>,
+    current: Vec<(f64, f64)>,
+}
+
+#[cfg(feature = "glyph-outlines")]
+impl Flattener {
+    fn point(&self, x: f32, y: f32) -> (f64, f64) {
+        (self.origin.0 + x as f64 * self.scale, self.origin.1 - y as f64 * self.scale)
+    }
+
+    fn last(&self) -> (f64, f64) {
+        self.current.last().copied().unwrap_or(self.origin)
+    }
+
+    // Adds the curve through `at` (t from 0 to 1) starting at the last point
+    fn curve(&mut self, end: (f64, f64), length: f64, at: impl Fn(f64) -> (f64, f64)) {
+        let segments = ((length / CURVE_STEP_PX).ceil() as usize).clamp(1, MAX_CURVE_SEGMENTS);
+        self.current.extend((1..segments).map(|i| at(i as f64 / segments as f64)));
+        self.current.push(end);
+    }
+}
+
+#[cfg(feature = "glyph-outlines")]
+impl ttf_parser::OutlineBuilder for Flattener {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.close();
+        let point = self.point(x, y);
+        self.current.push(point);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let point = self.point(x, y);
+        self.current.push(point);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.last(), self.point(x1, y1), self.point(x, y));
+        let length = distance(p0, p1) + distance(p1, p2);
+        self.curve(p2, length, |t| {
+            let (a, b, c) = ((1.0 - t) * (1.0 - t), 2.0 * t * (1.0 - t), t * t);
+            (a * p0.0 + b * p1.0 + c * p2.0, a * p0.1 + b * p1.1 + c * p2.1)
+        });
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1, p2, p3) = (self.last(), self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        let length = distance(p0, p1) + distance(p1, p2) + distance(p2, p3);
+        self.curve(p3, length, |t| {
+            let u = 1.0 - t;
+            let (a, b, c, d) = (u * u * u, 3.0 * t * u * u, 3.0 * t * t * u, t * t * t);
+            (a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0, a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1)
+        });
+    }
+
+    fn close(&mut self) {
+        let mut contour = std::mem::take(&mut self.current);
+        if contour.len() > 1 && contour.first() == contour.last() {
+            contour.pop();
+        }
+        if contour.len() >= 2 {
+            self.contours.push(contour);
+        }
+    }
+}
+
+#[cfg(feature = "glyph-outlines")]
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+// Finding font files by path, file name or name
+#[cfg(feature = "glyph-outlines")]
+mod font_files {
+    use crate::config::ServerConfig;
+    use crate::error::{MspMcpError, Result};
+    use std::path::{Component, Path, PathBuf};
+
+    // The system and per-user font folders
+    fn font_dirs() -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        if let Some(windows) = std::env::var_os("WINDIR") {
+            dirs.push(Path::new(&windows).join("Fonts"));
+        }
+        if let Some(local) = std::env::var_os("LOCALAPPDATA") {
+            dirs.push(Path::new(&local).join("Microsoft").join("Windows").join("Fonts"));
+        }
+        dirs
+    }
+
+    fn is_font_file(path: &Path) -> bool {
+        path.extension().and_then(|ext| ext.to_str())
+            .is_some_and(|ext| matches!(ext.to_ascii_lowercase().as_str(), "ttf" | "otf" | "ttc" | "otc"))
+    }
+
+    // Reads a font file, refusing ones over max_fetch_bytes
+    fn read(path: &Path, config: &ServerConfig) -> Result<Vec<u8>> {
+        let size_bytes = std::fs::metadata(path)?.len();
+        if size_bytes > config.max_fetch_bytes {
+            return Err(MspMcpError::PayloadTooLarge {
+                message: format!("Font file {} is {} bytes", path.display(), size_bytes),
+                limit: config.max_fetch_bytes,
+                unit: "bytes",
+            });
+        }
+        Ok(std::fs::read(path)?)
+    }
+
+    /// The font's file contents and its index in a collection.
+    pub fn load(font: &str, config: &ServerConfig) -> Result<(Vec<u8>, u32)> {
+        // Anything that isn't a bare file name is a path, checked like any other
+        let bare_name = !font.contains(['/', '\\'])
+            && matches!(Path::new(font).components().collect::<Vec<_>>()[..], [Component::Normal(_)]);
+        if !bare_name {
+            let path = crate::fs_guard::check_path(font, config)?;
+            if !path.is_file() {
+                return Err(MspMcpError::FileNotFound(path.display().to_string()));
+            }
+            return Ok((read(&path, config)?, 0));
+        }
+        if let Some(path) = font_dirs().into_iter().map(|dir| dir.join(font)).find(|path| path.is_file()) {
+            return Ok((read(&path, config)?, 0));
+        }
+        // Match the family or full name, e.g. "Arial" or "Arial Bold"
+        let wanted = font.to_lowercase();
+        for dir in font_dirs() {
+            let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+            for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| is_font_file(path)) {
+                let Ok(data) = read(&path, config) else { continue };
+                let faces = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+                let found = (0..faces).find(|&index| {
+                    ttf_parser::Face::parse(&data, index).is_ok_and(|face| face.names().into_iter()
+                        .filter(|name| matches!(name.name_id, ttf_parser::name_id::FAMILY | ttf_parser::name_id::FULL_NAME))
+                        .filter_map(|name| name.to_string())
+                        .any(|name| name.to_lowercase() == wanted))
+                });
+                if let Some(index) = found {
+                    return Ok((data, index));
+                }
+            }
+        }
+        Err(MspMcpError::InvalidParameters(format!(
+            "Can't find the font '{}'; give a font file path, a file name in the Windows font folder, or an installed font's name", font)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_follows_the_nonzero_rule() {
+        // A 10x6 square with a 4x2 hole wound the other way, and a second
+        // square overlapping it wound the same way
+        let outer = vec![(0.0, 0.0), (10.0, 0.0), (10.0, 6.0), (0.0, 6.0)];
+        let hole = vec![(3.0, 2.0), (3.0, 4.0), (7.0, 4.0), (7.0, 2.0)];
+        let strokes = fill_strokes(&[outer.clone(), hole]).unwrap();
+        assert_eq!(strokes.len(), 6 + 2);
+        assert_eq!(strokes[0], vec![(0, 0), (9, 0)]);
+        assert_eq!(&strokes[2..4], &[vec![(0, 2), (2, 2)], vec![(7, 2), (9, 2)]]);
+
+        let overlapping = vec![(5.0, 0.0), (15.0, 0.0), (15.0, 6.0), (5.0, 6.0)];
+        let strokes = fill_strokes(&[outer.clone(), overlapping]).unwrap();
+        assert_eq!(strokes.len(), 6);
+        assert!(strokes.iter().all(|stroke| stroke[0].0 == 0 && stroke[1].0 == 14));
+
+        assert_eq!(outline_strokes(&[outer]).unwrap(), vec![vec![(0, 0), (10, 0), (10, 6), (0, 6), (0, 0)]]);
+        assert!(fill_strokes(&[]).unwrap().is_empty());
+        assert!(outline_text("Arial", "A", 2, (0, 0), &ServerConfig::default()).is_err());
+    }
+
+    #[cfg(feature = "glyph-outlines")]
+    #[test]
+    fn test_font_paths_go_through_the_path_checks() {
+        let config = ServerConfig::default();
+        assert!(matches!(font_files::load("\\\\host\\share\\x.ttf", &config), Err(MspMcpError::PathNotAllowed(_))));
+        assert!(font_files::load("..\\..\\x.ttf", &config).is_err());
+        assert!(font_files::load("../x.ttf", &config).is_err());
+        assert!(font_files::load("..", &config).is_err());
+
+        let dir = std::env::temp_dir().join(format!("mspmcp-font-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let big = dir.join("big.ttf");
+        std::fs::write(&big, vec![0u8; 64]).unwrap();
+        let small_limit = ServerConfig { max_fetch_bytes: 10, ..ServerConfig::default() };
+        let result = font_files::load(big.to_str().unwrap(), &small_limit);
+        assert!(matches!(result, Err(MspMcpError::PayloadTooLarge { limit: 10, .. })));
+        let outside = ServerConfig { allowed_dirs: vec![dir.join("elsewhere")], ..ServerConfig::default() };
+        assert!(matches!(font_files::load(big.to_str().unwrap(), &outside), Err(MspMcpError::PathNotAllowed(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
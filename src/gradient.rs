@@ -0,0 +1,155 @@
+// Linear gradients drawn as bands of solid color.
+//
+// Paint has no gradient fill, but backgrounds and skies ask for one all the
+// time. `draw_gradient` splits a rectangle into `steps` bands across the
+// gradient's direction, interpolates each band's color between the two end
+// colors on the server, and fills every band with one pencil stroke
+// (geometry::cover_rect) whose width is set once for the whole gradient. Neighbouring bands
+// that round to the same color are merged, so a gentle gradient costs fewer
+// strokes than it has steps.
+
+use crate::error::{MspMcpError, Result};
+use crate::geometry::cover_rect;
+use crate::protocol::{GradientDirection, MAX_THICKNESS_PX};
+
+// Bands a gradient gets when the request doesn't say
+pub const DEFAULT_GRADIENT_STEPS: u32 = 32;
+
+// Most bands a gradient may ask for
+pub const MAX_GRADIENT_STEPS: u32 = 256;
+
+/// A gradient planned as strokes.
+#[derive(Debug, PartialEq)]
+pub struct GradientPlan {
+    pub thickness_px: u32, // Stroke width every band is drawn with
+    pub bands: Vec<Band>,
+}
+
+/// One band of solid color and the stroke that fills it.
+#[derive(Debug, PartialEq)]
+pub struct Band {
+    pub color: String,           // "#RRGGBB"
+    pub points: Vec<(i32, i32)>, // Stroke covering the band, in canvas pixels
+}
+
+/// Plans the gradient from `start_color` to `end_color` over the rectangle
+/// at (x, y). Horizontal gradients start at the left edge, vertical ones
+/// at the top. `steps` is capped at one band per pixel.
+pub fn plan(
+    (x, y, width, height): (i32, i32, u32, u32),
+    start_color: &str,
+    end_color: &str,
+    direction: GradientDirection,
+    steps: u32,
+) -> Result<GradientPlan> {
+    if width == 0 || height == 0 {
+        return Err(MspMcpError::InvalidParameters(format!("The gradient's rectangle is empty ({}x{})", width, height)));
+    }
+    if !(1..=MAX_GRADIENT_STEPS).contains(&steps) {
+        return Err(MspMcpError::InvalidParameters(format!("steps must be between 1 and {}, got {}", MAX_GRADIENT_STEPS, steps)));
+    }
+    let start = parse_rgb(start_color)?;
+    let end = parse_rgb(end_color)?;
+
+    // Positions along the gradient and across it, relative to the rectangle
+    let (along, across) = match direction {
+        GradientDirection::Horizontal => (width, height),
+        GradientDirection::Vertical => (height, width),
+    };
+    let steps = steps.min(along);
+    let edge = |i: u32| (i as u64 * along as u64 / steps as u64) as u32;
+
+    // Merge neighbouring steps of the same color into one band
+    let mut ranges: Vec<(String, u32, u32)> = Vec::new();
+    for i in 0..steps {
+        let color = interpolate(start, end, i, steps);
+        match ranges.last_mut() {
+            Some((last, _, until)) if *last == color => *until = edge(i + 1),
+            _ => ranges.push((color, edge(i), edge(i + 1))),
+        }
+    }
+
+    // The narrowest step decides the stroke width, so no band spills into the next
+    let thickness = (along / steps).min(across).min(MAX_THICKNESS_PX);
+    let bands = ranges.into_iter()
+        .map(|(color, from, until)| {
+            let points = match direction {
+                GradientDirection::Horizontal => cover_rect(x + from as i32, y, until - from, height, thickness),
+                GradientDirection::Vertical => cover_rect(x, y + from as i32, width, until - from, thickness),
+            };
+            Band { color, points }
+        })
+        .collect();
+    Ok(GradientPlan { thickness_px: thickness, bands })
+}
+
+fn parse_rgb(color: &str) -> Result<[u8; 3]> {
+    let color = crate::palette::normalize_color(color)?;
+    let channel = |i: usize| u8::from_str_radix(&color[1 + 2 * i..3 + 2 * i], 16).unwrap_or(0);
+    Ok([channel(0), channel(1), channel(2)])
+}
+
+// Color of step i of steps, the first and last being the end colors
+fn interpolate(start: [u8; 3], end: [u8; 3], i: u32, steps: u32) -> String {
+    let t = if steps > 1 { i as f64 / (steps - 1) as f64 } else { 0.0 };
+    let mix = |c: usize| (start[c] as f64 + (end[c] as f64 - start[c] as f64) * t).round() as u8;
+    format!("#{:02X}{:02X}{:02X}", mix(0), mix(1), mix(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulate::stroke_line;
+    use image::{Rgba, RgbaImage};
+
+    // Draws the plan the way the simulated Paint would and checks every
+    // pixel of the rectangle got its band's color and nothing else changed
+    fn assert_fills(rect: (i32, i32, u32, u32), gradient: &GradientPlan) {
+        let white = Rgba([255, 255, 255, 255]);
+        let mut canvas = RgbaImage::from_pixel(64, 64, white);
+        for band in &gradient.bands {
+            let rgb = parse_rgb(&band.color).unwrap();
+            let color = Rgba([rgb[0], rgb[1], rgb[2], 255]);
+            for segment in band.points.windows(2) {
+                stroke_line(&mut canvas, segment[0], segment[1], gradient.thickness_px, color);
+            }
+        }
+        let (x, y, width, height) = rect;
+        for py in 0..canvas.height() {
+            for px in 0..canvas.width() {
+                let inside = (x..x + width as i32).contains(&(px as i32)) && (y..y + height as i32).contains(&(py as i32));
+                assert_eq!(*canvas.get_pixel(px, py) != white, inside, "pixel ({}, {})", px, py);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bands_cover_the_rectangle() {
+        let rect = (3, 5, 40, 17);
+        let gradient = plan(rect, "#000000", "#FF0000", GradientDirection::Horizontal, 8).unwrap();
+        assert_eq!(gradient.thickness_px, 5);
+        assert_eq!(gradient.bands.len(), 8);
+        assert_eq!(gradient.bands[0].color, "#000000");
+        assert_eq!(gradient.bands[7].color, "#FF0000");
+        assert_eq!(gradient.bands[0].points, vec![(5, 7), (5, 19)]);
+        assert_fills(rect, &gradient);
+
+        // Uneven bands, wider than the stroke
+        let rect = (0, 0, 30, 50);
+        let gradient = plan(rect, "#0000FF", "#FFFF00", GradientDirection::Vertical, 4).unwrap();
+        assert_eq!(gradient.thickness_px, 12);
+        assert_fills(rect, &gradient);
+    }
+
+    #[test]
+    fn test_steps_are_capped_and_merged() {
+        let gradient = plan((0, 0, 10, 4), "#000000", "#0A0000", GradientDirection::Horizontal, 200).unwrap();
+        assert_eq!((gradient.thickness_px, gradient.bands.len()), (1, 10));
+        let flat = plan((0, 0, 10, 4), "#123456", "#123456", GradientDirection::Horizontal, 5).unwrap();
+        assert_eq!(flat.bands.len(), 1);
+
+        assert!(plan((0, 0, 0, 4), "#000000", "#FFFFFF", GradientDirection::Horizontal, 5).is_err());
+        assert!(plan((0, 0, 10, 4), "#000000", "#FFFFFF", GradientDirection::Horizontal, 0).is_err());
+        assert!(plan((0, 0, 10, 4), "black", "#FFFFFF", GradientDirection::Horizontal, 5).is_err());
+    }
+}
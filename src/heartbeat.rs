@@ -0,0 +1,122 @@
+// Liveness reporting: `ping` and periodic heartbeat notifications.
+//
+// Requests are handled one at a time, so a client can't tell a long
+// recreation from a wedged server, and hosts with idle timeouts may kill the
+// server mid-drawing. The stdio loop marks each request it is working on in
+// an Activity. `ping` reports it between requests, and with
+// `heartbeat_interval_ms` set a background thread sends it as
+// `notifications/paint/heartbeat` while requests run.
+
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+#[derive(Default)]
+struct Progress {
+    pending: usize,                     // Requests received and not yet answered
+    current: Option<(String, Instant)>, // The oldest of them and when it started
+    handled: u64,
+}
+
+/// What the server is doing, for `ping` and heartbeats.
+pub struct Activity {
+    started: Instant,
+    progress: Mutex<Progress>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Activity { started: Instant::now(), progress: Mutex::new(Progress::default()) }
+    }
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks `method` as in progress until the guard is dropped.
+    pub fn begin(&self, method: &str) -> ActivityGuard<'_> {
+        if let Ok(mut progress) = self.progress.lock() {
+            progress.pending += 1;
+            if progress.current.is_none() {
+                progress.current = Some((method.to_string(), Instant::now()));
+            }
+        }
+        ActivityGuard { activity: self }
+    }
+
+    /// Uptime, queue depth and the request in progress, if any.
+    pub fn status(&self) -> Value {
+        self.status_at(Instant::now())
+    }
+
+    fn status_at(&self, now: Instant) -> Value {
+        let Ok(progress) = self.progress.lock() else { return json!({}) };
+        let (busy_method, busy_ms) = match &progress.current {
+            Some((method, since)) => (Some(method.clone()), Some(now.saturating_duration_since(*since).as_millis() as u64)),
+            None => (None, None),
+        };
+        json!({
+            "uptime_ms": now.saturating_duration_since(self.started).as_millis() as u64,
+            "queue_depth": progress.pending,
+            "busy_method": busy_method,
+            "busy_ms": busy_ms,
+            "requests_handled": progress.handled,
+        })
+    }
+}
+
+/// Marks the request done when dropped.
+pub struct ActivityGuard<'a> {
+    activity: &'a Activity,
+}
+
+impl Drop for ActivityGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut progress) = self.activity.progress.lock() {
+            progress.pending = progress.pending.saturating_sub(1);
+            progress.handled += 1;
+            if progress.pending == 0 {
+                progress.current = None;
+            }
+        }
+    }
+}
+
+/// Sends `notifications/paint/heartbeat` with the activity's status every
+/// `interval`, for as long as the process runs.
+pub fn spawn(activity: Arc<Activity>, interval: Duration) {
+    info!("Sending heartbeats every {:?}", interval);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        crate::transport::send_notification("notifications/paint/heartbeat", activity.status());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_tracks_requests_in_progress() {
+        let activity = Activity::new();
+        let start = activity.started;
+        let idle = activity.status_at(start + Duration::from_secs(3));
+        assert_eq!(idle, json!({
+            "uptime_ms": 3000, "queue_depth": 0, "busy_method": null, "busy_ms": null, "requests_handled": 0,
+        }));
+
+        let first = activity.begin("draw_polyline");
+        let second = activity.begin("get_version");
+        let busy = activity.status_at(Instant::now() + Duration::from_millis(500));
+        assert_eq!((busy["queue_depth"].as_u64(), busy["busy_method"].as_str()), (Some(2), Some("draw_polyline")));
+        assert!(busy["busy_ms"].as_u64().unwrap() >= 500);
+
+        drop(first);
+        drop(second);
+        let done = activity.status();
+        assert_eq!((done["queue_depth"].as_u64(), done["busy_method"].is_null(), done["requests_handled"].as_u64()), (Some(0), true, Some(2)));
+    }
+}
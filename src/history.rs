@@ -0,0 +1,160 @@
+// Undo history kept by the server, with named checkpoints.
+//
+// Paint's undo stack can't be read from outside, so the server keeps count
+// itself. `checkpoint` marks the current state under a label, along with a
+// fingerprint of the canvas. From then on every request that changes the
+// canvas counts as an operation since that checkpoint; each one makes at
+// least one Paint undo step. `revert_to_checkpoint` presses Ctrl+Z once per
+// operation and compares the canvas with the fingerprint. A request that
+// made more than one undo step (a gradient is many strokes) or a change
+// made in Paint by hand leaves the canvas short of the checkpoint, so
+// reverting keeps undoing one step at a time until it matches, up to
+// MAX_EXTRA_UNDOS more.
+//
+// Starting a new document empties Paint's undo stack, so it drops every
+// checkpoint, as does switching to another Paint window.
+
+use crate::capture::CapturedImage;
+use crate::error::{MspMcpError, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// Undo steps revert_to_checkpoint may press beyond one per operation
+pub const MAX_EXTRA_UNDOS: u32 = 50;
+
+// Most checkpoints kept at once; setting another drops the oldest
+pub const MAX_CHECKPOINTS: usize = 100;
+
+// Methods after which Paint's undo stack starts over
+const RESETS_UNDO: &[&str] = &["new_document", "close_document", "quit_paint", "create_canvas"];
+
+/// A named state of the canvas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub label: String,
+    pub fingerprint: u64, // Of the canvas when the checkpoint was set
+    pub operations: u32,  // Canvas-changing requests made since
+}
+
+/// Checkpoints of the tracked Paint window, oldest first.
+#[derive(Debug, Default)]
+pub struct History {
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl History {
+    /// Sets a checkpoint at the current state, replacing one of the same
+    /// label. Returns true if it replaced one.
+    pub fn checkpoint(&mut self, label: &str, fingerprint: u64) -> Result<bool> {
+        if label.is_empty() {
+            return Err(MspMcpError::InvalidParameters("label must not be empty".to_string()));
+        }
+        let before = self.checkpoints.len();
+        self.checkpoints.retain(|checkpoint| checkpoint.label != label);
+        let replaced = self.checkpoints.len() < before;
+        if self.checkpoints.len() >= MAX_CHECKPOINTS {
+            self.checkpoints.remove(0);
+        }
+        self.checkpoints.push(Checkpoint { label: label.to_string(), fingerprint, operations: 0 });
+        Ok(replaced)
+    }
+
+    /// The checkpoint called `label`.
+    pub fn get(&self, label: &str) -> Result<&Checkpoint> {
+        self.checkpoints.iter().find(|checkpoint| checkpoint.label == label).ok_or_else(|| {
+            let labels: Vec<&str> = self.labels().collect();
+            MspMcpError::InvalidParameters(if labels.is_empty() {
+                format!("No checkpoint '{}'; there are no checkpoints", label)
+            } else {
+                format!("No checkpoint '{}'; checkpoints are {}", label, labels.join(", "))
+            })
+        })
+    }
+
+    /// Labels of the checkpoints, oldest first.
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.checkpoints.iter().map(|checkpoint| checkpoint.label.as_str())
+    }
+
+    /// Keeps the history in step after `method` succeeded. `mutates` is
+    /// whether the method changes the canvas.
+    pub fn record(&mut self, method: &str, mutates: bool) {
+        if RESETS_UNDO.contains(&method) {
+            self.clear();
+        } else if mutates {
+            for checkpoint in &mut self.checkpoints {
+                checkpoint.operations += 1;
+            }
+        }
+    }
+
+    /// Accounts for `operations` undone outside revert_to_checkpoint.
+    pub fn undone(&mut self, operations: u32) {
+        for checkpoint in &mut self.checkpoints {
+            checkpoint.operations = checkpoint.operations.saturating_sub(operations);
+        }
+    }
+
+    /// Marks the canvas as back at the checkpoint called `label`: the
+    /// checkpoints set after it are gone, along with the operations they
+    /// were counting from.
+    pub fn reverted(&mut self, label: &str) {
+        let Some(index) = self.checkpoints.iter().position(|checkpoint| checkpoint.label == label) else { return };
+        self.checkpoints.truncate(index + 1);
+        let undone = self.checkpoints[index].operations;
+        self.undone(undone);
+    }
+
+    /// Drops every checkpoint.
+    pub fn clear(&mut self) {
+        self.checkpoints.clear();
+    }
+}
+
+/// Fingerprint of a canvas capture, to tell whether two captures match.
+pub fn fingerprint(image: &CapturedImage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (image.width, image.height).hash(&mut hasher);
+    image.rgba.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoints_count_operations_until_reverted() {
+        let mut history = History::default();
+        history.record("draw_line", true); // No checkpoint yet: nothing to count
+        history.checkpoint("outline", 1).unwrap();
+        history.record("draw_line", true);
+        history.record("set_color", false);
+        history.checkpoint("fill", 2).unwrap();
+        history.record("draw_shape", true);
+        history.record("draw_pixel", true);
+        assert_eq!(history.get("outline").unwrap().operations, 3);
+        assert_eq!(history.get("fill").unwrap().operations, 2);
+
+        history.reverted("fill");
+        assert_eq!(history.get("outline").unwrap().operations, 1);
+        assert_eq!(history.get("fill").unwrap().operations, 0);
+        history.reverted("outline");
+        assert!(history.get("fill").is_err());
+        assert_eq!(history.get("outline").unwrap().operations, 0);
+
+        assert!(history.checkpoint("outline", 3).unwrap()); // Replaces the old one
+        assert_eq!(history.labels().collect::<Vec<_>>(), vec!["outline"]);
+        history.record("new_document", true);
+        assert!(history.get("outline").is_err());
+        assert!(history.checkpoint("", 0).is_err());
+    }
+
+    #[test]
+    fn test_fingerprint_tells_captures_apart() {
+        let image = |rgba: Vec<u8>, width| CapturedImage { width, height: 1, rgba };
+        assert_eq!(fingerprint(&image(vec![0; 8], 2)), fingerprint(&image(vec![0; 8], 2)));
+        assert_ne!(fingerprint(&image(vec![0; 8], 2)), fingerprint(&image(vec![0, 0, 0, 0, 1, 0, 0, 0], 2)));
+        assert_ne!(fingerprint(&image(vec![0; 8], 2)), fingerprint(&image(vec![0; 8], 1)));
+    }
+}
@@ -0,0 +1,253 @@
+// Record and replay of synthesized input.
+//
+// While recording is on, every INPUT the server passes to SendInput is
+// appended to a JSON Lines file together with its offset from the start of
+// the recording and, for absolute mouse moves, the screen position it
+// targets. Replaying the file re-injects the same events with the same
+// spacing, so a stroke can be reproduced exactly when chasing fidelity bugs
+// or a user-reported drawing problem.
+
+use crate::error::{MspMcpError, Result};
+use tracing::info;
+#[cfg(feature = "windows-automation")]
+use tracing::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+#[cfg(feature = "windows-automation")]
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+    SendInput, INPUT, INPUT_KEYBOARD, INPUT_MOUSE, MOUSEEVENTF_ABSOLUTE,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum InputEvent {
+    Mouse {
+        dx: i32,                   // Raw MOUSEINPUT values, replayed as-is
+        dy: i32,
+        mouse_data: u32,
+        flags: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        screen_x: Option<i32>,     // Target of absolute moves, for reading the log
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        screen_y: Option<i32>,
+    },
+    Keyboard {
+        vk: u16,
+        scan: u16,
+        flags: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedInput {
+    pub time_ms: u64, // Offset from the start of the recording
+    #[serde(flatten)]
+    pub event: InputEvent,
+}
+
+// An active recording; only SendInput adds to it, so without
+// windows-automation a recording stays empty
+#[cfg_attr(not(feature = "windows-automation"), allow(dead_code))]
+struct Recorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    started: Instant,
+    events: usize,
+}
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+// Events synthesized since startup, recorded or not (for rate limits)
+static EVENTS_SENT: AtomicU64 = AtomicU64::new(0);
+
+/// Number of input events synthesized since startup.
+pub fn events_sent() -> u64 {
+    EVENTS_SENT.load(Ordering::Relaxed)
+}
+
+/// Starts writing every synthesized input to `path` (truncating it).
+pub fn start_recording(path: &Path) -> Result<()> {
+    let mut recorder = RECORDER.lock().map_err(|_| MspMcpError::General("Failed to lock input recorder".to_string()))?;
+    if let Some(active) = recorder.as_ref() {
+        return Err(MspMcpError::General(format!("Already recording input to {}", active.path.display())));
+    }
+    let file = File::create(path)?;
+    *recorder = Some(Recorder { path: path.to_path_buf(), writer: BufWriter::new(file), started: crate::clock::now(), events: 0 });
+    info!("Recording synthesized input to {}", path.display());
+    Ok(())
+}
+
+/// Stops recording. Returns the file written and the number of events.
+pub fn stop_recording() -> Result<(PathBuf, usize)> {
+    let mut recorder = RECORDER.lock().map_err(|_| MspMcpError::General("Failed to lock input recorder".to_string()))?;
+    let mut active = recorder.take().ok_or_else(|| MspMcpError::General("Input recording is not active".to_string()))?;
+    active.writer.flush()?;
+    info!("Stopped recording input: {} events in {}", active.events, active.path.display());
+    Ok((active.path, active.events))
+}
+
+/// Counts inputs about to be sent and appends them to the active recording.
+/// Write failures end the recording rather than the drawing operation.
+#[cfg(feature = "windows-automation")]
+pub fn record(inputs: &[INPUT]) {
+    EVENTS_SENT.fetch_add(inputs.len() as u64, Ordering::Relaxed);
+    let Ok(mut recorder) = RECORDER.lock() else { return };
+    let Some(active) = recorder.as_mut() else { return };
+
+    let time_ms = (crate::clock::now() - active.started).as_millis() as u64;
+    let mut written = Ok(());
+    for input in inputs {
+        let Some(event) = event_from_input(input) else { continue };
+        let line = serde_json::to_string(&RecordedInput { time_ms, event }).map_err(std::io::Error::from);
+        written = line.and_then(|line| writeln!(active.writer, "{}", line));
+        if written.is_err() {
+            break;
+        }
+        active.events += 1;
+    }
+    if let Err(e) = written.and_then(|_| active.writer.flush()) {
+        warn!("Stopping input recording to {}: {}", active.path.display(), e);
+        *recorder = None;
+    }
+}
+
+// Converts an INPUT to its recorded form (hardware inputs are never sent)
+#[cfg(feature = "windows-automation")]
+fn event_from_input(input: &INPUT) -> Option<InputEvent> {
+    match input.r#type {
+        INPUT_MOUSE => {
+            let mi = unsafe { input.Anonymous.mi };
+            let (screen_x, screen_y) = if mi.dwFlags & MOUSEEVENTF_ABSOLUTE != 0 {
+                let (x, y) = crate::windows::normalized_to_screen(mi.dx, mi.dy);
+                (Some(x), Some(y))
+            } else {
+                (None, None)
+            };
+            Some(InputEvent::Mouse { dx: mi.dx, dy: mi.dy, mouse_data: mi.mouseData, flags: mi.dwFlags, screen_x, screen_y })
+        }
+        INPUT_KEYBOARD => {
+            let ki = unsafe { input.Anonymous.ki };
+            Some(InputEvent::Keyboard { vk: ki.wVk, scan: ki.wScan, flags: ki.dwFlags })
+        }
+        _ => None,
+    }
+}
+
+// Builds the INPUT for a recorded event
+#[cfg(feature = "windows-automation")]
+fn input_from_event(event: &InputEvent) -> INPUT {
+    let mut input: INPUT = unsafe { std::mem::zeroed() };
+    match *event {
+        InputEvent::Mouse { dx, dy, mouse_data, flags, .. } => {
+            input.r#type = INPUT_MOUSE;
+            let mi = unsafe { &mut input.Anonymous.mi };
+            mi.dx = dx;
+            mi.dy = dy;
+            mi.mouseData = mouse_data;
+            mi.dwFlags = flags;
+        }
+        InputEvent::Keyboard { vk, scan, flags } => {
+            input.r#type = INPUT_KEYBOARD;
+            let ki = unsafe { &mut input.Anonymous.ki };
+            ki.wVk = vk;
+            ki.wScan = scan;
+            ki.dwFlags = flags;
+        }
+    }
+    input
+}
+
+/// Parses a recording (one JSON object per line, blank lines ignored).
+pub fn parse_recording(text: &str) -> Result<Vec<RecordedInput>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e|
+            MspMcpError::InvalidParameters(format!("Recording line {}: {}", i + 1, e))))
+        .collect()
+}
+
+/// Reads a recording file.
+pub fn load_recording(path: &Path) -> Result<Vec<RecordedInput>> {
+    parse_recording(&std::fs::read_to_string(path)?)
+}
+
+/// Time to wait before each event when replaying at `speed` (2.0 is twice
+/// as fast). Out-of-order timestamps don't wait.
+pub fn replay_delays(events: &[RecordedInput], speed: f64) -> Vec<Duration> {
+    let mut previous = events.first().map(|e| e.time_ms).unwrap_or(0);
+    events.iter()
+        .map(|event| {
+            let gap = event.time_ms.saturating_sub(previous);
+            previous = previous.max(event.time_ms);
+            Duration::from_secs_f64(gap as f64 / 1000.0 / speed)
+        })
+        .collect()
+}
+
+/// Re-injects recorded events with their original spacing. Replayed input
+/// is not itself recorded.
+#[cfg(feature = "windows-automation")]
+pub fn replay(events: &[RecordedInput], speed: f64) -> Result<()> {
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err(MspMcpError::InvalidParameters(format!("Replay speed must be positive, got {}", speed)));
+    }
+    crate::windows::check_input_desktop()?;
+    info!("Replaying {} input events at {}x speed", events.len(), speed);
+
+    let result = events.iter().zip(replay_delays(events, speed)).try_for_each(|(event, delay)| {
+        crate::clock::sleep(delay);
+        let input = input_from_event(&event.event);
+        let sent = unsafe { SendInput(1, &input, std::mem::size_of::<INPUT>() as i32) };
+        if sent == 1 {
+            Ok(())
+        } else {
+            Err(MspMcpError::WindowsApiError(format!("SendInput failed replaying the event at {} ms", event.time_ms)))
+        }
+    });
+
+    // A recording cut off mid-stroke would leave the button down
+    if let Err(e) = crate::windows::release_held_input() {
+        warn!("Failed to release held input after replay: {}", e);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_round_trip() {
+        let text = concat!(
+            "{\"time_ms\":0,\"type\":\"mouse\",\"dx\":100,\"dy\":200,\"mouse_data\":0,\"flags\":32769,\"screen_x\":3,\"screen_y\":6}\n",
+            "\n",
+            "{\"time_ms\":15,\"type\":\"keyboard\",\"vk\":17,\"scan\":0,\"flags\":2}\n",
+        );
+        let events = parse_recording(text).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].event, InputEvent::Keyboard { vk: 17, scan: 0, flags: 2 });
+        let line = serde_json::to_string(&events[0]).unwrap();
+        assert_eq!(parse_recording(&line).unwrap()[0], events[0]);
+    }
+
+    #[test]
+    fn test_bad_line_is_reported() {
+        let err = parse_recording("{\"time_ms\":0,\"type\":\"keyboard\",\"vk\":1,\"scan\":0,\"flags\":0}\nnope").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn test_replay_delays_scale_with_speed() {
+        let event = |time_ms| RecordedInput { time_ms, event: InputEvent::Keyboard { vk: 0, scan: 0, flags: 0 } };
+        let events = [event(100), event(300), event(250), event(400)];
+        let delays = replay_delays(&events, 2.0);
+        let millis: Vec<u128> = delays.iter().map(|d| d.as_millis()).collect();
+        assert_eq!(millis, vec![0, 100, 0, 50]);
+    }
+}
@@ -0,0 +1,154 @@
+// JSON-RPC 2.0 request parsing and validation.
+//
+// Everything here sees raw client input before any handler does, so it is
+// kept free of side effects and is the entry point for the fuzz target in
+// fuzz/. Explicit limits keep pathological input (deep nesting, huge
+// strings) from exhausting the stack or memory; the transport separately
+// caps the size of a whole message.
+
+use serde_json::Value;
+
+// Deepest array/object nesting accepted. Real requests need about 4 levels;
+// serde_json's own limit (128) is kept well out of reach
+pub const MAX_NESTING_DEPTH: usize = 32;
+
+// Longest single string (key or value) accepted, in bytes
+pub const MAX_STRING_BYTES: usize = 1024 * 1024;
+
+/// A request split into its parts: method, params and id (None for
+/// notifications).
+pub type ParsedRequest = (String, Option<Value>, Option<Value>);
+
+/// Returns the deepest array/object nesting in `text`, giving up once it
+/// passes `limit`. Brackets inside strings don't count.
+pub fn nesting_depth(text: &str, limit: usize) -> usize {
+    let (mut depth, mut deepest) = (0usize, 0usize);
+    let (mut in_string, mut escaped) = (false, false);
+    for byte in text.bytes() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                deepest = deepest.max(depth);
+                if deepest > limit {
+                    break;
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    deepest
+}
+
+// Finds a string longer than MAX_STRING_BYTES anywhere in a parsed value
+fn has_oversized_string(value: &Value) -> bool {
+    match value {
+        Value::String(s) => s.len() > MAX_STRING_BYTES,
+        Value::Array(items) => items.iter().any(has_oversized_string),
+        Value::Object(map) => map.iter().any(|(k, v)| k.len() > MAX_STRING_BYTES || has_oversized_string(v)),
+        _ => false,
+    }
+}
+
+/// Parses one incoming message as JSON, enforcing the nesting and string
+/// limits. Errors are reported to the client as JSON-RPC parse errors.
+pub fn parse_json_rpc_request(text: &str) -> Result<Value, String> {
+    if nesting_depth(text, MAX_NESTING_DEPTH) > MAX_NESTING_DEPTH {
+        return Err(format!("JSON nesting deeper than {} levels", MAX_NESTING_DEPTH));
+    }
+    let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    if has_oversized_string(&value) {
+        return Err(format!("String longer than {} bytes", MAX_STRING_BYTES));
+    }
+    Ok(value)
+}
+
+/// Validates a JSON-RPC 2.0 request and extracts method, params and id.
+/// The id is None for notifications (no `id` member at all). On failure
+/// returns the request's id (when it has a usable one) and the reason.
+pub fn extract_method_and_params(request: &Value) -> Result<ParsedRequest, (Value, String)> {
+    // Check this is a JSON-RPC request object
+    let obj = request.as_object()
+        .ok_or_else(|| (Value::Null, "Request must be a JSON object".to_string()))?;
+
+    // Extract the id; an explicit null is still a request, only a missing id is a notification
+    let id = obj.get("id").cloned();
+    let error_id = match &id {
+        Some(value @ (Value::String(_) | Value::Number(_))) => value.clone(),
+        Some(Value::Null) | None => Value::Null,
+        Some(_) => return Err((Value::Null, "'id' must be a string, number or null".to_string())),
+    };
+
+    // The JSON-RPC version is required and must be exactly "2.0"
+    match obj.get("jsonrpc") {
+        Some(version) if version == "2.0" => {}
+        Some(_) => return Err((error_id, "Only JSON-RPC 2.0 is supported".to_string())),
+        None => return Err((error_id, "Missing 'jsonrpc' field".to_string())),
+    }
+
+    // Extract the method
+    let method = obj.get("method")
+        .ok_or_else(|| (error_id.clone(), "Missing 'method' field".to_string()))?
+        .as_str()
+        .ok_or_else(|| (error_id.clone(), "'method' must be a string".to_string()))?
+        .to_string();
+
+    // Extract the params (optional, but must be structured when present)
+    let params = obj.get("params").cloned();
+    if let Some(p) = &params {
+        if !p.is_object() && !p.is_array() {
+            return Err((error_id, "'params' must be an object or array".to_string()));
+        }
+    }
+
+    Ok((method, params, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_nesting_limit() {
+        let deep = format!("{}{}", "[".repeat(MAX_NESTING_DEPTH + 1), "]".repeat(MAX_NESTING_DEPTH + 1));
+        assert!(parse_json_rpc_request(&deep).unwrap_err().contains("nesting"));
+        // Brackets inside strings are just text
+        let quoted = format!("{{\"method\":\"{}\\\"\"}}", "[".repeat(100));
+        assert!(parse_json_rpc_request(&quoted).is_ok());
+        // Unbalanced input that would never parse is cut off early
+        assert_eq!(nesting_depth(&"{".repeat(10_000), 5), 6);
+    }
+
+    #[test]
+    fn test_string_limit() {
+        let huge = json!({"jsonrpc": "2.0", "method": "x".repeat(MAX_STRING_BYTES + 1)}).to_string();
+        assert!(parse_json_rpc_request(&huge).unwrap_err().contains("String longer"));
+    }
+
+    #[test]
+    fn test_extract_method_and_params() {
+        let (method, params, id) = extract_method_and_params(
+            &json!({"jsonrpc": "2.0", "id": 7, "method": "draw_pixel", "params": {"x": 1}})).unwrap();
+        assert_eq!((method.as_str(), params, id), ("draw_pixel", Some(json!({"x": 1})), Some(json!(7))));
+
+        let (_, _, id) = extract_method_and_params(&json!({"jsonrpc": "2.0", "method": "exit"})).unwrap();
+        assert_eq!(id, None);
+
+        let (id, message) = extract_method_and_params(&json!({"jsonrpc": "1.0", "id": "a", "method": "x"})).unwrap_err();
+        assert_eq!((id, message.as_str()), (json!("a"), "Only JSON-RPC 2.0 is supported"));
+        assert!(extract_method_and_params(&json!({"jsonrpc": "2.0", "id": [], "method": "x"})).is_err());
+        assert!(extract_method_and_params(&json!({"jsonrpc": "2.0", "method": "x", "params": 3})).is_err());
+        assert!(extract_method_and_params(&json!([1, 2])).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+// Paint's UI layout, per Paint version.
+//
+// Some automation still clicks by position: the tool buttons in the ribbon
+// and the drawing area's offset in the window. Those positions move between
+// Paint releases, so they come from a layout table picked by the version of
+// the connected Paint executable (e.g. 11.2410.28.0) instead of constants.
+// A table can also name the UI Automation id of a tool button, which is
+// tried before matching buttons by name. The `[ui_layout]` config table
+// picks a table by name and overrides any of its values, and positions found
+// by `calibrate` (see calibrate.rs) replace them for the window size and DPI
+// they were measured at.
+
+use crate::config::UiLayoutConfig;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tracing::info;
+
+// Layout used when Paint's version can't be read
+pub const DEFAULT_LAYOUT: &str = "windows11";
+
+struct BuiltInLayout {
+    name: &'static str,
+    min_version: &'static [u32], // Oldest Paint version the layout fits
+    toolbar_height: i32,
+    left_margin: i32,
+    tool_row_y: i32,
+    tools: &'static [(&'static str, f64)],
+}
+
+// Oldest first; a version gets the last layout whose min_version it reaches
+const BUILT_IN: &[BuiltInLayout] = &[
+    // Classic Paint (mspaint.exe 10.0.x on Windows 10), with the Tools group
+    // left of the Shapes gallery
+    BuiltInLayout {
+        name: "windows10",
+        min_version: &[],
+        toolbar_height: 145,
+        left_margin: 5,
+        tool_row_y: 75,
+        tools: &[
+            ("select", 0.06), ("pencil", 0.19), ("fill", 0.21), ("text", 0.23),
+            ("eraser", 0.25), ("brush", 0.29), ("shape", 0.38),
+        ],
+    },
+    // The Windows 11 Paint app (11.x), with the single-row toolbar
+    BuiltInLayout {
+        name: "windows11",
+        min_version: &[11],
+        toolbar_height: 120,
+        left_margin: 5,
+        tool_row_y: 60,
+        tools: &[
+            ("pencil", 1.0 / 20.0), ("brush", 1.0 / 10.0), ("fill", 1.0 / 7.0), ("text", 1.0 / 5.0),
+            ("eraser", 1.0 / 4.0), ("select", 1.0 / 3.0), ("shape", 1.0 / 2.0),
+        ],
+    },
+];
+
+/// Where things are in one Paint version's window.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct UiLayout {
+    pub name: String,
+    pub toolbar_height: i32,                      // Client pixels above the drawing area
+    pub left_margin: i32,                         // Client pixels left of the drawing area
+    pub tool_row_y: i32,                          // Client y of the tool buttons
+    pub tool_positions: BTreeMap<String, f64>,    // Tool -> client x as a fraction of the window width
+    pub automation_ids: BTreeMap<String, String>, // Tool -> AutomationId of its button
+    // Window key (see window_key) -> tool -> x measured by calibrate
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub calibrated: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+impl UiLayout {
+    /// The built-in layout called `name`, if there is one.
+    pub fn built_in(name: &str) -> Option<UiLayout> {
+        BUILT_IN.iter().find(|layout| layout.name == name).map(|layout| UiLayout {
+            name: layout.name.to_string(),
+            toolbar_height: layout.toolbar_height,
+            left_margin: layout.left_margin,
+            tool_row_y: layout.tool_row_y,
+            tool_positions: layout.tools.iter().map(|(tool, x)| (tool.to_string(), *x)).collect(),
+            automation_ids: BTreeMap::new(),
+            calibrated: BTreeMap::new(),
+        })
+    }
+
+    /// The layout for a Paint version (as its executable reports it) with
+    /// the config's choice and overrides applied.
+    pub fn select(paint_version: Option<&str>, config: &UiLayoutConfig) -> UiLayout {
+        let name = config.name.clone().unwrap_or_else(|| layout_for_version(paint_version).to_string());
+        let mut layout = UiLayout::built_in(&name)
+            .or_else(|| UiLayout::built_in(DEFAULT_LAYOUT))
+            .expect("the default layout is built in");
+        if let Some(height) = config.toolbar_height {
+            layout.toolbar_height = height;
+        }
+        if let Some(margin) = config.left_margin {
+            layout.left_margin = margin;
+        }
+        if let Some(y) = config.tool_row_y {
+            layout.tool_row_y = y;
+        }
+        layout.tool_positions.extend(config.tool_positions.clone());
+        layout.automation_ids.extend(config.automation_ids.clone());
+        layout
+    }
+
+    /// This layout with the calibrated positions for a window (see window_key) in place.
+    pub fn for_window(&self, window_key: &str) -> UiLayout {
+        let mut layout = self.clone();
+        if let Some(positions) = self.calibrated.get(window_key) {
+            layout.tool_positions.extend(positions.clone());
+        }
+        layout
+    }
+
+    /// Client position of a tool button in a window `window_width` pixels wide.
+    pub fn tool_position(&self, tool: &str, window_width: i32) -> Option<(i32, i32)> {
+        let x = self.tool_positions.get(&tool.to_lowercase())?;
+        Some(((window_width as f64 * x).round() as i32, self.tool_row_y))
+    }
+}
+
+/// Calibrations are kept per window size and DPI, e.g. "1280x800@144".
+pub fn window_key(width: i32, height: i32, dpi: u32) -> String {
+    format!("{}x{}@{}", width, height, dpi)
+}
+
+/// Names of the built-in layouts, oldest first.
+pub fn built_in_names() -> Vec<&'static str> {
+    BUILT_IN.iter().map(|layout| layout.name).collect()
+}
+
+// Name of the newest built-in layout the version reaches
+fn layout_for_version(paint_version: Option<&str>) -> &'static str {
+    let Some(version) = paint_version.and_then(parse_version) else {
+        return DEFAULT_LAYOUT;
+    };
+    BUILT_IN.iter()
+        .rfind(|layout| version.as_slice() >= layout.min_version)
+        .map_or(DEFAULT_LAYOUT, |layout| layout.name)
+}
+
+// "11.2410.28.0" -> [11, 2410, 28, 0]
+fn parse_version(version: &str) -> Option<Vec<u32>> {
+    version.trim().split('.').map(|part| part.parse().ok()).collect()
+}
+
+// The layout of the Paint window being driven. The Win32 code reads it
+// wherever it clicks by position
+static ACTIVE: Mutex<Option<UiLayout>> = Mutex::new(None);
+
+/// Makes `layout` the one used from now on.
+pub fn set_active(layout: UiLayout) {
+    info!("Using the '{}' Paint layout", layout.name);
+    if let Ok(mut active) = ACTIVE.lock() {
+        *active = Some(layout);
+    }
+}
+
+/// The layout in use; the default layout before a window is connected.
+pub fn active() -> UiLayout {
+    ACTIVE.lock().ok()
+        .and_then(|active| active.clone())
+        .or_else(|| UiLayout::built_in(DEFAULT_LAYOUT))
+        .expect("the default layout is built in")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_follows_version_and_config() {
+        let config = UiLayoutConfig::default();
+        assert_eq!(UiLayout::select(Some("11.2410.28.0"), &config).name, "windows11");
+        assert_eq!(UiLayout::select(Some("10.0.19041.1"), &config).name, "windows10");
+        assert_eq!(UiLayout::select(Some("not a version"), &config).name, DEFAULT_LAYOUT);
+        assert_eq!(UiLayout::select(None, &config).name, DEFAULT_LAYOUT);
+
+        let layout = UiLayout::built_in("windows11").unwrap();
+        assert_eq!(layout.tool_position("Pencil", 1000), Some((50, 60)));
+        assert_eq!(layout.tool_position("shape", 1000), Some((500, 60)));
+        assert_eq!(layout.tool_position("lasso", 1000), None);
+
+        let config = UiLayoutConfig {
+            name: Some("windows10".to_string()),
+            tool_row_y: Some(80),
+            tool_positions: BTreeMap::from([("pencil".to_string(), 0.5)]),
+            automation_ids: BTreeMap::from([("fill".to_string(), "FillButton".to_string())]),
+            ..UiLayoutConfig::default()
+        };
+        let layout = UiLayout::select(Some("11.2410.28.0"), &config);
+        assert_eq!(layout.name, "windows10");
+        assert_eq!(layout.toolbar_height, 145);
+        assert_eq!(layout.tool_position("pencil", 1000), Some((500, 80)));
+        assert_eq!(layout.automation_ids["fill"], "FillButton");
+
+        let mut layout = UiLayout::built_in("windows11").unwrap();
+        layout.calibrated.insert(window_key(1000, 700, 96), BTreeMap::from([("pencil".to_string(), 0.07)]));
+        assert_eq!(layout.for_window("1000x700@96").tool_position("pencil", 1000), Some((70, 60)));
+        assert_eq!(layout.for_window("1000x700@144").tool_position("pencil", 1000), Some((50, 60)));
+    }
+}
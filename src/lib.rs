@@ -4,22 +4,92 @@ use mcp_rust_sdk::{
     transport::stdio::StdioTransport,
     types::{ClientCapabilities, ServerCapabilities, Implementation},
 };
-use log::{info, error, LevelFilter, debug, warn};
+use tracing::{info, error, debug, warn, Instrument};
 use tokio::runtime::Runtime;
 use std::sync::Arc;
 use std::sync::Mutex;
-use windows_sys::Win32::Foundation::HWND;
+use std::collections::HashSet;
+use crate::backend::HWND;
 use std::process::Command;
 use std::io::{self, Write};
+use futures::FutureExt;
 
 // Define modules
 pub mod error;
 pub mod protocol;
+#[cfg(feature = "windows-automation")]
 pub mod windows;
 pub mod core;
+#[cfg(feature = "windows-automation")]
 pub mod uia;
+pub mod document;
+#[cfg(feature = "windows-automation")]
+pub mod virtual_desktop;
+pub mod transport;
+pub mod client_log;
+pub mod config;
+pub mod config_watch;
+pub mod tools;
+pub mod capture;
+pub mod resources;
+pub mod backend;
+pub mod simulate;
+pub mod input_record;
+pub mod self_test;
+pub mod jsonrpc;
+pub mod clock;
+pub mod faults;
+pub mod geometry;
+pub mod fs_guard;
+pub mod rate_limit;
+pub mod audit;
+pub mod log_level;
+pub mod timing;
+pub mod screenshot_trail;
+pub mod redact;
+pub mod heartbeat;
+pub mod panics;
+pub mod palette;
+pub mod coordinates;
+pub mod transform;
+pub mod pen;
+pub mod layout;
+pub mod calibrate;
+pub mod macros;
+pub mod plugins;
+pub mod repl;
+pub mod gradient;
+pub mod pattern;
+pub mod presets;
+pub mod stroke_width;
+pub mod glyphs;
+pub mod watchdog;
+pub mod session;
+pub mod history;
+#[cfg(feature = "windows-automation")]
+pub mod message_input;
+pub mod controller;
+
+// Paint automation without the JSON-RPC server
+pub use controller::PaintController;
 
 use crate::error::{Result, MspMcpError};
+use crate::document::DocumentState;
+use crate::config::ServerConfig;
+use crate::backend::PaintBackend;
+use crate::clock::{Clock, SystemClock};
+
+// The real Paint when this build can drive it
+#[cfg(feature = "windows-automation")]
+fn default_backend() -> Arc<dyn PaintBackend> {
+    Arc::new(crate::backend::WindowsBackend)
+}
+
+// Without the windows-automation feature there is only the in-memory canvas
+#[cfg(not(feature = "windows-automation"))]
+fn default_backend() -> Arc<dyn PaintBackend> {
+    Arc::new(crate::simulate::SimulatedBackend::new())
+}
 
 // Helper function to log process tree (Windows specific for now)
 fn log_process_tree(label: &str) {
@@ -48,77 +118,508 @@ fn log_process_tree(label: &str) {
     }
 }
 
+// Client id used until connect or initialize names the client
+pub const DEFAULT_CLIENT_ID: &str = "anonymous";
+
 // Define a struct to hold our server state
 #[derive(Clone)]
 pub struct PaintServerState {
     pub paint_hwnd: Arc<Mutex<Option<HWND>>>, // Store HWND in Arc<Mutex>
+    pub paint_pid: Arc<Mutex<Option<u32>>>,   // Process owning paint_hwnd
+    pub document: Arc<Mutex<DocumentState>>,  // Metadata derived from the window title
+    pub dedicated_desktop: Arc<Mutex<bool>>,  // Automate Paint on its own virtual desktop
+    pub protocol_version: Arc<Mutex<String>>, // Paint protocol version negotiated at initialize/connect
+    pub config: Arc<Mutex<ServerConfig>>,     // Settings loaded from the config file
+    pub subscriptions: Arc<Mutex<HashSet<String>>>, // Resource URIs the client subscribed to
+    pub shutdown_requested: Arc<Mutex<bool>>, // Set by `shutdown`; only `exit` is accepted afterwards
+    pub backend: Arc<dyn PaintBackend>,       // Drives Paint (Win32/UIA, or a mock in tests)
+    pub clock: Arc<dyn Clock>,                // Time source for waits while handling requests
+    pub client_id: Arc<Mutex<String>>,        // Who is calling: connect's client_id or initialize's clientInfo.name
+    pub rate_limiter: Arc<crate::rate_limit::RateLimiter>, // Session-wide budgets from the config
+    pub audit: Option<Arc<crate::audit::AuditLog>>, // Request trail, if audit_log is configured
+    pub activity: Arc<crate::heartbeat::Activity>, // Uptime and requests in progress, for ping and heartbeats
+    pub palettes: Arc<Mutex<crate::palette::PaletteState>>, // Named palettes and what Color 1 and Color 2 hold
+    pub snap_grid: Arc<Mutex<Option<u32>>>,   // Grid drawing positions are rounded to, set by `set_snap`
+    pub transforms: Arc<Mutex<crate::transform::TransformStack>>, // Pushed by `push_transform`
+    pub pen: Arc<Mutex<crate::pen::PenState>>, // Path gathered by `pen_move_to` and `pen_line_to`
+    pub macros: Arc<Mutex<crate::macros::MacroStore>>, // Scripts kept by `register_macro`
+    pub history: Arc<Mutex<crate::history::History>>, // Checkpoints set by `checkpoint`
 }
 
 impl PaintServerState {
     pub fn new() -> Self {
+        Self::with_config(ServerConfig::load_default())
+    }
+
+    pub fn with_config(config: ServerConfig) -> Self {
+        Self::with_backend(config, default_backend())
+    }
+
+    pub fn with_backend(config: ServerConfig, backend: Arc<dyn PaintBackend>) -> Self {
+        // A [faults] section in the config makes backend calls fail at random
+        let backend: Arc<dyn PaintBackend> = if config.faults.is_enabled() {
+            Arc::new(crate::faults::FaultInjectingBackend::new(backend, config.faults.clone()))
+        } else {
+            backend
+        };
+        // Outermost, so the trail also shows steps that failed by injection
+        let backend: Arc<dyn PaintBackend> = if config.screenshot_trail.enabled {
+            match crate::screenshot_trail::ScreenshotTrailBackend::new(backend.clone(), &config.screenshot_trail) {
+                Ok(trail) => Arc::new(trail),
+                Err(e) => {
+                    error!("Screenshot trail disabled: failed to create the session folder: {}", e);
+                    backend
+                }
+            }
+        } else {
+            backend
+        };
+        // An unwritable audit log is reported but doesn't stop the server
+        let audit = config.audit_log.as_deref().and_then(|path| match crate::audit::AuditLog::open(path) {
+            Ok(log) => Some(Arc::new(log)),
+            Err(e) => {
+                error!("Audit logging disabled: failed to open {}: {}", path.display(), e);
+                None
+            }
+        });
         PaintServerState {
             paint_hwnd: Arc::new(Mutex::new(None)),
+            paint_pid: Arc::new(Mutex::new(None)),
+            document: Arc::new(Mutex::new(DocumentState::default())),
+            dedicated_desktop: Arc::new(Mutex::new(false)),
+            protocol_version: Arc::new(Mutex::new(crate::protocol::LATEST_PROTOCOL_VERSION.to_string())),
+            rate_limiter: Arc::new(crate::rate_limit::RateLimiter::new(config.rate_limits.clone())),
+            audit,
+            activity: Arc::new(crate::heartbeat::Activity::new()),
+            palettes: Arc::new(Mutex::new(crate::palette::PaletteState::default())),
+            snap_grid: Arc::new(Mutex::new(None)),
+            transforms: Arc::new(Mutex::new(crate::transform::TransformStack::default())),
+            pen: Arc::new(Mutex::new(crate::pen::PenState::default())),
+            macros: Arc::new(Mutex::new(crate::macros::MacroStore::default())),
+            history: Arc::new(Mutex::new(crate::history::History::default())),
+            config: Arc::new(Mutex::new(config)),
+            subscriptions: Arc::new(Mutex::new(HashSet::new())),
+            shutdown_requested: Arc::new(Mutex::new(false)),
+            backend,
+            clock: Arc::new(SystemClock),
+            client_id: Arc::new(Mutex::new(DEFAULT_CLIENT_ID.to_string())),
         }
     }
-}
 
-// Implement the server handler trait from mcp_rust_sdk
-#[async_trait::async_trait]
-impl ServerHandler for PaintServerState {
+    /// Starts tracking a Paint window by HWND and owning PID.
+    pub fn set_paint_window(&self, hwnd: HWND) -> Result<()> {
+        let pid = self.backend.window_pid(hwnd);
+        *self.paint_hwnd.lock().map_err(|_| MspMcpError::General("Failed to lock HWND state".to_string()))? = Some(hwnd);
+        *self.paint_pid.lock().map_err(|_| MspMcpError::General("Failed to lock PID state".to_string()))? = Some(pid);
+        let mut document = self.document.lock().map_err(|_| MspMcpError::General("Failed to lock document state".to_string()))?;
+        *document = DocumentState::default();
+        document.update_from_title(&self.backend.window_title(hwnd));
+        self.forget_color_slots();
+        self.forget_custom_colors();
+        // Another window has its own undo stack
+        if let Ok(mut history) = self.history.lock() {
+            history.clear();
+        }
+        crate::layout::set_active(self.layout_for(hwnd, &self.config()?));
+        info!("Tracking Paint window HWND={} (PID={})", hwnd, pid);
+        Ok(())
+    }
 
-    // Required method: initialize
-    async fn initialize(&self, _implementation: Implementation, _client_capabilities: ClientCapabilities) -> std::result::Result<ServerCapabilities, SdkError> {
-        info!("Server received initialize request. Finding/Launching Paint...");
+    /// The UI layout for the Paint version running `hwnd`, as `config`
+    /// says, with the calibrations saved for it (see layout.rs).
+    fn layout_for(&self, hwnd: HWND, config: &ServerConfig) -> crate::layout::UiLayout {
+        let version = self.backend.paint_version(hwnd);
+        info!("Paint version {}", version.as_deref().unwrap_or("unknown"));
+        let mut layout = crate::layout::UiLayout::select(version.as_deref(), &config.ui_layout);
+        match crate::calibrate::load(&config.calibration_path()) {
+            Ok(mut calibrations) => layout.calibrated = calibrations.remove(&layout.name).unwrap_or_default(),
+            Err(e) => warn!("Ignoring the calibration file: {}", e),
+        }
+        layout
+    }
 
-        // --- Log process tree BEFORE attempting launch ---
-        log_process_tree("Before Paint Find/Launch");
-        // -----------------------------------------------
+    /// A library controller for the Paint window this session is connected to.
+    pub fn controller(&self) -> Result<PaintController> {
+        let hwnd = *self.paint_hwnd.lock()
+            .map_err(|_| MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        let hwnd = hwnd.ok_or(MspMcpError::WindowNotFound)?;
+        Ok(PaintController::attach(self.backend.clone(), hwnd))
+    }
 
-        // --- Start: Logic moved from handle_connect ---
-        match crate::windows::get_paint_hwnd() {
-            Ok(hwnd) => {
-                 // Store the HWND in the shared state
-                let mut hwnd_state = self.paint_hwnd.lock()
-                    .map_err(|_| SdkError::protocol(ErrorCode::InternalError, "Failed to lock HWND state".to_string()))?;
-                *hwnd_state = Some(hwnd);
-                info!("Stored Paint HWND: {}", hwnd);
+    /// Negotiates and stores the Paint protocol version for this session.
+    pub fn negotiate_protocol_version(&self, requested: Option<&str>) -> Result<&'static str> {
+        let version = crate::protocol::negotiate_protocol_version(requested)?;
+        *self.protocol_version.lock().map_err(|_| MspMcpError::General("Failed to lock protocol version state".to_string()))? = version.to_string();
+        info!("Negotiated protocol version {}", version);
+        Ok(version)
+    }
 
-                // --- Log process tree AFTER successful find/launch ---
-                log_process_tree("After Paint Find/Launch");
-                // -----------------------------------------------------
+    /// Returns the negotiated Paint protocol version.
+    pub fn protocol_version(&self) -> Result<String> {
+        self.protocol_version.lock()
+            .map(|v| v.clone())
+            .map_err(|_| MspMcpError::General("Failed to lock protocol version state".to_string()))
+    }
+
+    /// Records who is calling, for rate limits.
+    pub fn set_client_id(&self, client_id: &str) -> Result<()> {
+        *self.client_id.lock().map_err(|_| MspMcpError::General("Failed to lock client state".to_string()))? = client_id.to_string();
+        Ok(())
+    }
+
+    /// Returns the current client's id.
+    pub fn client_id(&self) -> String {
+        self.client_id.lock().map(|c| c.clone()).unwrap_or_else(|_| DEFAULT_CLIENT_ID.to_string())
+    }
+
+    /// True once the client has sent `shutdown`.
+    pub fn is_shutdown_requested(&self) -> bool {
+        self.shutdown_requested.lock().map(|s| *s).unwrap_or(true)
+    }
+
+    /// Returns a copy of the current configuration.
+    pub fn config(&self) -> Result<ServerConfig> {
+        self.config.lock()
+            .map(|c| c.clone())
+            .map_err(|_| MspMcpError::General("Failed to lock config state".to_string()))
+    }
+
+    /// Takes the named settings from a reloaded config and applies them to
+    /// the parts of the server that hold their own copy.
+    pub fn reload_config(&self, new: &ServerConfig, settings: &[String]) -> Result<()> {
+        // Check the new log level first, so a bad one leaves every setting as it was
+        let log_level = match new.log_level.as_deref().filter(|_| settings.iter().any(|s| s == "log_level")) {
+            Some(name) => Some(crate::log_level::parse_level(name).ok_or_else(|| MspMcpError::ConfigError(format!(
+                "log_level must be a level such as \"debug\" or \"warn\", got \"{}\"", name)))?),
+            None => None,
+        };
+        let mut config = self.config.lock()
+            .map_err(|_| MspMcpError::General("Failed to lock config state".to_string()))?;
+        *config = config.with_settings_from(new, settings)?;
+        for setting in settings {
+            match setting.as_str() {
+                "rate_limits" => self.rate_limiter.set_limits(config.rate_limits.clone()),
+                "log_max_field_len" => crate::redact::set_max_field_len(config.log_max_field_len),
+                "input_desktop_wait_ms" => crate::session::set_desktop_wait_ms(config.input_desktop_wait_ms),
+                "ui_layout" | "calibration_file" => {
+                    let hwnd = *self.paint_hwnd.lock()
+                        .map_err(|_| MspMcpError::General("Failed to lock HWND state".to_string()))?;
+                    if let Some(hwnd) = hwnd {
+                        crate::layout::set_active(self.layout_for(hwnd, &config));
+                    }
+                }
+                "log_level" => match log_level {
+                    Some(level) => crate::log_level::set_level(None, level)?,
+                    // Taken out of the file: back to the levels the server started with
+                    None => crate::log_level::reset_levels()?,
+                },
+                _ => {}
             }
-            Err(e) => {
-                // Log process tree on failure too
-                log_process_tree("After Paint Find/Launch Failure");
+        }
+        info!("Reloaded settings: {}", settings.join(", "));
+        Ok(())
+    }
 
-                // If we can't get the HWND during init, it's a fatal error for this server
-                let error_msg = format!("Failed to find or launch Paint during initialization: {}", e);
-                error!("{}", error_msg);
-                // Convert our error to an SdkError for the initialize response
-                return Err(SdkError::protocol(ErrorCode::InternalError, error_msg));
+    /// Stops tracking the Paint window.
+    pub fn clear_paint_window(&self) -> Result<()> {
+        *self.paint_hwnd.lock().map_err(|_| MspMcpError::General("Failed to lock HWND state".to_string()))? = None;
+        *self.paint_pid.lock().map_err(|_| MspMcpError::General("Failed to lock PID state".to_string()))? = None;
+        *self.document.lock().map_err(|_| MspMcpError::General("Failed to lock document state".to_string()))? = DocumentState::default();
+        self.forget_color_slots();
+        self.forget_custom_colors();
+        Ok(())
+    }
+
+    /// Turns a request's coordinates in another space than canvas pixels
+    /// (see coordinates.rs) into canvas pixels. Canvas requests pass unchanged.
+    fn resolve_coordinate_space(&self, method: &str, mut params: Option<serde_json::Value>) -> Result<Option<serde_json::Value>> {
+        let space = crate::coordinates::requested(params.as_ref())?;
+        if space == crate::protocol::CoordinateSpace::Canvas {
+            return Ok(params);
+        }
+        if !crate::coordinates::COORDINATE_METHODS.contains(&method) {
+            return Err(MspMcpError::InvalidParameters(format!("'{}' takes no coordinates to convert", method)));
+        }
+        let hwnd = *self.paint_hwnd.lock()
+            .map_err(|_| MspMcpError::General("Failed to lock HWND state".to_string()))?;
+        let hwnd = hwnd.ok_or(MspMcpError::WindowNotFound)?;
+        // Fractions only need the canvas size; window and screen positions
+        // also need where the canvas is shown
+        let frame = if space == crate::protocol::CoordinateSpace::Normalized {
+            let (width, height) = self.backend.canvas_dimensions(hwnd)?;
+            crate::geometry::CanvasFrame::of_size(width, height)
+        } else {
+            let view = self.backend.canvas_view(hwnd)?;
+            if view.estimated {
+                warn!("Converting {:?} coordinates with an estimated canvas position", space);
             }
+            let window = self.backend.window_info(hwnd)?;
+            crate::geometry::CanvasFrame::from_view(&view, (window.client_origin_x, window.client_origin_y))
+        };
+        if let Some(params) = params.as_mut() {
+            crate::coordinates::resolve(params, space, &frame)?;
         }
-        // --- End: Logic moved from handle_connect ---
-        
-        // Return default capabilities (or customize later if needed)
-        info!("Paint found/launched. Initialization successful.");
-        Ok(ServerCapabilities::default())
+        Ok(params)
     }
 
-    // Required method: shutdown
-    async fn shutdown(&self) -> std::result::Result<(), SdkError> {
-        info!("Server received shutdown request.");
-        // TODO: Perform cleanup if necessary
+    /// Maps the positions of a drawing primitive through the pushed transforms, if any.
+    fn apply_transforms(&self, method: &str, mut params: Option<serde_json::Value>) -> Result<Option<serde_json::Value>> {
+        if !crate::transform::TRANSFORM_METHODS.contains(&method) {
+            return Ok(params);
+        }
+        let transform = self.transforms.lock()
+            .map_err(|_| MspMcpError::General("Failed to lock transform state".to_string()))?
+            .current();
+        if let (Some(transform), Some(params)) = (transform, params.as_mut()) {
+            crate::transform::apply(method, params, &transform)?;
+        }
+        Ok(params)
+    }
+
+    /// Rounds the positions of a drawing primitive to the `set_snap` grid, if one is set.
+    fn snap_to_grid(&self, method: &str, mut params: Option<serde_json::Value>) -> Option<serde_json::Value> {
+        let grid = self.snap_grid.lock().ok().and_then(|grid| *grid);
+        if let (Some(grid), Some(params)) = (grid, params.as_mut()) {
+            if crate::coordinates::SNAP_METHODS.contains(&method) {
+                crate::coordinates::snap(params, grid);
+            }
+        }
+        params
+    }
+
+    /// Drops the record of what Color 1 and Color 2 hold, so the next
+    /// palette color is set in Paint even if it looks unchanged.
+    pub fn forget_color_slots(&self) {
+        if let Ok(mut palettes) = self.palettes.lock() {
+            palettes.forget_slots();
+        }
+    }
+
+    /// Drops the record of the custom colors we added; another Paint window
+    /// has its own.
+    pub fn forget_custom_colors(&self) {
+        if let Ok(mut palettes) = self.palettes.lock() {
+            palettes.forget_custom();
+        }
+    }
+
+    /// Re-validates the tracked window before a request runs. The window is
+    /// followed by HWND/PID rather than by title, so saves and renames don't
+    /// lose it; if Paint replaced its main window we pick up the new one
+    /// from the same process. Title changes update the document metadata.
+    pub fn refresh_window_tracking(&self) -> Result<()> {
+        let hwnd = match *self.paint_hwnd.lock().map_err(|_| MspMcpError::General("Failed to lock HWND state".to_string()))? {
+            Some(hwnd) => hwnd,
+            None => return Ok(()), // Nothing tracked yet
+        };
+        let pid = match *self.paint_pid.lock().map_err(|_| MspMcpError::General("Failed to lock PID state".to_string()))? {
+            Some(pid) => pid,
+            None => return Ok(()),
+        };
+
+        let hwnd = if self.backend.is_window_owned_by(hwnd, pid) {
+            hwnd
+        } else {
+            match self.backend.find_window_for_pid(pid) {
+                Some(new_hwnd) => {
+                    warn!("Paint window HWND={} is gone; following PID {} to HWND={}", hwnd, pid, new_hwnd);
+                    *self.paint_hwnd.lock().map_err(|_| MspMcpError::General("Failed to lock HWND state".to_string()))? = Some(new_hwnd);
+                    new_hwnd
+                }
+                None => {
+                    warn!("Tracked Paint window HWND={} (PID={}) no longer exists", hwnd, pid);
+                    return self.clear_paint_window();
+                }
+            }
+        };
+
+        let title = self.backend.window_title(hwnd);
+        let mut document = self.document.lock().map_err(|_| MspMcpError::General("Failed to lock document state".to_string()))?;
+        if document.update_from_title(&title) {
+            debug!("Paint window title is now '{}'", title);
+            crate::transport::send_notification("notifications/paint/document_changed", serde_json::json!({
+                "title": document.title,
+                "document_name": document.document_name,
+                "has_unsaved_changes": document.has_unsaved_changes,
+                "file_path": document.file_path
+            }));
+        }
         Ok(())
     }
 
-    // Required method: handle_method
-    async fn handle_method(&self, method: &str, params: Option<serde_json::Value>) -> std::result::Result<serde_json::Value, SdkError> {
-        info!("Handling method: {} with params: {:?}", method, params);
+    /// Routes a request to its handler in `core`. Used by both the SDK
+    /// handler and the stdio loop in main.rs, which needs the typed error
+    /// to pick the right JSON-RPC error code.
+    pub async fn handle_request(&self, method: &str, params: Option<serde_json::Value>) -> Result<serde_json::Value> {
+        self.handle_request_with_id(None, &protocol::next_request_id(), method, params).await
+    }
+
+    /// Handles a request inside a `request` span carrying its method, JSON-RPC
+    /// id, correlation id and client, so everything logged while it runs
+    /// (including from windows.rs and uia.rs) can be traced back to it. The
+    /// correlation id is also returned as `_request_id` in the result.
+    pub async fn handle_request_with_id(&self, id: Option<&serde_json::Value>, request_id: &str, method: &str, params: Option<serde_json::Value>) -> Result<serde_json::Value> {
+        let id = id.map(|id| id.to_string()).unwrap_or_default(); // Empty for notifications
+        let span = tracing::info_span!("request", method = %method, id = %id, request_id = %request_id, client = %self.client_id());
+
+        async {
+            info!("Handling method: {} with params: {}", method, crate::redact::Redacted(params.as_ref()));
+
+            // Every wait below, down to the Win32 and UIA helpers, uses this state's clock
+            let _clock = clock::install(self.clock.clone());
+
+            let timing = crate::timing::start();
+            let started = clock::now();
+            let params_hash = self.audit.as_ref().map(|_| crate::audit::hash_params(params.as_ref()));
+            let watchdog = self.arm_watchdog(method);
+            // A panicking handler fails this request instead of the whole server
+            let mut result = match std::panic::AssertUnwindSafe(self.dispatch_request(method, params)).catch_unwind().await {
+                Ok(result) => result,
+                Err(payload) => Err(self.recover_from_panic(method, payload.as_ref())),
+            };
+            // Whatever the handler made of it, a request the watchdog cancelled failed
+            if let Some((watchdog, limit)) = watchdog {
+                if watchdog.tripped() {
+                    result = Err(MspMcpError::OperationTimeout(format!(
+                        "{} ran past its {} ms limit; held input was released and the gesture cancelled",
+                        method, limit.as_millis())));
+                }
+            }
+            let elapsed = clock::now() - started;
+            if let (Some(audit), Some(params_hash)) = (&self.audit, params_hash) {
+                audit.append(&crate::audit::entry_for(&self.client_id(), method, params_hash, &result, elapsed.as_millis() as u64));
+            }
+            self.report_if_slow(method, request_id, elapsed, timing.breakdown());
+            if let Ok(response) = &mut result {
+                protocol::add_request_id(response, request_id);
+            }
+            result
+        }
+        .instrument(span)
+        .await
+    }
+
+    // Starts the watchdog for a request that sends input, unless its limit
+    // is off. Macros and animations aren't watched as a whole, since each of
+    // their steps gets its own watchdog. On tripping it cleans up from its
+    // own thread, since the handler is stuck
+    fn arm_watchdog(&self, method: &str) -> Option<(crate::watchdog::Watchdog, std::time::Duration)> {
+        if !tools::sends_input(method) || tools::reenters_request_path(method) {
+            return None;
+        }
+        let limit = self.config().ok()?.watchdog.limit_for(method)?;
+        let backend = self.backend.clone();
+        let method = method.to_string();
+        let watchdog = crate::watchdog::arm(limit, move || {
+            error!("{} is still running after {:?}; releasing held input and cancelling it", method, limit);
+            if let Err(e) = backend.release_held_input() {
+                warn!("Watchdog failed to release held input: {}", e);
+            }
+            if let Err(e) = backend.cancel_gesture() {
+                warn!("Watchdog failed to press Escape: {}", e);
+            }
+        });
+        Some((watchdog, limit))
+    }
+
+    // Cleans up after a handler panicked. Whatever it was doing may have left
+    // a mouse button or modifier down, which would otherwise stay down.
+    fn recover_from_panic(&self, method: &str, payload: &(dyn std::any::Any + Send)) -> MspMcpError {
+        let message = crate::panics::message(payload);
+        error!("Handler for {} panicked: {}", method, message);
+        if let Err(e) = self.backend.release_held_input() {
+            warn!("Failed to release held input after a panic: {}", e);
+        }
+        MspMcpError::Panicked(message)
+    }
+
+    // Warns (and with notify_client set, tells the client) when a request
+    // took longer than its slow_operations threshold
+    fn report_if_slow(&self, method: &str, request_id: &str, elapsed: std::time::Duration, breakdown: crate::timing::Breakdown) {
+        // The method run by tools/call reports for itself
+        if method == "tools/call" {
+            return;
+        }
+        let Ok(config) = self.config() else { return };
+        let Some(threshold) = config.slow_operations.threshold_for(method) else { return };
+        if elapsed <= threshold {
+            return;
+        }
+
+        warn!("Slow operation: {} took {:?} (threshold {:?}): {}", method, elapsed, threshold, breakdown.describe(elapsed));
+        if config.slow_operations.notify_client {
+            crate::transport::send_notification("notifications/paint/slow_operation", serde_json::json!({
+                "method": method,
+                "request_id": request_id,
+                "duration_ms": elapsed.as_millis() as u64,
+                "threshold_ms": threshold.as_millis() as u64,
+                "breakdown": breakdown.to_json(elapsed)
+            }));
+        }
+    }
+
+    async fn dispatch_request(&self, method: &str, mut params: Option<serde_json::Value>) -> Result<serde_json::Value> {
+        // After shutdown nothing but exit (and ping, which doesn't touch Paint) may run
+        if self.is_shutdown_requested() && method != "exit" && method != "ping" {
+            return Err(MspMcpError::ShuttingDown);
+        }
+
+        // With confirm_destructive on, destructive methods (and saves that may
+        // overwrite a file) need an explicit "confirm": true
+        if tools::needs_confirm(method, params.as_ref()) && self.config()?.confirm_destructive {
+            let confirmed = params.as_ref()
+                .and_then(|p| p.get("confirm"))
+                .and_then(|c| c.as_bool())
+                .unwrap_or(false);
+            if !confirmed {
+                let reason = if tools::is_destructive(method) { "discards existing work" } else { "may overwrite an existing file" };
+                return Err(MspMcpError::ConfirmationRequired(format!(
+                    "'{}' {}; repeat the request with \"confirm\": true", method, reason)));
+            }
+        }
+
+        // Refuse work beyond the session's budget before touching Paint. A
+        // request refused above hasn't used any of it
+        self.rate_limiter.check(method, tools::sends_input(method))?;
+        let events_before = self.backend.input_events_sent();
+
+        // Follow the tracked window by HWND/PID and pick up title changes
+        if let Err(e) = self.refresh_window_tracking() {
+            warn!("Failed to refresh Paint window tracking: {}", e);
+        }
+
+        // In dedicated desktop mode, switch to Paint's desktop for this request only
+        #[cfg(feature = "windows-automation")]
+        let dedicated_desktop = self.dedicated_desktop.lock().map(|d| *d).unwrap_or(false);
+        #[cfg(feature = "windows-automation")]
+        let _desktop_guard = if dedicated_desktop {
+            match virtual_desktop::DesktopSwitchGuard::enter() {
+                Ok(guard) => Some(guard),
+                Err(e) => {
+                    warn!("Failed to switch to the automation desktop: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // Whether this request may bring Paint to the front applies from here on,
+        // including to the canvas lookups for coordinate conversion
+        let activation = crate::session::take_activation(&mut params)?;
+        if activation.is_some() && !tools::sends_input(method) {
+            return Err(MspMcpError::InvalidParameters(format!("'{}' sends no input, so it takes no activation", method)));
+        }
+        let _activation = activation.map(crate::session::install_activation);
+
+        // Handlers only see canvas pixels; other coordinate spaces are resolved here
+        let params = self.resolve_coordinate_space(method, params)?;
+        let params = self.apply_transforms(method, params)?;
+        let params = self.snap_to_grid(method, params);
 
         // Route request to appropriate async handler in `core` module
         // Pass the cloned state to the handler
-        let result: std::result::Result<serde_json::Value, MspMcpError> = match method {
+        let result = match method {
             "initialize" => {
                 core::handle_initialize(self.clone(), params).await
             }
@@ -128,9 +629,25 @@ impl ServerHandler for PaintServerState {
             "disconnect" => {
                 core::handle_disconnect(self.clone(), params).await
             }
+            "exit" => {
+                // The stdio loop in main.rs ends the process; nothing to do here
+                Ok(protocol::success_response())
+            }
             "get_version" => {
                 core::handle_get_version(self.clone(), params).await
             }
+            "get_window_info" => {
+                core::handle_get_window_info(self.clone(), params).await
+            }
+            "ensure_window_on_monitor" => {
+                core::handle_ensure_window_on_monitor(self.clone(), params).await
+            }
+            "set_dedicated_desktop" => {
+                core::handle_set_dedicated_desktop(self.clone(), params).await
+            }
+            "logging/setLevel" => {
+                core::handle_set_log_level(self.clone(), params).await
+            }
             "activate_window" => {
                 core::handle_activate_window(self.clone(), params).await
             }
@@ -158,17 +675,144 @@ impl ServerHandler for PaintServerState {
             "set_fill" => {
                 core::handle_set_fill(self.clone(), params).await
             }
+            "set_tool_state" => {
+                core::handle_set_tool_state(self.clone(), params).await
+            }
+            "define_palette" => {
+                core::handle_define_palette(self.clone(), params).await
+            }
+            "use_palette_color" => {
+                core::handle_use_palette_color(self.clone(), params).await
+            }
+            "set_snap" => {
+                core::handle_set_snap(self.clone(), params).await
+            }
+            "push_transform" => {
+                core::handle_push_transform(self.clone(), params).await
+            }
+            "pop_transform" => {
+                core::handle_pop_transform(self.clone(), params).await
+            }
+            "pen_move_to" => {
+                core::handle_pen_move_to(self.clone(), params).await
+            }
+            "pen_line_to" => {
+                core::handle_pen_line_to(self.clone(), params).await
+            }
+            "pen_close" => {
+                core::handle_pen_close(self.clone(), params).await
+            }
+            "pen_stroke" => {
+                core::handle_pen_stroke(self.clone(), params).await
+            }
             "select_tool" => {
                 core::handle_select_tool(self.clone(), params).await
             }
             // Add other method handlers here, calling functions in core.rs
             _ => {
-                Err(MspMcpError::OperationNotSupported(format!("Method '{}' not implemented", method)))
+                // Everything else registered in the protocol method table,
+                // then the tools registered by an embedding program
+                match protocol::get_method_handler(method) {
+                    Some(handler) => handler(self.clone(), params).await,
+                    None => match crate::plugins::get(method) {
+                        Some(tool) => crate::plugins::handle(self.clone(), tool, params).await,
+                        None => Err(MspMcpError::MethodNotFound(method.to_string())),
+                    },
+                }
             }
         };
 
+        // Macro and animation steps already counted their own events
+        if !tools::reenters_request_path(method) {
+            let events_sent = self.backend.input_events_sent().saturating_sub(events_before);
+            self.rate_limiter.record_input_events(method, events_sent);
+        }
+
+        if crate::palette::forgets_slots(method) {
+            self.forget_color_slots();
+        }
+
+        // Count the request towards the checkpoints it may have to be undone to
+        if result.is_ok() && method != "revert_to_checkpoint" {
+            if let Ok(mut history) = self.history.lock() {
+                history.record(method, tools::mutates_canvas(method));
+            }
+        }
+
+        // Let subscribers know the canvas changed so previews can refresh
+        if result.is_ok() && tools::mutates_canvas(method) {
+            self.notify_resource_updated(crate::resources::CANVAS_URI);
+        }
+
+        result
+    }
+
+    /// Sends `notifications/resources/updated` if the client subscribed to `uri`.
+    pub fn notify_resource_updated(&self, uri: &str) {
+        let subscribed = self.subscriptions.lock().map(|s| s.contains(uri)).unwrap_or(false);
+        if subscribed {
+            crate::transport::send_notification("notifications/resources/updated", serde_json::json!({ "uri": uri }));
+        }
+    }
+}
+
+// Implement the server handler trait from mcp_rust_sdk
+#[async_trait::async_trait]
+impl ServerHandler for PaintServerState {
+
+    // Required method: initialize
+    async fn initialize(&self, _implementation: Implementation, _client_capabilities: ClientCapabilities) -> std::result::Result<ServerCapabilities, SdkError> {
+        info!("Server received initialize request. Finding/Launching Paint...");
+
+        // --- Log process tree BEFORE attempting launch ---
+        log_process_tree("Before Paint Find/Launch");
+        // -----------------------------------------------
+
+        // --- Start: Logic moved from handle_connect ---
+        match self.backend.find_or_launch_paint() {
+            Ok(hwnd) => {
+                 // Store the HWND (and its owning PID) in the shared state
+                self.set_paint_window(hwnd)
+                    .map_err(|e| SdkError::protocol(ErrorCode::InternalError, e.to_string()))?;
+                info!("Stored Paint HWND: {}", hwnd);
+
+                // --- Log process tree AFTER successful find/launch ---
+                log_process_tree("After Paint Find/Launch");
+                // -----------------------------------------------------
+            }
+            Err(e) => {
+                // Log process tree on failure too
+                log_process_tree("After Paint Find/Launch Failure");
+
+                // If we can't get the HWND during init, it's a fatal error for this server
+                let error_msg = format!("Failed to find or launch Paint during initialization: {}", e);
+                error!("{}", error_msg);
+                // Convert our error to an SdkError for the initialize response
+                return Err(SdkError::protocol(ErrorCode::InternalError, error_msg));
+            }
+        }
+        // --- End: Logic moved from handle_connect ---
+        
+        // Advertise what we support so hosts can discover our features
+        info!("Paint found/launched. Initialization successful.");
+        let capabilities = serde_json::from_value(crate::protocol::server_capabilities())
+            .map_err(|e| SdkError::protocol(ErrorCode::InternalError, format!("Failed to build server capabilities: {}", e)))?;
+        Ok(capabilities)
+    }
+
+    // Required method: shutdown
+    async fn shutdown(&self) -> std::result::Result<(), SdkError> {
+        info!("Server received shutdown request.");
+        core::handle_shutdown(self.clone(), None).await
+            .map(|_| ())
+            .map_err(|e| SdkError::protocol(ErrorCode::InternalError, e.to_string()))
+    }
+
+    // Required method: handle_method
+    async fn handle_method(&self, method: &str, params: Option<serde_json::Value>) -> std::result::Result<serde_json::Value, SdkError> {
         // Convert our Result<Value, MspMcpError> to Result<Value, SdkError>
-        match result {
+        let request_id = protocol::next_request_id();
+        match self.handle_request_with_id(None, &request_id, method, params).await {
             Ok(value) => {
                 // Just return the value since the SDK should handle adding jsonrpc and id
                 Ok(value)
@@ -176,13 +820,20 @@ impl ServerHandler for PaintServerState {
             Err(msp_error) => {
                 let code = msp_error.code(); // Keep our internal code for logging 
                 let message = msp_error.to_string();
-                error!("Error processing method '{}': Code {}, Message: {}", method, code, message);
+                error!("Error processing method '{}' (request {}): Code {}, Message: {}", method, request_id, code, message);
                 
                 // Convert to a SdkError which the SDK will format as a proper JSON-RPC error
+                let sdk_code = match msp_error.json_rpc_code() {
+                    protocol::METHOD_NOT_FOUND => ErrorCode::MethodNotFound,
+                    protocol::INVALID_PARAMS => ErrorCode::InvalidParams,
+                    _ => ErrorCode::InternalError,
+                };
+                let mut data = protocol::error_data(method, &msp_error);
+                protocol::add_request_id(&mut data, &request_id);
                 Err(SdkError::Protocol {
-                    code: ErrorCode::InternalError,
+                    code: sdk_code,
                     message: message,
-                    data: None,
+                    data: Some(data),
                 })
             }
         }
@@ -191,7 +842,7 @@ impl ServerHandler for PaintServerState {
 
 // Main entry point function
 pub fn run_server() -> Result<()> {
-    // Remove the env_logger initialization since we're using simplelog now
+    // Logging is set up by the binary (tracing-subscriber in main.rs)
     // env_logger::Builder::from_default_env()
     //    .filter_level(LevelFilter::Info)
     //    .try_init().map_err(|e| MspMcpError::General(format!("Failed to init logger: {}", e)))?;
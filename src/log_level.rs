@@ -0,0 +1,121 @@
+// Runtime control of the server's own log levels.
+//
+// Restarting the server to get debug logs loses the Paint session, so the
+// terminal and file loggers set up in main.rs register a reload hook here,
+// and `set_log_level` (or MCP `logging/setLevel`) changes their levels in
+// place.
+
+use crate::error::{MspMcpError, Result};
+use std::sync::Mutex;
+use tracing::level_filters::LevelFilter;
+
+type Reload = Box<dyn Fn(LevelFilter) -> std::result::Result<(), String> + Send + Sync>;
+
+struct Logger {
+    name: &'static str, // "terminal" or "file"
+    level: LevelFilter,
+    default: LevelFilter, // Level it was registered with
+    reload: Reload,
+}
+
+static LOGGERS: Mutex<Vec<Logger>> = Mutex::new(Vec::new());
+
+/// Parses a level name. Accepts `off`, the `tracing` levels and the MCP
+/// levels, which map onto the closest `tracing` level.
+pub fn parse_level(name: &str) -> Option<LevelFilter> {
+    match name.to_ascii_lowercase().as_str() {
+        "off" => Some(LevelFilter::OFF),
+        "trace" => Some(LevelFilter::TRACE),
+        "debug" => Some(LevelFilter::DEBUG),
+        "info" | "notice" => Some(LevelFilter::INFO),
+        "warn" | "warning" => Some(LevelFilter::WARN),
+        "error" | "critical" | "alert" | "emergency" => Some(LevelFilter::ERROR),
+        _ => None,
+    }
+}
+
+/// Makes a logger's level adjustable; `reload` applies a new level to it.
+pub fn register<F>(name: &'static str, level: LevelFilter, reload: F)
+where
+    F: Fn(LevelFilter) -> std::result::Result<(), String> + Send + Sync + 'static,
+{
+    if let Ok(mut loggers) = LOGGERS.lock() {
+        loggers.retain(|l| l.name != name);
+        loggers.push(Logger { name, level, default: level, reload: Box::new(reload) });
+    }
+}
+
+/// Sets the level of the named logger, or of every logger with None.
+pub fn set_level(logger: Option<&str>, level: LevelFilter) -> Result<()> {
+    let mut loggers = LOGGERS.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock log levels".to_string()))?;
+    if let Some(name) = logger {
+        if !loggers.iter().any(|l| l.name == name) {
+            let known: Vec<&str> = loggers.iter().map(|l| l.name).collect();
+            return Err(MspMcpError::InvalidParameters(format!(
+                "Unknown logger '{}'. Expected one of: {}", name, known.join(", "))));
+        }
+    }
+    for l in loggers.iter_mut().filter(|l| logger.is_none_or(|name| l.name == name)) {
+        (l.reload)(level).map_err(|e| MspMcpError::General(format!("Failed to set the {} log level: {}", l.name, e)))?;
+        l.level = level;
+    }
+    Ok(())
+}
+
+/// Puts every logger back to the level it was registered with.
+pub fn reset_levels() -> Result<()> {
+    let mut loggers = LOGGERS.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock log levels".to_string()))?;
+    for l in loggers.iter_mut() {
+        (l.reload)(l.default).map_err(|e| MspMcpError::General(format!("Failed to reset the {} log level: {}", l.name, e)))?;
+        l.level = l.default;
+    }
+    Ok(())
+}
+
+/// Current level of each registered logger, by name.
+pub fn levels() -> Vec<(&'static str, String)> {
+    LOGGERS.lock()
+        .map(|loggers| loggers.iter().map(|l| (l.name, l.level.to_string().to_lowercase())).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("DEBUG"), Some(LevelFilter::DEBUG));
+        assert_eq!(parse_level("warning"), Some(LevelFilter::WARN));
+        assert_eq!(parse_level("emergency"), Some(LevelFilter::ERROR));
+        assert_eq!(parse_level("off"), Some(LevelFilter::OFF));
+        assert_eq!(parse_level("verbose"), None);
+    }
+
+    #[test]
+    fn test_set_level_reloads_registered_loggers() {
+        let applied = Arc::new(Mutex::new(Vec::new()));
+        for name in ["terminal", "file"] {
+            let applied = applied.clone();
+            register(name, LevelFilter::INFO, move |level| {
+                applied.lock().unwrap().push((name, level));
+                Ok(())
+            });
+        }
+
+        set_level(Some("file"), LevelFilter::TRACE).unwrap();
+        set_level(None, LevelFilter::WARN).unwrap();
+        assert!(matches!(set_level(Some("syslog"), LevelFilter::WARN), Err(MspMcpError::InvalidParameters(_))));
+
+        assert_eq!(*applied.lock().unwrap(), [
+            ("file", LevelFilter::TRACE), ("terminal", LevelFilter::WARN), ("file", LevelFilter::WARN),
+        ]);
+        assert_eq!(levels(), [("terminal", "warn".to_string()), ("file", "warn".to_string())]);
+
+        reset_levels().unwrap();
+        assert_eq!(levels(), [("terminal", "info".to_string()), ("file", "info".to_string())]);
+    }
+}
@@ -0,0 +1,251 @@
+// Macros: rhai scripts that build a batch of drawing requests.
+//
+// Procedural art (spirals, grids, fractals) takes thousands of requests when
+// every stroke is its own round trip. `run_macro` runs a short script with
+// loops, math and conditionals instead. Each drawing function the script
+// calls (`line`, `shape`, `color`, ..., or `request(method, params)` for any
+// other method) adds one request to a batch, and once the script finishes
+// the batch runs request by request through the normal request path, so
+// coordinate spaces, transforms, snapping, rate limits and the audit log
+// apply to every step. Scripts don't see the results of their requests;
+// the canvas size is there as `canvas_width` and `canvas_height`, and the
+// macro's `args` as `args`. `register_macro` keeps a script under a name.
+
+use crate::error::{MspMcpError, Result};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, ImmutableString, Map, Position, Scope, INT};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+// Most requests one macro run may add to its batch
+pub const MAX_MACRO_REQUESTS: usize = 10_000;
+
+// Most macros register_macro keeps
+pub const MAX_MACROS: usize = 100;
+
+// Script operations allowed per run, so a runaway loop fails instead of hanging
+const MAX_OPERATIONS: u64 = 5_000_000;
+
+// Sizes a script's values may grow to, so doubling a string or array in a
+// loop fails long before it runs the server out of memory
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_ARRAY_SIZE: usize = 100_000;
+const MAX_MAP_SIZE: usize = 10_000;
+const MAX_CALL_LEVELS: usize = 64;
+
+// Methods a script (or an animation frame) can't call: macros don't start
+// other macros or animations, directly or through tools/call, and the
+// session lifecycle belongs to the client
+pub const FORBIDDEN_METHODS: &[&str] = &[
+    "run_macro", "register_macro", "draw_animation", "tools/call",
+    "initialize", "connect", "disconnect", "shutdown", "exit", "quit_paint", "self_test",
+];
+
+/// What a script can see besides its own code.
+pub struct MacroInput {
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+    pub args: Value,
+}
+
+/// Macros kept by `register_macro`, by name.
+#[derive(Debug, Default)]
+pub struct MacroStore {
+    macros: BTreeMap<String, String>,
+}
+
+impl MacroStore {
+    /// Checks and keeps `source` under `name`. Returns true if it replaced
+    /// a macro of the same name.
+    pub fn register(&mut self, name: &str, source: &str) -> Result<bool> {
+        let well_formed = !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !well_formed {
+            return Err(MspMcpError::InvalidParameters(
+                format!("Macro name '{}' must be lowercase letters, digits and underscores", name)));
+        }
+        engine(Arc::default()).compile(source)
+            .map_err(|e| MspMcpError::InvalidParameters(format!("Macro '{}' doesn't compile: {}", name, e)))?;
+        if !self.macros.contains_key(name) && self.macros.len() >= MAX_MACROS {
+            return Err(MspMcpError::InvalidParameters(format!("At most {} macros can be registered", MAX_MACROS)));
+        }
+        Ok(self.macros.insert(name.to_string(), source.to_string()).is_some())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.macros.get(name).map(String::as_str)
+    }
+}
+
+/// Runs `source` and returns the requests it asked for, in order.
+pub fn plan(source: &str, input: &MacroInput) -> Result<Vec<(String, Value)>> {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let engine = engine(requests.clone());
+    let mut scope = Scope::new();
+    scope.push_constant("canvas_width", input.canvas_width as INT);
+    scope.push_constant("canvas_height", input.canvas_height as INT);
+    let args: Dynamic = rhai::serde::to_dynamic(&input.args)
+        .map_err(|e| MspMcpError::InvalidParameters(format!("Macro args: {}", e)))?;
+    scope.push_constant_dynamic("args", args);
+    engine.run_with_scope(&mut scope, source)
+        .map_err(|e| MspMcpError::InvalidParameters(format!("Macro failed: {}", e)))?;
+    let requests = std::mem::take(&mut *requests.lock()
+        .map_err(|_| MspMcpError::General("Failed to lock the macro batch".to_string()))?);
+    Ok(requests)
+}
+
+type Requests = Arc<Mutex<Vec<(String, Value)>>>;
+type ScriptResult<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+// An engine whose drawing functions add to `requests`
+fn engine(requests: Requests) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_string_size(MAX_STRING_SIZE);
+    engine.set_max_array_size(MAX_ARRAY_SIZE);
+    engine.set_max_map_size(MAX_MAP_SIZE);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+
+    let batch = requests.clone();
+    engine.register_fn("request", move |method: &str, params: Map| -> ScriptResult<()> {
+        if FORBIDDEN_METHODS.contains(&method) {
+            return Err(script_error(format!("Macros can't call '{}'", method)));
+        }
+        let params: Value = rhai::serde::from_dynamic(&params.into())?;
+        add(&batch, method, params)
+    });
+    let batch = requests.clone();
+    engine.register_fn("pixel", move |x: Dynamic, y: Dynamic| -> ScriptResult<()> {
+        add(&batch, "draw_pixel", json!({"x": number(x)?, "y": number(y)?}))
+    });
+    let batch = requests.clone();
+    engine.register_fn("line", move |start_x: Dynamic, start_y: Dynamic, end_x: Dynamic, end_y: Dynamic| -> ScriptResult<()> {
+        add(&batch, "draw_line", json!({
+            "start_x": number(start_x)?, "start_y": number(start_y)?, "end_x": number(end_x)?, "end_y": number(end_y)?,
+        }))
+    });
+    let batch = requests.clone();
+    engine.register_fn("shape", move |shape_type: ImmutableString, start_x: Dynamic, start_y: Dynamic, end_x: Dynamic, end_y: Dynamic| -> ScriptResult<()> {
+        add(&batch, "draw_shape", json!({
+            "shape_type": shape_type.as_str(),
+            "start_x": number(start_x)?, "start_y": number(start_y)?, "end_x": number(end_x)?, "end_y": number(end_y)?,
+        }))
+    });
+    let batch = requests.clone();
+    engine.register_fn("polyline", move |points: Array| -> ScriptResult<()> {
+        let points = points.into_iter().map(point).collect::<ScriptResult<Vec<Value>>>()?;
+        add(&batch, "draw_polyline", json!({"points": points}))
+    });
+    let batch = requests.clone();
+    engine.register_fn("text", move |x: Dynamic, y: Dynamic, text: ImmutableString| -> ScriptResult<()> {
+        add(&batch, "add_text", json!({"x": number(x)?, "y": number(y)?, "text": text.as_str()}))
+    });
+    let batch = requests.clone();
+    engine.register_fn("color", move |color: ImmutableString| -> ScriptResult<()> {
+        add(&batch, "set_color", json!({"color": color.as_str()}))
+    });
+    let batch = requests.clone();
+    engine.register_fn("thickness", move |px: INT| -> ScriptResult<()> {
+        add(&batch, "set_tool_state", json!({"thickness_px": px}))
+    });
+    let batch = requests.clone();
+    engine.register_fn("tool", move |tool: ImmutableString| -> ScriptResult<()> {
+        add(&batch, "select_tool", json!({"tool": tool.as_str()}))
+    });
+    let batch = requests;
+    engine.register_fn("fill", move |fill_type: ImmutableString| -> ScriptResult<()> {
+        add(&batch, "set_fill", json!({"fill_type": fill_type.as_str()}))
+    });
+    engine
+}
+
+fn add(requests: &Requests, method: &str, params: Value) -> ScriptResult<()> {
+    let mut requests = requests.lock().map_err(|_| script_error("Failed to lock the macro batch".to_string()))?;
+    if requests.len() >= MAX_MACRO_REQUESTS {
+        return Err(script_error(format!("A macro can make at most {} requests", MAX_MACRO_REQUESTS)));
+    }
+    requests.push((method.to_string(), params));
+    Ok(())
+}
+
+// A position from an integer or a float, rounded to the nearest pixel
+fn number(value: Dynamic) -> ScriptResult<i64> {
+    if let Some(n) = value.clone().try_cast::<INT>() {
+        return Ok(n);
+    }
+    match value.clone().try_cast::<rhai::FLOAT>() {
+        Some(n) if n.is_finite() => Ok(n.round() as i64),
+        _ => Err(script_error(format!("Expected a number, got {}", value))),
+    }
+}
+
+// A polyline point, written [x, y] or #{x: .., y: ..}
+fn point(value: Dynamic) -> ScriptResult<Value> {
+    let (x, y) = if let Some(pair) = value.clone().try_cast::<Array>().filter(|pair| pair.len() == 2) {
+        (pair[0].clone(), pair[1].clone())
+    } else if let Some(map) = value.clone().try_cast::<Map>() {
+        match (map.get("x"), map.get("y")) {
+            (Some(x), Some(y)) => (x.clone(), y.clone()),
+            _ => return Err(script_error(format!("A point needs x and y, got {}", value))),
+        }
+    } else {
+        return Err(script_error(format!("A point is [x, y] or #{{x: .., y: ..}}, got {}", value)));
+    };
+    Ok(json!({"x": number(x)?, "y": number(y)?}))
+}
+
+fn script_error(message: String) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorRuntime(message.into(), Position::NONE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input() -> MacroInput {
+        MacroInput { canvas_width: 800, canvas_height: 600, args: json!({"count": 3}) }
+    }
+
+    #[test]
+    fn test_scripts_build_a_batch() {
+        let source = r##"
+            color("#FF0000");
+            for i in 0..args.count {
+                line(0, i * 10, canvas_width, i * 10);
+            }
+            let r = 50.0;
+            polyline([[0, 0], #{x: r * 0.5, y: r}]);
+            request("set_snap", #{grid_size: 5});
+        "##;
+        let requests = plan(source, &input()).unwrap();
+        assert_eq!(requests.len(), 6);
+        assert_eq!(requests[0], ("set_color".to_string(), json!({"color": "#FF0000"})));
+        assert_eq!(requests[3], ("draw_line".to_string(), json!({"start_x": 0, "start_y": 20, "end_x": 800, "end_y": 20})));
+        assert_eq!(requests[4].1, json!({"points": [{"x": 0, "y": 0}, {"x": 25, "y": 50}]}));
+        assert_eq!(requests[5], ("set_snap".to_string(), json!({"grid_size": 5})));
+    }
+
+    #[test]
+    fn test_bad_scripts_are_refused() {
+        assert!(plan("line(0, 0, \"far\", 1);", &input()).is_err());
+        assert!(plan("request(\"run_macro\", #{});", &input()).is_err());
+        for method in ["initialize", "shutdown", "exit", "tools/call"] {
+            assert!(plan(&format!("request(\"{}\", #{{}});", method), &input()).is_err(), "{}", method);
+        }
+        assert!(plan("loop { }", &input()).is_err());
+        assert!(plan("loop { pixel(1, 1); }", &input()).is_err());
+
+        // Values that keep doubling hit the size limits, not the memory
+        let error = plan("let s = \"x\"; loop { s += s; }", &input()).unwrap_err();
+        assert!(error.to_string().contains("too large"), "{}", error);
+        assert!(plan("let a = [0]; loop { a += a; }", &input()).is_err());
+        assert!(plan("fn down(n) { down(n + 1) } down(0);", &input()).is_err());
+
+        let mut store = MacroStore::default();
+        assert!(!store.register("grid", "line(0, 0, 1, 1);").unwrap());
+        assert!(store.register("grid", "pixel(0, 0);").unwrap());
+        assert_eq!(store.get("grid"), Some("pixel(0, 0);"));
+        assert!(store.register("grid", "line(0, 0").is_err());
+        assert!(store.register("Grid!", "pixel(0, 0);").is_err());
+    }
+}
@@ -1,12 +1,29 @@
 use mcp_server_microsoft_paint::PaintServerState;
-use mcp_rust_sdk::server::ServerHandler;
+use mcp_server_microsoft_paint::protocol;
+use mcp_server_microsoft_paint::jsonrpc::{extract_method_and_params, parse_json_rpc_request};
+use mcp_server_microsoft_paint::config::ServerConfig;
+use mcp_server_microsoft_paint::config_watch;
+use mcp_server_microsoft_paint::transport;
+use mcp_server_microsoft_paint::tools;
+use mcp_server_microsoft_paint::client_log::ClientLogLayer;
+use mcp_server_microsoft_paint::log_level;
+use mcp_server_microsoft_paint::redact::{self, Redacted, RedactedText};
+use mcp_server_microsoft_paint::heartbeat;
+use mcp_server_microsoft_paint::panics;
+use mcp_server_microsoft_paint::session;
+use mcp_server_microsoft_paint::simulate::SimulatedBackend;
+use mcp_server_microsoft_paint::self_test::{self, SelfTestOptions};
+use mcp_server_microsoft_paint::repl;
 use mcp_rust_sdk::transport::stdio::StdioTransport;
 use std::process;
-use log::{info, error, debug};
-use simplelog::{CombinedLogger, Config, ConfigBuilder, TermLogger, WriteLogger, TerminalMode, ColorChoice, LevelFilter};
+use tracing::{info, error, debug};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::reload;
 use std::fs::File;
-use std::sync::Once;
+use std::sync::{Arc, Once};
 use std::path::PathBuf;
+use std::time::Duration;
 use std::env;
 use std::io;
 use serde_json;
@@ -18,6 +35,8 @@ static LOGGER_INIT: Once = Once::new();
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize the logger
     init_logger();
+    // Log panics; a panicking request gets an error response instead of ending the process
+    panics::install_hook();
     
     info!("Starting MCP Server for Windows 11 Paint...");
     
@@ -25,88 +44,194 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let version = env!("CARGO_PKG_VERSION");
     info!("MCP Server version: {}", version);
     
+    // Pick the stdio framing (newline-delimited JSON or Content-Length headers)
+    let framing = parse_framing_arg(env::args().skip(1))?;
+    transport::set_framing(framing);
+    info!("Using {:?} framing on stdio", framing);
+    
     // Run the JSON-RPC server
-    run_server_async().await?;
+    let exit_code = run_server_async().await?;
     
-    info!("MCP Server shutting down");
-    Ok(())
+    info!("MCP Server shutting down with exit code {}", exit_code);
+    process::exit(exit_code);
 }
 
-// The main run loop for the server
-async fn run_server_async() -> Result<(), Box<dyn std::error::Error>> {
+// The main run loop for the server. Returns the process exit code: 0 when
+// the client ended the session with shutdown + exit (or closed stdin), 1
+// when it sent exit without shutdown first.
+async fn run_server_async() -> Result<i32, Box<dyn std::error::Error>> {
     info!("MCP Server starting run loop...");
 
-    // Create the Paint server state
-    let paint_server = PaintServerState::new();
+    // Print the API schema and exit instead of serving requests
+    if has_flag("--emit-schema") {
+        println!("{}", serde_json::to_string_pretty(&tools::api_schema())?);
+        return Ok(0);
+    }
+
+    // Create the Paint server state, with the config file from --config if given
+    let config_arg = parse_path_arg(env::args().skip(1), "--config")?;
+    let mut config = match &config_arg {
+        Some(path) => ServerConfig::load(path)?,
+        None => ServerConfig::load_default(),
+    };
+    let config_path = config_arg.or_else(ServerConfig::default_path);
+    let loaded_config = config.clone();
+    // Screenshot after every automation step, for diagnosing misplaced drawing
+    if has_flag("--screenshot-trail") {
+        config.screenshot_trail.enabled = true;
+    }
+    redact::set_max_field_len(config.log_max_field_len);
+    session::set_desktop_wait_ms(config.input_desktop_wait_ms);
+    if let Some(level) = config.log_level.as_deref().and_then(log_level::parse_level) {
+        log_level::set_level(None, level)?;
+    }
+    let heartbeat_interval = config.heartbeat_interval_ms;
+    // Builds without windows-automation can only draw on the in-memory canvas
+    let simulate = has_flag("--simulate") || !cfg!(feature = "windows-automation");
+    let paint_server = if simulate {
+        info!("Simulation mode: drawing on an in-memory canvas instead of Paint");
+        PaintServerState::with_backend(config, Arc::new(SimulatedBackend::new()))
+    } else {
+        // Fail now if input can't reach Paint in this session, with what to do about it
+        #[cfg(feature = "windows-automation")]
+        session::check(config.input_path)?;
+        PaintServerState::with_config(config)
+    };
+
+    // Record every synthesized input from the start if asked to
+    if let Some(path) = parse_path_arg(env::args().skip(1), "--record-input")? {
+        mcp_server_microsoft_paint::input_record::start_recording(&path)?;
+    }
+
+    // Draw the golden scenes and exit instead of serving requests
+    if has_flag("--self-test") {
+        let options = SelfTestOptions {
+            golden_dir: parse_path_arg(env::args().skip(1), "--golden-dir")?
+                .unwrap_or_else(|| PathBuf::from(self_test::DEFAULT_GOLDEN_DIR)),
+            backend_name: if simulate { "simulate" } else { "paint" }.to_string(),
+            update_golden: has_flag("--update-golden"),
+        };
+        return Ok(self_test::run(&paint_server, &options).await);
+    }
+
+    // Run a script of commands or requests and exit instead of serving requests
+    if let Some(path) = parse_path_arg(env::args().skip(1), "--script")? {
+        return Ok(repl::run_script(&paint_server, &path, has_flag("--exit-on-error")).await);
+    }
+
+    // Take commands from a prompt instead of serving requests
+    if has_flag("--repl") {
+        return Ok(repl::run(&paint_server).await);
+    }
+
+    // Run one request from the command line and print its response instead of serving requests
+    if let Some((method, params)) = parse_send_arg(env::args().skip(1))? {
+        return Ok(send_request(&paint_server, &method, params).await);
+    }
 
-    let mut buffer = String::new();
+    // Keep hosts with idle timeouts from killing the server during long requests
+    if heartbeat_interval > 0 {
+        heartbeat::spawn(paint_server.activity.clone(), Duration::from_millis(heartbeat_interval));
+    }
+
+    // Apply edits to the config file without a restart
+    if let Some(path) = config_path {
+        config_watch::spawn(paint_server.clone(), path, loaded_config);
+    }
+
+    let stdin = io::stdin();
+    let mut reader = stdin.lock();
     
     loop {
-        // Reset the buffer for the next request
-        buffer.clear();
-        
-        // Read a line from stdin
-        match io::stdin().read_line(&mut buffer) {
-            Ok(0) => {
+        // Read the next message from stdin using the configured framing
+        match transport::read_message(&mut reader) {
+            Ok(None) => {
                 // End of input (Ctrl+D or stream closed)
                 info!("End of input - server shutting down");
                 break;
             }
-            Ok(_) => {
-                // Process the received JSON-RPC request
-                if let Some(parsed_request) = parse_json_rpc_request(&buffer) {
-                    // If parsing successful, handle the request
-                    info!("Received request: {}", parsed_request.trim());
-                    
-                    // Extract method and params
-                    match extract_method_and_params(&parsed_request) {
-                        Ok((method, params, id)) => {
-                            // Handle the method call
-                            debug!("Handling method: {}, params: {:?}", method, params);
-                            
-                            let result = paint_server.clone().handle_method(&method, params).await;
-                            
-                            // Send the result back as a JSON-RPC response
-                            match result {
-                                Ok(response) => {
-                                    // Make sure the response has the correct ID
-                                    let mut response_obj = response.as_object().unwrap_or(&serde_json::Map::new()).clone();
-                                    response_obj.insert("id".to_string(), id);
-                                    
-                                    if !response_obj.contains_key("jsonrpc") {
-                                        response_obj.insert("jsonrpc".to_string(), serde_json::Value::String("2.0".to_string()));
-                                    }
-                                    
-                                    let response_json = serde_json::to_string(&response_obj)?;
-                                    println!("{}", response_json);
-                                }
-                                Err(e) => {
-                                    let error_response = serde_json::json!({
-                                        "jsonrpc": "2.0",
-                                        "id": id,
-                                        "error": {
-                                            "code": -32603, // Internal error
-                                            "message": e.to_string()
-                                        }
-                                    });
-                                    println!("{}", serde_json::to_string(&error_response)?);
-                                }
-                            }
+            Ok(Some(buffer)) => {
+                info!("Received request: {}", RedactedText(buffer.trim()));
+                
+                // Anything that isn't valid JSON (or is too deeply nested) is a parse error
+                let request: serde_json::Value = match parse_json_rpc_request(&buffer) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        error!("Failed to parse JSON-RPC request: {}", e);
+                        transport::write_message(&protocol::json_rpc_error(
+                            serde_json::Value::Null, protocol::PARSE_ERROR, format!("Parse error: {}", e), None))?;
+                        continue;
+                    }
+                };
+                
+                // Extract method and params
+                match extract_method_and_params(&request) {
+                    Ok((method, _params, id)) if method == "exit" => {
+                        // Exit may arrive as a request or a notification
+                        let exit_code = if paint_server.is_shutdown_requested() { 0 } else { 1 };
+                        info!("Received exit (shutdown requested: {})", exit_code == 0);
+                        if let Some(id) = id {
+                            let mut response = protocol::success_response();
+                            response["id"] = id;
+                            transport::write_message(&response)?;
                         }
-                        Err(e) => {
-                            let error_response = serde_json::json!({
-                                "jsonrpc": "2.0",
-                                "id": null,
-                                "error": {
-                                    "code": -32600, // Invalid request
-                                    "message": e
+                        return Ok(exit_code);
+                    }
+                    Ok((method, params, None)) => {
+                        // No id means a notification: handle it but never respond,
+                        // not even with an error
+                        debug!("Handling notification: {}, params: {}", method, Redacted(params.as_ref()));
+                        let _busy = paint_server.activity.begin(&method);
+                        
+                        let request_id = protocol::next_request_id();
+                        if let Err(e) = paint_server.handle_request_with_id(None, &request_id, &method, params).await {
+                            debug!("Notification '{}' not handled: {}", method, e);
+                        }
+                    }
+                    Ok((method, params, Some(id))) => {
+                        // Handle the method call
+                        debug!("Handling method: {}, params: {}", method, Redacted(params.as_ref()));
+                        // Until the response is written; ping reports the others
+                        let _busy = (method != "ping").then(|| paint_server.activity.begin(&method));
+                        
+                        let request_id = protocol::next_request_id();
+                        let result = paint_server.handle_request_with_id(Some(&id), &request_id, &method, params).await;
+                        
+                        // Send the result back as a JSON-RPC response
+                        match result {
+                            Ok(response) => {
+                                // Make sure the response has the correct ID
+                                let mut response_obj = response.as_object().unwrap_or(&serde_json::Map::new()).clone();
+                                response_obj.insert("id".to_string(), id);
+                                
+                                if !response_obj.contains_key("jsonrpc") {
+                                    response_obj.insert("jsonrpc".to_string(), serde_json::Value::String("2.0".to_string()));
                                 }
-                            });
-                            println!("{}", serde_json::to_string(&error_response)?);
+                                
+                                transport::write_message(&serde_json::Value::Object(response_obj))?;
+                            }
+                            Err(e) => {
+                                error!("Error processing method '{}' (request {}): Code {}, Message: {}", method, request_id, e.code(), e);
+                                let mut data = protocol::error_data(&method, &e);
+                                protocol::add_request_id(&mut data, &request_id);
+                                transport::write_message(&protocol::json_rpc_error(
+                                    id, e.json_rpc_code(), e.to_string(), Some(data)))?;
+                            }
                         }
                     }
+                    Err((id, message)) => {
+                        error!("Invalid JSON-RPC request: {}", message);
+                        transport::write_message(&protocol::json_rpc_error(
+                            id, protocol::INVALID_REQUEST, format!("Invalid request: {}", message), None))?;
+                    }
                 }
             }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                // Oversized, non-UTF-8 or badly framed message: report it and keep going
+                error!("Rejected malformed message: {}", e);
+                transport::write_message(&protocol::json_rpc_error(
+                    serde_json::Value::Null, protocol::PARSE_ERROR, format!("Parse error: {}", e), None))?;
+            }
             Err(e) => {
                 // Handle read errors
                 error!("Error reading from stdin: {}", e);
@@ -115,109 +240,145 @@ async fn run_server_async() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    Ok(())
+    Ok(0)
 }
 
-// Parse a string as a JSON-RPC request
-fn parse_json_rpc_request(input: &str) -> Option<String> {
-    let trimmed = input.trim();
-    if trimmed.is_empty() {
-        return None;
-    }
-    
-    match serde_json::from_str::<serde_json::Value>(trimmed) {
-        Ok(json) => {
-            // Just verify this is an object - more detailed checking
-            // happens in extract_method_and_params
-            if json.is_object() {
-                Some(trimmed.to_string())
-            } else {
-                error!("Invalid JSON-RPC request: Not an object");
-                None
+// Runs the request given with `--send` and prints its JSON-RPC response (or
+// error response) on stdout. Paint is connected, or launched, only if the
+// request needs the window. Returns the process exit code: 1 on error.
+async fn send_request(paint_server: &PaintServerState, method: &str, params: Option<serde_json::Value>) -> i32 {
+    let id = serde_json::json!(1);
+    let request_id = protocol::next_request_id();
+    let result = match paint_server.handle_request("initialize", None).await {
+        Ok(_) => repl::handle_connecting(paint_server, Some(&id), &request_id, method, params).await,
+        Err(e) => Err(e),
+    };
+
+    let (response, exit_code) = match result {
+        Ok(mut response) => {
+            if let Some(fields) = response.as_object_mut() {
+                fields.insert("id".to_string(), id);
+                fields.entry("jsonrpc").or_insert_with(|| "2.0".into());
             }
+            (response, 0)
         }
         Err(e) => {
-            error!("Failed to parse JSON-RPC request: {}", e);
-            None
+            let mut data = protocol::error_data(method, &e);
+            protocol::add_request_id(&mut data, &request_id);
+            (protocol::json_rpc_error(id, e.json_rpc_code(), e.to_string(), Some(data)), 1)
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&response).unwrap_or_default());
+    exit_code
+}
+
+// A request given with --send: its method and params
+type SendCommand = (String, Option<serde_json::Value>);
+
+// Parse `--send <method> [<params>]`: one request to run from the command
+// line. The params are JSON, or `-` to read them from stdin, and can be left
+// out when the next argument is another flag.
+fn parse_send_arg<I: Iterator<Item = String>>(args: I) -> Result<Option<SendCommand>, Box<dyn std::error::Error>> {
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        if arg != "--send" {
+            continue;
         }
+        let method = args.next().ok_or("--send requires a method")?;
+        let params = match args.next_if(|next| !next.starts_with("--")) {
+            Some(text) => {
+                let text = if text == "-" { io::read_to_string(io::stdin())? } else { text };
+                Some(serde_json::from_str(&text).map_err(|e| format!("--send params are not valid JSON: {}", e))?)
+            }
+            None => None,
+        };
+        return Ok(Some((method, params)));
     }
+    Ok(None)
 }
 
-// Extract method and params from JSON-RPC request
-fn extract_method_and_params(request_str: &str) -> Result<(String, Option<serde_json::Value>, serde_json::Value), String> {
-    // Parse the request
-    let request: serde_json::Value = serde_json::from_str(request_str)
-        .map_err(|e| format!("Invalid JSON: {}", e))?;
-    
-    // Check this is a JSON-RPC 2.0 request object
-    let obj = request.as_object()
-        .ok_or_else(|| "Request must be a JSON object".to_string())?;
-    
-    // Extract the JSON-RPC version (optional check)
-    if let Some(version) = obj.get("jsonrpc") {
-        if version != "2.0" {
-            return Err("Only JSON-RPC 2.0 is supported".to_string());
+// Parse the `--framing <auto|newline|content-length>` flag (also `--framing=...`)
+fn parse_framing_arg<I: Iterator<Item = String>>(mut args: I) -> Result<transport::Framing, Box<dyn std::error::Error>> {
+    let mut framing = transport::Framing::Auto;
+    while let Some(arg) = args.next() {
+        let value = if let Some(value) = arg.strip_prefix("--framing=") {
+            value.to_string()
+        } else if arg == "--framing" {
+            args.next().ok_or("--framing requires a value")?
+        } else {
+            continue;
+        };
+        framing = transport::Framing::parse(&value)
+            .ok_or_else(|| format!("Unknown framing '{}'. Expected auto, newline or content-length", value))?;
+    }
+    Ok(framing)
+}
+
+// True if a bare flag such as `--simulate` was passed
+fn has_flag(flag: &str) -> bool {
+    env::args().skip(1).any(|arg| arg == flag)
+}
+
+// Parse a `<flag> <path>` argument such as `--config` (also `<flag>=...`)
+fn parse_path_arg<I: Iterator<Item = String>>(mut args: I, flag: &str) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    let mut path = None;
+    let prefix = format!("{}=", flag);
+    while let Some(arg) = args.next() {
+        if let Some(value) = arg.strip_prefix(&prefix) {
+            path = Some(PathBuf::from(value));
+        } else if arg == flag {
+            path = Some(PathBuf::from(args.next().ok_or_else(|| format!("{} requires a path", flag))?));
         }
     }
-    
-    // Extract the method
-    let method = obj.get("method")
-        .ok_or_else(|| "Missing 'method' field".to_string())?
-        .as_str()
-        .ok_or_else(|| "'method' must be a string".to_string())?
-        .to_string();
-    
-    // Extract the params (optional)
-    let params = obj.get("params").cloned();
-    
-    // Extract the id (or use default)
-    let id = obj.get("id").unwrap_or(&serde_json::Value::Null).clone();
-    
-    Ok((method, params, id))
+    Ok(path)
 }
 
 // Initialize the logger
 fn init_logger() {
     // Initialize logger exactly once
     LOGGER_INIT.call_once(|| {
-        let log_level = LevelFilter::Debug; // Log debug level and above
+        let log_level = LevelFilter::DEBUG; // Log debug level and above
         let log_file_path = env::temp_dir().join("mcp_server_debug.log");
 
-        if let Ok(log_file) = File::create(&log_file_path) {
-            let config = ConfigBuilder::new()
-                .set_time_format_rfc3339()
-                .build();
-
-            let write_logger = WriteLogger::new(
-                log_level, 
-                config.clone(), 
-                log_file
-            );
-            
-            // Log to stderr instead of stdout to avoid interfering with JSON-RPC
-            let term_logger = TermLogger::new(
-                LevelFilter::Info, 
-                config, 
-                TerminalMode::Stderr, // Use Stderr instead of Mixed mode
-                ColorChoice::Auto
-            );
-
-            if let Err(e) = CombinedLogger::init(vec![term_logger, write_logger]) {
-                eprintln!("Failed to initialize combined logger: {}", e); // Fallback
-            }
-            
-            info!("Logging initialized. Debug logs writing to: {:?}", log_file_path);
+        // Levels are behind reload handles so set_log_level can change them at runtime
+        let (file_filter, file_reload) = reload::Layer::new(log_level);
+        let (term_filter, term_reload) = reload::Layer::new(LevelFilter::INFO);
 
-        } else {
-            eprintln!("Failed to create log file at {:?}, logging to stderr only.", log_file_path);
-            if let Err(e) = TermLogger::init(
-                log_level, 
-                ConfigBuilder::new().build(), 
-                TerminalMode::Stderr, // Use Stderr instead of Mixed mode
-                ColorChoice::Auto
-            ) {
-                 eprintln!("Failed to initialize terminal logger: {}", e); // Fallback
+        // Each line carries the request span (method, id, client) it was logged in
+        let file_layer = match File::create(&log_file_path) {
+            Ok(log_file) => Some(tracing_subscriber::fmt::layer()
+                .with_writer(std::sync::Mutex::new(log_file))
+                .with_ansi(false)
+                .with_filter(file_filter)),
+            Err(_) => {
+                eprintln!("Failed to create log file at {:?}, logging to stderr only.", log_file_path);
+                None
             }
+        };
+        let has_file = file_layer.is_some();
+
+        // Log to stderr instead of stdout to avoid interfering with JSON-RPC
+        let term_layer = tracing_subscriber::fmt::layer()
+            .with_writer(io::stderr)
+            .with_filter(term_filter);
+
+        // Forwards events at the client-selected level as notifications/message
+        let client_layer = ClientLogLayer;
+
+        if let Err(e) = tracing_subscriber::registry()
+            .with(file_layer) // First, so span fields are formatted without colors
+            .with(term_layer)
+            .with(client_layer)
+            .try_init()
+        {
+            eprintln!("Failed to initialize combined logger: {}", e); // Fallback
+            return;
+        }
+
+        log_level::register("terminal", LevelFilter::INFO, move |level| term_reload.reload(level).map_err(|e| e.to_string()));
+        if has_file {
+            log_level::register("file", log_level, move |level| file_reload.reload(level).map_err(|e| e.to_string()));
+            info!("Logging initialized. Debug logs writing to: {:?}", log_file_path);
         }
     });
-} 
\ No newline at end of file
+}
\ No newline at end of file
@@ -0,0 +1,145 @@
+// Input posted to Paint as window messages instead of sent with SendInput.
+//
+// Used while SendInput can't reach Paint (see session.rs). Each input that
+// would have gone to SendInput becomes the message Windows would have
+// delivered for it. Mouse input goes to the window under the cursor, or to
+// the one that got the button down until the button is released, as mouse
+// capture would have it. Key input goes to the focused window of the Paint
+// window activated last. The real cursor never moves and posted keys don't
+// change the keyboard state, so a shortcut that checks whether Ctrl is held
+// may not register; drawing with the mouse is what this path is for.
+
+use crate::windows::normalized_to_screen;
+use std::sync::Mutex;
+use windows_sys::Win32::Foundation::{FALSE, HWND, LPARAM, POINT, WPARAM};
+use windows_sys::Win32::Graphics::Gdi::ScreenToClient;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+    MapVirtualKeyW, INPUT, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE,
+    MAPVK_VK_TO_VSC, MAPVK_VSC_TO_VK, MOUSEEVENTF_ABSOLUTE, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP,
+    MOUSEEVENTF_MOVE, MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP, MOUSEINPUT, VK_CONTROL, VK_SHIFT,
+};
+use windows_sys::Win32::UI::WindowsAndMessaging::{
+    GetGUIThreadInfo, GetWindowThreadProcessId, PostMessageW, WindowFromPoint, GUITHREADINFO, WM_KEYDOWN,
+    WM_KEYUP, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MOUSEMOVE, WM_RBUTTONDOWN, WM_RBUTTONUP,
+};
+
+// wParam flags of mouse messages
+const MK_LBUTTON: WPARAM = 0x0001;
+const MK_RBUTTON: WPARAM = 0x0002;
+const MK_SHIFT: WPARAM = 0x0004;
+const MK_CONTROL: WPARAM = 0x0008;
+
+struct State {
+    cursor: (i32, i32), // Where the cursor would be, in screen coordinates
+    held: WPARAM,       // MK_ flags of the buttons and modifiers held down
+    captured: HWND,     // Window that got the button down; 0 if none is down
+    target: HWND,       // Paint window key input goes to
+}
+
+static STATE: Mutex<State> = Mutex::new(State { cursor: (0, 0), held: 0, captured: 0, target: 0 });
+
+/// Makes `hwnd` (a Paint window) the window key input goes to.
+pub fn set_target(hwnd: HWND) {
+    if let Ok(mut state) = STATE.lock() {
+        state.target = hwnd;
+    }
+}
+
+/// Posts the messages for `inputs`. Returns how many of the inputs were
+/// posted, as SendInput does.
+pub fn post(inputs: &[INPUT]) -> u32 {
+    let Ok(mut state) = STATE.lock() else { return 0 };
+    let mut posted = 0;
+    for input in inputs {
+        let ok = match input.r#type {
+            INPUT_MOUSE => post_mouse(&mut state, unsafe { &input.Anonymous.mi }),
+            INPUT_KEYBOARD => post_key(&mut state, unsafe { &input.Anonymous.ki }),
+            _ => false,
+        };
+        if !ok {
+            break;
+        }
+        posted += 1;
+    }
+    posted
+}
+
+fn post_mouse(state: &mut State, mi: &MOUSEINPUT) -> bool {
+    let moved = mi.dwFlags & MOUSEEVENTF_MOVE != 0;
+    if moved {
+        state.cursor = if mi.dwFlags & MOUSEEVENTF_ABSOLUTE != 0 {
+            normalized_to_screen(mi.dx, mi.dy)
+        } else {
+            (state.cursor.0 + mi.dx, state.cursor.1 + mi.dy)
+        };
+    }
+    let target = if state.captured != 0 { state.captured } else { unsafe { WindowFromPoint(POINT { x: state.cursor.0, y: state.cursor.1 }) } };
+    if target == 0 {
+        return false;
+    }
+
+    let mut ok = !moved || post_at(target, WM_MOUSEMOVE, state.held, state.cursor);
+    for (flag, message, button, down) in [
+        (MOUSEEVENTF_LEFTDOWN, WM_LBUTTONDOWN, MK_LBUTTON, true),
+        (MOUSEEVENTF_LEFTUP, WM_LBUTTONUP, MK_LBUTTON, false),
+        (MOUSEEVENTF_RIGHTDOWN, WM_RBUTTONDOWN, MK_RBUTTON, true),
+        (MOUSEEVENTF_RIGHTUP, WM_RBUTTONUP, MK_RBUTTON, false),
+    ] {
+        if mi.dwFlags & flag != 0 {
+            state.held = if down { state.held | button } else { state.held & !button };
+            ok &= post_at(target, message, state.held, state.cursor);
+        }
+    }
+    state.captured = if state.held & (MK_LBUTTON | MK_RBUTTON) != 0 { target } else { 0 };
+    ok
+}
+
+// Posts a mouse message with the cursor in `hwnd`'s client coordinates
+fn post_at(hwnd: HWND, message: u32, held: WPARAM, (x, y): (i32, i32)) -> bool {
+    let mut point = POINT { x, y };
+    unsafe {
+        ScreenToClient(hwnd, &mut point);
+    }
+    let lparam = ((point.y as u16 as u32) << 16 | point.x as u16 as u32) as LPARAM;
+    unsafe { PostMessageW(hwnd, message, held, lparam) != FALSE }
+}
+
+fn post_key(state: &mut State, ki: &KEYBDINPUT) -> bool {
+    let (vk, scan) = if ki.dwFlags & KEYEVENTF_SCANCODE != 0 {
+        (unsafe { MapVirtualKeyW(ki.wScan as u32, MAPVK_VSC_TO_VK) } as u16, ki.wScan as u32)
+    } else {
+        (ki.wVk, unsafe { MapVirtualKeyW(ki.wVk as u32, MAPVK_VK_TO_VSC) })
+    };
+    let up = ki.dwFlags & KEYEVENTF_KEYUP != 0;
+    let modifier = match vk {
+        VK_CONTROL => MK_CONTROL,
+        VK_SHIFT => MK_SHIFT,
+        _ => 0,
+    };
+    state.held = if up { state.held & !modifier } else { state.held | modifier };
+
+    let target = focus_of(state.target);
+    if target == 0 {
+        return false;
+    }
+    // Repeat count 1 and the scan code; a key-up also sets the previous
+    // state and transition bits
+    let lparam = 1 | (scan & 0xFF) << 16 | if up { 0xC000_0000 } else { 0 };
+    let message = if up { WM_KEYUP } else { WM_KEYDOWN };
+    unsafe { PostMessageW(target, message, vk as WPARAM, lparam as LPARAM) != FALSE }
+}
+
+// The focused window of `hwnd`'s thread, else `hwnd` itself
+fn focus_of(hwnd: HWND) -> HWND {
+    let thread = unsafe { GetWindowThreadProcessId(hwnd, std::ptr::null_mut()) };
+    if thread == 0 {
+        return hwnd;
+    }
+    let mut info: GUITHREADINFO = unsafe { std::mem::zeroed() };
+    info.cbSize = std::mem::size_of::<GUITHREADINFO>() as u32;
+    if unsafe { GetGUIThreadInfo(thread, &mut info) } != FALSE && info.hwndFocus != 0 {
+        info.hwndFocus
+    } else {
+        hwnd
+    }
+}
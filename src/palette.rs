@@ -0,0 +1,183 @@
+// Named color palettes and what Paint's color slots hold.
+//
+// An agent drawing with a color scheme defines it once with
+// `define_palette` and then picks entries with `use_palette_color`, instead
+// of repeating hex strings. Entries are set through the same color setter
+// as `set_color`. The server also remembers which color Color 1 and Color 2
+// were last set to, so picking the color a slot already holds sends nothing
+// to Paint. Palettes last as long as the server; the slot record is dropped
+// whenever something may have changed the colors without us knowing.
+//
+// Colors added to Paint's custom colors with `add_custom_color` are recorded
+// too, newest first as Paint shows them, so switching back to one clicks its
+// swatch instead of going through the Edit colors dialog again. That record
+// lasts until another Paint window is tracked.
+
+use crate::error::{MspMcpError, Result};
+use crate::protocol::MouseButton;
+use std::collections::HashMap;
+use tracing::debug;
+
+// Most colors a palette may hold
+pub const MAX_PALETTE_COLORS: usize = 64;
+
+// Custom color swatches Paint keeps; adding one more drops the oldest
+pub const CUSTOM_COLOR_SLOTS: usize = 10;
+
+// Methods that change Color 1 or Color 2 without going through the record:
+// they set colors inside the backend, or replay input we can't follow
+const UNTRACKED_COLOR_METHODS: &[&str] = &["add_text", "create_canvas", "replay_input", "run_benchmark", "self_test"];
+
+/// True if running `method` leaves the color slots unknown.
+pub fn forgets_slots(method: &str) -> bool {
+    UNTRACKED_COLOR_METHODS.contains(&method)
+}
+
+/// `color` as uppercase `#RRGGBB`, or an error if it isn't one.
+pub fn normalize_color(color: &str) -> Result<String> {
+    match color.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(format!("#{}", hex.to_ascii_uppercase())),
+        _ => Err(MspMcpError::InvalidParameters(format!("Color must be in #RRGGBB format, got '{}'", color))),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PaletteState {
+    palettes: HashMap<String, Vec<String>>,
+    slots: [Option<String>; 2], // Color 1 and Color 2, as last set by us
+    custom: Vec<String>,        // Custom colors we added, newest first
+}
+
+impl PaletteState {
+    /// Defines (or replaces) the palette `name`. Returns its colors, normalized.
+    pub fn define(&mut self, name: &str, colors: &[String]) -> Result<Vec<String>> {
+        if name.trim().is_empty() {
+            return Err(MspMcpError::InvalidParameters("Palette name must not be empty".to_string()));
+        }
+        if colors.is_empty() || colors.len() > MAX_PALETTE_COLORS {
+            return Err(MspMcpError::InvalidParameters(
+                format!("A palette holds 1 to {} colors, got {}", MAX_PALETTE_COLORS, colors.len())));
+        }
+        let colors = colors.iter().map(|c| normalize_color(c)).collect::<Result<Vec<_>>>()?;
+        self.palettes.insert(name.to_string(), colors.clone());
+        Ok(colors)
+    }
+
+    /// The color at `index` in the palette `name`.
+    pub fn color(&self, name: &str, index: usize) -> Result<String> {
+        let colors = self.palettes.get(name).ok_or_else(|| MspMcpError::InvalidParameters(
+            format!("No palette named '{}'; define it with define_palette", name)))?;
+        colors.get(index).cloned().ok_or_else(|| MspMcpError::InvalidParameters(
+            format!("Palette '{}' has {} colors, index {} is out of range", name, colors.len(), index)))
+    }
+
+    /// True if the slot `button` draws with is known to hold `color`.
+    pub fn slot_holds(&self, button: MouseButton, color: &str) -> bool {
+        let held = &self.slots[Self::slot(button)];
+        held.as_deref().is_some_and(|held| normalize_color(color).is_ok_and(|c| c == held))
+    }
+
+    /// Records that the slot `button` draws with was set to `color`.
+    pub fn record_slot(&mut self, button: MouseButton, color: &str) {
+        self.slots[Self::slot(button)] = normalize_color(color).ok();
+    }
+
+    /// Forgets what the slot `button` draws with holds.
+    pub fn forget_slot(&mut self, button: MouseButton) {
+        self.slots[Self::slot(button)] = None;
+    }
+
+    /// Forgets what both slots hold, e.g. when another Paint window is tracked.
+    pub fn forget_slots(&mut self) {
+        if self.slots.iter().any(Option::is_some) {
+            debug!("Forgetting the colors of Color 1 and Color 2");
+        }
+        self.slots = [None, None];
+    }
+
+    /// Position of `color` among the custom colors (0 is the newest), if we added it.
+    pub fn custom_slot(&self, color: &str) -> Option<usize> {
+        let color = normalize_color(color).ok()?;
+        self.custom.iter().position(|c| *c == color)
+    }
+
+    /// Records that Paint added `color` to the front of its custom colors.
+    pub fn record_custom(&mut self, color: &str) {
+        let Ok(color) = normalize_color(color) else { return };
+        self.custom.insert(0, color);
+        self.custom.truncate(CUSTOM_COLOR_SLOTS);
+    }
+
+    /// The custom colors we added, newest first.
+    pub fn custom_colors(&self) -> &[String] {
+        &self.custom
+    }
+
+    /// Forgets the custom colors, e.g. when another Paint window is tracked.
+    pub fn forget_custom(&mut self) {
+        self.custom.clear();
+    }
+
+    fn slot(button: MouseButton) -> usize {
+        match button {
+            MouseButton::Left => 0,
+            MouseButton::Right => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_define_and_look_up_palettes() {
+        let mut palettes = PaletteState::default();
+        let colors = palettes.define("sunset", &["#ff8800".to_string(), "#202040".to_string()]).unwrap();
+        assert_eq!(colors, vec!["#FF8800", "#202040"]);
+        assert_eq!(palettes.color("sunset", 1).unwrap(), "#202040");
+        assert!(palettes.color("sunset", 2).is_err());
+        assert!(palettes.color("dawn", 0).is_err());
+
+        // A bad color leaves the old definition in place
+        assert!(palettes.define("sunset", &["orange".to_string()]).is_err());
+        assert!(palettes.define("sunset", &[]).is_err());
+        assert!(palettes.define(" ", &["#000000".to_string()]).is_err());
+        assert_eq!(palettes.color("sunset", 0).unwrap(), "#FF8800");
+    }
+
+    #[test]
+    fn test_slot_record() {
+        let mut palettes = PaletteState::default();
+        assert!(!palettes.slot_holds(MouseButton::Left, "#FF8800"));
+        palettes.record_slot(MouseButton::Left, "#ff8800");
+        assert!(palettes.slot_holds(MouseButton::Left, "#FF8800"));
+        assert!(!palettes.slot_holds(MouseButton::Right, "#FF8800"));
+
+        palettes.record_slot(MouseButton::Right, "#000000");
+        palettes.forget_slot(MouseButton::Left);
+        assert!(!palettes.slot_holds(MouseButton::Left, "#FF8800"));
+        assert!(palettes.slot_holds(MouseButton::Right, "#000000"));
+        palettes.forget_slots();
+        assert!(!palettes.slot_holds(MouseButton::Right, "#000000"));
+    }
+
+    #[test]
+    fn test_custom_color_record() {
+        let mut palettes = PaletteState::default();
+        for i in 0..=CUSTOM_COLOR_SLOTS {
+            palettes.record_custom(&format!("#0000{:02x}", i));
+        }
+        assert_eq!(palettes.custom_colors().len(), CUSTOM_COLOR_SLOTS);
+        assert_eq!(palettes.custom_slot("#00000A"), Some(0));
+        assert_eq!(palettes.custom_slot("#000001"), Some(CUSTOM_COLOR_SLOTS - 1));
+        assert_eq!(palettes.custom_slot("#000000"), None); // Pushed out by the eleventh
+        assert_eq!(palettes.custom_slot("blue"), None);
+
+        // Forgetting the slots keeps the custom colors
+        palettes.forget_slots();
+        assert_eq!(palettes.custom_slot("#00000a"), Some(0));
+        palettes.forget_custom();
+        assert!(palettes.custom_colors().is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+// Surviving panics in request handlers.
+//
+// A panic deep in uia.rs or windows.rs used to end the process, possibly in
+// the middle of a drag with the left mouse button still down. main.rs
+// installs a hook that logs every panic (the log layers write unbuffered,
+// so it is on disk and with the client before unwinding goes on), and
+// PaintServerState catches panics per request: it releases held input and
+// answers with a Panicked error, and the server keeps running.
+
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use tracing::error;
+
+/// The message a panic was raised with.
+pub fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// Logs panics through tracing instead of printing them to stderr, with a
+/// backtrace when RUST_BACKTRACE asks for one.
+pub fn install_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let thread = std::thread::current();
+        let location = info.location().map(|l| l.to_string()).unwrap_or_else(|| "unknown location".to_string());
+        let backtrace = Backtrace::capture();
+        if backtrace.status() == BacktraceStatus::Captured {
+            error!("Panic in thread '{}' at {}: {}\n{}", thread.name().unwrap_or("unnamed"), location, message(info.payload()), backtrace);
+        } else {
+            error!("Panic in thread '{}' at {}: {}", thread.name().unwrap_or("unnamed"), location, message(info.payload()));
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_messages() {
+        let literal = std::panic::catch_unwind(|| panic!("lost the canvas")).unwrap_err();
+        assert_eq!(message(literal.as_ref()), "lost the canvas");
+        let formatted = std::panic::catch_unwind(|| panic!("no element {}", 7)).unwrap_err();
+        assert_eq!(message(formatted.as_ref()), "no element 7");
+        let other = std::panic::catch_unwind(|| std::panic::panic_any(42)).unwrap_err();
+        assert_eq!(message(other.as_ref()), "unknown panic payload");
+    }
+}
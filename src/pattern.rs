@@ -0,0 +1,338 @@
+// Pattern fills: hatching, cross-hatching, dots and checkerboards.
+//
+// Paint only fills with a solid color. `fill_pattern` works out a pattern's
+// strokes on the server and keeps them inside the target region, a
+// rectangle or a polygon, so agents get textures Paint can't make. The
+// clipping is done per pixel row: a row's spans are the runs of pixels whose
+// centers lie inside the region (even-odd rule, with polygon points taken as
+// pixel corners), and strokes only ever cover pixels in them. Hatch lines
+// are 1 pixel diagonals, which the pencil draws pixel for pixel; dots and
+// the checker squares that lie wholly inside use a wider square pen, and
+// squares cut by the region's edge are filled row by row.
+
+use crate::error::{MspMcpError, Result};
+use crate::geometry::cover_rect;
+use crate::protocol::{FillPattern, MAX_IMAGE_SIDE_PX, MAX_THICKNESS_PX};
+
+// Pixels between lines, dots or checker squares when the request doesn't say
+pub const DEFAULT_PATTERN_SPACING: u32 = 8;
+
+// Most strokes one pattern may take
+pub const MAX_PATTERN_STROKES: usize = 10_000;
+
+/// The area a pattern fills.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Region {
+    Rect(i32, i32, u32, u32),  // x, y, width, height
+    Polygon(Vec<(i32, i32)>),  // Corners, in order
+}
+
+/// One stroke of a pattern.
+#[derive(Debug, PartialEq)]
+pub struct PatternStroke {
+    pub thickness_px: u32,
+    pub points: Vec<(i32, i32)>,
+}
+
+/// Plans `pattern` over `region`, `spacing` pixels apart. Strokes come
+/// ordered by width, so the width changes as few times as possible.
+pub fn plan(region: &Region, pattern: FillPattern, spacing: u32) -> Result<Vec<PatternStroke>> {
+    if !(2..=MAX_IMAGE_SIDE_PX).contains(&spacing) {
+        return Err(MspMcpError::InvalidParameters(format!("spacing must be between 2 and {} pixels, got {}", MAX_IMAGE_SIDE_PX, spacing)));
+    }
+    let spans = Spans::of(region)?;
+    // Lines and grid cells are counted before they are planned, so a pattern
+    // far too fine for its region fails fast
+    let grid = |extent: i32| extent.max(0) as usize / spacing as usize + 1;
+    let (width, height) = (spans.right - spans.left, spans.bottom() - spans.top);
+    let least = match pattern {
+        FillPattern::Hatch => grid(width + height),
+        FillPattern::Crosshatch => 2 * grid(width + height),
+        FillPattern::Dots => grid(width) * grid(height),
+        FillPattern::Checker => grid(width) * grid(height) / 2,
+    };
+    if least > MAX_PATTERN_STROKES {
+        return Err(MspMcpError::InvalidParameters(format!(
+            "The pattern takes at least {} strokes, more than the {} allowed; use a larger spacing or a smaller region",
+            least, MAX_PATTERN_STROKES)));
+    }
+    let mut strokes = match pattern {
+        FillPattern::Hatch => spans.diagonals(spacing, 1),
+        FillPattern::Crosshatch => {
+            let mut lines = spans.diagonals(spacing, 1);
+            lines.extend(spans.diagonals(spacing, -1));
+            lines
+        }
+        FillPattern::Dots => spans.dots(spacing),
+        FillPattern::Checker => spans.checker(spacing),
+    };
+    if strokes.len() > MAX_PATTERN_STROKES {
+        return Err(MspMcpError::InvalidParameters(format!(
+            "The pattern takes {} strokes, more than the {} allowed; use a larger spacing or a smaller region",
+            strokes.len(), MAX_PATTERN_STROKES)));
+    }
+    strokes.sort_by_key(|stroke| stroke.thickness_px);
+    Ok(strokes)
+}
+
+// The inside pixels of a region, row by row
+struct Spans {
+    left: i32,
+    top: i32,
+    right: i32,                   // One past the last column
+    rows: Vec<Vec<(i32, i32)>>,   // First and last pixel of each span, from row `top` on
+}
+
+impl Spans {
+    fn of(region: &Region) -> Result<Spans> {
+        let corners: Vec<(f64, f64)> = match region {
+            Region::Rect(x, y, width, height) => {
+                if *width == 0 || *height == 0 {
+                    return Err(MspMcpError::InvalidParameters(format!("The region is empty ({}x{})", width, height)));
+                }
+                let (x, y, right, bottom) = (*x as f64, *y as f64, *x as f64 + *width as f64, *y as f64 + *height as f64);
+                vec![(x, y), (right, y), (right, bottom), (x, bottom)]
+            }
+            Region::Polygon(points) => {
+                if points.len() < 3 {
+                    return Err(MspMcpError::InvalidParameters(format!("A polygon needs at least 3 points, got {}", points.len())));
+                }
+                points.iter().map(|&(x, y)| (x as f64, y as f64)).collect()
+            }
+        };
+        let fold = |axis: fn(&(f64, f64)) -> f64| corners.iter().map(axis)
+            .fold((f64::MAX, f64::MIN), |(low, high), v| (low.min(v), high.max(v)));
+        let ((left, right), (top, bottom)) = (fold(|c| c.0), fold(|c| c.1));
+        if right - left > MAX_IMAGE_SIDE_PX as f64 || bottom - top > MAX_IMAGE_SIDE_PX as f64 {
+            return Err(MspMcpError::InvalidParameters(format!("The region is larger than {} pixels across", MAX_IMAGE_SIDE_PX)));
+        }
+        let (left, top, right, bottom) = (left.floor() as i32, top.floor() as i32, right.ceil() as i32, bottom.ceil() as i32);
+
+        let edges: Vec<_> = corners.iter().zip(corners.iter().cycle().skip(1)).collect();
+        let rows = (top..bottom)
+            .map(|row| {
+                let center = row as f64 + 0.5;
+                let mut crossings: Vec<f64> = edges.iter()
+                    .filter(|((_, y1), (_, y2))| (*y1 > center) != (*y2 > center))
+                    .map(|((x1, y1), (x2, y2))| x1 + (center - y1) * (x2 - x1) / (y2 - y1))
+                    .collect();
+                crossings.sort_by(f64::total_cmp);
+                // Pixels whose centers lie in [enter, leave)
+                crossings.chunks_exact(2)
+                    .map(|pair| ((pair[0] - 0.5).ceil() as i32, (pair[1] - 0.5).ceil() as i32 - 1))
+                    .filter(|(first, last)| first <= last)
+                    .collect()
+            })
+            .collect();
+        Ok(Spans { left, top, right, rows })
+    }
+
+    fn bottom(&self) -> i32 {
+        self.top + self.rows.len() as i32
+    }
+
+    fn row(&self, y: i32) -> &[(i32, i32)] {
+        if y < self.top {
+            return &[];
+        }
+        self.rows.get((y - self.top) as usize).map_or(&[], Vec::as_slice)
+    }
+
+    // True if pixels first..=last of row y are all inside
+    fn covers(&self, y: i32, first: i32, last: i32) -> bool {
+        self.row(y).iter().any(|&(from, to)| from <= first && last <= to)
+    }
+
+    // 1 pixel lines x + y = k (slope 1, "/") or x - y = k (slope -1, "\"),
+    // `spacing` apart along each axis, cut into the runs that are inside
+    fn diagonals(&self, spacing: u32, slope: i32) -> Vec<PatternStroke> {
+        let x_at = |k: i32, y: i32| if slope > 0 { k - y } else { k + y };
+        let (first_k, last_k) = if slope > 0 {
+            (self.left + self.top, self.right + self.bottom())
+        } else {
+            (self.left - self.bottom(), self.right - self.top)
+        };
+        let mut strokes = Vec::new();
+        for k in (first_k..=last_k).step_by(spacing as usize) {
+            // Rows where the line is within the region's columns
+            let (first_y, last_y) = if slope > 0 { (k - self.right + 1, k - self.left) } else { (self.left - k, self.right - 1 - k) };
+            let (first_y, last_y) = (first_y.max(self.top), last_y.min(self.bottom() - 1));
+            let mut run: Option<(i32, i32)> = None; // First and last row
+            for y in first_y..=last_y + 1 {
+                let inside = y <= last_y && self.covers(y, x_at(k, y), x_at(k, y));
+                match (inside, run) {
+                    (true, None) => run = Some((y, y)),
+                    (true, Some((first, _))) => run = Some((first, y)),
+                    (false, Some((first, last))) => {
+                        strokes.push(PatternStroke { thickness_px: 1, points: vec![(x_at(k, first), first), (x_at(k, last), last)] });
+                        run = None;
+                    }
+                    (false, None) => {}
+                }
+            }
+        }
+        strokes
+    }
+
+    // Square dots a quarter of the spacing wide on a `spacing` grid, where
+    // the whole dot is inside
+    fn dots(&self, spacing: u32) -> Vec<PatternStroke> {
+        let size = (spacing / 4).clamp(1, MAX_THICKNESS_PX);
+        let offset = spacing as i32 / 2;
+        let mut strokes = Vec::new();
+        for y in (self.top + offset..self.bottom()).step_by(spacing as usize) {
+            for x in (self.left + offset..self.right).step_by(spacing as usize) {
+                // The pen covers from center - size / 2
+                let first = (x - size as i32 / 2, y - size as i32 / 2);
+                let inside = (first.1..first.1 + size as i32).all(|row| self.covers(row, first.0, first.0 + size as i32 - 1));
+                if inside {
+                    strokes.push(PatternStroke { thickness_px: size, points: vec![(x, y), (x, y)] });
+                }
+            }
+        }
+        strokes
+    }
+
+    // Every other `spacing` square, starting at the region's top-left
+    fn checker(&self, spacing: u32) -> Vec<PatternStroke> {
+        let size = spacing as i32;
+        let mut strokes = Vec::new();
+        for (j, y) in (self.top..self.bottom()).step_by(spacing as usize).enumerate() {
+            for (i, x) in (self.left..self.right).step_by(spacing as usize).enumerate() {
+                if (i + j) % 2 == 0 {
+                    let (last_x, last_y) = ((x + size).min(self.right) - 1, (y + size).min(self.bottom()) - 1);
+                    strokes.extend(self.fill_square(x, y, last_x, last_y));
+                }
+            }
+        }
+        strokes
+    }
+
+    // Strokes covering the inside part of the square from (x, y) to (last_x, last_y)
+    fn fill_square(&self, x: i32, y: i32, last_x: i32, last_y: i32) -> Vec<PatternStroke> {
+        if (y..=last_y).all(|row| self.covers(row, x, last_x)) {
+            let (width, height) = ((last_x - x + 1) as u32, (last_y - y + 1) as u32);
+            let thickness = width.min(height).min(MAX_THICKNESS_PX);
+            return vec![PatternStroke { thickness_px: thickness, points: cover_rect(x, y, width, height, thickness) }];
+        }
+
+        // Cut by the edge: a row at a time, joining rows into one stroke
+        // while each starts above where the last one ended
+        let mut strokes = Vec::new();
+        let mut current: Vec<(i32, i32)> = Vec::new();
+        for row in y..=last_y {
+            let pieces: Vec<(i32, i32)> = self.row(row).iter()
+                .map(|&(from, to)| (from.max(x), to.min(last_x)))
+                .filter(|(from, to)| from <= to)
+                .collect();
+            let joins = match (pieces.as_slice(), current.last()) {
+                ([(from, to)], Some(&(end, end_row))) => end_row == row - 1 && (*from..=*to).contains(&end),
+                _ => false,
+            };
+            if joins {
+                let (from, to) = pieces[0];
+                let end = current.last().map_or(from, |point| point.0);
+                current.push((end, row));
+                // Along the row to the nearer end first, then to the other
+                if end - from < to - end {
+                    current.extend([(from, row), (to, row)]);
+                } else {
+                    current.extend([(to, row), (from, row)]);
+                }
+                continue;
+            }
+            if !current.is_empty() {
+                strokes.push(PatternStroke { thickness_px: 1, points: std::mem::take(&mut current) });
+            }
+            match pieces.as_slice() {
+                [(from, to)] => current = vec![(*from, row), (*to, row)],
+                _ => strokes.extend(pieces.iter().map(|&(from, to)| PatternStroke { thickness_px: 1, points: vec![(from, row), (to, row)] })),
+            }
+        }
+        if !current.is_empty() {
+            strokes.push(PatternStroke { thickness_px: 1, points: current });
+        }
+        strokes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulate::stroke_line;
+    use image::{Rgba, RgbaImage};
+
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    fn draw(strokes: &[PatternStroke]) -> RgbaImage {
+        let mut canvas = RgbaImage::from_pixel(64, 64, WHITE);
+        for stroke in strokes {
+            for pair in stroke.points.windows(2) {
+                stroke_line(&mut canvas, pair[0], pair[1], stroke.thickness_px, Rgba([0, 0, 0, 255]));
+            }
+        }
+        canvas
+    }
+
+    fn inked(canvas: &RgbaImage, x: u32, y: u32) -> bool {
+        *canvas.get_pixel(x, y) != WHITE
+    }
+
+    // Pixels centered inside the triangle (10, 10), (50, 10), (10, 50)
+    fn in_triangle(x: u32, y: u32) -> bool {
+        (10..50).contains(&x) && (10..50).contains(&y) && x + y < 59
+    }
+
+    #[test]
+    fn test_patterns_stay_inside_the_region() {
+        let triangle = Region::Polygon(vec![(10, 10), (50, 10), (10, 50)]);
+        for pattern in [FillPattern::Hatch, FillPattern::Crosshatch, FillPattern::Dots, FillPattern::Checker] {
+            let strokes = plan(&triangle, pattern, 6).unwrap();
+            assert!(!strokes.is_empty());
+            assert!(strokes.windows(2).all(|pair| pair[0].thickness_px <= pair[1].thickness_px));
+            let canvas = draw(&strokes);
+            for y in 0..64 {
+                for x in 0..64 {
+                    assert!(!inked(&canvas, x, y) || in_triangle(x, y), "{:?} inked ({}, {})", pattern, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_checker_squares() {
+        let strokes = plan(&Region::Rect(0, 0, 20, 10), FillPattern::Checker, 4).unwrap();
+        let canvas = draw(&strokes);
+        for y in 0..12 {
+            for x in 0..22 {
+                let filled = x < 20 && y < 10 && (x / 4 + y / 4) % 2 == 0;
+                assert_eq!(inked(&canvas, x, y), filled, "({}, {})", x, y);
+            }
+        }
+        // Every square lies wholly inside, so each is one stroke of the square's width
+        assert_eq!(strokes.len(), 8);
+        assert_eq!(strokes[0].thickness_px, 2); // The half squares along the bottom
+
+        let triangle = Region::Polygon(vec![(10, 10), (50, 10), (10, 50)]);
+        let canvas = draw(&plan(&triangle, FillPattern::Checker, 8).unwrap());
+        for y in 0..64 {
+            for x in 0..64 {
+                let filled = in_triangle(x, y) && ((x - 10) / 8 + (y - 10) / 8) % 2 == 0;
+                assert_eq!(inked(&canvas, x, y), filled, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hatch_lines() {
+        let strokes = plan(&Region::Rect(0, 0, 8, 8), FillPattern::Hatch, 4).unwrap();
+        assert_eq!(strokes[0].points, vec![(0, 0), (0, 0)]);
+        assert_eq!(strokes[1].points, vec![(4, 0), (0, 4)]);
+        assert_eq!(strokes.len(), 4);
+
+        assert!(plan(&Region::Rect(0, 0, 0, 8), FillPattern::Hatch, 4).is_err());
+        assert!(plan(&Region::Polygon(vec![(0, 0), (9, 9)]), FillPattern::Hatch, 4).is_err());
+        assert!(plan(&Region::Rect(0, 0, 8, 8), FillPattern::Hatch, 1).is_err());
+        assert!(plan(&Region::Rect(0, 0, 5000, 5000), FillPattern::Dots, 2).is_err());
+    }
+}
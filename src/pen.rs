@@ -0,0 +1,122 @@
+// A pen that draws paths built up point by point.
+//
+// Most drawing code, and most of what models write, says "move to, line to,
+// line to, close" rather than handing over a finished list of points.
+// `pen_move_to` starts a subpath at a point, `pen_line_to` extends it from
+// the current point, `pen_close` runs it back to where it started, and
+// `pen_stroke` draws everything gathered so far, one drag per subpath.
+// Positions go through coordinate_space conversion, transforms and snapping
+// when they are given, like any other drawing position.
+
+use crate::error::{MspMcpError, Result};
+
+// Most points a path may gather before it has to be stroked
+pub const MAX_PATH_POINTS: usize = 10_000;
+
+#[derive(Debug, Default)]
+pub struct PenState {
+    subpaths: Vec<Vec<(i32, i32)>>, // The last one is the one being extended
+    points: usize,
+}
+
+impl PenState {
+    /// Starts a new subpath at `point`.
+    pub fn move_to(&mut self, point: (i32, i32)) -> Result<()> {
+        self.check_room()?;
+        // A subpath that never got a line is just a move; drop it
+        if self.subpaths.last().is_some_and(|subpath| subpath.len() < 2) {
+            self.subpaths.pop();
+            self.points -= 1;
+        }
+        self.subpaths.push(vec![point]);
+        self.points += 1;
+        Ok(())
+    }
+
+    /// Extends the current subpath with a line to `point`.
+    pub fn line_to(&mut self, point: (i32, i32)) -> Result<()> {
+        self.check_room()?;
+        let subpath = self.subpaths.last_mut()
+            .ok_or_else(|| MspMcpError::InvalidParameters("No current point; start the path with pen_move_to".to_string()))?;
+        subpath.push(point);
+        self.points += 1;
+        Ok(())
+    }
+
+    /// Closes the current subpath with a line back to its start; the pen
+    /// stays at the start for the next line.
+    pub fn close(&mut self) -> Result<()> {
+        let start = match self.subpaths.last() {
+            Some(subpath) if subpath.len() >= 2 => subpath[0],
+            _ => return Err(MspMcpError::InvalidParameters("Nothing to close; draw a line with pen_line_to first".to_string())),
+        };
+        self.line_to(start)?;
+        self.move_to(start)
+    }
+
+    /// Where the next line starts, if anywhere.
+    pub fn current(&self) -> Option<(i32, i32)> {
+        self.subpaths.last().and_then(|subpath| subpath.last()).copied()
+    }
+
+    /// The gathered subpaths ready to draw: repeated and collinear middle
+    /// points are dropped, and a subpath that starts where the previous one
+    /// ended is joined to it. The pen is left at the current point with an
+    /// empty path.
+    pub fn take_strokes(&mut self) -> Vec<Vec<(i32, i32)>> {
+        let current = self.current();
+        let mut strokes: Vec<Vec<(i32, i32)>> = Vec::new();
+        for subpath in std::mem::take(&mut self.subpaths).into_iter().filter(|subpath| subpath.len() >= 2) {
+            match strokes.last_mut() {
+                Some(stroke) if stroke.last() == subpath.first() => stroke.extend_from_slice(&subpath[1..]),
+                _ => strokes.push(subpath),
+            }
+        }
+        self.points = 0;
+        if let Some(point) = current {
+            self.subpaths.push(vec![point]);
+            self.points = 1;
+        }
+        strokes.into_iter().map(|stroke| crate::geometry::simplify_straight_runs(&stroke)).collect()
+    }
+
+    fn check_room(&self) -> Result<()> {
+        if self.points >= MAX_PATH_POINTS {
+            return Err(MspMcpError::InvalidParameters(
+                format!("The path holds {} points already; draw it with pen_stroke first", MAX_PATH_POINTS)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_become_strokes() {
+        let mut pen = PenState::default();
+        assert!(pen.line_to((1, 1)).is_err());
+        assert!(pen.close().is_err());
+
+        pen.move_to((0, 0)).unwrap();
+        pen.line_to((10, 0)).unwrap();
+        pen.line_to((20, 0)).unwrap();
+        pen.line_to((20, 10)).unwrap();
+        pen.close().unwrap();
+        assert_eq!(pen.current(), Some((0, 0)));
+        // A bare move is dropped, and a subpath continuing the last one joins it
+        pen.move_to((50, 50)).unwrap();
+        pen.move_to((0, 0)).unwrap();
+        pen.line_to((0, 20)).unwrap();
+        pen.move_to((40, 40)).unwrap();
+        pen.line_to((40, 40)).unwrap();
+        pen.line_to((45, 40)).unwrap();
+
+        assert_eq!(pen.take_strokes(), vec![vec![(0, 0), (20, 0), (20, 10), (0, 0), (0, 20)], vec![(40, 40), (45, 40)]]);
+        assert_eq!(pen.current(), Some((45, 40)));
+        assert!(pen.take_strokes().is_empty());
+        pen.line_to((45, 50)).unwrap();
+        assert_eq!(pen.take_strokes(), vec![vec![(45, 40), (45, 50)]]);
+    }
+}
@@ -0,0 +1,159 @@
+// Custom tools registered by programs embedding the server.
+//
+// A PaintTool bundles a name, a description, a JSON Schema for its
+// arguments and a `call` that draws through a PaintController, so a
+// composite tool (a flowchart, a chart, a stamp) can be added without
+// touching core.rs. Registered tools are listed by `tools/list` and
+// `get_api_schema`, can be called by name or through `tools/call`, and go
+// through the same request path as the built-in methods: rate limits,
+// confirm_destructive, the audit log and canvas update notifications.
+//
+// Tools are registered from Rust before the server starts serving, e.g. in
+// a binary that calls `plugins::register` and then `run_server`.
+
+use crate::error::{MspMcpError, Result};
+use crate::tools::ToolAnnotations;
+use crate::{PaintController, PaintServerState};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+pub trait PaintTool: Send + Sync {
+    /// Method and tool name: lowercase letters, digits and underscores.
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+
+    /// JSON Schema of the arguments; no arguments by default.
+    fn input_schema(&self) -> Value {
+        json!({ "type": "object", "properties": {}, "required": [] })
+    }
+
+    /// A tool that only reads should say so; the default is a tool that draws.
+    fn annotations(&self) -> ToolAnnotations {
+        ToolAnnotations { read_only_hint: false, destructive_hint: false, idempotent_hint: false, open_world_hint: false }
+    }
+
+    /// Runs the tool on the connected Paint window. The value returned is
+    /// the `result` of the response.
+    fn call(&self, paint: &PaintController, params: Value) -> Result<Value>;
+}
+
+static TOOLS: Mutex<Vec<Arc<dyn PaintTool>>> = Mutex::new(Vec::new());
+
+/// Adds a tool. Fails if its name is malformed or already taken by a
+/// built-in method or another tool.
+pub fn register(tool: Arc<dyn PaintTool>) -> Result<()> {
+    let name = tool.name().to_string();
+    let well_formed = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !well_formed {
+        return Err(MspMcpError::InvalidParameters(
+            format!("Tool name '{}' must be lowercase letters, digits and underscores", name)));
+    }
+    if crate::protocol::PAINT_METHODS.contains(&name.as_str()) || crate::protocol::get_method_handler(&name).is_some() {
+        return Err(MspMcpError::InvalidParameters(format!("'{}' is a built-in method", name)));
+    }
+    if !tool.input_schema().is_object() {
+        return Err(MspMcpError::InvalidParameters(format!("The input schema of '{}' must be a JSON object", name)));
+    }
+    let mut tools = TOOLS.lock().map_err(|_| MspMcpError::General("Failed to lock the tool registry".to_string()))?;
+    if tools.iter().any(|registered| registered.name() == name) {
+        return Err(MspMcpError::InvalidParameters(format!("A tool named '{}' is already registered", name)));
+    }
+    tools.push(tool);
+    info!("Registered tool '{}'", name);
+    Ok(())
+}
+
+/// The registered tool called `name`, if any.
+pub fn get(name: &str) -> Option<Arc<dyn PaintTool>> {
+    TOOLS.lock().ok()?.iter().find(|tool| tool.name() == name).cloned()
+}
+
+/// Every registered tool, in registration order.
+pub fn registered() -> Vec<Arc<dyn PaintTool>> {
+    TOOLS.lock().map(|tools| tools.clone()).unwrap_or_default()
+}
+
+/// Runs a registered tool for a request.
+pub async fn handle(state: PaintServerState, tool: Arc<dyn PaintTool>, params: Option<Value>) -> Result<Value> {
+    info!("Handling tool '{}'", tool.name());
+    let paint = state.controller()?;
+    let mut params = params.unwrap_or_else(|| json!({}));
+    if let Some(fields) = params.as_object_mut() {
+        fields.remove("confirm"); // Checked before the tool runs
+    }
+    let result = tool.call(&paint, params)?;
+    Ok(json!({
+        "jsonrpc": "2.0",
+        "id": 1, // Should be extracted from the request
+        "result": result
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::config::ServerConfig;
+
+    // Draws an X through a square
+    struct Cross;
+
+    impl PaintTool for Cross {
+        fn name(&self) -> &str {
+            "test_cross"
+        }
+
+        fn description(&self) -> &str {
+            "Draws an X"
+        }
+
+        fn input_schema(&self) -> Value {
+            json!({ "type": "object", "properties": { "size": { "type": "integer" } }, "required": ["size"] })
+        }
+
+        fn call(&self, paint: &PaintController, params: Value) -> Result<Value> {
+            let size = params["size"].as_i64().ok_or_else(|| MspMcpError::InvalidParameters("size is required".to_string()))? as i32;
+            paint.draw_line((0, 0), (size, size))?;
+            paint.draw_line((size, 0), (0, size))?;
+            Ok(json!({ "lines": 2 }))
+        }
+    }
+
+    // A tool that pretends to be a built-in method
+    struct Impostor;
+
+    impl PaintTool for Impostor {
+        fn name(&self) -> &str {
+            "draw_line"
+        }
+
+        fn description(&self) -> &str {
+            "Not the real draw_line"
+        }
+
+        fn call(&self, _paint: &PaintController, _params: Value) -> Result<Value> {
+            Ok(json!({}))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_tools_are_listed_and_called() {
+        register(Arc::new(Cross)).unwrap();
+        assert!(register(Arc::new(Cross)).is_err());
+        assert!(register(Arc::new(Impostor)).is_err());
+        let tools = crate::tools::tool_definitions();
+        let cross = tools.iter().find(|tool| tool["name"] == "test_cross").unwrap();
+        assert_eq!(cross["inputSchema"]["required"], json!(["size"]));
+        assert_eq!(cross["annotations"]["readOnlyHint"], false);
+
+        let backend = Arc::new(MockBackend::default());
+        let state = PaintServerState::with_backend(ServerConfig::default(), backend.clone());
+        state.set_paint_window(crate::backend::mock::MOCK_HWND).unwrap();
+        let response = state.handle_request("tools/call", Some(json!({"name": "test_cross", "arguments": {"size": 40}}))).await.unwrap();
+        assert_eq!(response["result"]["isError"], false);
+        assert_eq!(backend.take_calls(), vec!["draw_line 0 0 40 40 Left", "draw_line 40 0 0 40 Left"]);
+        assert!(state.handle_request("test_cross", Some(json!({}))).await.is_err());
+    }
+}
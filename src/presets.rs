@@ -0,0 +1,121 @@
+// Stroke presets: named bundles of tool, brush type, size and color.
+//
+// Getting a particular look out of Paint takes several settings (the brush,
+// its variant, the size slider, a color), and agents tend to forget one of
+// them between strokes. A drawing request can name a preset instead with
+// its `preset` field; the preset fills in whatever the request leaves out,
+// so `{"preset": "marker", "color": "#FF0000"}` draws a red marker stroke.
+// A few presets are built in, and the `[stroke_presets]` config table adds
+// more or replaces built-in ones of the same name.
+
+use crate::error::{MspMcpError, Result};
+use crate::protocol::{BRUSH_TYPES, MAX_THICKNESS_PX};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+struct BuiltInPreset {
+    name: &'static str,
+    tool: &'static str,
+    brush_type: Option<&'static str>,
+    thickness_px: u32,
+    color: Option<&'static str>,
+}
+
+const BUILT_IN: &[BuiltInPreset] = &[
+    // Thin, exact lines for outlines and lettering
+    BuiltInPreset { name: "fine-liner", tool: "pencil", brush_type: None, thickness_px: 1, color: None },
+    // Broad, flat strokes for filling in and emphasis
+    BuiltInPreset { name: "marker", tool: "brush", brush_type: Some("marker"), thickness_px: 8, color: None },
+    // Soft graphite lines for rough drafts
+    BuiltInPreset { name: "sketch", tool: "brush", brush_type: Some("natural_pencil"), thickness_px: 2, color: Some("#595959") },
+];
+
+/// Defaults a preset gives a drawing request. Unset values leave Paint's
+/// current setting alone, as an unset request field does.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StrokePreset {
+    pub tool: Option<String>,       // "pencil" or "brush"
+    pub brush_type: Option<String>, // Brush variant (see BRUSH_TYPES); requires tool "brush"
+    pub thickness_px: Option<u32>,  // Stroke width, 1 to MAX_THICKNESS_PX
+    pub color: Option<String>,      // "#RRGGBB"
+}
+
+impl StrokePreset {
+    /// The built-in preset called `name`, if there is one.
+    pub fn built_in(name: &str) -> Option<StrokePreset> {
+        BUILT_IN.iter().find(|preset| preset.name == name).map(|preset| StrokePreset {
+            tool: Some(preset.tool.to_string()),
+            brush_type: preset.brush_type.map(str::to_string),
+            thickness_px: Some(preset.thickness_px),
+            color: preset.color.map(str::to_string),
+        })
+    }
+
+    /// Checks the values the way the drawing methods would.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(tool) = self.tool.as_deref().filter(|tool| !matches!(*tool, "pencil" | "brush")) {
+            return Err(MspMcpError::InvalidParameters(format!("tool must be \"pencil\" or \"brush\", got \"{}\"", tool)));
+        }
+        if let Some(brush_type) = &self.brush_type {
+            if self.tool.as_deref() != Some("brush") {
+                return Err(MspMcpError::InvalidParameters("brush_type requires tool \"brush\"".to_string()));
+            }
+            if !BRUSH_TYPES.contains(&brush_type.as_str()) {
+                return Err(MspMcpError::InvalidParameters(
+                    format!("brush_type must be one of {}, got \"{}\"", BRUSH_TYPES.join(", "), brush_type)));
+            }
+        }
+        if let Some(px) = self.thickness_px.filter(|px| !(1..=MAX_THICKNESS_PX).contains(px)) {
+            return Err(MspMcpError::InvalidParameters(format!("thickness_px must be between 1 and {}, got {}", MAX_THICKNESS_PX, px)));
+        }
+        if let Some(color) = &self.color {
+            crate::palette::normalize_color(color)?;
+        }
+        Ok(())
+    }
+}
+
+/// The preset called `name`: a configured one, else a built-in one.
+pub fn resolve(name: &str, configured: &BTreeMap<String, StrokePreset>) -> Result<StrokePreset> {
+    configured.get(name).cloned()
+        .or_else(|| StrokePreset::built_in(name))
+        .ok_or_else(|| MspMcpError::InvalidParameters(
+            format!("Unknown stroke preset '{}'; presets are {}", name, names(configured).join(", "))))
+}
+
+/// Names of the built-in and configured presets, sorted.
+pub fn names(configured: &BTreeMap<String, StrokePreset>) -> Vec<String> {
+    let mut names: Vec<String> = BUILT_IN.iter().map(|preset| preset.name.to_string())
+        .chain(configured.keys().cloned())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configured_presets_replace_built_in_ones() {
+        let configured = BTreeMap::from([
+            ("marker".to_string(), StrokePreset { thickness_px: Some(20), ..StrokePreset::default() }),
+            ("chalk".to_string(), StrokePreset { tool: Some("brush".to_string()), brush_type: Some("crayon".to_string()), ..StrokePreset::default() }),
+        ]);
+        assert_eq!(resolve("marker", &configured).unwrap().thickness_px, Some(20));
+        assert_eq!(resolve("marker", &configured).unwrap().tool, None);
+        assert_eq!(resolve("fine-liner", &configured).unwrap().thickness_px, Some(1));
+        assert_eq!(names(&configured), vec!["chalk", "fine-liner", "marker", "sketch"]);
+        assert!(resolve("crayon", &configured).is_err());
+
+        for name in names(&BTreeMap::new()) {
+            StrokePreset::built_in(&name).unwrap().validate().unwrap();
+        }
+        let pencil_marker = StrokePreset { tool: Some("pencil".to_string()), brush_type: Some("marker".to_string()), ..StrokePreset::default() };
+        assert!(pencil_marker.validate().is_err());
+        assert!(StrokePreset { thickness_px: Some(0), ..StrokePreset::default() }.validate().is_err());
+        assert!(StrokePreset { color: Some("grey".to_string()), ..StrokePreset::default() }.validate().is_err());
+    }
+}
@@ -1,8 +1,10 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use serde_json::json;
 use crate::error::Result;
 use crate::core;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 // Define handler type using Box<dyn Fn> to allow storing async functions
 // This avoids type issues with different impl Future types
@@ -20,57 +22,219 @@ where
 
 // === Request Parameters ===
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct ConnectParams {
     pub client_id: String,
     pub client_name: String,
+    pub monitor: Option<u32>, // Optional monitor index to move Paint onto before drawing
+    pub dedicated_desktop: Option<bool>, // Move Paint to its own virtual desktop and automate it there
+    pub protocol_version: Option<String>, // Requested Paint protocol version; defaults to the latest
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct ClientInfo {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub struct InitializeParams {
+    #[serde(rename = "protocolVersion")]
+    pub mcp_protocol_version: Option<String>, // MCP revision requested by the host
+    pub protocol_version: Option<String>,     // Requested Paint protocol version; defaults to the latest
+    #[serde(rename = "clientInfo")]
+    pub client_info: Option<ClientInfo>,      // Host application; its name identifies the client
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct SetDedicatedDesktopParams {
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct ToolsCallParams {
+    pub name: String,             // Tool (Paint method) to run
+    pub arguments: Option<Value>, // Passed through as the method's params
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct ResourceUriParams {
+    pub uri: String, // e.g. "paint://canvas"; used by resources/read, subscribe and unsubscribe
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct SetLogLevelParams {
+    pub level: String, // MCP level: "debug|info|notice|warning|error|critical|alert|emergency"
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct SetServerLogLevelParams {
+    pub level: String,          // "off|trace|debug|info|warn|error" (MCP level names also work)
+    pub logger: Option<String>, // "terminal" or "file"; both when omitted
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct EnsureWindowOnMonitorParams {
+    pub monitor: Option<u32>, // Monitor index from get_window_info/list order; defaults to the window's current monitor
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct SelectToolParams {
     pub tool: String, // Consider using an enum later: "pencil|brush|fill|text|eraser|select|shape"
     pub shape_type: Option<String>, // Consider enum: "rectangle|ellipse|line|..."
+    pub brush_type: Option<String>, // Brush variant (see BRUSH_TYPES); requires tool "brush"
 }
 
-#[derive(Deserialize, Debug)]
+// Brush variants in Windows 11 Paint's Brushes flyout
+pub const BRUSH_TYPES: &[&str] = &[
+    "brush", "calligraphy", "calligraphy_pen", "spray", "oil", "crayon", "marker", "natural_pencil", "watercolor",
+];
+
+// Shapes draw_shape can draw
+pub const SHAPE_TYPES: &[&str] = &["rectangle", "ellipse", "line", "arrow", "triangle", "pentagon", "hexagon"];
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct SetColorParams {
     pub color: String, // Expecting "#RRGGBB"
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct SetSecondaryColorParams {
+    pub color: String, // Expecting "#RRGGBB"; Paint's "Color 2", used by right-button strokes
+}
+
+// Mouse button a stroke is drawn with. Paint draws left-button strokes in
+// Color 1 and right-button strokes in Color 2
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MouseButton {
+    #[default]
+    Left,
+    Right,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct PickColorAtParams {
+    pub x: i32,                      // Canvas pixel to sample
+    pub y: i32,
+    pub button: Option<MouseButton>, // "left" (default) sets Color 1, "right" sets Color 2
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct DefinePaletteParams {
+    pub name: String,        // Replaces any palette of the same name
+    pub colors: Vec<String>, // "#RRGGBB" each
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct UsePaletteColorParams {
+    pub name: String,
+    pub index: usize,                // Position in the palette's colors, from 0
+    pub button: Option<MouseButton>, // "left" (default) sets Color 1, "right" sets Color 2
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct AddCustomColorParams {
+    pub color: String,               // "#RRGGBB"
+    pub button: Option<MouseButton>, // "left" (default) sets Color 1, "right" sets Color 2
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct UseCustomColorParams {
+    pub color: Option<String>,       // A color added with add_custom_color, or
+    pub slot: Option<usize>,         // its position among the custom colors, 0 being the newest
+    pub button: Option<MouseButton>, // "left" (default) sets Color 1, "right" sets Color 2
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct SetSnapParams {
+    pub grid_size: Option<u32>, // Pixels between grid points; 0 or missing turns snapping off
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct PenPointParams {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct PenStrokeParams {
+    pub color: Option<String>,       // Optional color in #RRGGBB format
+    pub thickness: Option<u32>,      // Deprecated: thickness level (1-5), use thickness_px
+    pub thickness_px: Option<u32>,   // Optional stroke width in pixels
+    pub tool: Option<String>,        // Optional tool: "pencil" or "brush"
+    pub button: Option<MouseButton>, // Optional "left" (default) or "right"
+    pub preset: Option<String>,      // Optional stroke preset giving the tool, color and width
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct PushTransformParams {
+    pub translate_x: Option<f64>,    // Pixels to move right (default 0)
+    pub translate_y: Option<f64>,    // Pixels to move down (default 0)
+    pub rotate_degrees: Option<f64>, // Clockwise rotation about the origin (default 0)
+    pub scale_x: Option<f64>,        // Horizontal scale factor (default 1)
+    pub scale_y: Option<f64>,        // Vertical scale factor (default 1)
+}
+
+// Stroke width in pixels that each deprecated thickness level stands for
+pub const THICKNESS_LEVEL_PIXELS: [u32; 5] = [1, 3, 5, 8, 12];
+
+// Largest stroke width Paint's size slider goes up to
+pub const MAX_THICKNESS_PX: u32 = 100;
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct SetThicknessParams {
     pub level: u32, // Expecting 1-5
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct SetBrushSizeParams {
     pub size: u32, // Expecting 1-30
     pub tool: Option<String>, // Consider enum: "pencil|brush"
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct SetFillParams {
     pub fill_type: String, // Expecting "none|solid|outline"
 }
 
-#[derive(Deserialize, Debug)]
+// Several tool settings applied in one pass; missing fields are left as they are
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, Clone, PartialEq)]
+pub struct SetToolStateParams {
+    pub tool: Option<String>,            // As in select_tool
+    pub brush_type: Option<String>,      // Brush variant (see BRUSH_TYPES); requires tool "brush"
+    pub color: Option<String>,           // Color 1, "#RRGGBB"
+    pub secondary_color: Option<String>, // Color 2, "#RRGGBB"
+    pub thickness_px: Option<u32>,       // Stroke width, 1 to MAX_THICKNESS_PX
+    pub fill_type: Option<String>,       // "none|solid|outline"
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct DrawPixelParams {
     pub x: i32,
     pub y: i32,
     pub color: Option<String>, // Optional color in #RRGGBB format
+    pub button: Option<MouseButton>, // Optional "left" (default) or "right"
+    pub dry_run: Option<bool>, // Optional: plan the figure but send no input
+    pub preset: Option<String>, // Optional stroke preset giving the color (see presets.rs)
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct DrawLineParams {
     pub start_x: i32,
     pub start_y: i32,
     pub end_x: i32,
     pub end_y: i32,
     pub color: Option<String>,     // Optional color in #RRGGBB format
-    pub thickness: Option<u32>,    // Optional thickness level (1-5)
+    pub thickness: Option<u32>,    // Deprecated: thickness level (1-5), use thickness_px
+    pub thickness_px: Option<u32>, // Optional stroke width in pixels
+    pub button: Option<MouseButton>, // Optional "left" (default) or "right"
+    pub clip: Option<bool>,        // Optional: draw only the part on the canvas
+    pub dry_run: Option<bool>,     // Optional: plan the figure but send no input
+    pub preset: Option<String>,    // Optional stroke preset giving the color and width
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct DrawShapeParams {
     pub shape_type: String,        // "rectangle|ellipse|line|arrow|triangle|pentagon|hexagon"
     pub start_x: i32,
@@ -78,19 +242,103 @@ pub struct DrawShapeParams {
     pub end_x: i32,
     pub end_y: i32,
     pub color: Option<String>,     // Optional color in #RRGGBB format
-    pub thickness: Option<u32>,    // Optional thickness level (1-5)
+    pub thickness: Option<u32>,    // Deprecated: thickness level (1-5), use thickness_px
+    pub thickness_px: Option<u32>, // Optional stroke width in pixels
     pub fill_type: Option<String>, // Optional fill type "none|solid|outline"
+    pub button: Option<MouseButton>, // Optional "left" (default) or "right"
+    pub clip: Option<bool>,        // Optional: draw only the part on the canvas
+    pub dry_run: Option<bool>,     // Optional: plan the figure but send no input
+    pub preset: Option<String>,    // Optional stroke preset giving the color and width
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct DrawPolylineParams {
     pub points: Vec<Point>,         // Series of points to connect
     pub color: Option<String>,      // Optional color in #RRGGBB format
-    pub thickness: Option<u32>,     // Optional thickness level (1-5)
+    pub thickness: Option<u32>,     // Deprecated: thickness level (1-5), use thickness_px
+    pub thickness_px: Option<u32>,  // Optional stroke width in pixels
+    pub tool: Option<String>,       // Optional tool: "pencil" or "brush"
+    pub button: Option<MouseButton>, // Optional "left" (default) or "right"
+    pub clip: Option<bool>,         // Optional: draw only the part on the canvas
+    pub dry_run: Option<bool>,      // Optional: plan the figure but send no input
+    pub smoothing: Option<Smoothing>, // Optional curve through the points
+    pub simplify_tolerance: Option<f64>, // Optional: drop points the path strays less than this from, in pixels
+    pub preset: Option<String>,     // Optional stroke preset giving the tool, color and width
+    pub width_profile: Option<WidthProfile>, // Optional width varying along the stroke
+}
+
+// How a draw_polyline's width varies along the stroke: per point, or
+// tapering from thickness_px towards the ends
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct WidthProfile {
+    pub widths: Option<Vec<u32>>, // Width in pixels at each point, changing evenly in between
+    pub taper_in: Option<f64>,    // Part of the stroke (0-1) over which it widens from min_px at the start
+    pub taper_out: Option<f64>,   // Part of the stroke (0-1) over which it narrows to min_px at the end
+    pub min_px: Option<u32>,      // Width at the tapered ends; defaults to 1
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct DrawEllipseParams {
+    pub x: i32,                     // Canvas coordinates of the center
+    pub y: i32,
+    pub radius_x: u32,              // Radius along the ellipse's own x axis
+    pub radius_y: u32,              // Radius along its y axis
+    pub rotation_deg: Option<f64>,  // Optional clockwise turn of the x axis (default 0)
+    pub start_angle: Option<f64>,   // Optional arc: from this direction, in degrees clockwise from the x axis,
+    pub end_angle: Option<f64>,     // clockwise to this one
+    pub color: Option<String>,      // Optional color in #RRGGBB format
+    pub thickness: Option<u32>,     // Deprecated: thickness level (1-5), use thickness_px
+    pub thickness_px: Option<u32>,  // Optional stroke width in pixels
     pub tool: Option<String>,       // Optional tool: "pencil" or "brush"
+    pub button: Option<MouseButton>, // Optional "left" (default) or "right"
+    pub clip: Option<bool>,         // Optional: draw only the part on the canvas
+    pub dry_run: Option<bool>,      // Optional: plan the figure but send no input
+    pub preset: Option<String>,     // Optional stroke preset giving the tool, color and width
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct DrawGradientParams {
+    pub x: i32,                      // Canvas coordinates of the rectangle's top-left corner
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub start_color: String,         // "#RRGGBB" at the left (horizontal) or top (vertical) edge
+    pub end_color: String,           // "#RRGGBB" at the opposite edge
+    pub direction: Option<GradientDirection>, // Optional, "horizontal" by default
+    pub steps: Option<u32>,          // Optional number of color bands (1-256, default 32)
+    pub button: Option<MouseButton>, // Optional "left" (default) or "right"
+    pub dry_run: Option<bool>,       // Optional: plan the figure but send no input
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct FillPatternParams {
+    pub pattern: FillPattern,
+    pub x: Option<i32>,              // The rectangle to fill, or
+    pub y: Option<i32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub points: Option<Vec<Point>>,  // the corners of a polygon to fill
+    pub spacing: Option<u32>,        // Optional pixels between lines, dots or squares (default 8)
+    pub color: Option<String>,       // Optional color in #RRGGBB format
+    pub button: Option<MouseButton>, // Optional "left" (default) or "right"
+    pub dry_run: Option<bool>,       // Optional: plan the figure but send no input
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct DrawGlyphTextParams {
+    pub x: i32,                      // Left edge of the text
+    pub y: i32,                      // Top of the first line
+    pub text: String,                // Text to draw; '\n' starts a new line
+    pub font: String,                // Font file path, file name in the font folders, or font name
+    pub size_px: u32,                // Em size in pixels
+    pub fill: Option<bool>,          // Optional: false draws the outlines only (default true)
+    pub thickness_px: Option<u32>,   // Optional outline width in pixels
+    pub color: Option<String>,       // Optional color in #RRGGBB format
+    pub button: Option<MouseButton>, // Optional "left" (default) or "right"
+    pub dry_run: Option<bool>,       // Optional: plan the strokes but send no input
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct AddTextParams {
     pub x: i32,                     // X position to place text
     pub y: i32,                     // Y position to place text
@@ -101,20 +349,308 @@ pub struct AddTextParams {
     pub font_style: Option<String>, // Optional style: "regular", "bold", "italic", "bold_italic"
 }
 
-#[derive(Deserialize, Debug)]
+// How to answer Paint's "Do you want to save changes?" prompt
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnsavedChanges {
+    Save,    // Save the document first (it must already have a file name)
+    Discard, // Drop the changes
+    #[default]
+    Fail,    // Cancel and return an error
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct CreateCanvasParams {
     pub width: u32,                 // Canvas width in pixels
     pub height: u32,                // Canvas height in pixels
     pub background_color: Option<String>, // Optional background color in #RRGGBB format
+    pub unsaved_changes: Option<UnsavedChanges>, // If Paint asks to save the current document (default "fail")
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct DuplicateCanvasParams {
+    pub target_hwnd: Option<isize>, // Optional Paint window to copy into instead of a new one
+    pub unsaved_changes: Option<UnsavedChanges>, // If that window asks to save its document (default "fail")
+    pub track_copy: Option<bool>,   // Optional: draw on the copy from now on (default false)
 }
 
-#[derive(Deserialize, Debug)]
+// Units for set_image_properties' width and height
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageUnits {
+    #[default]
+    Pixels,
+    Inches,
+    Centimeters,
+}
+
+// The Colors choice in Image Properties
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorMode {
+    Color,
+    BlackAndWhite, // Paint reduces the image to black and white pixels
+}
+
+// Paint's resolution for new images, used to convert inches and centimeters
+pub const IMAGE_DPI: f64 = 96.0;
+
+// Largest side set_image_properties accepts, so a mistyped size doesn't
+// ask Paint for gigabytes
+pub const MAX_IMAGE_SIDE_PX: u32 = 20_000;
+
+impl ImageUnits {
+    /// `value` in these units as a whole number of pixels.
+    pub fn to_pixels(self, value: f64) -> f64 {
+        match self {
+            ImageUnits::Pixels => value,
+            ImageUnits::Inches => value * IMAGE_DPI,
+            ImageUnits::Centimeters => value * IMAGE_DPI / 2.54,
+        }.round()
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub struct SetImagePropertiesParams {
+    pub width: Option<f64>,            // New width in `units`; unchanged if missing
+    pub height: Option<f64>,           // New height in `units`; unchanged if missing
+    pub units: Option<ImageUnits>,     // Default "pixels"
+    pub color_mode: Option<ColorMode>, // Unchanged if missing
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub struct NewDocumentParams {
+    pub unsaved_changes: Option<UnsavedChanges>, // If Paint asks to save the current document (default "fail")
+}
+
+// How long quit_paint waits for Paint to exit unless told otherwise
+pub const DEFAULT_QUIT_TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub struct CloseParams {
+    pub unsaved_changes: Option<UnsavedChanges>, // If Paint asks to save the current document (default "fail")
+    pub timeout_ms: Option<u64>,                 // quit_paint: how long to wait for Paint to exit
+}
+
+// Formats save_canvas can write
+pub const SAVE_FORMATS: &[&str] = &["png", "jpeg", "bmp"];
+
+// Formats save_as can write; it picks the type in the dialog, which GIF needs
+pub const SAVE_AS_FORMATS: &[&str] = &["png", "jpeg", "bmp", "gif"];
+
+// JPEG quality used when Paint asks for one and the request didn't say
+pub const DEFAULT_JPEG_QUALITY: u32 = 90;
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct SaveCanvasParams {
     pub file_path: String,         // Path where to save the file
-    pub format: String,            // Format - "png", "jpeg", or "bmp"
+    pub format: String,            // Format - "png", "jpeg", or "bmp" ("gif" too for save_as)
+    #[serde(default)]
+    pub overwrite: bool,           // Replace an existing file (default false)
+    pub jpeg_quality: Option<u32>, // 1-100, if Paint asks for a JPEG quality
+    #[serde(default)]
+    pub create_dirs: bool,         // Create missing parent directories (default false)
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct FetchImageParams {
+    pub file_path: String,           // Image file to read (PNG, JPEG, BMP or GIF)
+    pub preview_max_px: Option<u32>, // If the file is over the size limit, send a PNG preview this big instead
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct StampImageParams {
+    pub image: String,          // Base64-encoded image (PNG, JPEG, BMP or GIF)
+    pub positions: Vec<Point>,  // Where each copy's top-left corner goes
+    pub scale: Option<f64>,     // Optional size factor, 1.0 by default
+}
+
+// Largest stamp side, after scaling; stamps are small repeated elements
+pub const MAX_STAMP_SIDE_PX: u32 = 512;
+
+// Most copies one stamp_image request may paste
+pub const MAX_STAMP_POSITIONS: usize = 1000;
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct StartInputRecordingParams {
+    pub path: String,               // JSON Lines file to write (truncated)
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct ReplayInputParams {
+    pub path: String,               // Recording written by start_input_recording
+    pub speed: Option<f64>,         // Playback speed multiplier (default 1.0)
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub struct RunBenchmarkParams {
+    pub iterations: Option<u32>,    // Operations per measurement (default 20, max 500)
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub struct CalibrateParams {
+    pub tools: Option<Vec<String>>, // Tools to find (default: every calibratable tool)
+    pub persist: Option<bool>,      // Save the positions for later sessions (default true)
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct RunMacroParams {
+    pub source: Option<String>,     // Script to run
+    pub name: Option<String>,       // Or the name of a registered macro
+    pub args: Option<Value>,        // Passed to the script as `args`
+    pub dry_run: Option<bool>,      // Return the planned requests without running them
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct RegisterMacroParams {
+    pub name: String,               // Lowercase letters, digits and underscores
+    pub source: String,             // Script, checked when it is registered
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct DrawAnimationParams {
+    pub frames: Vec<AnimationFrame>,
+    pub frame_delay_ms: u64,        // From the start of one frame to the start of the next
+    pub loop_count: Option<u32>,    // Times to play the frames; defaults to 1
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct AnimationFrame {
+    pub before: Option<FrameReset>, // What happens to the frame before this one; unset draws over it
+    pub steps: Vec<AnimationStep>,  // Requests that draw the frame
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct AnimationStep {
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+// How draw_animation removes the previous frame
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameReset {
+    Clear, // Clear the whole canvas
+    Undo,  // Undo the previous frame's drawing steps, leaving what was there before
+}
+
+// Most times draw_animation plays its frames, and longest it holds one
+pub const MAX_ANIMATION_LOOPS: u32 = 100;
+pub const MAX_FRAME_DELAY_MS: u64 = 10_000;
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct RotateSelectionParams {
+    pub angle: u32,         // Clockwise degrees: 90, 180 or 270
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct FlipSelectionParams {
+    pub direction: String,  // "horizontal" or "vertical"
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct SetBackgroundParams {
+    pub color: String,      // #RRGGBB; also becomes Color 2
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub struct GetAuditLogParams {
+    pub client_id: Option<String>, // Only requests from this client
+    pub method: Option<String>,    // Only calls of this method
+    pub since_ms: Option<u64>,     // Only entries at or after this Unix time, in milliseconds
+    #[serde(default)]
+    pub errors_only: bool,         // Only failed requests
+    pub limit: Option<u32>,        // Most recent matching entries to return (default 100)
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub struct InvertColorsParams {
+    pub scope: Option<String>, // "canvas" (default) or "selection"
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct CheckpointParams {
+    pub label: String, // Name of the checkpoint; used by checkpoint and revert_to_checkpoint
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct LayerIndexParams {
+    pub index: u32, // Position in Paint's Layers panel, 0 = top layer; used by select_layer, merge_layers and delete_layer
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct SetLayerVisibilityParams {
+    pub index: u32,    // Position in the Layers panel, 0 = top layer
+    pub visible: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct GetCanvasRegionParams {
+    pub x: i32,      // Canvas coordinates of the top-left corner
+    pub y: i32,
+    pub width: u32,  // Clipped to the canvas
+    pub height: u32,
+}
+
+// How a request's positions and sizes are given
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CoordinateSpace {
+    #[default]
+    #[serde(alias = "pixels")]
+    Canvas,     // Image pixels
+    Normalized, // Fractions (0.0-1.0) of the canvas width and height
+    Client,     // Pixels of the Paint window's client area
+    Screen,     // Absolute screen pixels
+}
+
+// When a request that sends input brings Paint to the foreground
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    #[default]
+    Always,   // Before every step, as Paint may have lost focus since the last one
+    IfNeeded, // Only when Paint isn't the foreground window already
+    Never,    // Never; input is posted to Paint as window messages instead
+}
+
+// How draw_polyline curves between its points
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Smoothing {
+    CatmullRom, // A Catmull-Rom spline through every point
+}
+
+// Which way a draw_gradient's colors change
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GradientDirection {
+    #[default]
+    Horizontal, // From the left edge to the right
+    Vertical,   // From the top edge to the bottom
 }
 
-#[derive(Deserialize, Debug)]
+// What fill_pattern draws
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FillPattern {
+    Hatch,      // Diagonal lines
+    Crosshatch, // Diagonal lines both ways
+    Dots,       // A grid of square dots
+    Checker,    // Alternate squares filled
+}
+
+// How much of a figure drawn with `clip` was off the canvas and left out
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Clipped {
+    None,    // All of it was on the canvas
+    Partial, // Only the part on the canvas was drawn
+    All,     // Nothing was on the canvas, so nothing was drawn
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct Point {
     pub x: i32,
     pub y: i32,
@@ -125,12 +661,12 @@ pub struct Point {
 
 // === Response Payloads ===
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct SuccessResponse {
     pub status: String, // Always "success"
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct ConnectResponse {
     pub status: String, // Always "success"
     pub paint_version: String,
@@ -138,7 +674,7 @@ pub struct ConnectResponse {
     pub canvas_height: u32,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct GetVersionResponse {
     pub status: String, // Always "success"
     pub protocol_version: String,
@@ -146,22 +682,171 @@ pub struct GetVersionResponse {
     pub paint_version: String,
 }
 
-#[derive(Serialize, Debug)]
+// What the attached Paint build offers, as found in its UI by get_supported_tools
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct PaintCapabilities {
+    pub tools: Vec<String>,       // select_tool tools with a toolbar button
+    pub brush_types: Vec<String>, // BRUSH_TYPES entries in the Brushes flyout
+    pub shapes: Vec<String>,      // SHAPE_TYPES entries in the Shapes gallery
+    pub layers: bool,             // Has the Layers panel (builds from late 2023 on)
+    pub background_removal: bool, // Has the Remove background button
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct ErrorResponse {
     pub status: String, // Always "error"
     pub error: ErrorDetails,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct ErrorDetails {
     pub code: i32,
     pub message: String,
 }
 
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+pub struct RectInfo {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl RectInfo {
+    pub fn new(left: i32, top: i32, right: i32, bottom: i32) -> Self {
+        RectInfo { left, top, right, bottom, width: right - left, height: bottom - top }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MonitorInfo {
+    pub index: u32,             // Position in EnumDisplayMonitors order
+    pub handle: isize,
+    pub monitor_rect: RectInfo, // Full monitor area in screen coordinates
+    pub work_rect: RectInfo,    // Monitor area excluding the taskbar
+    pub is_primary: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct WindowInfoResponse {
+    pub hwnd: isize,
+    pub pid: u32,
+    pub class_name: String,
+    pub title: String,
+    pub window_rect: RectInfo,      // Screen coordinates
+    pub client_rect: RectInfo,      // Client coordinates (left/top always 0)
+    pub client_origin_x: i32,       // Screen position of the client area's top-left corner
+    pub client_origin_y: i32,
+    pub dpi: u32,
+    pub scale_factor: f64,          // dpi / 96
+    pub monitor: Option<MonitorInfo>,
+    pub is_foreground: bool,
+    pub is_minimized: bool,
+    pub is_maximized: bool,
+    pub z_order: u32,               // Number of top-level windows above this one (0 = topmost)
+    pub canvas_rect: RectInfo,      // Computed canvas area in client coordinates
+}
+
+/// The document's size and how much of it is on screen.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct CanvasDimensionsResponse {
+    pub width: u32,         // Document size in image pixels
+    pub height: u32,
+    pub zoom: f64,          // 1.0 is 100%
+    pub viewport: RectInfo, // Area the canvas is shown in, screen coordinates
+    pub scroll_x: u32,      // Image pixels scrolled out of view on the left
+    pub scroll_y: u32,      // and at the top
+    pub estimated: bool,    // Paint couldn't be read; the size was guessed from the window size
+}
+
 // Add more response structs here...
 // e.g., GetCanvasDimensionsResponse, FetchImageResponse, etc.
 
 
+// === Versions ===
+
+// MCP protocol revision this server implements
+pub const MCP_PROTOCOL_VERSION: &str = "2024-11-05";
+
+// MCP revisions we can speak, newest first
+pub const SUPPORTED_MCP_PROTOCOL_VERSIONS: &[&str] = &[MCP_PROTOCOL_VERSION];
+
+// Paint protocol versions we can speak, oldest first. 1.1 added the
+// monitor/dedicated_desktop connect options and the document/window
+// fields in the connect result.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["1.0", "1.1"];
+pub const LATEST_PROTOCOL_VERSION: &str = "1.1";
+
+/// Picks the Paint protocol version for a session: the client's requested
+/// version if we support it, the latest if none was requested.
+pub fn negotiate_protocol_version(requested: Option<&str>) -> Result<&'static str> {
+    match requested {
+        None => Ok(LATEST_PROTOCOL_VERSION),
+        Some(requested) => SUPPORTED_PROTOCOL_VERSIONS
+            .iter()
+            .find(|v| **v == requested)
+            .copied()
+            .ok_or_else(|| crate::error::MspMcpError::UnsupportedProtocolVersion(format!(
+                "'{}' is not supported. Supported versions: {}", requested, SUPPORTED_PROTOCOL_VERSIONS.join(", ")))),
+    }
+}
+
+/// Picks the MCP revision for the initialize result. Unlike the Paint
+/// protocol, MCP has the server answer with a version it does support and
+/// leaves it to the host to disconnect, so this never fails.
+pub fn negotiate_mcp_protocol_version(requested: Option<&str>) -> &'static str {
+    requested
+        .and_then(|r| SUPPORTED_MCP_PROTOCOL_VERSIONS.iter().find(|v| **v == r).copied())
+        .unwrap_or(SUPPORTED_MCP_PROTOCOL_VERSIONS[0])
+}
+
+/// True if `negotiated` is at least `minimum` (both "major.minor").
+pub fn protocol_at_least(negotiated: &str, minimum: &str) -> bool {
+    fn parse(v: &str) -> (u32, u32) {
+        let mut parts = v.split('.').map(|p| p.parse::<u32>().unwrap_or(0));
+        (parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+    }
+    parse(negotiated) >= parse(minimum)
+}
+
+// === Capabilities ===
+
+// Paint-specific methods advertised under experimental.paint
+pub const PAINT_METHODS: &[&str] = &[
+    "connect", "disconnect", "get_version", "get_supported_tools", "activate_window",
+    "get_canvas_dimensions", "get_window_info", "ensure_window_on_monitor", "set_dedicated_desktop",
+    "draw_pixel", "draw_line", "draw_shape", "draw_polyline", "draw_ellipse", "draw_gradient", "fill_pattern", "draw_glyph_text", "add_text",
+    "select_region", "copy_selection", "paste", "stamp_image", "rotate_selection", "flip_selection", "invert_colors", "clear_canvas", "new_document", "close_document", "quit_paint", "create_canvas", "duplicate_canvas", "set_image_properties", "set_background", "save_canvas", "save_as", "get_document_info", "fetch_image",
+    "select_tool", "set_color", "set_secondary_color", "pick_color_at", "set_thickness", "set_brush_size", "set_fill", "set_tool_state", "define_palette", "use_palette_color", "add_custom_color", "use_custom_color", "set_snap", "push_transform", "pop_transform",
+    "pen_move_to", "pen_line_to", "pen_close", "pen_stroke",
+    "add_layer", "select_layer", "set_layer_visibility", "merge_layers", "delete_layer",
+    "export_canvas", "capture_window", "get_canvas_region", "export_region",
+    "start_input_recording", "stop_input_recording", "replay_input",
+    "run_macro", "register_macro", "draw_animation", "checkpoint", "revert_to_checkpoint",
+    "run_benchmark", "self_test", "calibrate", "get_audit_log", "set_log_level",
+];
+
+/// Capabilities advertised to the host at initialize so feature discovery
+/// works. Kept as JSON so the same object can be returned from both the SDK
+/// handshake and the stdio loop's initialize result.
+pub fn server_capabilities() -> Value {
+    json!({
+        "tools": { "listChanged": false },
+        "resources": { "subscribe": true, "listChanged": false },
+        "prompts": { "listChanged": false },
+        "logging": {},
+        "experimental": {
+            "paint": {
+                "version": env!("CARGO_PKG_VERSION"),
+                "paint_version": "windows11",
+                "methods": PAINT_METHODS
+            }
+        }
+    })
+}
+
 // === Utility ===
 
 // Helper function to create a standard success response
@@ -173,6 +858,84 @@ pub fn success_response() -> serde_json::Value {
     })
 }
 
+// MCP text content item
+pub fn text_content(text: &str) -> Value {
+    json!({ "type": "text", "text": text })
+}
+
+// MCP image content item with base64-encoded data
+pub fn image_content(data: &[u8], mime_type: &str) -> Value {
+    use base64::Engine;
+    json!({
+        "type": "image",
+        "data": base64::engine::general_purpose::STANDARD.encode(data),
+        "mimeType": mime_type
+    })
+}
+
+// Standard JSON-RPC 2.0 error codes
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+pub const INTERNAL_ERROR: i32 = -32603;
+
+// Builds the `data` member for a failed method call
+pub fn error_data(method: &str, error: &crate::error::MspMcpError) -> Value {
+    let mut data = json!({
+        "method": method,
+        "code": error.code()
+    });
+    if let crate::error::MspMcpError::UnsupportedProtocolVersion(_) = error {
+        data["supported_versions"] = json!(SUPPORTED_PROTOCOL_VERSIONS);
+    }
+    if let crate::error::MspMcpError::PayloadTooLarge { limit, unit, .. } = error {
+        data["limit"] = json!(limit);
+        data["unit"] = json!(unit);
+    }
+    if let crate::error::MspMcpError::RateLimited { retry_after_ms, .. } = error {
+        data["retry_after_ms"] = json!(retry_after_ms);
+    }
+    data
+}
+
+// Sequence number of the next request handled by this process
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Generates a server-side correlation id for a request, unique across
+/// restarts because it starts with the process id.
+pub fn next_request_id() -> String {
+    format!("{}-{}", std::process::id(), NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Adds the correlation id to a response's `result`, or to error `data`,
+/// as `_request_id`. Non-object values are left alone.
+pub fn add_request_id(value: &mut Value, request_id: &str) {
+    let target = match value.get_mut("result") {
+        Some(result) => result,
+        None => value,
+    };
+    if let Some(object) = target.as_object_mut() {
+        object.insert("_request_id".to_string(), json!(request_id));
+    }
+}
+
+// Helper function to create a JSON-RPC error response with optional data
+pub fn json_rpc_error(id: Value, code: i32, message: String, data: Option<Value>) -> Value {
+    let mut error = json!({
+        "code": code,
+        "message": message
+    });
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": error
+    })
+}
+
 // Helper function to create a standard error response
 pub fn error_response(code: i32, message: String) -> serde_json::Value {
     json!({
@@ -253,7 +1016,263 @@ mod tests {
         assert_eq!(params.tool.as_deref(), Some("pencil"));
     }
 
-    // Add more tests for other structs...
+    #[test]
+    fn test_server_capabilities_advertise_features() {
+        let caps = server_capabilities();
+        for key in ["tools", "resources", "prompts", "logging", "experimental"] {
+            assert!(caps.get(key).is_some(), "missing capability '{}'", key);
+        }
+        let methods = caps["experimental"]["paint"]["methods"].as_array().unwrap();
+        assert_eq!(methods.len(), PAINT_METHODS.len());
+    }
+
+    #[test]
+    fn test_advertised_methods_have_handlers() {
+        for method in PAINT_METHODS {
+            assert!(get_method_handler(method).is_some(), "no handler for '{}'", method);
+        }
+    }
+
+    #[test]
+    fn test_json_rpc_error_codes() {
+        let err = crate::error::MspMcpError::MethodNotFound("frobnicate".to_string());
+        let response = json_rpc_error(json!(7), err.json_rpc_code(), err.to_string(), Some(error_data("frobnicate", &err)));
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(response["error"]["data"]["method"], "frobnicate");
+
+        let err = crate::error::MspMcpError::InvalidParameters("missing x".to_string());
+        assert_eq!(err.json_rpc_code(), INVALID_PARAMS);
+        assert_eq!(crate::error::MspMcpError::WindowNotFound.json_rpc_code(), INTERNAL_ERROR);
+
+        let parse = json_rpc_error(Value::Null, PARSE_ERROR, "bad".to_string(), None);
+        assert!(parse["error"].get("data").is_none());
+    }
+
+    #[test]
+    fn test_protocol_version_negotiation() {
+        assert_eq!(negotiate_protocol_version(None).unwrap(), LATEST_PROTOCOL_VERSION);
+        assert_eq!(negotiate_protocol_version(Some("1.0")).unwrap(), "1.0");
+        let err = negotiate_protocol_version(Some("2.0")).unwrap_err();
+        assert_eq!(err.json_rpc_code(), INVALID_PARAMS);
+        assert!(err.to_string().contains("1.0, 1.1"));
+
+        assert_eq!(negotiate_mcp_protocol_version(Some("1999-01-01")), MCP_PROTOCOL_VERSION);
+        assert!(protocol_at_least("1.1", "1.1"));
+        assert!(protocol_at_least("1.10", "1.9"));
+        assert!(!protocol_at_least("1.0", "1.1"));
+    }
+
+    #[test]
+    fn test_image_content_item() {
+        let item = image_content(&[0x89, b'P', b'N', b'G'], "image/png");
+        assert_eq!(item["type"], "image");
+        assert_eq!(item["mimeType"], "image/png");
+        assert_eq!(item["data"], "iVBORw==");
+    }
+
+    // === Property tests: every params/response struct survives a JSON round trip ===
+
+    use proptest::prelude::*;
+    use serde::de::DeserializeOwned;
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug>(value: &T) -> std::result::Result<(), TestCaseError> {
+        let text = serde_json::to_string(value).map_err(|e| TestCaseError::fail(e.to_string()))?;
+        let back: T = serde_json::from_str(&text).map_err(|e| TestCaseError::fail(format!("{}: {}", e, text)))?;
+        prop_assert_eq!(&back, value);
+        Ok(())
+    }
+
+    fn text() -> impl Strategy<Value = String> {
+        ".{0,16}"
+    }
+
+    fn opt_text() -> impl Strategy<Value = Option<String>> {
+        proptest::option::of(text())
+    }
+
+    // Binary fractions, which JSON carries exactly
+    fn fraction() -> impl Strategy<Value = f64> {
+        (0..100_000u32).prop_map(|n| n as f64 / 64.0)
+    }
+
+    fn button() -> impl Strategy<Value = Option<MouseButton>> {
+        proptest::option::of(prop_oneof![Just(MouseButton::Left), Just(MouseButton::Right)])
+    }
+
+    fn rect() -> impl Strategy<Value = RectInfo> {
+        (any::<i16>(), any::<i16>(), any::<i16>(), any::<i16>())
+            .prop_map(|(l, t, r, b)| RectInfo::new(l as i32, t as i32, r as i32, b as i32))
+    }
+
+    fn monitor() -> impl Strategy<Value = MonitorInfo> {
+        (any::<u32>(), any::<isize>(), rect(), rect(), any::<bool>()).prop_map(|(index, handle, monitor_rect, work_rect, is_primary)|
+            MonitorInfo { index, handle, monitor_rect, work_rect, is_primary })
+    }
+
+    proptest! {
+        #[test]
+        fn session_params_round_trip(
+            (client_id, client_name, monitor, dedicated_desktop, protocol_version) in (text(), text(), any::<Option<u32>>(), any::<Option<bool>>(), opt_text()),
+            mcp_protocol_version in opt_text(), enabled in any::<bool>(), name in text(), argument in proptest::option::of(any::<i64>()),
+            uri in text(), level in text(),
+        ) {
+            round_trip(&ConnectParams { client_id, client_name, monitor, dedicated_desktop, protocol_version: protocol_version.clone() })?;
+            let client_info = mcp_protocol_version.clone().map(|name| ClientInfo { name, version: protocol_version.clone() });
+            round_trip(&InitializeParams { mcp_protocol_version, protocol_version, client_info })?;
+            round_trip(&SetDedicatedDesktopParams { enabled })?;
+            round_trip(&ToolsCallParams { name, arguments: argument.map(|x| json!({ "x": x })) })?;
+            round_trip(&ResourceUriParams { uri })?;
+            round_trip(&SetServerLogLevelParams { level: level.clone(), logger: enabled.then(|| level.clone()) })?;
+            round_trip(&SetLogLevelParams { level })?;
+            round_trip(&EnsureWindowOnMonitorParams { monitor })?;
+        }
+
+        #[test]
+        fn tool_params_round_trip(
+            tool in text(), shape_type in opt_text(), color in text(), level in any::<u32>(),
+            size in any::<u32>(), brush_tool in opt_text(), fill_type in text(),
+        ) {
+            round_trip(&SelectToolParams { tool, shape_type: shape_type.clone(), brush_type: shape_type })?;
+            round_trip(&SetColorParams { color: color.clone() })?;
+            round_trip(&SetSecondaryColorParams { color: color.clone() })?;
+            round_trip(&SetBackgroundParams { color: color.clone() })?;
+            round_trip(&SetThicknessParams { level })?;
+            round_trip(&SetBrushSizeParams { size, tool: brush_tool.clone() })?;
+            round_trip(&SetFillParams { fill_type: fill_type.clone() })?;
+            round_trip(&FlipSelectionParams { direction: fill_type.clone() })?;
+            round_trip(&InvertColorsParams { scope: brush_tool.clone() })?;
+            round_trip(&SetToolStateParams {
+                tool: brush_tool, brush_type: None, color: Some(color.clone()), secondary_color: Some(color),
+                thickness_px: Some(size), fill_type: Some(fill_type),
+            })?;
+        }
+
+        #[test]
+        fn drawing_params_round_trip(
+            (x, y, end_x, end_y) in (any::<i32>(), any::<i32>(), any::<i32>(), any::<i32>()),
+            color in opt_text(), thickness in any::<Option<u32>>(), fill_type in opt_text(), shape_type in text(),
+            points in proptest::collection::vec((any::<i32>(), any::<i32>()), 0..8), tool in opt_text(), button in button(),
+            (text_value, font_name, font_size, font_style) in (text(), opt_text(), any::<Option<u32>>(), opt_text()),
+            (width, height) in (any::<u32>(), any::<u32>()),
+        ) {
+            round_trip(&PickColorAtParams { x, y, button })?;
+            round_trip(&DefinePaletteParams { name: shape_type.clone(), colors: color.iter().cloned().collect() })?;
+            round_trip(&UsePaletteColorParams { name: shape_type.clone(), index: width as usize, button })?;
+            round_trip(&AddCustomColorParams { color: shape_type.clone(), button })?;
+            round_trip(&UseCustomColorParams { color: color.clone(), slot: font_size.map(|n| n as usize), button })?;
+            round_trip(&SetSnapParams { grid_size: font_size })?;
+            let factor = font_size.map(|size| size as f64 / 8.0);
+            round_trip(&PushTransformParams {
+                translate_x: Some(x as f64), translate_y: Some(y as f64), rotate_degrees: factor, scale_x: factor, scale_y: None,
+            })?;
+            let clip = [None, Some(false), Some(true)][(width % 3) as usize];
+            round_trip(&DrawPixelParams { x, y, color: color.clone(), button, dry_run: clip, preset: tool.clone() })?;
+            round_trip(&DrawLineParams {
+                start_x: x, start_y: y, end_x, end_y, color: color.clone(), thickness, thickness_px: thickness, button, clip, dry_run: clip, preset: tool.clone(),
+            })?;
+            round_trip(&DrawShapeParams {
+                shape_type, start_x: x, start_y: y, end_x, end_y, color: color.clone(), thickness, thickness_px: thickness, fill_type, button, clip, dry_run: clip,
+                preset: tool.clone(),
+            })?;
+            let points = points.into_iter().map(|(x, y)| Point { x, y }).collect();
+            round_trip(&PenPointParams { x, y })?;
+            round_trip(&PenStrokeParams { color: color.clone(), thickness, thickness_px: thickness, tool: tool.clone(), button, preset: tool.clone() })?;
+            round_trip(&DrawEllipseParams {
+                x, y, radius_x: width, radius_y: height, rotation_deg: factor, start_angle: factor, end_angle: None,
+                color: color.clone(), thickness, thickness_px: thickness, tool: tool.clone(), button, clip, dry_run: clip, preset: color.clone(),
+            })?;
+            round_trip(&DrawPolylineParams {
+                points, color: color.clone(), thickness, thickness_px: thickness, tool, button, clip, dry_run: clip,
+                smoothing: clip.map(|_| Smoothing::CatmullRom), simplify_tolerance: factor,
+                preset: color.clone(),
+                width_profile: clip.map(|_| WidthProfile { widths: Some(vec![width, height]), taper_in: factor, taper_out: None, min_px: thickness }),
+            })?;
+            let direction = [None, Some(GradientDirection::Horizontal), Some(GradientDirection::Vertical)][(height % 3) as usize];
+            round_trip(&DrawGradientParams {
+                x, y, width, height, start_color: color.clone().unwrap_or_default(), end_color: text_value.clone(), direction, steps: font_size, button, dry_run: clip,
+            })?;
+            let pattern = [FillPattern::Hatch, FillPattern::Crosshatch, FillPattern::Dots, FillPattern::Checker][(width % 4) as usize];
+            round_trip(&FillPatternParams {
+                pattern, x: Some(x), y: None, width: Some(width), height: None, points: None, spacing: font_size, color: color.clone(), button, dry_run: clip,
+            })?;
+            round_trip(&StampImageParams { image: text_value.clone(), positions: vec![Point { x, y }], scale: Some(width as f64 / 4.0) })?;
+            round_trip(&DrawGlyphTextParams {
+                x, y, text: text_value.clone(), font: text_value.clone(), size_px: width, fill: clip, thickness_px: font_size, color: color.clone(), button, dry_run: clip,
+            })?;
+            round_trip(&AddTextParams { x, y, text: text_value, color: color.clone(), font_name, font_size, font_style })?;
+            let unsaved_changes = [None, Some(UnsavedChanges::Save), Some(UnsavedChanges::Discard), Some(UnsavedChanges::Fail)][(width % 4) as usize];
+            round_trip(&CreateCanvasParams { width, height, background_color: color, unsaved_changes })?;
+            round_trip(&DuplicateCanvasParams { target_hwnd: Some(x as isize), unsaved_changes, track_copy: clip })?;
+            round_trip(&NewDocumentParams { unsaved_changes })?;
+            round_trip(&CloseParams { unsaved_changes, timeout_ms: Some(width as u64) })?;
+            round_trip(&GetCanvasRegionParams { x, y, width, height })?;
+            round_trip(&RotateSelectionParams { angle: width })?;
+            round_trip(&LayerIndexParams { index: width })?;
+            round_trip(&SetLayerVisibilityParams { index: height, visible: width % 2 == 0 })?;
+            let units = [None, Some(ImageUnits::Pixels), Some(ImageUnits::Inches), Some(ImageUnits::Centimeters)][(height % 4) as usize];
+            let color_mode = [None, Some(ColorMode::Color), Some(ColorMode::BlackAndWhite)][(width % 3) as usize];
+            round_trip(&SetImagePropertiesParams { width: Some(width as f64 / 64.0), height: None, units, color_mode })?;
+        }
+
+        #[test]
+        fn file_params_round_trip(path in text(), format in text(), speed in proptest::option::of(fraction()), iterations in any::<Option<u32>>()) {
+            round_trip(&SaveCanvasParams { file_path: path.clone(), format: format.clone(), overwrite: iterations.is_some(), jpeg_quality: iterations, create_dirs: iterations.is_none() })?;
+            round_trip(&FetchImageParams { file_path: path.clone(), preview_max_px: iterations })?;
+            round_trip(&StartInputRecordingParams { path: path.clone() })?;
+            round_trip(&ReplayInputParams { path: path.clone(), speed })?;
+            round_trip(&RunBenchmarkParams { iterations })?;
+            round_trip(&CalibrateParams { tools: Some(vec![format.clone()]), persist: speed.map(|_| false) })?;
+            round_trip(&RunMacroParams { source: Some(path.clone()), name: None, args: Some(json!({ "n": iterations })), dry_run: speed.map(|_| true) })?;
+            round_trip(&RegisterMacroParams { name: format.clone(), source: path.clone() })?;
+            round_trip(&CheckpointParams { label: format.clone() })?;
+            let before = [None, Some(FrameReset::Clear), Some(FrameReset::Undo)][iterations.unwrap_or(0) as usize % 3];
+            round_trip(&DrawAnimationParams {
+                frames: vec![AnimationFrame { before, steps: vec![AnimationStep { method: format.clone(), params: json!({ "path": path.clone() }) }] }],
+                frame_delay_ms: iterations.unwrap_or(0) as u64, loop_count: iterations,
+            })?;
+            round_trip(&GetAuditLogParams { client_id: Some(path.clone()), method: Some(format), since_ms: iterations.map(u64::from), errors_only: speed.is_some(), limit: iterations })?;
+        }
+
+        #[test]
+        fn responses_round_trip(
+            status in text(), version in text(), (canvas_width, canvas_height) in (any::<u32>(), any::<u32>()),
+            code in any::<i32>(), message in text(),
+        ) {
+            round_trip(&SuccessResponse { status: status.clone() })?;
+            round_trip(&ConnectResponse { status: status.clone(), paint_version: version.clone(), canvas_width, canvas_height })?;
+            round_trip(&GetVersionResponse { status: status.clone(), protocol_version: version.clone(),
+                server_version: version.clone(), paint_version: version })?;
+            round_trip(&ErrorResponse { status, error: ErrorDetails { code, message } })?;
+        }
+
+        #[test]
+        fn window_info_round_trips(
+            (hwnd, pid, class_name, title) in (any::<isize>(), any::<u32>(), text(), text()),
+            (window_rect, client_rect, canvas_rect) in (rect(), rect(), rect()),
+            (client_origin_x, client_origin_y, dpi, scale_factor) in (any::<i32>(), any::<i32>(), any::<u32>(), fraction()),
+            monitor in proptest::option::of(monitor()),
+            (is_foreground, is_minimized, is_maximized, z_order) in (any::<bool>(), any::<bool>(), any::<bool>(), any::<u32>()),
+        ) {
+            round_trip(&WindowInfoResponse { hwnd, pid, class_name, title, window_rect, client_rect, client_origin_x,
+                client_origin_y, dpi, scale_factor, monitor, is_foreground, is_minimized, is_maximized, z_order, canvas_rect })?;
+        }
+
+        // Out-of-range numbers must be rejected, never truncated into range
+        #[test]
+        fn out_of_range_integers_are_rejected(wide in any::<i64>(), fractional in 0.01..0.99f64) {
+            let (level, canvas, pixel) = (json!({ "level": wide }), json!({ "width": wide, "height": 1 }), json!({ "x": wide, "y": 0 }));
+            if wide < 0 || wide > u32::MAX as i64 {
+                prop_assert!(serde_json::from_value::<SetThicknessParams>(level).is_err(), "level {} accepted", wide);
+                prop_assert!(serde_json::from_value::<CreateCanvasParams>(canvas).is_err(), "width {} accepted", wide);
+            }
+            if wide < i32::MIN as i64 || wide > i32::MAX as i64 {
+                prop_assert!(serde_json::from_value::<DrawPixelParams>(pixel).is_err(), "x {} accepted", wide);
+            }
+            let point = json!({ "x": 1.0 + fractional, "y": 0 });
+            prop_assert!(serde_json::from_value::<Point>(point).is_err(), "x {} accepted", 1.0 + fractional);
+        }
+    }
 }
 
 // Map of method names to handler functions
@@ -264,28 +1283,185 @@ pub fn get_method_handler(method: &str) -> Option<MethodHandler> {
         "activate_window" => Some(box_handler(core::handle_activate_window)),
         "get_canvas_dimensions" => Some(box_handler(core::handle_get_canvas_dimensions)),
         "disconnect" => Some(box_handler(core::handle_disconnect)),
+        "shutdown" => Some(box_handler(core::handle_shutdown)),
         "get_version" => Some(box_handler(core::handle_get_version)),
+        "get_supported_tools" => Some(box_handler(core::handle_get_supported_tools)),
+        "get_window_info" => Some(box_handler(core::handle_get_window_info)),
+        "ensure_window_on_monitor" => Some(box_handler(core::handle_ensure_window_on_monitor)),
+        "set_dedicated_desktop" => Some(box_handler(core::handle_set_dedicated_desktop)),
+        "logging/setLevel" => Some(box_handler(core::handle_set_log_level)),
+        "ping" => Some(box_handler(core::handle_ping)),
+        "tools/list" => Some(box_handler(core::handle_tools_list)),
+        "tools/call" => Some(box_handler(core::handle_tools_call)),
+        "get_api_schema" => Some(box_handler(core::handle_get_api_schema)),
+        "resources/list" => Some(box_handler(core::handle_resources_list)),
+        "resources/read" => Some(box_handler(core::handle_resources_read)),
+        "resources/subscribe" => Some(box_handler(core::handle_resources_subscribe)),
+        "resources/unsubscribe" => Some(box_handler(core::handle_resources_unsubscribe)),
+        // Read-back operations
+        "export_canvas" => Some(box_handler(core::handle_export_canvas)),
+        "capture_window" => Some(box_handler(core::handle_capture_window)),
+        "get_canvas_region" => Some(box_handler(core::handle_get_canvas_region)),
+        "export_region" => Some(box_handler(core::handle_export_region)),
+        // Input record/replay
+        "start_input_recording" => Some(box_handler(core::handle_start_input_recording)),
+        "stop_input_recording" => Some(box_handler(core::handle_stop_input_recording)),
+        "replay_input" => Some(box_handler(core::handle_replay_input)),
+        "run_benchmark" => Some(box_handler(core::handle_run_benchmark)),
+        "get_audit_log" => Some(box_handler(core::handle_get_audit_log)),
+        "set_log_level" => Some(box_handler(core::handle_set_server_log_level)),
+        "self_test" => Some(box_handler(core::handle_self_test)),
+        "calibrate" => Some(box_handler(core::handle_calibrate)),
+        // Macros
+        "run_macro" => Some(box_handler(core::handle_run_macro)),
+        "draw_animation" => Some(box_handler(core::handle_draw_animation)),
+        "checkpoint" => Some(box_handler(core::handle_checkpoint)),
+        "revert_to_checkpoint" => Some(box_handler(core::handle_revert_to_checkpoint)),
+        "register_macro" => Some(box_handler(core::handle_register_macro)),
         // Drawing commands
         "draw_pixel" => Some(box_handler(core::handle_draw_pixel)),
         "draw_line" => Some(box_handler(core::handle_draw_line)),
         "draw_shape" => Some(box_handler(core::handle_draw_shape)),
         "draw_polyline" => Some(box_handler(core::handle_draw_polyline)),
+        "draw_ellipse" => Some(box_handler(core::handle_draw_ellipse)),
+        "draw_gradient" => Some(box_handler(core::handle_draw_gradient)),
+        "fill_pattern" => Some(box_handler(core::handle_fill_pattern)),
+        "draw_glyph_text" => Some(box_handler(core::handle_draw_glyph_text)),
+        "pen_move_to" => Some(box_handler(core::handle_pen_move_to)),
+        "pen_line_to" => Some(box_handler(core::handle_pen_line_to)),
+        "pen_close" => Some(box_handler(core::handle_pen_close)),
+        "pen_stroke" => Some(box_handler(core::handle_pen_stroke)),
         // Text operations
         "add_text" => Some(box_handler(core::handle_add_text)),
         // Selection operations
         "select_region" => Some(box_handler(core::handle_select_region)),
         "copy_selection" => Some(box_handler(core::handle_copy_selection)),
         "paste" => Some(box_handler(core::handle_paste)),
+        "stamp_image" => Some(box_handler(core::handle_stamp_image)),
+        "rotate_selection" => Some(box_handler(core::handle_rotate_selection)),
+        "flip_selection" => Some(box_handler(core::handle_flip_selection)),
+        "invert_colors" => Some(box_handler(core::handle_invert_colors)),
         // Canvas operations
         "clear_canvas" => Some(box_handler(core::handle_clear_canvas)),
+        "new_document" => Some(box_handler(core::handle_new_document)),
+        "close_document" => Some(box_handler(core::handle_close_document)),
+        "quit_paint" => Some(box_handler(core::handle_quit_paint)),
         "create_canvas" => Some(box_handler(core::handle_create_canvas)),
+        "duplicate_canvas" => Some(box_handler(core::handle_duplicate_canvas)),
+        "set_image_properties" => Some(box_handler(core::handle_set_image_properties)),
+        "set_background" => Some(box_handler(core::handle_set_background)),
+        "save_canvas" => Some(box_handler(core::handle_save_canvas)),
+        "save_as" => Some(box_handler(core::handle_save_as)),
+        "get_document_info" => Some(box_handler(core::handle_get_document_info)),
+        "fetch_image" => Some(box_handler(core::handle_fetch_image)),
+        // Layers
+        "add_layer" => Some(box_handler(core::handle_add_layer)),
+        "select_layer" => Some(box_handler(core::handle_select_layer)),
+        "set_layer_visibility" => Some(box_handler(core::handle_set_layer_visibility)),
+        "merge_layers" => Some(box_handler(core::handle_merge_layers)),
+        "delete_layer" => Some(box_handler(core::handle_delete_layer)),
         // Tool settings
         "select_tool" => Some(box_handler(core::handle_select_tool)),
         "set_color" => Some(box_handler(core::handle_set_color)),
+        "set_secondary_color" => Some(box_handler(core::handle_set_secondary_color)),
+        "pick_color_at" => Some(box_handler(core::handle_pick_color_at)),
+        "add_custom_color" => Some(box_handler(core::handle_add_custom_color)),
+        "use_custom_color" => Some(box_handler(core::handle_use_custom_color)),
+        "define_palette" => Some(box_handler(core::handle_define_palette)),
+        "use_palette_color" => Some(box_handler(core::handle_use_palette_color)),
         "set_thickness" => Some(box_handler(core::handle_set_thickness)),
         "set_brush_size" => Some(box_handler(core::handle_set_brush_size)),
         "set_fill" => Some(box_handler(core::handle_set_fill)),
+        "set_tool_state" => Some(box_handler(core::handle_set_tool_state)),
+        "set_snap" => Some(box_handler(core::handle_set_snap)),
+        "push_transform" => Some(box_handler(core::handle_push_transform)),
+        "pop_transform" => Some(box_handler(core::handle_pop_transform)),
         // Unknown method
         _ => None,
     }
-} 
\ No newline at end of file
+} 
+// === Schemas ===
+
+/// JSON Schema of the params `method` deserializes, generated from its
+/// protocol struct. None for methods that take no params.
+pub fn params_schema(method: &str) -> Option<Value> {
+    let schema = match method {
+        "connect" => schema_of::<ConnectParams>(),
+        "ensure_window_on_monitor" => schema_of::<EnsureWindowOnMonitorParams>(),
+        "set_dedicated_desktop" => schema_of::<SetDedicatedDesktopParams>(),
+        "draw_pixel" | "paste" => schema_of::<DrawPixelParams>(),
+        "draw_line" | "select_region" => schema_of::<DrawLineParams>(),
+        "draw_shape" => schema_of::<DrawShapeParams>(),
+        "draw_polyline" => schema_of::<DrawPolylineParams>(),
+        "draw_ellipse" => schema_of::<DrawEllipseParams>(),
+        "draw_gradient" => schema_of::<DrawGradientParams>(),
+        "fill_pattern" => schema_of::<FillPatternParams>(),
+        "draw_glyph_text" => schema_of::<DrawGlyphTextParams>(),
+        "add_text" => schema_of::<AddTextParams>(),
+        "stamp_image" => schema_of::<StampImageParams>(),
+        "rotate_selection" => schema_of::<RotateSelectionParams>(),
+        "flip_selection" => schema_of::<FlipSelectionParams>(),
+        "invert_colors" => schema_of::<InvertColorsParams>(),
+        "new_document" => schema_of::<NewDocumentParams>(),
+        "close_document" | "quit_paint" => schema_of::<CloseParams>(),
+        "create_canvas" => schema_of::<CreateCanvasParams>(),
+        "duplicate_canvas" => schema_of::<DuplicateCanvasParams>(),
+        "set_image_properties" => schema_of::<SetImagePropertiesParams>(),
+        "set_background" => schema_of::<SetBackgroundParams>(),
+        "save_canvas" | "save_as" => schema_of::<SaveCanvasParams>(),
+        "fetch_image" => schema_of::<FetchImageParams>(),
+        "select_tool" => schema_of::<SelectToolParams>(),
+        "set_color" => schema_of::<SetColorParams>(),
+        "set_secondary_color" => schema_of::<SetSecondaryColorParams>(),
+        "pick_color_at" => schema_of::<PickColorAtParams>(),
+        "set_thickness" => schema_of::<SetThicknessParams>(),
+        "set_brush_size" => schema_of::<SetBrushSizeParams>(),
+        "set_fill" => schema_of::<SetFillParams>(),
+        "set_tool_state" => schema_of::<SetToolStateParams>(),
+        "define_palette" => schema_of::<DefinePaletteParams>(),
+        "use_palette_color" => schema_of::<UsePaletteColorParams>(),
+        "add_custom_color" => schema_of::<AddCustomColorParams>(),
+        "use_custom_color" => schema_of::<UseCustomColorParams>(),
+        "set_snap" => schema_of::<SetSnapParams>(),
+        "push_transform" => schema_of::<PushTransformParams>(),
+        "pen_move_to" | "pen_line_to" => schema_of::<PenPointParams>(),
+        "pen_stroke" => schema_of::<PenStrokeParams>(),
+        "select_layer" | "merge_layers" | "delete_layer" => schema_of::<LayerIndexParams>(),
+        "set_layer_visibility" => schema_of::<SetLayerVisibilityParams>(),
+        "get_canvas_region" | "export_region" => schema_of::<GetCanvasRegionParams>(),
+        "start_input_recording" => schema_of::<StartInputRecordingParams>(),
+        "replay_input" => schema_of::<ReplayInputParams>(),
+        "run_macro" => schema_of::<RunMacroParams>(),
+        "register_macro" => schema_of::<RegisterMacroParams>(),
+        "draw_animation" => schema_of::<DrawAnimationParams>(),
+        "checkpoint" | "revert_to_checkpoint" => schema_of::<CheckpointParams>(),
+        "run_benchmark" => schema_of::<RunBenchmarkParams>(),
+        "calibrate" => schema_of::<CalibrateParams>(),
+        "get_audit_log" => schema_of::<GetAuditLogParams>(),
+        "set_log_level" => schema_of::<SetServerLogLevelParams>(),
+        _ => return None,
+    };
+    Some(schema)
+}
+
+/// JSON Schema of the result `method` returns, for the methods whose result
+/// is a protocol struct.
+pub fn result_schema(method: &str) -> Option<Value> {
+    match method {
+        "get_canvas_dimensions" => Some(schema_of::<CanvasDimensionsResponse>()),
+        "get_window_info" => Some(schema_of::<WindowInfoResponse>()),
+        _ => None,
+    }
+}
+
+// Self-contained schema of `T`, with nested structs written out in place
+fn schema_of<T: JsonSchema>() -> Value {
+    let generator = schemars::generate::SchemaSettings::draft2020_12()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator();
+    let mut schema = generator.into_root_schema_for::<T>().to_value();
+    if let Some(schema) = schema.as_object_mut() {
+        schema.remove("$schema");
+    }
+    schema
+}
@@ -0,0 +1,170 @@
+// Session rate limits.
+//
+// An agent stuck in a loop can keep the user's mouse and keyboard busy
+// indefinitely. The limiter keeps a sliding one-minute window of the
+// operations the session ran and the input events they synthesized, and
+// refuses new requests once either budget is spent, with a hint of how long
+// until the oldest entries leave the window. The server talks to one client
+// over stdio, so there is one budget for the whole process: the id a client
+// gives at connect is its own choice, and connecting again under a new one
+// must not buy a fresh budget.
+
+use crate::config::RateLimitConfig;
+use crate::error::{MspMcpError, Result};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Length of the sliding window the limits apply to
+pub const WINDOW: Duration = Duration::from_secs(60);
+
+// Requests that never count against a budget: the session lifecycle, ping,
+// and tools/call, whose inner method is counted when it runs
+const EXEMPT_METHODS: &[&str] = &["initialize", "connect", "disconnect", "shutdown", "exit", "ping", "tools/call"];
+
+#[derive(Default)]
+struct Usage {
+    ops: VecDeque<Instant>,
+    input_events: VecDeque<(Instant, u64)>,
+}
+
+impl Usage {
+    fn expire(&mut self, now: Instant) {
+        while self.ops.front().is_some_and(|t| now - *t >= WINDOW) {
+            self.ops.pop_front();
+        }
+        while self.input_events.front().is_some_and(|(t, _)| now - *t >= WINDOW) {
+            self.input_events.pop_front();
+        }
+    }
+}
+
+pub struct RateLimiter {
+    limits: Mutex<RateLimitConfig>,
+    usage: Mutex<Usage>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimitConfig) -> Self {
+        RateLimiter { limits: Mutex::new(limits), usage: Mutex::new(Usage::default()) }
+    }
+
+    /// Replaces the limits; what the session already used still counts.
+    pub fn set_limits(&self, limits: RateLimitConfig) {
+        if let Ok(mut current) = self.limits.lock() {
+            *current = limits;
+        }
+    }
+
+    /// Counts one call of `method`, or fails with RateLimited if the session
+    /// is out of operations, or out of input events and the method would
+    /// send more.
+    pub fn check(&self, method: &str, sends_input: bool) -> Result<()> {
+        if EXEMPT_METHODS.contains(&method) {
+            return Ok(());
+        }
+        let now = crate::clock::now();
+        let limits = self.limits.lock()
+            .map_err(|_| MspMcpError::General("Failed to lock rate limits".to_string()))?
+            .clone();
+        let mut usage = self.usage.lock()
+            .map_err(|_| MspMcpError::General("Failed to lock rate limit state".to_string()))?;
+        usage.expire(now);
+
+        let op_limit = limits.ops_per_minute;
+        if op_limit > 0 && usage.ops.len() >= op_limit as usize {
+            let oldest = usage.ops[usage.ops.len() - op_limit as usize];
+            return Err(rate_limited(format!("The session ran {} operations in the last minute", usage.ops.len()), now, oldest));
+        }
+
+        let event_limit = limits.input_events_per_minute as u64;
+        let mut events: u64 = usage.input_events.iter().map(|(_, n)| n).sum();
+        if sends_input && event_limit > 0 && events >= event_limit {
+            // Wait until enough old events leave the window to get under the limit
+            let total = events;
+            let mut until = now;
+            for (t, n) in &usage.input_events {
+                events -= n;
+                if events < event_limit {
+                    until = *t;
+                    break;
+                }
+            }
+            return Err(rate_limited(format!("The session sent {} input events in the last minute", total), now, until));
+        }
+
+        usage.ops.push_back(now);
+        Ok(())
+    }
+
+    /// Adds input events `method` synthesized.
+    pub fn record_input_events(&self, method: &str, count: u64) {
+        if count == 0 || EXEMPT_METHODS.contains(&method) {
+            return;
+        }
+        if let Ok(mut usage) = self.usage.lock() {
+            usage.input_events.push_back((crate::clock::now(), count));
+        }
+    }
+}
+
+// RateLimited error saying when the entry made at `oldest` leaves the window
+fn rate_limited(message: String, now: Instant, oldest: Instant) -> MspMcpError {
+    let retry_after = WINDOW.saturating_sub(now - oldest);
+    MspMcpError::RateLimited { message, retry_after_ms: retry_after.as_millis() as u64 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use std::sync::Arc;
+
+    fn retry_after_ms(result: Result<()>) -> u64 {
+        match result {
+            Err(MspMcpError::RateLimited { retry_after_ms, .. }) => retry_after_ms,
+            other => panic!("expected RateLimited, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ops_per_minute() {
+        let clock = Arc::new(VirtualClock::new());
+        let _guard = crate::clock::install(clock.clone());
+        let limiter = RateLimiter::new(RateLimitConfig { ops_per_minute: 2, input_events_per_minute: 0 });
+
+        limiter.check("draw_line", true).unwrap();
+        clock.advance(Duration::from_secs(20));
+        limiter.check("get_version", false).unwrap();
+        assert_eq!(retry_after_ms(limiter.check("draw_line", true)), 40_000);
+        // A reloaded config raises the limit for operations already counted
+        limiter.set_limits(RateLimitConfig { ops_per_minute: 3, input_events_per_minute: 0 });
+        limiter.check("draw_line", true).unwrap();
+
+        // Lifecycle methods are unaffected
+        limiter.check("connect", false).unwrap();
+        limiter.check("shutdown", false).unwrap();
+
+        clock.advance(Duration::from_secs(40));
+        limiter.check("draw_line", true).unwrap();
+    }
+
+    #[test]
+    fn test_input_events_per_minute() {
+        let clock = Arc::new(VirtualClock::new());
+        let _guard = crate::clock::install(clock.clone());
+        let limiter = RateLimiter::new(RateLimitConfig { ops_per_minute: 0, input_events_per_minute: 100 });
+
+        limiter.check("draw_polyline", true).unwrap();
+        limiter.record_input_events("draw_polyline", 60);
+        clock.advance(Duration::from_secs(30));
+        limiter.check("draw_polyline", true).unwrap();
+        limiter.record_input_events("draw_polyline", 50);
+
+        // Reads still work; input waits until the first 60 events expire
+        limiter.check("get_canvas_dimensions", false).unwrap();
+        assert_eq!(retry_after_ms(limiter.check("draw_line", true)), 30_000);
+        clock.advance(Duration::from_secs(30));
+        limiter.check("draw_line", true).unwrap();
+    }
+}
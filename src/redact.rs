@@ -0,0 +1,93 @@
+// Keeping request payloads readable in the logs.
+//
+// Image methods carry megabytes of base64 in their params, and logging
+// requests as they are buries the log (and stderr) under it. Before a
+// request is logged, every string in it longer than `log_max_field_len`
+// characters is cut down to that many, followed by the original length.
+
+use serde_json::Value;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static MAX_FIELD_LEN: AtomicUsize = AtomicUsize::new(crate::config::DEFAULT_LOG_MAX_FIELD_LEN);
+
+/// Sets how many characters of a string are logged; 0 logs them in full.
+pub fn set_max_field_len(len: usize) {
+    MAX_FIELD_LEN.store(len, Ordering::Relaxed);
+}
+
+pub fn max_field_len() -> usize {
+    MAX_FIELD_LEN.load(Ordering::Relaxed)
+}
+
+/// `text` cut to its first `max` characters, with a note of its length.
+pub fn truncate_str(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((end, _)) if max > 0 => format!("{}... ({} chars)", &text[..end], text.chars().count()),
+        _ => text.to_string(),
+    }
+}
+
+/// A copy of `value` with every string longer than `max` characters cut down.
+pub fn truncate_strings(value: &Value, max: usize) -> Value {
+    match value {
+        Value::String(s) => Value::String(truncate_str(s, max)),
+        Value::Array(items) => Value::Array(items.iter().map(|v| truncate_strings(v, max)).collect()),
+        Value::Object(fields) => Value::Object(fields.iter().map(|(k, v)| (k.clone(), truncate_strings(v, max))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Displays request params as JSON with long strings cut down.
+pub struct Redacted<'a>(pub Option<&'a Value>);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(value) => write!(f, "{}", truncate_strings(value, max_field_len())),
+            None => write!(f, "none"),
+        }
+    }
+}
+
+/// Displays a raw message: its JSON with long strings cut down, or if it
+/// isn't JSON, the text itself cut down.
+pub struct RedactedText<'a>(pub &'a str);
+
+impl fmt::Display for RedactedText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match serde_json::from_str::<Value>(self.0) {
+            Ok(value) => Redacted(Some(&value)).fmt(f),
+            Err(_) => write!(f, "{}", truncate_str(self.0, max_field_len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_truncate_long_strings() {
+        assert_eq!(truncate_str("abcdef", 3), "abc... (6 chars)");
+        assert_eq!(truncate_str("abc", 3), "abc");
+        assert_eq!(truncate_str("abcdef", 0), "abcdef");
+        // Cuts on character boundaries
+        assert_eq!(truncate_str("ééééé", 2), "éé... (5 chars)");
+
+        let params = json!({"image": "A".repeat(5000), "x": 10, "points": [{"label": "B".repeat(10)}]});
+        assert_eq!(truncate_strings(&params, 4), json!({
+            "image": "AAAA... (5000 chars)", "x": 10, "points": [{"label": "BBBB... (10 chars)"}],
+        }));
+    }
+
+    #[test]
+    fn test_redacted_text() {
+        let line = format!(r#"{{"method":"paste_image","params":{{"data":"{}"}}}}"#, "A".repeat(1000));
+        let logged = RedactedText(&line).to_string();
+        assert!(logged.len() < line.len());
+        assert!(logged.contains("paste_image") && logged.contains("(1000 chars)"));
+        assert_eq!(Redacted(None).to_string(), "none");
+    }
+}
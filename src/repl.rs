@@ -0,0 +1,308 @@
+// Interactive prompt (`--repl`) and script files (`--script`).
+//
+// Reads one short command per line, such as `line 10 10 200 200 #FF0000`,
+// `tool brush` or `save out.png`, turns it into the request a client would
+// send and prints the result. Any method can also be called directly as
+// `<method> [<params JSON>]`. Meant for trying things out and reproducing
+// bugs without hand-writing JSON-RPC; requests go through the normal request
+// path, and Paint is connected the first time a command needs it.
+//
+// A script runs a file of the same commands, or of JSON-RPC requests, one
+// per line, and reports each line and a summary, so a drawing can be
+// reproduced or a demo run without any client.
+
+use crate::error::{MspMcpError, Result};
+use crate::PaintServerState;
+use serde_json::{json, Map, Value};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use tracing::info;
+
+const PROMPT: &str = "paint> ";
+
+const HELP: &str = "\
+Commands (positions in canvas pixels, colors as #RRGGBB):
+  pixel X Y [COLOR]
+  line X1 Y1 X2 Y2 [COLOR]
+  shape TYPE X1 Y1 X2 Y2 [COLOR]     rectangle, ellipse, line, arrow, triangle, pentagon, hexagon
+  poly X1 Y1 X2 Y2 ... [COLOR]
+  text X Y WORDS...
+  select X1 Y1 X2 Y2
+  tool NAME | color COLOR | fill TYPE | thickness PX
+  save PATH                          format from the extension
+  clear
+  METHOD [PARAMS JSON]               any method, e.g. set_snap {\"grid_size\": 10}
+  help | quit";
+
+/// Turns a command line into a method and its params. None for blank lines
+/// and `#` comments.
+pub fn parse_command(line: &str) -> Result<Option<(String, Option<Value>)>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let mut args: Vec<&str> = rest.split_whitespace().collect();
+    // A color goes last
+    let color = args.last().copied().filter(|arg| arg.starts_with('#'));
+    if color.is_some() && matches!(word, "pixel" | "line" | "shape" | "poly") {
+        args.pop();
+    }
+    let with_color = |mut params: Value| {
+        if let Some(color) = color {
+            params["color"] = json!(color);
+        }
+        Some(params)
+    };
+
+    let request = match word {
+        "pixel" => {
+            let [x, y] = fixed(word, &args)?;
+            ("draw_pixel", with_color(json!({"x": x, "y": y})))
+        }
+        "line" => {
+            let [start_x, start_y, end_x, end_y] = fixed(word, &args)?;
+            ("draw_line", with_color(json!({"start_x": start_x, "start_y": start_y, "end_x": end_x, "end_y": end_y})))
+        }
+        "shape" => {
+            let (shape_type, corners) = args.split_first().ok_or_else(|| usage(word))?;
+            let [start_x, start_y, end_x, end_y] = fixed(word, corners)?;
+            ("draw_shape", with_color(json!({
+                "shape_type": shape_type, "start_x": start_x, "start_y": start_y, "end_x": end_x, "end_y": end_y,
+            })))
+        }
+        "poly" => {
+            let numbers = numbers(word, &args)?;
+            if numbers.len() < 4 || numbers.len() % 2 != 0 {
+                return Err(usage(word));
+            }
+            let points: Vec<Value> = numbers.chunks(2).map(|p| json!({"x": p[0], "y": p[1]})).collect();
+            ("draw_polyline", with_color(json!({"points": points})))
+        }
+        "text" => {
+            // The text is everything after the position, spacing and all
+            let mut parts = rest.splitn(3, char::is_whitespace);
+            let position: Vec<&str> = parts.by_ref().take(2).collect();
+            let [x, y] = fixed(word, &position)?;
+            let text = parts.next().map(str::trim_start).filter(|text| !text.is_empty()).ok_or_else(|| usage(word))?;
+            ("add_text", Some(json!({"x": x, "y": y, "text": text})))
+        }
+        "select" => {
+            let [start_x, start_y, end_x, end_y] = fixed(word, &args)?;
+            ("select_region", Some(json!({"start_x": start_x, "start_y": start_y, "end_x": end_x, "end_y": end_y})))
+        }
+        "tool" => ("select_tool", Some(json!({"tool": single(word, &args)?}))),
+        "color" => ("set_color", Some(json!({"color": single(word, &args)?}))),
+        "fill" => ("set_fill", Some(json!({"fill_type": single(word, &args)?}))),
+        "thickness" => {
+            let [px] = fixed(word, &args)?;
+            ("set_tool_state", Some(json!({"thickness_px": px})))
+        }
+        "save" => {
+            let path = single(word, &args)?;
+            let format = path.rsplit_once('.').map(|(_, extension)| extension.to_lowercase()).unwrap_or_default();
+            ("save_canvas", Some(json!({"file_path": path, "format": format})))
+        }
+        "clear" => ("clear_canvas", None),
+        method if rest.is_empty() => (method, None),
+        method => {
+            let params: Value = serde_json::from_str(rest).map_err(|e| MspMcpError::InvalidParameters(
+                format!("Params for {} must be JSON: {}", method, e)))?;
+            (method, Some(params))
+        }
+    };
+    Ok(Some((request.0.to_string(), request.1)))
+}
+
+// The arguments of a command as whole numbers, or a usage error
+fn numbers(command: &str, args: &[&str]) -> Result<Vec<i64>> {
+    args.iter().map(|arg| arg.parse().map_err(|_| usage(command))).collect()
+}
+
+// Exactly N whole numbers, or a usage error
+fn fixed<const N: usize>(command: &str, args: &[&str]) -> Result<[i64; N]> {
+    numbers(command, args)?.try_into().map_err(|_| usage(command))
+}
+
+// Exactly one argument, or a usage error
+fn single<'a>(command: &str, args: &[&'a str]) -> Result<&'a str> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(usage(command)),
+    }
+}
+
+fn usage(command: &str) -> MspMcpError {
+    let line = HELP.lines().find(|line| line.trim_start().split([' ', '|']).any(|word| word == command));
+    MspMcpError::InvalidParameters(format!("Usage: {}", line.map(str::trim).unwrap_or(command)))
+}
+
+/// Handles a request, connecting to Paint (launching it if needed) and
+/// trying again if it needs a Paint window and none is connected yet.
+pub async fn handle_connecting(state: &PaintServerState, id: Option<&Value>, request_id: &str, method: &str, params: Option<Value>) -> Result<Value> {
+    match state.handle_request_with_id(id, request_id, method, params.clone()).await {
+        Err(MspMcpError::WindowNotFound) => {
+            info!("'{}' needs Paint; connecting first", method);
+            let connect = json!({"client_id": "paint-cli", "client_name": "paint-cli"});
+            state.handle_request("connect", Some(connect)).await?;
+            state.handle_request_with_id(id, request_id, method, params).await
+        }
+        result => result,
+    }
+}
+
+/// Runs the prompt on stdin and stdout until `quit` or the end of input.
+/// Returns the process exit code.
+pub async fn run(state: &PaintServerState) -> i32 {
+    if let Err(e) = state.handle_request("initialize", None).await {
+        println!("error: initialize failed: {}", e);
+        return 1;
+    }
+    println!("Paint REPL; type help for commands, quit to leave");
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{}", PROMPT);
+        // The prompt is only cosmetic
+        let _ = io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else { break };
+        match line.trim() {
+            "quit" | "exit" => break,
+            "help" => {
+                println!("{}", HELP);
+                continue;
+            }
+            _ => {}
+        }
+        let (method, params) = match parse_command(&line) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("error: {}", e);
+                continue;
+            }
+        };
+        let request_id = crate::protocol::next_request_id();
+        match handle_connecting(state, None, &request_id, &method, params).await {
+            Ok(response) => println!("{}", pretty(response.get("result").unwrap_or(&response))),
+            Err(e) => println!("error {}: {}", e.code(), e),
+        }
+    }
+    0
+}
+
+/// Turns a script line into a method and its params: a JSON-RPC request if
+/// it starts with `{`, a command otherwise. None for blank lines and comments.
+pub fn parse_script_line(line: &str) -> Result<Option<(String, Option<Value>)>> {
+    if !line.trim_start().starts_with('{') {
+        return parse_command(line);
+    }
+    let request = crate::jsonrpc::parse_json_rpc_request(line).map_err(|e| MspMcpError::InvalidParameters(format!("Invalid JSON: {}", e)))?;
+    let (method, params, _) = crate::jsonrpc::extract_method_and_params(&request)
+        .map_err(|(_, message)| MspMcpError::InvalidParameters(format!("Invalid request: {}", message)))?;
+    Ok(Some((method, params)))
+}
+
+/// Runs the script at `path` line by line, printing `OK` or `FAIL` for each
+/// request and a summary at the end. With `exit_on_error` the script stops
+/// at the first failure. Returns the process exit code: 1 if any request failed.
+pub async fn run_script(state: &PaintServerState, path: &Path, exit_on_error: bool) -> i32 {
+    let script = match std::fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(e) => {
+            println!("FAIL reading {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+    if let Err(e) = state.handle_request("initialize", None).await {
+        println!("FAIL initialize: {}", e);
+        return 1;
+    }
+    info!("Running script {}", path.display());
+
+    let (mut passed, mut failed) = (0, 0);
+    for (index, line) in script.lines().enumerate() {
+        let number = index + 1;
+        let result = match parse_script_line(line) {
+            Ok(None) => continue,
+            Ok(Some((method, params))) => {
+                let request_id = crate::protocol::next_request_id();
+                handle_connecting(state, None, &request_id, &method, params).await.map(|_| method)
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(method) => {
+                println!("OK   line {}: {}", number, method);
+                passed += 1;
+            }
+            Err(e) => {
+                println!("FAIL line {}: {}: {}", number, line.trim(), e);
+                failed += 1;
+                if exit_on_error {
+                    println!("Stopped at line {}", number);
+                    break;
+                }
+            }
+        }
+    }
+
+    println!("{} of {} requests succeeded", passed, passed + failed);
+    if failed == 0 { 0 } else { 1 }
+}
+
+// Pretty-printed JSON, with long data strings (images) cut short
+fn pretty(value: &Value) -> String {
+    fn shorten(value: &Value) -> Value {
+        match value {
+            Value::String(text) if text.len() > 200 => json!(format!("{}... ({} bytes)", text.chars().take(80).collect::<String>(), text.len())),
+            Value::Array(items) => Value::Array(items.iter().map(shorten).collect()),
+            Value::Object(fields) => Value::Object(fields.iter().map(|(k, v)| (k.clone(), shorten(v))).collect::<Map<_, _>>()),
+            other => other.clone(),
+        }
+    }
+    serde_json::to_string_pretty(&shorten(value)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_become_requests() {
+        let (method, params) = parse_command("line 10 10 200 200 #FF0000").unwrap().unwrap();
+        assert_eq!(method, "draw_line");
+        assert_eq!(params, Some(json!({"start_x": 10, "start_y": 10, "end_x": 200, "end_y": 200, "color": "#FF0000"})));
+        let (method, params) = parse_command("  poly 0 0 5 5 10 0").unwrap().unwrap();
+        assert_eq!(method, "draw_polyline");
+        assert_eq!(params.unwrap()["points"], json!([{"x": 0, "y": 0}, {"x": 5, "y": 5}, {"x": 10, "y": 0}]));
+        assert_eq!(parse_command("tool brush").unwrap(), Some(("select_tool".to_string(), Some(json!({"tool": "brush"})))));
+        assert_eq!(parse_command("save out.PNG").unwrap().unwrap().1, Some(json!({"file_path": "out.PNG", "format": "png"})));
+        assert_eq!(parse_command("text 5 6 hello  #1").unwrap().unwrap().1, Some(json!({"x": 5, "y": 6, "text": "hello  #1"})));
+        assert_eq!(parse_command("shape ellipse 1 2 3 4").unwrap().unwrap().1.unwrap()["shape_type"], "ellipse");
+
+        // Any method, with or without JSON params
+        assert_eq!(parse_command("get_version").unwrap(), Some(("get_version".to_string(), None)));
+        assert_eq!(parse_command("set_snap {\"grid_size\": 10}").unwrap().unwrap().1, Some(json!({"grid_size": 10})));
+        assert_eq!(parse_command("# a comment").unwrap(), None);
+        assert_eq!(parse_command("   ").unwrap(), None);
+
+        assert!(parse_command("line 10 10 200").is_err());
+        assert!(parse_command("line 10 ten 200 200").is_err());
+        assert!(parse_command("poly 0 0 5").is_err());
+        assert!(parse_command("tool").is_err());
+        assert!(parse_command("set_snap grid").is_err());
+    }
+
+    #[test]
+    fn test_script_lines() {
+        let request = r##"{"jsonrpc": "2.0", "id": 3, "method": "set_color", "params": {"color": "#00FF00"}}"##;
+        assert_eq!(parse_script_line(request).unwrap(), Some(("set_color".to_string(), Some(json!({"color": "#00FF00"})))));
+        assert_eq!(parse_script_line("clear").unwrap(), Some(("clear_canvas".to_string(), None)));
+        assert_eq!(parse_script_line("# setup").unwrap(), None);
+        assert!(parse_script_line(r#"{"jsonrpc": "2.0", "id": 1}"#).is_err());
+        assert!(parse_script_line("{not json").is_err());
+    }
+}
@@ -0,0 +1,40 @@
+// MCP resources exposed by the server.
+//
+// `paint://canvas` is the current canvas as a PNG. Clients can subscribe to
+// it and get `notifications/resources/updated` after every operation that
+// changes the canvas.
+
+use crate::error::{MspMcpError, Result};
+use serde_json::{json, Value};
+
+pub const CANVAS_URI: &str = "paint://canvas";
+
+/// Resource descriptors for `resources/list`.
+pub fn resource_list() -> Vec<Value> {
+    vec![json!({
+        "uri": CANVAS_URI,
+        "name": "Paint canvas",
+        "description": "The current Paint canvas as a PNG image",
+        "mimeType": crate::capture::PNG_MIME_TYPE
+    })]
+}
+
+/// Fails with ResourceNotFound unless `uri` is one of ours.
+pub fn check_known_uri(uri: &str) -> Result<()> {
+    if resource_list().iter().any(|r| r["uri"] == uri) {
+        Ok(())
+    } else {
+        Err(MspMcpError::ResourceNotFound(uri.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_uris() {
+        assert!(check_known_uri(CANVAS_URI).is_ok());
+        assert_eq!(check_known_uri("paint://nope").unwrap_err().json_rpc_code(), -32002);
+    }
+}
@@ -0,0 +1,439 @@
+// Debug screenshot trail.
+//
+// "It drew in the wrong place" is hard to act on without the screen the
+// user saw. With `[screenshot_trail]` enabled, ScreenshotTrailBackend wraps
+// the real backend and saves a numbered PNG of the Paint window after every
+// automation step (click, stroke, tool or color change, dialog) into a
+// folder for this server session. Steps that fail get a `-failed` suffix.
+// Old session folders, and the oldest screenshots of a long session, are
+// deleted so the trail can be left on without filling the disk.
+
+use crate::backend::PaintBackend;
+use crate::capture::CapturedImage;
+use crate::config::ScreenshotTrailConfig;
+use crate::error::Result;
+use crate::input_record::RecordedInput;
+use crate::protocol::{AddTextParams, CanvasDimensionsResponse, MonitorInfo, MouseButton, PaintCapabilities, SaveCanvasParams, SetImagePropertiesParams, SetToolStateParams, UnsavedChanges, WindowInfoResponse};
+use tracing::{debug, info, warn};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::backend::HWND;
+
+// Prefix of the per-session folders, followed by the start time and PID
+const SESSION_PREFIX: &str = "session-";
+
+/// Where session folders go unless the config names a directory.
+pub fn default_dir() -> PathBuf {
+    std::env::temp_dir().join("mcp_paint_screenshots")
+}
+
+#[derive(Default)]
+struct Trail {
+    step: u32,
+    saved: VecDeque<PathBuf>,
+    last_hwnd: Option<HWND>, // For steps that don't name a window (typing, replay)
+}
+
+pub struct ScreenshotTrailBackend {
+    inner: Arc<dyn PaintBackend>,
+    session_dir: PathBuf,
+    max_screenshots: usize,
+    trail: Mutex<Trail>,
+}
+
+impl ScreenshotTrailBackend {
+    /// Creates this session's folder, deleting the oldest sessions beyond
+    /// `max_sessions`.
+    pub fn new(inner: Arc<dyn PaintBackend>, config: &ScreenshotTrailConfig) -> Result<Self> {
+        let root = config.dir.clone().unwrap_or_else(default_dir);
+        std::fs::create_dir_all(&root)?;
+        if config.max_sessions > 0 {
+            prune_sessions(&root, config.max_sessions - 1)?; // Leaves room for this one
+        }
+        let session_dir = root.join(format!("{}{}-{}", SESSION_PREFIX, crate::audit::unix_time_ms(), std::process::id()));
+        std::fs::create_dir_all(&session_dir)?;
+        info!("Saving a screenshot after every automation step to {}", session_dir.display());
+        Ok(ScreenshotTrailBackend {
+            inner,
+            session_dir,
+            max_screenshots: config.max_screenshots,
+            trail: Mutex::new(Trail::default()),
+        })
+    }
+
+    pub fn session_dir(&self) -> &Path {
+        &self.session_dir
+    }
+
+    // Runs one automation step, then saves a screenshot of the window
+    fn step<T>(&self, hwnd: Option<HWND>, name: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let result = f();
+        self.snapshot(hwnd, name, result.is_ok());
+        result
+    }
+
+    // Failing to save a screenshot never fails the step itself
+    fn snapshot(&self, hwnd: Option<HWND>, name: &str, succeeded: bool) {
+        let mut trail = self.trail.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(hwnd) = hwnd.or(trail.last_hwnd) else { return };
+        trail.last_hwnd = Some(hwnd);
+        trail.step += 1;
+
+        let suffix = if succeeded { "" } else { "-failed" };
+        let path = self.session_dir.join(format!("{:05}-{}{}.png", trail.step, name, suffix));
+        let written = self.inner.capture_window(hwnd)
+            .and_then(|image| crate::capture::encode_png(&image))
+            .and_then(|png| std::fs::write(&path, png).map_err(Into::into));
+        if let Err(e) = written {
+            warn!("Failed to save screenshot {}: {}", path.display(), e);
+            return;
+        }
+        debug!("Saved screenshot {}", path.display());
+
+        trail.saved.push_back(path);
+        while self.max_screenshots > 0 && trail.saved.len() > self.max_screenshots {
+            if let Some(oldest) = trail.saved.pop_front() {
+                let _ = std::fs::remove_file(oldest);
+            }
+        }
+    }
+}
+
+// Deletes all but the newest `keep` session folders under `root`
+fn prune_sessions(root: &Path, keep: usize) -> Result<()> {
+    let mut sessions: Vec<PathBuf> = std::fs::read_dir(root)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(SESSION_PREFIX) && entry.path().is_dir())
+        .map(|entry| entry.path())
+        .collect();
+    sessions.sort(); // Names start with the session's start time
+    let excess = sessions.len().saturating_sub(keep);
+    for old in &sessions[..excess] {
+        debug!("Removing old screenshot session {}", old.display());
+        if let Err(e) = std::fs::remove_dir_all(old) {
+            warn!("Failed to remove old screenshot session {}: {}", old.display(), e);
+        }
+    }
+    Ok(())
+}
+
+impl PaintBackend for ScreenshotTrailBackend {
+    fn find_or_launch_paint(&self) -> Result<HWND> {
+        self.inner.find_or_launch_paint()
+    }
+
+    fn launch_paint_window(&self) -> Result<HWND> {
+        self.inner.launch_paint_window()
+    }
+
+    fn find_window_for_pid(&self, pid: u32) -> Option<HWND> {
+        self.inner.find_window_for_pid(pid)
+    }
+
+    fn is_window_owned_by(&self, hwnd: HWND, pid: u32) -> bool {
+        self.inner.is_window_owned_by(hwnd, pid)
+    }
+
+    fn window_pid(&self, hwnd: HWND) -> u32 {
+        self.inner.window_pid(hwnd)
+    }
+
+    fn window_title(&self, hwnd: HWND) -> String {
+        self.inner.window_title(hwnd)
+    }
+
+    fn paint_version(&self, hwnd: HWND) -> Option<String> {
+        self.inner.paint_version(hwnd)
+    }
+
+    fn capabilities(&self, hwnd: HWND) -> Result<PaintCapabilities> {
+        self.inner.capabilities(hwnd)
+    }
+
+    fn window_info(&self, hwnd: HWND) -> Result<WindowInfoResponse> {
+        self.inner.window_info(hwnd)
+    }
+
+    fn ensure_window_on_monitor(&self, hwnd: HWND, monitor: Option<u32>) -> Result<MonitorInfo> {
+        self.step(Some(hwnd), "ensure_window_on_monitor", || self.inner.ensure_window_on_monitor(hwnd, monitor))
+    }
+
+    // Activation precedes nearly every step, so it only shows in the next screenshot
+    fn activate(&self, hwnd: HWND) -> Result<()> {
+        self.inner.activate(hwnd)
+    }
+
+    fn click(&self, hwnd: HWND, x: i32, y: i32) -> Result<()> {
+        self.step(Some(hwnd), "click", || self.inner.click(hwnd, x, y))
+    }
+
+    fn drag(&self, hwnd: HWND, start: (i32, i32), end: (i32, i32)) -> Result<()> {
+        self.step(Some(hwnd), "drag", || self.inner.drag(hwnd, start, end))
+    }
+
+    fn type_text(&self, text: &str) -> Result<()> {
+        self.step(None, "type_text", || self.inner.type_text(text))
+    }
+
+    fn save(&self, hwnd: HWND) -> Result<()> {
+        self.step(Some(hwnd), "save", || self.inner.save(hwnd))
+    }
+
+    fn undo(&self, hwnd: HWND) -> Result<()> {
+        self.step(Some(hwnd), "undo", || self.inner.undo(hwnd))
+    }
+
+    fn release_held_input(&self) -> Result<()> {
+        self.inner.release_held_input()
+    }
+
+    fn cancel_gesture(&self) -> Result<()> {
+        self.step(None, "cancel_gesture", || self.inner.cancel_gesture())
+    }
+
+    fn terminate(&self, pid: u32) -> Result<()> {
+        self.inner.terminate(pid)
+    }
+
+    // No screenshot: the window is gone afterwards
+    fn quit_paint(&self, hwnd: HWND, unsaved_changes: UnsavedChanges, timeout: Duration) -> Result<()> {
+        self.inner.quit_paint(hwnd, unsaved_changes, timeout)
+    }
+
+    fn replay_input(&self, events: &[RecordedInput], speed: f64) -> Result<()> {
+        self.step(None, "replay_input", || self.inner.replay_input(events, speed))
+    }
+
+    fn input_events_sent(&self) -> u64 {
+        self.inner.input_events_sent()
+    }
+
+    fn select_tool(&self, hwnd: HWND, tool: &str) -> Result<()> {
+        self.step(Some(hwnd), "select_tool", || self.inner.select_tool(hwnd, tool))
+    }
+
+    fn select_brush_type(&self, hwnd: HWND, brush_type: &str) -> Result<()> {
+        self.step(Some(hwnd), "select_brush_type", || self.inner.select_brush_type(hwnd, brush_type))
+    }
+
+    fn set_color(&self, hwnd: HWND, color: &str) -> Result<()> {
+        self.step(Some(hwnd), "set_color", || self.inner.set_color(hwnd, color))
+    }
+
+    fn set_secondary_color(&self, hwnd: HWND, color: &str) -> Result<()> {
+        self.step(Some(hwnd), "set_secondary_color", || self.inner.set_secondary_color(hwnd, color))
+    }
+
+    fn add_custom_color(&self, hwnd: HWND, color: &str, button: MouseButton) -> Result<()> {
+        self.step(Some(hwnd), "add_custom_color", || self.inner.add_custom_color(hwnd, color, button))
+    }
+
+    fn select_custom_color(&self, hwnd: HWND, index: usize, button: MouseButton) -> Result<()> {
+        self.step(Some(hwnd), "select_custom_color", || self.inner.select_custom_color(hwnd, index, button))
+    }
+
+    fn set_thickness(&self, hwnd: HWND, level: u32) -> Result<()> {
+        self.step(Some(hwnd), "set_thickness", || self.inner.set_thickness(hwnd, level))
+    }
+
+    fn set_thickness_px(&self, hwnd: HWND, px: u32) -> Result<()> {
+        self.step(Some(hwnd), "set_thickness_px", || self.inner.set_thickness_px(hwnd, px))
+    }
+
+    fn set_brush_size(&self, hwnd: HWND, size: u32, tool: Option<&str>) -> Result<()> {
+        self.step(Some(hwnd), "set_brush_size", || self.inner.set_brush_size(hwnd, size, tool))
+    }
+
+    fn set_fill(&self, hwnd: HWND, fill_type: &str) -> Result<()> {
+        self.step(Some(hwnd), "set_fill", || self.inner.set_fill(hwnd, fill_type))
+    }
+
+    fn set_tool_state(&self, hwnd: HWND, params: &SetToolStateParams) -> Result<()> {
+        self.step(Some(hwnd), "set_tool_state", || self.inner.set_tool_state(hwnd, params))
+    }
+
+    fn canvas_dimensions(&self, hwnd: HWND) -> Result<(u32, u32)> {
+        self.inner.canvas_dimensions(hwnd)
+    }
+
+    fn canvas_view(&self, hwnd: HWND) -> Result<CanvasDimensionsResponse> {
+        self.inner.canvas_view(hwnd)
+    }
+
+    fn initial_canvas_dimensions(&self, hwnd: HWND) -> Result<(u32, u32)> {
+        self.inner.initial_canvas_dimensions(hwnd)
+    }
+
+    fn draw_pixel(&self, hwnd: HWND, x: i32, y: i32, button: MouseButton) -> Result<()> {
+        self.step(Some(hwnd), "draw_pixel", || self.inner.draw_pixel(hwnd, x, y, button))
+    }
+
+    fn draw_line(&self, hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+        self.step(Some(hwnd), "draw_line", || self.inner.draw_line(hwnd, start_x, start_y, end_x, end_y, button))
+    }
+
+    fn draw_shape(&self, hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+        self.step(Some(hwnd), "draw_shape", || self.inner.draw_shape(hwnd, shape_type, start_x, start_y, end_x, end_y, button))
+    }
+
+    fn draw_polyline(&self, hwnd: HWND, points: &[(i32, i32)], button: MouseButton) -> Result<()> {
+        self.step(Some(hwnd), "draw_polyline", || self.inner.draw_polyline(hwnd, points, button))
+    }
+
+    fn clear_canvas(&self, hwnd: HWND) -> Result<()> {
+        self.step(Some(hwnd), "clear_canvas", || self.inner.clear_canvas(hwnd))
+    }
+
+    fn select_region(&self, hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32) -> Result<()> {
+        self.step(Some(hwnd), "select_region", || self.inner.select_region(hwnd, start_x, start_y, end_x, end_y))
+    }
+
+    fn copy_selection(&self, hwnd: HWND) -> Result<()> {
+        self.step(Some(hwnd), "copy_selection", || self.inner.copy_selection(hwnd))
+    }
+
+    fn copy_selection_image(&self, hwnd: HWND) -> Result<CapturedImage> {
+        self.step(Some(hwnd), "copy_selection_image", || self.inner.copy_selection_image(hwnd))
+    }
+
+    fn copy_canvas_image(&self, hwnd: HWND) -> Result<CapturedImage> {
+        self.step(Some(hwnd), "copy_canvas_image", || self.inner.copy_canvas_image(hwnd))
+    }
+
+    fn paste_at(&self, hwnd: HWND, x: i32, y: i32) -> Result<()> {
+        self.step(Some(hwnd), "paste_at", || self.inner.paste_at(hwnd, x, y))
+    }
+
+    fn set_clipboard_image(&self, image: &CapturedImage) -> Result<()> {
+        self.step(None, "set_clipboard_image", || self.inner.set_clipboard_image(image))
+    }
+
+    fn stamp_clipboard_image(&self, hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<()> {
+        self.step(Some(hwnd), "stamp_clipboard_image", || self.inner.stamp_clipboard_image(hwnd, x, y, width, height))
+    }
+
+    fn rotate_selection(&self, hwnd: HWND, degrees: u32) -> Result<()> {
+        self.step(Some(hwnd), "rotate_selection", || self.inner.rotate_selection(hwnd, degrees))
+    }
+
+    fn flip_selection(&self, hwnd: HWND, direction: &str) -> Result<()> {
+        self.step(Some(hwnd), "flip_selection", || self.inner.flip_selection(hwnd, direction))
+    }
+
+    fn invert_colors(&self, hwnd: HWND, selection_only: bool) -> Result<()> {
+        self.step(Some(hwnd), "invert_colors", || self.inner.invert_colors(hwnd, selection_only))
+    }
+
+    fn add_text(&self, hwnd: HWND, params: &AddTextParams) -> Result<()> {
+        self.step(Some(hwnd), "add_text", || self.inner.add_text(hwnd, params))
+    }
+
+    fn new_document(&self, hwnd: HWND, unsaved_changes: UnsavedChanges) -> Result<()> {
+        self.step(Some(hwnd), "new_document", || self.inner.new_document(hwnd, unsaved_changes))
+    }
+
+    fn create_canvas(&self, hwnd: HWND, width: u32, height: u32, background_color: Option<&str>, unsaved_changes: UnsavedChanges) -> Result<()> {
+        self.step(Some(hwnd), "create_canvas", || self.inner.create_canvas(hwnd, width, height, background_color, unsaved_changes))
+    }
+
+    fn save_canvas(&self, hwnd: HWND, params: &SaveCanvasParams) -> Result<()> {
+        self.step(Some(hwnd), "save_canvas", || self.inner.save_canvas(hwnd, params))
+    }
+
+    fn save_as(&self, hwnd: HWND, params: &SaveCanvasParams) -> Result<()> {
+        self.step(Some(hwnd), "save_as", || self.inner.save_as(hwnd, params))
+    }
+
+    fn set_image_properties(&self, hwnd: HWND, params: &SetImagePropertiesParams) -> Result<()> {
+        self.step(Some(hwnd), "set_image_properties", || self.inner.set_image_properties(hwnd, params))
+    }
+
+    fn set_background(&self, hwnd: HWND, color: &str) -> Result<()> {
+        self.step(Some(hwnd), "set_background", || self.inner.set_background(hwnd, color))
+    }
+
+    fn add_layer(&self, hwnd: HWND) -> Result<()> {
+        self.step(Some(hwnd), "add_layer", || self.inner.add_layer(hwnd))
+    }
+
+    fn select_layer(&self, hwnd: HWND, index: u32) -> Result<()> {
+        self.step(Some(hwnd), "select_layer", || self.inner.select_layer(hwnd, index))
+    }
+
+    fn set_layer_visibility(&self, hwnd: HWND, index: u32, visible: bool) -> Result<()> {
+        self.step(Some(hwnd), "set_layer_visibility", || self.inner.set_layer_visibility(hwnd, index, visible))
+    }
+
+    fn merge_layer_down(&self, hwnd: HWND, index: u32) -> Result<()> {
+        self.step(Some(hwnd), "merge_layer_down", || self.inner.merge_layer_down(hwnd, index))
+    }
+
+    fn delete_layer(&self, hwnd: HWND, index: u32) -> Result<()> {
+        self.step(Some(hwnd), "delete_layer", || self.inner.delete_layer(hwnd, index))
+    }
+
+    fn capture_window(&self, hwnd: HWND) -> Result<CapturedImage> {
+        self.inner.capture_window(hwnd)
+    }
+
+    fn capture_canvas(&self, hwnd: HWND) -> Result<CapturedImage> {
+        self.inner.capture_canvas(hwnd)
+    }
+
+    fn capture_canvas_region(&self, hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<CapturedImage> {
+        self.inner.capture_canvas_region(hwnd, x, y, width, height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{MockBackend, MOCK_HWND};
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_screenshot_after_every_step() {
+        let root = std::env::temp_dir().join(format!("mspmcp-trail-steps-{}", std::process::id()));
+        let config = ScreenshotTrailConfig { enabled: true, dir: Some(root.clone()), max_sessions: 1, max_screenshots: 3 };
+        let mock = Arc::new(MockBackend::default());
+        let trail = ScreenshotTrailBackend::new(mock.clone(), &config).unwrap();
+
+        trail.type_text("no window yet").unwrap(); // Nothing to capture
+        trail.set_color(MOCK_HWND, "#FF0000").unwrap();
+        trail.draw_line(MOCK_HWND, 0, 0, 5, 5, MouseButton::Left).unwrap();
+        trail.type_text("hello").unwrap();
+        trail.canvas_dimensions(MOCK_HWND).unwrap(); // Queries aren't steps
+        assert_eq!(file_names(trail.session_dir()), ["00001-set_color.png", "00002-draw_line.png", "00003-type_text.png"]);
+
+        // Beyond max_screenshots the oldest go first
+        trail.click(MOCK_HWND, 1, 1).unwrap();
+        assert_eq!(file_names(trail.session_dir()), ["00002-draw_line.png", "00003-type_text.png", "00004-click.png"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_old_sessions_are_removed() {
+        let root = std::env::temp_dir().join(format!("mspmcp-trail-sessions-{}", std::process::id()));
+        for old in ["session-1000-1", "session-2000-1", "session-3000-1"] {
+            std::fs::create_dir_all(root.join(old)).unwrap();
+        }
+        std::fs::write(root.join("notes.txt"), "kept").unwrap();
+        let config = ScreenshotTrailConfig { enabled: true, dir: Some(root.clone()), max_sessions: 2, max_screenshots: 0 };
+        let trail = ScreenshotTrailBackend::new(Arc::new(MockBackend::default()), &config).unwrap();
+
+        let session = trail.session_dir().file_name().unwrap().to_string_lossy().into_owned();
+        let mut expected = vec!["notes.txt".to_string(), "session-3000-1".to_string(), session];
+        expected.sort();
+        assert_eq!(file_names(&root), expected);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
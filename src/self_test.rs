@@ -0,0 +1,371 @@
+// Golden-image self test (`--self-test`).
+//
+// Draws a fixed set of scenes through the normal request path, captures the
+// top-left corner of the canvas after each one and compares it with a PNG
+// checked in under tests/golden/<backend>/. Captures of real Paint differ
+// slightly between runs (antialiasing, ribbon layout), so pixels may be off
+// by CHANNEL_TOLERANCE per channel and up to MAX_MISMATCH_FRACTION of them
+// may differ entirely. Needs an interactive session unless combined with
+// `--simulate`.
+//
+// The `self_test` method is a quicker check for users: one scripted session
+// (connect, new canvas, shape, text, capture, verify, undo) reported step by
+// step, to prove the environment works before handing it to an agent.
+
+use crate::capture::{decode_png, encode_png, CapturedImage};
+use crate::error::{MspMcpError, Result};
+use crate::protocol::{MouseButton, UnsavedChanges};
+use crate::PaintServerState;
+use tracing::{error, info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+// Area compared for every scene, in canvas coordinates
+const SCENE_WIDTH: u32 = 240;
+const SCENE_HEIGHT: u32 = 160;
+
+// Per-channel difference still counted as a match
+pub const CHANNEL_TOLERANCE: u8 = 48;
+
+// Share of pixels allowed to differ by more than CHANNEL_TOLERANCE
+pub const MAX_MISMATCH_FRACTION: f64 = 0.01;
+
+// Where goldens live in the source tree
+pub const DEFAULT_GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+
+pub struct SelfTestOptions {
+    pub golden_dir: PathBuf,    // Parent of the per-backend directories
+    pub backend_name: String,   // "paint" or "simulate"
+    pub update_golden: bool,    // Write captures as the new goldens instead of comparing
+}
+
+// A named drawing made of ordinary requests
+struct Scene {
+    name: &'static str,
+    steps: Vec<(&'static str, Value)>,
+}
+
+fn scenes() -> Vec<Scene> {
+    let pixels = (0..5)
+        .flat_map(|row| (0..5).map(move |col| ("draw_pixel", json!({"x": 20 + col * 20, "y": 20 + row * 20, "color": "#000000"}))))
+        .collect();
+    vec![
+        Scene { name: "lines", steps: vec![
+            ("draw_line", json!({"start_x": 20, "start_y": 20, "end_x": 220, "end_y": 20, "color": "#000000", "thickness": 1})),
+            ("draw_line", json!({"start_x": 20, "start_y": 40, "end_x": 220, "end_y": 140, "color": "#FF0000", "thickness": 3})),
+            ("draw_line", json!({"start_x": 20, "start_y": 140, "end_x": 120, "end_y": 60, "color": "#0000FF", "thickness": 5})),
+        ]},
+        Scene { name: "shapes", steps: vec![
+            ("draw_shape", json!({"shape_type": "rectangle", "start_x": 20, "start_y": 20, "end_x": 110, "end_y": 90,
+                "color": "#0000FF", "thickness": 2, "fill_type": "none"})),
+            ("draw_shape", json!({"shape_type": "ellipse", "start_x": 130, "start_y": 20, "end_x": 220, "end_y": 90,
+                "color": "#00A000", "thickness": 1, "fill_type": "solid"})),
+            ("draw_shape", json!({"shape_type": "triangle", "start_x": 20, "start_y": 100, "end_x": 110, "end_y": 150,
+                "color": "#000000", "thickness": 1, "fill_type": "none"})),
+            ("draw_shape", json!({"shape_type": "hexagon", "start_x": 130, "start_y": 100, "end_x": 220, "end_y": 150,
+                "color": "#FF8000", "thickness": 3, "fill_type": "solid"})),
+        ]},
+        Scene { name: "polyline", steps: vec![
+            ("draw_polyline", json!({"points": [
+                {"x": 20, "y": 140}, {"x": 60, "y": 30}, {"x": 100, "y": 140},
+                {"x": 140, "y": 30}, {"x": 180, "y": 140}, {"x": 220, "y": 30}
+            ], "color": "#800080", "thickness": 2, "tool": "pencil"})),
+        ]},
+        Scene { name: "pixels", steps: pixels },
+    ]
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ImageDiff {
+    pub mismatched: usize, // Pixels differing by more than the tolerance
+    pub total: usize,
+    pub max_delta: u8,     // Largest channel difference seen
+}
+
+impl ImageDiff {
+    pub fn passes(&self) -> bool {
+        self.mismatched as f64 <= self.total as f64 * MAX_MISMATCH_FRACTION
+    }
+}
+
+/// Compares two images pixel by pixel. Fails if their sizes differ.
+pub fn compare_images(actual: &CapturedImage, expected: &CapturedImage, tolerance: u8) -> Result<ImageDiff> {
+    if (actual.width, actual.height) != (expected.width, expected.height) {
+        return Err(MspMcpError::General(format!("Captured {}x{} but the golden is {}x{}",
+            actual.width, actual.height, expected.width, expected.height)));
+    }
+    let mut diff = ImageDiff { mismatched: 0, total: (actual.width * actual.height) as usize, max_delta: 0 };
+    for (a, e) in actual.rgba.chunks_exact(4).zip(expected.rgba.chunks_exact(4)) {
+        let delta = a.iter().zip(e).map(|(a, e)| a.abs_diff(*e)).max().unwrap_or(0);
+        diff.max_delta = diff.max_delta.max(delta);
+        if delta > tolerance {
+            diff.mismatched += 1;
+        }
+    }
+    Ok(diff)
+}
+
+// Draws one scene on a cleared canvas and captures the compared area
+async fn render_scene(state: &PaintServerState, scene: &Scene) -> Result<CapturedImage> {
+    state.handle_request("clear_canvas", Some(json!({"confirm": true}))).await?;
+    for (method, params) in &scene.steps {
+        state.handle_request(method, Some(params.clone())).await?;
+    }
+    let hwnd = (*state.paint_hwnd.lock().map_err(|_|
+        MspMcpError::General("Failed to lock HWND state".to_string()))?).ok_or(MspMcpError::WindowNotFound)?;
+    state.backend.capture_canvas_region(hwnd, 0, 0, SCENE_WIDTH, SCENE_HEIGHT)
+}
+
+// Compares (or records) one scene; Ok(false) means it ran but didn't match
+async fn check_scene(state: &PaintServerState, scene: &Scene, dir: &Path, update: bool) -> Result<bool> {
+    let actual = render_scene(state, scene).await?;
+    let golden_path = dir.join(format!("{}.png", scene.name));
+    if update {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&golden_path, encode_png(&actual)?)?;
+        println!("UPDATED {} -> {}", scene.name, golden_path.display());
+        return Ok(true);
+    }
+
+    let golden = std::fs::read(&golden_path).map_err(|e| MspMcpError::General(format!(
+        "No golden at {} ({}); record one with --update-golden", golden_path.display(), e)))?;
+    let diff = compare_images(&actual, &decode_png(&golden)?, CHANNEL_TOLERANCE)?;
+    if diff.passes() {
+        println!("PASS {} ({} of {} pixels differ, max delta {})", scene.name, diff.mismatched, diff.total, diff.max_delta);
+        Ok(true)
+    } else {
+        // Keep the capture next to the golden so CI can upload both
+        let actual_path = dir.join(format!("{}.actual.png", scene.name));
+        std::fs::write(&actual_path, encode_png(&actual)?)?;
+        println!("FAIL {} ({} of {} pixels differ, max delta {}); capture saved to {}",
+            scene.name, diff.mismatched, diff.total, diff.max_delta, actual_path.display());
+        Ok(false)
+    }
+}
+
+/// Runs every scene and returns the process exit code (0 if all passed).
+pub async fn run(state: &PaintServerState, options: &SelfTestOptions) -> i32 {
+    let dir = options.golden_dir.join(&options.backend_name);
+    info!("Running self test against goldens in {}", dir.display());
+
+    if let Err(e) = state.handle_request("initialize", None).await {
+        println!("FAIL initialize: {}", e);
+        return 1;
+    }
+
+    let mut failures = 0;
+    let scenes = scenes();
+    for scene in &scenes {
+        match check_scene(state, scene, &dir, options.update_golden).await {
+            Ok(true) => {}
+            Ok(false) => failures += 1,
+            Err(e) => {
+                error!("Self test scene '{}' failed: {}", scene.name, e);
+                println!("FAIL {}: {}", scene.name, e);
+                failures += 1;
+            }
+        }
+    }
+
+    println!("{} of {} scenes passed", scenes.len() - failures, scenes.len());
+    if failures == 0 { 0 } else { 1 }
+}
+
+// === Smoke test (`self_test` method) ===
+
+// Canvas the smoke test draws on
+const SMOKE_CANVAS_WIDTH: u32 = 320;
+const SMOKE_CANVAS_HEIGHT: u32 = 240;
+
+// Where the smoke test draws: a filled rectangle, then text below it
+const SMOKE_RECT: (i32, i32, i32, i32) = (20, 20, 140, 100);
+const SMOKE_TEXT_AREA: (i32, i32, u32, u32) = (20, 140, 120, 40);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Pass,
+    Fail,
+    Skipped, // An earlier step failed
+}
+
+#[derive(Serialize, Debug)]
+pub struct StepReport {
+    pub step: &'static str,
+    pub status: StepStatus,
+    pub elapsed_ms: u64,
+    pub detail: String,    // What was checked, or why it failed
+}
+
+// Pixels in a canvas area that are clearly not white
+fn count_marked(image: &CapturedImage, x: i32, y: i32, width: u32, height: u32) -> usize {
+    let mut marked = 0;
+    for row in y.max(0) as u32..(y.max(0) as u32 + height).min(image.height) {
+        for col in x.max(0) as u32..(x.max(0) as u32 + width).min(image.width) {
+            let i = ((row * image.width + col) * 4) as usize;
+            if image.rgba[i..i + 3].iter().any(|c| 255 - c > CHANNEL_TOLERANCE) {
+                marked += 1;
+            }
+        }
+    }
+    marked
+}
+
+// Runs steps in order; once one fails the rest are skipped
+struct SmokeRun<'a> {
+    state: &'a PaintServerState,
+    steps: Vec<StepReport>,
+}
+
+impl SmokeRun<'_> {
+    fn step(&mut self, step: &'static str, run: impl FnOnce() -> Result<String>) {
+        if self.steps.iter().any(|s| s.status != StepStatus::Pass) {
+            self.steps.push(StepReport { step, status: StepStatus::Skipped, elapsed_ms: 0, detail: String::new() });
+            return;
+        }
+        let started = self.state.clock.now();
+        let outcome = run();
+        let elapsed_ms = (self.state.clock.now() - started).as_millis() as u64;
+        let (status, detail) = match outcome {
+            Ok(detail) => (StepStatus::Pass, detail),
+            Err(e) => {
+                warn!("Smoke test step '{}' failed: {}", step, e);
+                (StepStatus::Fail, e.to_string())
+            }
+        };
+        self.steps.push(StepReport { step, status, elapsed_ms, detail });
+    }
+}
+
+/// Runs the scripted smoke test against the real backend. Replaces the
+/// open document with a small test canvas and leaves Paint connected.
+pub fn run_smoke_test(state: &PaintServerState) -> Vec<StepReport> {
+    let backend = state.backend.clone();
+    let mut run = SmokeRun { state, steps: Vec::new() };
+    let mut hwnd = None;
+    let mut drawn = None;
+    let (text_x, text_y, text_width, text_height) = SMOKE_TEXT_AREA;
+
+    run.step("connect", || {
+        let found = backend.find_or_launch_paint()?;
+        state.set_paint_window(found)?;
+        hwnd = Some(found);
+        Ok(format!("Paint window {}", found))
+    });
+    let hwnd = hwnd.unwrap_or_default();
+
+    run.step("new_canvas", || {
+        backend.create_canvas(hwnd, SMOKE_CANVAS_WIDTH, SMOKE_CANVAS_HEIGHT, Some("#FFFFFF"), UnsavedChanges::Discard)?;
+        let (width, height) = backend.canvas_dimensions(hwnd)?;
+        if (width, height) != (SMOKE_CANVAS_WIDTH, SMOKE_CANVAS_HEIGHT) {
+            return Err(MspMcpError::General(format!("Canvas is {}x{} instead of {}x{}",
+                width, height, SMOKE_CANVAS_WIDTH, SMOKE_CANVAS_HEIGHT)));
+        }
+        Ok(format!("{}x{} canvas", width, height))
+    });
+
+    run.step("draw_shape", || {
+        let (left, top, right, bottom) = SMOKE_RECT;
+        backend.set_color(hwnd, "#FF0000")?;
+        backend.set_fill(hwnd, "solid")?;
+        backend.draw_shape(hwnd, "rectangle", left, top, right, bottom, MouseButton::Left)?;
+        Ok("Filled red rectangle".to_string())
+    });
+
+    run.step("draw_text", || {
+        let params = crate::protocol::AddTextParams {
+            x: text_x, y: text_y, text: "MCP".to_string(), color: Some("#000000".to_string()),
+            font_name: None, font_size: Some(24), font_style: None,
+        };
+        backend.add_text(hwnd, &params)?;
+        Ok("Black text \"MCP\"".to_string())
+    });
+
+    run.step("capture", || {
+        let image = backend.capture_canvas_region(hwnd, 0, 0, SMOKE_CANVAS_WIDTH, SMOKE_CANVAS_HEIGHT)?;
+        let detail = format!("Captured {}x{}", image.width, image.height);
+        drawn = Some(image);
+        Ok(detail)
+    });
+    let drawn = drawn.unwrap_or(CapturedImage { width: 0, height: 0, rgba: Vec::new() });
+
+    run.step("verify", || {
+        let (left, top, right, bottom) = SMOKE_RECT;
+        let inside = count_marked(&drawn, left + 10, top + 10, (right - left - 20) as u32, (bottom - top - 20) as u32);
+        let text = count_marked(&drawn, text_x, text_y, text_width, text_height);
+        if inside == 0 {
+            return Err(MspMcpError::General("The rectangle is missing from the capture".to_string()));
+        }
+        if text == 0 {
+            return Err(MspMcpError::General("The text is missing from the capture".to_string()));
+        }
+        Ok(format!("Rectangle ({} px) and text ({} px) found", inside, text))
+    });
+
+    run.step("undo", || {
+        backend.undo(hwnd)?;
+        let after = backend.capture_canvas_region(hwnd, 0, 0, SMOKE_CANVAS_WIDTH, SMOKE_CANVAS_HEIGHT)?;
+        let (before, remaining) = (count_marked(&drawn, text_x, text_y, text_width, text_height),
+            count_marked(&after, text_x, text_y, text_width, text_height));
+        if remaining >= before {
+            return Err(MspMcpError::General("Undo did not remove the text".to_string()));
+        }
+        Ok(format!("Text area went from {} to {} marked pixels", before, remaining))
+    });
+
+    run.steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, value: u8) -> CapturedImage {
+        CapturedImage { width, height, rgba: vec![value; (width * height * 4) as usize] }
+    }
+
+    #[test]
+    fn test_compare_within_tolerance() {
+        let diff = compare_images(&solid(10, 10, 100), &solid(10, 10, 120), 48).unwrap();
+        assert_eq!(diff, ImageDiff { mismatched: 0, total: 100, max_delta: 20 });
+        assert!(diff.passes());
+    }
+
+    #[test]
+    fn test_compare_counts_mismatches() {
+        let mut actual = solid(10, 10, 255);
+        actual.rgba[0] = 0; // One pixel is off: 1% is still allowed
+        assert!(compare_images(&actual, &solid(10, 10, 255), 48).unwrap().passes());
+        actual.rgba[4] = 0;
+        let diff = compare_images(&actual, &solid(10, 10, 255), 48).unwrap();
+        assert_eq!(diff.mismatched, 2);
+        assert!(!diff.passes());
+    }
+
+    #[test]
+    fn test_compare_rejects_size_mismatch() {
+        assert!(compare_images(&solid(10, 10, 0), &solid(10, 11, 0), 48).is_err());
+    }
+
+    #[test]
+    fn test_smoke_test_passes_on_simulated_paint() {
+        let state = PaintServerState::with_backend(crate::config::ServerConfig::default(),
+            std::sync::Arc::new(crate::simulate::SimulatedBackend::new()));
+        let steps = run_smoke_test(&state);
+        let names: Vec<&str> = steps.iter().map(|s| s.step).collect();
+        assert_eq!(names, ["connect", "new_canvas", "draw_shape", "draw_text", "capture", "verify", "undo"]);
+        assert!(steps.iter().all(|s| s.status == StepStatus::Pass), "{:?}", steps);
+    }
+
+    #[test]
+    fn test_smoke_test_skips_steps_after_a_failure() {
+        let state = PaintServerState::with_backend(crate::config::ServerConfig::default(),
+            std::sync::Arc::new(crate::backend::mock::MockBackend::default()));
+        // The mock's canvas stays 800x600, so new_canvas fails
+        let steps = run_smoke_test(&state);
+        assert_eq!(steps[0].status, StepStatus::Pass);
+        assert_eq!(steps[1].status, StepStatus::Fail);
+        assert!(steps[2..].iter().all(|s| s.status == StepStatus::Skipped));
+    }
+}
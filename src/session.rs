@@ -0,0 +1,246 @@
+// The Windows session the server runs in, and how input reaches Paint there.
+//
+// SendInput only reaches the desktop in front of the user. On the console,
+// and in a Remote Desktop session whose window is open, that is Paint's
+// desktop. A disconnected Remote Desktop session has no input desktop at
+// all, so SendInput fails or its input is quietly dropped and drags draw
+// nothing. Session 0, where services run, has no interactive desktop for
+// Paint to be shown on in the first place.
+//
+// `check` runs at startup: it detects the session, explains what to
+// expect, and fails in session 0. The `input_path` config setting then
+// picks how input is delivered. "auto" (the default) uses SendInput while
+// the session has an input desktop and posts window messages
+// (message_input.rs) while it is disconnected; "send_input" and "messages"
+// always use the one path.
+//
+// A request can also choose with its `activation` field not to take the
+// user's focus: "never" posts its input as window messages too, and
+// "if_needed" only activates Paint when it isn't in front already.
+//
+// Separately, while the workstation is locked or a UAC prompt is shown the
+// input desktop isn't the user's, and input waits for it to return for up
+// to `input_desktop_wait_ms` (windows::check_input_desktop).
+
+use crate::config::InputPath;
+use crate::error::{MspMcpError, Result};
+use crate::protocol::Activation;
+use serde_json::Value;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+#[cfg(feature = "windows-automation")]
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "windows-automation")]
+use std::sync::OnceLock;
+#[cfg(feature = "windows-automation")]
+use tracing::{info, warn};
+#[cfg(feature = "windows-automation")]
+use windows_sys::Win32::System::RemoteDesktop::{
+    ProcessIdToSessionId, WTSConnectState, WTSDisconnected, WTSFreeMemory, WTSQuerySessionInformationW,
+    WTS_CONNECTSTATE_CLASS, WTS_CURRENT_SERVER_HANDLE, WTS_CURRENT_SESSION,
+};
+#[cfg(feature = "windows-automation")]
+use windows_sys::Win32::System::Threading::GetCurrentProcessId;
+#[cfg(feature = "windows-automation")]
+use windows_sys::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, SM_REMOTESESSION};
+
+/// Kind of Windows session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    Console,      // Signed in at the machine
+    Remote,       // A connected Remote Desktop session
+    Disconnected, // A session whose Remote Desktop client went away
+    Service,      // Session 0, which has no interactive desktop
+}
+
+/// How input is delivered to Paint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    SendInput, // Synthesized input on the input desktop
+    Messages,  // Mouse and key messages posted to Paint's windows
+}
+
+/// How input is delivered in a session of `kind` with `input_path` set.
+/// Fails for session 0, which input can't reach either way.
+pub fn delivery(kind: SessionKind, input_path: InputPath) -> Result<Delivery> {
+    match (kind, input_path) {
+        (SessionKind::Service, _) => Err(MspMcpError::InputDesktopUnavailable(
+            "The server runs in session 0 (as a service), which has no desktop for Paint to be shown on. \
+             Run it in the signed-in user's session instead, e.g. from a scheduled task set to run only \
+             when the user is logged on".to_string())),
+        (_, InputPath::SendInput) => Ok(Delivery::SendInput),
+        (_, InputPath::Messages) | (SessionKind::Disconnected, InputPath::Auto) => Ok(Delivery::Messages),
+        (_, InputPath::Auto) => Ok(Delivery::SendInput),
+    }
+}
+
+static DESKTOP_WAIT_MS: AtomicU64 = AtomicU64::new(crate::config::DEFAULT_INPUT_DESKTOP_WAIT_MS);
+
+/// Sets how long input waits for the user's desktop to return.
+pub fn set_desktop_wait_ms(ms: u64) {
+    DESKTOP_WAIT_MS.store(ms, Ordering::Relaxed);
+}
+
+pub fn desktop_wait() -> Duration {
+    Duration::from_millis(DESKTOP_WAIT_MS.load(Ordering::Relaxed))
+}
+
+thread_local! {
+    // Focus policy of the request running on this thread
+    static ACTIVATION: Cell<Activation> = const { Cell::new(Activation::Always) };
+}
+
+/// Restores the previous focus policy when dropped.
+pub struct ActivationGuard {
+    previous: Activation,
+}
+
+impl Drop for ActivationGuard {
+    fn drop(&mut self) {
+        ACTIVATION.with(|activation| activation.set(self.previous));
+    }
+}
+
+/// Makes `activation` the focus policy on this thread until the guard is
+/// dropped. Requests run by this one (macro steps, tools/call) inherit it.
+pub fn install_activation(activation: Activation) -> ActivationGuard {
+    ActivationGuard { previous: ACTIVATION.with(|current| current.replace(activation)) }
+}
+
+/// Focus policy of the request running on this thread.
+pub fn activation() -> Activation {
+    ACTIVATION.with(Cell::get)
+}
+
+/// Removes the `activation` field from a request's params and returns the
+/// policy it asks for, if any.
+pub fn take_activation(params: &mut Option<Value>) -> Result<Option<Activation>> {
+    let Some(value) = params.as_mut().and_then(Value::as_object_mut).and_then(|fields| fields.remove("activation")) else {
+        return Ok(None);
+    };
+    if value.is_null() {
+        return Ok(None);
+    }
+    serde_json::from_value(value.clone()).map(Some).map_err(|_| MspMcpError::InvalidParameters(
+        format!("activation must be \"always\", \"if_needed\" or \"never\", got {}", value)))
+}
+
+#[cfg(feature = "windows-automation")]
+static INPUT_PATH: OnceLock<InputPath> = OnceLock::new();
+
+// Whether the last input went out as messages, to log switches between paths
+#[cfg(feature = "windows-automation")]
+static USING_MESSAGES: AtomicBool = AtomicBool::new(false);
+
+/// Detects the session the server runs in.
+#[cfg(feature = "windows-automation")]
+pub fn detect() -> SessionKind {
+    let mut session_id = 0;
+    if unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut session_id) } != 0 && session_id == 0 {
+        return SessionKind::Service;
+    }
+    if connect_state() == Some(WTSDisconnected) {
+        return SessionKind::Disconnected;
+    }
+    if unsafe { GetSystemMetrics(SM_REMOTESESSION) } != 0 {
+        SessionKind::Remote
+    } else {
+        SessionKind::Console
+    }
+}
+
+#[cfg(feature = "windows-automation")]
+fn connect_state() -> Option<WTS_CONNECTSTATE_CLASS> {
+    let mut buffer = std::ptr::null_mut();
+    let mut bytes = 0;
+    unsafe {
+        if WTSQuerySessionInformationW(WTS_CURRENT_SERVER_HANDLE, WTS_CURRENT_SESSION, WTSConnectState, &mut buffer, &mut bytes) == 0 {
+            return None;
+        }
+        let state = (bytes as usize >= std::mem::size_of::<WTS_CONNECTSTATE_CLASS>())
+            .then(|| *(buffer as *const WTS_CONNECTSTATE_CLASS));
+        WTSFreeMemory(buffer as *mut _);
+        state
+    }
+}
+
+/// Checks at startup that input can reach Paint in this session, and keeps
+/// `input_path` for `uses_messages`.
+#[cfg(feature = "windows-automation")]
+pub fn check(input_path: InputPath) -> Result<SessionKind> {
+    let kind = detect();
+    let delivery = delivery(kind, input_path)?;
+    info!("Running in a {:?} session; delivering input with {:?} (input_path {:?})", kind, delivery, input_path);
+    match (kind, input_path) {
+        (SessionKind::Remote, InputPath::Auto) => warn!(
+            "Running over Remote Desktop: input stops reaching Paint while the Remote Desktop window is minimized. \
+             If the session is disconnected, input switches to window messages"),
+        (SessionKind::Remote | SessionKind::Disconnected, InputPath::SendInput) => warn!(
+            "Running over Remote Desktop with input_path = \"send_input\": drags do nothing while the Remote Desktop \
+             window is minimized or the session is disconnected. Set input_path = \"auto\" to fall back to window messages"),
+        _ => {}
+    }
+    let _ = INPUT_PATH.set(input_path);
+    Ok(kind)
+}
+
+/// True if input is to be posted as window messages rather than sent with
+/// SendInput: always for a request that mustn't take the focus, and
+/// otherwise as input_path and the session, which can change while the
+/// server runs, decide.
+#[cfg(feature = "windows-automation")]
+pub fn uses_messages() -> bool {
+    if activation() == Activation::Never {
+        return true;
+    }
+    let input_path = INPUT_PATH.get().copied().unwrap_or_default();
+    let messages = match input_path {
+        InputPath::Auto => matches!(delivery(detect(), input_path), Ok(Delivery::Messages)),
+        InputPath::SendInput => false,
+        InputPath::Messages => true,
+    };
+    if USING_MESSAGES.swap(messages, Ordering::Relaxed) != messages && input_path == InputPath::Auto {
+        if messages {
+            warn!("The session is disconnected; posting input to Paint as window messages");
+        } else {
+            info!("The session has an input desktop again; sending input with SendInput");
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delivery_falls_back_to_messages_only_when_disconnected() {
+        assert_eq!(delivery(SessionKind::Console, InputPath::Auto).unwrap(), Delivery::SendInput);
+        assert_eq!(delivery(SessionKind::Remote, InputPath::Auto).unwrap(), Delivery::SendInput);
+        assert_eq!(delivery(SessionKind::Disconnected, InputPath::Auto).unwrap(), Delivery::Messages);
+        assert_eq!(delivery(SessionKind::Disconnected, InputPath::SendInput).unwrap(), Delivery::SendInput);
+        assert_eq!(delivery(SessionKind::Console, InputPath::Messages).unwrap(), Delivery::Messages);
+        for input_path in [InputPath::Auto, InputPath::SendInput, InputPath::Messages] {
+            assert!(matches!(delivery(SessionKind::Service, input_path), Err(MspMcpError::InputDesktopUnavailable(_))));
+        }
+    }
+
+    #[test]
+    fn test_activation_is_taken_from_params_and_scoped() {
+        let mut params = Some(serde_json::json!({"x": 1, "activation": "if_needed"}));
+        assert_eq!(take_activation(&mut params).unwrap(), Some(Activation::IfNeeded));
+        assert_eq!(params, Some(serde_json::json!({"x": 1})));
+        assert_eq!(take_activation(&mut params).unwrap(), None);
+        assert!(take_activation(&mut Some(serde_json::json!({"activation": "sometimes"}))).is_err());
+
+        let outer = install_activation(Activation::Never);
+        {
+            let _inner = install_activation(Activation::IfNeeded);
+            assert_eq!(activation(), Activation::IfNeeded);
+        }
+        assert_eq!(activation(), Activation::Never);
+        drop(outer);
+        assert_eq!(activation(), Activation::Always);
+    }
+}
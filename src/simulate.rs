@@ -0,0 +1,1179 @@
+// Headless simulation backend.
+//
+// `--simulate` replaces Paint with a software canvas held in memory. Drawing
+// calls rasterize into an RGBA bitmap and captures read it back, so the
+// whole protocol (and anything built on it) can be exercised in CI or on a
+// machine without an interactive desktop. The rendering approximates
+// Paint's: shapes and strokes use the current color (Color 2 for
+// right-button strokes and the eraser) and thickness, text is
+// drawn as one solid block per character (there is no font rasterizer).
+
+use crate::backend::PaintBackend;
+use crate::capture::CapturedImage;
+use crate::error::{MspMcpError, Result};
+use crate::input_record::RecordedInput;
+use crate::palette::CUSTOM_COLOR_SLOTS;
+use crate::protocol::{AddTextParams, CanvasDimensionsResponse, MonitorInfo, MouseButton, PaintCapabilities, RectInfo, ColorMode, SaveCanvasParams, SetImagePropertiesParams, SetToolStateParams, UnsavedChanges, WindowInfoResponse, MAX_THICKNESS_PX, THICKNESS_LEVEL_PIXELS};
+use image::{imageops, Rgba, RgbaImage};
+use tracing::{debug, info};
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::backend::HWND;
+
+// Fake handle reported for the simulated window
+pub const SIMULATED_HWND: HWND = 1;
+
+// Canvas size of a fresh simulated document (Paint's default)
+pub const DEFAULT_CANVAS_WIDTH: u32 = 1152;
+pub const DEFAULT_CANVAS_HEIGHT: u32 = 648;
+
+// Screen the simulated window lives on
+const SCREEN_WIDTH: i32 = 1920;
+const SCREEN_HEIGHT: i32 = 1080;
+
+// Text block height when no font size is given
+const DEFAULT_FONT_SIZE: u32 = 16;
+
+const WHITE: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
+const BLACK: Rgba<u8> = Rgba([0x00, 0x00, 0x00, 0xFF]);
+
+// Canvas snapshots kept for undo
+const UNDO_LEVELS: usize = 10;
+
+/// Parses `#RRGGBB`.
+pub fn parse_color(color: &str) -> Result<Rgba<u8>> {
+    let hex = color.strip_prefix('#').filter(|h| h.len() == 6 && h.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or_else(|| MspMcpError::InvalidParameters("Color must be in #RRGGBB format".to_string()))?;
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(0);
+    Ok(Rgba([channel(0), channel(2), channel(4), 0xFF]))
+}
+
+// Everything the simulated Paint remembers between calls
+struct SimulatedPaint {
+    canvas: RgbaImage,
+    color: Rgba<u8>,
+    secondary: Rgba<u8>,                       // Color 2
+    tool: String,
+    stroke: u32,                               // Stroke width in pixels
+    fill: String,                              // "none", "solid" or "outline"
+    selection: Option<(u32, u32, u32, u32)>,   // x, y, width, height
+    clipboard: Option<RgbaImage>,
+    history: Vec<RgbaImage>,                   // Canvas before each change, oldest first
+    custom_colors: Vec<Rgba<u8>>,              // Custom color swatches, newest first
+    dirty: bool,                               // Changed since the last save or new image
+}
+
+/// Software stand-in for Paint.
+pub struct SimulatedBackend {
+    paint: Mutex<SimulatedPaint>,
+}
+
+impl SimulatedPaint {
+    // A fresh, untitled document
+    fn new() -> Self {
+        SimulatedPaint {
+            canvas: RgbaImage::from_pixel(DEFAULT_CANVAS_WIDTH, DEFAULT_CANVAS_HEIGHT, WHITE),
+            color: BLACK,
+            secondary: WHITE,
+            tool: "pencil".to_string(),
+            stroke: THICKNESS_LEVEL_PIXELS[0],
+            fill: "none".to_string(),
+            selection: None,
+            clipboard: None,
+            history: Vec::new(),
+            custom_colors: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    // Remembers the canvas before a change so undo can restore it
+    fn checkpoint(&mut self) {
+        if self.history.len() == UNDO_LEVELS {
+            self.history.remove(0);
+        }
+        self.history.push(self.canvas.clone());
+        self.dirty = true;
+    }
+
+    // Answers the save prompt for a document about to be replaced. The
+    // simulated document is always untitled, so it can't be saved either
+    fn check_unsaved(&self, unsaved_changes: UnsavedChanges) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        match unsaved_changes {
+            UnsavedChanges::Discard => Ok(()),
+            UnsavedChanges::Save => Err(MspMcpError::InvalidParameters(
+                "The document has never been saved, so it can't be saved without a file name; use \"discard\" or save it first".to_string())),
+            UnsavedChanges::Fail => Err(MspMcpError::ConfirmationRequired(
+                "Paint has unsaved changes; pass unsaved_changes \"save\" or \"discard\"".to_string())),
+        }
+    }
+
+    // The color a stroke with `button` draws in
+    fn stroke_color(&self, button: MouseButton) -> Rgba<u8> {
+        match button {
+            MouseButton::Left => self.color,
+            MouseButton::Right => self.secondary,
+        }
+    }
+
+    // Sets the slot strokes with `button` draw in
+    fn set_stroke_color(&mut self, button: MouseButton, color: Rgba<u8>) {
+        match button {
+            MouseButton::Left => self.color = color,
+            MouseButton::Right => self.secondary = color,
+        }
+    }
+}
+
+impl Default for SimulatedBackend {
+    fn default() -> Self {
+        SimulatedBackend::new()
+    }
+}
+
+impl SimulatedBackend {
+    pub fn new() -> Self {
+        SimulatedBackend { paint: Mutex::new(SimulatedPaint::new()) }
+    }
+
+    fn paint(&self) -> Result<std::sync::MutexGuard<'_, SimulatedPaint>> {
+        self.paint.lock().map_err(|_| MspMcpError::General("Failed to lock simulated canvas".to_string()))
+    }
+
+    fn check_hwnd(hwnd: HWND) -> Result<()> {
+        if hwnd == SIMULATED_HWND { Ok(()) } else { Err(MspMcpError::WindowNotFound) }
+    }
+
+    fn to_captured(image: &RgbaImage) -> CapturedImage {
+        CapturedImage { width: image.width(), height: image.height(), rgba: image.as_raw().clone() }
+    }
+}
+
+fn layers_not_supported() -> MspMcpError {
+    MspMcpError::OperationNotSupported("Layers are not available in simulation mode".to_string())
+}
+
+// --- Rasterization ---
+
+// Sets a pixel, ignoring anything off the canvas
+fn plot(canvas: &mut RgbaImage, x: i32, y: i32, color: Rgba<u8>) {
+    if x >= 0 && y >= 0 && (x as u32) < canvas.width() && (y as u32) < canvas.height() {
+        canvas.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+// Stamps a width x width square centered on (x, y)
+fn stamp(canvas: &mut RgbaImage, x: i32, y: i32, width: u32, color: Rgba<u8>) {
+    let start = -(width as i32 / 2);
+    for dy in start..start + width as i32 {
+        for dx in start..start + width as i32 {
+            plot(canvas, x + dx, y + dy, color);
+        }
+    }
+}
+
+/// Draws a line of the given stroke width (Bresenham).
+pub fn stroke_line(canvas: &mut RgbaImage, from: (i32, i32), to: (i32, i32), width: u32, color: Rgba<u8>) {
+    let (mut x, mut y) = from;
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let sx = if x < to.0 { 1 } else { -1 };
+    let sy = if y < to.1 { 1 } else { -1 };
+    let mut err = dx + dy;
+    loop {
+        stamp(canvas, x, y, width, color);
+        if (x, y) == to {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+/// Fills the inside of a closed polygon (even-odd rule, pixel centers).
+pub fn fill_polygon(canvas: &mut RgbaImage, points: &[(i32, i32)], color: Rgba<u8>) {
+    if points.len() < 3 {
+        return;
+    }
+    let top = points.iter().map(|p| p.1).min().unwrap_or(0).max(0);
+    let bottom = points.iter().map(|p| p.1).max().unwrap_or(0).min(canvas.height() as i32 - 1);
+    for y in top..=bottom {
+        let scan = y as f64 + 0.5;
+        let mut crossings: Vec<f64> = Vec::new();
+        for (i, &(x0, y0)) in points.iter().enumerate() {
+            let (x1, y1) = points[(i + 1) % points.len()];
+            let (y0, y1) = (y0 as f64, y1 as f64);
+            if (y0 <= scan && scan < y1) || (y1 <= scan && scan < y0) {
+                crossings.push(x0 as f64 + (scan - y0) / (y1 - y0) * (x1 - x0) as f64);
+            }
+        }
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        for pair in crossings.chunks_exact(2) {
+            for x in (pair[0] - 0.5).ceil() as i32..=(pair[1] - 0.5).floor() as i32 {
+                plot(canvas, x, y, color);
+            }
+        }
+    }
+}
+
+// Outlines a closed polygon
+fn stroke_polygon(canvas: &mut RgbaImage, points: &[(i32, i32)], width: u32, color: Rgba<u8>) {
+    for (i, &point) in points.iter().enumerate() {
+        stroke_line(canvas, point, points[(i + 1) % points.len()], width, color);
+    }
+}
+
+// Vertices of a regular polygon (or ellipse, with many sides) inscribed in a box
+fn inscribed_polygon(left: i32, top: i32, right: i32, bottom: i32, sides: u32, start_degrees: f64) -> Vec<(i32, i32)> {
+    let (cx, cy) = ((left + right) as f64 / 2.0, (top + bottom) as f64 / 2.0);
+    let (rx, ry) = ((right - left) as f64 / 2.0, (bottom - top) as f64 / 2.0);
+    (0..sides)
+        .map(|i| {
+            let angle = (start_degrees + 360.0 * i as f64 / sides as f64).to_radians();
+            ((cx + rx * angle.cos()).round() as i32, (cy + ry * angle.sin()).round() as i32)
+        })
+        .collect()
+}
+
+/// Vertices of a named Paint shape dragged out between two corners.
+pub fn shape_outline(shape_type: &str, start: (i32, i32), end: (i32, i32)) -> Result<Vec<(i32, i32)>> {
+    let (left, right) = (start.0.min(end.0), start.0.max(end.0));
+    let (top, bottom) = (start.1.min(end.1), start.1.max(end.1));
+    let points = match shape_type.to_lowercase().as_str() {
+        "rectangle" => vec![(left, top), (right, top), (right, bottom), (left, bottom)],
+        "triangle" => vec![((left + right) / 2, top), (right, bottom), (left, bottom)],
+        "pentagon" => inscribed_polygon(left, top, right, bottom, 5, -90.0),
+        "hexagon" => inscribed_polygon(left, top, right, bottom, 6, 0.0),
+        "ellipse" => {
+            let sides = ((right - left + bottom - top) as u32).clamp(16, 720);
+            inscribed_polygon(left, top, right, bottom, sides, 0.0)
+        }
+        _ => return Err(MspMcpError::InvalidParameters(format!("Unsupported shape type: {}", shape_type))),
+    };
+    Ok(points)
+}
+
+/// Flood-fills the 4-connected area of the same color as (x, y).
+pub fn flood_fill(canvas: &mut RgbaImage, x: i32, y: i32, color: Rgba<u8>) {
+    if x < 0 || y < 0 || x as u32 >= canvas.width() || y as u32 >= canvas.height() {
+        return;
+    }
+    let target = *canvas.get_pixel(x as u32, y as u32);
+    if target == color {
+        return;
+    }
+    let mut pending = vec![(x as u32, y as u32)];
+    while let Some((x, y)) = pending.pop() {
+        if *canvas.get_pixel(x, y) != target {
+            continue;
+        }
+        canvas.put_pixel(x, y, color);
+        if x > 0 { pending.push((x - 1, y)); }
+        if y > 0 { pending.push((x, y - 1)); }
+        if x + 1 < canvas.width() { pending.push((x + 1, y)); }
+        if y + 1 < canvas.height() { pending.push((x, y + 1)); }
+    }
+}
+
+// Clips a rectangle given by two corners to the canvas; None if nothing is left
+fn clip_rect(canvas: &RgbaImage, start: (i32, i32), end: (i32, i32)) -> Option<(u32, u32, u32, u32)> {
+    let left = start.0.min(end.0).max(0);
+    let top = start.1.min(end.1).max(0);
+    let right = start.0.max(end.0).min(canvas.width() as i32);
+    let bottom = start.1.max(end.1).min(canvas.height() as i32);
+    if right <= left || bottom <= top {
+        None
+    } else {
+        Some((left as u32, top as u32, (right - left) as u32, (bottom - top) as u32))
+    }
+}
+
+impl PaintBackend for SimulatedBackend {
+    fn find_or_launch_paint(&self) -> Result<HWND> {
+        info!("Using the simulated Paint canvas (HWND={})", SIMULATED_HWND);
+        Ok(SIMULATED_HWND)
+    }
+
+    fn launch_paint_window(&self) -> Result<HWND> {
+        Err(MspMcpError::OperationNotSupported("Simulation mode has a single Paint window".to_string()))
+    }
+
+    fn find_window_for_pid(&self, pid: u32) -> Option<HWND> {
+        if pid == std::process::id() { Some(SIMULATED_HWND) } else { None }
+    }
+
+    fn is_window_owned_by(&self, hwnd: HWND, pid: u32) -> bool {
+        hwnd == SIMULATED_HWND && pid == std::process::id()
+    }
+
+    fn window_pid(&self, _hwnd: HWND) -> u32 {
+        // The "Paint process" is the server itself
+        std::process::id()
+    }
+
+    fn window_title(&self, _hwnd: HWND) -> String {
+        let dirty = self.paint().map(|paint| paint.dirty).unwrap_or(false);
+        format!("{}Untitled - Paint", if dirty { "*" } else { "" })
+    }
+
+    fn paint_version(&self, _hwnd: HWND) -> Option<String> {
+        None // Not a real Paint; the default layout applies
+    }
+
+    fn capabilities(&self, hwnd: HWND) -> Result<PaintCapabilities> {
+        Self::check_hwnd(hwnd)?;
+        // Everything the simulator draws; it has no layers or background removal
+        Ok(PaintCapabilities {
+            tools: ["brush", "eraser", "fill", "pencil", "select", "shape", "text"].map(str::to_string).to_vec(),
+            brush_types: crate::protocol::BRUSH_TYPES.iter().map(|brush_type| brush_type.to_string()).collect(),
+            shapes: crate::protocol::SHAPE_TYPES.iter().map(|shape| shape.to_string()).collect(),
+            layers: false,
+            background_removal: false,
+        })
+    }
+
+    fn window_info(&self, hwnd: HWND) -> Result<WindowInfoResponse> {
+        Self::check_hwnd(hwnd)?;
+        let (width, height) = self.canvas_dimensions(hwnd)?;
+        let screen = RectInfo::new(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT);
+        let client = RectInfo::new(0, 0, width as i32, height as i32);
+        Ok(WindowInfoResponse {
+            hwnd,
+            pid: self.window_pid(hwnd),
+            class_name: "MSPaintApp".to_string(),
+            title: self.window_title(hwnd),
+            window_rect: client,
+            client_rect: client,
+            client_origin_x: 0,
+            client_origin_y: 0,
+            dpi: 96,
+            scale_factor: 1.0,
+            monitor: Some(MonitorInfo { index: 0, handle: 0, monitor_rect: screen, work_rect: screen, is_primary: true }),
+            is_foreground: true,
+            is_minimized: false,
+            is_maximized: false,
+            z_order: 0,
+            canvas_rect: client,
+        })
+    }
+
+    fn ensure_window_on_monitor(&self, hwnd: HWND, monitor: Option<u32>) -> Result<MonitorInfo> {
+        Self::check_hwnd(hwnd)?;
+        match monitor {
+            Some(index) if index != 0 => Err(MspMcpError::InvalidParameters(format!(
+                "Monitor {} does not exist (the simulation has 1 monitor)", index))),
+            _ => {
+                let screen = RectInfo::new(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT);
+                Ok(MonitorInfo { index: 0, handle: 0, monitor_rect: screen, work_rect: screen, is_primary: true })
+            }
+        }
+    }
+
+    fn activate(&self, hwnd: HWND) -> Result<()> {
+        Self::check_hwnd(hwnd)
+    }
+
+    fn click(&self, hwnd: HWND, x: i32, y: i32) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut guard = self.paint()?;
+        let paint = &mut *guard;
+        let (color, stroke) = (paint.color, paint.stroke);
+        if matches!(paint.tool.as_str(), "pencil" | "brush" | "eraser" | "fill") {
+            paint.checkpoint();
+        }
+        match paint.tool.as_str() {
+            "pencil" | "brush" => stamp(&mut paint.canvas, x, y, stroke, color),
+            "eraser" => stamp(&mut paint.canvas, x, y, stroke, paint.secondary),
+            "fill" => flood_fill(&mut paint.canvas, x, y, color),
+            _ => debug!("Simulated click at ({}, {}) with the {} tool has no effect", x, y, paint.tool),
+        }
+        Ok(())
+    }
+
+    fn drag(&self, hwnd: HWND, start: (i32, i32), end: (i32, i32)) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let (color, stroke) = (if paint.tool == "eraser" { paint.secondary } else { paint.color }, paint.stroke);
+        paint.checkpoint();
+        stroke_line(&mut paint.canvas, start, end, stroke, color);
+        Ok(())
+    }
+
+    fn type_text(&self, text: &str) -> Result<()> {
+        debug!("Simulated typing ignored: {:?}", text);
+        Ok(())
+    }
+
+    fn save(&self, hwnd: HWND) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        info!("Simulated save (nothing is written to disk)");
+        self.paint()?.dirty = false;
+        Ok(())
+    }
+
+    // There is no dialog to pick the type in, so this is save_canvas
+    fn save_as(&self, hwnd: HWND, params: &SaveCanvasParams) -> Result<()> {
+        self.save_canvas(hwnd, params)
+    }
+
+    fn undo(&self, hwnd: HWND) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        // Like Paint, undo with nothing to undo does nothing
+        if let Some(previous) = paint.history.pop() {
+            paint.canvas = previous;
+            paint.selection = None;
+        }
+        Ok(())
+    }
+
+    fn release_held_input(&self) -> Result<()> {
+        Ok(())
+    }
+
+    // Escape drops the selection, as in Paint
+    fn cancel_gesture(&self) -> Result<()> {
+        self.paint()?.selection = None;
+        Ok(())
+    }
+
+    fn terminate(&self, _pid: u32) -> Result<()> {
+        info!("Discarding the simulated canvas");
+        *self.paint()? = SimulatedPaint::new();
+        Ok(())
+    }
+
+    // The next connect finds a freshly started Paint
+    fn quit_paint(&self, hwnd: HWND, unsaved_changes: UnsavedChanges, _timeout: Duration) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        paint.check_unsaved(unsaved_changes)?;
+        info!("Closing the simulated Paint");
+        *paint = SimulatedPaint::new();
+        Ok(())
+    }
+
+    fn replay_input(&self, _events: &[RecordedInput], _speed: f64) -> Result<()> {
+        // Recordings are raw screen input; there is no screen to send it to
+        Err(MspMcpError::OperationNotSupported("Input replay is not available in simulation mode".to_string()))
+    }
+
+    fn input_events_sent(&self) -> u64 {
+        // Drawing goes straight into the image; nothing is synthesized
+        0
+    }
+
+    fn select_tool(&self, hwnd: HWND, tool: &str) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let tool = tool.to_lowercase();
+        match tool.as_str() {
+            "pencil" | "brush" | "fill" | "text" | "eraser" | "select" | "shape" => {}
+            _ => return Err(MspMcpError::InvalidParameters(format!("Unsupported tool: {}", tool))),
+        }
+        self.paint()?.tool = tool;
+        Ok(())
+    }
+
+    fn select_brush_type(&self, hwnd: HWND, brush_type: &str) -> Result<()> {
+        if !crate::protocol::BRUSH_TYPES.contains(&brush_type) {
+            return Err(MspMcpError::InvalidParameters(format!("Unsupported brush type: {}", brush_type)));
+        }
+        // Every variant is rasterized like the plain brush
+        self.select_tool(hwnd, "brush")
+    }
+
+    fn set_color(&self, hwnd: HWND, color: &str) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        self.paint()?.color = parse_color(color)?;
+        Ok(())
+    }
+
+    fn set_secondary_color(&self, hwnd: HWND, color: &str) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        self.paint()?.secondary = parse_color(color)?;
+        Ok(())
+    }
+
+    fn add_custom_color(&self, hwnd: HWND, color: &str, button: MouseButton) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let color = parse_color(color)?;
+        let mut paint = self.paint()?;
+        paint.custom_colors.insert(0, color);
+        paint.custom_colors.truncate(CUSTOM_COLOR_SLOTS);
+        paint.set_stroke_color(button, color);
+        Ok(())
+    }
+
+    fn select_custom_color(&self, hwnd: HWND, index: usize, button: MouseButton) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let color = *paint.custom_colors.get(index)
+            .ok_or_else(|| MspMcpError::ElementNotFound(format!("Custom color {}", index)))?;
+        paint.set_stroke_color(button, color);
+        Ok(())
+    }
+
+    fn set_thickness(&self, hwnd: HWND, level: u32) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        if !(1..=5).contains(&level) {
+            return Err(MspMcpError::InvalidParameters("Thickness level must be between 1 and 5".to_string()));
+        }
+        self.paint()?.stroke = THICKNESS_LEVEL_PIXELS[level as usize - 1];
+        Ok(())
+    }
+
+    fn set_thickness_px(&self, hwnd: HWND, px: u32) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        if !(1..=MAX_THICKNESS_PX).contains(&px) {
+            return Err(MspMcpError::InvalidParameters(format!("Thickness must be between 1 and {} pixels", MAX_THICKNESS_PX)));
+        }
+        self.paint()?.stroke = px;
+        Ok(())
+    }
+
+    fn set_brush_size(&self, hwnd: HWND, size: u32, tool: Option<&str>) -> Result<()> {
+        if !(1..=30).contains(&size) {
+            return Err(MspMcpError::InvalidParameters("Brush size must be between 1 and 30".to_string()));
+        }
+        if let Some(tool) = tool {
+            self.select_tool(hwnd, tool)?;
+        }
+        Self::check_hwnd(hwnd)?;
+        self.paint()?.stroke = size;
+        Ok(())
+    }
+
+    fn set_fill(&self, hwnd: HWND, fill_type: &str) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let fill_type = fill_type.to_lowercase();
+        match fill_type.as_str() {
+            "none" | "solid" | "outline" => {}
+            _ => return Err(MspMcpError::InvalidParameters(
+                format!("Fill type must be 'none', 'solid', or 'outline', got '{}'", fill_type))),
+        }
+        self.paint()?.fill = fill_type;
+        Ok(())
+    }
+
+    fn set_tool_state(&self, hwnd: HWND, params: &SetToolStateParams) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let saved = {
+            let paint = self.paint()?;
+            (paint.tool.clone(), paint.color, paint.secondary, paint.stroke, paint.fill.clone())
+        };
+        let result = (|| {
+            if let Some(tool) = &params.tool {
+                self.select_tool(hwnd, tool)?;
+            }
+            if let Some(brush_type) = &params.brush_type {
+                self.select_brush_type(hwnd, brush_type)?;
+            }
+            if let Some(color) = &params.color {
+                self.set_color(hwnd, color)?;
+            }
+            if let Some(color) = &params.secondary_color {
+                self.set_secondary_color(hwnd, color)?;
+            }
+            if let Some(px) = params.thickness_px {
+                self.set_thickness_px(hwnd, px)?;
+            }
+            if let Some(fill_type) = &params.fill_type {
+                self.set_fill(hwnd, fill_type)?;
+            }
+            Ok(())
+        })();
+        // A bad value leaves every setting as it was
+        if result.is_err() {
+            let mut paint = self.paint()?;
+            (paint.tool, paint.color, paint.secondary, paint.stroke, paint.fill) = saved;
+        }
+        result
+    }
+
+    fn canvas_dimensions(&self, hwnd: HWND) -> Result<(u32, u32)> {
+        Self::check_hwnd(hwnd)?;
+        let paint = self.paint()?;
+        Ok((paint.canvas.width(), paint.canvas.height()))
+    }
+
+    fn canvas_view(&self, hwnd: HWND) -> Result<CanvasDimensionsResponse> {
+        // Always at 100%, with the whole canvas in view
+        let (width, height) = self.canvas_dimensions(hwnd)?;
+        Ok(CanvasDimensionsResponse {
+            width, height, zoom: 1.0, viewport: RectInfo::new(0, 0, width as i32, height as i32),
+            scroll_x: 0, scroll_y: 0, estimated: false,
+        })
+    }
+
+    fn initial_canvas_dimensions(&self, hwnd: HWND) -> Result<(u32, u32)> {
+        self.canvas_dimensions(hwnd)
+    }
+
+    fn draw_pixel(&self, hwnd: HWND, x: i32, y: i32, button: MouseButton) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let color = paint.stroke_color(button);
+        paint.checkpoint();
+        plot(&mut paint.canvas, x, y, color);
+        Ok(())
+    }
+
+    fn draw_line(&self, hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let (color, stroke) = (paint.stroke_color(button), paint.stroke);
+        paint.checkpoint();
+        stroke_line(&mut paint.canvas, (start_x, start_y), (end_x, end_y), stroke, color);
+        Ok(())
+    }
+
+    fn draw_shape(&self, hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let (color, stroke) = (paint.stroke_color(button), paint.stroke);
+        let (start, end) = ((start_x, start_y), (end_x, end_y));
+        let shape_type = shape_type.to_lowercase();
+        let outline = match shape_type.as_str() {
+            "line" | "arrow" => None,
+            _ => Some(shape_outline(&shape_type, start, end)?),
+        };
+        paint.checkpoint();
+        match shape_type.as_str() {
+            "line" => stroke_line(&mut paint.canvas, start, end, stroke, color),
+            "arrow" => {
+                stroke_line(&mut paint.canvas, start, end, stroke, color);
+                // Two barbs at 30 degrees either side of the shaft
+                let (dx, dy) = ((end_x - start_x) as f64, (end_y - start_y) as f64);
+                let length = (dx * dx + dy * dy).sqrt();
+                if length > 0.0 {
+                    let barb = (length / 4.0).clamp(6.0, 40.0);
+                    let back = dy.atan2(dx) + std::f64::consts::PI;
+                    for side in [-1.0, 1.0] {
+                        let angle = back + side * std::f64::consts::FRAC_PI_6;
+                        let tip = (end_x + (barb * angle.cos()).round() as i32, end_y + (barb * angle.sin()).round() as i32);
+                        stroke_line(&mut paint.canvas, end, tip, stroke, color);
+                    }
+                }
+            }
+            _ => {
+                let outline = outline.unwrap_or_default();
+                if paint.fill == "solid" {
+                    fill_polygon(&mut paint.canvas, &outline, color);
+                }
+                stroke_polygon(&mut paint.canvas, &outline, stroke, color);
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_polyline(&self, hwnd: HWND, points: &[(i32, i32)], button: MouseButton) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let (color, stroke) = (paint.stroke_color(button), paint.stroke);
+        paint.checkpoint();
+        for segment in points.windows(2) {
+            stroke_line(&mut paint.canvas, segment[0], segment[1], stroke, color);
+        }
+        Ok(())
+    }
+
+    fn clear_canvas(&self, hwnd: HWND) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let (width, height) = (paint.canvas.width(), paint.canvas.height());
+        paint.checkpoint();
+        paint.canvas = RgbaImage::from_pixel(width, height, WHITE);
+        paint.selection = None;
+        Ok(())
+    }
+
+    fn select_region(&self, hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        paint.selection = clip_rect(&paint.canvas, (start_x, start_y), (end_x, end_y));
+        if paint.selection.is_none() {
+            return Err(MspMcpError::InvalidParameters("Selection is outside the canvas".to_string()));
+        }
+        Ok(())
+    }
+
+    fn copy_selection(&self, hwnd: HWND) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let (x, y, width, height) = paint.selection
+            .ok_or_else(|| MspMcpError::General("Nothing is selected".to_string()))?;
+        paint.clipboard = Some(imageops::crop_imm(&paint.canvas, x, y, width, height).to_image());
+        Ok(())
+    }
+
+    fn copy_selection_image(&self, hwnd: HWND) -> Result<CapturedImage> {
+        self.copy_selection(hwnd)?;
+        let paint = self.paint()?;
+        let clipboard = paint.clipboard.as_ref()
+            .ok_or_else(|| MspMcpError::General("The clipboard is empty".to_string()))?;
+        Ok(Self::to_captured(clipboard))
+    }
+
+    fn copy_canvas_image(&self, hwnd: HWND) -> Result<CapturedImage> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        paint.clipboard = Some(paint.canvas.clone());
+        Ok(Self::to_captured(&paint.canvas))
+    }
+
+    fn paste_at(&self, hwnd: HWND, x: i32, y: i32) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let clipboard = paint.clipboard.clone()
+            .ok_or_else(|| MspMcpError::General("The clipboard is empty".to_string()))?;
+        paint.checkpoint();
+        imageops::replace(&mut paint.canvas, &clipboard, x as i64, y as i64);
+        Ok(())
+    }
+
+    fn set_clipboard_image(&self, image: &CapturedImage) -> Result<()> {
+        let bitmap = RgbaImage::from_raw(image.width, image.height, image.rgba.clone())
+            .ok_or_else(|| MspMcpError::InvalidImageFormat(format!("{} bytes don't make a {}x{} image", image.rgba.len(), image.width, image.height)))?;
+        self.paint()?.clipboard = Some(bitmap);
+        Ok(())
+    }
+
+    fn stamp_clipboard_image(&self, hwnd: HWND, x: i32, y: i32, _width: u32, _height: u32) -> Result<()> {
+        // Pasted pixels land where asked and are committed at once
+        self.paste_at(hwnd, x, y)?;
+        self.paint()?.selection = None;
+        Ok(())
+    }
+
+    fn rotate_selection(&self, hwnd: HWND, degrees: u32) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let (x, y, width, height) = paint.selection
+            .ok_or_else(|| MspMcpError::General("Nothing is selected".to_string()))?;
+        let selected = imageops::crop_imm(&paint.canvas, x, y, width, height).to_image();
+        let rotated = match degrees {
+            90 => imageops::rotate90(&selected),
+            180 => imageops::rotate180(&selected),
+            270 => imageops::rotate270(&selected),
+            _ => return Err(MspMcpError::InvalidParameters(format!("Rotation must be 90, 180 or 270 degrees, got {}", degrees))),
+        };
+        paint.checkpoint();
+        // Paint leaves Color 2 behind and turns the selection about its center
+        let background = RgbaImage::from_pixel(width, height, paint.secondary);
+        imageops::replace(&mut paint.canvas, &background, x as i64, y as i64);
+        let left = x as i32 + (width as i32 - rotated.width() as i32) / 2;
+        let top = y as i32 + (height as i32 - rotated.height() as i32) / 2;
+        imageops::replace(&mut paint.canvas, &rotated, left as i64, top as i64);
+        paint.selection = clip_rect(&paint.canvas, (left, top), (left + rotated.width() as i32, top + rotated.height() as i32));
+        Ok(())
+    }
+
+    fn flip_selection(&self, hwnd: HWND, direction: &str) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let (x, y, width, height) = paint.selection
+            .ok_or_else(|| MspMcpError::General("Nothing is selected".to_string()))?;
+        let selected = imageops::crop_imm(&paint.canvas, x, y, width, height).to_image();
+        let flipped = match direction {
+            "horizontal" => imageops::flip_horizontal(&selected),
+            "vertical" => imageops::flip_vertical(&selected),
+            _ => return Err(MspMcpError::InvalidParameters(format!("Flip direction must be 'horizontal' or 'vertical', got '{}'", direction))),
+        };
+        paint.checkpoint();
+        imageops::replace(&mut paint.canvas, &flipped, x as i64, y as i64);
+        Ok(())
+    }
+
+    fn invert_colors(&self, hwnd: HWND, selection_only: bool) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let mut paint = self.paint()?;
+        let (x, y, width, height) = match paint.selection {
+            Some(selection) if selection_only => selection,
+            None if selection_only => return Err(MspMcpError::General("Nothing is selected".to_string())),
+            _ => (0, 0, paint.canvas.width(), paint.canvas.height()),
+        };
+        paint.checkpoint();
+        for py in y..y + height {
+            for px in x..x + width {
+                let Rgba([r, g, b, a]) = *paint.canvas.get_pixel(px, py);
+                paint.canvas.put_pixel(px, py, Rgba([255 - r, 255 - g, 255 - b, a]));
+            }
+        }
+        Ok(())
+    }
+
+    fn add_text(&self, hwnd: HWND, params: &AddTextParams) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        if let Some(color) = &params.color {
+            self.set_color(hwnd, color)?;
+        }
+        let mut paint = self.paint()?;
+        let color = paint.color;
+        let height = params.font_size.unwrap_or(DEFAULT_FONT_SIZE).max(1) as i32;
+        let advance = (height * 3 / 5).max(1);
+        paint.checkpoint();
+        for (i, ch) in params.text.chars().enumerate() {
+            if ch.is_whitespace() {
+                continue;
+            }
+            let left = params.x + i as i32 * advance;
+            let glyph = [(left, params.y), (left + advance - 2, params.y), (left + advance - 2, params.y + height - 1), (left, params.y + height - 1)];
+            fill_polygon(&mut paint.canvas, &glyph, color);
+        }
+        Ok(())
+    }
+
+    // Paint keeps the tool and colors for the new document
+    fn new_document(&self, hwnd: HWND, unsaved_changes: UnsavedChanges) -> Result<()> {
+        self.create_canvas(hwnd, DEFAULT_CANVAS_WIDTH, DEFAULT_CANVAS_HEIGHT, None, unsaved_changes)
+    }
+
+    fn create_canvas(&self, hwnd: HWND, width: u32, height: u32, background_color: Option<&str>, unsaved_changes: UnsavedChanges) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        if width == 0 || height == 0 {
+            return Err(MspMcpError::InvalidParameters(format!("Canvas size {}x{} is empty", width, height)));
+        }
+        let background = background_color.map(parse_color).transpose()?.unwrap_or(WHITE);
+        let mut paint = self.paint()?;
+        paint.check_unsaved(unsaved_changes)?;
+        paint.dirty = false;
+        paint.canvas = RgbaImage::from_pixel(width, height, background);
+        paint.selection = None;
+        // A new document starts with nothing to undo
+        paint.history.clear();
+        Ok(())
+    }
+
+    // Writes the canvas for real, but only as PNG (there is no JPEG or BMP encoder)
+    fn save_canvas(&self, hwnd: HWND, params: &SaveCanvasParams) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        if params.format != "png" {
+            return Err(MspMcpError::OperationNotSupported(format!("The simulated canvas can't be saved as {}", params.format)));
+        }
+        let path = std::path::Path::new(&params.file_path);
+        if path.exists() && !params.overwrite {
+            return Err(MspMcpError::ConfirmationRequired(
+                format!("{} already exists; pass overwrite: true to replace it", params.file_path)));
+        }
+        let png = crate::capture::encode_png(&self.capture_canvas(hwnd)?)?;
+        std::fs::write(path, png)?;
+        self.paint()?.dirty = false;
+        Ok(())
+    }
+
+    // Like Paint, keeps the image at the top left and fills new area with
+    // white; black and white thresholds every pixel
+    fn set_image_properties(&self, hwnd: HWND, params: &SetImagePropertiesParams) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let units = params.units.unwrap_or_default();
+        let mut paint = self.paint()?;
+        let (old_width, old_height) = (paint.canvas.width(), paint.canvas.height());
+        let width = params.width.map_or(old_width, |w| units.to_pixels(w) as u32);
+        let height = params.height.map_or(old_height, |h| units.to_pixels(h) as u32);
+        if width == 0 || height == 0 {
+            return Err(MspMcpError::InvalidParameters(format!("Canvas size {}x{} is empty", width, height)));
+        }
+        paint.checkpoint();
+        if (width, height) != (old_width, old_height) {
+            let mut resized = RgbaImage::from_pixel(width, height, WHITE);
+            imageops::replace(&mut resized, &paint.canvas, 0, 0);
+            paint.canvas = resized;
+            paint.selection = None;
+        }
+        if params.color_mode == Some(ColorMode::BlackAndWhite) {
+            for py in 0..height {
+                for px in 0..width {
+                    let Rgba([r, g, b, _]) = *paint.canvas.get_pixel(px, py);
+                    let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+                    paint.canvas.put_pixel(px, py, if luma < 128 { BLACK } else { WHITE });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_background(&self, hwnd: HWND, color: &str) -> Result<()> {
+        Self::check_hwnd(hwnd)?;
+        let background = parse_color(color)?;
+        let mut paint = self.paint()?;
+        let (width, height) = (paint.canvas.width(), paint.canvas.height());
+        paint.checkpoint();
+        paint.canvas = RgbaImage::from_pixel(width, height, background);
+        paint.secondary = background;
+        paint.selection = None;
+        Ok(())
+    }
+
+    // The simulated document is a single bitmap, like Paint before layers
+    fn add_layer(&self, _hwnd: HWND) -> Result<()> {
+        Err(layers_not_supported())
+    }
+
+    fn select_layer(&self, _hwnd: HWND, _index: u32) -> Result<()> {
+        Err(layers_not_supported())
+    }
+
+    fn set_layer_visibility(&self, _hwnd: HWND, _index: u32, _visible: bool) -> Result<()> {
+        Err(layers_not_supported())
+    }
+
+    fn merge_layer_down(&self, _hwnd: HWND, _index: u32) -> Result<()> {
+        Err(layers_not_supported())
+    }
+
+    fn delete_layer(&self, _hwnd: HWND, _index: u32) -> Result<()> {
+        Err(layers_not_supported())
+    }
+
+    fn capture_window(&self, hwnd: HWND) -> Result<CapturedImage> {
+        // The simulated window is all canvas
+        self.capture_canvas(hwnd)
+    }
+
+    fn capture_canvas(&self, hwnd: HWND) -> Result<CapturedImage> {
+        Self::check_hwnd(hwnd)?;
+        Ok(Self::to_captured(&self.paint()?.canvas))
+    }
+
+    fn capture_canvas_region(&self, hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<CapturedImage> {
+        Self::check_hwnd(hwnd)?;
+        let paint = self.paint()?;
+        let end = (x.saturating_add(width as i32), y.saturating_add(height as i32));
+        let (left, top, width, height) = clip_rect(&paint.canvas, (x, y), end).ok_or_else(|| {
+            MspMcpError::InvalidParameters(format!("Region {}x{} at ({}, {}) is outside the {}x{} canvas",
+                width, height, x, y, paint.canvas.width(), paint.canvas.height()))
+        })?;
+        Ok(Self::to_captured(&imageops::crop_imm(&paint.canvas, left, top, width, height).to_image()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn red() -> Rgba<u8> {
+        parse_color("#FF0000").unwrap()
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#10a0Ff").unwrap(), Rgba([0x10, 0xA0, 0xFF, 0xFF]));
+        assert!(parse_color("10A0FF").is_err());
+        assert!(parse_color("#10A0FG").is_err());
+    }
+
+    #[test]
+    fn test_thick_line_covers_its_width() {
+        let mut canvas = RgbaImage::from_pixel(20, 20, WHITE);
+        stroke_line(&mut canvas, (2, 10), (17, 10), 3, red());
+        assert_eq!(*canvas.get_pixel(10, 9), red());
+        assert_eq!(*canvas.get_pixel(10, 11), red());
+        assert_eq!(*canvas.get_pixel(10, 12), WHITE);
+        assert_eq!(*canvas.get_pixel(19, 10), WHITE);
+    }
+
+    #[test]
+    fn test_solid_rectangle_fills_interior() {
+        let mut canvas = RgbaImage::from_pixel(20, 20, WHITE);
+        let outline = shape_outline("rectangle", (15, 15), (5, 5)).unwrap();
+        fill_polygon(&mut canvas, &outline, red());
+        assert_eq!(*canvas.get_pixel(10, 10), red());
+        assert_eq!(*canvas.get_pixel(16, 10), WHITE);
+        assert!(shape_outline("star", (0, 0), (5, 5)).is_err());
+    }
+
+    #[test]
+    fn test_flood_fill_stops_at_borders() {
+        let mut canvas = RgbaImage::from_pixel(10, 10, WHITE);
+        stroke_line(&mut canvas, (5, 0), (5, 9), 1, BLACK);
+        flood_fill(&mut canvas, 1, 1, red());
+        assert_eq!(*canvas.get_pixel(4, 9), red());
+        assert_eq!(*canvas.get_pixel(5, 5), BLACK);
+        assert_eq!(*canvas.get_pixel(6, 5), WHITE);
+    }
+
+    #[test]
+    fn test_copy_paste_and_capture_region() {
+        let backend = SimulatedBackend::new();
+        backend.set_color(SIMULATED_HWND, "#FF0000").unwrap();
+        backend.draw_pixel(SIMULATED_HWND, 1, 1, MouseButton::Left).unwrap();
+        backend.select_region(SIMULATED_HWND, 0, 0, 3, 3).unwrap();
+        let copied = backend.copy_selection_image(SIMULATED_HWND).unwrap();
+        assert_eq!((copied.width, copied.pixel_hex(1, 1).as_deref()), (3, Some("#FF0000")));
+        backend.paste_at(SIMULATED_HWND, 100, 100).unwrap();
+
+        let region = backend.capture_canvas_region(SIMULATED_HWND, 100, 100, 3, 3).unwrap();
+        assert_eq!((region.width, region.height), (3, 3));
+        assert_eq!(&region.rgba[16..20], &[0xFF, 0x00, 0x00, 0xFF]); // Pixel (1, 1)
+        assert!(backend.capture_canvas_region(SIMULATED_HWND, 5000, 0, 3, 3).is_err());
+    }
+
+    #[test]
+    fn test_undo_restores_previous_canvas() {
+        let backend = SimulatedBackend::new();
+        let blank = backend.capture_canvas(SIMULATED_HWND).unwrap().rgba;
+        backend.draw_line(SIMULATED_HWND, 0, 0, 50, 50, MouseButton::Left).unwrap();
+        let line = backend.capture_canvas(SIMULATED_HWND).unwrap().rgba;
+        backend.draw_pixel(SIMULATED_HWND, 100, 100, MouseButton::Left).unwrap();
+
+        backend.undo(SIMULATED_HWND).unwrap();
+        assert!(backend.capture_canvas(SIMULATED_HWND).unwrap().rgba == line);
+        backend.undo(SIMULATED_HWND).unwrap();
+        backend.undo(SIMULATED_HWND).unwrap(); // Nothing left: no-op
+        assert!(backend.capture_canvas(SIMULATED_HWND).unwrap().rgba == blank);
+    }
+
+    #[test]
+    fn test_right_button_draws_in_secondary_color() {
+        let backend = SimulatedBackend::new();
+        backend.set_color(SIMULATED_HWND, "#FF0000").unwrap();
+        backend.set_secondary_color(SIMULATED_HWND, "#0000FF").unwrap();
+        backend.draw_line(SIMULATED_HWND, 0, 5, 9, 5, MouseButton::Right).unwrap();
+        backend.draw_pixel(SIMULATED_HWND, 0, 0, MouseButton::Left).unwrap();
+
+        let canvas = backend.capture_canvas_region(SIMULATED_HWND, 0, 0, 10, 10).unwrap();
+        let pixel = |x: usize, y: usize| &canvas.rgba[(y * 10 + x) * 4..(y * 10 + x) * 4 + 4];
+        assert_eq!(pixel(4, 5), &[0x00, 0x00, 0xFF, 0xFF]);
+        assert_eq!(pixel(0, 0), &[0xFF, 0x00, 0x00, 0xFF]);
+        assert!(backend.set_secondary_color(SIMULATED_HWND, "blue").is_err());
+    }
+
+    #[test]
+    fn test_set_tool_state_applies_all_or_nothing() {
+        let backend = SimulatedBackend::new();
+        let state = SetToolStateParams {
+            tool: Some("pencil".to_string()), color: Some("#00FF00".to_string()), thickness_px: Some(3), ..Default::default()
+        };
+        backend.set_tool_state(SIMULATED_HWND, &state).unwrap();
+
+        let bad = SetToolStateParams {
+            color: Some("#FF0000".to_string()), fill_type: Some("dotted".to_string()), ..Default::default()
+        };
+        assert!(backend.set_tool_state(SIMULATED_HWND, &bad).is_err());
+        backend.draw_line(SIMULATED_HWND, 0, 5, 9, 5, MouseButton::Left).unwrap();
+        let canvas = backend.capture_canvas_region(SIMULATED_HWND, 0, 0, 10, 10).unwrap();
+        assert_eq!((canvas.pixel_hex(4, 4).as_deref(), canvas.pixel_hex(4, 5).as_deref()), (Some("#00FF00"), Some("#00FF00")));
+    }
+
+    #[test]
+    fn test_selection_transforms_stay_inside_the_selection() {
+        let backend = SimulatedBackend::new();
+        assert!(backend.flip_selection(SIMULATED_HWND, "horizontal").is_err());
+        backend.set_color(SIMULATED_HWND, "#FF0000").unwrap();
+        backend.draw_pixel(SIMULATED_HWND, 0, 0, MouseButton::Left).unwrap();
+        backend.draw_pixel(SIMULATED_HWND, 20, 0, MouseButton::Left).unwrap();
+        backend.select_region(SIMULATED_HWND, 0, 0, 3, 3).unwrap();
+
+        backend.flip_selection(SIMULATED_HWND, "horizontal").unwrap();
+        let canvas = backend.capture_canvas_region(SIMULATED_HWND, 0, 0, 21, 4).unwrap();
+        let pixel = |x: usize, y: usize| &canvas.rgba[(y * 21 + x) * 4..(y * 21 + x) * 4 + 4];
+        assert_eq!(pixel(0, 0), &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixel(2, 0), &[0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(20, 0), &[0xFF, 0x00, 0x00, 0xFF]); // Outside the selection
+
+        backend.rotate_selection(SIMULATED_HWND, 90).unwrap();
+        let canvas = backend.capture_canvas_region(SIMULATED_HWND, 0, 0, 3, 3).unwrap();
+        assert_eq!(&canvas.rgba[(2 * 3 + 2) * 4..(2 * 3 + 2) * 4 + 4], &[0xFF, 0x00, 0x00, 0xFF]);
+        assert!(backend.rotate_selection(SIMULATED_HWND, 45).is_err());
+    }
+
+    #[test]
+    fn test_create_canvas_follows_the_unsaved_changes_policy() {
+        let backend = SimulatedBackend::new();
+        backend.create_canvas(SIMULATED_HWND, 100, 100, None, UnsavedChanges::Fail).unwrap();
+        backend.draw_pixel(SIMULATED_HWND, 1, 1, MouseButton::Left).unwrap();
+        assert_eq!(backend.window_title(SIMULATED_HWND), "*Untitled - Paint");
+
+        let err = backend.create_canvas(SIMULATED_HWND, 50, 50, None, UnsavedChanges::Fail).unwrap_err();
+        assert!(matches!(err, MspMcpError::ConfirmationRequired(_)));
+        assert_eq!(backend.capture_canvas(SIMULATED_HWND).unwrap().width, 100);
+        assert!(backend.create_canvas(SIMULATED_HWND, 50, 50, None, UnsavedChanges::Save).is_err());
+
+        backend.create_canvas(SIMULATED_HWND, 50, 50, None, UnsavedChanges::Discard).unwrap();
+        assert_eq!(backend.window_title(SIMULATED_HWND), "Untitled - Paint");
+    }
+
+    #[test]
+    fn test_save_canvas_refuses_to_overwrite() {
+        let backend = SimulatedBackend::new();
+        let path = std::env::temp_dir().join(format!("mspmcp-save-{}.png", std::process::id()));
+        let params = |overwrite| SaveCanvasParams {
+            file_path: path.display().to_string(), format: "png".to_string(), overwrite, jpeg_quality: None, create_dirs: false,
+        };
+        let _ = std::fs::remove_file(&path);
+        backend.draw_pixel(SIMULATED_HWND, 0, 0, MouseButton::Left).unwrap();
+        backend.save_canvas(SIMULATED_HWND, &params(false)).unwrap();
+        assert_eq!(backend.window_title(SIMULATED_HWND), "Untitled - Paint");
+
+        let err = backend.save_canvas(SIMULATED_HWND, &params(false)).unwrap_err();
+        assert!(matches!(err, MspMcpError::ConfirmationRequired(_)));
+        backend.save_canvas(SIMULATED_HWND, &params(true)).unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_set_background_fills_the_canvas() {
+        let backend = SimulatedBackend::new();
+        backend.draw_line(SIMULATED_HWND, 0, 0, 50, 50, MouseButton::Left).unwrap();
+        backend.set_background(SIMULATED_HWND, "#00FF00").unwrap();
+        let canvas = backend.capture_canvas(SIMULATED_HWND).unwrap();
+        assert!(canvas.rgba.chunks_exact(4).all(|px| px == [0x00, 0xFF, 0x00, 0xFF]));
+
+        // Erasing now leaves the background color behind
+        backend.draw_line(SIMULATED_HWND, 0, 0, 10, 0, MouseButton::Right).unwrap();
+        assert_eq!(backend.capture_canvas(SIMULATED_HWND).unwrap().pixel_hex(5, 0).as_deref(), Some("#00FF00"));
+        assert!(backend.set_background(SIMULATED_HWND, "green").is_err());
+    }
+
+    #[test]
+    fn test_invert_colors_of_selection() {
+        let backend = SimulatedBackend::new();
+        assert!(backend.invert_colors(SIMULATED_HWND, true).is_err());
+        backend.select_region(SIMULATED_HWND, 0, 0, 2, 2).unwrap();
+        let before = backend.capture_canvas_region(SIMULATED_HWND, 0, 0, 4, 1).unwrap();
+        backend.invert_colors(SIMULATED_HWND, true).unwrap();
+
+        let after = backend.capture_canvas_region(SIMULATED_HWND, 0, 0, 4, 1).unwrap();
+        assert!(after.shows_inversion_of(&before));
+        assert_eq!(after.pixel_hex(1, 0).as_deref(), Some("#000000"));
+        assert_eq!(after.pixel_hex(2, 0).as_deref(), Some("#FFFFFF"));
+
+        backend.invert_colors(SIMULATED_HWND, false).unwrap();
+        assert_eq!(backend.capture_canvas_region(SIMULATED_HWND, 0, 0, 4, 1).unwrap().pixel_hex(2, 0).as_deref(), Some("#000000"));
+    }
+
+    #[test]
+    fn test_set_image_properties_resizes_and_converts() {
+        let backend = SimulatedBackend::new();
+        backend.create_canvas(SIMULATED_HWND, 10, 10, Some("#FF8080"), UnsavedChanges::Fail).unwrap();
+        backend.set_color(SIMULATED_HWND, "#400000").unwrap();
+        backend.draw_pixel(SIMULATED_HWND, 1, 1, MouseButton::Left).unwrap();
+
+        let grow = SetImagePropertiesParams { width: Some(20.0), ..Default::default() };
+        backend.set_image_properties(SIMULATED_HWND, &grow).unwrap();
+        let canvas = backend.capture_canvas(SIMULATED_HWND).unwrap();
+        assert_eq!((canvas.width, canvas.height), (20, 10));
+        assert_eq!((canvas.pixel_hex(1, 1).as_deref(), canvas.pixel_hex(15, 1).as_deref()), (Some("#400000"), Some("#FFFFFF")));
+
+        let monochrome = SetImagePropertiesParams {
+            height: Some(0.1), units: Some(crate::protocol::ImageUnits::Inches), color_mode: Some(ColorMode::BlackAndWhite), ..Default::default()
+        };
+        backend.set_image_properties(SIMULATED_HWND, &monochrome).unwrap();
+        let canvas = backend.capture_canvas(SIMULATED_HWND).unwrap();
+        assert_eq!((canvas.width, canvas.height), (20, 10));
+        assert_eq!((canvas.pixel_hex(1, 1).as_deref(), canvas.pixel_hex(2, 2).as_deref()), (Some("#000000"), Some("#FFFFFF")));
+
+        backend.undo(SIMULATED_HWND).unwrap();
+        assert_eq!(backend.capture_canvas(SIMULATED_HWND).unwrap().pixel_hex(2, 2).as_deref(), Some("#FF8080"));
+    }
+}
@@ -0,0 +1,219 @@
+// Width variation along a polyline.
+//
+// Paint draws a stroke at one brush size from end to end, which is a large
+// part of why drawn art looks mechanical. A `width_profile` on
+// draw_polyline varies the width along the stroke instead: tapering in at
+// the start and out at the end, as a pen lifted off the paper would, or
+// giving each point a width of its own. Paint can't change the size in the
+// middle of a drag, so the path is cut into short segments, each segment
+// gets the width at its middle, and runs of segments that round to the same
+// whole-pixel width are drawn as one stroke at that size. A gentle taper
+// therefore costs a few size changes, not one per segment.
+
+use crate::error::{MspMcpError, Result};
+use crate::protocol::{WidthProfile, MAX_THICKNESS_PX};
+
+// Longest stretch of the path given a single width
+const SEGMENT_PX: f64 = 6.0;
+
+// Most segments a path is cut into; longer paths get longer segments
+const MAX_SEGMENTS: f64 = 2000.0;
+
+/// A stroke of the path and the width in pixels to draw it at.
+pub type Piece = (u32, Vec<(i32, i32)>);
+
+/// Cuts `path` into strokes, each with the width in pixels to draw it at.
+/// Consecutive strokes share their end points, so they join up. `points`
+/// are the points the request gave, which per-point widths belong to; the
+/// path may have been smoothed or simplified from them. Tapers run from
+/// `full_px`, the width of the stroke.
+pub fn pieces(path: &[(i32, i32)], points: &[(i32, i32)], profile: &WidthProfile, full_px: Option<u32>) -> Result<Vec<Piece>> {
+    let width_at = width_function(points, profile, full_px)?;
+    let path = densify(path);
+    if path.len() < 2 {
+        return Ok(vec![(whole_px(width_at(0.0)), path)]);
+    }
+
+    let along = fractions(&path);
+    let mut pieces: Vec<Piece> = Vec::new();
+    for i in 1..path.len() {
+        let width = whole_px(width_at((along[i - 1] + along[i]) / 2.0));
+        match pieces.last_mut() {
+            Some((last_width, stroke)) if *last_width == width => stroke.push(path[i]),
+            _ => pieces.push((width, vec![path[i - 1], path[i]])),
+        }
+    }
+    Ok(pieces)
+}
+
+// Width at each fraction (0 to 1) of the way along the stroke
+fn width_function(points: &[(i32, i32)], profile: &WidthProfile, full_px: Option<u32>) -> Result<Box<dyn Fn(f64) -> f64>> {
+    let tapered = profile.taper_in.is_some() || profile.taper_out.is_some();
+    match (&profile.widths, tapered) {
+        (Some(_), true) => Err(MspMcpError::InvalidParameters(
+            "width_profile takes either widths or taper_in/taper_out, not both".to_string())),
+        (None, false) => Err(MspMcpError::InvalidParameters(
+            "width_profile needs widths or taper_in/taper_out".to_string())),
+        (Some(widths), false) => {
+            if widths.len() != points.len() {
+                return Err(MspMcpError::InvalidParameters(
+                    format!("width_profile has {} widths for {} points", widths.len(), points.len())));
+            }
+            for &px in widths {
+                check_px("widths", px)?;
+            }
+            let at: Vec<f64> = fractions(points);
+            let widths: Vec<f64> = widths.iter().map(|&px| px as f64).collect();
+            Ok(Box::new(move |t| interpolate(&at, &widths, t)))
+        }
+        (None, true) => {
+            let full_px = full_px.ok_or_else(|| MspMcpError::InvalidParameters(
+                "A tapered width_profile needs thickness_px, the width between the tapers".to_string()))?;
+            let min_px = profile.min_px.unwrap_or(1);
+            check_px("min_px", min_px)?;
+            let taper_in = taper_part("taper_in", profile.taper_in)?;
+            let taper_out = taper_part("taper_out", profile.taper_out)?;
+            let (full, min) = (full_px as f64, min_px as f64);
+            Ok(Box::new(move |t| {
+                let mut ramp: f64 = 1.0;
+                if taper_in > 0.0 {
+                    ramp = ramp.min(t / taper_in);
+                }
+                if taper_out > 0.0 {
+                    ramp = ramp.min((1.0 - t) / taper_out);
+                }
+                min + (full - min) * ramp.clamp(0.0, 1.0)
+            }))
+        }
+    }
+}
+
+fn check_px(name: &str, px: u32) -> Result<()> {
+    if (1..=MAX_THICKNESS_PX).contains(&px) {
+        Ok(())
+    } else {
+        Err(MspMcpError::InvalidParameters(format!("{} must be between 1 and {} pixels, got {}", name, MAX_THICKNESS_PX, px)))
+    }
+}
+
+fn taper_part(name: &str, part: Option<f64>) -> Result<f64> {
+    match part {
+        None => Ok(0.0),
+        Some(part) if (0.0..=1.0).contains(&part) => Ok(part),
+        Some(part) => Err(MspMcpError::InvalidParameters(format!("{} must be between 0 and 1 of the stroke, got {}", name, part))),
+    }
+}
+
+fn whole_px(width: f64) -> u32 {
+    (width.round() as u32).clamp(1, MAX_THICKNESS_PX)
+}
+
+// Length of the segment from `a` to `b`, in f64 so far-apart points can't overflow
+fn length(a: (i32, i32), b: (i32, i32)) -> f64 {
+    (b.0 as f64 - a.0 as f64).hypot(b.1 as f64 - a.1 as f64)
+}
+
+// How far along the path each point is, as a fraction of its length
+fn fractions(path: &[(i32, i32)]) -> Vec<f64> {
+    let mut lengths = vec![0.0];
+    for pair in path.windows(2) {
+        lengths.push(lengths[lengths.len() - 1] + length(pair[0], pair[1]));
+    }
+    let total = lengths[lengths.len() - 1];
+    lengths.iter().map(|length| if total > 0.0 { length / total } else { 0.0 }).collect()
+}
+
+// Linear interpolation of `values` at `t`, given at the fractions `at`
+fn interpolate(at: &[f64], values: &[f64], t: f64) -> f64 {
+    if at.len() < 2 {
+        return values.first().copied().unwrap_or(1.0);
+    }
+    let next = at.partition_point(|&fraction| fraction < t).clamp(1, at.len() - 1);
+    let (a, b) = (at[next - 1], at[next]);
+    if b <= a {
+        return values[next];
+    }
+    values[next - 1] + (values[next] - values[next - 1]) * ((t - a) / (b - a)).clamp(0.0, 1.0)
+}
+
+// The path with points added so no segment is longer than SEGMENT_PX, or
+// than a MAX_SEGMENTS share of a very long path
+fn densify(path: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    let total: f64 = path.windows(2).map(|pair| length(pair[0], pair[1])).sum();
+    let segment_px = SEGMENT_PX.max(total / MAX_SEGMENTS);
+    let mut dense: Vec<(i32, i32)> = path.first().copied().into_iter().collect();
+    for pair in path.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        let steps = (length(pair[0], pair[1]) / segment_px).ceil().max(1.0) as u32;
+        // Between two i32s, so the rounded result fits in an i32 again
+        let between = |from: i32, to: i32, t: f64| (from as f64 + (to as f64 - from as f64) * t).round() as i32;
+        for step in 1..=steps {
+            let t = step as f64 / steps as f64;
+            let point = (between(x0, x1, t), between(y0, y1, t));
+            if dense.last() != Some(&point) {
+                dense.push(point);
+            }
+        }
+    }
+    dense
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn taper(taper_in: Option<f64>, taper_out: Option<f64>) -> WidthProfile {
+        WidthProfile { widths: None, taper_in, taper_out, min_px: None }
+    }
+
+    #[test]
+    fn test_tapered_stroke_widens_then_narrows_in_joined_pieces() {
+        let line = [(0, 0), (120, 0)];
+        let pieces = pieces(&line, &line, &taper(Some(0.5), Some(0.5)), Some(9)).unwrap();
+        let widths: Vec<u32> = pieces.iter().map(|(width, _)| *width).collect();
+        let peak = widths.iter().position(|&width| width == 9).unwrap();
+        assert!(widths[..=peak].windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(widths[peak..].windows(2).all(|pair| pair[0] > pair[1]));
+        assert!(widths[0] <= 2 && widths[widths.len() - 1] <= 2);
+        // Each piece starts where the one before ended, covering the whole line
+        assert!(pieces.windows(2).all(|pair| pair[0].1.last() == pair[1].1.first()));
+        assert_eq!(pieces[0].1[0], (0, 0));
+        assert_eq!(*pieces[pieces.len() - 1].1.last().unwrap(), (120, 0));
+
+        // Only the start tapers; the rest is drawn as one stroke
+        let pieces = super::pieces(&line, &line, &taper(Some(0.25), None), Some(5)).unwrap();
+        assert_eq!(pieces.last().unwrap().0, 5);
+        assert!(pieces.last().unwrap().1.len() > 10);
+    }
+
+    #[test]
+    fn test_per_point_widths_follow_the_given_points() {
+        let points = [(0, 0), (60, 0), (60, 60)];
+        let profile = WidthProfile { widths: Some(vec![2, 10, 2]), taper_in: None, taper_out: None, min_px: None };
+        let pieces = pieces(&points, &points, &profile, None).unwrap();
+        let widest = pieces.iter().max_by_key(|(width, _)| *width).unwrap();
+        assert!(widest.0 >= 9);
+        // The widest piece is around the corner
+        assert!(widest.1.iter().any(|&(x, y)| (x - 60).abs() + y.abs() <= 12));
+
+        let wrong_count = WidthProfile { widths: Some(vec![2, 10]), ..profile.clone() };
+        assert!(super::pieces(&points, &points, &wrong_count, None).is_err());
+        let zero = WidthProfile { widths: Some(vec![0, 1, 1]), ..profile.clone() };
+        assert!(super::pieces(&points, &points, &zero, None).is_err());
+        let both = WidthProfile { taper_in: Some(0.2), ..profile };
+        assert!(super::pieces(&points, &points, &both, Some(4)).is_err());
+        assert!(super::pieces(&points, &points, &taper(Some(0.2), None), None).is_err());
+        assert!(super::pieces(&points, &points, &taper(Some(1.5), None), Some(4)).is_err());
+        assert!(super::pieces(&points, &points, &taper(None, None), Some(4)).is_err());
+    }
+
+    #[test]
+    fn test_far_apart_points_neither_overflow_nor_explode() {
+        let line = [(-2_000_000_000, 0), (2_000_000_000, 0)];
+        let pieces = pieces(&line, &line, &taper(Some(0.5), Some(0.5)), Some(9)).unwrap();
+        let points: usize = pieces.iter().map(|(_, piece)| piece.len()).sum();
+        assert!(points <= MAX_SEGMENTS as usize + pieces.len());
+        assert_eq!(pieces[0].1[0], line[0]);
+        assert_eq!(*pieces[pieces.len() - 1].1.last().unwrap(), line[1]);
+    }
+}
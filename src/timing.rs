@@ -0,0 +1,218 @@
+// Where a request's time went.
+//
+// Tuning the waits in windows.rs and uia.rs needs more than a request's
+// total duration. While a request runs, PaintServerState installs a tracker
+// on its thread (like the clock), and the Win32 and UIA helpers mark their
+// work with `enter` or `measure`: bringing Paint to the foreground, UI
+// Automation lookups and SendInput calls. `clock::sleep` adds its waits on
+// its own. Time is counted once, in the innermost phase, so a sleep inside
+// a UIA lookup shows up as sleep only.
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Activation, // Bringing Paint to the foreground
+    UiaLookup,  // Finding and invoking ribbon controls, dialogs and panes with UI Automation
+    Input,      // Sending mouse and keyboard input
+    Sleep,      // Waiting for Paint to react
+}
+
+/// Time spent in each phase.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Breakdown {
+    pub activation: Duration,
+    pub uia_lookup: Duration,
+    pub input: Duration,
+    pub sleep: Duration,
+}
+
+impl Breakdown {
+    fn add(&mut self, phase: Phase, duration: Duration) {
+        let total = match phase {
+            Phase::Activation => &mut self.activation,
+            Phase::UiaLookup => &mut self.uia_lookup,
+            Phase::Input => &mut self.input,
+            Phase::Sleep => &mut self.sleep,
+        };
+        *total += duration;
+    }
+
+    fn merge(&mut self, other: &Breakdown) {
+        self.activation += other.activation;
+        self.uia_lookup += other.uia_lookup;
+        self.input += other.input;
+        self.sleep += other.sleep;
+    }
+
+    /// Time outside the tracked phases, out of `total`.
+    pub fn other(&self, total: Duration) -> Duration {
+        total.saturating_sub(self.activation + self.uia_lookup + self.input + self.sleep)
+    }
+
+    /// The breakdown in milliseconds, with whatever is left of `total` as `other_ms`.
+    pub fn to_json(&self, total: Duration) -> serde_json::Value {
+        serde_json::json!({
+            "activation_ms": self.activation.as_millis() as u64,
+            "uia_lookup_ms": self.uia_lookup.as_millis() as u64,
+            "input_ms": self.input.as_millis() as u64,
+            "sleep_ms": self.sleep.as_millis() as u64,
+            "other_ms": self.other(total).as_millis() as u64,
+        })
+    }
+
+    /// One-line summary for the log.
+    pub fn describe(&self, total: Duration) -> String {
+        format!("activation {:?}, UIA lookup {:?}, input {:?}, sleeps {:?}, other {:?}",
+            self.activation, self.uia_lookup, self.input, self.sleep, self.other(total))
+    }
+}
+
+#[derive(Default)]
+struct Tracker {
+    breakdown: Breakdown,
+    // Time already counted by nested phases, for each phase in progress
+    nested: Vec<Duration>,
+}
+
+impl Tracker {
+    fn add(&mut self, phase: Phase, duration: Duration) {
+        self.breakdown.add(phase, duration);
+        if let Some(nested) = self.nested.last_mut() {
+            *nested += duration;
+        }
+    }
+}
+
+thread_local! {
+    static TRACKER: RefCell<Option<Tracker>> = const { RefCell::new(None) };
+}
+
+/// Stops tracking when dropped. A request started inside another one (a
+/// `tools/call`) hands its time on to the outer request.
+pub struct TimingGuard {
+    previous: Option<Tracker>,
+}
+
+impl TimingGuard {
+    /// Time tracked so far.
+    pub fn breakdown(&self) -> Breakdown {
+        TRACKER.with(|tracker| tracker.borrow().as_ref().map(|t| t.breakdown).unwrap_or_default())
+    }
+}
+
+impl Drop for TimingGuard {
+    fn drop(&mut self) {
+        let mut previous = self.previous.take();
+        TRACKER.with(|tracker| {
+            let mut tracker = tracker.borrow_mut();
+            if let (Some(finished), Some(outer)) = (tracker.as_ref(), previous.as_mut()) {
+                outer.breakdown.merge(&finished.breakdown);
+            }
+            *tracker = previous;
+        });
+    }
+}
+
+/// Starts tracking on this thread until the guard is dropped.
+pub fn start() -> TimingGuard {
+    let previous = TRACKER.with(|tracker| tracker.borrow_mut().replace(Tracker::default()));
+    TimingGuard { previous }
+}
+
+/// Adds time spent in `phase` to the current request, if one is tracked.
+pub fn record(phase: Phase, duration: Duration) {
+    TRACKER.with(|tracker| {
+        if let Some(tracker) = tracker.borrow_mut().as_mut() {
+            tracker.add(phase, duration);
+        }
+    });
+}
+
+/// Counts the time until the guard is dropped as `phase`, less the time
+/// spent in phases entered meanwhile.
+pub struct PhaseGuard {
+    phase: Phase,
+    started: std::time::Instant,
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        let elapsed = crate::clock::now() - self.started;
+        TRACKER.with(|tracker| {
+            if let Some(tracker) = tracker.borrow_mut().as_mut() {
+                let nested = tracker.nested.pop().unwrap_or_default();
+                tracker.breakdown.add(self.phase, elapsed.saturating_sub(nested));
+                if let Some(outer) = tracker.nested.last_mut() {
+                    *outer += elapsed;
+                }
+            }
+        });
+    }
+}
+
+/// Starts counting time as `phase` until the guard is dropped.
+pub fn enter(phase: Phase) -> PhaseGuard {
+    TRACKER.with(|tracker| {
+        if let Some(tracker) = tracker.borrow_mut().as_mut() {
+            tracker.nested.push(Duration::ZERO);
+        }
+    });
+    PhaseGuard { phase, started: crate::clock::now() }
+}
+
+/// Runs `f`, counting the time it takes as `phase`.
+pub fn measure<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let _phase = enter(phase);
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{self, VirtualClock};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_time_counts_in_the_innermost_phase() {
+        let clock = Arc::new(VirtualClock::new());
+        let _clock = clock::install(clock.clone());
+        let timing = start();
+
+        measure(Phase::UiaLookup, || {
+            clock.advance(Duration::from_millis(300));
+            clock::sleep(Duration::from_millis(200));
+            measure(Phase::Input, || clock.advance(Duration::from_millis(50)));
+        });
+        measure(Phase::Activation, || clock.advance(Duration::from_millis(10)));
+        clock::sleep(Duration::from_millis(100));
+
+        let breakdown = timing.breakdown();
+        assert_eq!(breakdown, Breakdown {
+            activation: Duration::from_millis(10),
+            uia_lookup: Duration::from_millis(300),
+            input: Duration::from_millis(50),
+            sleep: Duration::from_millis(300),
+        });
+        assert_eq!(breakdown.to_json(Duration::from_millis(700))["other_ms"], 40);
+    }
+
+    #[test]
+    fn test_nested_requests_add_to_the_outer_one() {
+        let clock = Arc::new(VirtualClock::new());
+        let _clock = clock::install(clock.clone());
+        let outer = start();
+        clock::sleep(Duration::from_millis(5));
+        {
+            let inner = start();
+            measure(Phase::Input, || clock.advance(Duration::from_millis(20)));
+            assert_eq!(inner.breakdown().sleep, Duration::ZERO);
+        }
+        assert_eq!((outer.breakdown().sleep, outer.breakdown().input), (Duration::from_millis(5), Duration::from_millis(20)));
+        drop(outer);
+
+        // Untracked threads just run the work
+        assert_eq!(measure(Phase::Input, || 7), 7);
+    }
+}
@@ -0,0 +1,834 @@
+// MCP tool definitions for the Paint methods.
+//
+// Each Paint method is exposed as a tool through `tools/list` and invoked
+// through `tools/call`. Annotations tell hosts which tools only read state
+// and which ones can destroy work, so they can ask the user before running
+// the latter.
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::protocol::PAINT_METHODS;
+
+// OpenRPC specification version of the document from api_schema
+const OPENRPC_VERSION: &str = "1.2.6";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
+    pub read_only_hint: bool,   // Doesn't change Paint or the canvas
+    pub destructive_hint: bool, // May discard existing work
+    pub idempotent_hint: bool,  // Repeating the call has no further effect
+    pub open_world_hint: bool,  // Interacts with things outside Paint
+}
+
+// Methods that only report state
+const READ_ONLY_METHODS: &[&str] = &[
+    "get_version", "get_supported_tools", "get_canvas_dimensions", "get_window_info",
+    "export_canvas", "capture_window", "get_canvas_region", "get_document_info", "fetch_image", "get_audit_log",
+];
+
+// Methods that change only the server's own state and send Paint no input
+const NO_INPUT_METHODS: &[&str] = &[
+    "set_log_level", "define_palette", "set_snap", "push_transform", "pop_transform",
+    "pen_move_to", "pen_line_to", "pen_close", "register_macro", "checkpoint",
+];
+
+// Methods that can throw away existing canvas content or documents
+const DESTRUCTIVE_METHODS: &[&str] = &[
+    "clear_canvas", "new_document", "close_document", "quit_paint", "create_canvas", "set_image_properties", "set_background", "self_test", "delete_layer",
+    "revert_to_checkpoint",
+];
+
+// Methods that replace an existing file when called with "overwrite": true
+const OVERWRITING_METHODS: &[&str] = &["save_canvas", "save_as"];
+
+// Methods that run other requests back through the request path, where
+// each of those counts and guards itself
+const REENTRANT_METHODS: &[&str] = &["tools/call", "run_macro", "draw_animation"];
+
+// Methods that change the canvas pixels
+const CANVAS_MUTATING_METHODS: &[&str] = &[
+    "draw_pixel", "draw_line", "draw_shape", "draw_polyline", "draw_ellipse", "draw_gradient", "fill_pattern", "draw_glyph_text", "pen_stroke", "add_text",
+    "paste", "stamp_image", "rotate_selection", "flip_selection", "invert_colors", "clear_canvas", "new_document", "close_document", "create_canvas", "set_image_properties",
+    "set_background", "replay_input", "run_benchmark",
+    "self_test", "set_layer_visibility", "merge_layers", "delete_layer", "revert_to_checkpoint",
+];
+
+/// True if a successful call changes the canvas content. Registered tools
+/// that aren't read-only are assumed to draw.
+pub fn mutates_canvas(method: &str) -> bool {
+    CANVAS_MUTATING_METHODS.contains(&method) || plugin_draws(method)
+}
+
+/// True if the method can discard existing work.
+pub fn is_destructive(method: &str) -> bool {
+    DESTRUCTIVE_METHODS.contains(&method)
+        || crate::plugins::get(method).is_some_and(|tool| tool.annotations().destructive_hint)
+}
+
+/// True if this call of `method` needs "confirm": true while
+/// confirm_destructive is on: destructive methods always, and saves only
+/// when they may overwrite a file.
+pub fn needs_confirm(method: &str, params: Option<&Value>) -> bool {
+    is_destructive(method)
+        || (OVERWRITING_METHODS.contains(&method)
+            && params.and_then(|p| p.get("overwrite")).and_then(Value::as_bool) == Some(true))
+}
+
+/// True if the method may drive Paint with synthesized mouse or keyboard input.
+pub fn sends_input(method: &str) -> bool {
+    (crate::protocol::PAINT_METHODS.contains(&method)
+        && !READ_ONLY_METHODS.contains(&method)
+        && !NO_INPUT_METHODS.contains(&method))
+        || plugin_draws(method)
+}
+
+/// True if the method runs other requests through the request path.
+pub fn reenters_request_path(method: &str) -> bool {
+    REENTRANT_METHODS.contains(&method)
+}
+
+fn plugin_draws(method: &str) -> bool {
+    crate::plugins::get(method).is_some_and(|tool| !tool.annotations().read_only_hint)
+}
+
+/// Returns the annotations for a method.
+pub fn annotations_for(method: &str) -> ToolAnnotations {
+    if let Some(tool) = crate::plugins::get(method) {
+        return tool.annotations();
+    }
+    let read_only = READ_ONLY_METHODS.contains(&method);
+    ToolAnnotations {
+        read_only_hint: read_only,
+        destructive_hint: is_destructive(method) || OVERWRITING_METHODS.contains(&method),
+        // Drawing twice draws twice; settings and reads can be repeated freely
+        idempotent_hint: read_only || method.starts_with("set_") || method == "activate_window"
+            || method == "ensure_window_on_monitor",
+        open_world_hint: false,
+    }
+}
+
+fn description_for(method: &str) -> &'static str {
+    match method {
+        "connect" => "Connect to the running Paint window",
+        "disconnect" => "Stop tracking the Paint window (Paint keeps running)",
+        "get_version" => "Report server, protocol and Paint versions",
+        "get_supported_tools" => "List the tools, brush types and shapes the attached Paint build has, whether it has layers and background removal, and which methods won't work on it",
+        "activate_window" => "Bring the Paint window to the foreground",
+        "get_canvas_dimensions" => "Return the image width and height in pixels, with the zoom level, the viewport on screen and the scroll position",
+        "get_window_info" => "Return the Paint window geometry, DPI, monitor and state",
+        "ensure_window_on_monitor" => "Move Paint fully onto a monitor",
+        "set_dedicated_desktop" => "Run Paint on its own virtual desktop",
+        "draw_pixel" => "Draw a single pixel",
+        "draw_line" => "Draw a straight line",
+        "draw_shape" => "Draw a shape such as a rectangle or ellipse",
+        "draw_polyline" => "Draw connected line segments through a list of points",
+        "draw_ellipse" => "Draw an ellipse turned to any angle, or an arc of one, as a smooth polyline",
+        "draw_gradient" => "Fill a rectangle with a linear gradient between two colors, drawn as bands of interpolated color",
+        "fill_pattern" => "Fill a rectangle or polygon with hatching, cross-hatching, dots or a checkerboard, clipped to its edges",
+        "draw_glyph_text" => "Draw text in any installed font at any size, filled or outlined, from the font's glyph outlines instead of Paint's text tool",
+        "add_text" => "Add text to the canvas",
+        "select_region" => "Select a rectangular region",
+        "copy_selection" => "Copy the current selection to the clipboard",
+        "paste" => "Paste the clipboard at a position",
+        "stamp_image" => "Paste a small image at each of a list of positions, for repeated elements like stars, trees or icons",
+        "rotate_selection" => "Rotate the current selection, leaving the rest of the canvas alone",
+        "flip_selection" => "Mirror the current selection horizontally or vertically",
+        "invert_colors" => "Invert the colors of the whole canvas or the current selection",
+        "clear_canvas" => "Erase everything on the canvas",
+        "new_document" => "Open a new document at Paint's default size, answering the save prompt as told",
+        "close_document" => "Close the open document, leaving Paint with a new untitled one",
+        "quit_paint" => "Close Paint and wait for it to exit, answering the save prompt as told",
+        "create_canvas" => "Start a new canvas of the given size, discarding the current one",
+        "duplicate_canvas" => "Copy the whole drawing into a new Paint window, as a checkpoint or to try something out on the copy",
+        "set_image_properties" => "Resize the image or switch it to black and white with Paint's Image Properties dialog",
+        "set_background" => "Fill the whole canvas with a color, covering everything drawn so far",
+        "save_canvas" => "Save the image to a file with Paint's Save As dialog",
+        "save_as" => "Save the image with Paint's Save As dialog, picking the file type (PNG, JPEG, BMP or GIF) in the dialog",
+        "get_document_info" => "Report the open document's name, file path, unsaved changes, size and last save time",
+        "fetch_image" => "Read an image file and return it base64-encoded with its format and dimensions",
+        "select_tool" => "Select a drawing tool",
+        "set_color" => "Set the primary color",
+        "set_secondary_color" => "Set the secondary color (Color 2), used by right-button strokes",
+        "pick_color_at" => "Set the active color from a canvas pixel and return it as #RRGGBB",
+        "set_thickness" => "Set the line thickness level",
+        "set_brush_size" => "Set the brush size in pixels",
+        "set_fill" => "Set the shape fill style",
+        "set_tool_state" => "Set the tool, colors, stroke width and fill in one call, activating Paint once",
+        "define_palette" => "Define a named list of colors to pick from later with use_palette_color",
+        "use_palette_color" => "Set the primary or secondary color to an entry of a palette from define_palette",
+        "add_custom_color" => "Add a color to Paint's custom colors and set the primary or secondary color to it; later switches click its swatch instead of opening Edit colors",
+        "use_custom_color" => "Set the primary or secondary color to a custom color added with add_custom_color, by color or by slot",
+        "set_snap" => "Round the positions of later drawing commands to a grid, or turn that off",
+        "push_transform" => "Move, rotate or scale the positions of later drawing commands, on top of the transforms already pushed",
+        "pop_transform" => "Drop the last transform pushed with push_transform",
+        "pen_move_to" => "Start a new subpath of the pen's path at a point, without drawing",
+        "pen_line_to" => "Add a line from the pen's current point to a point, without drawing yet",
+        "pen_close" => "Close the current subpath with a line back to its start",
+        "pen_stroke" => "Draw the pen's path gathered so far and start a new one",
+        "add_layer" => "Add a layer above the selected one (Paint builds with layers)",
+        "select_layer" => "Make a layer the one drawing goes to",
+        "set_layer_visibility" => "Show or hide a layer",
+        "merge_layers" => "Merge a layer into the layer below it",
+        "delete_layer" => "Delete a layer and everything on it",
+        "export_canvas" => "Return the whole canvas as a PNG image",
+        "capture_window" => "Return a screenshot of the Paint window as a PNG image",
+        "get_canvas_region" => "Return part of the canvas as a PNG image",
+        "export_region" => "Select part of the canvas, copy it and return the copied pixels as a PNG image",
+        "start_input_recording" => "Record all synthesized mouse and keyboard input to a file",
+        "stop_input_recording" => "Stop recording input and report the number of events",
+        "replay_input" => "Replay a recorded input file with its original timing",
+        "run_macro" => "Run a rhai script that draws with loops and math, as one batch of drawing requests",
+        "register_macro" => "Keep a rhai drawing script under a name for run_macro",
+        "checkpoint" => "Remember the current canvas under a label, to go back to with revert_to_checkpoint",
+        "revert_to_checkpoint" => "Undo everything drawn since a checkpoint, checking that the canvas matches it afterwards",
+        "draw_animation" => "Play a flip-book animation: draw frames of requests one after another, clearing or undoing the previous frame first",
+        "run_benchmark" => "Measure drawing throughput by drawing test strokes in the canvas corner",
+        "get_audit_log" => "List recent requests from the audit log, optionally filtered by client, method, time or failure",
+        "set_log_level" => "Change the level of the server's terminal and file logs without restarting",
+        "calibrate" => "Find the tool button positions for this Paint window by drawing and undoing probe strokes, and save them for later sessions",
+        "self_test" => "Check that Paint can be driven: draws on a new test canvas and reports each step",
+        _ => "Paint operation",
+    }
+}
+
+// Shared schema fragments
+fn color_schema() -> Value {
+    json!({ "type": "string", "pattern": "^#[0-9A-Fa-f]{6}$", "description": "Color as #RRGGBB" })
+}
+
+fn button_schema() -> Value {
+    json!({ "type": "string", "enum": ["left", "right"], "description": "Mouse button; right draws in the secondary color" })
+}
+
+fn clip_schema() -> Value {
+    json!({ "type": "boolean", "description": "Draw only the part on the canvas instead of dragging past its edges" })
+}
+
+fn dry_run_schema() -> Value {
+    json!({ "type": "boolean", "description": "Check and plan the figure, returning where it would be drawn, without drawing it" })
+}
+
+fn thickness_schema() -> Value {
+    json!({ "type": "integer", "minimum": 1, "maximum": 5, "description": "Deprecated thickness level (1-5); use thickness_px" })
+}
+
+fn thickness_px_schema() -> Value {
+    json!({ "type": "integer", "minimum": 1, "maximum": crate::protocol::MAX_THICKNESS_PX, "description": "Stroke width in pixels" })
+}
+
+fn preset_schema() -> Value {
+    json!({ "type": "string", "description": "Stroke preset (fine-liner, marker, sketch or one from the server config) giving the settings the request leaves out" })
+}
+
+fn layer_index_schema() -> Value {
+    json!({ "type": "integer", "minimum": 0, "description": "Position in the Layers panel, 0 = top layer" })
+}
+
+// Lets a coordinate method's positions and sizes be given in another space
+fn allow_coordinate_spaces(schema: &mut Value) {
+    schema["properties"]["coordinate_space"] = json!({
+        "type": "string",
+        "enum": ["canvas", "normalized", "client", "screen"],
+        "description": "canvas: image pixels (default); normalized: fractions (0-1) of the canvas width and height; client: pixels of Paint's client area; screen: absolute screen pixels"
+    });
+    relax_coordinate_fields(&mut schema["properties"]);
+    for list in ["points", "positions"] {
+        if let Some(points) = schema["properties"].get_mut(list) {
+            relax_coordinate_fields(&mut points["items"]["properties"]);
+        }
+    }
+}
+
+// Pixel fields declared as integers also take fractions and off-canvas positions
+fn relax_coordinate_fields(properties: &mut Value) {
+    let Some(properties) = properties.as_object_mut() else { return };
+    for (_, field) in properties.iter_mut().filter(|(name, _)| crate::coordinates::is_coordinate_field(name)) {
+        // Optional fields generated from the protocol structs also take null
+        field["type"] = match &field["type"] {
+            Value::Array(types) => types.iter().map(|t| if t == "integer" { json!("number") } else { t.clone() }).collect(),
+            _ => json!("number"),
+        };
+        // Screen positions can be negative, and a size of at least one
+        // pixel is any fraction above zero
+        if let Some(field) = field.as_object_mut() {
+            field.remove("format");
+            if field.remove("minimum") == Some(json!(1)) {
+                field.insert("exclusiveMinimum".to_string(), json!(0));
+            }
+        }
+    }
+}
+
+fn object_schema(properties: Value, required: &[&str]) -> Value {
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+fn input_schema(method: &str) -> Value {
+    match method {
+        "connect" => object_schema(json!({
+            "client_id": { "type": "string" },
+            "client_name": { "type": "string" },
+            "monitor": { "type": "integer", "minimum": 0 },
+            "dedicated_desktop": { "type": "boolean" },
+            "protocol_version": { "type": "string" }
+        }), &["client_id", "client_name"]),
+        "ensure_window_on_monitor" => object_schema(json!({
+            "monitor": { "type": "integer", "minimum": 0 }
+        }), &[]),
+        "set_dedicated_desktop" => object_schema(json!({
+            "enabled": { "type": "boolean" }
+        }), &["enabled"]),
+        "draw_pixel" => object_schema(json!({
+            "x": { "type": "integer" },
+            "y": { "type": "integer" },
+            "color": color_schema(),
+            "button": button_schema(),
+            "dry_run": dry_run_schema(),
+            "preset": preset_schema()
+        }), &["x", "y"]),
+        "draw_line" => object_schema(json!({
+            "start_x": { "type": "integer" },
+            "start_y": { "type": "integer" },
+            "end_x": { "type": "integer" },
+            "end_y": { "type": "integer" },
+            "color": color_schema(),
+            "thickness": thickness_schema(),
+            "thickness_px": thickness_px_schema(),
+            "button": button_schema(),
+            "clip": clip_schema(),
+            "dry_run": dry_run_schema(),
+            "preset": preset_schema()
+        }), &["start_x", "start_y", "end_x", "end_y"]),
+        "draw_shape" => object_schema(json!({
+            "shape_type": { "type": "string", "enum": ["rectangle", "ellipse", "line", "arrow", "triangle", "pentagon", "hexagon"] },
+            "start_x": { "type": "integer" },
+            "start_y": { "type": "integer" },
+            "end_x": { "type": "integer" },
+            "end_y": { "type": "integer" },
+            "color": color_schema(),
+            "thickness": thickness_schema(),
+            "thickness_px": thickness_px_schema(),
+            "fill_type": { "type": "string", "enum": ["none", "solid", "outline"] },
+            "button": button_schema(),
+            "clip": clip_schema(),
+            "dry_run": dry_run_schema(),
+            "preset": preset_schema()
+        }), &["shape_type", "start_x", "start_y", "end_x", "end_y"]),
+        "draw_polyline" => object_schema(json!({
+            "points": {
+                "type": "array",
+                "items": object_schema(json!({ "x": { "type": "integer" }, "y": { "type": "integer" } }), &["x", "y"]),
+                "minItems": 2
+            },
+            "color": color_schema(),
+            "thickness": thickness_schema(),
+            "thickness_px": thickness_px_schema(),
+            "tool": { "type": "string", "enum": ["pencil", "brush"] },
+            "button": button_schema(),
+            "clip": clip_schema(),
+            "dry_run": dry_run_schema(),
+            "smoothing": { "type": "string", "enum": ["catmull_rom"], "description": "Draw a smooth curve through the points" },
+            "simplify_tolerance": { "type": "number", "minimum": 0, "description": "Leave out points the path strays less than this many pixels from" },
+            "preset": preset_schema(),
+            "width_profile": object_schema(json!({
+                "widths": { "type": "array", "items": thickness_px_schema(), "description": "Width in pixels at each point" },
+                "taper_in": { "type": "number", "minimum": 0, "maximum": 1, "description": "Part of the stroke over which it widens from min_px to thickness_px" },
+                "taper_out": { "type": "number", "minimum": 0, "maximum": 1, "description": "Part of the stroke over which it narrows back to min_px" },
+                "min_px": thickness_px_schema()
+            }), &[])
+        }), &["points"]),
+        "draw_ellipse" => object_schema(json!({
+            "x": { "type": "integer", "description": "Center" },
+            "y": { "type": "integer", "description": "Center" },
+            "radius_x": { "type": "integer", "minimum": 1 },
+            "radius_y": { "type": "integer", "minimum": 1 },
+            "rotation_deg": { "type": "number", "description": "Clockwise turn of the x radius (default 0)" },
+            "start_angle": { "type": "number", "description": "Arc start, in degrees clockwise from the x radius; needs end_angle" },
+            "end_angle": { "type": "number", "description": "Arc end; the arc runs clockwise from start_angle" },
+            "color": color_schema(),
+            "thickness": thickness_schema(),
+            "thickness_px": thickness_px_schema(),
+            "tool": { "type": "string", "enum": ["pencil", "brush"] },
+            "button": button_schema(),
+            "clip": clip_schema(),
+            "dry_run": dry_run_schema(),
+            "preset": preset_schema()
+        }), &["x", "y", "radius_x", "radius_y"]),
+        "draw_gradient" => object_schema(json!({
+            "x": { "type": "integer" },
+            "y": { "type": "integer" },
+            "width": { "type": "integer", "minimum": 1 },
+            "height": { "type": "integer", "minimum": 1 },
+            "start_color": color_schema(),
+            "end_color": color_schema(),
+            "direction": { "type": "string", "enum": ["horizontal", "vertical"], "description": "horizontal goes from the left edge to the right, vertical from the top down" },
+            "steps": { "type": "integer", "minimum": 1, "maximum": crate::gradient::MAX_GRADIENT_STEPS, "description": "Bands of color; more look smoother but take longer" },
+            "button": button_schema(),
+            "dry_run": dry_run_schema()
+        }), &["x", "y", "width", "height", "start_color", "end_color"]),
+        "fill_pattern" => object_schema(json!({
+            "pattern": { "type": "string", "enum": ["hatch", "crosshatch", "dots", "checker"] },
+            "x": { "type": "integer", "description": "Give x, y, width and height for a rectangle, or points for a polygon" },
+            "y": { "type": "integer" },
+            "width": { "type": "integer", "minimum": 1 },
+            "height": { "type": "integer", "minimum": 1 },
+            "points": {
+                "type": "array",
+                "items": object_schema(json!({ "x": { "type": "integer" }, "y": { "type": "integer" } }), &["x", "y"]),
+                "minItems": 3
+            },
+            "spacing": { "type": "integer", "minimum": 2, "description": "Pixels between lines, dots or checker squares" },
+            "color": color_schema(),
+            "button": button_schema(),
+            "dry_run": dry_run_schema()
+        }), &["pattern"]),
+        "draw_glyph_text" => object_schema(json!({
+            "x": { "type": "integer", "description": "Left edge of the text" },
+            "y": { "type": "integer", "description": "Top of the first line" },
+            "text": { "type": "string", "description": "Text to draw; a newline starts a new line" },
+            "font": { "type": "string", "description": "Font file path, file name in the Windows font folders (e.g. \"arial.ttf\"), or installed font name (e.g. \"Arial Bold\")" },
+            "size_px": { "type": "integer", "minimum": crate::glyphs::MIN_GLYPH_SIZE_PX, "maximum": crate::glyphs::MAX_GLYPH_SIZE_PX, "description": "Em size in pixels" },
+            "fill": { "type": "boolean", "description": "false draws only the outlines (default true)" },
+            "thickness_px": { "type": "integer", "minimum": 1, "maximum": crate::protocol::MAX_THICKNESS_PX, "description": "Outline width; only with fill false" },
+            "color": color_schema(),
+            "button": button_schema(),
+            "dry_run": dry_run_schema()
+        }), &["x", "y", "text", "font", "size_px"]),
+        "add_text" => object_schema(json!({
+            "x": { "type": "integer" },
+            "y": { "type": "integer" },
+            "text": { "type": "string" },
+            "color": color_schema(),
+            "font_name": { "type": "string" },
+            "font_size": { "type": "integer", "minimum": 1 },
+            "font_style": { "type": "string", "enum": ["regular", "bold", "italic", "bold_italic"] }
+        }), &["x", "y", "text"]),
+        "select_region" => object_schema(json!({
+            "start_x": { "type": "integer" },
+            "start_y": { "type": "integer" },
+            "end_x": { "type": "integer" },
+            "end_y": { "type": "integer" }
+        }), &["start_x", "start_y", "end_x", "end_y"]),
+        "paste" => object_schema(json!({
+            "x": { "type": "integer" },
+            "y": { "type": "integer" }
+        }), &["x", "y"]),
+        "stamp_image" => object_schema(json!({
+            "image": { "type": "string", "description": "Base64-encoded PNG, JPEG, BMP or GIF; transparent pixels come out white" },
+            "positions": {
+                "type": "array",
+                "items": object_schema(json!({ "x": { "type": "integer" }, "y": { "type": "integer" } }), &["x", "y"]),
+                "minItems": 1,
+                "maxItems": crate::protocol::MAX_STAMP_POSITIONS,
+                "description": "Where each copy's top-left corner goes"
+            },
+            "scale": { "type": "number", "exclusiveMinimum": 0, "description": "Size factor (default 1), applied with nearest-neighbour sampling" }
+        }), &["image", "positions"]),
+        "save_canvas" => object_schema(json!({
+            "file_path": { "type": "string", "description": "Absolute path of the file to write; %VARIABLES% are expanded" },
+            "format": { "type": "string", "enum": crate::protocol::SAVE_FORMATS },
+            "create_dirs": { "type": "boolean", "description": "Create missing parent directories (default false)" },
+            "overwrite": { "type": "boolean", "description": "Replace the file if it exists (default false)" },
+            "jpeg_quality": { "type": "integer", "minimum": 1, "maximum": 100, "description": "Used if Paint asks for a JPEG quality" }
+        }), &["file_path", "format"]),
+        "save_as" => object_schema(json!({
+            "file_path": { "type": "string", "description": "Absolute path of the file to write; %VARIABLES% are expanded" },
+            "format": { "type": "string", "enum": crate::protocol::SAVE_AS_FORMATS },
+            "create_dirs": { "type": "boolean", "description": "Create missing parent directories (default false)" },
+            "overwrite": { "type": "boolean", "description": "Replace the file if it exists (default false)" },
+            "jpeg_quality": { "type": "integer", "minimum": 1, "maximum": 100, "description": "Used if Paint asks for a JPEG quality" }
+        }), &["file_path", "format"]),
+        "new_document" => object_schema(json!({
+            "unsaved_changes": {
+                "type": "string",
+                "enum": ["save", "discard", "fail"],
+                "description": "Answer if Paint asks to save the current document (default fail)"
+            }
+        }), &[]),
+        "close_document" => object_schema(json!({
+            "unsaved_changes": {
+                "type": "string",
+                "enum": ["save", "discard", "fail"],
+                "description": "Answer if Paint asks to save the current document (default fail)"
+            }
+        }), &[]),
+        "quit_paint" => object_schema(json!({
+            "unsaved_changes": {
+                "type": "string",
+                "enum": ["save", "discard", "fail"],
+                "description": "Answer if Paint asks to save the current document (default fail)"
+            },
+            "timeout_ms": { "type": "integer", "minimum": 1, "description": "How long to wait for Paint to exit (default 10000)" }
+        }), &[]),
+        "duplicate_canvas" => object_schema(json!({
+            "target_hwnd": { "type": "integer", "description": "Paint window to copy into instead of launching a new one; its document is replaced" },
+            "unsaved_changes": {
+                "type": "string",
+                "enum": ["save", "discard", "fail"],
+                "description": "Answer if the target window asks to save its document (default fail)"
+            },
+            "track_copy": { "type": "boolean", "description": "Draw on the copy from now on instead of the original (default false)" }
+        }), &[]),
+        "create_canvas" => object_schema(json!({
+            "width": { "type": "integer", "minimum": 1 },
+            "height": { "type": "integer", "minimum": 1 },
+            "background_color": color_schema(),
+            "unsaved_changes": {
+                "type": "string",
+                "enum": ["save", "discard", "fail"],
+                "description": "Answer if Paint asks to save the current document (default fail)"
+            }
+        }), &["width", "height"]),
+        "set_image_properties" => object_schema(json!({
+            "width": { "type": "number", "exclusiveMinimum": 0, "description": "New width in units; shrinking crops the right edge" },
+            "height": { "type": "number", "exclusiveMinimum": 0, "description": "New height in units; shrinking crops the bottom edge" },
+            "units": { "type": "string", "enum": ["pixels", "inches", "centimeters"], "description": "Default pixels" },
+            "color_mode": { "type": "string", "enum": ["color", "black_and_white"] }
+        }), &[]),
+        "select_tool" => object_schema(json!({
+            "tool": { "type": "string", "enum": ["pencil", "brush", "fill", "text", "eraser", "select", "shape"] },
+            "shape_type": { "type": "string" },
+            "brush_type": { "type": "string", "enum": crate::protocol::BRUSH_TYPES, "description": "Brush variant; requires tool \"brush\"" }
+        }), &["tool"]),
+        "set_color" => object_schema(json!({ "color": color_schema() }), &["color"]),
+        "set_secondary_color" | "set_background" => object_schema(json!({ "color": color_schema() }), &["color"]),
+        "pick_color_at" => object_schema(json!({
+            "x": { "type": "integer", "minimum": 0 },
+            "y": { "type": "integer", "minimum": 0 },
+            "button": { "type": "string", "enum": ["left", "right"], "description": "Color slot to set; right sets the secondary color" }
+        }), &["x", "y"]),
+        "set_thickness" => object_schema(json!({ "level": thickness_schema() }), &["level"]),
+        "set_brush_size" => object_schema(json!({
+            "size": { "type": "integer", "minimum": 1, "maximum": 30 },
+            "tool": { "type": "string", "enum": ["pencil", "brush"] }
+        }), &["size"]),
+        "set_fill" => object_schema(json!({
+            "fill_type": { "type": "string", "enum": ["none", "solid", "outline"] }
+        }), &["fill_type"]),
+        "set_tool_state" => object_schema(json!({
+            "tool": { "type": "string", "enum": ["pencil", "brush", "fill", "text", "eraser", "select", "shape"] },
+            "brush_type": { "type": "string", "enum": crate::protocol::BRUSH_TYPES, "description": "Brush variant; requires tool \"brush\"" },
+            "color": color_schema(),
+            "secondary_color": color_schema(),
+            "thickness_px": thickness_px_schema(),
+            "fill_type": { "type": "string", "enum": ["none", "solid", "outline"] }
+        }), &[]),
+        "define_palette" => object_schema(json!({
+            "name": { "type": "string", "minLength": 1 },
+            "colors": { "type": "array", "items": color_schema(), "minItems": 1, "maxItems": crate::palette::MAX_PALETTE_COLORS }
+        }), &["name", "colors"]),
+        "use_palette_color" => object_schema(json!({
+            "name": { "type": "string" },
+            "index": { "type": "integer", "minimum": 0, "description": "Position in the palette's colors, from 0" },
+            "button": { "type": "string", "enum": ["left", "right"], "description": "Color slot to set; right sets the secondary color" }
+        }), &["name", "index"]),
+        "add_custom_color" => object_schema(json!({
+            "color": color_schema(),
+            "button": { "type": "string", "enum": ["left", "right"], "description": "Color slot to set; right sets the secondary color" }
+        }), &["color"]),
+        "use_custom_color" => object_schema(json!({
+            "color": color_schema(),
+            "slot": { "type": "integer", "minimum": 0, "maximum": crate::palette::CUSTOM_COLOR_SLOTS - 1, "description": "Position among the custom colors, 0 being the newest; give this or color" },
+            "button": { "type": "string", "enum": ["left", "right"], "description": "Color slot to set; right sets the secondary color" }
+        }), &[]),
+        "set_snap" => object_schema(json!({
+            "grid_size": { "type": "integer", "minimum": 0, "description": "Pixels between grid points; 0 turns snapping off" }
+        }), &[]),
+        "pen_move_to" | "pen_line_to" => object_schema(json!({
+            "x": { "type": "integer" },
+            "y": { "type": "integer" }
+        }), &["x", "y"]),
+        "pen_stroke" => object_schema(json!({
+            "color": color_schema(),
+            "thickness": thickness_schema(),
+            "thickness_px": thickness_px_schema(),
+            "tool": { "type": "string", "enum": ["pencil", "brush"] },
+            "button": button_schema(),
+            "preset": preset_schema()
+        }), &[]),
+        "push_transform" => object_schema(json!({
+            "translate_x": { "type": "number", "description": "Pixels to move right" },
+            "translate_y": { "type": "number", "description": "Pixels to move down" },
+            "rotate_degrees": { "type": "number", "description": "Clockwise rotation about the origin" },
+            "scale_x": { "type": "number", "description": "Horizontal scale factor, not 0" },
+            "scale_y": { "type": "number", "description": "Vertical scale factor, not 0" }
+        }), &[]),
+        "rotate_selection" => object_schema(json!({
+            "angle": { "type": "integer", "enum": [90, 180, 270], "description": "Clockwise rotation in degrees" }
+        }), &["angle"]),
+        "flip_selection" => object_schema(json!({
+            "direction": { "type": "string", "enum": ["horizontal", "vertical"] }
+        }), &["direction"]),
+        "invert_colors" => object_schema(json!({
+            "scope": { "type": "string", "enum": ["canvas", "selection"], "description": "What to invert (default canvas)" }
+        }), &[]),
+        "select_layer" | "merge_layers" | "delete_layer" => object_schema(json!({
+            "index": layer_index_schema()
+        }), &["index"]),
+        "set_layer_visibility" => object_schema(json!({
+            "index": layer_index_schema(),
+            "visible": { "type": "boolean" }
+        }), &["index", "visible"]),
+        "get_canvas_region" => object_schema(json!({
+            "x": { "type": "integer", "minimum": 0 },
+            "y": { "type": "integer", "minimum": 0 },
+            "width": { "type": "integer", "minimum": 1 },
+            "height": { "type": "integer", "minimum": 1 }
+        }), &["x", "y", "width", "height"]),
+        "export_region" => object_schema(json!({
+            "x": { "type": "integer", "minimum": 0 },
+            "y": { "type": "integer", "minimum": 0 },
+            "width": { "type": "integer", "minimum": 1 },
+            "height": { "type": "integer", "minimum": 1 }
+        }), &["x", "y", "width", "height"]),
+        "fetch_image" => object_schema(json!({
+            "file_path": { "type": "string", "description": "Absolute path of a PNG, JPEG, BMP or GIF file" },
+            "preview_max_px": { "type": "integer", "minimum": 1, "description": "If the file is over the size limit, return a PNG at most this many pixels on a side instead" }
+        }), &["file_path"]),
+        "start_input_recording" => object_schema(json!({
+            "path": { "type": "string" }
+        }), &["path"]),
+        "replay_input" => object_schema(json!({
+            "path": { "type": "string" },
+            "speed": { "type": "number", "exclusiveMinimum": 0, "description": "Playback speed multiplier" }
+        }), &["path"]),
+        "run_macro" => object_schema(json!({
+            "source": { "type": "string", "description": "Rhai script; give this or name" },
+            "name": { "type": "string", "description": "A macro kept by register_macro" },
+            "args": { "type": "object", "description": "Available to the script as args" },
+            "dry_run": { "type": "boolean", "description": "Return the requests the script makes without running them" }
+        }), &[]),
+        "register_macro" => object_schema(json!({
+            "name": { "type": "string", "pattern": "^[a-z0-9_]+$" },
+            "source": { "type": "string", "description": "Rhai script" }
+        }), &["name", "source"]),
+        "checkpoint" | "revert_to_checkpoint" => object_schema(json!({
+            "label": { "type": "string", "minLength": 1 }
+        }), &["label"]),
+        "draw_animation" => object_schema(json!({
+            "frames": {
+                "type": "array",
+                "minItems": 1,
+                "items": object_schema(json!({
+                    "before": { "type": "string", "enum": ["clear", "undo"], "description": "Clear the canvas, or undo the previous frame's drawing, before drawing this one" },
+                    "steps": {
+                        "type": "array",
+                        "items": object_schema(json!({
+                            "method": { "type": "string" },
+                            "params": { "type": "object" }
+                        }), &["method"])
+                    }
+                }), &["steps"])
+            },
+            "frame_delay_ms": { "type": "integer", "minimum": 0, "maximum": crate::protocol::MAX_FRAME_DELAY_MS, "description": "From the start of one frame to the start of the next" },
+            "loop_count": { "type": "integer", "minimum": 1, "maximum": crate::protocol::MAX_ANIMATION_LOOPS, "description": "Times to play the frames; defaults to 1" }
+        }), &["frames", "frame_delay_ms"]),
+        "run_benchmark" => object_schema(json!({
+            "iterations": { "type": "integer", "minimum": 1, "maximum": 500, "description": "Operations per measurement" }
+        }), &[]),
+        "calibrate" => object_schema(json!({
+            "tools": { "type": "array", "items": { "type": "string", "enum": crate::calibrate::CALIBRATABLE_TOOLS }, "description": "Defaults to all of them" },
+            "persist": { "type": "boolean", "description": "Save the positions for later sessions (default true)" }
+        }), &[]),
+        "get_audit_log" => object_schema(json!({
+            "client_id": { "type": "string" },
+            "method": { "type": "string" },
+            "since_ms": { "type": "integer", "minimum": 0, "description": "Unix time in milliseconds" },
+            "errors_only": { "type": "boolean" },
+            "limit": { "type": "integer", "minimum": 1, "description": "Most recent entries to return (default 100)" }
+        }), &[]),
+        "set_log_level" => object_schema(json!({
+            "level": { "type": "string", "enum": ["off", "trace", "debug", "info", "warn", "error"] },
+            "logger": { "type": "string", "enum": ["terminal", "file"], "description": "Defaults to both" }
+        }), &["level"]),
+        _ => object_schema(json!({}), &[]),
+    }
+}
+
+/// Builds the MCP tool descriptor for a method.
+pub fn tool_definition(method: &str) -> Value {
+    let plugin = crate::plugins::get(method);
+    let mut schema = plugin.as_ref().map_or_else(|| input_schema(method), |tool| tool.input_schema());
+    add_request_options(method, &mut schema);
+    json!({
+        "name": method,
+        "description": plugin.as_ref().map_or(description_for(method), |tool| tool.description()),
+        "inputSchema": schema,
+        "annotations": annotations_for(method)
+    })
+}
+
+// Adds the params the request path itself takes (coordinate_space,
+// activation, confirm) before the handler sees the rest
+fn add_request_options(method: &str, schema: &mut Value) {
+    if crate::coordinates::COORDINATE_METHODS.contains(&method) {
+        allow_coordinate_spaces(schema);
+    }
+    if sends_input(method) {
+        schema["properties"]["activation"] = json!({
+            "type": "string",
+            "enum": ["always", "if_needed", "never"],
+            "description": "always: bring Paint to the front before each step (default); if_needed: only when it isn't in front; never: leave the focus alone and post input to Paint as window messages"
+        });
+    }
+    if is_destructive(method) || OVERWRITING_METHODS.contains(&method) {
+        // Lets callers acknowledge the tool when confirm_destructive is on
+        schema["properties"]["confirm"] = json!({
+            "type": "boolean",
+            "description": "Set to true to confirm this destructive operation"
+        });
+    }
+}
+
+/// Returns the descriptors for every advertised Paint method, then for the
+/// registered tools.
+pub fn tool_definitions() -> Vec<Value> {
+    let tools = crate::plugins::registered();
+    PAINT_METHODS.iter().copied().chain(tools.iter().map(|tool| tool.name())).map(tool_definition).collect()
+}
+
+/// An OpenRPC document describing every Paint method and registered tool,
+/// so client SDKs can be generated from it. Paint methods' params and
+/// results come from the protocol.rs structs the handlers use; registered
+/// tools bring their own input schema.
+pub fn api_schema() -> Value {
+    let methods: Vec<Value> = tool_definitions().into_iter().map(|tool| {
+        let method = tool["name"].as_str().unwrap_or_default();
+        let mut schema = if crate::plugins::get(method).is_some() {
+            tool["inputSchema"].clone()
+        } else {
+            crate::protocol::params_schema(method).unwrap_or_else(|| object_schema(json!({}), &[]))
+        };
+        add_request_options(method, &mut schema);
+        let required = schema["required"].as_array().cloned().unwrap_or_default();
+        let params: Vec<Value> = schema["properties"].as_object().map(|properties| properties.iter().map(|(name, property)| json!({
+            "name": name,
+            "required": required.contains(&json!(name)),
+            "schema": property
+        })).collect()).unwrap_or_default();
+        json!({
+            "name": tool["name"],
+            "summary": tool["description"],
+            "paramStructure": "by-name",
+            "params": params,
+            // Results without a protocol struct are objects whose fields vary by method
+            "result": {
+                "name": "result",
+                "schema": crate::protocol::result_schema(method).unwrap_or_else(|| json!({ "type": "object" }))
+            },
+            "x-annotations": tool["annotations"]
+        })
+    }).collect();
+    json!({
+        "openrpc": OPENRPC_VERSION,
+        "info": { "title": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+        "methods": methods
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_destructive_tools_are_annotated() {
+        let tool = tool_definition("clear_canvas");
+        assert_eq!(tool["annotations"]["destructiveHint"], true);
+        assert_eq!(tool["annotations"]["readOnlyHint"], false);
+        assert_eq!(tool["inputSchema"]["properties"]["confirm"]["type"], "boolean");
+
+        // Saving only destroys anything when it overwrites a file
+        let tool = tool_definition("save_canvas");
+        assert_eq!(tool["annotations"]["destructiveHint"], true);
+        assert_eq!(tool["inputSchema"]["properties"]["confirm"]["type"], "boolean");
+        assert!(needs_confirm("save_canvas", Some(&json!({"file_path": "C:/a.png", "format": "png", "overwrite": true}))));
+        assert!(!needs_confirm("save_canvas", Some(&json!({"file_path": "C:/a.png", "format": "png"}))));
+        assert!(needs_confirm("save_as", Some(&json!({"file_path": "C:/a.gif", "format": "gif", "overwrite": true}))));
+        assert_eq!(tool_definition("save_as")["annotations"]["destructiveHint"], true);
+        assert!(needs_confirm("clear_canvas", None));
+    }
+
+    #[test]
+    fn test_coordinate_tools_take_fractions() {
+        let region = tool_definition("get_canvas_region")["inputSchema"].clone();
+        assert_eq!(region["properties"]["coordinate_space"]["enum"], json!(["canvas", "normalized", "client", "screen"]));
+        assert_eq!(region["properties"]["width"], json!({ "type": "number", "exclusiveMinimum": 0 }));
+        assert_eq!(region["properties"]["x"], json!({ "type": "number" }));
+        let polyline = tool_definition("draw_polyline")["inputSchema"].clone();
+        assert_eq!(polyline["properties"]["points"]["items"]["properties"]["y"]["type"], "number");
+        assert!(tool_definition("set_color")["inputSchema"]["properties"].get("coordinate_space").is_none());
+    }
+
+    #[test]
+    fn test_input_tools_take_an_activation_policy() {
+        assert_eq!(tool_definition("draw_line")["inputSchema"]["properties"]["activation"]["enum"], json!(["always", "if_needed", "never"]));
+        assert!(tool_definition("get_window_info")["inputSchema"]["properties"].get("activation").is_none());
+    }
+
+    #[test]
+    fn test_api_schema_describes_every_method() {
+        let schema = api_schema();
+        let methods = schema["methods"].as_array().unwrap();
+        assert!(PAINT_METHODS.iter().all(|name| methods.iter().any(|method| method["name"] == *name)));
+        let draw_line = methods.iter().find(|method| method["name"] == "draw_line").unwrap();
+        let param = |name: &str| draw_line["params"].as_array().unwrap().iter().find(|p| p["name"] == name).cloned().unwrap();
+        assert_eq!(param("start_x")["required"], true);
+        assert_eq!(param("color")["required"], false);
+        assert_eq!(param("coordinate_space")["schema"]["type"], "string");
+    }
+
+    #[test]
+    fn test_api_schema_follows_the_protocol_structs() {
+        use std::collections::BTreeSet;
+        let names = |value: &Value| -> BTreeSet<String> {
+            value.as_object().map(|o| o.keys().cloned().collect()).unwrap_or_default()
+        };
+        let required = |schema: &Value| -> BTreeSet<String> {
+            schema["required"].as_array().map(|r| r.iter().filter_map(|n| n.as_str().map(String::from)).collect()).unwrap_or_default()
+        };
+        // Taken by the request path rather than the handler's struct
+        let options = ["coordinate_space", "activation", "confirm"];
+
+        let schema = api_schema();
+        let methods = schema["methods"].as_array().unwrap();
+        for &method in PAINT_METHODS {
+            let params = crate::protocol::params_schema(method).unwrap_or_else(|| json!({}));
+            let fields = names(&params["properties"]);
+            let documented = methods.iter().find(|m| m["name"] == method).unwrap();
+            let documented: BTreeSet<String> = documented["params"].as_array().unwrap().iter()
+                .filter_map(|p| p["name"].as_str().map(String::from))
+                .filter(|name| !options.contains(&name.as_str()))
+                .collect();
+            assert_eq!(documented, fields, "{}", method);
+
+            // The hand-written tool schemas may leave optional fields out, but
+            // can't take fields the struct doesn't or skip required ones
+            let input = &tool_definition(method)["inputSchema"];
+            let extra: Vec<String> = names(&input["properties"]).into_iter()
+                .filter(|name| !options.contains(&name.as_str()) && !fields.contains(name))
+                .collect();
+            assert!(extra.is_empty(), "{} takes no {:?}", method, extra);
+            assert!(required(&params).is_subset(&required(input)), "{} leaves out required params", method);
+        }
+
+        let dimensions = methods.iter().find(|m| m["name"] == "get_canvas_dimensions").unwrap();
+        assert!(dimensions["result"]["schema"]["properties"]["zoom"].is_object());
+    }
+
+    #[test]
+    fn test_read_only_tools_are_annotated() {
+        let tool = tool_definition("get_window_info");
+        assert_eq!(tool["annotations"]["readOnlyHint"], true);
+        assert_eq!(tool["annotations"]["destructiveHint"], false);
+        assert!(tool["inputSchema"]["properties"].get("confirm").is_none());
+
+        // Server-side state changes send no input but aren't read-only
+        let tool = tool_definition("pen_line_to");
+        assert_eq!(tool["annotations"]["readOnlyHint"], false);
+        assert_eq!(tool["annotations"]["idempotentHint"], false);
+        assert!(!sends_input("pen_line_to"));
+        assert!(!sends_input("checkpoint"));
+    }
+}
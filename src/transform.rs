@@ -0,0 +1,178 @@
+// A transform stack for drawing the same figure in several places.
+//
+// `push_transform` adds a translation, rotation and/or scale on top of the
+// transforms already pushed, and `pop_transform` drops the last one. While
+// any are pushed, the positions in TRANSFORM_METHODS requests are mapped
+// through them before the request runs, so a client can draw a figure once
+// in its own coordinates and stamp it anywhere. Positions are transformed
+// after coordinate_space conversion and before snapping. Stroke widths are
+// not scaled, and Paint's shape tool only draws upright shapes, so rotating
+// a shape by anything but a right angle is refused.
+
+use crate::error::{MspMcpError, Result};
+use serde_json::{json, Map, Value};
+
+// Most transforms that can be pushed at once
+pub const MAX_TRANSFORM_DEPTH: usize = 32;
+
+// Drawing primitives whose positions are transformed
+pub const TRANSFORM_METHODS: &[&str] = &["draw_pixel", "draw_line", "draw_shape", "draw_polyline", "pen_move_to", "pen_line_to"];
+
+// Fields holding an x and y position together
+const POSITIONS: &[(&str, &str)] = &[("x", "y"), ("start_x", "start_y"), ("end_x", "end_y")];
+
+/// An affine transform: (x, y) maps to (a x + c y + e, b x + d y + f).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Affine {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+    pub f: f64,
+}
+
+impl Affine {
+    pub const IDENTITY: Affine = Affine { a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: 0.0, f: 0.0 };
+
+    /// Scales by `scale`, then rotates by `rotate_degrees` clockwise (y
+    /// points down), then translates by `translate`.
+    pub fn new(translate: (f64, f64), rotate_degrees: f64, scale: (f64, f64)) -> Result<Self> {
+        let values = [translate.0, translate.1, rotate_degrees, scale.0, scale.1];
+        if values.iter().any(|v| !v.is_finite()) || scale.0 == 0.0 || scale.1 == 0.0 {
+            return Err(MspMcpError::InvalidParameters(
+                "Transform values must be finite numbers, and scales must not be 0".to_string()));
+        }
+        let (sin, cos) = rotate_degrees.to_radians().sin_cos();
+        Ok(Affine { a: scale.0 * cos, b: scale.0 * sin, c: -scale.1 * sin, d: scale.1 * cos, e: translate.0, f: translate.1 })
+    }
+
+    /// This transform followed by `outer`.
+    pub fn then(&self, outer: &Affine) -> Affine {
+        Affine {
+            a: outer.a * self.a + outer.c * self.b,
+            b: outer.b * self.a + outer.d * self.b,
+            c: outer.a * self.c + outer.c * self.d,
+            d: outer.b * self.c + outer.d * self.d,
+            e: outer.a * self.e + outer.c * self.f + outer.e,
+            f: outer.b * self.e + outer.d * self.f + outer.f,
+        }
+    }
+
+    /// Maps a position, rounded to the nearest pixel.
+    pub fn apply(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        let (x, y) = (x as f64, y as f64);
+        let round = |v: f64| v.round().clamp(i32::MIN as f64, i32::MAX as f64) as i32;
+        (round(self.a * x + self.c * y + self.e), round(self.b * x + self.d * y + self.f))
+    }
+
+    /// True if upright rectangles stay upright: no rotation other than by
+    /// right angles.
+    pub fn keeps_axes(&self) -> bool {
+        const EPSILON: f64 = 1e-9;
+        (self.b.abs() < EPSILON && self.c.abs() < EPSILON) || (self.a.abs() < EPSILON && self.d.abs() < EPSILON)
+    }
+}
+
+/// The pushed transforms, each kept combined with the ones below it.
+#[derive(Debug, Default)]
+pub struct TransformStack {
+    stack: Vec<Affine>,
+}
+
+impl TransformStack {
+    /// Pushes `transform`, applied before the ones already pushed. Returns the new depth.
+    pub fn push(&mut self, transform: Affine) -> Result<usize> {
+        if self.stack.len() >= MAX_TRANSFORM_DEPTH {
+            return Err(MspMcpError::InvalidParameters(
+                format!("At most {} transforms can be pushed; pop some with pop_transform", MAX_TRANSFORM_DEPTH)));
+        }
+        let combined = transform.then(&self.current().unwrap_or(Affine::IDENTITY));
+        self.stack.push(combined);
+        Ok(self.stack.len())
+    }
+
+    /// Drops the last pushed transform. Returns the new depth.
+    pub fn pop(&mut self) -> Result<usize> {
+        self.stack.pop()
+            .ok_or_else(|| MspMcpError::InvalidParameters("No transform to pop".to_string()))?;
+        Ok(self.stack.len())
+    }
+
+    /// All pushed transforms combined, or None if there are none.
+    pub fn current(&self) -> Option<Affine> {
+        self.stack.last().copied()
+    }
+}
+
+/// Maps the positions in `params` (and in its `points`) through `transform`.
+pub fn apply(method: &str, params: &mut Value, transform: &Affine) -> Result<()> {
+    let Some(fields) = params.as_object_mut() else { return Ok(()) };
+    let shape_type = fields.get("shape_type").and_then(Value::as_str).map(str::to_lowercase);
+    if method == "draw_shape" && !matches!(shape_type.as_deref(), Some("line" | "arrow")) && !transform.keeps_axes() {
+        return Err(MspMcpError::InvalidParameters(
+            "Paint's shape tool can't draw rotated shapes; rotate by a multiple of 90 degrees or draw the outline with draw_polyline".to_string()));
+    }
+    apply_fields(fields, transform);
+    if let Some(Value::Array(points)) = fields.get_mut("points") {
+        for point in points.iter_mut().filter_map(Value::as_object_mut) {
+            apply_fields(point, transform);
+        }
+    }
+    Ok(())
+}
+
+fn apply_fields(fields: &mut Map<String, Value>, transform: &Affine) {
+    for &(x_name, y_name) in POSITIONS {
+        // Anything but whole numbers is left for the handler to reject
+        let as_i32 = |name: &str| fields.get(name).and_then(Value::as_i64).and_then(|v| i32::try_from(v).ok());
+        let (Some(x), Some(y)) = (as_i32(x_name), as_i32(y_name)) else { continue };
+        let (x, y) = transform.apply((x, y));
+        fields.insert(x_name.to_string(), json!(x));
+        fields.insert(y_name.to_string(), json!(y));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transforms_combine() {
+        let mut transforms = TransformStack::default();
+        assert_eq!(transforms.current(), None);
+        // Move to (100, 50), then turn a quarter clockwise and double
+        transforms.push(Affine::new((100.0, 50.0), 0.0, (1.0, 1.0)).unwrap()).unwrap();
+        assert_eq!(transforms.push(Affine::new((0.0, 0.0), 90.0, (2.0, 2.0)).unwrap()).unwrap(), 2);
+        let current = transforms.current().unwrap();
+        assert_eq!(current.apply((10, 0)), (100, 70));
+        assert_eq!(current.apply((0, 10)), (80, 50));
+        assert!(current.keeps_axes());
+        assert!(!Affine::new((0.0, 0.0), 30.0, (1.0, 1.0)).unwrap().keeps_axes());
+
+        assert_eq!(transforms.pop().unwrap(), 1);
+        assert_eq!(transforms.current().unwrap().apply((10, 0)), (110, 50));
+        transforms.pop().unwrap();
+        assert!(transforms.pop().is_err());
+        assert!(Affine::new((0.0, 0.0), 0.0, (0.0, 1.0)).is_err());
+        assert!(Affine::new((f64::NAN, 0.0), 0.0, (1.0, 1.0)).is_err());
+    }
+
+    #[test]
+    fn test_params_are_transformed() {
+        let shift = Affine::new((5.0, -5.0), 0.0, (1.0, 1.0)).unwrap();
+        let mut line = json!({"start_x": 0, "start_y": 10, "end_x": 20, "end_y": 30, "color": "#000000"});
+        apply("draw_line", &mut line, &shift).unwrap();
+        assert_eq!(line, json!({"start_x": 5, "start_y": 5, "end_x": 25, "end_y": 25, "color": "#000000"}));
+        let mut polyline = json!({"points": [{"x": 1, "y": 2}, {"x": "far", "y": 2}]});
+        apply("draw_polyline", &mut polyline, &shift).unwrap();
+        assert_eq!(polyline["points"], json!([{"x": 6, "y": -3}, {"x": "far", "y": 2}]));
+
+        let tilt = Affine::new((0.0, 0.0), 45.0, (1.0, 1.0)).unwrap();
+        let mut rectangle = json!({"shape_type": "rectangle", "start_x": 0, "start_y": 0, "end_x": 10, "end_y": 10});
+        assert!(apply("draw_shape", &mut rectangle, &tilt).is_err());
+        let mut arrow = json!({"shape_type": "Arrow", "start_x": 0, "start_y": 0, "end_x": 10, "end_y": 0});
+        apply("draw_shape", &mut arrow, &tilt).unwrap();
+        assert_eq!((arrow["end_x"].as_i64(), arrow["end_y"].as_i64()), (Some(7), Some(7)));
+    }
+}
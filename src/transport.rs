@@ -0,0 +1,262 @@
+// Stdio transport: message framing plus the shared output writer.
+//
+// Responses from the request loop and notifications raised anywhere in the
+// server (background watchers, handlers) go through the same locked writer
+// so messages are never interleaved on stdout.
+//
+// Two framings are supported: newline-delimited JSON (the default) and
+// LSP-style `Content-Length` headers. In auto mode the framing of the first
+// incoming message is used for everything that follows.
+
+use tracing::{debug, error, info};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+
+// Serializes all writes to stdout
+static OUTPUT_LOCK: Mutex<()> = Mutex::new(());
+
+// Framing used for output; Auto until the first message is read
+static FRAMING: AtomicU8 = AtomicU8::new(Framing::Auto as u8);
+
+const CONTENT_LENGTH_HEADER: &str = "content-length";
+
+// Largest message accepted; bigger ones are skipped and reported as parse errors
+pub const MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+
+// Longest header line accepted in Content-Length framing
+const MAX_HEADER_BYTES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Framing {
+    Auto = 0,          // Detect from the first incoming message
+    Newline = 1,       // One JSON message per line
+    ContentLength = 2, // `Content-Length: N` header, blank line, N bytes of JSON
+}
+
+impl Framing {
+    /// Parses a `--framing` value.
+    pub fn parse(value: &str) -> Option<Framing> {
+        match value.to_ascii_lowercase().as_str() {
+            "auto" => Some(Framing::Auto),
+            "newline" | "ndjson" | "line" => Some(Framing::Newline),
+            "content-length" | "lsp" | "header" => Some(Framing::ContentLength),
+            _ => None,
+        }
+    }
+
+    fn from_u8(value: u8) -> Framing {
+        match value {
+            1 => Framing::Newline,
+            2 => Framing::ContentLength,
+            _ => Framing::Auto,
+        }
+    }
+}
+
+/// Returns the framing currently in effect.
+pub fn framing() -> Framing {
+    Framing::from_u8(FRAMING.load(Ordering::SeqCst))
+}
+
+/// Sets the framing used for reading and writing.
+pub fn set_framing(framing: Framing) {
+    FRAMING.store(framing as u8, Ordering::SeqCst);
+}
+
+// Returns the header value if `line` is a Content-Length header
+fn parse_content_length(line: &str) -> Option<io::Result<usize>> {
+    let (name, value) = line.split_once(':')?;
+    if !name.trim().eq_ignore_ascii_case(CONTENT_LENGTH_HEADER) {
+        return None;
+    }
+    Some(value.trim().parse::<usize>().map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Invalid Content-Length '{}': {}", value.trim(), e))
+    }))
+}
+
+// Reads one line into `buf` (newline included). Lines longer than `limit`
+// are consumed but not kept. Returns the number of bytes taken from the
+// stream (0 at end of input) and whether the line fit.
+fn read_line_bounded<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>, limit: usize) -> io::Result<(usize, bool)> {
+    buf.clear();
+    let (mut total, mut fits) = (0, true);
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            return Ok((total, fits));
+        }
+        let (used, done) = match available.iter().position(|&b| b == b'\n') {
+            Some(newline) => (newline + 1, true),
+            None => (available.len(), false),
+        };
+        if fits && buf.len() + used <= limit {
+            buf.extend_from_slice(&available[..used]);
+        } else {
+            fits = false;
+            buf.clear();
+        }
+        reader.consume(used);
+        total += used;
+        if done {
+            return Ok((total, fits));
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn utf8(bytes: &[u8]) -> io::Result<&str> {
+    std::str::from_utf8(bytes).map_err(|e| invalid_data(format!("Message is not valid UTF-8: {}", e)))
+}
+
+/// Reads the next message body from `reader` using the current framing.
+/// In auto mode the framing is fixed by the first non-empty line. Returns
+/// Ok(None) at end of input. Messages over MAX_MESSAGE_BYTES, invalid
+/// UTF-8 and bad headers fail with `InvalidData` after the offending input
+/// has been consumed, so the caller can report them and keep reading.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    loop {
+        let limit = if framing() == Framing::ContentLength { MAX_HEADER_BYTES } else { MAX_MESSAGE_BYTES };
+        let (read, fits) = read_line_bounded(reader, &mut line, limit)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if !fits {
+            return Err(invalid_data(format!("Line longer than {} bytes", limit)));
+        }
+        let trimmed = utf8(&line)?.trim();
+        if trimmed.is_empty() {
+            continue; // Skip blank lines between messages
+        }
+
+        let content_length = parse_content_length(trimmed);
+        if framing() == Framing::Auto {
+            let detected = if content_length.is_some() { Framing::ContentLength } else { Framing::Newline };
+            info!("Detected {:?} framing on stdin", detected);
+            set_framing(detected);
+        }
+
+        if framing() == Framing::Newline {
+            return Ok(Some(trimmed.to_string()));
+        }
+
+        let length = match content_length {
+            Some(length) => length?,
+            None => {
+                // Some other header (e.g. Content-Type) before Content-Length
+                debug!("Skipping header line: {}", trimmed);
+                continue;
+            }
+        };
+
+        // Skip any remaining headers up to the blank separator line
+        loop {
+            let (read, _) = read_line_bounded(reader, &mut line, MAX_HEADER_BYTES)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            if line.iter().all(|b| b.is_ascii_whitespace()) {
+                break;
+            }
+        }
+
+        if length > MAX_MESSAGE_BYTES {
+            // Drain the body so the next message starts in the right place
+            io::copy(&mut io::Read::take(&mut *reader, length as u64), &mut io::sink())?;
+            return Err(invalid_data(format!("Message of {} bytes exceeds the {} byte limit", length, MAX_MESSAGE_BYTES)));
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body)?;
+        return String::from_utf8(body)
+            .map(Some)
+            .map_err(|e| invalid_data(format!("Message is not valid UTF-8: {}", e)));
+    }
+}
+
+// Frames a serialized message for the current output framing
+fn frame(serialized: &str) -> String {
+    match framing() {
+        Framing::ContentLength => format!("Content-Length: {}\r\n\r\n{}", serialized.len(), serialized),
+        Framing::Newline | Framing::Auto => format!("{}\n", serialized),
+    }
+}
+
+/// Writes one JSON-RPC message (response or notification) to stdout.
+pub fn write_message(message: &Value) -> io::Result<()> {
+    let framed = frame(&serde_json::to_string(message)?);
+    let _guard = OUTPUT_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    out.write_all(framed.as_bytes())?;
+    out.flush()
+}
+
+/// Sends a JSON-RPC notification (a message without an id) to the client.
+pub fn send_notification(method: &str, params: Value) {
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params
+    });
+    debug!("Sending notification: {}", method);
+    if let Err(e) = write_message(&notification) {
+        error!("Failed to send notification '{}': {}", method, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Framing is process-wide, so everything that touches it runs in one test
+    #[test]
+    fn test_read_message_framings() {
+        set_framing(Framing::Auto);
+        let mut input = Cursor::new("Content-Length: 17\r\nContent-Type: application/json\r\n\r\n{\"method\":\"ping\"}Content-Length: 2\r\n\r\n{}");
+        assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("{\"method\":\"ping\"}"));
+        assert_eq!(framing(), Framing::ContentLength);
+        assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("{}"));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+        assert_eq!(frame("{}"), "Content-Length: 2\r\n\r\n{}");
+
+        set_framing(Framing::Auto);
+        let mut input = Cursor::new("\n{\"method\":\"ping\"}\n{}\n");
+        assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("{\"method\":\"ping\"}"));
+        assert_eq!(framing(), Framing::Newline);
+        assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("{}"));
+        assert_eq!(frame("{}"), "{}\n");
+
+        set_framing(Framing::ContentLength);
+        let mut input = Cursor::new("Content-Length: abc\r\n\r\n{}");
+        assert!(read_message(&mut input).is_err());
+
+        // Oversized bodies are skipped without losing the next message
+        let mut input = Cursor::new(format!("Content-Length: {}\r\n\r\n{}Content-Length: 2\r\n\r\n{{}}",
+            MAX_MESSAGE_BYTES + 1, " ".repeat(MAX_MESSAGE_BYTES + 1)));
+        assert_eq!(read_message(&mut input).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("{}"));
+
+        // So are overlong and non-UTF-8 lines
+        set_framing(Framing::Newline);
+        let mut input = Cursor::new([&[b'x'; MAX_MESSAGE_BYTES + 1][..], b"\n\xFF\xFE\n{}\n"].concat());
+        assert_eq!(read_message(&mut input).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read_message(&mut input).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("{}"));
+        set_framing(Framing::Auto);
+    }
+
+    #[test]
+    fn test_parse_framing_flag() {
+        assert_eq!(Framing::parse("auto"), Some(Framing::Auto));
+        assert_eq!(Framing::parse("Content-Length"), Some(Framing::ContentLength));
+        assert_eq!(Framing::parse("newline"), Some(Framing::Newline));
+        assert_eq!(Framing::parse("xml"), None);
+    }
+}
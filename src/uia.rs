@@ -1,22 +1,26 @@
 use crate::error::{MspMcpError, Result};
-use log::{debug, info, warn, error};
-use std::collections::HashMap;
+use tracing::{debug, info, warn, error};
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 use uiautomation::{
     UIAutomation,
     UIElement,
-    patterns::UIInvokePattern,
+    patterns::{UIExpandCollapsePattern, UIInvokePattern, UIRangeValuePattern, UIScrollPattern, UISelectionItemPattern, UIValuePattern},
     types::TreeScope,
-    controls::{PaneControl, ToolBarControl, ButtonControl, Control},
+    controls::{PaneControl, ToolBarControl, ButtonControl, ComboBoxControl, EditControl, ListItemControl, RadioButtonControl, SliderControl, TextControl, Control},
 };
 use windows_sys::Win32::Foundation::HWND;
+use crate::protocol::{CanvasDimensionsResponse, ColorMode, ImageUnits, MouseButton, PaintCapabilities, RectInfo, SetImagePropertiesParams, UnsavedChanges, BRUSH_TYPES, SHAPE_TYPES};
+use crate::geometry;
 use crate::windows;
+use crate::timing::{self, Phase};
 
 // Cached mapping of tool names to their UI Automation elements
 static mut TOOL_BUTTON_CACHE: Option<HashMap<String, String>> = None;
 
 /// Initialize UI Automation - must be called before using any UIA functions
 pub fn initialize_uia() -> Result<UIAutomation> {
+    let _timing = timing::enter(Phase::UiaLookup);
     match UIAutomation::new() {
         Ok(client) => {
             info!("UI Automation initialized successfully");
@@ -33,6 +37,7 @@ pub fn initialize_uia() -> Result<UIAutomation> {
 
 /// Get Paint's ribbon UI element given a window handle
 pub fn get_paint_ribbon(automation: &UIAutomation, hwnd: HWND) -> Result<UIElement> {
+    let _timing = timing::enter(Phase::UiaLookup);
     let window = match automation.element_from_handle((hwnd as isize).into()) {
         Ok(window) => window,
         Err(err) => {
@@ -66,6 +71,7 @@ pub fn get_paint_ribbon(automation: &UIAutomation, hwnd: HWND) -> Result<UIEleme
 
 /// Get toolbar buttons container
 pub fn get_tools_container(automation: &UIAutomation, hwnd: HWND) -> Result<UIElement> {
+    let _timing = timing::enter(Phase::UiaLookup);
     let ribbon = get_paint_ribbon(automation, hwnd)?;
     
     // Look for the tools container (toolbar or another container with tool buttons)
@@ -228,6 +234,7 @@ fn get_tool_mapping(automation: &UIAutomation, hwnd: HWND) -> Result<HashMap<Str
 
 /// Find a tool button element by its name
 pub fn find_tool_button(automation: &UIAutomation, hwnd: HWND, tool_name: &str) -> Result<UIElement> {
+    let _timing = timing::enter(Phase::UiaLookup);
     let tool_mapping = get_tool_mapping(automation, hwnd)?;
     
     // Check if we have this tool in our mapping
@@ -301,6 +308,15 @@ pub fn find_tool_button(automation: &UIAutomation, hwnd: HWND, tool_name: &str)
         })
         .collect();
     
+    // An AutomationId from the layout for this Paint version beats name matching
+    if let Some(wanted) = crate::layout::active().automation_ids.get(tool_name) {
+        if let Some(button) = buttons.iter().find(|b| b.get_automation_id().is_ok_and(|id| id == *wanted)) {
+            info!("Found tool button '{}' with layout automation ID '{}'", tool_name, wanted);
+            return Ok(button.clone());
+        }
+        warn!("No button has the layout's automation ID '{}' for tool '{}'; matching by name", wanted, tool_name);
+    }
+    
     // Search through the buttons for our tool
     for button in buttons {
         // Check name property
@@ -335,6 +351,7 @@ pub fn find_tool_button(automation: &UIAutomation, hwnd: HWND, tool_name: &str)
 
 /// Select a tool in Paint using UI Automation
 pub fn select_tool_uia(hwnd: HWND, tool_name: &str) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
     info!("Selecting tool '{}' using UI Automation", tool_name);
     
     // Initialize UIA if needed
@@ -380,6 +397,7 @@ pub fn select_tool_uia(hwnd: HWND, tool_name: &str) -> Result<()> {
 
 /// Set color in Paint using UI Automation
 pub fn set_color_uia(hwnd: HWND, color_hex: &str) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
     info!("Setting color to '{}' using UI Automation", color_hex);
     
     // Initialize UIA
@@ -505,7 +523,7 @@ pub fn set_color_uia(hwnd: HWND, color_hex: &str) -> Result<()> {
     };
     
     // Wait for the color dialog to appear
-    std::thread::sleep(Duration::from_millis(500));
+    crate::clock::sleep(Duration::from_millis(500));
     
     // TODO: Implement the actual color selection using the hex value
     // This would involve finding and interacting with the RGB input fields
@@ -527,8 +545,1021 @@ pub fn set_color_uia(hwnd: HWND, color_hex: &str) -> Result<()> {
     ))
 }
 
+/// Selects which color slot the color picker edits: 1 for Color 1 (left
+/// button), 2 for Color 2 (right button). Classic Paint names the swatches
+/// "Color 1"/"Color 2"; Windows 11 Paint calls them primary/secondary.
+pub fn select_color_slot_uia(hwnd: HWND, slot: u32) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Selecting Color {} using UI Automation", slot);
+    
+    let names: &[&str] = match slot {
+        1 => &["color 1", "primary color"],
+        2 => &["color 2", "secondary color"],
+        _ => return Err(MspMcpError::InvalidParameters(format!("Color slot must be 1 or 2, got {}", slot))),
+    };
+    
+    // Initialize UIA
+    let automation = initialize_uia()?;
+    
+    // Get the Paint window element
+    let window = automation.element_from_handle((hwnd as isize).into())
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to get Paint window element: {}", e)))?;
+    
+    let true_condition = automation.create_true_condition()
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to create UICondition: {}", e)))?;
+    
+    let all_elements = window.find_all(TreeScope::Subtree, &true_condition)
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Error finding elements: {}", e)))?;
+    
+    // The swatch is a button (or radio button) whose name starts with the slot name
+    let swatch = all_elements.into_iter().find(|el| {
+        el.get_name()
+            .map(|name| {
+                let name_lower = name.to_lowercase();
+                names.iter().any(|n| name_lower.starts_with(n))
+            })
+            .unwrap_or(false)
+    });
+    
+    let swatch = match swatch {
+        Some(swatch) => swatch,
+        None => {
+            warn!("Could not find the Color {} swatch", slot);
+            return Err(MspMcpError::ElementNotFound(format!("Color {} swatch", slot)));
+        }
+    };
+    
+    // Click the swatch
+    match swatch.get_pattern::<UIInvokePattern>() {
+        Ok(invoke_pattern) => invoke_pattern.invoke()
+            .map_err(|e| MspMcpError::WindowsApiError(format!("Error invoking Color {} swatch: {}", slot, e)))?,
+        Err(_) => {
+            // Try sending space key as a fallback
+            swatch.send_keys(" ", 10)
+                .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to activate Color {} swatch: {}", slot, e)))?;
+        }
+    }
+    
+    crate::clock::sleep(Duration::from_millis(200));
+    Ok(())
+}
+
+/// Adds a color to Paint's custom colors. Opens Edit colors, enters the
+/// color in the Hex field (or Red/Green/Blue where there is none), presses
+/// "Add to custom colors" on dialogs that have it and then OK. Paint puts the
+/// color first among the custom colors and sets the selected slot to it.
+pub fn add_custom_color_uia(hwnd: HWND, color_hex: &str) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Adding custom color '{}' using UI Automation", color_hex);
+    let color = crate::palette::normalize_color(color_hex)?;
+    let automation = initialize_uia()?;
+
+    let edit_colors = find_named(&automation, hwnd, |name| name.contains("edit color") || name.contains("more color"))?
+        .ok_or_else(|| MspMcpError::ElementNotFound("Edit colors button".to_string()))?;
+    activate_element(&edit_colors, "Edit colors button")?;
+
+    // Wait for the dialog to appear
+    crate::clock::sleep(Duration::from_millis(500));
+
+    let filled = fill_color_fields(&automation, hwnd, &color)
+        .and_then(|()| match press_dialog_button(&automation, hwnd, "add to custom colors") {
+            Err(MspMcpError::ElementNotFound(_)) => Ok(()),
+            other => other,
+        })
+        .and_then(|()| press_dialog_button(&automation, hwnd, "ok"));
+    if let Err(e) = filled {
+        warn!("Could not add custom color {}: {}", color, e);
+        // Close the dialog again
+        let _ = press_dialog_button(&automation, hwnd, "cancel");
+        return Err(e);
+    }
+
+    crate::clock::sleep(Duration::from_millis(300));
+    info!("Added custom color {}", color);
+    Ok(())
+}
+
+// Enters a "#RRGGBB" color in the open Edit colors dialog
+fn fill_color_fields(automation: &UIAutomation, hwnd: HWND, color: &str) -> Result<()> {
+    match set_dialog_field(automation, hwnd, "hex", &color[1..]) {
+        Err(MspMcpError::ElementNotFound(_)) => {}
+        other => return other,
+    }
+    for (label, channel) in [("red", &color[1..3]), ("green", &color[3..5]), ("blue", &color[5..7])] {
+        let value = u8::from_str_radix(channel, 16)
+            .map_err(|_| MspMcpError::InvalidParameters(format!("Color must be in #RRGGBB format, got '{}'", color)))?;
+        set_dialog_field(automation, hwnd, label, &value.to_string())?;
+    }
+    Ok(())
+}
+
+/// Clicks the custom color swatch at `index`, counted from the newest (0).
+/// The swatches are the ones named "Custom color" in the Colors section.
+pub fn select_custom_color_uia(hwnd: HWND, index: usize) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Selecting custom color {} using UI Automation", index);
+    let automation = initialize_uia()?;
+
+    let window = automation.element_from_handle((hwnd as isize).into())
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to get Paint window element: {}", e)))?;
+    let swatches = find_all_named(&automation, &window, |name| name.starts_with("custom color"))?;
+    let swatch = swatches.get(index).ok_or_else(|| MspMcpError::ElementNotFound(
+        format!("Custom color {} (Paint shows {})", index, swatches.len())))?;
+    activate_element(swatch, &format!("custom color {}", index))?;
+
+    crate::clock::sleep(Duration::from_millis(200));
+    Ok(())
+}
+
+/// Clicks an element through its Invoke pattern, falling back to a space
+/// key press for elements that don't expose Invoke.
+fn activate_element(element: &UIElement, what: &str) -> Result<()> {
+    match element.get_pattern::<UIInvokePattern>() {
+        Ok(invoke_pattern) => invoke_pattern.invoke()
+            .map_err(|e| MspMcpError::WindowsApiError(format!("Error invoking {}: {}", what, e))),
+        Err(_) => element.send_keys(" ", 10)
+            .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to activate {}: {}", what, e))),
+    }
+}
+
+/// Finds the elements under `root` whose (lowercased) name satisfies `matches`.
+fn find_all_named(automation: &UIAutomation, root: &UIElement, matches: impl Fn(&str) -> bool) -> Result<Vec<UIElement>> {
+    let true_condition = automation.create_true_condition()
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to create UICondition: {}", e)))?;
+    let all_elements = root.find_all(TreeScope::Subtree, &true_condition)
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Error finding elements: {}", e)))?;
+    Ok(all_elements.into_iter()
+        .filter(|el| el.get_name().map(|name| matches(&name.to_lowercase())).unwrap_or(false))
+        .collect())
+}
+
+/// Finds the first element under the Paint window whose name satisfies `matches`.
+fn find_named(automation: &UIAutomation, hwnd: HWND, matches: impl Fn(&str) -> bool) -> Result<Option<UIElement>> {
+    let window = automation.element_from_handle((hwnd as isize).into())
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to get Paint window element: {}", e)))?;
+    Ok(find_all_named(automation, &window, matches)?.into_iter().next())
+}
+
+/// Name of a brush variant in Windows 11 Paint's Brushes flyout.
+pub fn brush_type_label(brush_type: &str) -> Option<&'static str> {
+    match brush_type {
+        "brush" => Some("Brush"),
+        "calligraphy" => Some("Calligraphy brush"),
+        "calligraphy_pen" => Some("Calligraphy pen"),
+        "spray" => Some("Airbrush"),
+        "oil" => Some("Oil brush"),
+        "crayon" => Some("Crayon"),
+        "marker" => Some("Marker"),
+        "natural_pencil" => Some("Natural pencil"),
+        "watercolor" => Some("Watercolor brush"),
+        _ => None,
+    }
+}
+
+/// Picks a brush variant by opening the Brushes flyout and clicking the
+/// variant's entry. Selecting a variant also makes the brush the active tool.
+pub fn select_brush_type_uia(hwnd: HWND, brush_type: &str) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Selecting brush type '{}' using UI Automation", brush_type);
+    
+    let label = brush_type_label(brush_type)
+        .ok_or_else(|| MspMcpError::InvalidParameters(format!("Unsupported brush type: {}", brush_type)))?;
+    
+    let automation = initialize_uia()?;
+    
+    // Open the flyout
+    let brushes_button = find_named(&automation, hwnd, |name| name.starts_with("brushes"))?
+        .ok_or_else(|| MspMcpError::ElementNotFound("Brushes button".to_string()))?;
+    activate_element(&brushes_button, "Brushes button")?;
+    
+    // Wait for the flyout to appear
+    crate::clock::sleep(Duration::from_millis(500));
+    
+    let label_lower = label.to_lowercase();
+    let entry = match find_named(&automation, hwnd, |name| name == label_lower)? {
+        Some(entry) => entry,
+        None => {
+            warn!("Could not find brush '{}' in the Brushes flyout", label);
+            // Close the flyout again
+            brushes_button.send_keys("{ESC}", 10)
+                .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to send Escape key: {}", e)))?;
+            return Err(MspMcpError::ElementNotFound(format!("Brush '{}'", label)));
+        }
+    };
+    activate_element(&entry, &format!("brush '{}'", label))?;
+    
+    crate::clock::sleep(Duration::from_millis(300));
+    info!("Selected brush '{}'", label);
+    Ok(())
+}
+
+// --- Save As dialog ---
+
+// How long to wait for the Save As dialog and for the save to finish
+const SAVE_DIALOG_TIMEOUT: Duration = Duration::from_secs(5);
+const SAVE_FINISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Finds an element in any of Paint's top-level windows, which includes the
+/// file dialogs and the message boxes they raise.
+fn find_in_paint_windows(automation: &UIAutomation, hwnd: HWND, matches: impl Fn(&str) -> bool, keep: impl Fn(&UIElement) -> bool) -> Result<Option<UIElement>> {
+    let pid = windows::get_window_pid(hwnd);
+    let root = automation.get_root_element()
+        .map_err(|e| MspMcpError::UiAutomationError(format!("Failed to get the desktop element: {}", e)))?;
+    let true_condition = automation.create_true_condition()
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to create UICondition: {}", e)))?;
+    let windows = root.find_all(TreeScope::Children, &true_condition)
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Error finding windows: {}", e)))?;
+    for window in windows.into_iter().filter(|w| w.get_process_id().map(|id| id == pid).unwrap_or(false)) {
+        if let Some(found) = find_all_named(automation, &window, &matches)?.into_iter().find(&keep) {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
+fn is_edit(element: &UIElement) -> bool {
+    element.get_control_type().map(|t| t == EditControl::TYPE).unwrap_or(false)
+}
+
+fn is_combo_box(element: &UIElement) -> bool {
+    element.get_control_type().map(|t| t == ComboBoxControl::TYPE).unwrap_or(false)
+}
+
+/// Clicks a button (by its exact, lowercased label) on one of Paint's dialogs.
+fn press_dialog_button(automation: &UIAutomation, hwnd: HWND, label: &str) -> Result<()> {
+    let button = find_in_paint_windows(automation, hwnd, |name| name == label, is_button)?
+        .ok_or_else(|| MspMcpError::ElementNotFound(format!("'{}' button", label)))?;
+    activate_element(&button, label)
+}
+
+/// Picks the entry of the "Save as type" list whose name contains `pattern`
+/// (e.g. "*.png"). Returns false if the list or entry isn't there.
+fn select_save_type(automation: &UIAutomation, hwnd: HWND, pattern: &str) -> Result<bool> {
+    let combo = match find_in_paint_windows(automation, hwnd, |name| name.starts_with("save as type"), is_combo_box)? {
+        Some(combo) => combo,
+        None => return Ok(false),
+    };
+    if let Ok(expand) = combo.get_pattern::<UIExpandCollapsePattern>() {
+        let _ = expand.expand();
+        crate::clock::sleep(Duration::from_millis(200));
+    }
+    let entry = find_all_named(automation, &combo, |name| name.contains(pattern))?
+        .into_iter()
+        .find(|el| el.get_control_type().map(|t| t == ListItemControl::TYPE).unwrap_or(false));
+    let selected = match entry {
+        Some(entry) => entry.get_pattern::<UISelectionItemPattern>()
+            .and_then(|item| item.select())
+            .is_ok(),
+        None => false,
+    };
+    if let Ok(expand) = combo.get_pattern::<UIExpandCollapsePattern>() {
+        let _ = expand.collapse();
+    }
+    Ok(selected)
+}
+
+/// Fills in the Save As dialog (already opened with F12) and confirms it,
+/// then answers whatever Paint asks next: the "already exists" confirmation
+/// (replaced only if `overwrite`), a JPEG quality prompt, and warnings that
+/// the format drops transparency, layers or colors. With `require_type`, a
+/// format that can't be picked in "Save as type" cancels the dialog instead
+/// of leaving the type to the file extension. Gives up with a timeout
+/// instead of hanging if the dialog doesn't go away.
+pub fn save_as_uia(hwnd: HWND, file_path: &str, format: &str, overwrite: bool, jpeg_quality: Option<u32>, require_type: bool) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Saving as {} ({}) using UI Automation", file_path, format);
+    let automation = initialize_uia()?;
+    let is_file_name = |name: &str| name == "file name:";
+    
+    // Wait for the dialog
+    let started = crate::clock::now();
+    let file_name = loop {
+        if let Some(edit) = find_in_paint_windows(&automation, hwnd, is_file_name, is_edit)? {
+            break edit;
+        }
+        if crate::clock::now() - started > SAVE_DIALOG_TIMEOUT {
+            return Err(MspMcpError::OperationTimeout("The Save As dialog did not open".to_string()));
+        }
+        crate::clock::sleep(Duration::from_millis(250));
+    };
+    
+    let value = file_name.get_pattern::<UIValuePattern>()
+        .map_err(|e| MspMcpError::UiAutomationError(format!("File name box has no ValuePattern: {}", e)))?;
+    value.set_value(file_path)
+        .map_err(|e| MspMcpError::UiAutomationError(format!("Failed to enter the file name: {}", e)))?;
+    
+    let type_pattern = match format {
+        "jpeg" => "*.jpg",
+        "bmp" => "*.bmp",
+        "gif" => "*.gif",
+        _ => "*.png",
+    };
+    if !select_save_type(&automation, hwnd, type_pattern)? {
+        if require_type {
+            press_dialog_button(&automation, hwnd, "cancel")?;
+            return Err(MspMcpError::ElementNotFound(format!("'{}' in the Save as type list", type_pattern)));
+        }
+        warn!("Could not pick '{}' in Save as type; relying on the file extension", type_pattern);
+    }
+    press_dialog_button(&automation, hwnd, "save")?;
+    
+    // Answer follow-up dialogs until the Save As dialog is gone
+    let started = crate::clock::now();
+    loop {
+        crate::clock::sleep(Duration::from_millis(250));
+        
+        if find_in_paint_windows(&automation, hwnd, |name| name.contains("already exists"), |_| true)?.is_some() {
+            if overwrite {
+                info!("Replacing existing file {}", file_path);
+                press_dialog_button(&automation, hwnd, "yes")?;
+                continue;
+            }
+            press_dialog_button(&automation, hwnd, "no")?;
+            crate::clock::sleep(Duration::from_millis(250));
+            press_dialog_button(&automation, hwnd, "cancel")?;
+            return Err(MspMcpError::ConfirmationRequired(
+                format!("{} already exists; pass overwrite: true to replace it", file_path)));
+        }
+        
+        if let Some(quality) = find_in_paint_windows(&automation, hwnd, |name| name.contains("quality"),
+            |el| el.get_pattern::<UIRangeValuePattern>().is_ok() || el.get_pattern::<UIValuePattern>().is_ok())? {
+            let level = jpeg_quality.unwrap_or(crate::protocol::DEFAULT_JPEG_QUALITY);
+            info!("Setting JPEG quality to {}", level);
+            if let Ok(range) = quality.get_pattern::<UIRangeValuePattern>() {
+                range.set_value(level as f64)
+                    .map_err(|e| MspMcpError::UiAutomationError(format!("Failed to set JPEG quality: {}", e)))?;
+            } else if let Ok(value) = quality.get_pattern::<UIValuePattern>() {
+                value.set_value(&level.to_string())
+                    .map_err(|e| MspMcpError::UiAutomationError(format!("Failed to set JPEG quality: {}", e)))?;
+            }
+            press_dialog_button(&automation, hwnd, "ok")?;
+            continue;
+        }
+        
+        let is_format_warning = |name: &str| name.contains("transparen") || name.contains("flatten") || name.contains("will be lost")
+            || name.contains("color quality");
+        if find_in_paint_windows(&automation, hwnd, is_format_warning, |_| true)?.is_some() {
+            info!("Accepting Paint's format warning for {}", format);
+            press_dialog_button(&automation, hwnd, "ok")
+                .or_else(|_| press_dialog_button(&automation, hwnd, "save"))?;
+            continue;
+        }
+        
+        if find_in_paint_windows(&automation, hwnd, is_file_name, is_edit)?.is_none() {
+            return Ok(());
+        }
+        if crate::clock::now() - started > SAVE_FINISH_TIMEOUT {
+            return Err(MspMcpError::OperationTimeout(
+                "Saving did not finish; Paint may be showing an unexpected dialog".to_string()));
+        }
+    }
+}
+
+// --- Image Properties dialog ---
+
+// How long to wait for Image Properties to open, and to close after OK
+const IMAGE_PROPERTIES_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn is_radio_button(element: &UIElement) -> bool {
+    element.get_control_type().map(|t| t == RadioButtonControl::TYPE).unwrap_or(false)
+}
+
+/// Picks a radio button (by its exact, lowercased label) on one of Paint's dialogs.
+fn select_dialog_option(automation: &UIAutomation, hwnd: HWND, label: &str) -> Result<()> {
+    let option = find_in_paint_windows(automation, hwnd, |name| name == label, is_radio_button)?
+        .ok_or_else(|| MspMcpError::ElementNotFound(format!("'{}' option", label)))?;
+    match option.get_pattern::<UISelectionItemPattern>() {
+        Ok(item) => item.select()
+            .map_err(|e| MspMcpError::UiAutomationError(format!("Failed to select '{}': {}", label, e))),
+        Err(_) => activate_element(&option, label),
+    }
+}
+
+/// Replaces the text of an edit box (by its lowercased label, with or
+/// without a trailing colon) on one of Paint's dialogs.
+fn set_dialog_field(automation: &UIAutomation, hwnd: HWND, label: &str, value: &str) -> Result<()> {
+    let edit = find_in_paint_windows(automation, hwnd, |name| name.trim_end_matches(':') == label, is_edit)?
+        .ok_or_else(|| MspMcpError::ElementNotFound(format!("'{}' field", label)))?;
+    edit.get_pattern::<UIValuePattern>()
+        .map_err(|e| MspMcpError::UiAutomationError(format!("'{}' field has no ValuePattern: {}", label, e)))?
+        .set_value(value)
+        .map_err(|e| MspMcpError::UiAutomationError(format!("Failed to set '{}': {}", label, e)))
+}
+
+/// Fills in the Image Properties dialog (already opened with Ctrl+E) and
+/// confirms it, accepting Paint's warning that black and white drops the
+/// colors. Units are picked first because switching them converts the
+/// numbers already in the size fields. If the dialog stays open, Paint
+/// refused a value, so it is cancelled and an error returned.
+pub fn set_image_properties_uia(hwnd: HWND, params: &SetImagePropertiesParams) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Setting image properties {:?} using UI Automation", params);
+    let automation = initialize_uia()?;
+    let is_width = |name: &str| name.trim_end_matches(':') == "width";
+    
+    // Wait for the dialog
+    let started = crate::clock::now();
+    while find_in_paint_windows(&automation, hwnd, is_width, is_edit)?.is_none() {
+        if crate::clock::now() - started > IMAGE_PROPERTIES_TIMEOUT {
+            return Err(MspMcpError::OperationTimeout("The Image Properties dialog did not open".to_string()));
+        }
+        crate::clock::sleep(Duration::from_millis(250));
+    }
+    
+    let units = params.units.unwrap_or_default();
+    if params.width.is_some() || params.height.is_some() {
+        select_dialog_option(&automation, hwnd, match units {
+            ImageUnits::Pixels => "pixels",
+            ImageUnits::Inches => "inches",
+            ImageUnits::Centimeters => "centimeters",
+        })?;
+        crate::clock::sleep(Duration::from_millis(100));
+    }
+    // Pixels are whole; inches and centimeters go in with two decimals at most
+    let format_size = |value: f64| match units {
+        ImageUnits::Pixels => format!("{}", value as u32),
+        _ => format!("{}", (value * 100.0).round() / 100.0),
+    };
+    if let Some(width) = params.width {
+        set_dialog_field(&automation, hwnd, "width", &format_size(width))?;
+    }
+    if let Some(height) = params.height {
+        set_dialog_field(&automation, hwnd, "height", &format_size(height))?;
+    }
+    if let Some(mode) = params.color_mode {
+        select_dialog_option(&automation, hwnd, match mode {
+            ColorMode::Color => "color",
+            ColorMode::BlackAndWhite => "black and white",
+        })?;
+    }
+    press_dialog_button(&automation, hwnd, "ok")?;
+    
+    // Answer the black and white warning until the dialog is gone
+    let started = crate::clock::now();
+    loop {
+        crate::clock::sleep(Duration::from_millis(250));
+        
+        let is_color_warning = |name: &str| name.contains("color information") || name.contains("cannot be undone");
+        if find_in_paint_windows(&automation, hwnd, is_color_warning, |_| true)?.is_some() {
+            info!("Accepting Paint's black and white warning");
+            press_dialog_button(&automation, hwnd, "ok")
+                .or_else(|_| press_dialog_button(&automation, hwnd, "yes"))?;
+            continue;
+        }
+        
+        if find_in_paint_windows(&automation, hwnd, is_width, is_edit)?.is_none() {
+            info!("Image properties applied");
+            return Ok(());
+        }
+        if crate::clock::now() - started > IMAGE_PROPERTIES_TIMEOUT {
+            let _ = press_dialog_button(&automation, hwnd, "cancel");
+            return Err(MspMcpError::UiAutomationError(
+                "Paint kept the Image Properties dialog open; it may have rejected the size".to_string()));
+        }
+    }
+}
+
+// --- Unsaved changes prompt ---
+
+/// Answers Paint's "Do you want to save your work?" prompt if it is showing,
+/// returning whether it was. `Fail` cancels the prompt and returns a
+/// ConfirmationRequired error so nothing is lost or overwritten silently.
+pub fn answer_unsaved_changes_prompt_uia(hwnd: HWND, choice: UnsavedChanges) -> Result<bool> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    let automation = initialize_uia()?;
+    
+    // "Don't save" (or "Do not save") only exists on the prompt
+    let is_dont_save = |name: &str| (name.starts_with("don") && name.ends_with("save")) || name == "do not save";
+    let dont_save = match find_named(&automation, hwnd, is_dont_save)? {
+        Some(button) => button,
+        None => return Ok(false),
+    };
+    info!("Paint is asking to save changes; answering {:?}", choice);
+    
+    let walker = automation.get_control_view_walker()
+        .map_err(|e| MspMcpError::UiAutomationError(format!("Failed to create tree walker: {}", e)))?;
+    let dialog = walker.get_parent(&dont_save)
+        .map_err(|e| MspMcpError::UiAutomationError(format!("Failed to find the save prompt: {}", e)))?;
+    let button_in_dialog = |label: &str| -> Result<UIElement> {
+        find_all_named(&automation, &dialog, |name| name == label)?
+            .into_iter()
+            .find(is_button)
+            .ok_or_else(|| MspMcpError::ElementNotFound(format!("'{}' button on the save prompt", label)))
+    };
+    
+    match choice {
+        UnsavedChanges::Save => {
+            let title = windows::get_window_title(hwnd);
+            let (name, _) = crate::document::parse_paint_title(&title);
+            if name.as_deref().map_or(true, |name| name.eq_ignore_ascii_case("untitled")) {
+                activate_element(&button_in_dialog("cancel")?, "Cancel button")?;
+                return Err(MspMcpError::InvalidParameters(
+                    "The document has never been saved, so it can't be saved without a file name; use \"discard\" or save it first".to_string()));
+            }
+            activate_element(&button_in_dialog("save")?, "Save button")?;
+        }
+        UnsavedChanges::Discard => activate_element(&dont_save, "Don't save button")?,
+        UnsavedChanges::Fail => {
+            activate_element(&button_in_dialog("cancel")?, "Cancel button")?;
+            return Err(MspMcpError::ConfirmationRequired(
+                "Paint has unsaved changes; pass unsaved_changes \"save\" or \"discard\"".to_string()));
+        }
+    }
+    
+    crate::clock::sleep(Duration::from_millis(500));
+    Ok(true)
+}
+
+// --- Selection transforms ---
+
+/// True if Paint has an active selection. The Crop button is only enabled
+/// while something is selected.
+fn has_selection(automation: &UIAutomation, hwnd: HWND) -> Result<bool> {
+    let crop = find_named(automation, hwnd, |name| name == "crop")?
+        .ok_or_else(|| MspMcpError::ElementNotFound("Crop button".to_string()))?;
+    Ok(crop.is_enabled().unwrap_or(false))
+}
+
+/// True if something is selected on the canvas.
+pub fn selection_active_uia(hwnd: HWND) -> Result<bool> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    let automation = initialize_uia()?;
+    has_selection(&automation, hwnd)
+}
+
+/// Opens the toolbar dropdown whose button name starts with `button_prefix`
+/// and invokes the entry whose name starts with `command`.
+fn run_dropdown_command(automation: &UIAutomation, hwnd: HWND, button_prefix: &str, command: &str) -> Result<()> {
+    let window = automation.element_from_handle((hwnd as isize).into())
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to get Paint window element: {}", e)))?;
+    let button = find_all_named(automation, &window, |name| name.starts_with(button_prefix))?
+        .into_iter()
+        .find(is_button)
+        .ok_or_else(|| MspMcpError::ElementNotFound(format!("'{}' dropdown", button_prefix)))?;
+    activate_element(&button, button_prefix)?;
+    crate::clock::sleep(Duration::from_millis(500));
+    
+    let command_lower = command.to_lowercase();
+    let entry = match find_named(automation, hwnd, |name| name.starts_with(&command_lower))? {
+        Some(entry) => entry,
+        None => {
+            warn!("Could not find '{}' in the '{}' dropdown", command, button_prefix);
+            button.send_keys("{ESC}", 10)
+                .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to send Escape key: {}", e)))?;
+            return Err(MspMcpError::ElementNotFound(format!("'{}' command", command)));
+        }
+    };
+    activate_element(&entry, command)?;
+    crate::clock::sleep(Duration::from_millis(300));
+    Ok(())
+}
+
+/// Runs an entry of the Rotate dropdown ("Rotate right 90°", "Flip
+/// vertical", ...) on the current selection. Fails if nothing is selected,
+/// since Paint would otherwise transform the whole image.
+fn transform_selection_uia(hwnd: HWND, command: &str) -> Result<()> {
+    info!("Applying '{}' to the selection using UI Automation", command);
+    let automation = initialize_uia()?;
+    
+    if !has_selection(&automation, hwnd)? {
+        return Err(MspMcpError::General("Nothing is selected".to_string()));
+    }
+    run_dropdown_command(&automation, hwnd, "rotate", command)
+}
+
+/// Inverts the colors of the selection through the Select dropdown's
+/// "Invert color" entry, for builds without the Ctrl+Shift+I shortcut.
+pub fn invert_colors_uia(hwnd: HWND) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Inverting colors using UI Automation");
+    let automation = initialize_uia()?;
+    run_dropdown_command(&automation, hwnd, "select", "Invert color")
+}
+
+/// Rotates the selection clockwise by 90, 180 or 270 degrees.
+pub fn rotate_selection_uia(hwnd: HWND, degrees: u32) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    let command = match degrees {
+        90 => "Rotate right 90",
+        180 => "Rotate 180",
+        270 => "Rotate left 90",
+        _ => return Err(MspMcpError::InvalidParameters(format!("Rotation must be 90, 180 or 270 degrees, got {}", degrees))),
+    };
+    transform_selection_uia(hwnd, command)
+}
+
+/// Mirrors the selection; `direction` is "horizontal" or "vertical".
+pub fn flip_selection_uia(hwnd: HWND, direction: &str) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    let command = match direction {
+        "horizontal" => "Flip horizontal",
+        "vertical" => "Flip vertical",
+        _ => return Err(MspMcpError::InvalidParameters(format!("Flip direction must be 'horizontal' or 'vertical', got '{}'", direction))),
+    };
+    transform_selection_uia(hwnd, command)
+}
+
+// --- Capabilities ---
+//
+// Paint gains features from release to release (brush variants, shapes,
+// layers, background removal), so rather than guess from the version
+// number, get_supported_tools looks for the controls themselves.
+
+// Tools select_tool picks, and how the names of their toolbar buttons start
+const TOOL_LABELS: &[(&str, &str)] = &[
+    ("pencil", "pencil"), ("brush", "brushes"), ("fill", "fill"), ("text", "text"),
+    ("eraser", "eraser"), ("select", "select"), ("shape", "shapes"),
+];
+
+/// Name of a shape in Windows 11 Paint's Shapes gallery.
+fn shape_label(shape_type: &str) -> Option<&'static str> {
+    match shape_type {
+        "rectangle" => Some("Rectangle"),
+        "ellipse" => Some("Oval"),
+        "line" => Some("Line"),
+        "arrow" => Some("Right arrow"),
+        "triangle" => Some("Triangle"),
+        "pentagon" => Some("Pentagon"),
+        "hexagon" => Some("Hexagon"),
+        _ => None,
+    }
+}
+
+/// Lowercased names of the elements under the Paint window that `keep` accepts.
+fn element_names(automation: &UIAutomation, hwnd: HWND, keep: impl Fn(&UIElement) -> bool) -> Result<HashSet<String>> {
+    let window = automation.element_from_handle((hwnd as isize).into())
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to get Paint window element: {}", e)))?;
+    Ok(find_all_named(automation, &window, |_| true)?
+        .into_iter()
+        .filter(|el| keep(el))
+        .filter_map(|el| el.get_name().ok())
+        .map(|name| name.to_lowercase())
+        .collect())
+}
+
+/// Lowercased names of the elements shown while the flyout of the button
+/// whose name starts with `button_prefix` is open, or none if Paint has no
+/// such button. Closes the flyout again.
+fn flyout_names(automation: &UIAutomation, hwnd: HWND, button_prefix: &str) -> Result<HashSet<String>> {
+    let window = automation.element_from_handle((hwnd as isize).into())
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to get Paint window element: {}", e)))?;
+    let Some(button) = find_all_named(automation, &window, |name| name.starts_with(button_prefix))?
+        .into_iter()
+        .find(is_button) else {
+        return Ok(HashSet::new());
+    };
+    activate_element(&button, button_prefix)?;
+    crate::clock::sleep(Duration::from_millis(500));
+    let names = element_names(automation, hwnd, |_| true);
+    button.send_keys("{ESC}", 10)
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to send Escape key: {}", e)))?;
+    names
+}
+
+/// Lists the tools, brush variants and shapes this Paint build has, and
+/// whether it has layers and background removal. The Brushes flyout is
+/// opened to read its entries, and so is the Shapes gallery when a narrow
+/// window has collapsed it into a button.
+pub fn read_capabilities_uia(hwnd: HWND) -> Result<PaintCapabilities> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Reading Paint's capabilities using UI Automation");
+    
+    let automation = initialize_uia()?;
+    let names = element_names(&automation, hwnd, |_| true)?;
+    let buttons = element_names(&automation, hwnd, is_button)?;
+    let starts_with = |names: &HashSet<String>, prefix: &str| names.iter().any(|name| name.starts_with(prefix));
+    
+    let tools = TOOL_LABELS.iter()
+        .filter(|(_, label)| starts_with(&buttons, label))
+        .map(|(tool, _)| tool.to_string())
+        .collect();
+    
+    let brushes = flyout_names(&automation, hwnd, "brushes")?;
+    let brush_types = BRUSH_TYPES.iter()
+        .filter(|brush_type| brush_type_label(brush_type).is_some_and(|label| brushes.contains(&label.to_lowercase())))
+        .map(|brush_type| brush_type.to_string())
+        .collect();
+    
+    let in_gallery = |names: &HashSet<String>| -> Vec<String> {
+        SHAPE_TYPES.iter()
+            .filter(|shape| shape_label(shape).is_some_and(|label| names.contains(&label.to_lowercase())))
+            .map(|shape| shape.to_string())
+            .collect()
+    };
+    let mut shapes = in_gallery(&names);
+    if shapes.is_empty() {
+        shapes = in_gallery(&flyout_names(&automation, hwnd, "shapes")?);
+    }
+    
+    let capabilities = PaintCapabilities {
+        tools,
+        brush_types,
+        shapes,
+        layers: names.contains("layers"),
+        background_removal: starts_with(&names, "remove background"),
+    };
+    info!("Paint capabilities: {:?}", capabilities);
+    Ok(capabilities)
+}
+
+// --- Layers ---
+//
+// Paint builds from late 2023 on have a Layers panel. Layers are addressed
+// by their position in the panel, which lists the top layer first.
+
+fn is_button(element: &UIElement) -> bool {
+    element.get_control_type().map(|t| t == ButtonControl::TYPE).unwrap_or(false)
+}
+
+/// Finds the Layers panel, opening it from the toolbar if it is closed.
+/// Builds without layers have neither the panel nor its toolbar button.
+fn find_layers_pane(automation: &UIAutomation, hwnd: HWND) -> Result<UIElement> {
+    let window = automation.element_from_handle((hwnd as isize).into())
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to get Paint window element: {}", e)))?;
+    let named_layers = |name: &str| name == "layers";
+    
+    let elements = find_all_named(automation, &window, named_layers)?;
+    if let Some(pane) = elements.iter().find(|el| !is_button(el)) {
+        return Ok(pane.clone());
+    }
+    let toggle = elements.iter().find(|el| is_button(el)).ok_or_else(|| {
+        MspMcpError::OperationNotSupported("This Paint build has no Layers panel".to_string())
+    })?;
+    
+    info!("Opening the Layers panel");
+    activate_element(toggle, "Layers button")?;
+    crate::clock::sleep(Duration::from_millis(500));
+    
+    find_all_named(automation, &window, named_layers)?
+        .into_iter()
+        .find(|el| !is_button(el))
+        .ok_or_else(|| MspMcpError::ElementNotFound("Layers panel".to_string()))
+}
+
+/// The layer entries in the panel, top layer first.
+fn layer_items(pane: &UIElement, automation: &UIAutomation) -> Result<Vec<UIElement>> {
+    let true_condition = automation.create_true_condition()
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to create UICondition: {}", e)))?;
+    let elements = pane.find_all(TreeScope::Subtree, &true_condition)
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Error finding elements: {}", e)))?;
+    Ok(elements.into_iter()
+        .filter(|el| el.get_control_type().map(|t| t == ListItemControl::TYPE).unwrap_or(false))
+        .collect())
+}
+
+fn layer_at(items: &[UIElement], index: u32) -> Result<&UIElement> {
+    items.get(index as usize).ok_or_else(|| MspMcpError::InvalidParameters(format!(
+        "Layer {} does not exist (there are {} layers)", index, items.len())))
+}
+
+/// Makes a layer entry the selected (active) layer.
+fn select_layer_item(item: &UIElement, index: u32) -> Result<()> {
+    match item.get_pattern::<UISelectionItemPattern>() {
+        Ok(selection_pattern) => selection_pattern.select()
+            .map_err(|e| MspMcpError::WindowsApiError(format!("Error selecting layer {}: {}", index, e)))?,
+        Err(_) => activate_element(item, &format!("layer {}", index))?,
+    }
+    crate::clock::sleep(Duration::from_millis(200));
+    Ok(())
+}
+
+/// Runs a per-layer command ("Delete layer", "Merge layer down") on the
+/// selected layer. The commands live in the layer's options menu on some
+/// builds, so that menu is opened if the command isn't visible.
+fn run_layer_command(automation: &UIAutomation, hwnd: HWND, item: &UIElement, command: &str) -> Result<()> {
+    let command_lower = command.to_lowercase();
+    let mut button = find_named(automation, hwnd, |name| name == command_lower)?;
+    if button.is_none() {
+        let options = find_all_named(automation, item, |name| name.contains("option"))?;
+        if let Some(options) = options.first() {
+            activate_element(options, "layer options")?;
+            crate::clock::sleep(Duration::from_millis(300));
+            button = find_named(automation, hwnd, |name| name == command_lower)?;
+        }
+    }
+    let button = button.ok_or_else(|| MspMcpError::ElementNotFound(format!("'{}' command", command)))?;
+    activate_element(&button, command)?;
+    crate::clock::sleep(Duration::from_millis(300));
+    Ok(())
+}
+
+/// Adds a layer above the selected one.
+pub fn add_layer_uia(hwnd: HWND) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Adding a layer using UI Automation");
+    let automation = initialize_uia()?;
+    let pane = find_layers_pane(&automation, hwnd)?;
+    let add_button = find_all_named(&automation, &pane, |name| name == "add layer" || name == "new layer")?
+        .into_iter()
+        .next()
+        .ok_or_else(|| MspMcpError::ElementNotFound("Add layer button".to_string()))?;
+    activate_element(&add_button, "Add layer button")?;
+    crate::clock::sleep(Duration::from_millis(300));
+    Ok(())
+}
+
+/// Selects the layer at `index` (0 = top of the panel).
+pub fn select_layer_uia(hwnd: HWND, index: u32) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Selecting layer {} using UI Automation", index);
+    let automation = initialize_uia()?;
+    let pane = find_layers_pane(&automation, hwnd)?;
+    let items = layer_items(&pane, &automation)?;
+    select_layer_item(layer_at(&items, index)?, index)
+}
+
+/// Shows or hides the layer at `index`. The layer's toggle is named for
+/// what it will do ("Hide layer" while visible), so it's only clicked when
+/// the layer isn't in the requested state already.
+pub fn set_layer_visibility_uia(hwnd: HWND, index: u32, visible: bool) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Setting layer {} visibility to {} using UI Automation", index, visible);
+    let automation = initialize_uia()?;
+    let pane = find_layers_pane(&automation, hwnd)?;
+    let items = layer_items(&pane, &automation)?;
+    let item = layer_at(&items, index)?;
+    
+    let wanted = if visible { "show" } else { "hide" };
+    let toggles = find_all_named(&automation, item, |name| name.starts_with("show") || name.starts_with("hide"))?;
+    let toggle = toggles.first()
+        .ok_or_else(|| MspMcpError::ElementNotFound(format!("Visibility toggle of layer {}", index)))?;
+    let label = toggle.get_name().unwrap_or_default().to_lowercase();
+    if label.starts_with(wanted) {
+        activate_element(toggle, &format!("visibility toggle of layer {}", index))?;
+        crate::clock::sleep(Duration::from_millis(200));
+    } else {
+        debug!("Layer {} is already {}", index, if visible { "visible" } else { "hidden" });
+    }
+    Ok(())
+}
+
+/// Merges the layer at `index` into the layer below it.
+pub fn merge_layer_down_uia(hwnd: HWND, index: u32) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Merging layer {} down using UI Automation", index);
+    let automation = initialize_uia()?;
+    let pane = find_layers_pane(&automation, hwnd)?;
+    let items = layer_items(&pane, &automation)?;
+    let item = layer_at(&items, index)?;
+    if index as usize + 1 >= items.len() {
+        return Err(MspMcpError::InvalidParameters(format!("Layer {} is the bottom layer; there is nothing to merge it into", index)));
+    }
+    select_layer_item(item, index)?;
+    run_layer_command(&automation, hwnd, item, "Merge layer down")
+}
+
+/// Deletes the layer at `index`. Paint keeps at least one layer.
+pub fn delete_layer_uia(hwnd: HWND, index: u32) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Deleting layer {} using UI Automation", index);
+    let automation = initialize_uia()?;
+    let pane = find_layers_pane(&automation, hwnd)?;
+    let items = layer_items(&pane, &automation)?;
+    let item = layer_at(&items, index)?;
+    if items.len() == 1 {
+        return Err(MspMcpError::InvalidParameters("Can't delete the only layer".to_string()));
+    }
+    select_layer_item(item, index)?;
+    run_layer_command(&automation, hwnd, item, "Delete layer")
+}
+
+// --- Canvas size and view ---
+
+/// Reads the document size and zoom level from the status bar, and the
+/// viewport and scroll position from the scroll viewer the canvas sits in.
+pub fn read_canvas_view_uia(hwnd: HWND) -> Result<CanvasDimensionsResponse> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    let automation = initialize_uia()?;
+    let window = automation.element_from_handle((hwnd as isize).into())
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to get Paint window element: {}", e)))?;
+    let true_condition = automation.create_true_condition()
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to create UICondition: {}", e)))?;
+    let elements = window.find_all(TreeScope::Subtree, &true_condition)
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Error finding elements: {}", e)))?;
+    
+    // The status bar shows the cursor position, then the selection size
+    // while something is selected, then the image size
+    let texts: Vec<String> = elements.iter()
+        .filter(|el| el.get_control_type().map(|t| t == TextControl::TYPE).unwrap_or(false))
+        .filter_map(|el| el.get_name().ok())
+        .collect();
+    let (width, height) = texts.iter().rev().find_map(|text| geometry::parse_image_size(text))
+        .ok_or_else(|| MspMcpError::ElementNotFound("Image size in the status bar".to_string()))?;
+    
+    // Some builds only expose the zoom level as the value of the zoom box
+    let zoom = texts.iter().find_map(|text| geometry::parse_zoom(text))
+        .or_else(|| elements.iter()
+            .filter(|el| el.get_name().map(|name| name.to_lowercase().starts_with("zoom")).unwrap_or(false))
+            .filter_map(|el| el.get_pattern::<UIValuePattern>().ok()?.get_value().ok())
+            .find_map(|value| geometry::parse_zoom(&value)))
+        .unwrap_or_else(|| {
+            warn!("Could not read the zoom level; assuming 100%");
+            1.0
+        });
+    
+    let viewer = find_canvas_viewer(&elements)?;
+    let bounds = viewer.get_bounding_rectangle()
+        .map_err(|e| MspMcpError::UiAutomationError(format!("Failed to get the canvas viewport: {}", e)))?;
+    let viewport = RectInfo::new(bounds.get_left(), bounds.get_top(), bounds.get_right(), bounds.get_bottom());
+    let (viewport_width, viewport_height) = geometry::rect_size(viewport.left, viewport.top, viewport.right, viewport.bottom);
+    let scroll = viewer.get_pattern::<UIScrollPattern>()
+        .map_err(|e| MspMcpError::UiAutomationError(format!("Canvas viewport has no ScrollPattern: {}", e)))?;
+    let scroll_x = geometry::scroll_offset(scroll.get_horizontal_scroll_percent().unwrap_or(-1.0), width, zoom, viewport_width);
+    let scroll_y = geometry::scroll_offset(scroll.get_vertical_scroll_percent().unwrap_or(-1.0), height, zoom, viewport_height);
+    
+    debug!("Canvas {}x{} at {}% in a {}x{} viewport, scrolled to ({}, {})",
+        width, height, zoom * 100.0, viewport_width, viewport_height, scroll_x, scroll_y);
+    Ok(CanvasDimensionsResponse { width, height, zoom, viewport, scroll_x, scroll_y, estimated: false })
+}
+
+/// Scrolls the canvas so image pixel `scroll` is at the viewport's top-left
+/// corner, as far as the document allows. `view` is the view it is scrolled
+/// from, as read by read_canvas_view_uia.
+pub fn scroll_canvas_uia(hwnd: HWND, view: &CanvasDimensionsResponse, scroll: (u32, u32)) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    let automation = initialize_uia()?;
+    let window = automation.element_from_handle((hwnd as isize).into())
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to get Paint window element: {}", e)))?;
+    let true_condition = automation.create_true_condition()
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to create UICondition: {}", e)))?;
+    let elements = window.find_all(TreeScope::Subtree, &true_condition)
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Error finding elements: {}", e)))?;
+    let viewer = find_canvas_viewer(&elements)?;
+    
+    let (viewport_width, viewport_height) = geometry::rect_size(view.viewport.left, view.viewport.top, view.viewport.right, view.viewport.bottom);
+    let horizontal = geometry::scroll_percent(scroll.0, view.width, view.zoom, viewport_width);
+    let vertical = geometry::scroll_percent(scroll.1, view.height, view.zoom, viewport_height);
+    debug!("Scrolling the canvas to ({}, {}): {}% across, {}% down", scroll.0, scroll.1, horizontal, vertical);
+    viewer.get_pattern::<UIScrollPattern>()
+        .map_err(|e| MspMcpError::UiAutomationError(format!("Canvas viewport has no ScrollPattern: {}", e)))?
+        .set_scroll_percent(horizontal, vertical)
+        .map_err(|e| MspMcpError::UiAutomationError(format!("Failed to scroll the canvas: {}", e)))
+}
+
+/// The canvas's scroll viewer: the largest scrollable element.
+fn find_canvas_viewer(elements: &[UIElement]) -> Result<&UIElement> {
+    let area = |el: &UIElement| el.get_bounding_rectangle()
+        .map(|r| (r.get_right() - r.get_left()) as i64 * (r.get_bottom() - r.get_top()) as i64)
+        .unwrap_or(0);
+    elements.iter()
+        .filter(|el| el.get_pattern::<UIScrollPattern>().is_ok())
+        .max_by_key(|el| area(el))
+        .ok_or_else(|| MspMcpError::ElementNotFound("Canvas scroll viewer".to_string()))
+}
+
+/// Finds the stroke size slider, if it is on screen.
+fn find_size_slider(automation: &UIAutomation, hwnd: HWND) -> Result<Option<UIElement>> {
+    let window = automation.element_from_handle((hwnd as isize).into())
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to get Paint window element: {}", e)))?;
+    Ok(find_all_named(automation, &window, |name| name.contains("size"))?
+        .into_iter()
+        .find(|el| el.get_control_type().map(|t| t == SliderControl::TYPE).unwrap_or(false)))
+}
+
+/// Sets the stroke width in pixels with the size slider's RangeValuePattern.
+/// Newer Paint builds keep the slider in a flyout behind the Size button,
+/// which is opened first and closed again afterwards.
+pub fn set_size_slider_uia(hwnd: HWND, px: u32) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
+    info!("Setting stroke size to {}px using UI Automation", px);
+    let automation = initialize_uia()?;
+    
+    let mut flyout_button = None;
+    let slider = match find_size_slider(&automation, hwnd)? {
+        Some(slider) => slider,
+        None => {
+            let button = find_named(&automation, hwnd, |name| name == "size" || name == "brush size")?
+                .ok_or_else(|| MspMcpError::ElementNotFound("Size button".to_string()))?;
+            activate_element(&button, "Size button")?;
+            crate::clock::sleep(Duration::from_millis(300));
+            flyout_button = Some(button);
+            find_size_slider(&automation, hwnd)?
+                .ok_or_else(|| MspMcpError::ElementNotFound("Size slider".to_string()))?
+        }
+    };
+    
+    let range = slider.get_pattern::<UIRangeValuePattern>()
+        .map_err(|e| MspMcpError::WindowsApiError(format!("Size slider has no RangeValuePattern: {}", e)))?;
+    let minimum = range.get_minimum().unwrap_or(1.0);
+    let maximum = range.get_maximum().unwrap_or(crate::protocol::MAX_THICKNESS_PX as f64);
+    let result = if (px as f64) < minimum || (px as f64) > maximum {
+        Err(MspMcpError::InvalidParameters(format!("Thickness must be between {} and {} pixels for this tool", minimum, maximum)))
+    } else {
+        range.set_value(px as f64)
+            .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to set size slider: {}", e)))
+    };
+    
+    // Close the flyout we opened, whatever happened
+    if let Some(button) = flyout_button {
+        button.send_keys("{ESC}", 10)
+            .map_err(|e| MspMcpError::WindowsApiError(format!("Failed to send Escape key: {}", e)))?;
+    }
+    result
+}
+
 /// Set thickness in Paint using UI Automation
 pub fn set_thickness_uia(hwnd: HWND, level: u32) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
     info!("Setting thickness to level {} using UI Automation", level);
     
     // Initialize UIA
@@ -652,6 +1683,7 @@ pub fn set_thickness_uia(hwnd: HWND, level: u32) -> Result<()> {
 
 /// Set fill type in Paint using UI Automation
 pub fn set_fill_uia(hwnd: HWND, fill_type: &str) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
     info!("Setting fill type to '{}' using UI Automation", fill_type);
     
     // Initialize UIA
@@ -807,7 +1839,9 @@ pub fn set_fill_uia(hwnd: HWND, fill_type: &str) -> Result<()> {
 }
 
 /// Draw a shape in Paint using UI Automation
-pub fn draw_shape_uia(hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32) -> Result<()> {
+#[allow(clippy::too_many_arguments)]
+pub fn draw_shape_uia(hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
+    let _timing = timing::enter(Phase::UiaLookup);
     info!("Drawing shape '{}' from ({},{}) to ({},{}) using UI Automation", shape_type, start_x, start_y, end_x, end_y);
     
     // Initialize UIA
@@ -938,7 +1972,7 @@ pub fn draw_shape_uia(hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32,
         }
         
         // Wait for the shapes dropdown to appear
-        std::thread::sleep(Duration::from_millis(500));
+        crate::clock::sleep(Duration::from_millis(500));
     } else {
         // If we couldn't find the Shapes button, try using keyboard shortcuts
         info!("Shapes button not found, using fallback keyboard method");
@@ -948,9 +1982,9 @@ pub fn draw_shape_uia(hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32,
         
         // Alt+H to access Home tab, then S for Shapes, then Down Arrow
         window.send_keys("%h", 100)?; // Alt+H
-        std::thread::sleep(Duration::from_millis(300));
+        crate::clock::sleep(Duration::from_millis(300));
         window.send_keys("s", 100)?; // S for Shapes
-        std::thread::sleep(Duration::from_millis(300));
+        crate::clock::sleep(Duration::from_millis(300));
     }
     
     // Now the shapes dropdown should be open - select rectangle or specific shape
@@ -967,12 +2001,12 @@ pub fn draw_shape_uia(hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32,
     info!("Selecting shape {} using keyboard navigation", shape_type);
     for _ in 0..shape_index {
         window.send_keys("{DOWN}", 50)?;
-        std::thread::sleep(Duration::from_millis(100));
+        crate::clock::sleep(Duration::from_millis(100));
     }
     
     // Enter to select the shape
     window.send_keys("{ENTER}", 100)?;
-    std::thread::sleep(Duration::from_millis(300));
+    crate::clock::sleep(Duration::from_millis(300));
     
     // Now draw the shape by finding the canvas and performing mouse actions
     info!("Finding canvas element to draw shape");
@@ -1057,18 +2091,18 @@ pub fn draw_shape_uia(hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32,
     // Now use the windows API to directly manipulate the mouse
     // This is more reliable than sending keyboard events for exact positioning
     windows::move_mouse_to(adjusted_start_x, adjusted_start_y)?;
-    std::thread::sleep(Duration::from_millis(300));
+    crate::clock::sleep(Duration::from_millis(300));
     
     // Mouse down
-    windows::send_mouse_down()?;
-    std::thread::sleep(Duration::from_millis(300));
+    let held = windows::HeldButton::press(button)?;
+    crate::clock::sleep(Duration::from_millis(300));
     
     // Move to end position
     windows::move_mouse_to(adjusted_end_x, adjusted_end_y)?;
-    std::thread::sleep(Duration::from_millis(300));
+    crate::clock::sleep(Duration::from_millis(300));
     
     // Mouse up
-    windows::send_mouse_up()?;
+    held.release()?;
     
     info!("Successfully drew shape '{}' from ({},{}) to ({},{}) using UIA", 
           shape_type, start_x, start_y, end_x, end_y);
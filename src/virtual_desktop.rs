@@ -0,0 +1,81 @@
+// Optional dedicated virtual desktop for automation.
+//
+// SendInput only reaches windows on the visible desktop, so this mode keeps
+// Paint on its own virtual desktop and switches to it just for the duration
+// of each request. The user's own desktop keeps its window order and focus
+// between requests instead of having Paint pulled on top every time.
+
+use crate::error::{MspMcpError, Result};
+use tracing::{debug, info, warn};
+use windows_sys::Win32::Foundation::HWND;
+
+// Name used to find (or create) the automation desktop
+const AUTOMATION_DESKTOP_NAME: &str = "Paint Automation";
+
+// Time for the desktop switch animation to finish before input is sent
+const SWITCH_SETTLE_MS: u64 = 400;
+
+fn vd_error(context: &str, err: winvd::Error) -> MspMcpError {
+    MspMcpError::WindowsApiError(format!("{}: {:?}", context, err))
+}
+
+/// Returns the index of the automation desktop, creating it if needed.
+pub fn find_or_create_automation_desktop() -> Result<u32> {
+    let desktops = winvd::get_desktops().map_err(|e| vd_error("Failed to list virtual desktops", e))?;
+    for desktop in desktops {
+        if desktop.get_name().map(|name| name == AUTOMATION_DESKTOP_NAME).unwrap_or(false) {
+            return desktop.get_index().map_err(|e| vd_error("Failed to read desktop index", e));
+        }
+    }
+
+    info!("Creating virtual desktop '{}'", AUTOMATION_DESKTOP_NAME);
+    let desktop = winvd::create_desktop().map_err(|e| vd_error("Failed to create virtual desktop", e))?;
+    if let Err(e) = desktop.set_name(AUTOMATION_DESKTOP_NAME) {
+        warn!("Failed to name automation desktop: {:?}", e);
+    }
+    desktop.get_index().map_err(|e| vd_error("Failed to read desktop index", e))
+}
+
+/// Moves the Paint window onto the automation desktop.
+pub fn move_to_automation_desktop(hwnd: HWND) -> Result<u32> {
+    let index = find_or_create_automation_desktop()?;
+    winvd::move_window_to_desktop(index, &winvd::HWND(hwnd))
+        .map_err(|e| vd_error("Failed to move Paint to the automation desktop", e))?;
+    info!("Moved Paint window HWND={} to virtual desktop {}", hwnd, index);
+    Ok(index)
+}
+
+/// Switches to the automation desktop for as long as the guard lives and
+/// switches back to the user's desktop when dropped.
+pub struct DesktopSwitchGuard {
+    previous_index: Option<u32>,
+}
+
+impl DesktopSwitchGuard {
+    pub fn enter() -> Result<Self> {
+        let target = find_or_create_automation_desktop()?;
+        let current = winvd::get_current_desktop()
+            .and_then(|d| d.get_index())
+            .map_err(|e| vd_error("Failed to read current virtual desktop", e))?;
+
+        if current == target {
+            return Ok(DesktopSwitchGuard { previous_index: None });
+        }
+
+        debug!("Switching from virtual desktop {} to automation desktop {}", current, target);
+        winvd::switch_desktop(target).map_err(|e| vd_error("Failed to switch virtual desktop", e))?;
+        crate::clock::sleep(std::time::Duration::from_millis(SWITCH_SETTLE_MS));
+        Ok(DesktopSwitchGuard { previous_index: Some(current) })
+    }
+}
+
+impl Drop for DesktopSwitchGuard {
+    fn drop(&mut self) {
+        if let Some(index) = self.previous_index {
+            debug!("Switching back to virtual desktop {}", index);
+            if let Err(e) = winvd::switch_desktop(index) {
+                warn!("Failed to switch back to virtual desktop {}: {:?}", index, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// Hard time limits on requests that drive Paint.
+//
+// A request can get stuck halfway through a gesture: a dialog that never
+// opens, a drag waiting on a window that stopped responding. Left alone it
+// would keep the mouse button or a modifier held and Paint mid-selection,
+// and every request after it would inherit the mess. While a request that
+// sends input runs, a watchdog thread waits on it. If it is still running
+// at its limit (`[watchdog]` in the config), the watchdog releases held
+// input and presses Escape from its own thread, and marks the request
+// tripped. From then on the request's own input is refused
+// (windows::send_input checks `tripped`), so it fails at its next step, and
+// PaintServerState replaces its result with an OperationTimeout error.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use tracing::warn;
+
+thread_local! {
+    // Tripped flag of the watchdog guarding the request on this thread
+    static CURRENT: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// The watchdog of a running request. Dropping it, when the request
+/// finishes, stops the watchdog.
+pub struct Watchdog {
+    tripped: Arc<AtomicBool>,
+    previous: Option<Arc<AtomicBool>>,
+    _stop: mpsc::Sender<()>, // Dropped with the guard, which wakes the watchdog thread
+}
+
+impl Watchdog {
+    /// True if the request ran past its limit.
+    pub fn tripped(&self) -> bool {
+        self.tripped.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Starts a watchdog for the request running on this thread. If the guard
+/// is still alive after `limit`, the request is marked tripped and
+/// `on_trip` runs on the watchdog's thread.
+pub fn arm(limit: Duration, on_trip: impl FnOnce() + Send + 'static) -> Watchdog {
+    let tripped = Arc::new(AtomicBool::new(false));
+    let (stop, stopped) = mpsc::channel::<()>();
+    let flag = tripped.clone();
+    let spawned = std::thread::Builder::new().name("watchdog".to_string()).spawn(move || {
+        if let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(limit) {
+            flag.store(true, Ordering::SeqCst);
+            on_trip();
+        }
+    });
+    if let Err(e) = spawned {
+        warn!("Failed to start the watchdog; the request runs without a limit: {}", e);
+    }
+    let previous = CURRENT.with(|current| current.replace(Some(tripped.clone())));
+    Watchdog { tripped, previous, _stop: stop }
+}
+
+/// True if the request running on this thread ran past its limit and must
+/// not send any more input.
+pub fn tripped() -> bool {
+    CURRENT.with(|current| current.borrow().as_ref().is_some_and(|flag| flag.load(Ordering::SeqCst)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_trips_only_past_its_limit() {
+        let (tripped_tx, tripped_rx) = mpsc::channel();
+        let watchdog = arm(Duration::from_millis(20), move || tripped_tx.send(()).unwrap());
+        tripped_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(watchdog.tripped());
+        assert!(tripped());
+        drop(watchdog);
+        assert!(!tripped());
+
+        let (tripped_tx, tripped_rx) = mpsc::channel();
+        let watchdog = arm(Duration::from_millis(100), move || tripped_tx.send(()).unwrap());
+        assert!(!watchdog.tripped());
+        drop(watchdog);
+        // The watchdog thread exits without calling on_trip, dropping the sender
+        assert_eq!(tripped_rx.recv_timeout(Duration::from_secs(5)), Err(mpsc::RecvTimeoutError::Disconnected));
+    }
+}
@@ -1,693 +1,581 @@
 // Placeholder for Windows API interaction logic 
 
 use crate::error::{MspMcpError, Result};
-use std::ffi::OsStr;
-use std::os::windows::ffi::OsStrExt;
 use std::ptr;
-use windows_sys::Win32::Foundation::{BOOL, HWND, LPARAM, TRUE, FALSE, POINT};
-use windows_sys::Win32::System::Threading::{CreateProcessW, PROCESS_INFORMATION, STARTUPINFOW};
+use windows_sys::Win32::Foundation::{BOOL, HWND, LPARAM, TRUE, FALSE, POINT, RECT, WAIT_OBJECT_0};
+use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, HANDLE, GENERIC_ALL};
+use windows_sys::Win32::System::Threading::{
+    OpenProcess, OpenProcessToken, QueryFullProcessImageNameW, GetCurrentProcess, GetCurrentProcessId, GetCurrentThreadId,
+    TerminateProcess, WaitForSingleObject, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_NAME_WIN32, PROCESS_SYNCHRONIZE,
+    PROCESS_TERMINATE,
+};
+use windows_sys::Win32::Storage::FileSystem::{GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW, VS_FIXEDFILEINFO};
+use windows_sys::Win32::Security::{
+    GetTokenInformation, GetSidSubAuthority, GetSidSubAuthorityCount,
+    TokenIntegrityLevel, TOKEN_MANDATORY_LABEL, TOKEN_QUERY,
+};
 use windows_sys::Win32::UI::WindowsAndMessaging::{
     EnumWindows, GetClassNameW, GetWindowTextW, IsWindowVisible, GetWindowRect,
     SetForegroundWindow, ShowWindow, SW_RESTORE, SW_SHOWMAXIMIZED,
     GetWindowLongW, SetWindowPos, GWL_STYLE, WS_MAXIMIZE, HWND_TOP, SWP_SHOWWINDOW,
     GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN,
+    SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN,
+    SW_MAXIMIZE, SWP_NOZORDER, SWP_NOACTIVATE,
+    GetClientRect, GetForegroundWindow, GetWindowThreadProcessId, GetWindow, GW_HWNDPREV, GW_OWNER,
+    IsWindow, IsIconic, IsZoomed, MONITORINFOF_PRIMARY, PostMessageW, WM_CLOSE,
 };
 // Input-related imports from correct modules
 use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
-    SendInput, INPUT_MOUSE, MOUSEEVENTF_MOVE, MOUSEEVENTF_ABSOLUTE, 
+    SendInput, INPUT_MOUSE, MOUSEEVENTF_MOVE, MOUSEEVENTF_ABSOLUTE, MOUSEEVENTF_VIRTUALDESK,
     MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP,
     // Keyboard related imports
     INPUT_KEYBOARD, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, VK_CONTROL, VK_SHIFT, VK_MENU,
     VK_RETURN, VK_TAB, VK_ESCAPE, VK_DELETE, VK_BACK, VK_SPACE, VK_LEFT, VK_RIGHT, VK_UP, VK_DOWN,
+    VK_F12, VK_LBUTTON, VK_RBUTTON, BlockInput, GetAsyncKeyState,
 };
 // INPUT struct and MOUSEINPUT
 use windows_sys::Win32::UI::Input::KeyboardAndMouse::INPUT;
 use windows_sys::Win32::UI::Input::KeyboardAndMouse::MOUSEINPUT;
 // ClientToScreen is in Win32::UI::Input::KeyboardAndMouse
-use windows_sys::Win32::Graphics::Gdi::ClientToScreen;
+use windows_sys::Win32::Graphics::Gdi::{
+    ClientToScreen, MonitorFromWindow, GetMonitorInfoW, MONITORINFO, MONITOR_DEFAULTTONEAREST,
+    EnumDisplayMonitors, HMONITOR, HDC,
+};
+use windows_sys::Win32::UI::HiDpi::GetDpiForWindow;
+use windows_sys::Win32::System::StationsAndDesktops::{
+    OpenInputDesktop, CloseDesktop, GetUserObjectInformationW, GetThreadDesktop, SetThreadDesktop,
+    DESKTOP_SWITCHDESKTOP, HDESK, UOI_NAME,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::protocol::{CanvasDimensionsResponse, WindowInfoResponse, RectInfo, MonitorInfo, MouseButton, PaintCapabilities, SetImagePropertiesParams, SetToolStateParams, UnsavedChanges};
+use crate::geometry::{bounding_box, compute_canvas_dimensions, fit_rect_within, rect_size, split_stroke, CanvasFrame, ScreenRect};
+use crate::timing::{self, Phase};
 
-use log::{debug, info, warn, error};
+use tracing::{debug, info, warn, error};
 
 const PAINT_CLASS_NAME: &str = "MSPaintApp";
-const PAINT_WINDOW_TITLE_SUBSTRING: &str = "Paint";
 const MSPAINT_EXECUTABLE: &str = "mspaint.exe";
 
-// Structure to hold data passed to the EnumWindows callback
-struct EnumWindowData {
-    hwnd: Option<HWND>,
-    target_class: Vec<u16>,
-    target_title_substring: Vec<u16>,
+// A visible top-level window candidate collected during enumeration
+struct WindowCandidate {
+    hwnd: HWND,
+    pid: u32,
+    class_name: String,
+    area: i64,
 }
 
-// Callback function for EnumWindows
+// Callback function for EnumWindows that collects visible, unowned top-level windows
 unsafe extern "system" fn enum_window_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
-    // Check visibility first
-    if IsWindowVisible(hwnd) == FALSE {
-        // Optional: Log skipped invisible windows at debug level if needed
-        // debug!("Skipping invisible window: HWND={}", hwnd);
+    // Skip invisible windows and owned windows (dialogs, popups, tooltips)
+    if IsWindowVisible(hwnd) == FALSE || GetWindow(hwnd, GW_OWNER) != 0 {
         return TRUE; // Continue enumeration
     }
 
-    let data = &mut *(lparam as *mut EnumWindowData);
-    let mut found = false;
+    let candidates = &mut *(lparam as *mut Vec<WindowCandidate>);
 
-    // Always get class name and title for logging, even if not searching by them
-    let mut current_class_name: [u16; 128] = [0; 128];
-    let class_name_len = GetClassNameW(hwnd, current_class_name.as_mut_ptr(), current_class_name.len() as i32);
-    let class_name_str = if class_name_len > 0 { 
-        String::from_utf16_lossy(&current_class_name[..class_name_len as usize])
-    } else { 
-        "<Unknown Class>".to_string() 
-    };
-
-    let mut current_window_title: [u16; 256] = [0; 256];
-    let window_title_len = GetWindowTextW(hwnd, current_window_title.as_mut_ptr(), current_window_title.len() as i32);
-    let window_title_str = if window_title_len > 0 { 
-        String::from_utf16_lossy(&current_window_title[..window_title_len as usize]) 
-    } else { 
-        "<Unknown Title>".to_string() 
+    let mut rect: RECT = std::mem::zeroed();
+    let area = if GetWindowRect(hwnd, &mut rect) != FALSE {
+        (rect.right - rect.left) as i64 * (rect.bottom - rect.top) as i64
+    } else {
+        0
     };
 
-    // IMPORTANT: Skip the MCP server itself
-    if window_title_str.contains("mcp-server-microsoft-paint") {
-        debug!("Skipping MCP server window: HWND={}, Class='{}', Title='{}'", 
-               hwnd, class_name_str, window_title_str);
-        return TRUE; // Continue enumeration, ignore this window
-    }
+    candidates.push(WindowCandidate {
+        hwnd,
+        pid: get_window_pid(hwnd),
+        class_name: get_window_class_name(hwnd),
+        area,
+    });
 
-    // Log every visible window encountered at debug level
-    debug!("EnumWindows Checking: HWND={}, Class='{}', Title='{}'", hwnd, class_name_str, window_title_str);
-
-    // Check class name if specified in search criteria
-    if !data.target_class.is_empty() {
-        let target_class_str = String::from_utf16_lossy(&data.target_class[..data.target_class.len() - 1]); // Remove null term
-        if class_name_str.contains(&target_class_str) {
-            info!("Found window matching class '{}': HWND={}, Class='{}', Title='{}'", 
-                  target_class_str, hwnd, class_name_str, window_title_str);
-            data.hwnd = Some(hwnd);
-            found = true;
-        }
-    }
-
-    // Check window title if specified in search criteria (and not already found by class)
-    if !found && !data.target_title_substring.is_empty() {
-        let target_title_str = String::from_utf16_lossy(&data.target_title_substring);
-        if window_title_str.to_lowercase().contains(&target_title_str.to_lowercase()) {
-            info!("Found window matching title '{}': HWND={}, Class='{}', Title='{}'", 
-                  target_title_str, hwnd, class_name_str, window_title_str);
-            data.hwnd = Some(hwnd);
-            found = true;
-        }
-    }
+    TRUE // Continue enumeration
+}
 
-    if found {
-        FALSE // Stop enumeration
-    } else {
-        TRUE // Continue enumeration
+/// Enumerates all visible, unowned top-level windows.
+fn enumerate_top_level_windows() -> Vec<WindowCandidate> {
+    let mut candidates: Vec<WindowCandidate> = Vec::new();
+    unsafe {
+        let lparam = &mut candidates as *mut Vec<WindowCandidate> as LPARAM;
+        EnumWindows(Some(enum_window_proc), lparam);
     }
+    candidates
 }
 
 /// Log all visible windows - useful for diagnostics
 pub fn log_all_visible_windows() -> Result<()> {
-    info!("==== LOGGING ALL VISIBLE WINDOWS ====");
+    debug!("==== LOGGING ALL VISIBLE WINDOWS ====");
     
-    unsafe {
-        let enum_data = &mut EnumWindowData {
-            hwnd: None,
-            target_class: Vec::new(),
-            target_title_substring: Vec::new(),
-        };
-        let lparam = enum_data as *mut _ as LPARAM;
-        EnumWindows(Some(enum_diagnostic_window_proc), lparam);
+    for candidate in enumerate_top_level_windows() {
+        debug!("HWND={}, PID={}, Class='{}', Title='{}'", 
+               candidate.hwnd, candidate.pid, candidate.class_name, get_window_title(candidate.hwnd));
     }
     
-    info!("==== END WINDOW ENUMERATION ====");
+    debug!("==== END WINDOW ENUMERATION ====");
     Ok(())
 }
 
-// A special callback for diagnostic logging of ALL windows
-unsafe extern "system" fn enum_diagnostic_window_proc(hwnd: HWND, _lparam: LPARAM) -> BOOL {
-    // Skip invisible windows
-    if IsWindowVisible(hwnd) == FALSE {
-        return TRUE; // Continue enumeration
+/// Returns the executable file name (e.g. "mspaint.exe") of a process, if it can be queried.
+pub fn get_process_image_name(pid: u32) -> Option<String> {
+    get_process_image_path(pid)?
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+/// Returns the full path of a process's executable, if it can be queried.
+fn get_process_image_path(pid: u32) -> Option<std::path::PathBuf> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+        if process == 0 {
+            return None;
+        }
+        
+        let mut buffer: [u16; 1024] = [0; 1024];
+        let mut size = buffer.len() as u32;
+        let ok = QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, buffer.as_mut_ptr(), &mut size);
+        CloseHandle(process);
+        
+        if ok == FALSE {
+            return None;
+        }
+        
+        Some(std::path::PathBuf::from(String::from_utf16_lossy(&buffer[..size as usize])))
+    }
+}
+
+/// Returns the file version of the Paint executable that owns the window,
+/// e.g. "11.2410.28.0", read from its version resource.
+pub fn get_paint_version(hwnd: HWND) -> Option<String> {
+    let path = get_process_image_path(get_window_pid(hwnd))?;
+    let wide_path: Vec<u16> = path.as_os_str().to_string_lossy().encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        let mut handle = 0;
+        let size = GetFileVersionInfoSizeW(wide_path.as_ptr(), &mut handle);
+        if size == 0 {
+            return None;
+        }
+        let mut data = vec![0u8; size as usize];
+        if GetFileVersionInfoW(wide_path.as_ptr(), 0, size, data.as_mut_ptr().cast()) == FALSE {
+            return None;
+        }
+        
+        let root: Vec<u16> = "\\".encode_utf16().chain(std::iter::once(0)).collect();
+        let mut info: *mut std::ffi::c_void = ptr::null_mut();
+        let mut info_len = 0;
+        if VerQueryValueW(data.as_ptr().cast(), root.as_ptr(), &mut info, &mut info_len) == FALSE
+            || info.is_null()
+            || (info_len as usize) < std::mem::size_of::<VS_FIXEDFILEINFO>()
+        {
+            return None;
+        }
+        let info = &*(info as *const VS_FIXEDFILEINFO);
+        Some(format!(
+            "{}.{}.{}.{}",
+            info.dwFileVersionMS >> 16, info.dwFileVersionMS & 0xFFFF,
+            info.dwFileVersionLS >> 16, info.dwFileVersionLS & 0xFFFF,
+        ))
     }
+}
 
-    // Get class name
-    let mut class_name: [u16; 128] = [0; 128];
-    let class_len = GetClassNameW(hwnd, class_name.as_mut_ptr(), class_name.len() as i32);
-    let class_str = if class_len > 0 { 
-        String::from_utf16_lossy(&class_name[..class_len as usize])
-    } else { 
-        "<Unknown Class>".to_string() 
-    };
+/// Picks the best main window among candidates: prefer Paint's own window
+/// class, then the largest window.
+fn pick_main_window(candidates: Vec<WindowCandidate>) -> Option<HWND> {
+    candidates.into_iter()
+        .max_by_key(|c| (c.class_name == PAINT_CLASS_NAME, c.area))
+        .map(|c| c.hwnd)
+}
 
-    // Get window title
-    let mut title: [u16; 256] = [0; 256];
-    let title_len = GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as i32);
-    let title_str = if title_len > 0 { 
-        String::from_utf16_lossy(&title[..title_len as usize]) 
-    } else { 
-        "<No Title>".to_string() 
-    };
+/// Returns true if the window still exists and is still owned by the given process.
+/// Guards against HWND reuse after Paint closes its window.
+pub fn is_window_owned_by(hwnd: HWND, pid: u32) -> bool {
+    let exists = unsafe { IsWindow(hwnd) } != FALSE;
+    exists && get_window_pid(hwnd) == pid
+}
 
-    // Log every visible window
-    info!("HWND={}, Class='{}', Title='{}'", hwnd, class_str, title_str);
-    
-    // Continue enumeration
-    TRUE
+/// Finds the main window owned by a specific process.
+pub fn find_window_for_pid(pid: u32) -> Option<HWND> {
+    let candidates: Vec<WindowCandidate> = enumerate_top_level_windows()
+        .into_iter()
+        .filter(|c| c.pid == pid)
+        .collect();
+    pick_main_window(candidates)
 }
 
 /// Finds the HWND of a visible Windows 11 Paint window.
-/// Searches first by class name "MSPaintApp", then by title containing "Paint".
+/// Windows are matched by the executable of their owning process rather than
+/// by title, so renamed documents and localized titles don't matter.
 pub fn find_paint_window() -> Result<HWND> {
     info!("Attempting to find Paint window...");
     
-    // First, let's log all visible windows to help diagnose the issue
     log_all_visible_windows()?;
     
-    // Add more possible class and title names for Paint
-    const POSSIBLE_CLASS_NAMES: [&str; 4] = [
-        "MSPaintApp",      // Windows 11/10 Paint class
-        "Afx:1000000:8",   // Older Paint class name
-        "ApplicationFrameWindow", // Modern UWP container class
-        "MSPaintDesktop::CMainWindow", // Another possible Paint class
-    ];
-    
-    const POSSIBLE_TITLE_SUBSTRINGS: [&str; 8] = [
-        "Paint",         // English
-        "paint",         // Case insensitive match
-        "Untitled - Paint", // Common title
-        "Bez tytułu - Paint", // Other variations
-        "Paint 3D",      // Paint 3D (as fallback)
-        "Malování",      // Other localized names
-        "Drawing",       // Alternative name
-        ""               // Empty string to catch all windows (will check class afterwards)
-    ];
-
-    // Add a prohibited strings list to explicitly filter out the MCP server
-    const PROHIBITED_SUBSTRINGS: [&str; 1] = [
-        "mcp-server-microsoft-paint"
-    ];
-    
-    // First try window that might be from manually started Paint
-    // Look for any window with "Paint" in title and manually verify it's not the server
-    unsafe {
-        // Create specialized search data
-        let mut search_data = EnumWindowData {
-            hwnd: None,
-            target_class: Vec::new(),
-            target_title_substring: OsStr::new("paint").encode_wide().collect(),
-        };
-        let lparam = &mut search_data as *mut EnumWindowData as LPARAM;
-        EnumWindows(Some(enum_window_proc), lparam);
-        
-        if let Some(hwnd) = search_data.hwnd {
-            let mut window_title: [u16; 256] = [0; 256];
-            let title_len = GetWindowTextW(hwnd, window_title.as_mut_ptr(), window_title.len() as i32);
-            
-            if title_len > 0 {
-                let title_str = String::from_utf16_lossy(&window_title[..title_len as usize]);
-                
-                if !PROHIBITED_SUBSTRINGS.iter().any(|s| title_str.contains(s)) {
-                    info!("Found manually started Paint window: HWND={}, Title='{}'", hwnd, title_str);
-                    return Ok(hwnd);
-                }
-            }
+    match pick_main_window(paint_window_candidates()) {
+        Some(hwnd) => {
+            info!("Found Paint window: HWND={}, PID={}, Title='{}'", 
+                  hwnd, get_window_pid(hwnd), get_window_title(hwnd));
+            Ok(hwnd)
         }
-    }
-    
-    // Search by class name
-    for class_name in &POSSIBLE_CLASS_NAMES {
-        let target_class_u16: Vec<u16> = OsStr::new(class_name).encode_wide().chain(Some(0)).collect();
-        
-        let mut data = EnumWindowData {
-            hwnd: None,
-            target_class: target_class_u16,
-            target_title_substring: Vec::new(), // Not used for class search
-        };
-        
-        unsafe {
-            let lparam = &mut data as *mut EnumWindowData as LPARAM;
-            EnumWindows(Some(enum_window_proc), lparam);
-            
-            if let Some(hwnd) = data.hwnd {
-                // Additional check to make sure it's not the MCP server
-                let mut window_title: [u16; 256] = [0; 256];
-                let title_len = GetWindowTextW(hwnd, window_title.as_mut_ptr(), window_title.len() as i32);
-                if title_len > 0 {
-                    let title_str = String::from_utf16_lossy(&window_title[..title_len as usize]);
-                    
-                    // Check if this is a prohibited window (MCP server)
-                    let is_prohibited = PROHIBITED_SUBSTRINGS.iter()
-                        .any(|prohibited| title_str.contains(prohibited));
-                        
-                    if is_prohibited {
-                        debug!("Skipping prohibited window: {}", title_str);
-                        continue; // Skip this window, try next class
-                    }
-                }
-                
-                info!("Found Paint window via class name '{}': HWND={}", class_name, hwnd);
-                return Ok(hwnd);
-            }
+        None => {
+            warn!("Paint window not found via EnumWindows.");
+            Err(MspMcpError::WindowNotFound)
         }
     }
-    
-    // If class name search failed, try by title substring
-    for title in &POSSIBLE_TITLE_SUBSTRINGS {
-        let target_title_u16: Vec<u16> = OsStr::new(title).encode_wide().collect();
-        
-        let mut data = EnumWindowData {
-            hwnd: None,
-            target_class: Vec::new(), // Not used for title search 
-            target_title_substring: target_title_u16,
-        };
-        
-        unsafe {
-            let lparam = &mut data as *mut EnumWindowData as LPARAM;
-            EnumWindows(Some(enum_window_proc), lparam);
-            
-            if let Some(hwnd) = data.hwnd {
-                // Additional check to make sure it's not the MCP server
-                let mut window_title: [u16; 256] = [0; 256];
-                let title_len = GetWindowTextW(hwnd, window_title.as_mut_ptr(), window_title.len() as i32);
-                if title_len > 0 {
-                    let title_str = String::from_utf16_lossy(&window_title[..title_len as usize]);
-                    
-                    // Check if this is a prohibited window (MCP server)
-                    let is_prohibited = PROHIBITED_SUBSTRINGS.iter()
-                        .any(|prohibited| title_str.contains(prohibited));
-                        
-                    if is_prohibited {
-                        debug!("Skipping prohibited window: {}", title_str);
-                        continue; // Skip this window, try next title
-                    }
-                    
-                    // Additional class check when using empty title
-                    if title.is_empty() {
-                        // Get the class name and check if it's a paint class
-                        let mut class_name: [u16; 128] = [0; 128];
-                        let class_len = GetClassNameW(hwnd, class_name.as_mut_ptr(), class_name.len() as i32);
-                        
-                        if class_len > 0 {
-                            let class_str = String::from_utf16_lossy(&class_name[..class_len as usize]);
-                            
-                            let is_paint_class = POSSIBLE_CLASS_NAMES.iter()
-                                .any(|paint_class| class_str.contains(paint_class));
-                                
-                            if !is_paint_class {
-                                debug!("Skipping non-Paint class window: {}", class_str);
-                                continue;
-                            }
-                        }
-                    }
-                }
-                
-                info!("Found Paint window via title substring '{}': HWND={}", title, hwnd);
-                return Ok(hwnd);
-            }
+}
+
+// Top-level windows of Paint processes other than this one
+fn paint_window_candidates() -> Vec<WindowCandidate> {
+    let own_pid = unsafe { GetCurrentProcessId() };
+    enumerate_top_level_windows()
+        .into_iter()
+        .filter(|c| c.pid != own_pid)
+        .filter(|c| {
+            get_process_image_name(c.pid)
+                .map(|name| name.eq_ignore_ascii_case(MSPAINT_EXECUTABLE))
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+/// Launches another Paint window and returns it, leaving the windows
+/// already open alone. The packaged Paint may open the window in a process
+/// of its own or hand it to a running one, so the new window is the Paint
+/// window that wasn't there before the launch.
+pub fn launch_paint_window() -> Result<HWND> {
+    let before: Vec<HWND> = paint_window_candidates().iter().map(|c| c.hwnd).collect();
+    launch_paint()?;
+
+    let max_retries = 20;
+    let retry_delay = std::time::Duration::from_millis(500);
+    for attempt in 1..=max_retries {
+        crate::clock::sleep(retry_delay);
+        debug!("Waiting for a new Paint window (attempt {}/{})...", attempt, max_retries);
+        let fresh: Vec<WindowCandidate> = paint_window_candidates()
+            .into_iter()
+            .filter(|c| !before.contains(&c.hwnd))
+            .collect();
+        if let Some(hwnd) = pick_main_window(fresh) {
+            info!("New Paint window: HWND={}, PID={}", hwnd, get_window_pid(hwnd));
+            return Ok(hwnd);
         }
     }
-    
-    // As a last resort, try to find any window with "paint" in its executable path
-    unsafe {
-        // First log process IDs to help with debugging
-        let _ = std::process::Command::new("wmic")
-            .args(["process", "where", "name='mspaint.exe'", "get", "processid,commandline", "/format:list"])
-            .status();
-            
-        // This is a lot more complex in reality, but left as a future enhancement
-    }
-    
-    warn!("Paint window not found via EnumWindows.");
+
+    error!("No new Paint window appeared after {} retries", max_retries);
     Err(MspMcpError::WindowNotFound)
 }
 
-/// Launches the mspaint.exe process.
-pub fn launch_paint() -> Result<()> {
-    info!("Launching mspaint.exe using ShellExecuteW...");
-    
-    use windows_sys::Win32::UI::Shell::ShellExecuteW;
-    use windows_sys::Win32::UI::WindowsAndMessaging::SW_NORMAL;
-    use std::ptr::null;
+/// Launches the mspaint.exe process and returns its process ID.
+pub fn launch_paint() -> Result<u32> {
+    info!("Launching {}...", MSPAINT_EXECUTABLE);
     
-    let operation: Vec<u16> = OsStr::new("open").encode_wide().chain(Some(0)).collect();
-    let file: Vec<u16> = OsStr::new(MSPAINT_EXECUTABLE).encode_wide().chain(Some(0)).collect();
-    
-    let result = unsafe {
-        ShellExecuteW(
-            0,                      // hwnd (NULL for no parent)
-            operation.as_ptr(),     // lpOperation ("open")
-            file.as_ptr(),          // lpFile ("mspaint.exe")
-            null(),                 // lpParameters (NULL for no parameters)
-            null(),                 // lpDirectory (NULL for current directory)
-            SW_NORMAL               // nShowCmd (normal window)
-        )
-    };
-    
-    // ShellExecuteW returns an HINSTANCE, which is interpreted differently than a BOOL
-    // A value > 32 indicates success
-    if result <= 32 {
-        let error_code = result;
-        error!("Failed to launch mspaint.exe with ShellExecuteW. Error code: {}", error_code);
-        return Err(MspMcpError::WindowsApiError(format!("ShellExecuteW failed for mspaint.exe with error code {}", error_code)));
+    match std::process::Command::new(MSPAINT_EXECUTABLE).spawn() {
+        Ok(child) => {
+            let pid = child.id();
+            info!("Launched {} with PID {}", MSPAINT_EXECUTABLE, pid);
+            Ok(pid)
+        }
+        Err(e) => {
+            error!("Failed to launch {}: {}", MSPAINT_EXECUTABLE, e);
+            Err(MspMcpError::WindowsApiError(format!("Failed to launch {}: {}", MSPAINT_EXECUTABLE, e)))
+        }
     }
-
-    // Increase initial delay after launch
-    info!("Waiting 3 seconds after launch attempt...");
-    std::thread::sleep(std::time::Duration::from_millis(3000));
-
-    info!("mspaint.exe launch attempt finished."); 
-    Ok(())
 }
 
 /// Attempts to find an existing Paint window, or launches it if not found.
-/// Retries finding the window briefly after launching.
+/// After launching, waits for a window owned by the launched process to appear.
 /// Returns the HWND of the Paint window.
 pub fn get_paint_hwnd() -> Result<HWND> {
     info!("Starting get_paint_hwnd - attempting to find or launch Paint");
     
-    // Try direct command line to check if mspaint.exe exists on the system
-    let check_command = std::process::Command::new("where")
-        .arg("mspaint.exe")
-        .output();
-    
-    match check_command {
-        Ok(output) => {
-            if output.status.success() {
-                if let Ok(paths) = String::from_utf8(output.stdout) {
-                    info!("mspaint.exe found at: {}", paths.trim());
-                }
-            } else {
-                warn!("mspaint.exe not found in PATH");
-            }
-        }
-        Err(e) => {
-            warn!("Failed to run 'where mspaint.exe': {}", e);
-        }
-    }
-    
-    // Check if mspaint.exe is already running
-    check_mspaint_running();
-    
-    // First check for any existing Paint windows using normal methods
+    // First check for any existing Paint windows
     match find_paint_window() {
         Ok(hwnd) => {
             info!("Found existing Paint window: HWND={}", hwnd);
             return Ok(hwnd);
         }
         Err(MspMcpError::WindowNotFound) => {
-            // If a manually started Paint is running but not detected,
-            // we can try a more direct approach to find it
-            info!("Regular Paint window detection failed, checking if Paint is manually running...");
-            
-            // Check if there's a running Paint process
-            let mspaint_running = is_mspaint_running();
-            
-            if mspaint_running {
-                info!("mspaint.exe is running according to task list, trying to force-capture it");
-                
-                // Special last-resort approach: Find ANY window that might be Paint
-                // since we know Paint is running but our detection fails
-                match find_any_paint_window() {
-                    Ok(hwnd) => {
-                        info!("Found potential Paint window with last-resort method: HWND={}", hwnd);
-                        return Ok(hwnd);
-                    }
-                    Err(_) => {
-                        warn!("Failed to find Paint window despite process running");
-                        // Continue with normal launch procedure
-                    }
-                }
-            }
-            
             info!("Paint window not found, attempting to launch...");
-            
-            // First attempt - use ShellExecuteW to launch Paint
-            match launch_paint() {
-                Ok(_) => {
-                    info!("Successfully launched Paint using ShellExecuteW");
-                }
-                Err(e) => {
-                    // If ShellExecuteW failed, try an alternative approach
-                    warn!("Primary launch method failed: {}. Trying alternative...", e);
-                    
-                    // Try using a more direct "start" command which has elevated privileges
-                    match std::process::Command::new("cmd")
-                        .args(["/C", "start", "mspaint.exe"])
-                        .spawn() {
-                        Ok(_) => {
-                            info!("Successfully launched Paint using cmd start command");
-                            // Give it time to start
-                            std::thread::sleep(std::time::Duration::from_millis(3000));
-                        }
-                        Err(e) => {
-                            // Try a third method - run Paint directly using Command
-                            warn!("Second launch method failed: {}. Trying third method...", e);
-                            match std::process::Command::new("mspaint.exe").spawn() {
-                                Ok(_) => {
-                                    info!("Successfully launched Paint using direct Command::new");
-                                    std::thread::sleep(std::time::Duration::from_millis(3000));
-                                }
-                                Err(e) => {
-                                    error!("All Paint launch methods failed. Last error: {}", e);
-                                    return Err(MspMcpError::WindowsApiError(
-                                        format!("Failed to launch Paint after multiple attempts: {}", e)
-                                    ));
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            
-            // After launch, check if mspaint.exe process is running
-            check_mspaint_running();
-            
-            // Increase retry count and delay for more reliable window detection
-            let max_retries = 20; // Significantly increased from 10
-            let retry_delay = std::time::Duration::from_millis(1000);
-            
-            for attempt in 1..=max_retries {
-                info!("Retrying find_paint_window (attempt {}/{}) after launch...", attempt, max_retries);
-                
-                // On certain attempts, force enumeration of ALL windows for debugging
-                if attempt % 2 == 0 {
-                    debug!("Diagnostic window enumeration on attempt {}:", attempt);
-                    unsafe {
-                        let enum_data = &mut EnumWindowData {
-                            hwnd: None,
-                            target_class: Vec::new(),
-                            target_title_substring: Vec::new(),
-                        };
-                        let lparam = enum_data as *mut _ as LPARAM;
-                        EnumWindows(Some(enum_window_proc), lparam);
-                    }
-                }
-                
-                std::thread::sleep(retry_delay);
-                
-                // On every 3rd attempt, try the last-resort method
-                if attempt % 3 == 0 {
-                    match find_any_paint_window() {
-                        Ok(hwnd) => {
-                            info!("Found Paint window with last-resort method: HWND={}", hwnd);
-                            return Ok(hwnd);
-                        }
-                        Err(_) => {} // Ignore error from last-resort method
-                    }
-                }
-                
-                match find_paint_window() {
-                    Ok(hwnd) => {
-                        info!("Found Paint window after launch: HWND={}", hwnd);
-                        
-                        // Try to activate the window as a final check
-                        match activate_paint_window(hwnd) {
-                            Ok(_) => {
-                                info!("Successfully activated Paint window");
-                                return Ok(hwnd);
-                            }
-                            Err(e) => {
-                                warn!("Found Paint window but failed to activate it: {}", e);
-                                // Continue anyway - at least we found the window
-                                return Ok(hwnd);
-                            }
-                        }
-                    }
-                    Err(MspMcpError::WindowNotFound) => {
-                        // Try again
-                        continue;
-                    }
-                    Err(e) => return Err(e), // Propagate other errors
-                }
+        }
+        Err(e) => return Err(e), // Propagate other errors
+    }
+    
+    let launched_pid = launch_paint()?;
+    
+    let max_retries = 20;
+    let retry_delay = std::time::Duration::from_millis(500);
+    
+    for attempt in 1..=max_retries {
+        crate::clock::sleep(retry_delay);
+        debug!("Waiting for Paint window (attempt {}/{})...", attempt, max_retries);
+        
+        // The launched process normally owns the window
+        if let Some(hwnd) = find_window_for_pid(launched_pid) {
+            info!("Found Paint window for launched PID {}: HWND={}", launched_pid, hwnd);
+            return Ok(hwnd);
+        }
+        
+        // On Windows 11 mspaint.exe may hand off to the packaged app, which runs
+        // under a different PID; fall back to matching any Paint process
+        match find_paint_window() {
+            Ok(hwnd) => {
+                info!("Found Paint window after launch: HWND={}, PID={}", hwnd, get_window_pid(hwnd));
+                return Ok(hwnd);
             }
-            
-            error!("Failed to find Paint window after {} retries", max_retries);
-            Err(MspMcpError::WindowNotFound)
+            Err(MspMcpError::WindowNotFound) => continue,
+            Err(e) => return Err(e),
         }
-        Err(e) => Err(e), // Propagate other errors
     }
+    
+    error!("Failed to find Paint window after {} retries", max_retries);
+    Err(MspMcpError::WindowNotFound)
 }
 
-/// Helper function to check if mspaint.exe is running using tasklist
-fn check_mspaint_running() {
-    match std::process::Command::new("tasklist")
-        .args(["/FI", "IMAGENAME eq mspaint.exe", "/FO", "LIST"])
-        .output() {
-        Ok(output) => {
-            if let Ok(tasklist) = String::from_utf8(output.stdout) {
-                // Only consider it running if the output contains both "mspaint.exe" AND "Image Name"
-                let is_running = tasklist.contains("mspaint.exe") && tasklist.contains("Image Name");
-                
-                if is_running {
-                    info!("Found mspaint.exe process running");
-                    info!("Tasklist results for mspaint.exe:\n{}", tasklist);
-                } else {
-                    info!("No mspaint.exe process found in tasklist");
-                }
-            }
-        }
-        Err(e) => {
-            warn!("Failed to check tasklist for mspaint.exe: {}", e);
-        }
+// Mandatory integrity level RIDs (see SECURITY_MANDATORY_*_RID)
+const INTEGRITY_LOW: u32 = 0x1000;
+const INTEGRITY_MEDIUM: u32 = 0x2000;
+const INTEGRITY_HIGH: u32 = 0x3000;
+const INTEGRITY_SYSTEM: u32 = 0x4000;
+
+/// Returns a readable name for an integrity level RID.
+pub fn integrity_level_name(level: u32) -> &'static str {
+    match level {
+        l if l >= INTEGRITY_SYSTEM => "system",
+        l if l >= INTEGRITY_HIGH => "high (elevated)",
+        l if l >= INTEGRITY_MEDIUM => "medium",
+        l if l >= INTEGRITY_LOW => "low",
+        _ => "untrusted",
     }
 }
 
-/// Helper function that returns true if mspaint.exe is running
-fn is_mspaint_running() -> bool {
-    match std::process::Command::new("tasklist")
-        .args(["/FI", "IMAGENAME eq mspaint.exe", "/FO", "LIST"])
-        .output() {
-        Ok(output) => {
-            if let Ok(tasklist) = String::from_utf8(output.stdout) {
-                return tasklist.contains("mspaint.exe") && tasklist.contains("Image Name");
-            }
-        }
-        Err(e) => {
-            warn!("Failed to check tasklist for mspaint.exe: {}", e);
+// Reads the integrity level RID from a process token
+unsafe fn token_integrity_level(process: HANDLE) -> Option<u32> {
+    let mut token: HANDLE = 0;
+    if OpenProcessToken(process, TOKEN_QUERY, &mut token) == FALSE {
+        return None;
+    }
+    
+    // First call gets the required buffer size
+    let mut size: u32 = 0;
+    GetTokenInformation(token, TokenIntegrityLevel, ptr::null_mut(), 0, &mut size);
+    if size == 0 {
+        CloseHandle(token);
+        return None;
+    }
+    
+    // Use a u64 buffer so the TOKEN_MANDATORY_LABEL is suitably aligned
+    let mut buffer: Vec<u64> = vec![0; (size as usize + 7) / 8];
+    let ok = GetTokenInformation(token, TokenIntegrityLevel, buffer.as_mut_ptr() as *mut _, size, &mut size);
+    CloseHandle(token);
+    if ok == FALSE {
+        return None;
+    }
+    
+    let label = &*(buffer.as_ptr() as *const TOKEN_MANDATORY_LABEL);
+    let sid = label.Label.Sid;
+    let count = *GetSidSubAuthorityCount(sid);
+    if count == 0 {
+        return None;
+    }
+    Some(*GetSidSubAuthority(sid, (count - 1) as u32))
+}
+
+/// Returns the integrity level of the given process, if it can be queried.
+pub fn get_process_integrity_level(pid: u32) -> Option<u32> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+        if process == 0 {
+            return None;
         }
+        let level = token_integrity_level(process);
+        CloseHandle(process);
+        level
     }
-    false
 }
 
-/// Last-resort method to find any window that might be Paint
-pub fn find_any_paint_window() -> Result<HWND> {
-    // This is a more aggressive approach when we know Paint is running
-    // but our normal detection methods fail
+/// Returns the integrity level of the server process.
+pub fn get_own_integrity_level() -> Option<u32> {
+    unsafe { token_integrity_level(GetCurrentProcess()) }
+}
+
+/// Checks that synthesized input can reach the window. UIPI silently drops
+/// SendInput events aimed at a process with a higher integrity level (e.g.
+/// Paint started "as administrator" while the server isn't), so detect that
+/// up front instead of failing later with confusing drag errors.
+pub fn check_input_privileges(hwnd: HWND) -> Result<()> {
+    let pid = get_window_pid(hwnd);
+    let (own_level, target_level) = match (get_own_integrity_level(), get_process_integrity_level(pid)) {
+        (Some(own), Some(target)) => (own, target),
+        _ => {
+            debug!("Could not determine integrity levels for server/Paint (PID={}); skipping UIPI check", pid);
+            return Ok(());
+        }
+    };
     
-    info!("Attempting last-resort Paint window detection...");
+    if target_level > own_level {
+        let message = format!(
+            "Paint (PID {}) runs at {} integrity but the server runs at {} integrity, so Windows blocks \
+             synthesized mouse/keyboard input. Restart Paint without elevation, or run the server elevated.",
+            pid, integrity_level_name(target_level), integrity_level_name(own_level));
+        error!("{}", message);
+        return Err(MspMcpError::ElevationMismatch(message));
+    }
     
+    Ok(())
+}
+
+// Name of the interactive user desktop; Winlogon/Screen-saver mean locked or UAC
+const DEFAULT_DESKTOP_NAME: &str = "Default";
+
+// Set while a background thread is waiting for the input desktop to return
+static INPUT_DESKTOP_WATCHER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+// How often a paused request checks whether the input desktop is back
+const INPUT_DESKTOP_POLL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Returns the name of the desktop currently receiving user input, or None if
+/// it can't be opened (which happens while the workstation is locked or the
+/// UAC secure desktop is shown).
+pub fn get_input_desktop_name() -> Option<String> {
     unsafe {
-        // Look for any window that might be Paint with basic criteria
-        let mut potential_hwnd = 0;
-        
-        // Try direct window captures based on common patterns
-        let hwnd_result = std::process::Command::new("powershell")
-            .args([
-                "-Command", 
-                r#"Add-Type -TypeDefinition 'using System; using System.Runtime.InteropServices; public class WindowFinder { [DllImport("user32.dll")] public static extern IntPtr FindWindow(string lpClassName, string lpWindowName); }'; [WindowFinder]::FindWindow($null, 'Untitled - Paint')"#
-            ])
-            .output();
-            
-        if let Ok(output) = hwnd_result {
-            if let Ok(output_str) = String::from_utf8(output.stdout) {
-                if let Ok(hwnd) = output_str.trim().parse::<i32>() {
-                    if hwnd != 0 {
-                        return Ok(hwnd as HWND);
-                    }
-                }
-            }
+        let desktop = OpenInputDesktop(0, FALSE, DESKTOP_SWITCHDESKTOP);
+        if desktop == 0 {
+            return None;
         }
-        
-        // Try a general purpose enumeration looking for specific features
-        let enum_data = &mut EnumWindowData {
-            hwnd: None,
-            target_class: Vec::new(),
-            target_title_substring: Vec::new(),
-        };
-        let lparam = enum_data as *mut _ as LPARAM;
-        
-        // Custom callback for finding any window that might be Paint
-        unsafe extern "system" fn find_any_paint_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
-            if IsWindowVisible(hwnd) == FALSE {
-                return TRUE; // Continue enumeration
-            }
-            
-            let data = &mut *(lparam as *mut EnumWindowData);
-            
-            // Get window title
-            let mut window_title: [u16; 256] = [0; 256];
-            let title_len = GetWindowTextW(hwnd, window_title.as_mut_ptr(), window_title.len() as i32);
-            
-            if title_len > 0 {
-                let title_str = String::from_utf16_lossy(&window_title[..title_len as usize]);
-                
-                // Don't match our own application
-                if title_str.contains("mcp-server-microsoft-paint") {
-                    return TRUE; // Continue enumeration
-                }
-                
-                // If it has "Paint" in the title, it's a strong candidate
-                if title_str.to_lowercase().contains("paint") {
-                    data.hwnd = Some(hwnd);
-                    return FALSE; // Stop enumeration
-                }
-            }
-            
-            // Get class name
-            let mut class_name: [u16; 128] = [0; 128];
-            let class_len = GetClassNameW(hwnd, class_name.as_mut_ptr(), class_name.len() as i32);
-            
-            if class_len > 0 {
-                let class_str = String::from_utf16_lossy(&class_name[..class_len as usize]);
-                
-                // Check for any class that might be Paint-related
-                if class_str.contains("Paint") || class_str.contains("Afx") {
-                    data.hwnd = Some(hwnd);
-                    return FALSE; // Stop enumeration
-                }
-            }
-            
-            TRUE // Continue enumeration
+        let name = desktop_name(desktop);
+        CloseDesktop(desktop);
+        name
+    }
+}
+
+// Name of a desktop, or None if it can't be read
+fn desktop_name(desktop: HDESK) -> Option<String> {
+    let mut name: [u16; 256] = [0; 256];
+    let mut needed: u32 = 0;
+    let ok = unsafe {
+        GetUserObjectInformationW(
+            desktop,
+            UOI_NAME,
+            name.as_mut_ptr() as *mut _,
+            (name.len() * std::mem::size_of::<u16>()) as u32,
+            &mut needed,
+        )
+    };
+    if ok == FALSE {
+        return None;
+    }
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    Some(String::from_utf16_lossy(&name[..len]))
+}
+
+// SendInput only reaches the calling thread's desktop. If this thread is on
+// another desktop than the input desktop, moves it there, where Windows
+// allows that (it doesn't once the thread owns windows or hooks, or without
+// access to the desktop)
+fn attach_to_input_desktop() {
+    unsafe {
+        let input = OpenInputDesktop(0, FALSE, GENERIC_ALL);
+        if input == 0 {
+            return;
         }
-        
-        EnumWindows(Some(find_any_paint_proc), lparam);
-        
-        if let Some(found_hwnd) = enum_data.hwnd {
-            // Double-check this looks like a Paint window
-            let mut window_title: [u16; 256] = [0; 256];
-            let title_len = GetWindowTextW(found_hwnd, window_title.as_mut_ptr(), window_title.len() as i32);
-            
-            let mut class_name: [u16; 128] = [0; 128];
-            let class_len = GetClassNameW(found_hwnd, class_name.as_mut_ptr(), class_name.len() as i32);
-            
-            let title_str = if title_len > 0 { 
-                String::from_utf16_lossy(&window_title[..title_len as usize]) 
-            } else { 
-                "<No Title>".to_string() 
-            };
-            
-            let class_str = if class_len > 0 { 
-                String::from_utf16_lossy(&class_name[..class_len as usize])
-            } else { 
-                "<Unknown Class>".to_string() 
-            };
-            
-            info!("Last-resort found potential Paint window: HWND={}, Class='{}', Title='{}'", 
-                  found_hwnd, class_str, title_str);
-            
-            return Ok(found_hwnd);
+        let input_name = desktop_name(input);
+        if desktop_name(GetThreadDesktop(GetCurrentThreadId())) == input_name {
+            CloseDesktop(input);
+            return;
+        }
+        // The desktop stays open while the thread uses it
+        if SetThreadDesktop(input) == FALSE {
+            debug!("Could not move to the input desktop {:?} (error {})", input_name, GetLastError());
+            CloseDesktop(input);
+        } else {
+            info!("Moved to the input desktop {:?}", input_name);
         }
     }
+}
+
+/// Returns true if synthesized input would reach the user's desktop.
+pub fn is_input_desktop_available() -> bool {
+    matches!(get_input_desktop_name(), Some(name) if name.eq_ignore_ascii_case(DEFAULT_DESKTOP_NAME))
+}
+
+/// Makes sure synthesized input reaches the user's desktop, moving this
+/// thread to it if needed. While the workstation is locked or a secure
+/// desktop (UAC prompt, Winlogon) is active, input would go nowhere, so the
+/// request waits for the user's desktop to return, up to
+/// `input_desktop_wait_ms`; the client is sent notifications/paint/input_paused
+/// and, once input is possible again, notifications/paint/input_available.
+/// Fails if the desktop doesn't come back in time.
+pub fn check_input_desktop() -> Result<()> {
+    if is_input_desktop_available() {
+        attach_to_input_desktop();
+        return Ok(());
+    }
     
-    Err(MspMcpError::WindowNotFound)
+    let reason = match get_input_desktop_name() {
+        Some(name) => format!("the '{}' desktop is active (secure desktop, UAC prompt or lock screen)", name),
+        None => "the workstation is locked or the input desktop is inaccessible".to_string(),
+    };
+    let wait = crate::session::desktop_wait();
+    warn!("Input injection paused for up to {:?}: {}", wait, reason);
+    if watch_for_input_desktop() {
+        crate::transport::send_notification("notifications/paint/input_paused", serde_json::json!({
+            "reason": reason,
+            "wait_ms": wait.as_millis() as u64
+        }));
+    }
+    
+    let deadline = crate::clock::now() + wait;
+    while crate::clock::now() < deadline {
+        crate::clock::sleep(INPUT_DESKTOP_POLL);
+        if is_input_desktop_available() {
+            info!("Input desktop is back; resuming");
+            attach_to_input_desktop();
+            return Ok(());
+        }
+    }
+    Err(MspMcpError::InputDesktopUnavailable(format!(
+        "{}; a notifications/paint/input_available notification will be sent when input is possible again", reason)))
+}
+
+/// Polls in the background until the user's desktop receives input again and
+/// then sends a notification. Only one watcher runs at a time; returns true
+/// if this call started it.
+fn watch_for_input_desktop() -> bool {
+    if INPUT_DESKTOP_WATCHER_RUNNING.swap(true, Ordering::SeqCst) {
+        return false; // Already watching
+    }
+    
+    std::thread::spawn(|| {
+        while !is_input_desktop_available() {
+            crate::clock::sleep(std::time::Duration::from_millis(1000));
+        }
+        info!("Input desktop is available again");
+        INPUT_DESKTOP_WATCHER_RUNNING.store(false, Ordering::SeqCst);
+        crate::transport::send_notification("notifications/paint/input_available", serde_json::json!({
+            "desktop": DEFAULT_DESKTOP_NAME
+        }));
+    });
+    true
 }
 
 /// Activates the Paint window, bringing it to the foreground.
 /// Handles maximized state and ensures the window is not minimized.
 pub fn activate_paint_window(hwnd: HWND) -> Result<()> {
+    let _timing = timing::enter(Phase::Activation);
     info!("Activating Paint window: HWND={}", hwnd);
+    crate::message_input::set_target(hwnd);
+    
+    // Posted messages reach Paint whether or not it is in front, and a
+    // disconnected session has no foreground to bring it to
+    if crate::session::uses_messages() {
+        return if unsafe { IsWindow(hwnd) } == FALSE { Err(MspMcpError::WindowNotFound) } else { Ok(()) };
+    }
+    
+    // Input can't be injected while locked or on a secure desktop
+    check_input_desktop()?;
+    
+    // The request leaves the focus alone if Paint has it already
+    if crate::session::activation() == crate::protocol::Activation::IfNeeded
+        && unsafe { GetForegroundWindow() } == hwnd && unsafe { IsIconic(hwnd) } == FALSE {
+        debug!("Paint is already the foreground window; not activating it");
+        return Ok(());
+    }
     
     // Check if window is valid
     let is_visible = unsafe { IsWindowVisible(hwnd) };
@@ -695,8 +583,11 @@ pub fn activate_paint_window(hwnd: HWND) -> Result<()> {
         return Err(MspMcpError::WindowNotFound);
     }
     
+    // Input to an elevated Paint would be silently dropped
+    check_input_privileges(hwnd)?;
+    
     // Wait a bit before activation attempts - helps with stability
-    std::thread::sleep(std::time::Duration::from_millis(200));
+    crate::clock::sleep(std::time::Duration::from_millis(200));
     
     let mut success = true;
     let mut activation_error = String::new();
@@ -714,7 +605,7 @@ pub fn activate_paint_window(hwnd: HWND) -> Result<()> {
         }
         
         // Wait a bit after ShowWindow
-        std::thread::sleep(std::time::Duration::from_millis(300));
+        crate::clock::sleep(std::time::Duration::from_millis(300));
         
         // Attempt to activate window (bring to foreground)
         if SetForegroundWindow(hwnd) == FALSE {
@@ -725,7 +616,7 @@ pub fn activate_paint_window(hwnd: HWND) -> Result<()> {
             }
             
             // Wait a bit before alternate attempt
-            std::thread::sleep(std::time::Duration::from_millis(200));
+            crate::clock::sleep(std::time::Duration::from_millis(200));
             
             // Attempt alternative activation method
             // SetWindowPos can sometimes succeed when SetForegroundWindow fails
@@ -752,20 +643,40 @@ pub fn activate_paint_window(hwnd: HWND) -> Result<()> {
     }
     
     // Give the window more time to become fully active
-    std::thread::sleep(std::time::Duration::from_millis(500));
+    crate::clock::sleep(std::time::Duration::from_millis(500));
     
     Ok(())
 }
 
-/// Calculates the actual canvas dimensions within the Paint window.
-/// This is a more accurate version of get_initial_canvas_dimensions.
-/// TODO: Implement proper calculation based on Win11 Paint's UI layout.
+/// Returns the document size in image pixels, whatever the zoom level.
 pub fn get_canvas_dimensions(hwnd: HWND) -> Result<(u32, u32)> {
+    let view = get_canvas_view(hwnd)?;
+    Ok((view.width, view.height))
+}
+
+/// Reads the document size, zoom, viewport and scroll position from Paint.
+/// If Paint's status bar can't be read, the size is estimated from the
+/// window size instead and marked as such.
+pub fn get_canvas_view(hwnd: HWND) -> Result<CanvasDimensionsResponse> {
     // First ensure the window is activated, as dimensions might not be correct
     // if the window is minimized
     activate_paint_window(hwnd)?;
     
-    // Get the window rectangle
+    match crate::uia::read_canvas_view_uia(hwnd) {
+        Ok(view) => {
+            info!("Canvas is {}x{} at {}%", view.width, view.height, view.zoom * 100.0);
+            Ok(view)
+        }
+        Err(e) => {
+            warn!("Could not read the canvas size from Paint ({}); estimating it from the window size", e);
+            estimate_canvas_view(hwnd)
+        }
+    }
+}
+
+// The pre-UIA estimate: the window size minus typical toolbar and panel
+// sizes, shown at 100% from the drawing area offset
+fn estimate_canvas_view(hwnd: HWND) -> Result<CanvasDimensionsResponse> {
     let mut rect: windows_sys::Win32::Foundation::RECT = unsafe { std::mem::zeroed() };
     unsafe {
         if GetWindowRect(hwnd, &mut rect) == FALSE {
@@ -773,30 +684,118 @@ pub fn get_canvas_dimensions(hwnd: HWND) -> Result<(u32, u32)> {
         }
     }
     
-    // Calculate window dimensions first
-    let window_width = (rect.right - rect.left) as u32;
-    let window_height = (rect.bottom - rect.top) as u32;
+    let (window_width, window_height) = rect_size(rect.left, rect.top, rect.right, rect.bottom);
+    let (canvas_width, canvas_height) = compute_canvas_dimensions(window_width, window_height);
+    let (offset_x, offset_y) = get_drawing_area_offset(hwnd)?;
+    let (left, top) = client_to_screen(hwnd, offset_x, offset_y)?;
     
-    // Approximate the canvas dimensions by subtracting typical UI elements sizes
-    // These values are estimates and may need adjustment based on actual Win11 Paint UI
-    const TITLE_BAR_HEIGHT: u32 = 32;
-    const MENU_BAR_HEIGHT: u32 = 30; 
-    const TOOLBAR_HEIGHT: u32 = 80;  // Combined height of ribbon/toolbar
-    const STATUS_BAR_HEIGHT: u32 = 25;
-    const LEFT_PANEL_WIDTH: u32 = 0;  // No left panel in modern Paint
-    const RIGHT_PANEL_WIDTH: u32 = 270; // Right tools/properties panel
+    info!("Estimated canvas dimensions: {}x{} (window: {}x{})", 
+        canvas_width, canvas_height, window_width, window_height);
     
-    // Calculate canvas dimensions by subtracting UI elements
-    // Ensure we don't underflow if window is very small
-    let canvas_width = window_width.saturating_sub(LEFT_PANEL_WIDTH + RIGHT_PANEL_WIDTH);
-    let canvas_height = window_height.saturating_sub(
-        TITLE_BAR_HEIGHT + MENU_BAR_HEIGHT + TOOLBAR_HEIGHT + STATUS_BAR_HEIGHT
-    );
+    Ok(CanvasDimensionsResponse {
+        width: canvas_width,
+        height: canvas_height,
+        zoom: 1.0,
+        viewport: RectInfo::new(left, top, left + canvas_width as i32, top + canvas_height as i32),
+        scroll_x: 0,
+        scroll_y: 0,
+        estimated: true,
+    })
+}
+
+/// Returns the class name of a window, or an empty string if it can't be read.
+pub fn get_window_class_name(hwnd: HWND) -> String {
+    let mut class_name: [u16; 256] = [0; 256];
+    let class_len = unsafe { GetClassNameW(hwnd, class_name.as_mut_ptr(), class_name.len() as i32) };
+    if class_len > 0 {
+        String::from_utf16_lossy(&class_name[..class_len as usize])
+    } else {
+        String::new()
+    }
+}
+
+/// Returns the title of a window, or an empty string if it has none.
+pub fn get_window_title(hwnd: HWND) -> String {
+    let mut title: [u16; 512] = [0; 512];
+    let title_len = unsafe { GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as i32) };
+    if title_len > 0 {
+        String::from_utf16_lossy(&title[..title_len as usize])
+    } else {
+        String::new()
+    }
+}
+
+/// Returns the ID of the process that owns the window.
+pub fn get_window_pid(hwnd: HWND) -> u32 {
+    let mut pid: u32 = 0;
+    unsafe { GetWindowThreadProcessId(hwnd, &mut pid) };
+    pid
+}
+
+/// Collects geometry and state information about a window for debugging
+/// and for clients that want to do their own coordinate math.
+/// Does not activate the window.
+pub fn get_window_info(hwnd: HWND) -> Result<WindowInfoResponse> {
+    if unsafe { IsWindow(hwnd) } == FALSE {
+        return Err(MspMcpError::WindowNotFound);
+    }
     
-    info!("Calculated canvas dimensions: {}x{} (window: {}x{})", 
-        canvas_width, canvas_height, window_width, window_height);
+    let mut window_rect: windows_sys::Win32::Foundation::RECT = unsafe { std::mem::zeroed() };
+    let mut client_rect: windows_sys::Win32::Foundation::RECT = unsafe { std::mem::zeroed() };
+    unsafe {
+        if GetWindowRect(hwnd, &mut window_rect) == FALSE {
+            return Err(MspMcpError::WindowsApiError("GetWindowRect failed".to_string()));
+        }
+        if GetClientRect(hwnd, &mut client_rect) == FALSE {
+            return Err(MspMcpError::WindowsApiError("GetClientRect failed".to_string()));
+        }
+    }
+    
+    let (client_origin_x, client_origin_y) = client_to_screen(hwnd, 0, 0)?;
+    
+    // GetDpiForWindow returns 0 for invalid windows; fall back to the default DPI
+    let dpi = crate::geometry::effective_dpi(unsafe { GetDpiForWindow(hwnd) });
+    
+    let monitor = get_window_monitor(hwnd);
+    if monitor.is_none() {
+        warn!("Failed to query monitor info for HWND={}", hwnd);
+    }
+    
+    // Count the top-level windows above this one to report its z-order position
+    let mut z_order = 0;
+    let mut above = unsafe { GetWindow(hwnd, GW_HWNDPREV) };
+    while above != 0 {
+        z_order += 1;
+        above = unsafe { GetWindow(above, GW_HWNDPREV) };
+    }
     
-    Ok((canvas_width, canvas_height))
+    let (window_width, window_height) = rect_size(window_rect.left, window_rect.top, window_rect.right, window_rect.bottom);
+    let (canvas_width, canvas_height) = compute_canvas_dimensions(window_width, window_height);
+    let (offset_x, offset_y) = get_drawing_area_offset(hwnd)?;
+    
+    Ok(WindowInfoResponse {
+        hwnd,
+        pid: get_window_pid(hwnd),
+        class_name: get_window_class_name(hwnd),
+        title: get_window_title(hwnd),
+        window_rect: RectInfo::new(window_rect.left, window_rect.top, window_rect.right, window_rect.bottom),
+        client_rect: RectInfo::new(client_rect.left, client_rect.top, client_rect.right, client_rect.bottom),
+        client_origin_x,
+        client_origin_y,
+        dpi,
+        scale_factor: crate::geometry::scale_factor(dpi),
+        monitor,
+        is_foreground: unsafe { GetForegroundWindow() } == hwnd,
+        is_minimized: unsafe { IsIconic(hwnd) } != FALSE,
+        is_maximized: unsafe { IsZoomed(hwnd) } != FALSE,
+        z_order,
+        canvas_rect: RectInfo::new(
+            offset_x,
+            offset_y,
+            offset_x + canvas_width as i32,
+            offset_y + canvas_height as i32,
+        ),
+    })
 }
 
 // TODO: Add tests (might require manual setup or #[ignore])
@@ -812,13 +811,138 @@ pub fn get_initial_canvas_dimensions(hwnd: HWND) -> Result<(u32, u32)> {
         }
     }
     // Placeholder: return full window dimensions
-    let width = (rect.right - rect.left) as u32;
-    let height = (rect.bottom - rect.top) as u32;
+    let (width, height) = rect_size(rect.left, rect.top, rect.right, rect.bottom);
     info!("GetWindowRect returned dimensions: {}x{}", width, height);
     // TODO: Subtract toolbars, panels, etc., to get canvas size
     Ok((width, height))
 }
 
+// Callback for EnumDisplayMonitors that collects monitor handles
+unsafe extern "system" fn enum_monitor_proc(hmonitor: HMONITOR, _hdc: HDC, _rect: *mut RECT, lparam: LPARAM) -> BOOL {
+    let monitors = &mut *(lparam as *mut Vec<HMONITOR>);
+    monitors.push(hmonitor);
+    TRUE // Continue enumeration
+}
+
+/// Lists all display monitors in EnumDisplayMonitors order.
+pub fn list_monitors() -> Result<Vec<MonitorInfo>> {
+    let mut handles: Vec<HMONITOR> = Vec::new();
+    unsafe {
+        let lparam = &mut handles as *mut Vec<HMONITOR> as LPARAM;
+        if EnumDisplayMonitors(0, ptr::null(), Some(enum_monitor_proc), lparam) == FALSE {
+            return Err(MspMcpError::WindowsApiError("EnumDisplayMonitors failed".to_string()));
+        }
+    }
+    
+    let mut monitors = Vec::with_capacity(handles.len());
+    for (index, hmonitor) in handles.into_iter().enumerate() {
+        let mut info: MONITORINFO = unsafe { std::mem::zeroed() };
+        info.cbSize = std::mem::size_of::<MONITORINFO>() as u32;
+        if unsafe { GetMonitorInfoW(hmonitor, &mut info) } == FALSE {
+            warn!("GetMonitorInfoW failed for monitor {}", index);
+            continue;
+        }
+        monitors.push(MonitorInfo {
+            index: index as u32,
+            handle: hmonitor,
+            monitor_rect: RectInfo::new(info.rcMonitor.left, info.rcMonitor.top, info.rcMonitor.right, info.rcMonitor.bottom),
+            work_rect: RectInfo::new(info.rcWork.left, info.rcWork.top, info.rcWork.right, info.rcWork.bottom),
+            is_primary: (info.dwFlags & MONITORINFOF_PRIMARY) != 0,
+        });
+    }
+    Ok(monitors)
+}
+
+/// Returns the monitor the window is (mostly) on.
+pub fn get_window_monitor(hwnd: HWND) -> Option<MonitorInfo> {
+    let hmonitor = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST) };
+    if hmonitor == 0 {
+        return None;
+    }
+    list_monitors().ok()?.into_iter().find(|m| m.handle == hmonitor)
+}
+
+/// Moves the Paint window onto the given monitor (or its current monitor when
+/// `monitor_index` is None) and makes sure it is fully visible, so that screen
+/// coordinates computed from the client area land on the window.
+/// Returns the monitor the window ended up on.
+pub fn ensure_window_on_monitor(hwnd: HWND, monitor_index: Option<u32>) -> Result<MonitorInfo> {
+    let target = match monitor_index {
+        Some(index) => {
+            let monitors = list_monitors()?;
+            let count = monitors.len();
+            monitors.into_iter().find(|m| m.index == index).ok_or_else(|| {
+                MspMcpError::InvalidParameters(format!(
+                    "Monitor index {} out of range ({} monitors available)", index, count))
+            })?
+        }
+        None => get_window_monitor(hwnd).ok_or_else(|| 
+            MspMcpError::WindowsApiError("Failed to determine the window's monitor".to_string()))?,
+    };
+    
+    info!("Ensuring Paint window HWND={} is fully on monitor {}", hwnd, target.index);
+    
+    unsafe {
+        // Minimized windows report bogus coordinates; restore first
+        if IsIconic(hwnd) != FALSE {
+            ShowWindow(hwnd, SW_RESTORE);
+            crate::clock::sleep(std::time::Duration::from_millis(300));
+        }
+    }
+    
+    let current_monitor = get_window_monitor(hwnd);
+    let on_target = current_monitor.as_ref().map(|m| m.handle) == Some(target.handle);
+    let was_maximized = unsafe { IsZoomed(hwnd) } != FALSE;
+    
+    // A maximized window on the right monitor is already fully visible
+    if was_maximized && on_target {
+        return Ok(target);
+    }
+    
+    // Maximized windows can't be moved; restore, move, then maximize again
+    if was_maximized {
+        unsafe { ShowWindow(hwnd, SW_RESTORE) };
+        crate::clock::sleep(std::time::Duration::from_millis(300));
+    }
+    
+    let mut rect: windows_sys::Win32::Foundation::RECT = unsafe { std::mem::zeroed() };
+    unsafe {
+        if GetWindowRect(hwnd, &mut rect) == FALSE {
+            return Err(MspMcpError::WindowsApiError("GetWindowRect failed".to_string()));
+        }
+    }
+    let mut window = RectInfo::new(rect.left, rect.top, rect.right, rect.bottom);
+    
+    // When switching monitors, keep the window's offset relative to the work area
+    if !on_target {
+        if let Some(current) = &current_monitor {
+            let dx = target.work_rect.left - current.work_rect.left;
+            let dy = target.work_rect.top - current.work_rect.top;
+            window = RectInfo::new(window.left + dx, window.top + dy, window.right + dx, window.bottom + dy);
+        }
+    }
+    
+    let fitted = fit_rect_within(window, target.work_rect);
+    if fitted != RectInfo::new(rect.left, rect.top, rect.right, rect.bottom) {
+        info!("Moving Paint window to ({}, {}) size {}x{}", 
+              fitted.left, fitted.top, fitted.width, fitted.height);
+        unsafe {
+            if SetWindowPos(hwnd, 0, fitted.left, fitted.top, fitted.width, fitted.height, 
+                            SWP_NOZORDER | SWP_NOACTIVATE) == FALSE {
+                return Err(MspMcpError::WindowsApiError("SetWindowPos failed".to_string()));
+            }
+        }
+        crate::clock::sleep(std::time::Duration::from_millis(200));
+    }
+    
+    if was_maximized {
+        unsafe { ShowWindow(hwnd, SW_MAXIMIZE) };
+        crate::clock::sleep(std::time::Duration::from_millis(300));
+    }
+    
+    Ok(target)
+}
+
 /// Converts client coordinates to screen coordinates
 /// Client coordinates are relative to the client area of the window,
 /// while screen coordinates are absolute positions on the screen.
@@ -837,17 +961,52 @@ pub fn client_to_screen(hwnd: HWND, client_x: i32, client_y: i32) -> Result<(i32
     Ok((point.x, point.y))
 }
 
+// Returns the virtual desktop's origin and size
+fn virtual_screen_rect() -> ScreenRect {
+    let (virtual_x, virtual_y, virtual_width, virtual_height) = unsafe {
+        (
+            GetSystemMetrics(SM_XVIRTUALSCREEN),
+            GetSystemMetrics(SM_YVIRTUALSCREEN),
+            GetSystemMetrics(SM_CXVIRTUALSCREEN),
+            GetSystemMetrics(SM_CYVIRTUALSCREEN),
+        )
+    };
+    
+    // Fall back to the primary screen if the virtual screen metrics are unavailable
+    if virtual_width > 0 && virtual_height > 0 {
+        (virtual_x, virtual_y, virtual_width, virtual_height)
+    } else {
+        unsafe { (0, 0, GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN)) }
+    }
+}
+
 /// Converts a screen coordinate to a normalized coordinate (0-65535 range)
 /// Normalized coordinates are used by SendInput to ensure compatibility with multiple monitors
-/// and different screen resolutions.
+/// and different screen resolutions. The range spans the whole virtual desktop so
+/// windows on secondary monitors can be targeted (see MOUSEEVENTF_VIRTUALDESK).
 fn screen_to_normalized(x: i32, y: i32) -> (i32, i32) {
-    let screen_width = unsafe { GetSystemMetrics(SM_CXSCREEN) };
-    let screen_height = unsafe { GetSystemMetrics(SM_CYSCREEN) };
-    
-    let normalized_x = (x * 65535) / screen_width;
-    let normalized_y = (y * 65535) / screen_height;
-    
-    (normalized_x, normalized_y)
+    crate::geometry::screen_to_normalized(x, y, virtual_screen_rect())
+}
+
+/// Converts a normalized (0-65535) coordinate back to screen coordinates.
+pub fn normalized_to_screen(normalized_x: i32, normalized_y: i32) -> (i32, i32) {
+    crate::geometry::normalized_to_screen(normalized_x, normalized_y, virtual_screen_rect())
+}
+
+/// SendInput, copying the inputs to the active input recording (if any).
+/// All synthesized input goes through here. Where SendInput can't reach
+/// Paint (see session.rs) the inputs are posted as window messages instead.
+unsafe fn send_input(count: u32, inputs: *const INPUT, size: i32) -> u32 {
+    // A request the watchdog gave up on sends nothing more; 0 fails its caller
+    if crate::watchdog::tripped() {
+        return 0;
+    }
+    let slice = std::slice::from_raw_parts(inputs, count as usize);
+    crate::input_record::record(slice);
+    if crate::session::uses_messages() {
+        return timing::measure(Phase::Input, || crate::message_input::post(slice));
+    }
+    timing::measure(Phase::Input, || SendInput(count, inputs, size))
 }
 
 /// Simulates moving the mouse cursor to the specified screen coordinates.
@@ -869,12 +1028,12 @@ pub fn move_mouse_to(screen_x: i32, screen_y: i32) -> Result<()> {
         mi.dx = normalized_x;
         mi.dy = normalized_y;
         mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE;
+        mi.dwFlags = MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK;
         mi.time = 0;
         mi.dwExtraInfo = 0;
         
         // Send the input
-        let inputs_sent = SendInput(1, &mut input_struct, std::mem::size_of::<INPUT>() as i32);
+        let inputs_sent = send_input(1, &mut input_struct, std::mem::size_of::<INPUT>() as i32);
         
         if inputs_sent != 1 {
             return Err(MspMcpError::WindowsApiError("Failed to send mouse movement input".to_string()));
@@ -882,11 +1041,27 @@ pub fn move_mouse_to(screen_x: i32, screen_y: i32) -> Result<()> {
     }
     
     // Brief delay to allow the movement to register
-    std::thread::sleep(std::time::Duration::from_millis(5));
+    crate::clock::sleep(std::time::Duration::from_millis(5));
     
     Ok(())
 }
 
+/// SendInput down and up flags for a mouse button.
+pub fn button_flags(button: MouseButton) -> (u32, u32) {
+    match button {
+        MouseButton::Left => (MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP),
+        MouseButton::Right => (MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP),
+    }
+}
+
+/// Clicks `button` at the current cursor position.
+pub fn click_mouse_button(button: MouseButton) -> Result<()> {
+    match button {
+        MouseButton::Left => click_left_mouse_button(),
+        MouseButton::Right => click_right_mouse_button(),
+    }
+}
+
 /// Simulates a left mouse button click at the current cursor position.
 pub fn click_left_mouse_button() -> Result<()> {
     debug!("Simulating left mouse click...");
@@ -916,7 +1091,7 @@ pub fn click_left_mouse_button() -> Result<()> {
         
         // Send the inputs
         debug!("Sending MOUSEEVENTF_LEFTDOWN + MOUSEEVENTF_LEFTUP");
-        let inputs_sent = SendInput(2, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32);
+        let inputs_sent = send_input(2, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32);
         
         if inputs_sent != 2 {
             error!("SendInput failed for left click (sent {} inputs)", inputs_sent);
@@ -927,7 +1102,7 @@ pub fn click_left_mouse_button() -> Result<()> {
     }
     
     // Brief delay to allow the click to register
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    crate::clock::sleep(std::time::Duration::from_millis(10));
     
     Ok(())
 }
@@ -961,7 +1136,7 @@ pub fn click_right_mouse_button() -> Result<()> {
         
         // Send the inputs
         debug!("Sending MOUSEEVENTF_RIGHTDOWN + MOUSEEVENTF_RIGHTUP");
-        let inputs_sent = SendInput(2, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32);
+        let inputs_sent = send_input(2, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32);
         
         if inputs_sent != 2 {
             error!("SendInput failed for right click (sent {} inputs)", inputs_sent);
@@ -972,7 +1147,7 @@ pub fn click_right_mouse_button() -> Result<()> {
     }
     
     // Brief delay to allow the click to register
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    crate::clock::sleep(std::time::Duration::from_millis(10));
     
     Ok(())
 }
@@ -984,31 +1159,11 @@ pub fn drag_mouse(start_screen_x: i32, start_screen_y: i32, end_screen_x: i32, e
     move_mouse_to(start_screen_x, start_screen_y)?;
     
     // Brief delay before clicking
-    std::thread::sleep(std::time::Duration::from_millis(50));
+    crate::clock::sleep(std::time::Duration::from_millis(50));
     
-    // Perform mouse down
-    let mut input: INPUT = unsafe { std::mem::zeroed() };
-    input.r#type = INPUT_MOUSE;
-    
-    unsafe {
-        // Mouse down
-        let mi = &mut input.Anonymous.mi;
-        mi.dx = 0;
-        mi.dy = 0;
-        mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTDOWN;
-        mi.time = 0;
-        mi.dwExtraInfo = 0;
-        
-        debug!("Sending MOUSEEVENTF_LEFTDOWN for drag start at ({}, {})", start_screen_x, start_screen_y);
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-        if inputs_sent != 1 {
-            error!("SendInput failed for drag start (sent {} inputs)", inputs_sent);
-            return Err(MspMcpError::WindowsApiError("Failed to send mouse down input".to_string()));
-        } else {
-            debug!("SendInput successful for drag start.");
-        }
-    }
+    // Perform mouse down; the button is let go of even if a step below fails
+    debug!("Sending MOUSEEVENTF_LEFTDOWN for drag start at ({}, {})", start_screen_x, start_screen_y);
+    let held = HeldButton::press(MouseButton::Left)?;
     
     // Move to end position in small steps for smoother drawing
     let steps = 10; // Use 10 steps for smoother drawing
@@ -1023,37 +1178,18 @@ pub fn drag_mouse(start_screen_x: i32, start_screen_y: i32, end_screen_x: i32, e
         move_mouse_to(x, y)?;
         
         // Brief delay between steps
-        std::thread::sleep(std::time::Duration::from_millis(5));
+        crate::clock::sleep(std::time::Duration::from_millis(5));
     }
     
     // Ensure we're at the end position
     move_mouse_to(end_screen_x, end_screen_y)?;
     
     // Brief delay before releasing
-    std::thread::sleep(std::time::Duration::from_millis(50));
+    crate::clock::sleep(std::time::Duration::from_millis(50));
     
     // Perform mouse up
-    unsafe {
-        // Mouse up
-        let mi = &mut input.Anonymous.mi;
-        mi.dx = 0;
-        mi.dy = 0;
-        mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTUP;
-        mi.time = 0;
-        mi.dwExtraInfo = 0;
-        
-        debug!("Sending MOUSEEVENTF_LEFTUP for drag end at ({}, {})", end_screen_x, end_screen_y);
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-        if inputs_sent != 1 {
-            error!("SendInput failed for drag end (sent {} inputs)", inputs_sent);
-            return Err(MspMcpError::WindowsApiError("Failed to send mouse up input".to_string()));
-        } else {
-            debug!("SendInput successful for drag end.");
-        }
-    }
-    
-    Ok(())
+    debug!("Sending MOUSEEVENTF_LEFTUP for drag end at ({}, {})", end_screen_x, end_screen_y);
+    held.release()
 }
 
 /// Helper function to click at a specific position.
@@ -1072,36 +1208,70 @@ pub fn click_at_client_position(hwnd: HWND, client_x: i32, client_y: i32) -> Res
 
 /// Calculate the drawing area offset
 /// This adds the extra vertical offset needed to account for toolbars in Paint
-pub fn get_drawing_area_offset(hwnd: HWND) -> Result<(i32, i32)> {
-    // The toolbar and ribbon height varies based on Paint version
-    // Windows 11 Paint has a larger ribbon than Windows 10
-    // These are approximations that should work in most cases
-    let toolbar_height = 120;  // Combined height of title bar, ribbon, etc.
-    let left_offset = 5;       // Small left margin
-    
-    Ok((left_offset, toolbar_height))
+pub fn get_drawing_area_offset(_hwnd: HWND) -> Result<(i32, i32)> {
+    // The toolbar and ribbon height varies based on Paint version, so it
+    // comes from the layout picked for the connected Paint (see layout.rs)
+    let layout = crate::layout::active();
+    Ok((layout.left_margin, layout.toolbar_height))
 }
 
-/// Draws a pixel at the specified coordinates.
-pub fn draw_pixel_at(hwnd: HWND, canvas_x: i32, canvas_y: i32) -> Result<()> {
+/// Draws a pixel at the specified coordinates. A right click draws in Color 2.
+pub fn draw_pixel_at(hwnd: HWND, canvas_x: i32, canvas_y: i32, button: MouseButton) -> Result<()> {
     // First make sure the Paint window is active
     activate_paint_window(hwnd)?;
     
     // Select the pencil tool for reliable drawing
     select_tool(hwnd, "pencil")?;
     
-    // Get drawing area offset
-    let (offset_x, offset_y) = get_drawing_area_offset(hwnd)?;
-    
-    // Add offset to canvas coordinates to get client coordinates
-    let client_x = canvas_x + offset_x;
-    let client_y = canvas_y + offset_y;
+    // Simple click to draw a pixel with the pencil tool
+    draw_in_view(hwnd, &[(canvas_x, canvas_y)], |screen| {
+        move_mouse_to(screen[0].0, screen[0].1)?;
+        click_mouse_button(button)
+    })
+}
+
+/// Runs `stroke` with the screen positions of the canvas pixels `points`,
+/// scrolling the canvas first when some of them are out of view. A stroke
+/// bigger than the viewport is split into pieces that are scrolled into
+/// view and drawn one after another. The scroll position is put back
+/// afterwards, also when drawing fails.
+fn draw_in_view(hwnd: HWND, points: &[(i32, i32)], mut stroke: impl FnMut(&[(i32, i32)]) -> Result<()>) -> Result<()> {
+    let client_origin = client_to_screen(hwnd, 0, 0)?;
+    let view = crate::uia::read_canvas_view_uia(hwnd).or_else(|e| {
+        debug!("Could not read the canvas view ({}); drawing at the estimated canvas position", e);
+        estimate_canvas_view(hwnd)
+    })?;
+    let frame = CanvasFrame::from_view(&view, client_origin);
+    let to_screen = |frame: &CanvasFrame, points: &[(i32, i32)]| points.iter().map(|&(x, y)| frame.to_screen(x, y)).collect::<Vec<_>>();
+    // Off-canvas points can't be scrolled to; the stroke just runs off the edge there
+    let on_canvas = |&&(x, y): &&(i32, i32)| x >= 0 && y >= 0 && (x as u32) < view.width && (y as u32) < view.height;
+    if view.estimated || points.iter().filter(on_canvas).all(|&(x, y)| frame.is_visible(x, y)) {
+        return stroke(&to_screen(&frame, points));
+    }
     
-    // Convert to screen coordinates
-    let (screen_x, screen_y) = client_to_screen(hwnd, client_x, client_y)?;
+    let (visible_width, visible_height) = frame.visible_size();
+    if visible_width == 0 || visible_height == 0 {
+        return Err(MspMcpError::UiAutomationError("The canvas viewport is too small to draw in".to_string()));
+    }
+    let pieces = split_stroke(points, (visible_width, visible_height));
+    info!("Scrolling the canvas to draw outside the visible area, in {} piece(s)", pieces.len());
+    let result = pieces.iter().try_for_each(|piece| {
+        let Some((min, max)) = bounding_box(piece) else { return Ok(()) };
+        let scroll = frame.scroll_to_show(min, max);
+        crate::uia::scroll_canvas_uia(hwnd, &view, scroll)?;
+        crate::clock::sleep(std::time::Duration::from_millis(200));
+        
+        let scrolled = CanvasFrame::from_view(&crate::uia::read_canvas_view_uia(hwnd)?, client_origin);
+        if let Some(&(x, y)) = piece.iter().filter(on_canvas).find(|&&(x, y)| !scrolled.is_visible(x, y)) {
+            return Err(MspMcpError::UiAutomationError(format!("Canvas pixel ({}, {}) is still out of view after scrolling", x, y)));
+        }
+        stroke(&to_screen(&scrolled, piece))
+    });
     
-    // Simple click to draw a pixel with the pencil tool
-    click_at_position(screen_x, screen_y)
+    if let Err(e) = crate::uia::scroll_canvas_uia(hwnd, &view, (view.scroll_x, view.scroll_y)) {
+        warn!("Could not restore the canvas scroll position: {}", e);
+    }
+    result
 }
 
 /// Simulates pressing a keyboard key (key down followed by key up).
@@ -1130,7 +1300,7 @@ pub fn press_key(key_code: u16) -> Result<()> {
         ki_up.dwExtraInfo = 0;
         
         // Send the inputs
-        let inputs_sent = SendInput(2, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32);
+        let inputs_sent = send_input(2, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32);
         
         if inputs_sent != 2 {
             return Err(MspMcpError::WindowsApiError("Failed to send key press input".to_string()));
@@ -1138,7 +1308,7 @@ pub fn press_key(key_code: u16) -> Result<()> {
     }
     
     // Brief delay to allow the key press to register
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    crate::clock::sleep(std::time::Duration::from_millis(10));
     
     Ok(())
 }
@@ -1169,7 +1339,7 @@ pub fn press_key_scan(scan_code: u16) -> Result<()> {
         ki_up.dwExtraInfo = 0;
         
         // Send the inputs
-        let inputs_sent = SendInput(2, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32);
+        let inputs_sent = send_input(2, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32);
         
         if inputs_sent != 2 {
             return Err(MspMcpError::WindowsApiError("Failed to send key press scan code input".to_string()));
@@ -1177,7 +1347,7 @@ pub fn press_key_scan(scan_code: u16) -> Result<()> {
     }
     
     // Brief delay to allow the key press to register
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    crate::clock::sleep(std::time::Duration::from_millis(10));
     
     Ok(())
 }
@@ -1196,7 +1366,7 @@ pub fn key_down(key_code: u16) -> Result<()> {
         ki.time = 0;
         ki.dwExtraInfo = 0;
         
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
+        let inputs_sent = send_input(1, &mut input, std::mem::size_of::<INPUT>() as i32);
         
         if inputs_sent != 1 {
             return Err(MspMcpError::WindowsApiError("Failed to send key down input".to_string()));
@@ -1204,7 +1374,7 @@ pub fn key_down(key_code: u16) -> Result<()> {
     }
     
     // Brief delay to ensure the key press registers
-    std::thread::sleep(std::time::Duration::from_millis(5));
+    crate::clock::sleep(std::time::Duration::from_millis(5));
     
     Ok(())
 }
@@ -1223,7 +1393,7 @@ pub fn key_up(key_code: u16) -> Result<()> {
         ki.time = 0;
         ki.dwExtraInfo = 0;
         
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
+        let inputs_sent = send_input(1, &mut input, std::mem::size_of::<INPUT>() as i32);
         
         if inputs_sent != 1 {
             return Err(MspMcpError::WindowsApiError("Failed to send key up input".to_string()));
@@ -1231,44 +1401,72 @@ pub fn key_up(key_code: u16) -> Result<()> {
     }
     
     // Brief delay
-    std::thread::sleep(std::time::Duration::from_millis(5));
+    crate::clock::sleep(std::time::Duration::from_millis(5));
     
     Ok(())
 }
 
 /// Simulates pressing Ctrl+A (Select All)
 pub fn press_ctrl_a() -> Result<()> {
-    key_down(VK_CONTROL)?;
+    let ctrl = HeldKey::press(VK_CONTROL)?;
     press_key('A' as u16)?;
-    key_up(VK_CONTROL)
+    ctrl.release()
 }
 
 /// Simulates pressing Ctrl+C (Copy)
 pub fn press_ctrl_c() -> Result<()> {
-    key_down(VK_CONTROL)?;
+    let ctrl = HeldKey::press(VK_CONTROL)?;
     press_key('C' as u16)?;
-    key_up(VK_CONTROL)
+    ctrl.release()
 }
 
 /// Simulates pressing Ctrl+V (Paste)
 pub fn press_ctrl_v() -> Result<()> {
-    key_down(VK_CONTROL)?;
+    let ctrl = HeldKey::press(VK_CONTROL)?;
     press_key('V' as u16)?;
-    key_up(VK_CONTROL)
+    ctrl.release()
 }
 
 /// Simulates pressing Ctrl+N (New)
 pub fn press_ctrl_n() -> Result<()> {
-    key_down(VK_CONTROL)?;
+    let ctrl = HeldKey::press(VK_CONTROL)?;
     press_key('N' as u16)?;
-    key_up(VK_CONTROL)
+    ctrl.release()
+}
+
+/// Simulates pressing Ctrl+E (Image properties)
+pub fn press_ctrl_e() -> Result<()> {
+    let ctrl = HeldKey::press(VK_CONTROL)?;
+    press_key('E' as u16)?;
+    ctrl.release()
 }
 
 /// Simulates pressing Ctrl+S (Save)
 pub fn press_ctrl_s() -> Result<()> {
-    key_down(VK_CONTROL)?;
+    let ctrl = HeldKey::press(VK_CONTROL)?;
     press_key('S' as u16)?;
-    key_up(VK_CONTROL)
+    ctrl.release()
+}
+
+/// Simulates pressing Ctrl+Z (Undo)
+pub fn press_ctrl_z() -> Result<()> {
+    let ctrl = HeldKey::press(VK_CONTROL)?;
+    press_key('Z' as u16)?;
+    ctrl.release()
+}
+
+/// Simulates pressing Ctrl+Shift+I (Invert colors)
+pub fn press_ctrl_shift_i() -> Result<()> {
+    let ctrl = HeldKey::press(VK_CONTROL)?;
+    let shift = HeldKey::press(VK_SHIFT)?;
+    press_key('I' as u16)?;
+    shift.release()?;
+    ctrl.release()
+}
+
+/// Simulates pressing F12 (Save As)
+pub fn press_f12() -> Result<()> {
+    press_key(VK_F12)
 }
 
 /// Simulates pressing Delete key
@@ -1316,9 +1514,9 @@ pub fn type_text(text: &str) -> Result<()> {
                     press_key(key_code)?;
                 } else if c.is_uppercase() && c.is_alphabetic() {
                     // For uppercase letters, use Shift
-                    key_down(VK_SHIFT)?;
+                    let shift = HeldKey::press(VK_SHIFT)?;
                     press_key(key_code)?;
-                    key_up(VK_SHIFT)?;
+                    shift.release()?;
                 } else {
                     // For numbers and other characters
                     press_key(key_code)?;
@@ -1332,7 +1530,7 @@ pub fn type_text(text: &str) -> Result<()> {
         }
         
         // Brief delay between key presses
-        std::thread::sleep(std::time::Duration::from_millis(5));
+        crate::clock::sleep(std::time::Duration::from_millis(5));
     }
     
     Ok(())
@@ -1340,108 +1538,81 @@ pub fn type_text(text: &str) -> Result<()> {
 
 /// Helper function to draw a line from (start_x, start_y) to (end_x, end_y).
 /// Uses the mouse drag functionality to simulate drawing a line - similar to the direct_paint_test.py approach.
-pub fn draw_line_at(hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32) -> Result<()> {
+/// Dragging with the right button draws in Color 2.
+pub fn draw_line_at(hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
     // Make sure the Paint window is active
     activate_paint_window(hwnd)?;
     
     // Select the pencil tool for reliable drawing
     select_tool(hwnd, "pencil")?;
     
-    // Get drawing area offset
-    let (offset_x, offset_y) = get_drawing_area_offset(hwnd)?;
-    
-    // Add offset to canvas coordinates to get client coordinates
-    let client_start_x = start_x + offset_x;
-    let client_start_y = start_y + offset_y;
-    let client_end_x = end_x + offset_x;
-    let client_end_y = end_y + offset_y;
-    
-    // Convert client coordinates to screen coordinates
-    let (start_screen_x, start_screen_y) = client_to_screen(hwnd, client_start_x, client_start_y)?;
-    let (end_screen_x, end_screen_y) = client_to_screen(hwnd, client_end_x, client_end_y)?;
-    
-    info!("Drawing line from ({},{}) to ({},{}) on screen: ({},{}) to ({},{})", 
-          start_x, start_y, end_x, end_y,
-          start_screen_x, start_screen_y, end_screen_x, end_screen_y);
+    draw_in_view(hwnd, &[(start_x, start_y), (end_x, end_y)], |screen| {
+        info!("Drawing line from ({},{}) to ({},{}) on screen: {:?}", start_x, start_y, end_x, end_y, screen);
+        drag_through_screen(screen, button)
+    })
+}
+
+// Drags the mouse with `button` held through `points` on screen, in small
+// steps between each point and the next for smoother drawing
+fn drag_through_screen(points: &[(i32, i32)], button: MouseButton) -> Result<()> {
+    let Some(&(start_screen_x, start_screen_y)) = points.first() else { return Ok(()) };
     
     // First, move to the start position
     move_mouse_to(start_screen_x, start_screen_y)?;
     
     // Wait a moment to ensure position
-    std::thread::sleep(std::time::Duration::from_millis(500));
+    crate::clock::sleep(std::time::Duration::from_millis(500));
     
-    // Mouse down at start position
-    let mut input: INPUT = unsafe { std::mem::zeroed() };
-    input.r#type = INPUT_MOUSE;
-    
-    unsafe {
-        let mi = &mut input.Anonymous.mi;
-        mi.dx = 0;
-        mi.dy = 0;
-        mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTDOWN;
-        mi.time = 0;
-        mi.dwExtraInfo = 0;
-        
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-        if inputs_sent != 1 {
-            return Err(MspMcpError::WindowsApiError("Failed to send mouse down input".to_string()));
-        }
-    }
+    // Mouse down at start position; released on any early return too
+    let held = HeldButton::press(button)?;
     
     // Wait a moment
-    std::thread::sleep(std::time::Duration::from_millis(300));
-    
-    // Move in small steps to the end position for smoother drawing
-    let steps = 10;
-    let dx = (end_screen_x - start_screen_x) as f32 / steps as f32;
-    let dy = (end_screen_y - start_screen_y) as f32 / steps as f32;
-    
-    for i in 1..=steps {
-        let x = start_screen_x + (dx * i as f32) as i32;
-        let y = start_screen_y + (dy * i as f32) as i32;
+    crate::clock::sleep(std::time::Duration::from_millis(300));
+    
+    // Move in small steps to each next position for smoother drawing
+    for pair in points.windows(2) {
+        let ((from_x, from_y), (to_x, to_y)) = (pair[0], pair[1]);
+        let steps = 10;
+        let dx = (to_x - from_x) as f32 / steps as f32;
+        let dy = (to_y - from_y) as f32 / steps as f32;
         
-        // Move to intermediate position
-        move_mouse_to(x, y)?;
+        for i in 1..=steps {
+            let x = from_x + (dx * i as f32) as i32;
+            let y = from_y + (dy * i as f32) as i32;
+            
+            // Move to intermediate position
+            move_mouse_to(x, y)?;
+            
+            // Brief delay between steps
+            crate::clock::sleep(std::time::Duration::from_millis(50));
+        }
         
-        // Brief delay between steps
-        std::thread::sleep(std::time::Duration::from_millis(50));
+        // Ensure we're at the position
+        move_mouse_to(to_x, to_y)?;
     }
     
-    // Ensure we're at the end position
-    move_mouse_to(end_screen_x, end_screen_y)?;
-    
     // Wait a moment before releasing
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
     // Mouse up at end position
-    unsafe {
-        let mi = &mut input.Anonymous.mi;
-        mi.dx = 0;
-        mi.dy = 0;
-        mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTUP;
-        mi.time = 0;
-        mi.dwExtraInfo = 0;
-        
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-        if inputs_sent != 1 {
-            return Err(MspMcpError::WindowsApiError("Failed to send mouse up input".to_string()));
-        }
-    }
+    held.release()?;
     
     // Wait a moment to ensure the drawing is complete
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
     Ok(())
 }
 
 /// Selects a drawing tool in Paint by clicking its position in the toolbar.
-/// The tool positions are based on Windows 11 Paint's modern UI layout.
+/// The tool positions come from the active layout (see layout.rs).
 pub fn select_tool(hwnd: HWND, tool: &str) -> Result<()> {
     // First ensure the Paint window is active
     activate_paint_window(hwnd)?;
-    
+    click_tool(hwnd, tool)
+}
+
+// Clicks a tool in the toolbar of the already active Paint window
+fn click_tool(hwnd: HWND, tool: &str) -> Result<()> {
     // Get window dimensions to help with adaptive positioning
     let mut rect: windows_sys::Win32::Foundation::RECT = unsafe { std::mem::zeroed() };
     unsafe {
@@ -1451,19 +1622,14 @@ pub fn select_tool(hwnd: HWND, tool: &str) -> Result<()> {
     }
     
     let window_width = rect.right - rect.left;
+    let dpi = crate::geometry::effective_dpi(unsafe { GetDpiForWindow(hwnd) });
+    let window_key = crate::layout::window_key(window_width, rect.bottom - rect.top, dpi);
     
-    // Define tool positions based on the top toolbar (using percentages of window width)
-    // These are approximate positions that should work across different window sizes
-    let tool_positions = match tool.to_lowercase().as_str() {
-        "pencil" => (window_width / 20, 60),       // Left toolbar area
-        "brush" => (window_width / 10, 60),        // Brush tool
-        "fill" => (window_width / 7, 60),          // Fill tool
-        "text" => (window_width / 5, 60),          // Text tool
-        "eraser" => (window_width / 4, 60),        // Eraser tool
-        "select" => (window_width / 3, 60),        // Selection tool
-        "shape" => (window_width / 2.5 as i32, 60),// Shape tool
-        _ => return Err(MspMcpError::InvalidParameters(format!("Unsupported tool: {}", tool))),
-    };
+    // Tool positions in the top toolbar, as fractions of the window width,
+    // come from the layout for this Paint version, or from calibrate
+    let layout = crate::layout::active().for_window(&window_key);
+    let tool_positions = layout.tool_position(tool, window_width)
+        .ok_or_else(|| MspMcpError::InvalidParameters(format!("Unsupported tool: {}", tool)))?;
     
     info!("Selecting tool: {} at position ({}, {})", tool, tool_positions.0, tool_positions.1);
     
@@ -1474,17 +1640,114 @@ pub fn select_tool(hwnd: HWND, tool: &str) -> Result<()> {
     click_at_position(screen_x, screen_y)?;
     
     // Wait for tool selection to take effect
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
     Ok(())
 }
 
+/// Selects a brush variant (marker, watercolor, ...) from the Brushes flyout.
+pub fn select_brush_type(hwnd: HWND, brush_type: &str) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    crate::uia::select_brush_type_uia(hwnd, brush_type)
+}
+
+/// Inverts the colors of the current selection, or of the whole canvas
+/// (selected with Ctrl+A and deselected afterwards). Tries Ctrl+Shift+I
+/// first and falls back to the Select dropdown; canvas captures taken
+/// before and after confirm that the colors actually changed.
+pub fn invert_colors(hwnd: HWND, selection_only: bool) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    
+    if selection_only {
+        if !crate::uia::selection_active_uia(hwnd)? {
+            return Err(MspMcpError::General("Nothing is selected".to_string()));
+        }
+    } else {
+        press_ctrl_a()?;
+        crate::clock::sleep(std::time::Duration::from_millis(300));
+    }
+    
+    let before = crate::capture::capture_canvas(hwnd)?;
+    press_ctrl_shift_i()?;
+    crate::clock::sleep(std::time::Duration::from_millis(300));
+    let mut applied = crate::capture::capture_canvas(hwnd)?.shows_inversion_of(&before);
+    
+    if !applied {
+        warn!("Ctrl+Shift+I had no visible effect, trying the Select dropdown");
+        crate::uia::invert_colors_uia(hwnd)?;
+        applied = crate::capture::capture_canvas(hwnd)?.shows_inversion_of(&before);
+    }
+    
+    if !selection_only {
+        press_escape()?;
+    }
+    
+    if applied {
+        Ok(())
+    } else {
+        Err(MspMcpError::General("Inverting colors had no visible effect on the canvas".to_string()))
+    }
+}
+
+/// Rotates the current selection clockwise by 90, 180 or 270 degrees.
+pub fn rotate_selection(hwnd: HWND, degrees: u32) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    crate::uia::rotate_selection_uia(hwnd, degrees)
+}
+
+/// Flips the current selection "horizontal"ly or "vertical"ly.
+pub fn flip_selection(hwnd: HWND, direction: &str) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    crate::uia::flip_selection_uia(hwnd, direction)
+}
+
+/// Reads what this Paint build offers from its UI. Opens and closes the
+/// flyouts whose entries it lists.
+pub fn read_capabilities(hwnd: HWND) -> Result<PaintCapabilities> {
+    activate_paint_window(hwnd)?;
+    crate::uia::read_capabilities_uia(hwnd)
+}
+
+/// Adds a layer above the selected one (Paint builds with a Layers panel).
+pub fn add_layer(hwnd: HWND) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    crate::uia::add_layer_uia(hwnd)
+}
+
+/// Makes the layer at `index` (0 = top of the Layers panel) the active one.
+pub fn select_layer(hwnd: HWND, index: u32) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    crate::uia::select_layer_uia(hwnd, index)
+}
+
+/// Shows or hides the layer at `index`.
+pub fn set_layer_visibility(hwnd: HWND, index: u32, visible: bool) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    crate::uia::set_layer_visibility_uia(hwnd, index, visible)
+}
+
+/// Merges the layer at `index` into the one below it.
+pub fn merge_layer_down(hwnd: HWND, index: u32) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    crate::uia::merge_layer_down_uia(hwnd, index)
+}
+
+/// Deletes the layer at `index`.
+pub fn delete_layer(hwnd: HWND, index: u32) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    crate::uia::delete_layer_uia(hwnd, index)
+}
+
 /// Sets the active color in Paint by selecting it from the color panel.
 /// The color should be in "#RRGGBB" format.
 pub fn set_color(hwnd: HWND, color: &str) -> Result<()> {
     // First ensure the Paint window is active
     activate_paint_window(hwnd)?;
-    
+    pick_color(color)
+}
+
+// Sets the selected color slot of the already active Paint window
+fn pick_color(color: &str) -> Result<()> {
     // Parse the color string
     if !color.starts_with('#') || color.len() != 7 {
         return Err(MspMcpError::InvalidParameters("Color must be in #RRGGBB format".to_string()));
@@ -1497,22 +1760,70 @@ pub fn set_color(hwnd: HWND, color: &str) -> Result<()> {
     Ok(())
 }
 
+/// Sets Color 2, which right-button strokes draw with (and the eraser
+/// leaves behind). Selects the Color 2 swatch, sets the color, then
+/// switches back to Color 1 so later left-button strokes are unaffected.
+pub fn set_secondary_color(hwnd: HWND, color: &str) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    
+    crate::uia::select_color_slot_uia(hwnd, 2)?;
+    let result = pick_color(color);
+    
+    // Always hand the picker back to Color 1, even if setting the color failed
+    crate::uia::select_color_slot_uia(hwnd, 1)?;
+    result
+}
+
+/// Adds a color to Paint's custom colors with the Edit colors dialog. Paint
+/// also sets the selected slot to it, so for right-button colors Color 2 is
+/// selected around the dialog, as set_secondary_color does.
+pub fn add_custom_color(hwnd: HWND, color: &str, button: MouseButton) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    with_color_slot(hwnd, button, || crate::uia::add_custom_color_uia(hwnd, color))
+}
+
+/// Sets the slot `button` draws with to a custom color swatch, counted from
+/// the newest (0).
+pub fn select_custom_color(hwnd: HWND, index: usize, button: MouseButton) -> Result<()> {
+    activate_paint_window(hwnd)?;
+    with_color_slot(hwnd, button, || crate::uia::select_custom_color_uia(hwnd, index))
+}
+
+// Runs a color change with the slot `button` draws with selected, handing
+// the picker back to Color 1 afterwards
+fn with_color_slot(hwnd: HWND, button: MouseButton, change: impl FnOnce() -> Result<()>) -> Result<()> {
+    if let MouseButton::Left = button {
+        return change();
+    }
+    crate::uia::select_color_slot_uia(hwnd, 2)?;
+    let result = change();
+    crate::uia::select_color_slot_uia(hwnd, 1)?;
+    result
+}
+
 /// Sets the line thickness or brush size in Paint.
-/// The level parameter should be between 1 and 5.
+/// The level parameter should be between 1 and 5. Deprecated in favour of
+/// set_thickness_px; each level maps to a fixed pixel width.
 pub fn set_thickness(hwnd: HWND, level: u32) -> Result<()> {
     // Validate thickness level
     if level < 1 || level > 5 {
         return Err(MspMcpError::InvalidParameters("Thickness level must be between 1 and 5".to_string()));
     }
     
+    set_thickness_px(hwnd, crate::protocol::THICKNESS_LEVEL_PIXELS[level as usize - 1])
+}
+
+/// Sets the stroke width in pixels with Paint's size slider.
+pub fn set_thickness_px(hwnd: HWND, px: u32) -> Result<()> {
+    if px < 1 || px > crate::protocol::MAX_THICKNESS_PX {
+        return Err(MspMcpError::InvalidParameters(
+            format!("Thickness must be between 1 and {} pixels", crate::protocol::MAX_THICKNESS_PX)));
+    }
+    
     // First ensure the Paint window is active
     activate_paint_window(hwnd)?;
     
-    // For now, just log the thickness that would be selected
-    // In a real implementation, we would interact with Paint's thickness control
-    info!("Would set thickness level: {}", level);
-    
-    Ok(())
+    crate::uia::set_size_slider_uia(hwnd, px)
 }
 
 /// Sets the brush size for a specific tool.
@@ -1531,21 +1842,14 @@ pub fn set_brush_size(hwnd: HWND, size: u32, tool: Option<&str>) -> Result<()> {
         select_tool(hwnd, tool_name)?;
     }
     
-    // For now, just log the size that would be selected
-    info!("Would set brush size: {} for tool: {}", size, tool.unwrap_or("current"));
-    
-    Ok(())
+    info!("Setting brush size: {} for tool: {}", size, tool.unwrap_or("current"));
+    crate::uia::set_size_slider_uia(hwnd, size)
 }
 
 /// Sets the fill type for shapes in Paint.
 /// The fill_type parameter should be "none", "solid", or "outline".
 pub fn set_fill(hwnd: HWND, fill_type: &str) -> Result<()> {
-    // Validate fill type
-    match fill_type.to_lowercase().as_str() {
-        "none" | "solid" | "outline" => {},
-        _ => return Err(MspMcpError::InvalidParameters(
-            format!("Fill type must be 'none', 'solid', or 'outline', got '{}'", fill_type)))
-    }
+    check_fill_type(fill_type)?;
     
     // First ensure the Paint window is active
     activate_paint_window(hwnd)?;
@@ -1556,11 +1860,68 @@ pub fn set_fill(hwnd: HWND, fill_type: &str) -> Result<()> {
     Ok(())
 }
 
+fn check_fill_type(fill_type: &str) -> Result<()> {
+    match fill_type.to_lowercase().as_str() {
+        "none" | "solid" | "outline" => Ok(()),
+        _ => Err(MspMcpError::InvalidParameters(
+            format!("Fill type must be 'none', 'solid', or 'outline', got '{}'", fill_type)))
+    }
+}
+
+/// Applies several tool settings with a single activation of the Paint
+/// window. The tool and brush variant go first, since picking a tool can
+/// move the size slider, then the colors, the stroke width and the fill.
+/// Every value is checked before Paint is touched, so a bad one changes nothing.
+pub fn set_tool_state(hwnd: HWND, params: &SetToolStateParams) -> Result<()> {
+    for color in params.color.iter().chain(&params.secondary_color) {
+        if !color.starts_with('#') || color.len() != 7 {
+            return Err(MspMcpError::InvalidParameters("Color must be in #RRGGBB format".to_string()));
+        }
+    }
+    if let Some(px) = params.thickness_px {
+        if px < 1 || px > crate::protocol::MAX_THICKNESS_PX {
+            return Err(MspMcpError::InvalidParameters(
+                format!("Thickness must be between 1 and {} pixels", crate::protocol::MAX_THICKNESS_PX)));
+        }
+    }
+    if let Some(fill_type) = &params.fill_type {
+        check_fill_type(fill_type)?;
+    }
+    
+    activate_paint_window(hwnd)?;
+    
+    if let Some(tool) = &params.tool {
+        click_tool(hwnd, tool)?;
+    }
+    if let Some(brush_type) = &params.brush_type {
+        crate::uia::select_brush_type_uia(hwnd, brush_type)?;
+    }
+    if let Some(color) = &params.color {
+        pick_color(color)?;
+    }
+    if let Some(color) = &params.secondary_color {
+        crate::uia::select_color_slot_uia(hwnd, 2)?;
+        let result = pick_color(color);
+        crate::uia::select_color_slot_uia(hwnd, 1)?;
+        result?;
+    }
+    if let Some(px) = params.thickness_px {
+        crate::uia::set_size_slider_uia(hwnd, px)?;
+    }
+    if let Some(fill_type) = &params.fill_type {
+        info!("Would set fill type: {}", fill_type);
+    }
+    
+    Ok(())
+}
+
 /// Draws a shape from (start_x, start_y) to (end_x, end_y).
 /// Selects the appropriate shape tool and uses mouse drag to create the shape.
-pub fn draw_shape(hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32) -> Result<()> {
+/// Dragging with the right button draws the outline in Color 2.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_shape(hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton) -> Result<()> {
     // First, try to use the UIA implementation
-    if let Ok(()) = crate::uia::draw_shape_uia(hwnd, shape_type, start_x, start_y, end_x, end_y) {
+    if let Ok(()) = crate::uia::draw_shape_uia(hwnd, shape_type, start_x, start_y, end_x, end_y, button) {
         return Ok(());
     }
     
@@ -1591,52 +1952,22 @@ pub fn draw_shape(hwnd: HWND, shape_type: &str, start_x: i32, start_y: i32, end_
     // Draw the shape with a mouse drag
     // Move to start position
     move_mouse_to(start_screen_x, start_screen_y)?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
-    
-    // Press mouse down
-    let mut input: INPUT = unsafe { std::mem::zeroed() };
-    input.r#type = INPUT_MOUSE;
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
-    unsafe {
-        let mi = &mut input.Anonymous.mi;
-        mi.dx = 0;
-        mi.dy = 0;
-        mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTDOWN;
-        mi.time = 0;
-        mi.dwExtraInfo = 0;
-        
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-        if inputs_sent != 1 {
-            return Err(MspMcpError::WindowsApiError("Failed to send mouse down input".to_string()));
-        }
-    }
+    // Press mouse down; released on any early return too
+    let held = HeldButton::press(button)?;
     
     // Move to end position
     move_mouse_to(end_screen_x, end_screen_y)?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
     // Release mouse button
-    unsafe {
-        let mi = &mut input.Anonymous.mi;
-        mi.dx = 0;
-        mi.dy = 0;
-        mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTUP;
-        mi.time = 0;
-        mi.dwExtraInfo = 0;
-        
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-        if inputs_sent != 1 {
-            return Err(MspMcpError::WindowsApiError("Failed to send mouse up input".to_string()));
-        }
-    }
-    
-    Ok(())
+    held.release()
 }
 
 /// Draws a polyline (series of connected lines) by drawing line segments between consecutive points.
-pub fn draw_polyline(hwnd: HWND, points: &[(i32, i32)]) -> Result<()> {
+/// Dragging with the right button draws in Color 2.
+pub fn draw_polyline(hwnd: HWND, points: &[(i32, i32)], button: MouseButton) -> Result<()> {
     // Validate input
     if points.len() < 2 {
         return Err(MspMcpError::InvalidParameters(
@@ -1648,58 +1979,30 @@ pub fn draw_polyline(hwnd: HWND, points: &[(i32, i32)]) -> Result<()> {
     
     // Select the pencil tool
     select_tool(hwnd, "pencil")?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
-    // Convert first point to screen coordinates
-    let (start_screen_x, start_screen_y) = client_to_screen(hwnd, points[0].0, points[0].1)?;
+    draw_in_view(hwnd, points, |screen| polyline_through_screen(screen, button))
+}
+
+// Drags the mouse with `button` held straight through `points` on screen
+fn polyline_through_screen(points: &[(i32, i32)], button: MouseButton) -> Result<()> {
+    let Some(&(start_screen_x, start_screen_y)) = points.first() else { return Ok(()) };
     
     // Move to start position
     move_mouse_to(start_screen_x, start_screen_y)?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
-    
-    // Press mouse down
-    let mut input: INPUT = unsafe { std::mem::zeroed() };
-    input.r#type = INPUT_MOUSE;
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
-    unsafe {
-        let mi = &mut input.Anonymous.mi;
-        mi.dx = 0;
-        mi.dy = 0;
-        mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTDOWN;
-        mi.time = 0;
-        mi.dwExtraInfo = 0;
-        
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-        if inputs_sent != 1 {
-            return Err(MspMcpError::WindowsApiError("Failed to send mouse down input".to_string()));
-        }
-    }
+    // Press mouse down; released on any early return too
+    let held = HeldButton::press(button)?;
     
     // Move through each point
-    for i in 1..points.len() {
-        let (screen_x, screen_y) = client_to_screen(hwnd, points[i].0, points[i].1)?;
+    for &(screen_x, screen_y) in &points[1..] {
         move_mouse_to(screen_x, screen_y)?;
-        std::thread::sleep(std::time::Duration::from_millis(50));
+        crate::clock::sleep(std::time::Duration::from_millis(50));
     }
     
     // Release mouse button
-    unsafe {
-        let mi = &mut input.Anonymous.mi;
-        mi.dx = 0;
-        mi.dy = 0;
-        mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTUP;
-        mi.time = 0;
-        mi.dwExtraInfo = 0;
-        
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-        if inputs_sent != 1 {
-            return Err(MspMcpError::WindowsApiError("Failed to send mouse up input".to_string()));
-        }
-    }
-    
-    Ok(())
+    held.release()
 }
 
 /// Clears the canvas in Paint using Ctrl+A then Delete.
@@ -1709,7 +2012,7 @@ pub fn clear_canvas(hwnd: HWND) -> Result<()> {
     
     // Select all (Ctrl+A)
     press_ctrl_a()?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
     // Press Delete
     press_delete()?;
@@ -1724,7 +2027,7 @@ pub fn select_region(hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y:
     
     // Select the selection tool
     select_tool(hwnd, "select")?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
     // Convert client coordinates to screen coordinates
     let (start_screen_x, start_screen_y) = client_to_screen(hwnd, start_x, start_y)?;
@@ -1733,48 +2036,17 @@ pub fn select_region(hwnd: HWND, start_x: i32, start_y: i32, end_x: i32, end_y:
     // Draw the selection with a mouse drag
     // Move to start position
     move_mouse_to(start_screen_x, start_screen_y)?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
-    
-    // Press mouse down
-    let mut input: INPUT = unsafe { std::mem::zeroed() };
-    input.r#type = INPUT_MOUSE;
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
-    unsafe {
-        let mi = &mut input.Anonymous.mi;
-        mi.dx = 0;
-        mi.dy = 0;
-        mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTDOWN;
-        mi.time = 0;
-        mi.dwExtraInfo = 0;
-        
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-        if inputs_sent != 1 {
-            return Err(MspMcpError::WindowsApiError("Failed to send mouse down input".to_string()));
-        }
-    }
+    // Press mouse down; released on any early return too
+    let held = HeldButton::press(MouseButton::Left)?;
     
     // Move to end position
     move_mouse_to(end_screen_x, end_screen_y)?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
     // Release mouse button
-    unsafe {
-        let mi = &mut input.Anonymous.mi;
-        mi.dx = 0;
-        mi.dy = 0;
-        mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTUP;
-        mi.time = 0;
-        mi.dwExtraInfo = 0;
-        
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-        if inputs_sent != 1 {
-            return Err(MspMcpError::WindowsApiError("Failed to send mouse up input".to_string()));
-        }
-    }
-    
-    Ok(())
+    held.release()
 }
 
 /// Copies the current selection to the clipboard.
@@ -1796,7 +2068,7 @@ pub fn paste_at(hwnd: HWND, x: i32, y: i32) -> Result<()> {
     // Click at the paste location
     let (screen_x, screen_y) = client_to_screen(hwnd, x, y)?;
     click_at_position(screen_x, screen_y)?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
     // Press Ctrl+V
     press_ctrl_v()?;
@@ -1804,6 +2076,31 @@ pub fn paste_at(hwnd: HWND, x: i32, y: i32) -> Result<()> {
     Ok(())
 }
 
+/// Pastes the clipboard bitmap (`width` x `height`), drags it so its
+/// top-left corner lands on canvas pixel (x, y) and commits it. Paint
+/// pastes at the top-left corner of the visible canvas, so the target is
+/// scrolled into view first.
+pub fn stamp_clipboard_image(hwnd: HWND, x: i32, y: i32, width: u32, height: u32) -> Result<()> {
+    // Make sure the Paint window is active
+    activate_paint_window(hwnd)?;
+    
+    // The pasted selection is dragged by its middle
+    let (grab_x, grab_y) = ((width / 2) as i32, (height / 2) as i32);
+    draw_in_view(hwnd, &[(x + grab_x, y + grab_y)], |screen| {
+        let view = crate::uia::read_canvas_view_uia(hwnd).or_else(|_| estimate_canvas_view(hwnd))?;
+        let frame = CanvasFrame::from_view(&view, client_to_screen(hwnd, 0, 0)?);
+        press_ctrl_v()?;
+        crate::clock::sleep(std::time::Duration::from_millis(300));
+        
+        let (from_x, from_y) = frame.to_screen(view.scroll_x as i32 + grab_x, view.scroll_y as i32 + grab_y);
+        drag_mouse(from_x, from_y, screen[0].0, screen[0].1)?;
+        crate::clock::sleep(std::time::Duration::from_millis(200));
+        
+        // Dropping the selection leaves the pasted pixels on the canvas
+        press_escape()
+    })
+}
+
 /// Adds text at the specified position.
 pub fn add_text(
     hwnd: HWND, 
@@ -1820,7 +2117,7 @@ pub fn add_text(
     
     // Select the text tool
     select_tool(hwnd, "text")?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
     // If a color is specified, set it
     if let Some(color_str) = color {
@@ -1830,7 +2127,7 @@ pub fn add_text(
     // Click at the text position
     let (screen_x, screen_y) = client_to_screen(hwnd, x, y)?;
     click_at_position(screen_x, screen_y)?;
-    std::thread::sleep(std::time::Duration::from_millis(300));
+    crate::clock::sleep(std::time::Duration::from_millis(300));
     
     // Type the text
     type_text(text)?;
@@ -1841,30 +2138,86 @@ pub fn add_text(
     Ok(())
 }
 
+/// Opens a new document at Paint's default size (Ctrl+N).
+pub fn new_document(hwnd: HWND, unsaved_changes: UnsavedChanges) -> Result<()> {
+    // Make sure the Paint window is active
+    activate_paint_window(hwnd)?;
+    
+    // Press Ctrl+N for a new canvas
+    press_ctrl_n()?;
+    crate::clock::sleep(std::time::Duration::from_millis(500));
+    
+    // With unsaved changes Paint asks first; answer as the caller asked
+    // rather than pressing Enter on whatever button has focus
+    crate::uia::answer_unsaved_changes_prompt_uia(hwnd, unsaved_changes)?;
+    Ok(())
+}
+
 /// Creates a new canvas with the specified dimensions.
 pub fn create_canvas(
     hwnd: HWND, 
     width: u32, 
     height: u32, 
-    background_color: Option<&str>
+    background_color: Option<&str>,
+    unsaved_changes: UnsavedChanges,
 ) -> Result<()> {
-    // Make sure the Paint window is active
+    new_document(hwnd, unsaved_changes)?;
+    
+    // New images open at Paint's default size
+    info!("Resizing the new canvas to {}x{}", width, height);
+    set_image_properties(hwnd, &SetImagePropertiesParams {
+        width: Some(width as f64),
+        height: Some(height as f64),
+        ..Default::default()
+    })?;
+    
+    // Paint has no background option for new images; fill it before anything is drawn
+    if let Some(color) = background_color {
+        set_background(hwnd, color)?;
+    }
+    
+    Ok(())
+}
+
+/// Saves the image to `file_path` through the Save As dialog (F12). An
+/// existing file is only replaced if `overwrite` is set.
+pub fn save_canvas(hwnd: HWND, file_path: &str, format: &str, overwrite: bool, jpeg_quality: Option<u32>) -> Result<()> {
     activate_paint_window(hwnd)?;
     
-    // Press Ctrl+N for a new canvas
-    press_ctrl_n()?;
-    std::thread::sleep(std::time::Duration::from_millis(500));
+    press_f12()?;
+    crate::uia::save_as_uia(hwnd, file_path, format, overwrite, jpeg_quality, false)
+}
+
+/// Like save_canvas, but the format must be picked in "Save as type"; the
+/// dialog is cancelled with an error if it can't be.
+pub fn save_as(hwnd: HWND, file_path: &str, format: &str, overwrite: bool, jpeg_quality: Option<u32>) -> Result<()> {
+    activate_paint_window(hwnd)?;
     
-    // For now, just log the action
-    info!("Would create a {}x{} canvas with background: {}", 
-          width, 
-          height, 
-          background_color.unwrap_or("default"));
+    press_f12()?;
+    crate::uia::save_as_uia(hwnd, file_path, format, overwrite, jpeg_quality, true)
+}
+
+/// Resizes the image and/or changes its color mode through the Image
+/// Properties dialog (Ctrl+E).
+pub fn set_image_properties(hwnd: HWND, params: &SetImagePropertiesParams) -> Result<()> {
+    activate_paint_window(hwnd)?;
     
-    // Press Enter to accept
-    press_enter()?;
+    press_ctrl_e()?;
+    crate::uia::set_image_properties_uia(hwnd, params)
+}
+
+/// Fills the whole canvas with a color. Sets Color 2, selects all and
+/// presses Delete, which leaves Color 2 behind, then drops the selection.
+/// Color 2 stays set to the background color afterwards, like the eraser expects.
+pub fn set_background(hwnd: HWND, color: &str) -> Result<()> {
+    set_secondary_color(hwnd, color)?;
+    activate_paint_window(hwnd)?;
     
-    Ok(())
+    press_ctrl_a()?;
+    crate::clock::sleep(std::time::Duration::from_millis(300));
+    press_delete()?;
+    crate::clock::sleep(std::time::Duration::from_millis(300));
+    press_escape()
 }
 
 /// Alternative function to get the Paint window handle directly.
@@ -1873,8 +2226,8 @@ pub fn get_direct_paint_hwnd() -> Result<HWND> {
     get_paint_hwnd()
 }
 
-/// Sends a mouse left button down event at the current cursor position
-pub fn send_mouse_down() -> Result<()> {
+/// Sends a mouse button down event at the current cursor position
+pub fn send_mouse_down(button: MouseButton) -> Result<()> {
     let mut input: INPUT = unsafe { std::mem::zeroed() };
     input.r#type = INPUT_MOUSE;
     
@@ -1883,11 +2236,11 @@ pub fn send_mouse_down() -> Result<()> {
         mi.dx = 0;
         mi.dy = 0;
         mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTDOWN;
+        mi.dwFlags = button_flags(button).0;
         mi.time = 0;
         mi.dwExtraInfo = 0;
         
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
+        let inputs_sent = send_input(1, &mut input, std::mem::size_of::<INPUT>() as i32);
         if inputs_sent != 1 {
             return Err(MspMcpError::WindowsApiError("Failed to send mouse down input".to_string()));
         }
@@ -1896,8 +2249,8 @@ pub fn send_mouse_down() -> Result<()> {
     Ok(())
 }
 
-/// Sends a mouse left button up event at the current cursor position
-pub fn send_mouse_up() -> Result<()> {
+/// Sends a mouse button up event at the current cursor position
+pub fn send_mouse_up(button: MouseButton) -> Result<()> {
     let mut input: INPUT = unsafe { std::mem::zeroed() };
     input.r#type = INPUT_MOUSE;
     
@@ -1906,11 +2259,11 @@ pub fn send_mouse_up() -> Result<()> {
         mi.dx = 0;
         mi.dy = 0;
         mi.mouseData = 0;
-        mi.dwFlags = MOUSEEVENTF_LEFTUP;
+        mi.dwFlags = button_flags(button).1;
         mi.time = 0;
         mi.dwExtraInfo = 0;
         
-        let inputs_sent = SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
+        let inputs_sent = send_input(1, &mut input, std::mem::size_of::<INPUT>() as i32);
         if inputs_sent != 1 {
             return Err(MspMcpError::WindowsApiError("Failed to send mouse up input".to_string()));
         }
@@ -1919,4 +2272,152 @@ pub fn send_mouse_up() -> Result<()> {
     Ok(())
 }
 
+/// A mouse button held down by `press`. `release` lets go of it; if the
+/// guard is dropped first (an early return between the down and the up),
+/// dropping it lets go, so no error path leaves the button stuck.
+#[must_use = "the button is released as soon as the guard is dropped"]
+pub struct HeldButton {
+    button: MouseButton,
+    released: bool,
+}
+
+impl HeldButton {
+    /// Presses `button` at the current cursor position.
+    pub fn press(button: MouseButton) -> Result<HeldButton> {
+        send_mouse_down(button)?;
+        Ok(HeldButton { button, released: false })
+    }
+
+    /// Lets go of the button.
+    pub fn release(mut self) -> Result<()> {
+        self.released = true;
+        send_mouse_up(self.button)
+    }
+}
+
+impl Drop for HeldButton {
+    fn drop(&mut self) {
+        if !self.released {
+            debug!("Releasing {:?} mouse button after an interrupted gesture", self.button);
+            if let Err(e) = send_mouse_up(self.button) {
+                warn!("Failed to release the {:?} mouse button: {}", self.button, e);
+            }
+        }
+    }
+}
+
+/// A key held down by `press`, released like HeldButton.
+#[must_use = "the key is released as soon as the guard is dropped"]
+pub struct HeldKey {
+    key_code: u16,
+    released: bool,
+}
+
+impl HeldKey {
+    /// Presses the key and keeps it down.
+    pub fn press(key_code: u16) -> Result<HeldKey> {
+        key_down(key_code)?;
+        Ok(HeldKey { key_code, released: false })
+    }
+
+    /// Lets go of the key.
+    pub fn release(mut self) -> Result<()> {
+        self.released = true;
+        key_up(self.key_code)
+    }
+}
+
+impl Drop for HeldKey {
+    fn drop(&mut self) {
+        if !self.released {
+            debug!("Releasing key VK={} after an interrupted shortcut", self.key_code);
+            if let Err(e) = key_up(self.key_code) {
+                warn!("Failed to release key VK={}: {}", self.key_code, e);
+            }
+        }
+    }
+}
+
+/// Releases any mouse buttons or modifier keys that are still held down
+/// and lifts BlockInput, so an interrupted operation can't leave the user's
+/// input stuck. Only keys that are actually down get a release event.
+pub fn release_held_input() -> Result<()> {
+    unsafe {
+        BlockInput(FALSE);
+    }
+    
+    let is_down = |vk: u16| unsafe { GetAsyncKeyState(vk as i32) } < 0;
+    if is_down(VK_LBUTTON) {
+        debug!("Releasing held left mouse button");
+        send_mouse_up(MouseButton::Left)?;
+    }
+    if is_down(VK_RBUTTON) {
+        debug!("Releasing held right mouse button");
+        let mut input: INPUT = unsafe { std::mem::zeroed() };
+        input.r#type = INPUT_MOUSE;
+        unsafe {
+            input.Anonymous.mi.dwFlags = MOUSEEVENTF_RIGHTUP;
+            send_input(1, &mut input, std::mem::size_of::<INPUT>() as i32);
+        }
+    }
+    for vk in [VK_CONTROL, VK_SHIFT, VK_MENU] {
+        if is_down(vk) {
+            debug!("Releasing held key VK={}", vk);
+            key_up(vk)?;
+        }
+    }
+    Ok(())
+}
+
+/// Terminates a process, discarding any unsaved work in it.
+pub fn terminate_process(pid: u32) -> Result<()> {
+    unsafe {
+        let process = OpenProcess(PROCESS_TERMINATE, FALSE, pid);
+        if process == 0 {
+            return Err(MspMcpError::WindowsApiError(format!("OpenProcess failed for PID {}", pid)));
+        }
+        let terminated = TerminateProcess(process, 0);
+        CloseHandle(process);
+        if terminated == FALSE {
+            return Err(MspMcpError::WindowsApiError(format!("TerminateProcess failed for PID {}", pid)));
+        }
+    }
+    info!("Terminated process PID={}", pid);
+    Ok(())
+}
+
+/// Closes Paint like its close button (WM_CLOSE), answers the save prompt
+/// as `unsaved_changes` says and waits up to `timeout` for the process to end.
+pub fn quit_paint(hwnd: HWND, unsaved_changes: UnsavedChanges, timeout: std::time::Duration) -> Result<()> {
+    let pid = get_window_pid(hwnd);
+    activate_paint_window(hwnd)?;
+    
+    if unsafe { PostMessageW(hwnd, WM_CLOSE, 0, 0) } == FALSE {
+        return Err(MspMcpError::WindowsApiError("Failed to send WM_CLOSE to Paint".to_string()));
+    }
+    crate::clock::sleep(std::time::Duration::from_millis(500));
+    crate::uia::answer_unsaved_changes_prompt_uia(hwnd, unsaved_changes)?;
+    
+    wait_for_process_exit(pid, timeout)?;
+    info!("Paint (PID={}) has exited", pid);
+    Ok(())
+}
+
+/// Waits for a process to end. A process that can't be opened is taken to
+/// have ended already.
+fn wait_for_process_exit(pid: u32, timeout: std::time::Duration) -> Result<()> {
+    unsafe {
+        let process = OpenProcess(PROCESS_SYNCHRONIZE, FALSE, pid);
+        if process == 0 {
+            return Ok(());
+        }
+        let waited = WaitForSingleObject(process, timeout.as_millis().min(u32::MAX as u128 - 1) as u32);
+        CloseHandle(process);
+        if waited != WAIT_OBJECT_0 {
+            return Err(MspMcpError::OperationTimeout(format!("Paint (PID={}) did not exit within {:?}", pid, timeout)));
+        }
+    }
+    Ok(())
+}
+
 // ... existing code ...
\ No newline at end of file